@@ -0,0 +1,403 @@
+//! Known deployments of the token market program by cluster.
+//!
+//! Program ids, the market authority PDA and the mints markets usually
+//! accept differ between clusters. An [AddressBook](struct.AddressBook.html)
+//! keeps them in one place instead of pubkeys hardcoded by every integration,
+//! and accepts overrides for private deployments:
+//!
+//! ```no_run
+//! use solana_sdk::pubkey::Pubkey;
+//! use token_market_cli::addresses::{AddressBook, Cluster};
+//!
+//! let mut book = AddressBook::builtin();
+//! book.override_program(Cluster::Localnet, Pubkey::new_unique());
+//! let cluster = Cluster::from_url("https://api.devnet.solana.com").unwrap();
+//! let deployment = book.deployment(cluster).unwrap();
+//! println!("markets of {} sign as {}", cluster, deployment.authority());
+//! ```
+//!
+//! The CLI reads overrides from the `token_market_addresses` key of its
+//! config profile, and warns when the cluster it talks to doesn't host the
+//! program of the book:
+//!
+//! ```yaml
+//! token_market_addresses:
+//!   localnet:
+//!     program_id: 846LfoLdcu47CV7jpZhxtYr99GNcwcFD2kpktUReeYNx
+//!     acceptable_mints:
+//!       USDC: 4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU
+//! ```
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+use token_market::state::{find_authority_address, find_fee_vault_address, find_tenant_address};
+
+/// Profile key of the address book overrides
+pub const PROFILE_KEY: &str = "token_market_addresses";
+
+/// Circle USDC on mainnet-beta
+const MAINNET_USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+/// Circle USDC on devnet
+const DEVNET_USDC: &str = "4zMMC9srt5Ri5X14GAgXhaHii3GnPAEERYPJgZJDncDU";
+
+/// A Solana cluster
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Cluster {
+    MainnetBeta,
+    Devnet,
+    Testnet,
+    /// A test validator on this machine
+    Localnet,
+}
+
+impl Cluster {
+    pub const ALL: &'static [Cluster] = &[
+        Cluster::MainnetBeta,
+        Cluster::Devnet,
+        Cluster::Testnet,
+        Cluster::Localnet,
+    ];
+
+    /// Name as the Solana CLI spells it
+    pub fn name(&self) -> &'static str {
+        match self {
+            Cluster::MainnetBeta => "mainnet-beta",
+            Cluster::Devnet => "devnet",
+            Cluster::Testnet => "testnet",
+            Cluster::Localnet => "localnet",
+        }
+    }
+
+    /// Public RPC endpoint of the cluster
+    pub fn rpc_url(&self) -> &'static str {
+        match self {
+            Cluster::MainnetBeta => "https://api.mainnet-beta.solana.com",
+            Cluster::Devnet => "https://api.devnet.solana.com",
+            Cluster::Testnet => "https://api.testnet.solana.com",
+            Cluster::Localnet => "http://localhost:8899",
+        }
+    }
+
+    /// Cluster a moniker of the Solana CLI names, or its first letter
+    pub fn from_moniker(moniker: &str) -> Option<Cluster> {
+        match moniker {
+            "m" | "mainnet" | "mainnet-beta" => Some(Cluster::MainnetBeta),
+            "d" | "devnet" => Some(Cluster::Devnet),
+            "t" | "testnet" => Some(Cluster::Testnet),
+            "l" | "localhost" | "localnet" => Some(Cluster::Localnet),
+            _ => None,
+        }
+    }
+
+    /// Cluster an RPC URL points at, `None` for URLs of private RPC
+    /// providers that don't name it
+    pub fn from_url(url: &str) -> Option<Cluster> {
+        let url = url.to_lowercase();
+        if url.contains("localhost") || url.contains("127.0.0.1") {
+            Some(Cluster::Localnet)
+        } else if url.contains("devnet") {
+            Some(Cluster::Devnet)
+        } else if url.contains("testnet") {
+            Some(Cluster::Testnet)
+        } else if url.contains("mainnet") {
+            Some(Cluster::MainnetBeta)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Cluster {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Cluster::ALL
+            .iter()
+            .copied()
+            .find(|cluster| cluster.name() == name)
+            .or_else(|| Some(Cluster::MainnetBeta).filter(|_| name == "mainnet"))
+            .ok_or_else(|| anyhow!("Unknown cluster {}", name))
+    }
+}
+
+/// The token market program as deployed on a cluster
+#[derive(Clone, Debug, PartialEq)]
+pub struct Deployment {
+    pub program_id: Pubkey,
+    /// Mints markets of the deployment usually accept, by symbol
+    pub acceptable_mints: BTreeMap<String, Pubkey>,
+}
+
+impl Deployment {
+    /// Deployment of `program_id` accepting no known mint
+    pub fn new(program_id: Pubkey) -> Self {
+        Self {
+            program_id,
+            acceptable_mints: BTreeMap::new(),
+        }
+    }
+
+    /// The PDA signing for every market of the deployment
+    pub fn authority(&self) -> Pubkey {
+        find_authority_address(&self.program_id).0
+    }
+
+    /// Vault collecting the protocol fees of `market` paid in `mint`
+    pub fn fee_vault(&self, market: &Pubkey, mint: &Pubkey) -> Pubkey {
+        find_fee_vault_address(&self.program_id, market, mint).0
+    }
+
+    /// Usage stats of the market owner `owner`, counting its protocol fees
+    pub fn tenant_stats(&self, owner: &Pubkey) -> Pubkey {
+        find_tenant_address(&self.program_id, owner).0
+    }
+
+    /// Mint of the token `symbol`, e.g. USDC
+    pub fn acceptable_mint(&self, symbol: &str) -> Option<Pubkey> {
+        self.acceptable_mints.get(symbol).copied()
+    }
+
+    /// Symbol of the known acceptable `mint`
+    pub fn acceptable_symbol(&self, mint: &Pubkey) -> Option<&str> {
+        self.acceptable_mints
+            .iter()
+            .find(|(_, known)| *known == mint)
+            .map(|(symbol, _)| symbol.as_str())
+    }
+}
+
+/// Overrides of one cluster, as written in a config profile
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DeploymentOverride {
+    program_id: Option<String>,
+    acceptable_mints: BTreeMap<String, String>,
+}
+
+fn parse_pubkey(what: &str, address: &str) -> Result<Pubkey> {
+    Pubkey::from_str(address).map_err(|_| anyhow!("Invalid {} {}", what, address))
+}
+
+/// Deployments by cluster
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AddressBook {
+    deployments: BTreeMap<Cluster, Deployment>,
+}
+
+impl AddressBook {
+    /// The deployments this build knows, every cluster runs the program at
+    /// the id it was built with
+    pub fn builtin() -> Self {
+        let mut book = Self::default();
+        for cluster in Cluster::ALL {
+            book.set(*cluster, Deployment::new(token_market::id()));
+        }
+        let usdc = |address: &str| Pubkey::from_str(address).expect("valid builtin address");
+        book.add_acceptable_mint(Cluster::MainnetBeta, "USDC", usdc(MAINNET_USDC));
+        book.add_acceptable_mint(Cluster::Devnet, "USDC", usdc(DEVNET_USDC));
+        book
+    }
+
+    /// Deployment on `cluster`, if there is one
+    pub fn deployment(&self, cluster: Cluster) -> Option<&Deployment> {
+        self.deployments.get(&cluster)
+    }
+
+    /// Replace the deployment on `cluster`
+    pub fn set(&mut self, cluster: Cluster, deployment: Deployment) {
+        self.deployments.insert(cluster, deployment);
+    }
+
+    /// Remove the deployment on `cluster`, e.g. one retired early
+    pub fn remove(&mut self, cluster: Cluster) -> Option<Deployment> {
+        self.deployments.remove(&cluster)
+    }
+
+    /// Point `cluster` at `program_id`, keeping its known mints
+    pub fn override_program(&mut self, cluster: Cluster, program_id: Pubkey) {
+        self.deployments
+            .entry(cluster)
+            .or_insert_with(|| Deployment::new(program_id))
+            .program_id = program_id;
+    }
+
+    /// Add or replace the mint of `symbol` on `cluster`, which must have a deployment
+    pub fn add_acceptable_mint(&mut self, cluster: Cluster, symbol: &str, mint: Pubkey) {
+        if let Some(deployment) = self.deployments.get_mut(&cluster) {
+            deployment.acceptable_mints.insert(symbol.to_string(), mint);
+        }
+    }
+
+    /// Apply the overrides under [PROFILE_KEY](constant.PROFILE_KEY.html)
+    /// of a CLI config profile, a profile without them changes nothing
+    pub fn with_profile(mut self, profile: &serde_json::Value) -> Result<Self> {
+        let overrides = match profile.get(PROFILE_KEY) {
+            Some(overrides) => BTreeMap::<String, DeploymentOverride>::deserialize(overrides)?,
+            None => return Ok(self),
+        };
+        for (name, deployment) in overrides {
+            let cluster = Cluster::from_str(&name)?;
+            if let Some(program_id) = &deployment.program_id {
+                self.override_program(cluster, parse_pubkey("program id", program_id)?);
+            } else if self.deployment(cluster).is_none() {
+                bail!("No known deployment on {}, give its program_id", cluster);
+            }
+            for (symbol, mint) in &deployment.acceptable_mints {
+                self.add_acceptable_mint(cluster, symbol, parse_pubkey("mint", mint)?);
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// RPC URL of a moniker like `devnet` or `d`, other text is taken as a URL
+pub fn url_or_moniker(text: &str) -> String {
+    Cluster::from_moniker(text)
+        .map_or_else(|| text.to_string(), |cluster| cluster.rpc_url().to_string())
+}
+
+/// Acceptable mint on `to` of the token `mint` is on `from`, matched by
+/// the symbol the deployments of `book` know it by
+pub fn mirrored_mint(
+    book: &AddressBook,
+    from: Cluster,
+    to: Cluster,
+    mint: &Pubkey,
+) -> Option<Pubkey> {
+    let symbol = book.deployment(from)?.acceptable_symbol(mint)?;
+    book.deployment(to)?.acceptable_mint(symbol)
+}
+
+/// Whether the cluster `rpc_client` talks to has an executable program at `program_id`
+pub fn hosts_program(rpc_client: &RpcClient, program_id: &Pubkey) -> Result<bool> {
+    let account = rpc_client
+        .get_account_with_commitment(program_id, CommitmentConfig::confirmed())?
+        .value;
+    Ok(account.map_or(false, |account| account.executable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cluster_from_url() {
+        assert_eq!(
+            Cluster::from_url("https://api.mainnet-beta.solana.com"),
+            Some(Cluster::MainnetBeta)
+        );
+        assert_eq!(
+            Cluster::from_url("https://api.devnet.solana.com"),
+            Some(Cluster::Devnet)
+        );
+        assert_eq!(
+            Cluster::from_url("http://127.0.0.1:8899"),
+            Some(Cluster::Localnet)
+        );
+        assert_eq!(Cluster::from_url("https://rpc.example.com/key"), None);
+        assert_eq!(Cluster::from_str("mainnet").unwrap(), Cluster::MainnetBeta);
+    }
+
+    #[test]
+    fn test_url_or_moniker() {
+        assert_eq!(url_or_moniker("d"), "https://api.devnet.solana.com");
+        assert_eq!(
+            Cluster::from_url(&url_or_moniker("mainnet")),
+            Some(Cluster::MainnetBeta)
+        );
+        assert_eq!(
+            url_or_moniker("https://rpc.example.com/key"),
+            "https://rpc.example.com/key"
+        );
+    }
+
+    #[test]
+    fn test_mirrored_mint() {
+        let book = AddressBook::builtin();
+        let mainnet_usdc = Pubkey::from_str(MAINNET_USDC).unwrap();
+        assert_eq!(
+            mirrored_mint(&book, Cluster::MainnetBeta, Cluster::Devnet, &mainnet_usdc),
+            Some(Pubkey::from_str(DEVNET_USDC).unwrap())
+        );
+        assert_eq!(
+            mirrored_mint(&book, Cluster::MainnetBeta, Cluster::Testnet, &mainnet_usdc),
+            None
+        );
+        assert_eq!(
+            mirrored_mint(
+                &book,
+                Cluster::MainnetBeta,
+                Cluster::Devnet,
+                &Pubkey::new_unique()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_builtin_deployments() {
+        let book = AddressBook::builtin();
+        let mainnet = book.deployment(Cluster::MainnetBeta).unwrap();
+        assert_eq!(mainnet.program_id, token_market::id());
+        assert_eq!(
+            mainnet.authority(),
+            find_authority_address(&token_market::id()).0
+        );
+        assert_ne!(
+            mainnet.acceptable_mint("USDC"),
+            book.deployment(Cluster::Devnet)
+                .unwrap()
+                .acceptable_mint("USDC")
+        );
+        assert_eq!(
+            book.deployment(Cluster::Testnet)
+                .unwrap()
+                .acceptable_mint("USDC"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_profile_overrides() {
+        let (program_id, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let profile = serde_json::json!({
+            "json_rpc_url": "http://localhost:8899",
+            PROFILE_KEY: {
+                "localnet": {
+                    "program_id": program_id.to_string(),
+                    "acceptable_mints": { "USDC": mint.to_string() },
+                },
+                "devnet": { "acceptable_mints": { "EURC": mint.to_string() } },
+            },
+        });
+        let book = AddressBook::builtin().with_profile(&profile).unwrap();
+        let localnet = book.deployment(Cluster::Localnet).unwrap();
+        assert_eq!(localnet.program_id, program_id);
+        assert_eq!(localnet.acceptable_mint("USDC"), Some(mint));
+        assert_ne!(
+            localnet.fee_vault(&Pubkey::default(), &mint),
+            find_fee_vault_address(&token_market::id(), &Pubkey::default(), &mint).0
+        );
+        // Overrides add to the known mints
+        let devnet = book.deployment(Cluster::Devnet).unwrap();
+        assert_eq!(devnet.acceptable_mints.len(), 2);
+
+        let unknown = serde_json::json!({ PROFILE_KEY: { "staging": {} } });
+        assert!(AddressBook::builtin().with_profile(&unknown).is_err());
+        assert_eq!(
+            AddressBook::builtin()
+                .with_profile(&serde_json::json!({}))
+                .unwrap(),
+            AddressBook::builtin()
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Alert thresholds and webhooks market owners publish for their monitoring
+
+use anyhow::{anyhow, Result};
+use solana_sdk::pubkey::Pubkey;
+use token_market::amounts::UiAmount;
+use token_market_cli::reader::MarketReader;
+
+/// Process `show-alerts` command, the config of the market owner without `owner`
+pub fn show_alerts(reader: &MarketReader, market: &Pubkey, owner: Option<Pubkey>) -> Result<()> {
+    let token_market = reader.market(market)?;
+    let owner = owner.unwrap_or(token_market.owner);
+    let config = reader
+        .notification_config(market, &owner)?
+        .ok_or_else(|| anyhow!("{} published no alerts for market {}", owner, market))?;
+    let emitted = reader.decimals(&token_market.emitter_mint)?;
+    let acceptable = reader.decimals(&token_market.mint_of_acceptable)?;
+    let threshold = |amount: u64, decimals: u8| match amount {
+        0 => "off".to_string(),
+        amount => UiAmount::from_raw(amount, decimals).to_string(),
+    };
+
+    println!("Market:           {}", config.market);
+    println!("Owner:            {}", config.owner);
+    println!("Webhook hash:     {}", hex::encode(config.webhook_hash));
+    println!(
+        "Large purchase:   {}",
+        threshold(config.thresholds.large_purchase, emitted)
+    );
+    println!(
+        "Daily volume:     {}",
+        threshold(config.thresholds.daily_volume, acceptable)
+    );
+    println!(
+        "Low bank balance: {}",
+        threshold(config.thresholds.low_bank_balance, acceptable)
+    );
+    println!("Sealed settings:  {} bytes", config.sealed.len());
+    Ok(())
+}
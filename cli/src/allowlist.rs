@@ -0,0 +1,48 @@
+//! Merkle allowlists kept as address files
+
+use anyhow::{Context, Result};
+use solana_sdk::pubkey::Pubkey;
+use std::{fs, path::Path, str::FromStr};
+use token_market::merkle::MerkleTree;
+
+/// Allowlist file with one base58 address per line, in the order the tree
+/// is built in. Empty lines and lines starting with `#` are ignored.
+pub struct Allowlist {
+    buyers: Vec<Pubkey>,
+    tree: MerkleTree,
+}
+
+impl Allowlist {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Can't read allowlist {}", path.display()))?;
+        let mut buyers = vec![];
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let buyer = Pubkey::from_str(line)
+                .with_context(|| format!("Invalid address at {}:{}", path.display(), number + 1))?;
+            buyers.push(buyer);
+        }
+        let tree = MerkleTree::new(&buyers);
+        Ok(Self { buyers, tree })
+    }
+
+    /// Number of listed buyers
+    pub fn count(&self) -> usize {
+        self.buyers.len()
+    }
+
+    /// Root the market stores, all zeros for an empty file
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    /// Proof of `buyer`, `None` if it isn't listed
+    pub fn proof(&self, buyer: &Pubkey) -> Option<Vec<[u8; 32]>> {
+        let index = self.buyers.iter().position(|listed| listed == buyer)?;
+        self.tree.proof(index)
+    }
+}
@@ -0,0 +1,1870 @@
+//! Typed command line arguments.
+//!
+//! Every subcommand parses into a struct of its own, so values are validated
+//! and converted once by clap instead of at every use. Names, summaries and
+//! aliases of the subcommands stay in [commands](../commands/index.html).
+
+use crate::{
+    campaign, commands,
+    gift::GiftClaim,
+    listing,
+    logging::LogFormat,
+    messages::{Locale, ProgressFormat},
+    parse_overpayment_policy, parse_wormhole_emitter, quote_server, voucher,
+};
+use clap::{crate_description, crate_name, crate_version, App, AppSettings, ArgMatches};
+use solana_clap_utils::input_validators::{is_url_or_moniker, is_valid_signer};
+use solana_sdk::pubkey::Pubkey;
+use std::{ffi::OsString, path::PathBuf, time::Duration};
+use structopt::StructOpt;
+use token_market::{
+    amounts::UiAmount,
+    pricing::Price,
+    state::{CurveKind, OverpaymentPolicy},
+};
+use token_market_cli::signer::external_signer_from_path;
+
+/// Where the configuration and the cluster come from
+#[derive(StructOpt)]
+pub struct ConnectionArgs {
+    /// Configuration file to use. Defaults to the one of the Solana CLI.
+    /// Its `rpc_requests_per_second`, `rpc_burst` and `rpc_retries` limit
+    /// the RPC calls of every command
+    #[structopt(short = "C", long = "config", value_name = "PATH", global = true)]
+    pub config_file: Option<String>,
+    /// URL for Solana's JSON RPC or moniker (or their first letter):
+    /// [mainnet-beta, testnet, devnet, localhost].
+    /// Default from the configuration file
+    #[structopt(
+        short = "u",
+        long = "url",
+        value_name = "URL_OR_MONIKER",
+        global = true,
+        validator = is_url_or_moniker
+    )]
+    pub json_rpc_url: Option<String>,
+}
+
+/// Keypairs signing the transactions
+#[derive(StructOpt)]
+pub struct SignerArgs {
+    /// Specify the token owner account. This may be a keypair file,
+    /// the ASK keyword or external:PUBKEY=COMMAND for a key held by a
+    /// signing service. Defaults to the client keypair
+    #[structopt(long, value_name = "KEYPAIR", global = true, validator = is_valid_market_signer)]
+    pub owner: Option<String>,
+    /// Specify the fee-payer account. This may be a keypair file,
+    /// the ASK keyword or external:PUBKEY=COMMAND for a key held by a
+    /// signing service. Defaults to the client keypair
+    #[structopt(long, value_name = "KEYPAIR", global = true, validator = is_valid_market_signer)]
+    pub fee_payer: Option<String>,
+}
+
+/// Options accepted by every subcommand
+#[derive(StructOpt)]
+pub struct Options {
+    #[structopt(flatten)]
+    pub connection: ConnectionArgs,
+    #[structopt(flatten)]
+    pub signers: SignerArgs,
+    /// Language of the messages [default: from LC_ALL, LC_MESSAGES or LANG]
+    #[structopt(
+        long,
+        value_name = "LOCALE",
+        global = true,
+        possible_values = Locale::NAMES,
+        parse(try_from_str = locale)
+    )]
+    pub locale: Option<Locale>,
+    /// Report progress, results and errors as text,
+    /// or as one JSON event per line for programs wrapping the CLI
+    #[structopt(
+        long,
+        value_name = "FORMAT",
+        global = true,
+        possible_values = ProgressFormat::NAMES,
+        default_value = "text",
+        parse(try_from_str = progress_format)
+    )]
+    pub progress: ProgressFormat,
+    /// Write diagnostic logs to stderr as text, or as one JSON object per line
+    /// with the spans of the command and transaction for log collectors.
+    /// Levels are taken from RUST_LOG
+    #[structopt(
+        long,
+        value_name = "FORMAT",
+        global = true,
+        possible_values = LogFormat::NAMES,
+        default_value = "text",
+        parse(try_from_str = log_format)
+    )]
+    pub log_format: LogFormat,
+    /// Simulate every transaction and show its effects before sending.
+    /// Also enabled by `simulate_first: true` in the config file
+    #[structopt(long, global = true)]
+    pub simulate_first: bool,
+    /// Before sending, check that the accounts a command relies on exist
+    /// with the expected owners and mints and that the fee payer covers fees
+    /// and rent, reporting every problem at once.
+    /// Also enabled by `strict_preflight: true` in the config file
+    #[structopt(long, global = true)]
+    pub strict_preflight: bool,
+    /// File with addresses, one per line, that are not allowed
+    /// to take part in purchases
+    #[structopt(long, value_name = "PATH", global = true, parse(from_os_str))]
+    pub denylist: Option<PathBuf>,
+}
+
+/// A signer path of the Solana CLI or an `external:PUBKEY=COMMAND` signer
+fn is_valid_market_signer(path: String) -> Result<(), String> {
+    match external_signer_from_path(&path) {
+        Some(external) => external.map(|_| ()).map_err(|err| err.to_string()),
+        None => is_valid_signer(path),
+    }
+}
+
+fn locale(name: &str) -> Result<Locale, String> {
+    Locale::parse(name).ok_or_else(|| format!("Unknown locale {}", name))
+}
+
+fn progress_format(name: &str) -> Result<ProgressFormat, String> {
+    ProgressFormat::parse(name).ok_or_else(|| format!("Unknown progress format {}", name))
+}
+
+fn log_format(name: &str) -> Result<LogFormat, String> {
+    LogFormat::parse(name).ok_or_else(|| format!("Unknown log format {}", name))
+}
+
+/// Duration given as a number followed by `s`, `m`, `h` or `d`,
+/// a bare number counts `unit` seconds
+fn duration(text: &str, unit: u64) -> Result<Duration, String> {
+    let (number, unit) = [("s", 1), ("m", 60), ("h", 60 * 60), ("d", 24 * 60 * 60)]
+        .iter()
+        .find_map(|(suffix, seconds)| Some((text.strip_suffix(suffix)?, *seconds)))
+        .unwrap_or((text, unit));
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Invalid duration {}, e.g. 90, 15m, 24h or 7d", text))
+}
+
+/// Duration whose bare number counts seconds
+fn seconds(text: &str) -> Result<Duration, String> {
+    duration(text, 1)
+}
+
+/// Duration whose bare number counts hours
+fn hours(text: &str) -> Result<Duration, String> {
+    duration(text, 60 * 60)
+}
+
+/// One `--phase` of a phased sale, its amounts in emitted tokens
+pub struct PhaseArg {
+    pub start: i64,
+    pub price: Price,
+    pub cap: Option<UiAmount>,
+    pub max_purchase: Option<UiAmount>,
+    pub allowlist_only: bool,
+}
+
+/// Phase given as comma separated terms, e.g.
+/// `start=1700000000,price=2/1,cap=50000,max=500,allowlist`
+fn sale_phase(text: &str) -> Result<PhaseArg, String> {
+    let (mut start, mut price) = (None, None);
+    let mut phase = PhaseArg {
+        start: 0,
+        price: Price::PAR,
+        cap: None,
+        max_purchase: None,
+        allowlist_only: false,
+    };
+    for term in text.split(',') {
+        let mut parts = term.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = parts.next().unwrap_or_default().trim();
+        let invalid = || format!("Invalid {} in phase {}", key, text);
+        match key {
+            "start" => start = Some(value.parse().map_err(|_| invalid())?),
+            "price" => {
+                price = Some(quote_server::parse_price(value).map_err(|_| invalid())?);
+            }
+            "cap" => phase.cap = Some(value.parse().map_err(|_| invalid())?),
+            "max" => phase.max_purchase = Some(value.parse().map_err(|_| invalid())?),
+            "allowlist" if value.is_empty() => phase.allowlist_only = true,
+            _ => return Err(format!("Unknown term {} in phase {}", term, text)),
+        }
+    }
+    match (start, price) {
+        (Some(start), Some(price)) => Ok(PhaseArg {
+            start,
+            price,
+            ..phase
+        }),
+        _ => Err(format!("Phase {} needs a start and a price", text)),
+    }
+}
+
+/// One `--tier` of a tiered market, its bound in emitted tokens
+pub struct TierArg {
+    pub up_to: Option<UiAmount>,
+    pub price: Price,
+}
+
+/// Tier given as comma separated terms, e.g. `up_to=1000000,price=2/1`,
+/// without `up_to` for a last tier that never ends
+fn pricing_tier(text: &str) -> Result<TierArg, String> {
+    let (mut up_to, mut price) = (None, None);
+    for term in text.split(',') {
+        let mut parts = term.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = parts.next().unwrap_or_default().trim();
+        let invalid = || format!("Invalid {} in tier {}", key, text);
+        match key {
+            "up_to" => up_to = Some(value.parse().map_err(|_| invalid())?),
+            "price" => {
+                price = Some(quote_server::parse_price(value).map_err(|_| invalid())?);
+            }
+            _ => return Err(format!("Unknown term {} in tier {}", term, text)),
+        }
+    }
+    match price {
+        Some(price) => Ok(TierArg { up_to, price }),
+        None => Err(format!("Tier {} needs a price", text)),
+    }
+}
+
+/// `--tier` of a loyalty discount
+pub struct LoyaltyArg {
+    pub from: UiAmount,
+    pub discount_bps: u16,
+}
+
+/// Loyalty tier given as comma separated terms, e.g. `from=1000,discount=200`
+fn loyalty_tier(text: &str) -> Result<LoyaltyArg, String> {
+    let (mut from, mut discount_bps) = (None, None);
+    for term in text.split(',') {
+        let mut parts = term.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = parts.next().unwrap_or_default().trim();
+        let invalid = || format!("Invalid {} in tier {}", key, text);
+        match key {
+            "from" => from = Some(value.parse().map_err(|_| invalid())?),
+            "discount" => discount_bps = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("Unknown term {} in tier {}", term, text)),
+        }
+    }
+    match (from, discount_bps) {
+        (Some(from), Some(discount_bps)) => Ok(LoyaltyArg { from, discount_bps }),
+        _ => Err(format!("Tier {} needs from and discount", text)),
+    }
+}
+
+/// `--curve` of a bonding curve market
+pub struct CurveArg {
+    pub kind: CurveKind,
+    pub base: UiAmount,
+    pub growth: UiAmount,
+    pub step: Option<UiAmount>,
+}
+
+/// Curve given as its kind followed by comma separated terms, e.g.
+/// `linear,base=0.5,growth=0.001` or `exponential,base=0.5,growth=100,step=1000`
+fn bonding_curve(text: &str) -> Result<CurveArg, String> {
+    let mut terms = text.split(',');
+    let kind = match terms.next().unwrap_or_default().trim() {
+        "linear" => CurveKind::Linear,
+        "exponential" => CurveKind::Exponential,
+        kind => return Err(format!("Unknown curve {}, linear or exponential", kind)),
+    };
+    let (mut base, mut growth, mut step) = (None, None, None);
+    for term in terms {
+        let mut parts = term.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().trim();
+        let value = parts.next().unwrap_or_default().trim();
+        let invalid = || format!("Invalid {} in curve {}", key, text);
+        match key {
+            "base" => base = Some(value.parse().map_err(|_| invalid())?),
+            "growth" => growth = Some(value.parse().map_err(|_| invalid())?),
+            "step" => step = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("Unknown term {} in curve {}", term, text)),
+        }
+    }
+    match (kind, base, growth, step) {
+        (CurveKind::Linear, Some(base), Some(growth), None) => Ok(CurveArg {
+            kind,
+            base,
+            growth,
+            step,
+        }),
+        (CurveKind::Exponential, Some(base), Some(growth), Some(_)) => Ok(CurveArg {
+            kind,
+            base,
+            growth,
+            step,
+        }),
+        _ => Err(format!(
+            "Curve {} needs a base and a growth, and a step only if exponential",
+            text
+        )),
+    }
+}
+
+fn on(state: &str) -> bool {
+    state == "on"
+}
+
+fn required(state: &str) -> bool {
+    state == "required"
+}
+
+#[derive(StructOpt)]
+pub struct CreateMarketArgs {
+    /// Mint of the token the market accepts as payment, required unless
+    /// named in the --from-config file
+    #[structopt(value_name = "ADDRESS", required_unless = "from-config")]
+    pub acceptable: Option<Pubkey>,
+    /// Settings file written by export-config to create the market from
+    /// and apply once it exists, the other arguments override it
+    #[structopt(long, value_name = "SETTINGS", parse(from_os_str))]
+    pub from_config: Option<PathBuf>,
+    /// Market type of up to 4 ASCII characters, e.g. IDO
+    #[structopt(long, value_name = "TAG", parse(try_from_str = listing::parse_category))]
+    pub category: Option<[u8; 4]>,
+    /// 32 byte identifier of the project, as hex
+    #[structopt(long, value_name = "HEX", parse(try_from_str = listing::parse_project_id))]
+    pub project_id: Option<[u8; 32]>,
+    /// Comma separated features the market supports, all but selling by default:
+    /// relayer-tips, vesting, vouchers, price-quotes, receipts,
+    /// campaigns, delegation
+    #[structopt(long, value_name = "LIST", parse(try_from_str = listing::parse_features))]
+    pub features: Option<u64>,
+    /// Sell in phases, given in the order they open as
+    /// start=UNIX_TIMESTAMP,price=N/D[,cap=AMOUNT][,max=AMOUNT][,allowlist].
+    /// Allowlisted phases only accept vouchers and quotes
+    #[structopt(
+        long = "phase",
+        value_name = "PHASE",
+        number_of_values = 1,
+        parse(try_from_str = sale_phase)
+    )]
+    pub phases: Vec<PhaseArg>,
+    /// When the phased sale closes. Defaults to never
+    #[structopt(long, value_name = "UNIX_TIMESTAMP", requires = "phases")]
+    pub sale_end: Option<i64>,
+    /// Price by the emitted tokens sold so far, given in order as
+    /// up_to=AMOUNT,price=N/D, the last one may leave out up_to to never end.
+    /// Tiers are fixed once the market exists
+    #[structopt(
+        long = "tier",
+        value_name = "TIER",
+        number_of_values = 1,
+        parse(try_from_str = pricing_tier)
+    )]
+    pub tiers: Vec<TierArg>,
+    /// Price by a bonding curve of the emitted tokens sold so far, either
+    /// linear,base=PRICE,growth=PRICE with the price growing by growth for
+    /// every emitted token sold, or exponential,base=PRICE,growth=BPS,step=AMOUNT
+    /// with the price growing by growth basis points every step tokens sold.
+    /// The curve is fixed once the market exists
+    #[structopt(long, value_name = "CURVE", conflicts_with = "tiers", parse(try_from_str = bonding_curve))]
+    pub curve: Option<CurveArg>,
+    /// Fewest accepted tokens a single purchase may pay, e.g. 1.5
+    #[structopt(long, value_name = "AMOUNT")]
+    pub min_purchase: Option<UiAmount>,
+    /// Most accepted tokens a single purchase may pay, no limit by default
+    #[structopt(long, value_name = "AMOUNT")]
+    pub max_purchase: Option<UiAmount>,
+    /// When the market starts selling. Defaults to right away
+    #[structopt(long, value_name = "UNIX_TIMESTAMP")]
+    pub opens_at: Option<i64>,
+    /// When the market stops selling. Defaults to never
+    #[structopt(long, value_name = "UNIX_TIMESTAMP")]
+    pub closes_at: Option<i64>,
+    /// Seconds a wallet waits after a purchase before its next one, no wait by default
+    #[structopt(long, value_name = "SECONDS")]
+    pub purchase_cooldown: Option<i64>,
+}
+
+#[derive(StructOpt)]
+pub struct FindMarketArgs {
+    /// Emitter mint of the market
+    #[structopt(value_name = "MINT_ADDRESS")]
+    pub mint: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct ListMarketsArgs {
+    /// Market owner
+    #[structopt(long, value_name = "ADDRESS")]
+    pub market_owner: Option<Pubkey>,
+    /// Market type
+    #[structopt(long, value_name = "TAG", parse(try_from_str = listing::parse_category))]
+    pub category: Option<[u8; 4]>,
+    /// Project identifier, as hex
+    #[structopt(long, value_name = "HEX", parse(try_from_str = listing::parse_project_id))]
+    pub project_id: Option<[u8; 32]>,
+}
+
+#[derive(StructOpt)]
+pub struct BuyTokensArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// User which tokens are received
+    #[structopt(value_name = "ACCOUNT_ADDRESS")]
+    pub recipient: Pubkey,
+    /// Amount of paid tokens, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+    /// Relay the purchase: the fee payer submits it and receives this tip
+    /// in its associated account of the acceptable mint
+    #[structopt(long, value_name = "AMOUNT")]
+    pub relayer_tip: Option<UiAmount>,
+    /// Buy under a voucher printed by sign-voucher
+    #[structopt(long, value_name = "VOUCHER", conflicts_with = "relayer-tip")]
+    pub voucher: Option<String>,
+    /// Buy at the price of a quote served by quote-server
+    #[structopt(
+        long,
+        value_name = "QUOTE",
+        conflicts_with_all = &["relayer-tip", "voucher", "campaign"]
+    )]
+    pub quote: Option<String>,
+    /// Attribute the purchase to a campaign code of up to 8 characters
+    #[structopt(
+        long,
+        value_name = "CODE",
+        conflicts_with_all = &["relayer-tip", "voucher"],
+        parse(try_from_str = campaign::parse_campaign)
+    )]
+    pub campaign: Option<[u8; 8]>,
+    /// Redeem a discount coupon of the market
+    #[structopt(
+        long,
+        value_name = "CODE",
+        conflicts_with_all = &["relayer-tip", "voucher", "quote", "campaign"]
+    )]
+    pub coupon: Option<String>,
+    /// State the purpose of the payment, e.g. an invoice number
+    #[structopt(long, value_name = "TEXT")]
+    pub memo: Option<String>,
+    /// Buy even if the market is deprecated
+    #[structopt(long)]
+    pub allow_deprecated: bool,
+    /// Allowlist file of the market, to prove the recipient's owner is on it
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub allowlist: Option<PathBuf>,
+    /// Pay in native SOL, wrapped into the bank of a market accepting wrapped SOL
+    #[structopt(
+        long,
+        conflicts_with_all = &["relayer-tip", "voucher", "quote", "campaign", "coupon", "allowlist"]
+    )]
+    pub native_sol: bool,
+}
+
+#[derive(StructOpt)]
+pub struct DeprecateArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Market that replaces this one
+    #[structopt(long, value_name = "MARKET_ADDRESS")]
+    pub successor: Option<Pubkey>,
+    /// Where buyers can read why the market is deprecated
+    #[structopt(long, value_name = "URI")]
+    pub message_uri: String,
+}
+
+#[derive(StructOpt)]
+pub struct SetVoucherSignerArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Ethereum address of the voucher key,
+    /// 0x0000000000000000000000000000000000000000 disables vouchers
+    #[structopt(value_name = "ETH_ADDRESS", parse(try_from_str = voucher::parse_eth_address))]
+    pub eth_address: [u8; 20],
+}
+
+#[derive(StructOpt)]
+pub struct SignVoucherArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Owner of the paying token account
+    #[structopt(value_name = "BUYER_ADDRESS")]
+    pub buyer: Pubkey,
+    /// Largest amount of paid tokens per purchase, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub max_amount: UiAmount,
+    /// Last moment the voucher can be used
+    #[structopt(long, value_name = "UNIX_TIMESTAMP")]
+    pub expiry: i64,
+    /// File holding the hex encoded secp256k1 voucher key
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub voucher_key: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct SignOwnershipProofArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Domain of the site listing the market, e.g. explorer.example.com
+    #[structopt(long, value_name = "DOMAIN")]
+    pub domain: String,
+}
+
+#[derive(StructOpt)]
+pub struct QuoteServerArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Emitted tokens per acceptable token, e.g. 3/2
+    #[structopt(
+        long,
+        value_name = "PRICE",
+        required_unless = "price-file",
+        conflicts_with = "price-file",
+        parse(try_from_str = quote_server::parse_price)
+    )]
+    pub price: Option<Price>,
+    /// File with the current price, read on every request
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub price_file: Option<PathBuf>,
+    /// Largest amount of paid tokens a quote covers, e.g. 1.5
+    #[structopt(long, value_name = "AMOUNT")]
+    pub max_amount: UiAmount,
+    /// How long a quote stays valid, in seconds unless suffixed with m or h
+    #[structopt(long, value_name = "DURATION", default_value = "30", parse(try_from_str = seconds))]
+    pub ttl: Duration,
+    /// Address to serve quotes on
+    #[structopt(long, value_name = "ADDRESS", default_value = "127.0.0.1:8080")]
+    pub bind: String,
+    /// File holding the hex encoded secp256k1 voucher key
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub voucher_key: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct CreateVestingArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Wallet that can claim the vested tokens
+    #[structopt(value_name = "ACCOUNT_ADDRESS")]
+    pub beneficiary: Pubkey,
+    /// Amount of locked tokens, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub total: UiAmount,
+    /// Schedule start. Defaults to now
+    #[structopt(long, value_name = "UNIX_TIMESTAMP")]
+    pub start: Option<i64>,
+    /// Time after start before anything is vested, in seconds unless suffixed
+    /// with m, h or d
+    #[structopt(long, value_name = "DURATION", default_value = "0", parse(try_from_str = seconds))]
+    pub cliff: Duration,
+    /// Time after start when everything is vested, in seconds unless suffixed
+    /// with m, h or d
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = seconds))]
+    pub duration: Duration,
+    /// Allow the owner to revoke unvested tokens
+    #[structopt(long)]
+    pub revocable: bool,
+}
+
+#[derive(StructOpt)]
+pub struct VestingArgs {
+    /// Vesting account pubkey
+    #[structopt(value_name = "VESTING_ADDRESS")]
+    pub vesting: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct MigrateAcceptableArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// New mint of the accepted token
+    #[structopt(value_name = "MINT_ADDRESS")]
+    pub acceptable: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct MarketArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct WithdrawBankArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Amount of the accepted token to withdraw, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+    /// Token account of the accepted mint, the treasury or the associated
+    /// account of the owner by default
+    #[structopt(long, value_name = "ACCOUNT_ADDRESS")]
+    pub destination: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+pub struct SetWithdrawRateArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Most accepted tokens a day the bank releases, e.g. 1000. Can only be
+    /// lowered once set
+    #[structopt(value_name = "AMOUNT")]
+    pub withdraw_rate: UiAmount,
+}
+
+#[derive(StructOpt)]
+pub struct WithdrawFeesArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Amount of the collected fees to withdraw, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+    /// Token account of the accepted mint, the treasury or the associated
+    /// account of the owner by default
+    #[structopt(long, value_name = "ACCOUNT_ADDRESS")]
+    pub destination: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+pub struct SetPhasesArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Phase as start=UNIX_TIMESTAMP,price=N/D[,cap=AMOUNT][,max=AMOUNT][,allowlist],
+    /// repeated in the order the phases open. Tokens sold so far stay counted
+    #[structopt(
+        long = "phase",
+        value_name = "PHASE",
+        required = true,
+        number_of_values = 1,
+        parse(try_from_str = sale_phase)
+    )]
+    pub phases: Vec<PhaseArg>,
+    /// When the sale closes. Defaults to never
+    #[structopt(long, value_name = "UNIX_TIMESTAMP")]
+    pub sale_end: Option<i64>,
+}
+
+#[derive(StructOpt)]
+pub struct SetTreasuryArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Token account of the accepted mint
+    #[structopt(value_name = "ACCOUNT_ADDRESS")]
+    pub treasury: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct SetOverpaymentPolicyArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Reject the purchase, keep the excess in the bank or refund it
+    #[structopt(
+        value_name = "POLICY",
+        possible_values = &["reject", "donate", "refund"],
+        parse(try_from_str = parse_overpayment_policy)
+    )]
+    pub policy: OverpaymentPolicy,
+}
+
+#[derive(StructOpt)]
+pub struct SetReceiptMintingArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Whether purchases mint receipts
+    #[structopt(value_name = "STATE", possible_values = &["on", "off"], parse(from_str = on))]
+    pub enabled: bool,
+}
+
+#[derive(StructOpt)]
+pub struct SetMemoPolicyArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Whether every purchase must state a memo
+    #[structopt(
+        value_name = "STATE",
+        possible_values = &["required", "optional"],
+        parse(from_str = required)
+    )]
+    pub required: bool,
+}
+
+#[derive(StructOpt)]
+pub struct SetSandwichGuardArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Whether quoted purchases must be alone on the market in their transaction
+    #[structopt(value_name = "STATE", possible_values = &["on", "off"], parse(from_str = on))]
+    pub enabled: bool,
+}
+
+#[derive(StructOpt)]
+pub struct SetProtocolFeeArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Share of every payment kept in the fee vault, in basis points
+    #[structopt(value_name = "BPS")]
+    pub fee_bps: u16,
+}
+
+#[derive(StructOpt)]
+pub struct SetPurchaseLimitsArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Fewest accepted tokens a single purchase may pay, no minimum by default
+    #[structopt(long, value_name = "AMOUNT")]
+    pub min: Option<UiAmount>,
+    /// Most accepted tokens a single purchase may pay, no limit by default
+    #[structopt(long, value_name = "AMOUNT")]
+    pub max: Option<UiAmount>,
+}
+
+#[derive(StructOpt)]
+pub struct SetSaleWindowArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// When the market starts selling. Defaults to right away
+    #[structopt(long, value_name = "UNIX_TIMESTAMP")]
+    pub start: Option<i64>,
+    /// When the market stops selling. Defaults to never
+    #[structopt(long, value_name = "UNIX_TIMESTAMP")]
+    pub end: Option<i64>,
+}
+
+#[derive(StructOpt)]
+pub struct SetWalletCapArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Most emitted tokens a single wallet may buy in total, 0 for no cap
+    #[structopt(value_name = "AMOUNT")]
+    pub wallet_cap: UiAmount,
+}
+
+#[derive(StructOpt)]
+pub struct SetWalletSpendCapArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Most accepted tokens a single wallet may spend in total, 0 for no cap
+    #[structopt(value_name = "AMOUNT")]
+    pub wallet_spend_cap: UiAmount,
+}
+
+#[derive(StructOpt)]
+pub struct SetPurchaseCooldownArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Seconds a wallet waits after a purchase before its next one, 0 for no wait
+    #[structopt(value_name = "SECONDS")]
+    pub purchase_cooldown: i64,
+}
+
+#[derive(StructOpt)]
+pub struct SetLoyaltyTiersArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Discount buyers who purchased enough emitted tokens before, given in
+    /// order as from=AMOUNT,discount=BPS. Discounts stop if omitted
+    #[structopt(
+        long = "tier",
+        value_name = "TIER",
+        number_of_values = 1,
+        parse(try_from_str = loyalty_tier)
+    )]
+    pub tiers: Vec<LoyaltyArg>,
+}
+
+#[derive(StructOpt)]
+pub struct SetStakeWeightArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Pool mint of the stake pool, or governing token mint with
+    /// --governance-program. Stakes stop raising caps if omitted
+    #[structopt(long, value_name = "MINT_ADDRESS")]
+    pub stake_mint: Option<Pubkey>,
+    /// Governance program holding the deposits, pool tokens count if omitted
+    #[structopt(long, value_name = "PROGRAM_ID")]
+    pub governance_program: Option<Pubkey>,
+    /// Emitted tokens added to the wallet cap per 10 000 staked tokens
+    #[structopt(long, value_name = "BPS", default_value = "10000")]
+    pub bonus_bps: u32,
+}
+
+#[derive(StructOpt)]
+pub struct SetAllowlistArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Whether only allowlisted buyers may purchase
+    #[structopt(value_name = "STATE", possible_values = &["on", "off"], parse(from_str = on))]
+    pub enabled: bool,
+}
+
+#[derive(StructOpt)]
+pub struct SetAllowlistRootArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// File with one buyer address per line, an empty one drops the root
+    #[structopt(value_name = "FILE", parse(from_os_str))]
+    pub file: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct AllowlistArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Wallet owning the accounts the buyer receives tokens in
+    #[structopt(value_name = "BUYER_ADDRESS")]
+    pub buyer: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct SetWormholeEmitterArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Wormhole chain id of the emitter, e.g. 2 for Ethereum
+    #[structopt(value_name = "CHAIN_ID")]
+    pub chain: u16,
+    /// Emitter contract address as hex, all zeros stop cross-chain purchases
+    #[structopt(value_name = "EMITTER", parse(try_from_str = parse_wormhole_emitter))]
+    pub emitter: [u8; 32],
+}
+
+#[derive(StructOpt)]
+pub struct RedeemWormholeArgs {
+    /// Account the Wormhole core bridge posted the purchase message to
+    #[structopt(value_name = "POSTED_VAA_ADDRESS")]
+    pub posted_vaa: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct SetOnrampSignerArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Key of the on-ramp provider, the default pubkey stops fiat purchases
+    #[structopt(value_name = "SIGNER_ADDRESS")]
+    pub signer: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct BuyWithFiatArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// User which tokens are received
+    #[structopt(value_name = "ACCOUNT_ADDRESS")]
+    pub recipient: Pubkey,
+    /// Amount paid in fiat, in acceptable tokens, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+    /// Payment id at the on-ramp provider, each one mints once
+    #[structopt(long, value_name = "TEXT")]
+    pub reference: String,
+    /// On-ramp provider keypair attesting the payment
+    #[structopt(long, value_name = "KEYPAIR", validator = is_valid_market_signer)]
+    pub onramp_key: String,
+}
+
+#[derive(StructOpt)]
+pub struct SetDisputeEscrowArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Emitted amount from which purchases are escrowed, 0 stops escrowing
+    #[structopt(long, value_name = "AMOUNT")]
+    pub threshold: UiAmount,
+    /// How long an escrowed purchase can be disputed, in hours unless
+    /// suffixed with m or d
+    #[structopt(long, value_name = "DURATION", default_value = "24", parse(try_from_str = hours))]
+    pub window: Duration,
+    /// Key that may dispute purchases besides the owner
+    #[structopt(long, value_name = "ADDRESS")]
+    pub guardian: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+pub struct CreateCouponArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Code buyers pass to buy-tokens --coupon, case-insensitive
+    #[structopt(long, value_name = "CODE")]
+    pub code: String,
+    /// Part of the price waived, in basis points, e.g. 1500 for 15%
+    #[structopt(long, value_name = "BPS")]
+    pub discount_bps: u16,
+    /// Purchases that may redeem the coupon
+    #[structopt(long, value_name = "COUNT")]
+    pub max_uses: u32,
+    /// Unix timestamp from which the coupon can't be redeemed
+    #[structopt(long, value_name = "UNIX_TIMESTAMP")]
+    pub expiry: Option<i64>,
+}
+
+#[derive(StructOpt)]
+pub struct BuyEscrowedArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// User which tokens are received
+    #[structopt(value_name = "ACCOUNT_ADDRESS")]
+    pub recipient: Pubkey,
+    /// Amount of paid tokens, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+    /// State the purpose of the payment, e.g. an invoice number
+    #[structopt(long, value_name = "TEXT")]
+    pub memo: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct BuyMultiArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// File of the wallets to buy for, one `WALLET AMOUNT` per line
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub recipients: PathBuf,
+    /// State the purpose of the payments, e.g. an invoice number
+    #[structopt(long, value_name = "TEXT")]
+    pub memo: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct BuyExactOutArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// User which tokens are received
+    #[structopt(value_name = "ACCOUNT_ADDRESS")]
+    pub recipient: Pubkey,
+    /// Amount of emitted tokens to receive, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+    /// Most paid tokens to spend, the purchase fails if they cost more
+    #[structopt(long, value_name = "AMOUNT")]
+    pub max_in: UiAmount,
+    /// State the purpose of the payment, e.g. an invoice number
+    #[structopt(long, value_name = "TEXT")]
+    pub memo: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct BuyGiftArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Amount of paid tokens, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+    /// How long the gift can be claimed before it is refundable, in hours
+    /// unless suffixed with m or d
+    #[structopt(long, value_name = "DURATION", default_value = "30d", parse(try_from_str = hours))]
+    pub valid_for: Duration,
+    /// State the purpose of the payment, e.g. an invoice number
+    #[structopt(long, value_name = "TEXT")]
+    pub memo: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct ClaimGiftArgs {
+    /// Claim printed by buy-gift
+    #[structopt(value_name = "MARKET:SECRET")]
+    pub claim: GiftClaim,
+    /// Wallet receiving the tokens, the owner keypair by default
+    #[structopt(long, value_name = "ADDRESS")]
+    pub recipient: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+pub struct RefundGiftArgs {
+    /// Expired unclaimed gift
+    #[structopt(value_name = "GIFT_ADDRESS")]
+    pub gift: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct SellTokensArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Amount of emitted tokens to sell, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+}
+
+#[derive(StructOpt)]
+pub struct ReleaseEscrowArgs {
+    /// Escrowed purchase whose dispute window passed
+    #[structopt(value_name = "ESCROW_ADDRESS")]
+    pub escrow: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct DisputeEscrowArgs {
+    /// Escrowed purchase to refund, signed by the owner or guardian keypair
+    #[structopt(value_name = "ESCROW_ADDRESS")]
+    pub escrow: Pubkey,
+}
+
+#[derive(StructOpt)]
+pub struct SetDelegationArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Hot key that signs in place of the owner
+    #[structopt(value_name = "DELEGATE_ADDRESS")]
+    pub delegate: Pubkey,
+    /// Emitted tokens the delegate may lock in vestings per day, e.g. 1.5
+    #[structopt(long, value_name = "AMOUNT")]
+    pub vesting_per_day: Option<UiAmount>,
+    /// Listing updates per day
+    #[structopt(long, value_name = "COUNT")]
+    pub listing_per_day: Option<u64>,
+    /// Overpayment policy changes per day
+    #[structopt(long, value_name = "COUNT")]
+    pub overpayment_policy_per_day: Option<u64>,
+    /// Receipt minting changes per day
+    #[structopt(long, value_name = "COUNT")]
+    pub receipt_minting_per_day: Option<u64>,
+}
+
+#[derive(StructOpt)]
+pub struct EstimateCostArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// User which tokens are received
+    #[structopt(value_name = "ACCOUNT_ADDRESS")]
+    pub recipient: Pubkey,
+    /// Amount of paid tokens, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+    /// Tip of a relayed purchase
+    #[structopt(long, value_name = "AMOUNT")]
+    pub relayer_tip: Option<UiAmount>,
+    /// Campaign code the purchase is attributed to
+    #[structopt(
+        long,
+        value_name = "CODE",
+        conflicts_with = "relayer-tip",
+        parse(try_from_str = campaign::parse_campaign)
+    )]
+    pub campaign: Option<[u8; 8]>,
+    /// Also print every intermediate value of the purchase math, as the
+    /// program built with `debug-math` logs it
+    #[structopt(long)]
+    pub explain_quote: bool,
+}
+
+#[derive(StructOpt)]
+pub struct CampaignStatsArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Campaign code given to buy-tokens
+    #[structopt(value_name = "CODE")]
+    pub campaign: String,
+}
+
+#[derive(StructOpt)]
+pub struct PhaseStatsArgs {
+    /// Phased markets to compare, added up phase by phase when several
+    #[structopt(value_name = "MARKET_ADDRESS", required = true)]
+    pub markets: Vec<Pubkey>,
+    /// Print the stats as JSON
+    #[structopt(long)]
+    pub json: bool,
+    /// Also value the proceeds and holdings of the markets in this currency, e.g. usd
+    #[structopt(long, value_name = "CURRENCY")]
+    pub value_in: Option<String>,
+    /// Where --value-in prices come from: coingecko, pyth or a file of
+    /// MINT=PRICE lines
+    #[structopt(long, value_name = "SOURCE", default_value = "coingecko")]
+    pub prices: String,
+    /// Pyth price account of a mint as MINT=PRICE_ACCOUNT, for --prices pyth
+    #[structopt(
+        long = "pyth-feed",
+        value_name = "MINT=ACCOUNT",
+        number_of_values = 1,
+        parse(try_from_str = listing::parse_pyth_feed)
+    )]
+    pub pyth_feeds: Vec<(Pubkey, Pubkey)>,
+}
+
+#[derive(StructOpt)]
+pub struct TenantStatsArgs {
+    /// Market owner to show, every tenant of the deployment if omitted
+    #[structopt(value_name = "OWNER_ADDRESS")]
+    pub market_owner: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+pub struct SetListingArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Project website
+    #[structopt(long, value_name = "URL")]
+    pub website: String,
+    /// Logo URI, only its hash is stored
+    #[structopt(long, value_name = "URI")]
+    pub logo_uri: String,
+    /// Issuer contact, only its hash is stored
+    #[structopt(long, value_name = "CONTACT")]
+    pub contact: String,
+}
+
+#[derive(StructOpt)]
+pub struct SetAlertsArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Webhook notified of alerts, only its hash is stored
+    #[structopt(long, value_name = "URL")]
+    pub webhook: Option<String>,
+    /// Alert on purchases of at least this many emitted tokens
+    #[structopt(long, value_name = "AMOUNT")]
+    pub large_purchase: Option<UiAmount>,
+    /// Alert once a UTC day pays at least this many accepted tokens
+    #[structopt(long, value_name = "AMOUNT")]
+    pub daily_volume: Option<UiAmount>,
+    /// Alert once the bank holds less than this many accepted tokens
+    #[structopt(long, value_name = "AMOUNT")]
+    pub low_bank_balance: Option<UiAmount>,
+    /// File of settings already encrypted for the notifier, stored as is
+    #[structopt(long, value_name = "PATH")]
+    pub sealed: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct ShowAlertsArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Publisher of the alerts, the market owner by default
+    #[structopt(long, value_name = "PUBKEY")]
+    pub owner: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+pub struct CreateHoldingsMigrationArgs {
+    /// Deprecated market owned by the owner keypair
+    #[structopt(value_name = "DEPRECATED_MARKET")]
+    pub from_market: Pubkey,
+    /// Replacement market
+    #[structopt(value_name = "NEW_MARKET")]
+    pub to_market: Pubkey,
+    /// Owner of the replacement market
+    #[structopt(long, value_name = "KEYPAIR", validator = is_valid_market_signer)]
+    pub to_owner: String,
+    /// New tokens received per denominator burned tokens
+    #[structopt(long, value_name = "NUMBER", default_value = "1")]
+    pub numerator: u64,
+    /// Burned tokens per numerator new tokens
+    #[structopt(long, value_name = "NUMBER", default_value = "1")]
+    pub denominator: u64,
+}
+
+#[derive(StructOpt)]
+pub struct MigrateHoldingsArgs {
+    /// Holdings migration account pubkey
+    #[structopt(value_name = "MIGRATION_ADDRESS")]
+    pub migration: Pubkey,
+    /// Amount of deprecated tokens to burn, e.g. 1.5
+    #[structopt(value_name = "AMOUNT")]
+    pub amount: UiAmount,
+}
+
+#[derive(StructOpt)]
+pub struct DoctorArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Print the report as JSON for monitoring
+    #[structopt(long)]
+    pub json: bool,
+}
+
+#[derive(StructOpt)]
+pub struct HistoryArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Number of transactions to show
+    #[structopt(long, value_name = "COUNT", default_value = "20")]
+    pub limit: usize,
+}
+
+#[derive(StructOpt)]
+pub struct GenFixturesArgs {
+    /// Directory the fixtures are written to
+    #[structopt(long, value_name = "DIR", parse(from_os_str))]
+    pub out: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct EncodeInstructionArgs {
+    /// Instruction as JSON, e.g. `{"SetWalletCap": {"wallet_cap": 100}}`, `-` reads it from stdin
+    #[structopt(value_name = "JSON")]
+    pub json: String,
+    /// Hex encoded trailing fields appended after the instruction
+    #[structopt(long, value_name = "HEX")]
+    pub trailing: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct DecodeInstructionDataArgs {
+    /// Hex encoded instruction data, with or without `0x`
+    #[structopt(value_name = "HEX")]
+    pub data: String,
+}
+
+#[derive(StructOpt)]
+pub struct SettingsFileArgs {
+    /// TOML file with the desired market settings
+    #[structopt(long, value_name = "SETTINGS", parse(from_os_str))]
+    pub file: PathBuf,
+    /// Market account pubkey, defaults to `market` in the file
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+pub struct ExportConfigArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// File to write the settings to instead of stdout
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct MirrorArgs {
+    /// Market to copy
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Cluster the market is on, as a URL or moniker
+    #[structopt(long, value_name = "URL_OR_MONIKER", validator = is_url_or_moniker)]
+    pub from: String,
+    /// Cluster to create the copy on, as a URL or moniker. Defaults to --url
+    #[structopt(long, value_name = "URL_OR_MONIKER", validator = is_url_or_moniker)]
+    pub to: Option<String>,
+    /// Mint the copy accepts, by default the one of the same symbol in the
+    /// address book
+    #[structopt(long, value_name = "MINT_ADDRESS")]
+    pub acceptable: Option<Pubkey>,
+    /// Mapping file of the copies on the target cluster, updated in place
+    #[structopt(
+        long,
+        value_name = "PATH",
+        default_value = "mirrors.json",
+        parse(from_os_str)
+    )]
+    pub mapping: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct StateDiffArgs {
+    /// Market account pubkey
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// Diff from the latest snapshot at or before this slot
+    #[structopt(long, value_name = "SLOT", required_unless = "record")]
+    pub before: Option<u64>,
+    /// Diff to the latest snapshot at or before this slot instead of the live state
+    #[structopt(long, value_name = "SLOT", conflicts_with = "record")]
+    pub after: Option<u64>,
+    /// Directory of the snapshots, one directory of account files per slot
+    #[structopt(
+        long,
+        value_name = "PATH",
+        default_value = "snapshots",
+        parse(from_os_str)
+    )]
+    pub snapshots: PathBuf,
+    /// Write the accounts of the market at the current slot into the snapshots instead
+    #[structopt(long, conflicts_with = "before")]
+    pub record: bool,
+}
+
+#[derive(StructOpt)]
+pub struct MultiExecArgs {
+    /// YAML plan listing the steps to execute
+    #[structopt(long, value_name = "PLAN", parse(from_os_str))]
+    pub file: PathBuf,
+    /// Print the transactions the plan resolves to without sending them
+    #[structopt(long)]
+    pub dry_run: bool,
+}
+
+#[derive(StructOpt)]
+pub struct ProbeArgs {
+    /// Deployed program address. Defaults to the token market program id
+    #[structopt(long, value_name = "PROGRAM_ID")]
+    pub program_id: Option<Pubkey>,
+}
+
+#[derive(StructOpt)]
+pub struct VerifyBuildArgs {
+    /// Deployed program address. Defaults to the token market program id
+    #[structopt(long, value_name = "PROGRAM_ID")]
+    pub program_id: Option<Pubkey>,
+    /// Commit of this repository the program must be built from
+    #[structopt(long, value_name = "HASH")]
+    pub commit: String,
+    /// Path to the token market repository
+    #[structopt(long, value_name = "PATH", default_value = ".", parse(from_os_str))]
+    pub repo: PathBuf,
+    /// Published artifact to compare instead of rebuilding the commit
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub artifact: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+pub struct UpgradeRehearseArgs {
+    /// Market to clone into the local validator
+    #[structopt(value_name = "MARKET_ADDRESS")]
+    pub market: Pubkey,
+    /// New build of the program to compare with the deployed one
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub program_so: PathBuf,
+    /// YAML scenario of buy, sell and withdraw steps to replay
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub scenario: PathBuf,
+    /// RPC port of the local validator
+    #[structopt(long, value_name = "PORT", default_value = "8899")]
+    pub rpc_port: u16,
+}
+
+#[derive(StructOpt)]
+#[structopt(setting = AppSettings::TrailingVarArg)]
+pub struct GenProposalArgs {
+    /// Governance account that owns the markets and executes the proposal
+    #[structopt(long, value_name = "GOVERNANCE_ADDRESS")]
+    pub governance: Pubkey,
+    /// Subcommand to propose followed by its arguments, e.g. `set-protocol-fee MARKET 50`
+    #[structopt(value_name = "COMMAND", required = true)]
+    pub invocation: Vec<String>,
+}
+
+#[derive(StructOpt)]
+#[structopt(setting = AppSettings::TrailingVarArg)]
+pub struct PlanArgs {
+    /// Subcommand to plan followed by its arguments, e.g. `deprecate MARKET --successor NEW`
+    #[structopt(value_name = "COMMAND", required = true)]
+    pub invocation: Vec<String>,
+}
+
+/// The command line `args` without the `wrapper` subcommand, e.g. `plan`,
+/// and the valued `wrapper_options` of its own in front of `invocation`,
+/// so the wrapped command parses exactly as if it were run on its own
+pub fn planned_command_line(
+    args: Vec<OsString>,
+    wrapper: &str,
+    wrapper_options: &[&str],
+    invocation: &[String],
+) -> Vec<OsString> {
+    let head = args.len() - invocation.len();
+    let start = args[..head]
+        .iter()
+        .rposition(|arg| arg == wrapper)
+        .expect("the wrapper is on the command line");
+    let mut dropped = vec![start];
+    for index in start + 1..head {
+        let arg = args[index].to_string_lossy();
+        for option in wrapper_options {
+            if arg == *option {
+                dropped.extend(&[index, index + 1]);
+            } else if arg.starts_with(&format!("{}=", option)) {
+                dropped.push(index);
+            }
+        }
+    }
+    args.into_iter()
+        .enumerate()
+        .filter(|(index, _)| !dropped.contains(index))
+        .map(|(_, arg)| arg)
+        .collect()
+}
+
+/// Declare the `Command` enum, one variant per subcommand name
+macro_rules! subcommands {
+    ($($variant:ident($args:ty) = $name:literal,)*) => {
+        /// Subcommand with its parsed arguments
+        pub enum Command {
+            $($variant($args),)*
+        }
+
+        impl Command {
+            /// The subcommands with the summaries and aliases of
+            /// [GROUPS](../commands/constant.GROUPS.html)
+            fn apps<'a, 'b>() -> Vec<App<'a, 'b>> {
+                vec![$(commands::subcommand($name, <$args>::clap()),)*]
+            }
+
+            /// Name of the subcommand, never an alias
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Command::$variant(_) => $name,)*
+                }
+            }
+
+            fn from_matches(matches: &ArgMatches) -> Command {
+                match matches.subcommand() {
+                    $(($name, Some(args)) => Command::$variant(<$args>::from_clap(args)),)*
+                    _ => unreachable!(),
+                }
+            }
+        }
+    };
+}
+
+subcommands! {
+    CreateMarket(CreateMarketArgs) = "create-market",
+    FindMarket(FindMarketArgs) = "find-market",
+    ListMarkets(ListMarketsArgs) = "list-markets",
+    BuyTokens(BuyTokensArgs) = "buy-tokens",
+    Deprecate(DeprecateArgs) = "deprecate",
+    SetVoucherSigner(SetVoucherSignerArgs) = "set-voucher-signer",
+    SignVoucher(SignVoucherArgs) = "sign-voucher",
+    SignOwnershipProof(SignOwnershipProofArgs) = "sign-ownership-proof",
+    QuoteServer(QuoteServerArgs) = "quote-server",
+    CreateVesting(CreateVestingArgs) = "create-vesting",
+    ClaimVesting(VestingArgs) = "claim-vesting",
+    RevokeVesting(VestingArgs) = "revoke-vesting",
+    CrankVesting(MarketArgs) = "crank-vesting",
+    GarbageCollect(MarketArgs) = "garbage-collect",
+    MigrateAcceptable(MigrateAcceptableArgs) = "migrate-acceptable",
+    RecreateBank(MarketArgs) = "recreate-bank",
+    WithdrawBank(WithdrawBankArgs) = "withdraw-bank",
+    SetWithdrawRate(SetWithdrawRateArgs) = "set-withdraw-rate",
+    WithdrawFees(WithdrawFeesArgs) = "withdraw-fees",
+    Pause(MarketArgs) = "pause",
+    Resume(MarketArgs) = "resume",
+    SetPhases(SetPhasesArgs) = "set-phases",
+    AdvancePhase(MarketArgs) = "advance-phase",
+    ShowPhases(MarketArgs) = "show-phases",
+    SetTreasury(SetTreasuryArgs) = "set-treasury",
+    SetOverpaymentPolicy(SetOverpaymentPolicyArgs) = "set-overpayment-policy",
+    SetMemoPolicy(SetMemoPolicyArgs) = "set-memo-policy",
+    SetSandwichGuard(SetSandwichGuardArgs) = "set-sandwich-guard",
+    SetProtocolFee(SetProtocolFeeArgs) = "set-protocol-fee",
+    SetPurchaseLimits(SetPurchaseLimitsArgs) = "set-purchase-limits",
+    SetWalletCap(SetWalletCapArgs) = "set-wallet-cap",
+    SetWalletSpendCap(SetWalletSpendCapArgs) = "set-wallet-spend-cap",
+    SetPurchaseCooldown(SetPurchaseCooldownArgs) = "set-purchase-cooldown",
+    SetLoyaltyTiers(SetLoyaltyTiersArgs) = "set-loyalty-tiers",
+    SetStakeWeight(SetStakeWeightArgs) = "set-stake-weight",
+    SetSaleWindow(SetSaleWindowArgs) = "set-sale-window",
+    SetAllowlist(SetAllowlistArgs) = "set-allowlist",
+    AllowlistAdd(AllowlistArgs) = "allowlist-add",
+    AllowlistRemove(AllowlistArgs) = "allowlist-remove",
+    SetAllowlistRoot(SetAllowlistRootArgs) = "set-allowlist-root",
+    SetWormholeEmitter(SetWormholeEmitterArgs) = "set-wormhole-emitter",
+    RedeemWormhole(RedeemWormholeArgs) = "redeem-wormhole",
+    SetOnrampSigner(SetOnrampSignerArgs) = "set-onramp-signer",
+    BuyWithFiat(BuyWithFiatArgs) = "buy-with-fiat",
+    SetDisputeEscrow(SetDisputeEscrowArgs) = "set-dispute-escrow",
+    BuyEscrowed(BuyEscrowedArgs) = "buy-escrowed",
+    BuyMulti(BuyMultiArgs) = "buy-multi",
+    BuyExactOut(BuyExactOutArgs) = "buy-exact-out",
+    ReleaseEscrow(ReleaseEscrowArgs) = "release-escrow",
+    DisputeEscrow(DisputeEscrowArgs) = "dispute-escrow",
+    CreateCoupon(CreateCouponArgs) = "create-coupon",
+    BuyGift(BuyGiftArgs) = "buy-gift",
+    ClaimGift(ClaimGiftArgs) = "claim-gift",
+    RefundGift(RefundGiftArgs) = "refund-gift",
+    SellTokens(SellTokensArgs) = "sell-tokens",
+    SetReceiptMinting(SetReceiptMintingArgs) = "set-receipt-minting",
+    SetDelegation(SetDelegationArgs) = "set-delegation",
+    EstimateCost(EstimateCostArgs) = "estimate-cost",
+    CampaignStats(CampaignStatsArgs) = "campaign-stats",
+    TenantStats(TenantStatsArgs) = "tenant-stats",
+    PhaseStats(PhaseStatsArgs) = "phase-stats",
+    SetListing(SetListingArgs) = "set-listing",
+    ShowListing(MarketArgs) = "show-listing",
+    SetAlerts(SetAlertsArgs) = "set-alerts",
+    ShowAlerts(ShowAlertsArgs) = "show-alerts",
+    CreateHoldingsMigration(CreateHoldingsMigrationArgs) = "create-holdings-migration",
+    MigrateHoldings(MigrateHoldingsArgs) = "migrate-holdings",
+    Doctor(DoctorArgs) = "doctor",
+    Reconcile(MarketArgs) = "reconcile",
+    StateDiff(StateDiffArgs) = "state-diff",
+    History(HistoryArgs) = "history",
+    GenFixtures(GenFixturesArgs) = "gen-fixtures",
+    EncodeInstruction(EncodeInstructionArgs) = "encode-instruction",
+    DecodeInstructionData(DecodeInstructionDataArgs) = "decode-instruction-data",
+    Diff(SettingsFileArgs) = "diff",
+    Apply(SettingsFileArgs) = "apply",
+    ExportConfig(ExportConfigArgs) = "export-config",
+    Mirror(MirrorArgs) = "mirror",
+    MultiExec(MultiExecArgs) = "multi-exec",
+    VerifyBuild(VerifyBuildArgs) = "verify-build",
+    UpgradeRehearse(UpgradeRehearseArgs) = "upgrade-rehearse",
+    Probe(ProbeArgs) = "probe",
+    Plan(PlanArgs) = "plan",
+    GenProposal(GenProposalArgs) = "gen-proposal",
+}
+
+/// Parsed command line
+pub struct Cli {
+    pub options: Options,
+    pub command: Command,
+}
+
+impl Cli {
+    pub fn from_matches(matches: &ArgMatches) -> Cli {
+        Cli {
+            options: Options::from_clap(matches),
+            command: Command::from_matches(matches),
+        }
+    }
+}
+
+/// The command line parser, its help listing subcommands by `help_template`
+pub fn app<'a, 'b>(help_template: &'b str) -> App<'a, 'b> {
+    Options::clap()
+        .name(crate_name!())
+        .about(crate_description!())
+        .version(crate_version!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .template(help_template)
+        .subcommands(Command::apps())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ErrorKind;
+    use std::str::FromStr;
+
+    const MARKET: &str = "11111111111111111111111111111111";
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        let template = commands::help_template();
+        let matches = app(&template).get_matches_from_safe(
+            std::iter::once("token-market-cli").chain(args.iter().copied()),
+        )?;
+        Ok(Cli::from_matches(&matches))
+    }
+
+    fn error_kind(args: &[&str]) -> ErrorKind {
+        match parse(args) {
+            Ok(cli) => panic!("{} parsed", cli.command.name()),
+            Err(err) => err.kind,
+        }
+    }
+
+    #[test]
+    fn test_parse_buy_tokens() {
+        let cli = parse(&["buy", MARKET, MARKET, "1.5", "--campaign", "SPRING"]).unwrap();
+        match cli.command {
+            Command::BuyTokens(args) => {
+                assert_eq!(args.market, Pubkey::from_str(MARKET).unwrap());
+                assert_eq!(args.amount, UiAmount::from_str("1.5").unwrap());
+                assert_eq!(&args.campaign.unwrap(), b"SPRING\0\0");
+                assert_eq!(args.relayer_tip, None);
+                assert!(!args.allow_deprecated);
+            }
+            _ => panic!("buy is not buy-tokens"),
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_values() {
+        let invalid = ErrorKind::ValueValidation;
+        assert_eq!(error_kind(&["buy-tokens", "market", MARKET, "1"]), invalid);
+        assert_eq!(error_kind(&["buy-tokens", MARKET, MARKET, "1,5"]), invalid);
+        assert_eq!(
+            error_kind(&[
+                "buy-tokens",
+                MARKET,
+                MARKET,
+                "1",
+                "--campaign",
+                "TOO-LONG-CODE"
+            ]),
+            invalid
+        );
+        assert_eq!(
+            error_kind(&["set-receipt-minting", MARKET, "yes"]),
+            ErrorKind::InvalidValue
+        );
+    }
+
+    #[test]
+    fn test_parse_conflicting_arguments() {
+        assert_eq!(
+            error_kind(&[
+                "buy-tokens",
+                MARKET,
+                MARKET,
+                "1",
+                "--voucher",
+                "00",
+                "--relayer-tip",
+                "1"
+            ]),
+            ErrorKind::ArgumentConflict
+        );
+        assert_eq!(
+            error_kind(&[
+                "quote-server",
+                MARKET,
+                "--max-amount",
+                "1",
+                "--voucher-key",
+                "key"
+            ]),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn test_parse_durations() {
+        let window = |window: &str| match parse(&[
+            "set-dispute-escrow",
+            MARKET,
+            "--threshold",
+            "1",
+            "--window",
+            window,
+        ])
+        .unwrap()
+        .command
+        {
+            Command::SetDisputeEscrow(args) => args.window,
+            _ => unreachable!(),
+        };
+        assert_eq!(window("24"), Duration::from_secs(24 * 60 * 60));
+        assert_eq!(window("90m"), Duration::from_secs(90 * 60));
+        assert_eq!(window("2d"), Duration::from_secs(2 * 24 * 60 * 60));
+
+        match parse(&["create-vesting", MARKET, MARKET, "10", "--duration", "600"])
+            .unwrap()
+            .command
+        {
+            Command::CreateVesting(args) => {
+                assert_eq!(args.duration, Duration::from_secs(600));
+                assert_eq!(args.cliff, Duration::from_secs(0));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(
+            error_kind(&[
+                "set-dispute-escrow",
+                MARKET,
+                "--threshold",
+                "1",
+                "--window",
+                "1w"
+            ]),
+            ErrorKind::ValueValidation
+        );
+    }
+
+    #[test]
+    fn test_parse_global_options() {
+        let cli = parse(&[
+            "--owner",
+            "ASK",
+            "--progress",
+            "json",
+            "--log-format",
+            "json",
+            "--simulate-first",
+            "set-memo-policy",
+            MARKET,
+            "required",
+        ])
+        .unwrap();
+        assert_eq!(cli.options.signers.owner.as_deref(), Some("ASK"));
+        assert_eq!(cli.options.signers.fee_payer, None);
+        assert!(cli.options.progress == ProgressFormat::Json);
+        assert!(cli.options.log_format == LogFormat::Json);
+        assert!(cli.options.simulate_first);
+        match cli.command {
+            Command::SetMemoPolicy(args) => assert!(args.required),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_create_market_from_config() {
+        assert_eq!(
+            error_kind(&["create-market"]),
+            ErrorKind::MissingRequiredArgument
+        );
+        let cli = parse(&["create-market", "--from-config", "market.toml"]).unwrap();
+        match cli.command {
+            Command::CreateMarket(args) => {
+                assert_eq!(args.acceptable, None);
+                assert_eq!(args.from_config, Some(PathBuf::from("market.toml")));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_parse_sale_phases() {
+        let cli = parse(&[
+            "create-market",
+            MARKET,
+            "--phase",
+            "start=100,price=2/1,cap=1.5,allowlist",
+            "--phase",
+            "start=200,price=1,max=10",
+            "--sale-end",
+            "300",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::CreateMarket(args) => {
+                assert_eq!(args.acceptable, Some(Pubkey::from_str(MARKET).unwrap()));
+                let (presale, public) = (&args.phases[0], &args.phases[1]);
+                assert_eq!(presale.start, 100);
+                assert_eq!(
+                    presale.price,
+                    Price {
+                        numerator: 2,
+                        denominator: 1
+                    }
+                );
+                assert_eq!(presale.cap, Some(UiAmount::from_str("1.5").unwrap()));
+                assert!(presale.allowlist_only);
+                assert_eq!(public.price, Price::PAR);
+                assert_eq!(public.max_purchase, Some(UiAmount::from_str("10").unwrap()));
+                assert!(!public.allowlist_only);
+                assert_eq!(args.sale_end, Some(300));
+            }
+            _ => unreachable!(),
+        }
+
+        let invalid = ErrorKind::ValueValidation;
+        assert_eq!(
+            error_kind(&["set-phases", MARKET, "--phase", "price=2"]),
+            invalid
+        );
+        assert_eq!(
+            error_kind(&["set-phases", MARKET, "--phase", "start=1,price=2,vip"]),
+            invalid
+        );
+        assert_eq!(
+            error_kind(&["set-phases", MARKET]),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn test_parse_plan() {
+        let line = [
+            "token-market-cli",
+            "--owner",
+            "ASK",
+            "plan",
+            "--progress",
+            "json",
+            "deprecate",
+            MARKET,
+            "--successor",
+            MARKET,
+        ];
+        let cli = parse(&line[1..]).unwrap();
+        let invocation = match cli.command {
+            Command::Plan(args) => args.invocation,
+            _ => unreachable!(),
+        };
+        assert_eq!(invocation, &line[6..]);
+
+        let planned = planned_command_line(
+            line.iter().map(OsString::from).collect(),
+            "plan",
+            &[],
+            &invocation,
+        );
+        let template = commands::help_template();
+        let cli = Cli::from_matches(&app(&template).get_matches_from_safe(planned).unwrap());
+        assert_eq!(cli.command.name(), "deprecate");
+        assert_eq!(cli.options.signers.owner.as_deref(), Some("ASK"));
+        assert!(cli.options.progress == ProgressFormat::Json);
+    }
+
+    #[test]
+    fn test_parse_gen_proposal() {
+        let line = [
+            "token-market-cli",
+            "gen-proposal",
+            "--governance",
+            MARKET,
+            "--progress",
+            "json",
+            "set-protocol-fee",
+            MARKET,
+            "50",
+        ];
+        let cli = parse(&line[1..]).unwrap();
+        let (governance, invocation) = match cli.command {
+            Command::GenProposal(args) => (args.governance, args.invocation),
+            _ => unreachable!(),
+        };
+        assert_eq!(governance, Pubkey::from_str(MARKET).unwrap());
+        assert_eq!(invocation, &line[6..]);
+
+        let proposed = planned_command_line(
+            line.iter().map(OsString::from).collect(),
+            "gen-proposal",
+            &["--governance"],
+            &invocation,
+        );
+        assert_eq!(proposed.len(), line.len() - 3);
+        let template = commands::help_template();
+        let cli = Cli::from_matches(&app(&template).get_matches_from_safe(proposed).unwrap());
+        assert_eq!(cli.command.name(), "set-protocol-fee");
+        assert!(cli.options.progress == ProgressFormat::Json);
+    }
+
+    #[test]
+    fn test_every_declared_subcommand_parses() {
+        for group in commands::GROUPS {
+            for command in group.commands {
+                assert_eq!(
+                    error_kind(&[command.name, "--help"]),
+                    ErrorKind::HelpDisplayed,
+                    "{} has no arguments",
+                    command.name
+                );
+            }
+        }
+    }
+}
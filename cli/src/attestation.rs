@@ -0,0 +1,153 @@
+//! Ownership attestations of markets.
+//!
+//! Explorers and aggregators list a market as official when its issuer
+//! proves holding the owner key. `sign-ownership-proof` signs, off-chain, a
+//! message binding the owner, the market and the domain of the listing
+//! site, so an attestation made for one site can't be replayed on another:
+//!
+//! ```text
+//! token-market ownership attestation
+//! market: 4uQeVj5tqViQh7yWWGStvkEG1Zmhx6uasJtWCJziofM
+//! owner: 9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin
+//! domain: explorer.example.com
+//! ```
+//!
+//! The site checks a submitted attestation against the market it reads:
+//!
+//! ```no_run
+//! use token_market_cli::{attestation::OwnershipAttestation, reader::MarketReader};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! # let submitted = "";
+//! let attestation: OwnershipAttestation = serde_json::from_str(submitted)?;
+//! let reader = MarketReader::new("https://api.mainnet-beta.solana.com");
+//! let market = reader.market(&attestation.market)?;
+//! attestation.verify(&market.owner, "explorer.example.com")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+};
+
+/// First line of every attested message
+const HEADER: &str = "token-market ownership attestation";
+
+/// Signed claim of the owner of `market` that it lists the market on `domain`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OwnershipAttestation {
+    #[serde(with = "base58")]
+    pub market: Pubkey,
+    #[serde(with = "base58")]
+    pub owner: Pubkey,
+    pub domain: String,
+    #[serde(with = "base58")]
+    pub signature: Signature,
+}
+
+/// Message the owner signs
+pub fn message(market: &Pubkey, owner: &Pubkey, domain: &str) -> Vec<u8> {
+    format!(
+        "{}\nmarket: {}\nowner: {}\ndomain: {}",
+        HEADER, market, owner, domain
+    )
+    .into_bytes()
+}
+
+fn check_domain(domain: &str) -> Result<()> {
+    if domain.is_empty() || domain.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        bail!("Invalid domain {:?}", domain);
+    }
+    Ok(())
+}
+
+impl OwnershipAttestation {
+    /// Attest with `owner` that `market` is listed on `domain`
+    pub fn sign<S: Signer + ?Sized>(owner: &S, market: &Pubkey, domain: &str) -> Result<Self> {
+        check_domain(domain)?;
+        let owner_key = owner.try_pubkey()?;
+        let signature = owner.try_sign_message(&message(market, &owner_key, domain))?;
+        Ok(Self {
+            market: *market,
+            owner: owner_key,
+            domain: domain.to_string(),
+            signature,
+        })
+    }
+
+    /// Fail unless the attestation is made for `domain`, signed by its owner
+    /// and `market_owner`, the owner of the market as read on chain, is it
+    pub fn verify(&self, market_owner: &Pubkey, domain: &str) -> Result<()> {
+        if self.domain != domain {
+            bail!("Attestation is made for {}, not {}", self.domain, domain);
+        }
+        check_domain(&self.domain)?;
+        let message = message(&self.market, &self.owner, &self.domain);
+        if !self.signature.verify(self.owner.as_ref(), &message) {
+            bail!("Attestation isn't signed by {}", self.owner);
+        }
+        if *market_owner != self.owner {
+            bail!(
+                "Market {} is owned by {}, not {}",
+                self.market,
+                market_owner,
+                self.owner
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Keys and signatures as base58 strings
+mod base58 {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::{fmt::Display, str::FromStr};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T: FromStr, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        T::from_str(&text).map_err(|_| de::Error::custom(format!("Invalid base58 value {}", text)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::Keypair;
+
+    #[test]
+    fn test_verify() {
+        let owner = Keypair::new();
+        let market = Pubkey::new_unique();
+        let attestation = OwnershipAttestation::sign(&owner, &market, "example.com").unwrap();
+        let text = serde_json::to_string(&attestation).unwrap();
+        let parsed: OwnershipAttestation = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed, attestation);
+        parsed.verify(&owner.pubkey(), "example.com").unwrap();
+
+        // replayed on another site
+        assert!(parsed.verify(&owner.pubkey(), "other.com").is_err());
+        // claimed for another market
+        let forged = OwnershipAttestation {
+            market: Pubkey::new_unique(),
+            ..parsed.clone()
+        };
+        assert!(forged.verify(&owner.pubkey(), "example.com").is_err());
+        // the market changed hands
+        assert!(parsed.verify(&Pubkey::new_unique(), "example.com").is_err());
+
+        assert!(OwnershipAttestation::sign(&owner, &market, "example.com\nowner: x").is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! Campaign codes attributing purchases to marketing campaigns
+
+use anyhow::{anyhow, bail, Result};
+use solana_sdk::pubkey::Pubkey;
+use token_market_cli::reader::MarketReader;
+
+/// Parse a campaign code of 1 to 8 ASCII characters, padded with zeros
+pub fn parse_campaign(campaign: &str) -> Result<[u8; 8]> {
+    if !campaign.is_ascii() || campaign.is_empty() || campaign.len() > 8 {
+        bail!("Campaign {} must be 1 to 8 ASCII characters", campaign);
+    }
+    let mut bytes = [0; 8];
+    bytes[..campaign.len()].copy_from_slice(campaign.as_bytes());
+    Ok(bytes)
+}
+
+/// Process `campaign-stats` command
+pub fn show_campaign_stats(reader: &MarketReader, market: &Pubkey, campaign: &str) -> Result<()> {
+    let stats = reader
+        .campaign_stats(market, &parse_campaign(campaign)?)?
+        .ok_or_else(|| anyhow!("Campaign {} has no sales on market {}", campaign, market))?;
+
+    println!("Campaign:  {}", campaign);
+    println!("Purchases: {}", stats.purchases);
+    println!("Bought:    {}", stats.amount);
+    Ok(())
+}
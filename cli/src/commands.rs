@@ -0,0 +1,534 @@
+//! Subcommand names, aliases and the groups of the top level help.
+//!
+//! Every subcommand is declared here once, with the summary shown in help.
+//! The help lists them by group instead of one alphabetical list, and an
+//! unknown subcommand is answered with the closest name or alias.
+
+use clap::App;
+use std::process;
+
+pub struct Command {
+    pub name: &'static str,
+    /// Shorter names accepted in place of `name`
+    pub aliases: &'static [&'static str],
+    pub about: &'static str,
+}
+
+pub struct Group {
+    pub title: &'static str,
+    pub commands: &'static [Command],
+}
+
+const fn command(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    about: &'static str,
+) -> Command {
+    Command {
+        name,
+        aliases,
+        about,
+    }
+}
+
+pub const GROUPS: &[Group] = &[
+    Group {
+        title: "LIFECYCLE",
+        commands: &[
+            command("create-market", &["init"], "Create a new token market"),
+            command(
+                "deprecate",
+                &[],
+                "Deprecate a market and point buyers at its successor",
+            ),
+            command(
+                "migrate-acceptable",
+                &[],
+                "Switch the market to a successor of the accepted mint",
+            ),
+            command(
+                "create-holdings-migration",
+                &[],
+                "Let holders swap emitter tokens of a deprecated market for a new one",
+            ),
+            command(
+                "migrate-holdings",
+                &[],
+                "Burn deprecated emitter tokens of the owner keypair and receive the new ones",
+            ),
+            command(
+                "recreate-bank",
+                &[],
+                "Replace the closed bank of a market with a new one",
+            ),
+            command(
+                "withdraw-bank",
+                &["withdraw"],
+                "Withdraw collected payments from the bank of a market",
+            ),
+            command(
+                "set-withdraw-rate",
+                &[],
+                "Stream withdrawals from the bank at a daily rate that can only go down",
+            ),
+            command(
+                "withdraw-fees",
+                &[],
+                "Withdraw protocol fees collected in the fee vault of a market",
+            ),
+            command("pause", &[], "Halt purchases and sales on a market"),
+            command("resume", &[], "Let a paused market trade again"),
+            command(
+                "set-phases",
+                &[],
+                "Sell in phases with their own price, caps and allowlist",
+            ),
+            command(
+                "advance-phase",
+                &[],
+                "Open the next sale phase ahead of its start, or close the sale after the last",
+            ),
+        ],
+    },
+    Group {
+        title: "SETTINGS",
+        commands: &[
+            command(
+                "set-treasury",
+                &[],
+                "Set the account that receives withdrawn payments",
+            ),
+            command(
+                "set-overpayment-policy",
+                &[],
+                "Choose what purchases do with a delegation larger than their cost",
+            ),
+            command(
+                "set-receipt-minting",
+                &[],
+                "Mint a receipt NFT to the buyer on every purchase, paid by the fee payer",
+            ),
+            command(
+                "set-memo-policy",
+                &[],
+                "Require every purchase to carry a memo stating its purpose",
+            ),
+            command(
+                "set-sandwich-guard",
+                &[],
+                "Refuse quoted purchases sharing a transaction with other market or exchange instructions",
+            ),
+            command(
+                "set-protocol-fee",
+                &[],
+                "Keep a share of every payment in a fee vault, in basis points",
+            ),
+            command(
+                "set-purchase-limits",
+                &[],
+                "Set the fewest and most accepted tokens a single purchase may pay",
+            ),
+            command(
+                "set-wallet-cap",
+                &[],
+                "Cap the emitted tokens a single wallet may buy in total",
+            ),
+            command(
+                "set-wallet-spend-cap",
+                &[],
+                "Cap the accepted tokens a single wallet may spend in total",
+            ),
+            command(
+                "set-purchase-cooldown",
+                &[],
+                "Make a wallet wait between its purchases",
+            ),
+            command(
+                "set-loyalty-tiers",
+                &[],
+                "Discount wallets by the emitted tokens they purchased before",
+            ),
+            command(
+                "set-stake-weight",
+                &[],
+                "Raise wallet caps by what buyers stake in a stake pool or governance realm",
+            ),
+            command(
+                "set-sale-window",
+                &[],
+                "Schedule when a market sells, changeable until the sale starts",
+            ),
+            command(
+                "set-allowlist",
+                &[],
+                "Restrict purchases to allowlisted buyers, or lift the restriction",
+            ),
+            command(
+                "allowlist-add",
+                &[],
+                "Let a buyer purchase on a market with the allowlist on",
+            ),
+            command(
+                "allowlist-remove",
+                &[],
+                "Take a buyer off the allowlist, returning the rent of its entry",
+            ),
+            command(
+                "set-allowlist-root",
+                &[],
+                "Admit the buyers of an address file by the root of their Merkle tree",
+            ),
+            command(
+                "set-wormhole-emitter",
+                &[],
+                "Accept purchases paid on another chain through Wormhole messages",
+            ),
+            command(
+                "set-onramp-signer",
+                &[],
+                "Set the on-ramp provider key that attests card purchases",
+            ),
+            command(
+                "set-dispute-escrow",
+                &[],
+                "Hold large purchases in escrow while the owner or a guardian can dispute them",
+            ),
+            command(
+                "create-coupon",
+                &[],
+                "Issue a discount code redeemable a limited number of times",
+            ),
+            command(
+                "set-voucher-signer",
+                &[],
+                "Set the key that signs purchase vouchers of a market",
+            ),
+            command(
+                "set-delegation",
+                &[],
+                "Let a hot key sign some owner commands within daily limits, \
+                 all limits omitted revoke it",
+            ),
+            command(
+                "set-listing",
+                &[],
+                "Publish listing metadata of a market for explorers",
+            ),
+            command(
+                "set-alerts",
+                &[],
+                "Publish the alert thresholds and webhook monitoring of a market reads",
+            ),
+            command(
+                "diff",
+                &[],
+                "Show how a market's settings differ from a settings file",
+            ),
+            command(
+                "apply",
+                &[],
+                "Update a market's settings to match a settings file",
+            ),
+            command(
+                "export-config",
+                &[],
+                "Write a market's settings to a file create-market --from-config recreates it from",
+            ),
+            command(
+                "mirror",
+                &[],
+                "Copy a market's configuration to another cluster and record the copy",
+            ),
+            command(
+                "multi-exec",
+                &[],
+                "Execute a declarative plan of market operations in dependency order",
+            ),
+        ],
+    },
+    Group {
+        title: "TRADING",
+        commands: &[
+            command("buy-tokens", &["buy"], "Buy emitted tokens of a market"),
+            command(
+                "buy-multi",
+                &[],
+                "Buy emitted tokens for every wallet of a file, many per transaction",
+            ),
+            command(
+                "buy-exact-out",
+                &[],
+                "Buy an exact amount of emitted tokens, paying at most a given amount",
+            ),
+            command(
+                "sell-tokens",
+                &["sell"],
+                "Sell emitted tokens back to the market bank",
+            ),
+            command(
+                "estimate-cost",
+                &[],
+                "Quote everything a purchase costs, in tokens and in SOL",
+            ),
+            command(
+                "redeem-wormhole",
+                &[],
+                "Mint the tokens of a purchase paid on another chain",
+            ),
+            command(
+                "buy-with-fiat",
+                &[],
+                "Mint the tokens of a card purchase attested by the on-ramp provider",
+            ),
+            command(
+                "buy-escrowed",
+                &[],
+                "Buy tokens held in escrow until the dispute window passes",
+            ),
+            command(
+                "release-escrow",
+                &[],
+                "Move the tokens of an escrowed purchase to its recipient",
+            ),
+            command(
+                "dispute-escrow",
+                &[],
+                "Burn the tokens of an escrowed purchase and refund its payment",
+            ),
+            command(
+                "buy-gift",
+                &[],
+                "Buy tokens for someone else and print the claim to share with them",
+            ),
+            command(
+                "claim-gift",
+                &[],
+                "Claim the tokens of a gift to a wallet",
+            ),
+            command(
+                "refund-gift",
+                &[],
+                "Burn the tokens of an expired gift and refund its payment",
+            ),
+            command("sign-voucher", &[], "Print a purchase voucher for a buyer"),
+            command(
+                "sign-ownership-proof",
+                &[],
+                "Print an attestation of owning a market for a listing site",
+            ),
+            command("quote-server", &[], "Serve signed price quotes over HTTP"),
+            command(
+                "create-vesting",
+                &[],
+                "Lock emitted tokens for a beneficiary",
+            ),
+            command(
+                "claim-vesting",
+                &[],
+                "Claim vested tokens, the owner keypair must be the beneficiary",
+            ),
+            command(
+                "revoke-vesting",
+                &[],
+                "Revoke a vesting and return unvested tokens to the owner",
+            ),
+            command(
+                "crank-vesting",
+                &[],
+                "Push vested tokens of every vesting of a market to the beneficiaries",
+            ),
+            command(
+                "garbage-collect",
+                &["gc"],
+                "Close the buyer records and receipts of an ended sale, returning their rent",
+            ),
+        ],
+    },
+    Group {
+        title: "ANALYTICS",
+        commands: &[
+            command("find-market", &["find"], "Find the market issuing a token"),
+            command(
+                "list-markets",
+                &["list"],
+                "List markets, optionally only those matching every given filter",
+            ),
+            command("show-listing", &[], "Display listing metadata of a market"),
+            command(
+                "show-alerts",
+                &[],
+                "Display the alert thresholds published for a market",
+            ),
+            command(
+                "show-phases",
+                &[],
+                "Display the sale phases of a market and what each sold",
+            ),
+            command(
+                "phase-stats",
+                &[],
+                "Compare purchases, volume and average size of the sale phases of markets",
+            ),
+            command(
+                "campaign-stats",
+                &["stats"],
+                "Show the sales attributed to a campaign code",
+            ),
+            command(
+                "tenant-stats",
+                &[],
+                "Show the markets and protocol fees of owners of a shared deployment",
+            ),
+            command(
+                "history",
+                &[],
+                "Show the latest transactions of a market and the events they logged",
+            ),
+            command("doctor", &[], "Check a market for misconfigured accounts"),
+            command(
+                "reconcile",
+                &[],
+                "Check the emitter supply against the recorded purchases, sales, grants and burns",
+            ),
+            command(
+                "state-diff",
+                &[],
+                "Show what changed on a market and its child accounts between two slots",
+            ),
+        ],
+    },
+    Group {
+        title: "TOOLING",
+        commands: &[
+            command(
+                "verify-build",
+                &[],
+                "Verify that the deployed program matches a source commit",
+            ),
+            command(
+                "upgrade-rehearse",
+                &[],
+                "Replay a scenario on a market cloned into a local validator, \
+                 with the deployed program and with a new build",
+            ),
+            command(
+                "probe",
+                &[],
+                "Show the version and the features of the deployed program",
+            ),
+            command(
+                "gen-fixtures",
+                &[],
+                "Write JSON fixtures of markets, quotes, events and errors for frontend tests",
+            ),
+            command(
+                "encode-instruction",
+                &[],
+                "Print the hex instruction data of an instruction given as JSON",
+            ),
+            command(
+                "decode-instruction-data",
+                &[],
+                "Print hex instruction data as the JSON encode-instruction takes",
+            ),
+            command(
+                "plan",
+                &[],
+                "Print the instructions a command would send, without signing or sending them",
+            ),
+            command(
+                "gen-proposal",
+                &[],
+                "Print the instructions of a command as a governance proposal payload",
+            ),
+        ],
+    },
+];
+
+fn commands() -> impl Iterator<Item = &'static Command> {
+    GROUPS.iter().flat_map(|group| group.commands)
+}
+
+/// Whether `name` is a subcommand or an alias of one
+pub fn is_declared(name: &str) -> bool {
+    commands().any(|command| command.name == name || command.aliases.contains(&name))
+}
+
+/// The subcommand `name` taking the arguments of `args`, with its summary and aliases.
+/// Panics if `name` is not declared in [GROUPS](constant.GROUPS.html).
+pub fn subcommand<'a, 'b>(name: &str, args: App<'a, 'b>) -> App<'a, 'b> {
+    let command = commands()
+        .find(|command| command.name == name)
+        .unwrap_or_else(|| panic!("Subcommand {} is not declared", name));
+    args.name(command.name)
+        .about(command.about)
+        .aliases(command.aliases)
+}
+
+/// Top level help template listing the subcommands by group
+pub fn help_template() -> String {
+    let label = |command: &Command| match command.aliases {
+        [] => command.name.to_string(),
+        aliases => format!("{} ({})", command.name, aliases.join(", ")),
+    };
+    let width = commands()
+        .map(|command| label(command).len())
+        .max()
+        .unwrap_or(0);
+
+    let mut template = "{bin} {version}\n{about}\n\nUSAGE:\n    {usage}\n\n\
+                        FLAGS:\n{flags}\n\nOPTIONS:\n{options}\n"
+        .to_string();
+    for group in GROUPS {
+        template.push_str(&format!("\n{}:\n", group.title));
+        for command in group.commands {
+            template.push_str(&format!(
+                "    {:width$}    {}\n",
+                label(command),
+                command.about,
+                width = width
+            ));
+        }
+    }
+    template.push_str("\nRun `{bin} help <SUBCOMMAND>` for the arguments of a subcommand\n");
+    template
+}
+
+/// Number of single character edits turning `a` into `b`
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + (a != *b) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The subcommand whose name or alias is closest to `typed`, if any is close enough
+pub fn suggest(typed: &str) -> Option<&'static str> {
+    let max_distance = (typed.chars().count() / 3).max(2);
+    commands()
+        .flat_map(|command| {
+            std::iter::once(command.name)
+                .chain(command.aliases.iter().copied())
+                .map(move |name| (levenshtein(typed, name), command.name))
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// Report an unknown subcommand with a suggestion and exit like clap does
+pub fn unknown_subcommand(typed: &str) -> ! {
+    eprintln!("error: The subcommand '{}' wasn't recognized", typed);
+    if let Some(name) = suggest(typed) {
+        eprintln!("\tDid you mean '{}'?", name);
+    }
+    eprintln!("\nFor more information try --help");
+    process::exit(1)
+}
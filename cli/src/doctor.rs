@@ -0,0 +1,275 @@
+//! Market health checks
+
+use anyhow::{bail, Result};
+use borsh::de::BorshDeserialize;
+use solana_sdk::{
+    account::Account,
+    program_option::COption,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+use token_market::state::{find_authority_address, TokenMarket};
+use token_market_cli::throttle::ThrottledClient;
+
+/// Outcome of a single check
+pub struct Check {
+    pub name: String,
+    pub passed: bool,
+    /// What is wrong and how to fix it, empty for passed checks
+    pub hint: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, failure: Option<String>) -> Self {
+        Self {
+            name: name.into(),
+            passed: failure.is_none(),
+            hint: failure.unwrap_or_default(),
+        }
+    }
+}
+
+/// Run every check against `market`. Only a missing or unreadable market
+/// account is an error, everything else is reported as a failed check.
+pub fn run_checks(rpc_client: &ThrottledClient, market: &Pubkey) -> Result<Vec<Check>> {
+    let market_account = rpc_client.get_account(market)?;
+    if market_account.owner != token_market::id() {
+        bail!(
+            "{} is owned by {}, not by the token market program",
+            market,
+            market_account.owner
+        );
+    }
+    let token_market = TokenMarket::try_from_slice(&market_account.data)?;
+
+    let mut checks = vec![Check::new(
+        "market is initialized",
+        (!token_market.is_initialized)
+            .then(|| "Market account was created but Initialize never succeeded".to_string()),
+    )];
+
+    checks.push(Check::new(
+        "market is not deprecated",
+        token_market
+            .deprecated
+            .then(|| match token_market.successor() {
+                Some(successor) => format!("Market is deprecated, buyers should use {}", successor),
+                None => "Market is deprecated without a successor".to_string(),
+            }),
+    ));
+
+    checks.push(Check::new(
+        "market is not paused",
+        token_market
+            .paused
+            .then(|| "Market is paused, nothing can be bought until resume".to_string()),
+    ));
+
+    let (authority, _) = find_authority_address(&token_market::id());
+    checks.push(Check::new(
+        "authority matches the program PDA",
+        (token_market.authority != authority).then(|| {
+            format!(
+                "Market records authority {}, expected {}. Recreate the market",
+                token_market.authority, authority
+            )
+        }),
+    ));
+    checks.push(rent_check(rpc_client, "market", &market_account));
+
+    checks.extend(bank_checks(
+        rpc_client,
+        "bank",
+        &token_market.bank,
+        &token_market.mint_of_acceptable,
+        &authority,
+        ". If it was closed, replace it with recreate-bank",
+    ));
+    if token_market.has_legacy_bank() {
+        checks.extend(bank_checks(
+            rpc_client,
+            "legacy bank",
+            &token_market.legacy_bank,
+            &token_market.legacy_mint_of_acceptable,
+            &authority,
+            "",
+        ));
+    }
+
+    if token_market.has_treasury() {
+        checks.push(
+            match fetch_packed::<TokenAccount>(rpc_client, &token_market.treasury) {
+                Ok((_, treasury)) => Check::new(
+                    "treasury holds the acceptable mint",
+                    (treasury.mint != token_market.mint_of_acceptable).then(|| {
+                        format!(
+                            "Treasury {} holds {}, expected {}. Set a new one with set-treasury",
+                            token_market.treasury, treasury.mint, token_market.mint_of_acceptable
+                        )
+                    }),
+                ),
+                Err(err) => Check::new(
+                    "treasury is readable",
+                    Some(format!(
+                        "Can't load treasury {}: {}",
+                        token_market.treasury, err
+                    )),
+                ),
+            },
+        );
+    }
+
+    match fetch_packed::<Mint>(rpc_client, &token_market.emitter_mint) {
+        Ok((account, emitter)) => {
+            checks.push(Check::new(
+                "emitter mint authority is held by the PDA",
+                (emitter.mint_authority != COption::Some(authority)).then(|| {
+                    format!(
+                        "Emitter mint {} authority is {:?}, expected {}. Tokens can't be issued",
+                        token_market.emitter_mint, emitter.mint_authority, authority
+                    )
+                }),
+            ));
+            checks.push(rent_check(rpc_client, "emitter mint", &account));
+            if let Ok((_, accepted)) =
+                fetch_packed::<Mint>(rpc_client, &token_market.mint_of_acceptable)
+            {
+                checks.push(Check::new(
+                    "emitter decimals match the acceptable mint",
+                    (accepted.decimals != emitter.decimals).then(|| {
+                        format!(
+                            "Emitter has {} decimals, acceptable mint has {}. Prices are off by a power of ten",
+                            emitter.decimals, accepted.decimals
+                        )
+                    }),
+                ));
+            }
+        }
+        Err(err) => checks.push(Check::new(
+            "emitter mint is readable",
+            Some(format!(
+                "Can't load emitter mint {}: {}",
+                token_market.emitter_mint, err
+            )),
+        )),
+    }
+
+    Ok(checks)
+}
+
+fn bank_checks(
+    rpc_client: &ThrottledClient,
+    label: &str,
+    bank: &Pubkey,
+    mint: &Pubkey,
+    authority: &Pubkey,
+    unreadable_hint: &str,
+) -> Vec<Check> {
+    let (account, token_account) = match fetch_packed::<TokenAccount>(rpc_client, bank) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            return vec![Check::new(
+                format!("{} is readable", label),
+                Some(format!(
+                    "Can't load {} {}: {}{}",
+                    label, bank, err, unreadable_hint
+                )),
+            )]
+        }
+    };
+    vec![
+        Check::new(
+            format!("{} holds the acceptable mint", label),
+            (token_account.mint != *mint).then(|| {
+                format!(
+                    "{} {} holds {}, expected {}. Payments can't be received",
+                    label, bank, token_account.mint, mint
+                )
+            }),
+        ),
+        Check::new(
+            format!("{} is owned by the PDA", label),
+            (token_account.owner != *authority).then(|| {
+                format!(
+                    "{} {} is owned by {}, expected {}. Funds can't be withdrawn by the program",
+                    label, bank, token_account.owner, authority
+                )
+            }),
+        ),
+        Check::new(
+            format!("{} is not frozen", label),
+            token_account.is_frozen().then(|| {
+                format!(
+                    "{} {} is frozen. Purchases fail until the freeze authority of {} thaws it",
+                    label, bank, mint
+                )
+            }),
+        ),
+        rent_check(rpc_client, label, &account),
+    ]
+}
+
+fn rent_check(rpc_client: &ThrottledClient, label: &str, account: &Account) -> Check {
+    let name = format!("{} is rent exempt", label);
+    match rpc_client.get_minimum_balance_for_rent_exemption(account.data.len()) {
+        Ok(minimum) => Check::new(
+            name,
+            (account.lamports < minimum).then(|| {
+                format!(
+                    "{} holds {} lamports, {} required. Top it up before it is collected",
+                    label, account.lamports, minimum
+                )
+            }),
+        ),
+        Err(err) => Check::new(name, Some(format!("Can't query rent: {}", err))),
+    }
+}
+
+fn fetch_packed<T: Pack + IsInitialized>(
+    rpc_client: &ThrottledClient,
+    address: &Pubkey,
+) -> Result<(Account, T)> {
+    let account = rpc_client.get_account(address)?;
+    if account.owner != spl_token::id() {
+        bail!("{} is not owned by the token program", address);
+    }
+    let state = T::unpack(&account.data)?;
+    Ok((account, state))
+}
+
+/// Process `doctor` command
+pub fn doctor(rpc_client: &ThrottledClient, market: &Pubkey, json: bool) -> Result<()> {
+    let checks = run_checks(rpc_client, market)?;
+    let failed = checks.iter().filter(|check| !check.passed).count();
+
+    if json {
+        let report = serde_json::json!({
+            "market": market.to_string(),
+            "healthy": failed == 0,
+            "checks": checks
+                .iter()
+                .map(|check| serde_json::json!({
+                    "name": check.name,
+                    "passed": check.passed,
+                    "hint": check.hint,
+                }))
+                .collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for check in &checks {
+            if check.passed {
+                println!("[pass] {}", check.name);
+            } else {
+                println!("[FAIL] {}\n       {}", check.name, check.hint);
+            }
+        }
+        println!("{} of {} checks failed", failed, checks.len());
+    }
+
+    if failed > 0 {
+        bail!("Market {} is unhealthy", market);
+    }
+    Ok(())
+}
@@ -0,0 +1,307 @@
+//! Several RPC endpoints of one cluster behind one client.
+//!
+//! A profile of the CLI config can list read replicas of its `json_rpc_url`:
+//!
+//! ```yaml
+//! json_rpc_url: https://rpc.example.com
+//! rpc_replicas:
+//!   - https://replica-1.example.com
+//!   - https://replica-2.example.com
+//! ```
+//!
+//! An [EndpointPool](struct.EndpointPool.html) tracks the latency and the
+//! failures of every endpoint. Calls are spread over the endpoints answering
+//! about as fast as the fastest one, and a call the endpoint couldn't be
+//! reached for is made again on the next one, sends included: a signed
+//! transaction is the same on every endpoint. An endpoint that failed is left
+//! out for a while, longer after each further failure, and taken back once
+//! it answers again.
+
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_client::RpcClient,
+};
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Key of the read replicas in a CLI config profile
+pub const PROFILE_KEY: &str = "rpc_replicas";
+
+/// Time an endpoint is left out after its first failure, doubled on every further one
+const FIRST_COOL_DOWN: Duration = Duration::from_secs(5);
+/// Longest time an endpoint is left out
+const MAX_COOL_DOWN: Duration = Duration::from_secs(60);
+/// Endpoints slower than this many times the fastest one only get calls
+/// when the faster ones are down
+const SLOWDOWN: f64 = 2.0;
+/// Weight of the latest call in the latency average
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Read replicas listed by a CLI config profile, none if it lists none
+pub fn replicas_from_profile(profile: &serde_json::Value) -> serde_json::Result<Vec<String>> {
+    match profile.get(PROFILE_KEY) {
+        Some(replicas) => serde_json::from_value(replicas.clone()),
+        None => Ok(vec![]),
+    }
+}
+
+/// Whether `error` means the endpoint couldn't answer, rather than that it
+/// answered with an error every endpoint would give
+pub fn is_unreachable(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Io(_) => true,
+        ClientErrorKind::Reqwest(error) => {
+            error.is_connect()
+                || error.is_timeout()
+                || error
+                    .status()
+                    .map_or(false, |status| status.is_server_error())
+        }
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct Health {
+    /// Moving average of the latency, `None` before the first answer
+    latency: Option<f64>,
+    /// Failures since the last answer
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+pub struct Endpoint {
+    url: String,
+    client: RpcClient,
+    health: Mutex<Health>,
+}
+
+impl Endpoint {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn client(&self) -> &RpcClient {
+        &self.client
+    }
+
+    fn answered(&self, latency: Duration) {
+        let mut health = self.health.lock().unwrap();
+        let latency = latency.as_secs_f64();
+        health.latency = Some(match health.latency {
+            Some(average) => average + (latency - average) * LATENCY_WEIGHT,
+            None => latency,
+        });
+        health.failures = 0;
+        health.down_until = None;
+    }
+
+    fn failed(&self, now: Instant) {
+        let mut health = self.health.lock().unwrap();
+        let cool_down = FIRST_COOL_DOWN
+            .checked_mul(1u32 << health.failures.min(16))
+            .map_or(MAX_COOL_DOWN, |cool_down| cool_down.min(MAX_COOL_DOWN));
+        health.failures += 1;
+        health.down_until = Some(now + cool_down);
+    }
+
+    /// Failures since the last answer, whether the endpoint is left out at
+    /// `now` and its average latency
+    fn state(&self, now: Instant) -> (u32, bool, Option<f64>) {
+        let health = self.health.lock().unwrap();
+        let down = health.down_until.map_or(false, |until| until > now);
+        (health.failures, down, health.latency)
+    }
+}
+
+/// RPC endpoints of one cluster, the first being the primary one
+pub struct EndpointPool {
+    endpoints: Vec<Endpoint>,
+    /// Turn of the next call among the endpoints it can go to
+    turn: AtomicUsize,
+}
+
+impl EndpointPool {
+    /// Pool of the endpoints at `urls`, at `commitment`
+    pub fn new(urls: &[String], commitment: CommitmentConfig) -> Self {
+        assert!(!urls.is_empty(), "an endpoint pool needs an endpoint");
+        Self {
+            endpoints: urls
+                .iter()
+                .map(|url| Endpoint {
+                    url: url.clone(),
+                    client: RpcClient::new_with_commitment(url.clone(), commitment),
+                    health: Mutex::default(),
+                })
+                .collect(),
+            turn: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pool of the single endpoint `client` talks to
+    pub fn single(client: RpcClient) -> Self {
+        Self {
+            endpoints: vec![Endpoint {
+                url: String::new(),
+                client,
+                health: Mutex::default(),
+            }],
+            turn: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Endpoints to try a call on at `now`, in order: the one whose turn it
+    /// is among those up and about as fast as the fastest, then the other
+    /// ones up from the fastest, then the ones down from the least failing
+    fn ranked(&self, now: Instant) -> Vec<&Endpoint> {
+        let states: Vec<_> = self
+            .endpoints
+            .iter()
+            .map(|endpoint| (endpoint, endpoint.state(now)))
+            .collect();
+        // endpoints not measured yet count as fast, so every one gets measured
+        let fastest = states
+            .iter()
+            .filter(|(_, (_, down, _))| !down)
+            .map(|(_, (_, _, latency))| latency.unwrap_or(0.0))
+            .fold(None, |fastest: Option<f64>, latency| {
+                Some(fastest.map_or(latency, |fastest| fastest.min(latency)))
+            });
+        let (up, mut down): (Vec<_>, Vec<_>) =
+            states.into_iter().partition(|(_, (_, down, _))| !down);
+
+        let mut ranked = vec![];
+        if let Some(fastest) = fastest {
+            let (fast, mut slow): (Vec<_>, Vec<_>) = up
+                .into_iter()
+                .partition(|(_, (_, _, latency))| latency.unwrap_or(0.0) <= fastest * SLOWDOWN);
+            let turn = self.turn.fetch_add(1, Ordering::Relaxed) % fast.len();
+            ranked.extend(
+                fast[turn..]
+                    .iter()
+                    .chain(&fast[..turn])
+                    .map(|(endpoint, _)| *endpoint),
+            );
+            slow.sort_by(|(_, (_, _, a)), (_, (_, _, b))| {
+                a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
+            });
+            ranked.extend(slow.into_iter().map(|(endpoint, _)| endpoint));
+        }
+        down.sort_by_key(|(_, (failures, _, _))| *failures);
+        ranked.extend(down.into_iter().map(|(endpoint, _)| endpoint));
+        ranked
+    }
+
+    /// Endpoint the next call goes to
+    pub fn preferred(&self) -> &Endpoint {
+        self.ranked(Instant::now())[0]
+    }
+
+    /// Make `call` on the endpoints in turn until one can answer it
+    pub fn call<T>(
+        &self,
+        mut call: impl FnMut(&RpcClient) -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        let ranked = self.ranked(Instant::now());
+        let last = ranked.len() - 1;
+        for (index, endpoint) in ranked.into_iter().enumerate() {
+            let started = Instant::now();
+            match call(&endpoint.client) {
+                Err(error) if is_unreachable(&error) => {
+                    endpoint.failed(Instant::now());
+                    if index == last {
+                        return Err(error);
+                    }
+                    warn!(url = endpoint.url.as_str(), %error, "endpoint unreachable, failing over");
+                }
+                result => {
+                    endpoint.answered(started.elapsed());
+                    return result;
+                }
+            }
+        }
+        unreachable!("an endpoint pool has an endpoint")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(count: usize) -> EndpointPool {
+        let urls: Vec<_> = (0..count)
+            .map(|index| format!("http://127.0.0.1:{}", 1000 + index))
+            .collect();
+        EndpointPool::new(&urls, CommitmentConfig::confirmed())
+    }
+
+    fn urls(ranked: &[&Endpoint]) -> Vec<String> {
+        ranked
+            .iter()
+            .map(|endpoint| endpoint.url().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_spreads_over_fast_endpoints() {
+        let pool = pool(3);
+        let endpoints = pool.endpoints();
+        endpoints[0].answered(Duration::from_millis(100));
+        endpoints[1].answered(Duration::from_millis(150));
+        endpoints[2].answered(Duration::from_millis(500));
+        let now = Instant::now();
+        let first = urls(&pool.ranked(now));
+        let second = urls(&pool.ranked(now));
+        assert_ne!(first[0], second[0]);
+        // the slow one is only tried last
+        assert_eq!(first[2], endpoints[2].url());
+        assert_eq!(second[2], endpoints[2].url());
+    }
+
+    #[test]
+    fn test_leaves_failed_endpoints_out() {
+        let pool = pool(2);
+        let endpoints = pool.endpoints();
+        let now = Instant::now();
+        endpoints[0].failed(now);
+        for _ in 0..3 {
+            assert_eq!(pool.ranked(now)[0].url(), endpoints[1].url());
+        }
+        // tried again once the cool down is over, taken back when it answers
+        assert!(!endpoints[0].state(now + FIRST_COOL_DOWN * 2).1);
+        endpoints[0].answered(Duration::from_millis(10));
+        assert_eq!(endpoints[0].state(now), (0, false, Some(0.01)));
+
+        // with every endpoint down the least failing one is tried first
+        endpoints[0].failed(now);
+        endpoints[1].failed(now);
+        endpoints[1].failed(now);
+        assert_eq!(pool.ranked(now)[0].url(), endpoints[0].url());
+    }
+
+    #[test]
+    fn test_replicas_from_profile() {
+        let profile = serde_json::json!({
+            "json_rpc_url": "https://rpc.example.com",
+            "rpc_replicas": ["https://replica.example.com"],
+        });
+        assert_eq!(
+            replicas_from_profile(&profile).unwrap(),
+            vec!["https://replica.example.com".to_string()]
+        );
+        assert!(replicas_from_profile(&serde_json::json!({}))
+            .unwrap()
+            .is_empty());
+    }
+}
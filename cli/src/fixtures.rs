@@ -0,0 +1,327 @@
+//! Deterministic JSON fixtures for frontend test suites
+//!
+//! Every fixture is produced by the program crate itself: markets are borsh
+//! encoded and decoded through `token_market::state`, quotes come from
+//! `token_market::quote`, log lines from `token_market::events` and errors
+//! from `token_market::error` and `token_market::pricing`.
+
+use anyhow::Result;
+use borsh::BorshSerialize;
+use serde_json::{json, Value};
+use solana_sdk::{decode_error::DecodeError, hash::hash, pubkey::Pubkey, rent::Rent};
+use std::{fs, path::Path};
+use token_market::{
+    amounts::AcceptableAmount,
+    error::TokenMarketError,
+    events::Event,
+    pricing::settle_purchase,
+    quote::{quote_purchase, PurchaseSetup},
+    state::{BondingCurve, LoyaltyTier, OverpaymentPolicy, PricingTier, StakeWeight, TokenMarket},
+};
+
+/// Lamports per signature the quotes are computed with
+const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+
+fn key(seed: u8) -> Pubkey {
+    Pubkey::new(&[seed; 32])
+}
+
+fn markets() -> Result<Value> {
+    let fresh = TokenMarket {
+        is_initialized: true,
+        owner: key(1),
+        bank: key(2),
+        emitter_mint: key(3),
+        authority: key(4),
+        mint_of_acceptable: key(5),
+        legacy_bank: Pubkey::default(),
+        legacy_mint_of_acceptable: Pubkey::default(),
+        voucher_signer: [0; 20],
+        category: *b"IDO\0",
+        project_id: [9; 32],
+        treasury: Pubkey::default(),
+        deprecated: false,
+        successor: Pubkey::default(),
+        overpayment_policy: OverpaymentPolicy::Reject,
+        mint_receipts: false,
+        features: TokenMarket::ALL_FEATURES,
+        require_memo: false,
+        sandwich_guard: false,
+        wormhole_chain: 0,
+        wormhole_emitter: [0; 32],
+        onramp_signer: Pubkey::default(),
+        escrow_threshold: 0,
+        dispute_window: 0,
+        dispute_guardian: Pubkey::default(),
+        paused: false,
+        phased: false,
+        fee_bps: 0,
+        fee_vault: Pubkey::default(),
+        min_purchase: 0,
+        max_purchase: 0,
+        wallet_cap: 0,
+        sale_start: 0,
+        sale_end: 0,
+        allowlist_enabled: false,
+        allowlist_root: [0; 32],
+        pricing_tiers: [PricingTier::default(); TokenMarket::MAX_PRICING_TIERS],
+        total_sold: 0,
+        bonding_curve: BondingCurve::default(),
+        stake_weight: StakeWeight::default(),
+        withdraw_rate: 0,
+        last_withdraw: 0,
+        wallet_spend_cap: 0,
+        purchase_cooldown_secs: 0,
+        loyalty_tiers: [LoyaltyTier::default(); TokenMarket::MAX_LOYALTY_TIERS],
+    };
+    let migrated = TokenMarket {
+        mint_of_acceptable: key(6),
+        legacy_bank: key(2),
+        legacy_mint_of_acceptable: key(5),
+        bank: key(7),
+        voucher_signer: [8; 20],
+        treasury: key(10),
+        deprecated: true,
+        successor: key(11),
+        overpayment_policy: OverpaymentPolicy::Refund,
+        mint_receipts: true,
+        features: TokenMarket::ALL_FEATURES,
+        require_memo: false,
+        sandwich_guard: false,
+        wormhole_chain: 0,
+        wormhole_emitter: [0; 32],
+        onramp_signer: Pubkey::default(),
+        escrow_threshold: 0,
+        dispute_window: 0,
+        dispute_guardian: Pubkey::default(),
+        ..fresh
+    };
+
+    let mut fixtures = vec![];
+    for market in &[fresh, migrated] {
+        fixtures.push(json!({
+            "data": hex::encode(market.try_to_vec()?),
+            "market": serde_json::to_value(market)?,
+        }));
+    }
+    Ok(Value::Array(fixtures))
+}
+
+fn quotes() -> Result<Value> {
+    let setups = [
+        PurchaseSetup::default(),
+        PurchaseSetup {
+            create_recipient: true,
+            ..PurchaseSetup::default()
+        },
+        PurchaseSetup {
+            create_recipient: true,
+            mint_receipt: true,
+            open_campaign: true,
+            buyer_signs: true,
+        },
+    ];
+
+    let mut fixtures = vec![];
+    for (amount, tip) in &[(30, 0), (1_500_000_000, 25_000)] {
+        for setup in &setups {
+            let quote = quote_purchase(
+                AcceptableAmount(*amount),
+                AcceptableAmount(*tip),
+                *setup,
+                &Rent::default(),
+                LAMPORTS_PER_SIGNATURE,
+            );
+            fixtures.push(json!({
+                "amount": amount,
+                "tip": tip,
+                "setup": serde_json::to_value(setup)?,
+                "lamports_per_signature": LAMPORTS_PER_SIGNATURE,
+                "quote": serde_json::to_value(quote)?,
+                "total_tokens": quote.total_tokens().map(|total| total.0),
+                "total_lamports": quote.total_lamports(),
+            }));
+        }
+    }
+    Ok(Value::Array(fixtures))
+}
+
+fn events() -> Result<Value> {
+    let events = [
+        Event::TreasuryChanged {
+            market: key(1),
+            old: Pubkey::default(),
+            new: key(10),
+        },
+        Event::BankRecreated {
+            market: key(1),
+            old: key(2),
+            new: key(14),
+        },
+        Event::Purchase {
+            market: key(1),
+            buyer: key(13),
+            amount: 50,
+            memo_hash: hash(b"invoice 42"),
+        },
+        Event::WormholePurchase {
+            market: key(1),
+            buyer: key(13),
+            amount: 50,
+            sequence: 3,
+        },
+        Event::FiatPurchase {
+            market: key(1),
+            buyer: key(15),
+            amount: 75,
+            reference: hash(b"card-payment-7"),
+        },
+        Event::PurchaseEscrowed {
+            market: key(1),
+            buyer: key(15),
+            escrow: key(16),
+            amount: 5000,
+        },
+        Event::EscrowReleased {
+            market: key(1),
+            escrow: key(16),
+            amount: 5000,
+        },
+        Event::EscrowDisputed {
+            market: key(1),
+            escrow: key(16),
+            refund: 5000,
+        },
+        Event::Overpayment {
+            market: key(1),
+            policy: OverpaymentPolicy::Donate,
+            excess: 8,
+        },
+        Event::ReceiptMinted {
+            market: key(1),
+            receipt_mint: key(12),
+            buyer: key(13),
+        },
+        Event::CampaignSale {
+            market: key(1),
+            campaign: *b"SPRING21",
+            amount: 30,
+        },
+        Event::CouponRedeemed {
+            market: key(1),
+            coupon: key(17),
+            bonus: 12,
+        },
+        Event::GiftPurchased {
+            market: key(1),
+            buyer: key(15),
+            gift: key(18),
+            amount: 40,
+        },
+        Event::GiftClaimed {
+            market: key(1),
+            gift: key(18),
+            recipient: key(13),
+            amount: 40,
+        },
+        Event::GiftRefunded {
+            market: key(1),
+            gift: key(18),
+            refund: 40,
+        },
+        Event::TokensSold {
+            market: key(1),
+            seller: key(15),
+            amount: 25,
+        },
+        Event::BankWithdrawn {
+            market: key(1),
+            destination: key(19),
+            amount: 60,
+        },
+        Event::PhaseSale {
+            market: key(1),
+            phase: 1,
+            amount: 100,
+            paid: 50,
+        },
+        Event::ProtocolFee {
+            market: key(1),
+            fee_vault: key(20),
+            amount: 2,
+        },
+        Event::FeesWithdrawn {
+            market: key(1),
+            fee_vault: key(20),
+            destination: key(19),
+            amount: 2,
+        },
+    ];
+
+    let mut fixtures = vec![];
+    for event in &events {
+        fixtures.push(json!({
+            "event": serde_json::to_value(event)?,
+            "log": event.to_string(),
+        }));
+    }
+    Ok(Value::Array(fixtures))
+}
+
+fn errors() -> Result<Value> {
+    let mut program_errors = vec![];
+    for code in 0.. {
+        let error: TokenMarketError =
+            match <TokenMarketError as DecodeError<TokenMarketError>>::decode_custom_error_to_enum(
+                code,
+            ) {
+                Some(error) => error,
+                None => break,
+            };
+        program_errors.push(json!({
+            "code": code,
+            "name": format!("{:?}", error),
+            "message": error.to_string(),
+        }));
+    }
+
+    let mut purchases = vec![];
+    let cases = [
+        (OverpaymentPolicy::Reject, 30, 0, 31),
+        (OverpaymentPolicy::Donate, 30, 2, 31),
+        (OverpaymentPolicy::Refund, u64::MAX, 1, u64::MAX),
+    ];
+    for (policy, amount, tip, delegated) in &cases {
+        if let Err(error) = settle_purchase(*policy, *amount, *tip, *delegated) {
+            purchases.push(json!({
+                "policy": serde_json::to_value(policy)?,
+                "amount": amount,
+                "tip": tip,
+                "delegated": delegated,
+                "error_name": format!("{:?}", error),
+                "error": u64::from(error),
+            }));
+        }
+    }
+
+    Ok(json!({
+        "program_errors": program_errors,
+        "purchases": purchases,
+    }))
+}
+
+/// Process `gen-fixtures` command
+pub fn generate(out: &Path) -> Result<()> {
+    fs::create_dir_all(out)?;
+    for (name, fixture) in &[
+        ("markets.json", markets()?),
+        ("quotes.json", quotes()?),
+        ("events.json", events()?),
+        ("errors.json", errors()?),
+    ] {
+        let path = out.join(name);
+        fs::write(&path, serde_json::to_string_pretty(fixture)? + "\n")?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Claim codes of gifted purchases
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use solana_sdk::pubkey::Pubkey;
+use std::{fmt, str::FromStr};
+use token_market::state::{find_gift_address, gift_secret_hash};
+
+/// What a gift recipient needs to claim it, shared as `MARKET:SECRET` with
+/// the secret hex encoded
+pub struct GiftClaim {
+    pub market: Pubkey,
+    pub secret: [u8; 32],
+}
+
+impl GiftClaim {
+    /// Claim of a new gift on `market` under a random secret
+    pub fn generate(market: Pubkey) -> GiftClaim {
+        GiftClaim {
+            market,
+            secret: rand::random(),
+        }
+    }
+
+    pub fn secret_hash(&self) -> [u8; 32] {
+        gift_secret_hash(&self.secret)
+    }
+
+    /// Address of the gift the claim opens
+    pub fn gift(&self) -> Pubkey {
+        find_gift_address(&token_market::id(), &self.market, &self.secret_hash()).0
+    }
+}
+
+impl fmt::Display for GiftClaim {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.market, hex::encode(self.secret))
+    }
+}
+
+impl FromStr for GiftClaim {
+    type Err = Error;
+
+    fn from_str(claim: &str) -> Result<GiftClaim> {
+        let mut parts = claim.trim().splitn(2, ':');
+        let (market, secret) = match (parts.next(), parts.next()) {
+            (Some(market), Some(secret)) => (market, secret),
+            _ => bail!("Gift claim must look like MARKET:SECRET"),
+        };
+        let market = Pubkey::from_str(market)
+            .map_err(|_| anyhow!("Gift claim names an invalid market {}", market))?;
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(secret, &mut bytes)
+            .context("Gift claim secret is not 64 hex characters")?;
+        Ok(GiftClaim {
+            market,
+            secret: bytes,
+        })
+    }
+}
@@ -0,0 +1,117 @@
+//! Raw instruction data of the token market program.
+//!
+//! Governance proposals and multisig transactions carry the instructions
+//! they execute as bytes, built by tools that don't know the program. The
+//! helpers here convert between those bytes and the JSON form serde gives
+//! [TokenMarketInstructions](../../token_market/instruction/enum.TokenMarketInstructions.html):
+//!
+//! ```
+//! use token_market_cli::instruction_data::{decode, encode};
+//!
+//! let data = encode(r#"{"SetAllowlist": {"enabled": true}}"#, &[]).unwrap();
+//! let decoded = decode(&data).unwrap();
+//! assert!(decoded.trailing.is_empty());
+//! println!("{}", decoded.to_json().unwrap());
+//! ```
+//!
+//! Pubkeys and hashes are written as arrays of their bytes. Bytes after the
+//! instruction are the [trailing fields](../../token_market/instruction/struct.TrailingFields.html)
+//! of newer program versions, kept as they are.
+
+use anyhow::{Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
+use token_market::instruction::TokenMarketInstructions;
+
+/// Instruction data split into the instruction and what follows it
+#[derive(Debug, PartialEq)]
+pub struct DecodedInstruction {
+    pub instruction: TokenMarketInstructions,
+    /// Trailing fields, empty if there are none
+    pub trailing: Vec<u8>,
+}
+
+impl DecodedInstruction {
+    /// JSON [encode](fn.encode.html) takes back, with the trailing fields
+    /// as hex under `trailing` when there are any
+    pub fn to_json(&self) -> Result<String> {
+        let mut json = serde_json::json!({
+            "instruction": serde_json::to_value(&self.instruction)?,
+        });
+        if !self.trailing.is_empty() {
+            json["trailing"] = hex::encode(&self.trailing).into();
+        }
+        Ok(serde_json::to_string_pretty(&json)?)
+    }
+}
+
+/// Instruction data of the instruction `json` describes, either bare like
+/// `"Pause"` or `{"SetWalletCap": {"wallet_cap": 100}}`, or as [DecodedInstruction::to_json](struct.DecodedInstruction.html#method.to_json)
+/// writes it, followed by `trailing`
+pub fn encode(json: &str, trailing: &[u8]) -> Result<Vec<u8>> {
+    let mut value: serde_json::Value =
+        serde_json::from_str(json).context("Instruction is not valid JSON")?;
+    let mut data_trailing = vec![];
+    if let Some(instruction) = value.get_mut("instruction").map(serde_json::Value::take) {
+        if let Some(hex) = value.get("trailing").and_then(serde_json::Value::as_str) {
+            data_trailing = parse_hex(hex)?;
+        }
+        value = instruction;
+    }
+    let instruction: TokenMarketInstructions = serde_json::from_value(value)
+        .context("JSON doesn't describe a token market instruction")?;
+    let mut data = instruction.try_to_vec()?;
+    data.extend(data_trailing);
+    data.extend(trailing);
+    Ok(data)
+}
+
+/// Split instruction `data` into the instruction and its trailing fields
+pub fn decode(mut data: &[u8]) -> Result<DecodedInstruction> {
+    let instruction = TokenMarketInstructions::deserialize(&mut data)
+        .context("Data doesn't start with a token market instruction")?;
+    Ok(DecodedInstruction {
+        instruction,
+        trailing: data.to_vec(),
+    })
+}
+
+/// Parse hex encoded bytes, with or without `0x` and surrounding whitespace
+pub fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let text = text.trim();
+    hex::decode(text.strip_prefix("0x").unwrap_or(text))
+        .with_context(|| format!("{} is not hex encoded", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = encode(r#"{"SetWalletCap": {"wallet_cap": 100}}"#, &[]).unwrap();
+        let decoded = decode(&data).unwrap();
+        assert_eq!(
+            decoded.instruction,
+            TokenMarketInstructions::SetWalletCap { wallet_cap: 100 }
+        );
+        assert_eq!(encode(&decoded.to_json().unwrap(), &[]).unwrap(), data);
+    }
+
+    #[test]
+    fn test_trailing_fields() {
+        let data = encode(r#"{"SetAllowlist": {"enabled": false}}"#, &[7, 0]).unwrap();
+        let decoded = decode(&data).unwrap();
+        assert_eq!(decoded.trailing, vec![7, 0]);
+        let json = decoded.to_json().unwrap();
+        assert!(json.contains(r#""trailing": "0700""#));
+        assert_eq!(encode(&json, &[]).unwrap(), data);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(encode(r#"{"NoSuchInstruction": {}}"#, &[]).is_err());
+        assert!(decode(&[0xff; 4]).is_err());
+        assert_eq!(parse_hex(" 0x0aff\n").unwrap(), vec![0x0a, 0xff]);
+        assert!(parse_hex("0xzz").is_err());
+    }
+}
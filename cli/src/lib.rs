@@ -0,0 +1,14 @@
+//! Off-chain access to token markets, the library side of `token-market-cli`
+
+pub mod addresses;
+pub mod attestation;
+pub mod endpoints;
+pub mod instruction_data;
+pub mod phases;
+pub mod probe;
+pub mod projection;
+pub mod reader;
+pub mod signer;
+pub mod supply;
+pub mod throttle;
+pub mod valuation;
@@ -0,0 +1,346 @@
+//! Market tags and listing metadata, listing markets and finding them by their mint
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use solana_sdk::{program_pack::Pack, pubkey::Pubkey};
+use spl_token::state::Account;
+use std::{
+    collections::HashMap,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use token_market::{amounts::UiAmount, filters::MemcmpFilter, state::TokenMarket};
+use token_market_cli::{
+    phases::{aggregate_by_phase, market_phase_stats, MarketPhaseStats, PhaseStats},
+    projection::MarketField,
+    reader::MarketReader,
+    valuation::{value, Coingecko, FixedPrices, PriceFeed, PriceSnapshot, PythFeed, Valuation},
+};
+
+/// Parse a category of up to 4 ASCII characters, padded with zeros
+pub fn parse_category(category: &str) -> Result<[u8; 4]> {
+    if !category.is_ascii() || category.len() > 4 {
+        bail!("Category {} must be at most 4 ASCII characters", category);
+    }
+    let mut bytes = [0; 4];
+    bytes[..category.len()].copy_from_slice(category.as_bytes());
+    Ok(bytes)
+}
+
+/// Parse a project id given as 64 hex characters
+pub fn parse_project_id(project_id: &str) -> Result<[u8; 32]> {
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(project_id, &mut bytes)
+        .with_context(|| format!("Project id {} must be 64 hex characters", project_id))?;
+    Ok(bytes)
+}
+
+/// Parse comma separated feature names of
+/// [FEATURE_NAMES](../../token_market/state/struct.TokenMarket.html#associatedconstant.FEATURE_NAMES)
+/// into market feature bits
+pub fn parse_features(features: &str) -> Result<u64> {
+    features
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .try_fold(0, |bits, name| {
+            TokenMarket::FEATURE_NAMES
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|(_, bit)| bits | bit)
+                .ok_or_else(|| anyhow!("Unknown feature {}", name))
+        })
+}
+
+/// Names of the features `market` supports, comma separated
+pub fn display_features(market: &TokenMarket) -> String {
+    let names: Vec<&str> = TokenMarket::FEATURE_NAMES
+        .iter()
+        .filter(|(_, bit)| market.supports(*bit))
+        .map(|(name, _)| *name)
+        .collect();
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+fn display_category(category: &[u8; 4]) -> String {
+    String::from_utf8_lossy(category)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// Process `list-markets` command
+pub fn list_markets(reader: &MarketReader, memcmp: &[MemcmpFilter]) -> Result<()> {
+    let fields = [
+        MarketField::Owner,
+        MarketField::Category,
+        MarketField::ProjectId,
+    ];
+    let markets = reader.market_views(&fields, memcmp)?;
+    for (address, market) in &markets {
+        println!(
+            "{} category {:?} project {} owner {}",
+            address,
+            display_category(&market.category.unwrap_or_default()),
+            hex::encode(market.project_id.unwrap_or_default()),
+            market.owner.unwrap_or_default()
+        );
+    }
+    println!("{} market(s) found", markets.len());
+    Ok(())
+}
+
+/// Process `find-market` command
+pub fn find_market(reader: &MarketReader, emitter_mint: &Pubkey) -> Result<()> {
+    let market = reader
+        .find_market(emitter_mint)
+        .ok_or_else(|| anyhow!("No market issues {}", emitter_mint))?;
+    let token_market = reader.market(&market)?;
+
+    println!(
+        "Market {} sells {} for {} one to one",
+        market, emitter_mint, token_market.mint_of_acceptable
+    );
+    println!("Features: {}", display_features(&token_market));
+    Ok(())
+}
+
+/// Process `show-listing` command
+pub fn show_listing(reader: &MarketReader, market: &Pubkey) -> Result<()> {
+    let listing = reader
+        .listing(market)?
+        .ok_or_else(|| anyhow!("Market {} has no listing", market))?;
+
+    println!("Market:    {}", listing.market);
+    println!("Website:   {}", listing.website);
+    println!("Logo hash: {}", hex::encode(listing.logo_uri_hash));
+    println!("Contact:   {}", hex::encode(listing.contact_hash));
+    Ok(())
+}
+
+/// Process `show-phases` command
+pub fn show_phases(reader: &MarketReader, market: &Pubkey) -> Result<()> {
+    let phases = reader
+        .sale_phases(market)?
+        .ok_or_else(|| anyhow!("Market {} does not sell in phases", market))?;
+    let decimals = reader.decimals(&reader.market(market)?.emitter_mint)?;
+    let tokens = |amount: u64| UiAmount::from_raw(amount, decimals);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let active = phases.active(now);
+
+    for (index, (phase, sold)) in phases.phases.iter().zip(&phases.sold).enumerate() {
+        let state = match active {
+            Some(active) if active == index => "open",
+            Some(active) if active > index => "over",
+            _ if phases.closed(now) => "over",
+            _ => "upcoming",
+        };
+        let cap = match phase.cap {
+            0 => "no cap".to_string(),
+            cap => format!("cap {}", tokens(cap)),
+        };
+        let max_purchase = match phase.max_purchase {
+            0 => "no purchase limit".to_string(),
+            max => format!("at most {} per purchase", tokens(max)),
+        };
+        println!(
+            "Phase {} ({}): from {} at {}/{}, sold {}, {}, {}{}",
+            index + 1,
+            state,
+            phase.start,
+            phase.price_numerator,
+            phase.price_denominator,
+            tokens(*sold),
+            cap,
+            max_purchase,
+            if phase.allowlist_only {
+                ", allowlist only"
+            } else {
+                ""
+            }
+        );
+    }
+    match phases.end {
+        0 => println!("The sale stays open in the last phase"),
+        end => println!("The sale closes at {}", end),
+    }
+    Ok(())
+}
+
+fn print_phase_stats(stats: &PhaseStats, share_bps: Option<u64>) {
+    let average = |value: Option<u64>| value.map_or("-".to_string(), |value| value.to_string());
+    println!(
+        "  Phase {}: {} purchases, sold {}, paid {}, average purchase {}, average price {}{}",
+        stats.phase + 1,
+        stats.purchases,
+        stats.sold,
+        stats.paid,
+        average(stats.average_purchase()),
+        stats
+            .average_price()
+            .map_or("-".to_string(), |price| format!("{:.6}", price)),
+        share_bps.map_or(String::new(), |bps| format!(
+            ", {}.{:02}% of the market",
+            bps / 100,
+            bps % 100
+        )),
+    );
+}
+
+/// Parse a Pyth price account of a mint given as `MINT=PRICE_ACCOUNT`
+pub fn parse_pyth_feed(feed: &str) -> Result<(Pubkey, Pubkey)> {
+    let equals = feed
+        .find('=')
+        .ok_or_else(|| anyhow!("Expected MINT=PRICE_ACCOUNT, got {}", feed))?;
+    let parse = |address: &str| {
+        Pubkey::from_str(address.trim()).map_err(|_| anyhow!("Invalid address {}", address))
+    };
+    Ok((parse(&feed[..equals])?, parse(&feed[equals + 1..])?))
+}
+
+/// Reference currency reports value amounts in and the feed of its prices
+pub struct ReportCurrency<'a> {
+    pub currency: String,
+    pub feed: Box<dyn PriceFeed + 'a>,
+}
+
+impl<'a> ReportCurrency<'a> {
+    /// Prices in `currency` of `source`: `coingecko`, `pyth` with the price
+    /// accounts `pyth_feeds` or else a file of `MINT=PRICE` lines
+    pub fn new(
+        reader: &'a MarketReader,
+        currency: &str,
+        source: &str,
+        pyth_feeds: &[(Pubkey, Pubkey)],
+    ) -> Result<Self> {
+        let feed: Box<dyn PriceFeed + 'a> = match source {
+            "coingecko" => Box::new(Coingecko::new()),
+            "pyth" => Box::new(PythFeed::new(reader, pyth_feeds.iter().copied().collect())),
+            path => Box::new(FixedPrices::load(Path::new(path), currency)?),
+        };
+        Ok(Self {
+            currency: currency.to_lowercase(),
+            feed,
+        })
+    }
+}
+
+/// Proceeds and holdings of a market in the reference currency, each with
+/// the price it was valued at
+#[derive(Serialize)]
+struct MarketValuation {
+    market: String,
+    /// Acceptable tokens the bank kept from purchases
+    proceeds: Valuation,
+    /// Acceptable tokens the bank holds now
+    holdings: Valuation,
+}
+
+fn value_markets(
+    reader: &MarketReader,
+    markets: &[MarketPhaseStats],
+    currency: &ReportCurrency,
+) -> Result<Vec<MarketValuation>> {
+    // markets of the same mint are valued at the same price
+    let mut snapshots: HashMap<Pubkey, PriceSnapshot> = HashMap::new();
+    let mut valued = vec![];
+    for stats in markets {
+        let market = reader.market(&stats.market)?;
+        let mint = market.mint_of_acceptable;
+        let snapshot = match snapshots.get(&mint) {
+            Some(snapshot) => snapshot.clone(),
+            None => {
+                let snapshot = currency.feed.price(&mint, &currency.currency)?;
+                snapshots.insert(mint, snapshot.clone());
+                snapshot
+            }
+        };
+        let decimals = reader.decimals(&mint)?;
+        let held = Account::unpack(&reader.rpc_client().get_account_data(&market.bank)?)?.amount;
+        valued.push(MarketValuation {
+            market: stats.market.to_string(),
+            proceeds: value(stats.total().paid, decimals, &snapshot),
+            holdings: value(held, decimals, &snapshot),
+        });
+    }
+    Ok(valued)
+}
+
+fn print_valuation(name: &str, valuation: &Valuation) {
+    let snapshot = &valuation.snapshot;
+    println!(
+        "  {} {} = {:.2} {} at {} from {} published at {}",
+        name,
+        valuation.amount,
+        valuation.value,
+        snapshot.currency,
+        snapshot.price,
+        snapshot.source,
+        snapshot.published_at
+    );
+}
+
+/// Process `phase-stats` command, amounts in raw token units. With a
+/// `currency` the proceeds and holdings of every market are valued in it too.
+pub fn show_phase_stats(
+    reader: &MarketReader,
+    markets: &[Pubkey],
+    json: bool,
+    currency: Option<&ReportCurrency>,
+) -> Result<()> {
+    let markets = markets
+        .iter()
+        .map(|market| market_phase_stats(reader, market))
+        .collect::<Result<Vec<_>>>()?;
+    let combined = aggregate_by_phase(&markets);
+    let valued = match currency {
+        Some(currency) => value_markets(reader, &markets, currency)?,
+        None => vec![],
+    };
+    let proceeds: f64 = valued.iter().map(|market| market.proceeds.value).sum();
+    let holdings: f64 = valued.iter().map(|market| market.holdings.value).sum();
+
+    if json {
+        let mut report = serde_json::json!({
+            "markets": markets,
+            "phases": combined,
+        });
+        if let Some(currency) = currency {
+            report["valuation"] = serde_json::json!({
+                "currency": currency.currency,
+                "markets": valued,
+                "proceeds": proceeds,
+                "holdings": holdings,
+            });
+        }
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    for (index, stats) in markets.iter().enumerate() {
+        println!("Market {}", stats.market);
+        for phase in &stats.phases {
+            print_phase_stats(phase, Some(stats.share_bps(phase.phase)));
+        }
+        if let Some(valuation) = valued.get(index) {
+            print_valuation("Proceeds", &valuation.proceeds);
+            print_valuation("Holdings", &valuation.holdings);
+        }
+    }
+    if markets.len() > 1 {
+        println!("All markets");
+        for phase in &combined {
+            print_phase_stats(phase, None);
+        }
+    }
+    if let Some(currency) = currency {
+        println!(
+            "Total proceeds {:.2} {}, holdings {:.2} {}",
+            proceeds, currency.currency, holdings, currency.currency
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+//! Diagnostic logs of the CLI, written to stderr apart from the command output.
+//!
+//! Commands run in a `command` span. Every transaction runs in a
+//! `transaction` span recording its signature once signed, with `build`
+//! (holding `preflight` when enabled), `sign`, `simulate` and `confirm`
+//! spans inside, and reader lookups open a
+//! span per RPC call. With `--log-format json` every line is one JSON object
+//! carrying the spans it happened in, so failures of batch runs can be matched
+//! to their command, worker process and transaction. `RUST_LOG` picks the
+//! levels, e.g. `RUST_LOG=token_market_cli=debug` shows every RPC call.
+
+use tracing_subscriber::EnvFilter;
+
+/// Levels used when `RUST_LOG` is not set
+const DEFAULT_FILTER: &str = "solana=info,token_market_cli=info";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub const NAMES: &'static [&'static str] = &["text", "json"];
+
+    pub fn parse(name: &str) -> Option<LogFormat> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Write the logs of the CLI and the libraries it uses to stderr in `format`
+pub fn init(format: LogFormat) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
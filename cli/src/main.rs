@@ -1,105 +1,418 @@
-use anyhow::Result;
-use borsh::de::BorshDeserialize;
-use clap::{
-    crate_description, crate_name, crate_version, value_t, App, AppSettings, Arg, SubCommand,
+mod alerts;
+mod allowlist;
+mod args;
+mod campaign;
+mod commands;
+mod doctor;
+mod fixtures;
+mod gift;
+mod listing;
+mod logging;
+mod messages;
+mod mirror;
+mod plan;
+mod proposal;
+mod quote_server;
+mod recipients;
+mod reconcile;
+mod rehearsal;
+mod screening;
+mod state_diff;
+mod tenants;
+mod verify;
+mod voucher;
+
+use allowlist::Allowlist;
+use anyhow::{anyhow, bail, Context, Result};
+use args::{
+    Cli, Command, CurveArg, LoyaltyArg, MirrorArgs, Options, PhaseArg, SetAlertsArgs,
+    SetStakeWeightArgs, TierArg,
 };
-use solana_clap_utils::{
-    fee_payer::fee_payer_arg,
-    input_parsers::pubkey_of,
-    input_validators::{is_pubkey, is_url_or_moniker, is_valid_signer},
-    keypair::signer_from_path,
+use borsh::de::BorshDeserialize;
+use clap::{ArgMatches, ErrorKind};
+use gift::GiftClaim;
+use messages::{message, Locale, Output, ProgressFormat};
+use quote_server::{PriceSource, QuoteServer};
+use screening::{screen_all, AddressScreener, DenylistScreener};
+use solana_account_decoder::UiAccountData;
+use solana_clap_utils::keypair::signer_from_path;
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
+    rpc_request::TokenAccountsFilter,
 };
-use solana_client::{rpc_client::RpcClient, rpc_request::TokenAccountsFilter};
 use solana_sdk::{
     commitment_config::CommitmentConfig,
-    message::Message,
+    hash::hash,
+    instruction::Instruction,
+    native_token::lamports_to_sol,
     program_pack::Pack,
     pubkey::Pubkey,
     rent::Rent,
-    signature::{Keypair, Signer},
+    signature::{Keypair, Signature, Signer},
+    signer::null_signer::NullSigner,
     system_instruction::create_account,
     transaction::Transaction,
 };
 use spl_token::state::{Account, Mint};
-use std::str::FromStr;
-use token_market::{instruction, state::TokenMarket};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Read},
+    path::Path,
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use token_market::{
+    accounts::VestingClaimAccounts,
+    amounts::{AcceptableAmount, EmittedAmount, UiAmount},
+    curve,
+    error::TokenMarketError,
+    filters,
+    instruction::{self, TokenMarketInstructions},
+    memo,
+    pricing::{self, Price},
+    stake::TokenOwnerRecord,
+    state::{
+        coupon_code_hash, find_allowlist_address, find_associated_token_address, find_bank_address,
+        find_buyer_record_address, find_coupon_address, find_deprecation_address,
+        find_escrow_address, find_fee_vault_address, find_receipt_address,
+        find_sale_phases_address, AlertThresholds, BondingCurve, BuyerRecord, Coupon, CurveKind,
+        DelegatedAction, DeprecationNotice, Gift, GiftStatus, HoldingsMigration, ListingInfo,
+        LoyaltyTier, NotificationConfig, OverpaymentPolicy, PriceQuote, PricingTier,
+        PurchaseEscrow, PurchaseReceipt, PurchaseVoucher, SalePhase, SalePhases, StakeSource,
+        StakeWeight, TokenMarket, VestingVault,
+    },
+    validation::{checked_buy_tokens, FetchedAccount, TransactionBuilder},
+    wormhole::{PostedVaa, PurchasePayload},
+};
+use token_market_cli::{
+    addresses::{self, AddressBook, Cluster},
+    attestation::OwnershipAttestation,
+    endpoints::{self, EndpointPool},
+    instruction_data, probe,
+    reader::MarketReader,
+    signer::{self, MarketSigner, WalletSigner},
+    supply,
+    throttle::{Throttle, ThrottleSettings, ThrottledClient},
+};
+use tracing::{debug, debug_span, field, info, info_span, warn};
+
+/// Whether a command prints its transactions instead of sending them, for
+/// `plan` or `gen-proposal`
+#[derive(Clone, Copy, PartialEq)]
+enum Planning {
+    Off,
+    Transactions,
+    /// As a proposal the governance account executes
+    Proposal(Pubkey),
+}
 
 struct Config {
-    owner: Box<dyn Signer>,
-    fee_payer: Box<dyn Signer>,
-    rpc_client: RpcClient,
+    owner: Box<dyn MarketSigner>,
+    fee_payer: Box<dyn MarketSigner>,
+    rpc_client: ThrottledClient,
+    screener: Option<Box<dyn AddressScreener>>,
+    /// Simulate every transaction and show its effects before sending it
+    simulate_first: bool,
+    /// Run the pre-flight checks of every transaction before signing it
+    strict_preflight: bool,
+    /// Print every transaction instead of signing and sending it
+    planning: Planning,
+    output: Output,
 }
 
-fn create_market(config: &Config, mint_acceptable: Pubkey) -> Result<()> {
-    println!("Creating market...");
+#[allow(clippy::too_many_arguments)]
+fn create_market(
+    config: &Config,
+    mint_acceptable: Pubkey,
+    category: [u8; 4],
+    project_id: [u8; 32],
+    features: u64,
+    purchase_limits: (u64, u64),
+    pricing_tiers: &[PricingTier],
+    bonding_curve: BondingCurve,
+    purchase_cooldown_secs: i64,
+) -> Result<Pubkey> {
+    config.output.progress(message!("market.creating"));
 
     let market = Keypair::new();
     let bank = Keypair::new();
     let emitter = Keypair::new();
 
-    let instructions = &[
+    let instructions = create_market_instructions(
+        config,
+        &market.pubkey(),
+        &bank.pubkey(),
+        &emitter.pubkey(),
+        &mint_acceptable,
+        category,
+        project_id,
+        features,
+        purchase_limits,
+        pricing_tiers,
+        bonding_curve,
+        purchase_cooldown_secs,
+    )?;
+    send(
+        config,
+        &instructions,
+        &[config.owner.as_ref(), &market, &bank, &emitter],
+    )?;
+
+    config.output.done(message!(
+        "market.created",
+        market = market.pubkey(),
+        acceptable = mint_acceptable,
+        emitter = emitter.pubkey(),
+        bank = bank.pubkey(),
+    ));
+    Ok(market.pubkey())
+}
+
+/// Allocate the market, bank and emitter accounts and initialize the market,
+/// counted in the owner's tenant stats. The three new accounts and the owner
+/// must sign the transaction. `purchase_limits` are the raw minimum and
+/// maximum payment of a purchase, 0 for no maximum, and `pricing_tiers` or
+/// `bonding_curve` what the market prices by if any, and buyers wait
+/// `purchase_cooldown_secs` between purchases. Initialize is encoded
+/// for the program version the cluster runs.
+#[allow(clippy::too_many_arguments)]
+fn create_market_instructions(
+    config: &Config,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    mint_acceptable: &Pubkey,
+    category: [u8; 4],
+    project_id: [u8; 32],
+    features: u64,
+    purchase_limits: (u64, u64),
+    pricing_tiers: &[PricingTier],
+    bonding_curve: BondingCurve,
+    purchase_cooldown_secs: i64,
+) -> Result<Vec<Instruction>> {
+    let initialize = instruction::with_tenant_stats(
+        &token_market::id(),
+        instruction::initialize(
+            &token_market::id(),
+            &config.owner.pubkey(),
+            &config.fee_payer.pubkey(),
+            market,
+            bank,
+            emitter,
+            mint_acceptable,
+            &spl_token::id(),
+            category,
+            project_id,
+        )?,
+    );
+    let capabilities = probe::probe(
+        &config.rpc_client,
+        &token_market::id(),
+        &config.fee_payer.pubkey(),
+    )?;
+    let initialize = probe::initialize_fields(
+        &capabilities,
+        initialize,
+        features,
+        purchase_limits,
+        pricing_tiers,
+        bonding_curve,
+        purchase_cooldown_secs,
+    )?;
+    Ok(vec![
         create_account(
             &config.fee_payer.pubkey(),
-            &market.pubkey(),
+            market,
             Rent::default().minimum_balance(TokenMarket::LEN),
             TokenMarket::LEN as u64,
             &token_market::id(),
         ),
         create_account(
             &config.fee_payer.pubkey(),
-            &bank.pubkey(),
+            bank,
             Rent::default().minimum_balance(Account::LEN),
             Account::LEN as u64,
-            &token_market::id(),
+            &spl_token::id(),
         ),
         create_account(
             &config.fee_payer.pubkey(),
-            &emitter.pubkey(),
+            emitter,
             Rent::default().minimum_balance(Mint::LEN),
             Mint::LEN as u64,
-            &token_market::id(),
-        ),
-        instruction::initialize(
-            &token_market::id(),
-            &config.owner.pubkey(),
-            &market.pubkey(),
-            &bank.pubkey(),
-            &emitter.pubkey(),
-            &emitter.pubkey(),
-            &mint_acceptable,
             &spl_token::id(),
-        )?,
-    ];
+        ),
+        initialize,
+    ])
+}
 
-    let mut ts = Transaction::new_with_payer(instructions, Some(&config.fee_payer.pubkey()));
-    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
-    let signers = vec![
-        config.fee_payer.as_ref(), 
-        config.owner.as_ref(), 
-        &market as &dyn Signer,
-    ];
-    ts.sign(&signers, recent_blockhash);
-    config
-        .rpc_client
-        .send_and_confirm_transaction_with_spinner(&ts)?;
+/// Raw purchase limits of a market accepting `mint`, no limits when omitted
+fn purchase_limits(
+    config: &Config,
+    mint: &Pubkey,
+    min_purchase: Option<&UiAmount>,
+    max_purchase: Option<&UiAmount>,
+) -> Result<(u64, u64)> {
+    let raw = |amount: Option<&UiAmount>| match amount {
+        Some(amount) => to_raw(config, amount, mint),
+        None => Ok(0),
+    };
+    let (min_purchase, max_purchase) = (raw(min_purchase)?, raw(max_purchase)?);
+    if max_purchase != 0 && min_purchase > max_purchase {
+        bail!("Minimum purchase is above the maximum");
+    }
+    Ok((min_purchase, max_purchase))
+}
 
-    println!(
-        "Market created: market {}, accepted tokens: {}, tradable tokens: {}, bank: {}",
-        market.pubkey(),
-        mint_acceptable,
-        emitter.pubkey(),
-        bank.pubkey()
-    );
-    Ok(())
+/// Tiers of `tiers` with their bounds in raw emitted tokens, which have the
+/// decimals of the acceptable `mint`
+fn pricing_tiers(config: &Config, mint: &Pubkey, tiers: &[TierArg]) -> Result<Vec<PricingTier>> {
+    if tiers.len() > TokenMarket::MAX_PRICING_TIERS {
+        bail!(
+            "A market prices by at most {} tiers",
+            TokenMarket::MAX_PRICING_TIERS
+        );
+    }
+    let tiers = tiers
+        .iter()
+        .map(|tier| {
+            Ok(PricingTier {
+                up_to: match &tier.up_to {
+                    Some(up_to) => to_raw(config, up_to, mint)?,
+                    None => 0,
+                },
+                price_numerator: tier.price.numerator,
+                price_denominator: tier.price.denominator,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if pricing::check_pricing_tiers(&tiers).is_err() {
+        bail!("Tiers need nonzero prices and increasing bounds, only the last may leave out up_to");
+    }
+    Ok(tiers)
+}
+
+/// Bonding curve of `curve` with its prices scaled by [PRICE_SCALE](../token_market/curve/constant.PRICE_SCALE.html)
+/// and its step in raw emitted tokens, which have the decimals of the acceptable `mint`
+fn bonding_curve(config: &Config, mint: &Pubkey, curve: &Option<CurveArg>) -> Result<BondingCurve> {
+    let curve = match curve {
+        Some(curve) => curve,
+        None => return Ok(BondingCurve::default()),
+    };
+    // prices are in acceptable tokens per emitted token, whichever decimals
+    let scaled = |price: &UiAmount| {
+        price
+            .to_raw(9)
+            .ok_or_else(|| anyhow!("Price {} has more than 9 decimals", price))
+    };
+    let base_price = scaled(&curve.base)?;
+    let (growth, step) = match curve.kind {
+        // the growth is per whole emitted token, the program's per PRICE_SCALE raw ones
+        CurveKind::Linear => {
+            let token = to_raw(config, &"1".parse()?, mint)?;
+            let growth =
+                scaled(&curve.growth)? as u128 * curve::PRICE_SCALE as u128 / token as u128;
+            (u64::try_from(growth)?, 0)
+        }
+        _ => {
+            let growth = curve
+                .growth
+                .to_raw(0)
+                .ok_or_else(|| anyhow!("Growth {} is not whole basis points", curve.growth))?;
+            let step = match &curve.step {
+                Some(step) => to_raw(config, step, mint)?,
+                None => 0,
+            };
+            (growth, step)
+        }
+    };
+    let curve = BondingCurve {
+        kind: curve.kind,
+        base_price,
+        growth,
+        step,
+    };
+    if curve::check_bonding_curve(&curve).is_err() {
+        bail!("Curves need a nonzero base price and exponential ones a nonzero step");
+    }
+    Ok(curve)
 }
 
-fn buy_tokens(config: &Config, market: Pubkey, recipient: Pubkey, amount: u64) -> Result<()> {
-    println!("Buying tokens...");
+/// Buy tokens for `recipient`. With `tip` the fee payer acts as a relayer
+/// and receives the tip in its associated account of the acceptable mint.
+#[allow(clippy::too_many_arguments)]
+fn buy_tokens(
+    config: &Config,
+    market: Pubkey,
+    recipient: Pubkey,
+    amount: UiAmount,
+    tip: Option<UiAmount>,
+    voucher: Option<String>,
+    quote: Option<String>,
+    campaign: Option<[u8; 8]>,
+    coupon: Option<String>,
+    memo: Option<String>,
+    allow_deprecated: bool,
+    allowlist: Option<&Path>,
+    native_sol: bool,
+) -> Result<()> {
+    config.output.progress(message!("purchase.buying"));
 
     let mut instructions = vec![];
 
     let market_data = config.rpc_client.get_account_data(&market)?;
     let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    if token_market.paused {
+        bail!("Market {} is paused by its owner", market);
+    }
+    if token_market.deprecated {
+        print_deprecation_notice(config, &market, &token_market);
+    }
+    let amount = to_raw(config, &amount, &token_market.mint_of_acceptable)?;
+    let tip = match tip {
+        Some(tip) => Some(to_raw(config, &tip, &token_market.mint_of_acceptable)?),
+        None => None,
+    };
+    if token_market.require_memo && memo.is_none() {
+        bail!("Market {} requires a memo, pass --memo", market);
+    }
+    if token_market.check_purchase(amount).is_err() {
+        bail!(
+            "Market {} accepts purchases paying from {} to {}",
+            market,
+            token_market.min_purchase,
+            match token_market.max_purchase {
+                0 => "any amount".to_string(),
+                max => max.to_string(),
+            }
+        );
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    match token_market.check_sale_window(now) {
+        Err(TokenMarketError::SaleNotStarted) => bail!(
+            "The sale of market {} starts at {}",
+            market,
+            token_market.sale_start
+        ),
+        Err(TokenMarketError::SaleEnded) => bail!(
+            "The sale of market {} ended at {}",
+            market,
+            token_market.sale_end
+        ),
+        _ => {}
+    }
+    if token_market.escrows(amount) {
+        bail!(
+            "Market {} holds purchases of {} or more in escrow, use buy-escrowed",
+            market,
+            token_market.escrow_threshold
+        );
+    }
 
     // Finding a suitable account for placement of purchased tokens.
     // If suitable account is not found - create it.
@@ -113,172 +426,3727 @@ fn buy_tokens(config: &Config, market: Pubkey, recipient: Pubkey, amount: u64) -
         &token_market.mint_of_acceptable,
     );
 
-    instructions.extend_from_slice(&[
-        spl_token::instruction::approve(
+    screen_all(
+        config.screener.as_deref(),
+        &[&config.owner.pubkey(), &recipient],
+    )?;
+
+    if native_sol {
+        return buy_tokens_with_sol(config, &market, &token_market, &recipient, amount, memo);
+    }
+
+    let mut buy_tokens = checked_buy_tokens(
+        &|address: &Pubkey| {
+            config
+                .rpc_client
+                .get_account(address)
+                .ok()
+                .map(FetchedAccount::from)
+        },
+        &token_market::id(),
+        &market,
+        &token_market.bank,
+        &token_market.emitter_mint,
+        &recipient_acc,
+        &write_off_account,
+        &spl_token::id(),
+        AcceptableAmount(amount),
+        allow_deprecated,
+    )?;
+    if let Some(tip) = tip {
+        let tip_account = spl_associated_token_account::get_associated_token_address(
+            &config.fee_payer.pubkey(),
+            &token_market.mint_of_acceptable,
+        );
+        buy_tokens = instruction::buy_tokens_with_tip(
             &token_market::id(),
-            &recipient,
+            &market,
+            &token_market.bank,
+            &token_market.emitter_mint,
+            &recipient_acc,
+            &write_off_account,
+            &tip_account,
+            AcceptableAmount(amount),
+            AcceptableAmount(tip),
+        )?;
+    }
+    if let Some(voucher) = voucher {
+        let (verify, voucher): (_, PurchaseVoucher) = voucher::decode(&voucher)?;
+        if voucher.market != market || voucher.buyer != config.owner.pubkey() {
+            bail!(
+                "Voucher is issued for buyer {} on market {}",
+                voucher.buyer,
+                voucher.market
+            );
+        }
+        if amount > voucher.max_amount {
+            bail!("Voucher allows at most {} per purchase", voucher.max_amount);
+        }
+        buy_tokens = instruction::buy_tokens_with_voucher(
             &token_market::id(),
-            &config.owner.pubkey(),
-            &[],
-            amount,
-        )?,
-        instruction::buy_tokens(
+            &market,
+            &token_market.bank,
+            &token_market.emitter_mint,
+            &recipient_acc,
+            &write_off_account,
+            AcceptableAmount(amount),
+            &voucher,
+        )?;
+        // The signature offsets of a voucher point at instruction 0
+        instructions.push(verify);
+    }
+    if let Some(quote) = quote {
+        let (verify, quote): (_, PriceQuote) = voucher::decode(&quote)?;
+        if quote.market != market {
+            bail!("Quote is issued for market {}", quote.market);
+        }
+        if amount > quote.max_amount {
+            bail!("Quote allows at most {} per purchase", quote.max_amount);
+        }
+        if SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64 > quote.expiry {
+            bail!("Quote expired at {}", quote.expiry);
+        }
+        buy_tokens = instruction::buy_tokens_with_quote(
             &token_market::id(),
             &market,
             &token_market.bank,
+            &token_market.emitter_mint,
             &recipient_acc,
             &write_off_account,
+            AcceptableAmount(amount),
+            &quote,
+        )?;
+        // Quotes are signed like vouchers, the verification goes first as well
+        instructions.push(verify);
+    }
+    if let Some(campaign) = campaign {
+        buy_tokens = instruction::buy_tokens_with_campaign(
             &token_market::id(),
-            amount,
-        )?,
-    ]);
+            &market,
+            &token_market.bank,
+            &token_market.emitter_mint,
+            &recipient_acc,
+            &write_off_account,
+            &config.fee_payer.pubkey(),
+            AcceptableAmount(amount),
+            campaign,
+        )?;
+    }
+    let receipt_mint = Keypair::new();
+    let receipt_holding = Keypair::new();
+    if token_market.mint_receipts {
+        buy_tokens = instruction::with_receipt(
+            &token_market::id(),
+            buy_tokens,
+            &config.fee_payer.pubkey(),
+            &receipt_mint.pubkey(),
+            &receipt_holding.pubkey(),
+        );
+    }
+    if let Some(code) = coupon {
+        let code_hash = coupon_code_hash(&market, &code);
+        let address = find_coupon_address(&token_market::id(), &market, &code_hash).0;
+        let coupon = match config.rpc_client.get_account_data(&address) {
+            Ok(data) => Coupon::try_from_slice(&data)?,
+            Err(_) => bail!("Market {} has no coupon {}", market, code),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        if coupon.expired(now) {
+            bail!("Coupon {} expired at {}", code, coupon.expiry);
+        }
+        if coupon.remaining_uses == 0 {
+            bail!("Coupon {} has no uses left", code);
+        }
+        buy_tokens = instruction::with_coupon(&token_market::id(), buy_tokens, &market, &code_hash);
+        config.output.progress(message!(
+            "coupon.applying",
+            discount_bps = coupon.discount_bps,
+            uses = coupon.remaining_uses,
+        ));
+    }
+    if token_market.phased {
+        let address = find_sale_phases_address(&token_market::id(), &market).0;
+        let phases = SalePhases::load(&config.rpc_client.get_account_data(&address)?)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        if phases.active(now).is_none() {
+            bail!("Market {} has no sale phase open", market);
+        }
+        buy_tokens = instruction::with_sale_phases(&token_market::id(), buy_tokens, &market);
+    }
+    if token_market.fee_bps > 0 {
+        buy_tokens = instruction::with_protocol_fee(
+            &token_market::id(),
+            buy_tokens,
+            &token_market.fee_vault,
+            &token_market.owner,
+        );
+    }
+    if token_market.counts_sales() {
+        buy_tokens = instruction::with_tiered_pricing(buy_tokens);
+    }
+    if token_market.tracks_buyers() {
+        let stake = find_stake(config, &token_market.stake_weight, &recipient)?;
+        let wallet_cap = token_market.wallet_cap_with(stake.map_or(0, |(_, staked)| staked));
+        let address = find_buyer_record_address(&token_market::id(), &market, &recipient).0;
+        // no record yet means no purchase counted yet, the purchase creates it
+        if let Ok(data) = config.rpc_client.get_account_data(&address) {
+            let record = BuyerRecord::try_from_slice(&data)?;
+            if token_market.wallet_cap > 0 && record.remaining(wallet_cap) == 0 {
+                bail!(
+                    "{} already bought all of its {} on market {}",
+                    recipient,
+                    wallet_cap,
+                    market
+                );
+            }
+            if token_market.wallet_spend_cap > 0
+                && record.remaining_spend(token_market.wallet_spend_cap) < amount
+            {
+                bail!(
+                    "{} can spend {} more on market {}",
+                    recipient,
+                    record.remaining_spend(token_market.wallet_spend_cap),
+                    market
+                );
+            }
+            if record
+                .clone()
+                .cool_down(now, token_market.purchase_cooldown_secs)
+                .is_err()
+            {
+                bail!(
+                    "{} purchased on market {} less than {} seconds ago",
+                    recipient,
+                    market,
+                    token_market.purchase_cooldown_secs
+                );
+            }
+        }
+        if let Some((stake, _)) = stake {
+            buy_tokens = instruction::with_stake(buy_tokens, &stake);
+        }
+        buy_tokens = instruction::with_buyer_record(
+            &token_market::id(),
+            buy_tokens,
+            &market,
+            &recipient,
+            &config.fee_payer.pubkey(),
+        );
+    }
+    if token_market.allowlist_enabled {
+        let address = find_allowlist_address(&token_market::id(), &market, &recipient).0;
+        let proof = match allowlist {
+            Some(path) => Allowlist::load(path)?.proof(&recipient),
+            None => None,
+        };
+        if config.rpc_client.get_account_data(&address).is_ok() {
+            buy_tokens = instruction::with_allowlist_entry(
+                &token_market::id(),
+                buy_tokens,
+                &market,
+                &recipient,
+            );
+        } else if let Some(proof) = proof {
+            // only plain purchases read the proof
+            if !matches!(
+                TokenMarketInstructions::unpack(&buy_tokens.data),
+                Ok((TokenMarketInstructions::BuyTokens { .. }, _))
+            ) {
+                bail!("Allowlist proofs can't go with vouchers, quotes, tips or campaigns");
+            }
+            buy_tokens = instruction::with_allowlist_proof(buy_tokens, &proof)?;
+        } else {
+            bail!("{} isn't on the allowlist of market {}", recipient, market);
+        }
+    }
+    if let Some(text) = memo {
+        buy_tokens = instruction::with_instructions_sysvar(buy_tokens);
+        instructions.push(memo::memo(&text));
+    }
+    let delegated = amount
+        .checked_add(tip.unwrap_or(0))
+        .ok_or_else(|| anyhow!("Amount and tip overflow"))?;
+    instructions.extend(instruction::bundle_buy_tokens(
+        &token_market::id(),
+        buy_tokens,
+        &write_off_account,
+        &config.owner.pubkey(),
+        AcceptableAmount(delegated),
+    )?);
 
-    let message = Message::new(instructions.as_slice(), Some(&config.fee_payer.pubkey()));
-    let transaction = Transaction::new_unsigned(message);
-    config
-        .rpc_client
-        .send_and_confirm_transaction_with_spinner(&transaction)?;
+    send(
+        config,
+        &instructions,
+        &[config.owner.as_ref(), &receipt_mint, &receipt_holding],
+    )?;
+
+    config.output.done(message!(
+        "purchase.done",
+        amount = amount,
+        recipient = recipient,
+        account = recipient_acc,
+    ));
+    if token_market.mint_receipts {
+        config.output.done(message!(
+            "purchase.receipt",
+            mint = receipt_mint.pubkey(),
+            holding = receipt_holding.pubkey(),
+        ));
+    }
+    Ok(())
+}
 
+/// Account holding the stake of `buyer` that `weight` reads, with the
+/// tokens staked in it, `None` if the buyer stakes nothing there
+fn find_stake(
+    config: &Config,
+    weight: &StakeWeight,
+    buyer: &Pubkey,
+) -> Result<Option<(Pubkey, u64)>> {
+    match weight.source {
+        StakeSource::Off => Ok(None),
+        StakeSource::PoolTokens => {
+            let address = find_associated_token_address(buyer, &weight.mint);
+            match config.rpc_client.get_account_data(&address) {
+                Ok(data) => Ok(Some((address, Account::unpack(&data)?.amount))),
+                Err(_) => Ok(None),
+            }
+        }
+        StakeSource::GovernanceDeposit => {
+            // token owner records are found by mint and owner, a buyer may
+            // have deposited in several realms
+            let records = config.rpc_client.retrying(|client| {
+                client.get_program_accounts_with_config(
+                    &weight.governance_program,
+                    RpcProgramAccountsConfig {
+                        filters: Some(vec![
+                            RpcFilterType::Memcmp(Memcmp {
+                                offset: 1 + 32,
+                                bytes: MemcmpEncodedBytes::Binary(weight.mint.to_string()),
+                                encoding: None,
+                            }),
+                            RpcFilterType::Memcmp(Memcmp {
+                                offset: 1 + 32 * 2,
+                                bytes: MemcmpEncodedBytes::Binary(buyer.to_string()),
+                                encoding: None,
+                            }),
+                        ]),
+                        account_config: RpcAccountInfoConfig::default(),
+                    },
+                )
+            })?;
+            Ok(records
+                .into_iter()
+                .filter_map(|(address, account)| {
+                    let record = TokenOwnerRecord::unpack(&account.data)?;
+                    Some((address, record.governing_token_deposit_amount))
+                })
+                .max_by_key(|(_, staked)| *staked))
+        }
+    }
+}
+
+/// Buy tokens for `recipient` paying `lamports` of native SOL, which the
+/// program wraps into the bank of a market accepting wrapped SOL
+fn buy_tokens_with_sol(
+    config: &Config,
+    market: &Pubkey,
+    token_market: &TokenMarket,
+    recipient: &Pubkey,
+    lamports: u64,
+    memo: Option<String>,
+) -> Result<()> {
+    if token_market.mint_of_acceptable != spl_token::native_mint::id() {
+        bail!(
+            "Market {} accepts {}, not wrapped SOL",
+            market,
+            token_market.mint_of_acceptable
+        );
+    }
+    if token_market.phased
+        || token_market.counts_sales()
+        || token_market.caps_buyers()
+        || token_market.allowlist_enabled
+    {
+        bail!(
+            "Market {} takes native SOL for plain purchases only, pay in wrapped SOL",
+            market
+        );
+    }
+
+    let mut instructions = vec![];
+    let recipient_acc = emitter_account(
+        config,
+        &mut instructions,
+        recipient,
+        &token_market.emitter_mint,
+    )?;
+    let mut buy_tokens = instruction::buy_tokens_with_sol(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        market,
+        &token_market.bank,
+        &token_market.emitter_mint,
+        &recipient_acc,
+        AcceptableAmount(lamports),
+    )?;
+    if token_market.fee_bps > 0 {
+        buy_tokens = instruction::with_protocol_fee(
+            &token_market::id(),
+            buy_tokens,
+            &token_market.fee_vault,
+            &token_market.owner,
+        );
+    }
+    if let Some(text) = memo {
+        buy_tokens = instruction::with_instructions_sysvar(buy_tokens);
+        instructions.push(memo::memo(&text));
+    }
+    instructions.push(buy_tokens);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "purchase.done",
+        amount = lamports,
+        recipient = recipient,
+        account = recipient_acc,
+    ));
+    Ok(())
+}
+
+fn estimate_cost(
+    reader: &MarketReader,
+    market: Pubkey,
+    recipient: Pubkey,
+    amount: UiAmount,
+    tip: Option<UiAmount>,
+    campaign: Option<[u8; 8]>,
+    buyer_signs: bool,
+    explain: bool,
+) -> Result<()> {
+    let token_market = reader.market(&market)?;
+    let decimals = reader.decimals(&token_market.mint_of_acceptable)?;
+    let raw = |amount: &UiAmount| {
+        amount
+            .to_raw(decimals)
+            .map(AcceptableAmount)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Amount {} doesn't fit mint {} with {} decimals",
+                    amount,
+                    token_market.mint_of_acceptable,
+                    decimals
+                )
+            })
+    };
+    let quote = reader.quote(
+        &market,
+        &recipient,
+        raw(&amount)?,
+        match tip {
+            Some(tip) => raw(&tip)?,
+            None => AcceptableAmount(0),
+        },
+        campaign,
+        buyer_signs,
+    )?;
+    let tokens = |amount: u64| UiAmount::from_raw(amount, decimals);
+    let sol = |lamports: u64| format!("{} SOL", lamports_to_sol(lamports));
+    let total_tokens = quote
+        .total_tokens()
+        .ok_or_else(|| anyhow!("Amount and tip overflow"))?;
+    let total_lamports = quote
+        .total_lamports()
+        .ok_or_else(|| anyhow!("Lamports overflow"))?;
+
+    println!("Tokens received: {}", tokens(quote.emitted.0));
+    println!("Price:           {}", tokens(quote.price.0));
+    println!("Relayer tip:     {}", tokens(quote.relayer_tip.0));
+    println!("Total tokens:    {}", tokens(total_tokens.0));
+    println!("Recipient rent:  {}", sol(quote.recipient_rent));
+    println!("Receipt rent:    {}", sol(quote.receipt_rent));
+    println!("Campaign rent:   {}", sol(quote.campaign_rent));
+    println!("Network fee:     {}", sol(quote.network_fee));
+    println!("Total SOL:       {}", sol(total_lamports));
+    if explain {
+        // the CLI delegates exactly the price and the tip
+        let breakdown = pricing::explain_purchase(
+            token_market.overpayment_policy,
+            Price::PAR,
+            quote.price.0,
+            quote.relayer_tip.0,
+            total_tokens.0,
+            token_market.fee_bps,
+        )?;
+        println!("Math, in raw token units:");
+        for line in breakdown.to_string().lines() {
+            println!("  {}", line);
+        }
+    }
+    Ok(())
+}
+
+fn show_history(reader: &MarketReader, market: &Pubkey, limit: usize) -> Result<()> {
+    for transaction in reader.history(market, limit)? {
+        println!(
+            "{} slot {}{}",
+            transaction.signature,
+            transaction.slot,
+            if transaction.failed { " failed" } else { "" }
+        );
+        for event in &transaction.events {
+            println!("  {}", event);
+        }
+    }
+    Ok(())
+}
+
+/// Process `reconcile` command, failing unless the supply matches the records
+fn reconcile_supply(reader: &MarketReader, market: &Pubkey) -> Result<()> {
+    let report = supply::reconcile_supply(reader, market)?;
+    for change in &report.discrepancies {
+        println!(
+            "{} slot {}: supply changed by {}, {} recorded",
+            change.signature, change.slot, change.actual, change.recorded
+        );
+    }
+    for signature in &report.unverifiable {
+        println!("{}: no token balances to check", signature);
+    }
     println!(
-        "Purchased {} tokens. Recipient user {}. Target ATA {}",
-        amount, recipient, recipient_acc
+        "Supply of {} is {}, records account for {}",
+        report.emitter, report.supply, report.recorded
     );
+
+    if !report.balanced() {
+        bail!("Supply of market {} doesn't match its records", market);
+    }
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let matches = App::new(crate_name!())
-        .about(crate_description!())
-        .version(crate_version!())
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .arg({
-            let arg = Arg::with_name("config_file")
-                .short("C")
-                .long("config")
-                .value_name("PATH")
-                .takes_value(true)
-                .global(true)
-                .help("Configuration file to use");
-            if let Some(ref config_file) = *solana_cli_config::CONFIG_FILE {
-                arg.default_value(&config_file)
-            } else {
-                arg
+/// Warn that `market` is deprecated, with its successor and notice if any
+fn print_deprecation_notice(config: &Config, market: &Pubkey, token_market: &TokenMarket) {
+    config
+        .output
+        .warning(message!("market.deprecated", market = market));
+    if let Some(successor) = token_market.successor() {
+        config
+            .output
+            .warning(message!("market.successor", successor = successor));
+    }
+    let notice = find_deprecation_address(&token_market::id(), market).0;
+    if let Ok(notice) = config.rpc_client.get_account_data(&notice) {
+        if let Ok(notice) = DeprecationNotice::load(&notice) {
+            config.output.warning(message!(
+                "market.deprecation_notice",
+                uri = notice.message_uri
+            ));
+        }
+    }
+}
+
+fn deprecate(
+    config: &Config,
+    market: Pubkey,
+    successor: Option<Pubkey>,
+    message_uri: &str,
+) -> Result<()> {
+    config.output.progress(message!("deprecation.setting"));
+
+    if message_uri.len() > DeprecationNotice::MAX_MESSAGE_URI_LEN {
+        bail!(
+            "Message URI must be at most {} bytes",
+            DeprecationNotice::MAX_MESSAGE_URI_LEN
+        );
+    }
+    let instructions = &[instruction::deprecate(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &config.fee_payer.pubkey(),
+        &market,
+        successor,
+        message_uri.to_string(),
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config
+        .output
+        .done(message!("deprecation.set", market = market));
+    Ok(())
+}
+
+/// Sign `instructions` with the fee payer and whichever of `signers` the
+/// message requires, then send them. Signers that aren't required are skipped.
+fn send(
+    config: &Config,
+    instructions: &[Instruction],
+    signers: &[&dyn MarketSigner],
+) -> Result<Signature> {
+    let mut transaction = TransactionBuilder::new(&config.fee_payer.pubkey());
+    for instruction in instructions {
+        transaction.instruction(instruction.clone());
+    }
+    send_checked(config, &transaction, signers)
+}
+
+/// Send the instructions of `transaction`, checking the accounts it
+/// expects first when `--strict-preflight` is given
+fn send_checked(
+    config: &Config,
+    transaction: &TransactionBuilder,
+    signers: &[&dyn MarketSigner],
+) -> Result<Signature> {
+    let instructions = transaction.instructions();
+    match config.planning {
+        Planning::Off => {}
+        Planning::Transactions => {
+            print!(
+                "{}",
+                planned_transaction(&config.fee_payer.pubkey(), instructions)
+            );
+            return Ok(Signature::default());
+        }
+        Planning::Proposal(governance) => {
+            println!("{}", proposal::payload(&governance, instructions)?);
+            return Ok(Signature::default());
+        }
+    }
+
+    let span = info_span!(
+        "transaction",
+        instructions = instructions.len(),
+        signature = field::Empty,
+    );
+    let _transaction = span.enter();
+
+    let (mut ts, recent_blockhash) = debug_span!("build").in_scope(|| -> Result<_> {
+        let ts = Transaction::new_with_payer(instructions, Some(&config.fee_payer.pubkey()));
+        let (recent_blockhash, fee_calculator) = config
+            .rpc_client
+            .failover(|client| client.get_recent_blockhash())?;
+        debug!(%recent_blockhash, "built");
+        if config.strict_preflight {
+            debug_span!("preflight").in_scope(|| {
+                transaction.preflight(
+                    &|address: &Pubkey| {
+                        config
+                            .rpc_client
+                            .get_account(address)
+                            .ok()
+                            .map(FetchedAccount::from)
+                    },
+                    fee_calculator.calculate_fee(&ts.message),
+                )
+            })?;
+        }
+        Ok((ts, recent_blockhash))
+    })?;
+    debug_span!("sign").in_scope(|| -> Result<()> {
+        let mut available = vec![config.fee_payer.as_ref()];
+        available.extend_from_slice(signers);
+        signer::sign_transaction(&mut ts, &available, recent_blockhash)
+    })?;
+    span.record("signature", &field::display(ts.signatures[0]));
+    if config.simulate_first {
+        debug_span!("simulate").in_scope(|| simulate(config, &ts))?;
+    }
+    // The spinner would garble the event stream
+    let sent = debug_span!("confirm").in_scope(|| {
+        config
+            .rpc_client
+            .failover(|client| match config.output.format {
+                ProgressFormat::Text => client.send_and_confirm_transaction_with_spinner(&ts),
+                ProgressFormat::Json => client.send_and_confirm_transaction(&ts),
+            })
+    });
+    match sent {
+        Ok(signature) => {
+            info!("confirmed");
+            Ok(signature)
+        }
+        Err(err) => {
+            warn!(error = %err, "not confirmed");
+            Err(err.into())
+        }
+    }
+}
+
+/// The transaction paid by `fee_payer` in the canonical format of `plan`:
+/// every instruction in order with its accounts as they are passed and its
+/// data in hex, one item per line so that reviews can diff two plans
+fn planned_transaction(fee_payer: &Pubkey, instructions: &[Instruction]) -> String {
+    let mut text = format!("transaction fee-payer={}\n", fee_payer);
+    for (index, ix) in instructions.iter().enumerate() {
+        text.push_str(&format!(
+            "  instruction {} program={}\n",
+            index, ix.program_id
+        ));
+        for meta in &ix.accounts {
+            text.push_str(&format!(
+                "    account {} {}{}\n",
+                meta.pubkey,
+                if meta.is_writable {
+                    "writable"
+                } else {
+                    "readonly"
+                },
+                if meta.is_signer { " signer" } else { "" },
+            ));
+        }
+        text.push_str(&format!("    data {}\n", hex::encode(&ix.data)));
+    }
+    text
+}
+
+/// Simulate `ts`, print the decoded instructions and the program logs,
+/// and fail if the transaction would not succeed
+fn simulate(config: &Config, ts: &Transaction) -> Result<()> {
+    config.output.progress(message!("transaction.simulating"));
+    for (index, ix) in ts.message.instructions.iter().enumerate() {
+        let program_id = ts.message.account_keys[ix.program_id_index as usize];
+        if program_id == token_market::id() {
+            match TokenMarketInstructions::unpack(&ix.data) {
+                Ok((decoded, _)) => config.output.progress(message!(
+                    "transaction.instruction",
+                    index = index,
+                    instruction = format!("{:?}", decoded),
+                )),
+                Err(_) => config.output.progress(message!(
+                    "transaction.instruction_undecodable",
+                    index = index
+                )),
             }
-        })
-        .arg(
-            Arg::with_name("json_rpc_url")
-                .short("u")
-                .long("url")
-                .value_name("URL_OR_MONIKER")
-                .takes_value(true)
-                .global(true)
-                .validator(is_url_or_moniker)
-                .help(
-                    "URL for Solana's JSON RPC or moniker (or their first letter): \
-                       [mainnet-beta, testnet, devnet, localhost] \
-                    Default from the configuration file.",
-                ),
-        )
-        .arg(
-            Arg::with_name("owner")
-                .long("owner")
-                .value_name("KEYPAIR")
-                .validator(is_valid_signer)
-                .takes_value(true)
-                .global(true)
-                .help(
-                    "Specify the token owner account. \
-                 This may be a keypair file, the ASK keyword. \
-                 Defaults to the client keypair.",
-                ),
-        )
-        .arg(fee_payer_arg().global(true))
-        .subcommand(
-            SubCommand::with_name("create-market")
-                .args(&[Arg::with_name("acceptable")
-                    .value_name("ADDRESS")
-                    .takes_value(true)
-                    .validator(is_pubkey)
-                    .required(true)
-                    .help("TODO")])
-                .about("Create a new token market"),
+        } else {
+            config.output.progress(message!(
+                "transaction.instruction_foreign",
+                index = index,
+                program = program_id,
+            ));
+        }
+    }
+
+    let result = config.rpc_client.simulate_transaction(ts)?.value;
+    for log in result.logs.unwrap_or_default() {
+        config
+            .output
+            .progress(message!("transaction.log", log = log));
+    }
+    if let Some(err) = result.err {
+        bail!("Simulation failed: {}", err);
+    }
+    config.output.progress(message!("transaction.simulated"));
+    Ok(())
+}
+
+/// Raw amount of `mint` that the human readable `amount` stands for
+fn to_raw(config: &Config, amount: &UiAmount, mint: &Pubkey) -> Result<u64> {
+    let decimals = Mint::unpack(&config.rpc_client.get_account_data(mint)?)?.decimals;
+    amount.to_raw(decimals).ok_or_else(|| {
+        anyhow!(
+            "Amount {} doesn't fit mint {} with {} decimals",
+            amount,
+            mint,
+            decimals
         )
-        .subcommand(
-            SubCommand::with_name("buy-tokens").args(&[
-                Arg::with_name("market")
-                    .validator(is_pubkey)
-                    .value_name("MARKET_ADDRESS")
-                    .takes_value(true)
-                    .required(true)
-                    .help("Market account pubkey"),
-                Arg::with_name("recipient")
-                    .validator(is_pubkey)
-                    .value_name("ACCOUNT_ADDRESS")
-                    .takes_value(true)
-                    .required(true)
-                    .help("User which tokens are received"),
-                Arg::with_name("amount")
-                    .value_name("NUMBER")
-                    .takes_value(true)
-                    .required(true)
-                    .help("Number of exchanged tokens"),
-            ]),
+    })
+}
+
+/// Associated emitter token account of `wallet`, created if it does not exist yet
+fn emitter_account(
+    config: &Config,
+    instructions: &mut Vec<Instruction>,
+    wallet: &Pubkey,
+    emitter_mint: &Pubkey,
+) -> Result<Pubkey> {
+    let address = spl_associated_token_account::get_associated_token_address(wallet, emitter_mint);
+    if config.rpc_client.get_account_data(&address).is_err() {
+        instructions.push(
+            spl_associated_token_account::create_associated_token_account(
+                &config.fee_payer.pubkey(),
+                wallet,
+                emitter_mint,
+            ),
+        );
+    }
+    Ok(address)
+}
+
+/// Sign the owner instruction `instruction` of `market` under the delegation
+/// of the configured owner keypair when it isn't the market owner itself
+fn owner_or_delegate(
+    config: &Config,
+    market: &Pubkey,
+    token_market: &TokenMarket,
+    instruction: Instruction,
+) -> Instruction {
+    if token_market.owner == config.owner.pubkey() {
+        instruction
+    } else {
+        instruction::with_delegation(
+            &token_market::id(),
+            instruction,
+            market,
+            &config.owner.pubkey(),
         )
-        .get_matches();
+    }
+}
 
-    let mut wallet_manager = None;
+fn set_delegation(
+    config: &Config,
+    market: Pubkey,
+    delegate: Pubkey,
+    vesting_per_day: Option<UiAmount>,
+    calls_per_day: [u64; 3],
+) -> Result<()> {
+    config.output.progress(message!("delegation.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let mut daily_limits = [0; DelegatedAction::COUNT];
+    if let Some(vesting_per_day) = vesting_per_day {
+        daily_limits[DelegatedAction::CreateVestingVault as usize] =
+            to_raw(config, &vesting_per_day, &token_market.emitter_mint)?;
+    }
+    daily_limits[DelegatedAction::SetListingInfo as usize] = calls_per_day[0];
+    daily_limits[DelegatedAction::SetOverpaymentPolicy as usize] = calls_per_day[1];
+    daily_limits[DelegatedAction::SetReceiptMinting as usize] = calls_per_day[2];
+
+    let instructions = &[instruction::set_delegation(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &config.fee_payer.pubkey(),
+        &market,
+        &delegate,
+        daily_limits,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
 
-    let cli_config = if let Some(config_file) = matches.value_of("config_file") {
-        solana_cli_config::Config::load(config_file)?
+    if daily_limits.iter().all(|limit| *limit == 0) {
+        config.output.done(message!(
+            "delegation.revoked",
+            delegate = delegate,
+            market = market,
+        ));
     } else {
-        println!("Config file not provided and default config unexist. Create config");
-        solana_cli_config::Config::default()
-    };
-    let json_rpc_url = value_t!(matches, "json_rpc_url", String)
-        .unwrap_or_else(|_| cli_config.json_rpc_url.clone());
-    let owner = signer_from_path(
-        &matches,
-        matches
-            .value_of("owner")
-            .unwrap_or(&cli_config.keypair_path),
-        "owner",
-        &mut wallet_manager,
-    )
-    .unwrap(); //TODO
-    let fee_payer = signer_from_path(
-        &matches,
-        matches
-            .value_of("fee_payer")
-            .unwrap_or(&cli_config.keypair_path),
-        "fee_payer",
-        &mut wallet_manager,
-    )
-    .unwrap(); //TODO
+        config.output.done(message!(
+            "delegation.set",
+            delegate = delegate,
+            market = market,
+            limits = format!("{:?}", daily_limits),
+        ));
+    }
+    Ok(())
+}
 
-    let config = &Config {
-        owner: owner,
-        fee_payer: fee_payer,
-        rpc_client: RpcClient::new_with_commitment(json_rpc_url, CommitmentConfig::confirmed()),
-    };
+#[allow(clippy::too_many_arguments)]
+fn create_vesting(
+    config: &Config,
+    market: Pubkey,
+    beneficiary: Pubkey,
+    total: UiAmount,
+    start: i64,
+    cliff: i64,
+    duration: i64,
+    revocable: bool,
+) -> Result<()> {
+    config.output.progress(message!("vesting.creating"));
 
-    solana_logger::setup_with_default("solana=info");
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let total = to_raw(config, &total, &token_market.emitter_mint)?;
+    let vesting = Keypair::new();
+    let vault = Keypair::new();
 
-    match matches.subcommand() {
-        ("create-market", Some(args)) => {
-            let acceptable = pubkey_of(args, "acceptable").unwrap();
+    let instructions = &[
+        create_account(
+            &config.fee_payer.pubkey(),
+            &vesting.pubkey(),
+            Rent::default().minimum_balance(VestingVault::LEN),
+            VestingVault::LEN as u64,
+            &token_market::id(),
+        ),
+        create_account(
+            &config.fee_payer.pubkey(),
+            &vault.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        owner_or_delegate(
+            config,
+            &market,
+            &token_market,
+            instruction::create_vesting_vault(
+                &token_market::id(),
+                &config.owner.pubkey(),
+                &market,
+                &vesting.pubkey(),
+                &vault.pubkey(),
+                &token_market.emitter_mint,
+                &beneficiary,
+                EmittedAmount(total),
+                start,
+                cliff,
+                duration,
+                revocable,
+            )?,
+        ),
+    ];
+    send(
+        config,
+        instructions,
+        &[config.owner.as_ref(), &vesting, &vault],
+    )?;
 
-            create_market(config, acceptable)
-        }
-        ("buy-tokens", Some(args)) => {
-            let market = pubkey_of(args, "market").unwrap();
-            let recipient = pubkey_of(args, "recipient").unwrap();
-            let mv = matches.value_of("amount");
-            dbg!(market, recipient, mv);
-            let amount = value_t!(matches.value_of("amount"), u64)
-                .expect("Can't parse amount, it is must present like integer");
+    config.output.done(message!(
+        "vesting.created",
+        vesting = vesting.pubkey(),
+        vault = vault.pubkey(),
+        beneficiary = beneficiary,
+    ));
+    Ok(())
+}
 
-            buy_tokens(config, market, recipient, amount)
-        }
+fn claim_vesting(config: &Config, vesting: Pubkey) -> Result<()> {
+    config.output.progress(message!("vesting.claiming"));
+
+    let vesting_data = config.rpc_client.get_account_data(&vesting)?;
+    let vesting_vault = VestingVault::try_from_slice(vesting_data.as_slice())?;
+    let market_data = config.rpc_client.get_account_data(&vesting_vault.market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+
+    let mut instructions = vec![];
+    let destination = emitter_account(
+        config,
+        &mut instructions,
+        &config.owner.pubkey(),
+        &token_market.emitter_mint,
+    )?;
+    instructions.push(instruction::claim_vested(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &vesting,
+        &vesting_vault.vault,
+        &destination,
+    )?);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    config
+        .output
+        .done(message!("vesting.claimed", destination = destination));
+    Ok(())
+}
+
+fn revoke_vesting(config: &Config, vesting: Pubkey) -> Result<()> {
+    config.output.progress(message!("vesting.revoking"));
+
+    let vesting_data = config.rpc_client.get_account_data(&vesting)?;
+    let vesting_vault = VestingVault::try_from_slice(vesting_data.as_slice())?;
+    let market_data = config.rpc_client.get_account_data(&vesting_vault.market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+
+    let mut instructions = vec![];
+    let destination = emitter_account(
+        config,
+        &mut instructions,
+        &config.owner.pubkey(),
+        &token_market.emitter_mint,
+    )?;
+    instructions.push(instruction::revoke_vesting(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &vesting_vault.market,
+        &vesting,
+        &vesting_vault.vault,
+        &destination,
+    )?);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    config
+        .output
+        .done(message!("vesting.revoked", destination = destination));
+    Ok(())
+}
+
+/// Vestings claimed per `crank-vesting` transaction, so that it still fits
+/// when the associated token account of every beneficiary is created in it
+const VESTINGS_PER_CRANK: usize = 5;
+
+/// Claim every vesting of `market` with tokens to release on behalf of its
+/// beneficiary, creating the associated token accounts that are missing
+fn crank_vesting(config: &Config, market: Pubkey) -> Result<()> {
+    config.output.progress(message!("vesting.cranking"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let of_market = filters::vestings_of(&market);
+    let accounts = config.rpc_client.retrying(|client| {
+        client.get_program_accounts_with_config(
+            &token_market::id(),
+            RpcProgramAccountsConfig {
+                filters: Some(vec![
+                    RpcFilterType::DataSize(filters::VESTING_DATA_SIZE),
+                    RpcFilterType::Memcmp(Memcmp {
+                        offset: of_market.offset,
+                        bytes: MemcmpEncodedBytes::Binary(of_market.encoded_bytes()),
+                        encoding: None,
+                    }),
+                ]),
+                account_config: RpcAccountInfoConfig::default(),
+            },
+        )
+    })?;
+
+    // The program skips vestings with nothing to claim, they are only left
+    // out here to spare transactions
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let claimable: Vec<(Pubkey, VestingVault)> = accounts
+        .into_iter()
+        .filter_map(|(address, account)| {
+            let vesting = VestingVault::try_from_slice(&account.data).ok()?;
+            (vesting.is_initialized && vesting.vested_amount(now) > vesting.released)
+                .then(|| (address, vesting))
+        })
+        .collect();
+
+    let mut cranked = 0;
+    for batch in claimable.chunks(VESTINGS_PER_CRANK) {
+        let mut instructions = vec![];
+        let mut claims = vec![];
+        for (vesting, vesting_vault) in batch {
+            // A beneficiary of several vestings gets its account created once
+            let address = find_associated_token_address(
+                &vesting_vault.beneficiary,
+                &token_market.emitter_mint,
+            );
+            let destination = if claims
+                .iter()
+                .any(|claim: &VestingClaimAccounts| claim.destination == address)
+            {
+                address
+            } else {
+                emitter_account(
+                    config,
+                    &mut instructions,
+                    &vesting_vault.beneficiary,
+                    &token_market.emitter_mint,
+                )?
+            };
+            claims.push(VestingClaimAccounts {
+                vesting: *vesting,
+                vault: vesting_vault.vault,
+                destination,
+            });
+        }
+        instructions.push(instruction::crank_vesting(&token_market::id(), &claims)?);
+        let signature = send(config, &instructions, &[])?;
+
+        cranked += batch.len();
+        config.output.progress(message!(
+            "vesting.crank_sent",
+            cranked = cranked,
+            total = claimable.len(),
+            signature = signature,
+        ));
+    }
+
+    config
+        .output
+        .done(message!("vesting.cranked", count = cranked));
+    Ok(())
+}
+
+/// Close instructions per `garbage-collect` transaction, so that it still
+/// fits with a receipt mint and buyer of its own for every one
+const CLOSES_PER_TRANSACTION: usize = 8;
+
+/// Accounts of `market` of `data_size` decoding as `T`, with their lamports
+fn market_records<T: BorshDeserialize>(
+    config: &Config,
+    market: &Pubkey,
+    data_size: u64,
+) -> Result<Vec<(Pubkey, u64, T)>> {
+    let of_market = filters::records_of(market);
+    let accounts = config.rpc_client.retrying(|client| {
+        client.get_program_accounts_with_config(
+            &token_market::id(),
+            RpcProgramAccountsConfig {
+                filters: Some(vec![
+                    RpcFilterType::DataSize(data_size),
+                    RpcFilterType::Memcmp(Memcmp {
+                        offset: of_market.offset,
+                        bytes: MemcmpEncodedBytes::Binary(of_market.encoded_bytes()),
+                        encoding: None,
+                    }),
+                ]),
+                account_config: RpcAccountInfoConfig::default(),
+            },
+        )
+    })?;
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(address, account)| {
+            T::try_from_slice(&account.data)
+                .ok()
+                .map(|record| (address, account.lamports, record))
+        })
+        .collect())
+}
+
+/// Mints of the token accounts `owner` holds
+fn held_mints(config: &Config, owner: &Pubkey) -> Result<Vec<Pubkey>> {
+    let accounts = config.rpc_client.retrying(|client| {
+        client.get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(spl_token::id()))
+    })?;
+    Ok(accounts
+        .into_iter()
+        .filter_map(|keyed| match keyed.account.data {
+            UiAccountData::Json(account) => account.parsed["info"]["mint"].as_str()?.parse().ok(),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Close the buyer records of `market` once its sale is over for good, and
+/// its purchase receipts once disputes are over as well, returning the rent
+/// to the buyers
+fn garbage_collect(config: &Config, market: Pubkey) -> Result<()> {
+    config.output.progress(message!("garbage.collecting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    if !token_market.sale_finalized(now) {
+        bail!(
+            "The sale of market {} isn't over for good, its records are still needed",
+            market
+        );
+    }
+
+    let program_id = token_market::id();
+    let (mut closes, mut lamports) = (vec![], 0);
+    let records: Vec<(Pubkey, u64, BuyerRecord)> =
+        market_records(config, &market, filters::BUYER_RECORD_DATA_SIZE)?;
+    // accounts of other kinds may have the same size and start
+    for (address, rent, record) in &records {
+        if *address == find_buyer_record_address(&program_id, &market, &record.buyer).0 {
+            closes.push(instruction::close_buyer_record(
+                &program_id,
+                &market,
+                &record.buyer,
+            ));
+            lamports += rent;
+        }
+    }
+    let record_count = closes.len();
+
+    let (mut receipt_count, mut skipped) = (0, 0);
+    let disputes_over = token_market
+        .sale_end
+        .checked_add(token_market.dispute_window)
+        .map_or(false, |end| now >= end);
+    if disputes_over {
+        let receipts: Vec<(Pubkey, u64, PurchaseReceipt)> =
+            market_records(config, &market, filters::RECEIPT_DATA_SIZE)?;
+        // a receipt is found by its mint, which only the buyer's holding names
+        let mut mints = HashMap::new();
+        for (address, rent, receipt) in &receipts {
+            if !mints.contains_key(&receipt.buyer) {
+                mints.insert(receipt.buyer, held_mints(config, &receipt.buyer)?);
+            }
+            match mints[&receipt.buyer]
+                .iter()
+                .find(|mint| find_receipt_address(&program_id, mint).0 == *address)
+            {
+                Some(mint) => {
+                    closes.push(instruction::close_receipt(
+                        &program_id,
+                        &market,
+                        mint,
+                        &receipt.buyer,
+                    ));
+                    receipt_count += 1;
+                    lamports += rent;
+                }
+                None => skipped += 1,
+            }
+        }
+    }
+
+    for batch in closes.chunks(CLOSES_PER_TRANSACTION) {
+        send(config, batch, &[])?;
+    }
+
+    if skipped > 0 {
+        config
+            .output
+            .warning(message!("garbage.skipped", count = skipped));
+    }
+    config.output.done(message!(
+        "garbage.collected",
+        records = record_count,
+        receipts = receipt_count,
+        sol = lamports_to_sol(lamports),
+    ));
+    Ok(())
+}
+
+fn set_voucher_signer(config: &Config, market: Pubkey, eth_address: [u8; 20]) -> Result<()> {
+    config.output.progress(message!("voucher_signer.setting"));
+
+    let instructions = &[instruction::set_voucher_signer(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        eth_address,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "voucher_signer.set",
+        market = market,
+        signer = hex::encode(eth_address),
+    ));
+    Ok(())
+}
+
+fn sign_voucher(
+    config: &Config,
+    key: &Path,
+    market: Pubkey,
+    buyer: Pubkey,
+    max_amount: UiAmount,
+    expiry: i64,
+) -> Result<()> {
+    let key = voucher::load_key(key)?;
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let eth_address = voucher::eth_address(&key);
+    if token_market.voucher_signer != eth_address {
+        bail!(
+            "Market {} doesn't accept vouchers signed by 0x{}",
+            market,
+            hex::encode(eth_address)
+        );
+    }
+
+    let voucher = PurchaseVoucher {
+        market,
+        buyer,
+        max_amount: to_raw(config, &max_amount, &token_market.mint_of_acceptable)?,
+        expiry,
+    };
+    println!("{}", voucher::sign(&key, &voucher)?);
+    Ok(())
+}
+
+/// Print the owner's attestation that `market` is listed on `domain`
+fn sign_ownership_proof(config: &Config, market: &Pubkey, domain: &str) -> Result<()> {
+    let market_data = config.rpc_client.get_account_data(market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    if token_market.owner != config.owner.pubkey() {
+        bail!(
+            "Market {} is owned by {}, not {}",
+            market,
+            token_market.owner,
+            config.owner.pubkey()
+        );
+    }
+    let attestation = OwnershipAttestation::sign(config.owner.as_ref(), market, domain)?;
+    println!("{}", serde_json::to_string_pretty(&attestation)?);
+    Ok(())
+}
+
+fn serve_quotes(
+    config: &Config,
+    key: &Path,
+    market: Pubkey,
+    prices: PriceSource,
+    max_amount: UiAmount,
+    ttl: i64,
+    bind: &str,
+) -> Result<()> {
+    let key = voucher::load_key(key)?;
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let eth_address = voucher::eth_address(&key);
+    if token_market.voucher_signer != eth_address {
+        bail!(
+            "Market {} doesn't accept quotes signed by 0x{}",
+            market,
+            hex::encode(eth_address)
+        );
+    }
+    if ttl <= 0 {
+        bail!("Quotes must stay valid for a positive number of seconds");
+    }
+
+    QuoteServer {
+        key,
+        market,
+        prices,
+        max_amount: to_raw(config, &max_amount, &token_market.mint_of_acceptable)?,
+        ttl,
+    }
+    .serve(bind)
+}
+
+fn migrate_acceptable(config: &Config, market: Pubkey, new_acceptable: Pubkey) -> Result<()> {
+    config.output.progress(message!("acceptable.migrating"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let bank = Keypair::new();
+
+    let instructions = &[
+        create_account(
+            &config.fee_payer.pubkey(),
+            &bank.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        instruction::migrate_acceptable_mint(
+            &token_market::id(),
+            &config.owner.pubkey(),
+            &market,
+            &bank.pubkey(),
+            &new_acceptable,
+            &token_market.emitter_mint,
+        )?,
+    ];
+    send(config, instructions, &[config.owner.as_ref(), &bank])?;
+
+    config.output.done(message!(
+        "acceptable.migrated",
+        market = market,
+        acceptable = new_acceptable,
+        bank = bank.pubkey(),
+        legacy_bank = token_market.bank,
+    ));
+    Ok(())
+}
+
+fn withdraw_bank(
+    config: &Config,
+    market: Pubkey,
+    amount: UiAmount,
+    destination: Option<Pubkey>,
+) -> Result<()> {
+    config.output.progress(message!("bank.withdrawing"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let amount = to_raw(config, &amount, &token_market.mint_of_acceptable)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    if amount > token_market.withdrawable(now) {
+        bail!(
+            "The withdraw stream of market {} released {} so far",
+            market,
+            token_market.withdrawable(now)
+        );
+    }
+
+    let mut instructions = vec![];
+    let destination = match destination {
+        Some(destination) => destination,
+        None if token_market.has_treasury() => token_market.treasury,
+        None => emitter_account(
+            config,
+            &mut instructions,
+            &config.owner.pubkey(),
+            &token_market.mint_of_acceptable,
+        )?,
+    };
+    if token_market.has_treasury() && destination != token_market.treasury {
+        bail!(
+            "Market {} only pays out to its treasury {}",
+            market,
+            token_market.treasury
+        );
+    }
+    instructions.push(instruction::withdraw_bank(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        &token_market.bank,
+        &destination,
+        AcceptableAmount(amount),
+    )?);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "bank.withdrawn",
+        amount = amount,
+        destination = destination,
+    ));
+    Ok(())
+}
+
+fn withdraw_fees(
+    config: &Config,
+    market: Pubkey,
+    amount: UiAmount,
+    destination: Option<Pubkey>,
+) -> Result<()> {
+    config.output.progress(message!("fees.withdrawing"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    if token_market.fee_vault == Pubkey::default() {
+        bail!("Market {} has no fee vault", market);
+    }
+    // Fees collected before a migration stay in the vault of the old mint
+    let vault = Account::unpack(
+        &config
+            .rpc_client
+            .get_account_data(&token_market.fee_vault)?,
+    )?;
+    let amount = to_raw(config, &amount, &vault.mint)?;
+
+    let mut instructions = vec![];
+    let destination = match destination {
+        Some(destination) => destination,
+        None if token_market.has_treasury() => token_market.treasury,
+        None => emitter_account(
+            config,
+            &mut instructions,
+            &config.owner.pubkey(),
+            &vault.mint,
+        )?,
+    };
+    instructions.push(instruction::withdraw_fees(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        &vault.mint,
+        &destination,
+        AcceptableAmount(amount),
+    )?);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "fees.withdrawn",
+        amount = amount,
+        destination = destination,
+    ));
+    Ok(())
+}
+
+fn set_protocol_fee(config: &Config, market: Pubkey, fee_bps: u16) -> Result<()> {
+    config.output.progress(message!("fees.setting"));
+
+    if fee_bps > TokenMarket::MAX_FEE_BPS {
+        bail!(
+            "Protocol fee must be at most {} basis points",
+            TokenMarket::MAX_FEE_BPS
+        );
+    }
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let instructions = &[instruction::set_protocol_fee(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &config.fee_payer.pubkey(),
+        &market,
+        &token_market.mint_of_acceptable,
+        fee_bps,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "fees.set",
+        market = market,
+        fee_bps = fee_bps,
+        fee_vault = find_fee_vault_address(
+            &token_market::id(),
+            &market,
+            &token_market.mint_of_acceptable
+        )
+        .0,
+    ));
+    Ok(())
+}
+
+fn pause(config: &Config, market: Pubkey) -> Result<()> {
+    config.output.progress(message!("market.pausing"));
+
+    let instructions = &[instruction::pause(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config
+        .output
+        .done(message!("market.paused", market = market));
+    Ok(())
+}
+
+fn resume(config: &Config, market: Pubkey) -> Result<()> {
+    config.output.progress(message!("market.resuming"));
+
+    let instructions = &[instruction::resume(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config
+        .output
+        .done(message!("market.resumed", market = market));
+    Ok(())
+}
+
+/// Phases of `phases` with their amounts in raw emitted tokens of `market`
+fn sale_phases(
+    config: &Config,
+    market: &TokenMarket,
+    phases: &[PhaseArg],
+) -> Result<Vec<SalePhase>> {
+    let raw = |amount: &Option<UiAmount>| match amount {
+        Some(amount) => to_raw(config, amount, &market.emitter_mint),
+        None => Ok(0),
+    };
+    phases
+        .iter()
+        .map(|phase| {
+            Ok(SalePhase {
+                start: phase.start,
+                price_numerator: phase.price.numerator,
+                price_denominator: phase.price.denominator,
+                cap: raw(&phase.cap)?,
+                max_purchase: raw(&phase.max_purchase)?,
+                allowlist_only: phase.allowlist_only,
+            })
+        })
+        .collect()
+}
+
+fn set_phases(
+    config: &Config,
+    market: Pubkey,
+    phases: &[PhaseArg],
+    end: Option<i64>,
+) -> Result<()> {
+    config.output.progress(message!("phases.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let phases = sale_phases(config, &token_market, phases)?;
+    let end = end.unwrap_or_default();
+    if !SalePhases::is_valid_schedule(&phases, end) {
+        bail!(
+            "Phases must open in order, at most {} of them, and the sale end after the last one",
+            SalePhases::MAX_PHASES
+        );
+    }
+    let instructions = &[instruction::set_sale_phases(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &config.fee_payer.pubkey(),
+        &market,
+        phases.clone(),
+        end,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "phases.set",
+        market = market,
+        count = phases.len(),
+    ));
+    Ok(())
+}
+
+fn advance_phase(config: &Config, market: Pubkey) -> Result<()> {
+    config.output.progress(message!("phases.advancing"));
+
+    let instructions = &[instruction::advance_sale_phase(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    let address = find_sale_phases_address(&token_market::id(), &market).0;
+    let phases = SalePhases::load(&config.rpc_client.get_account_data(&address)?)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    match phases.active(now) {
+        Some(phase) => config.output.done(message!(
+            "phases.opened",
+            market = market,
+            phase = phase + 1,
+        )),
+        None => config
+            .output
+            .done(message!("phases.closed", market = market)),
+    }
+    Ok(())
+}
+
+fn set_treasury(config: &Config, market: Pubkey, treasury: Pubkey) -> Result<()> {
+    config.output.progress(message!("treasury.setting"));
+
+    let instructions = &[instruction::set_treasury(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        &treasury,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "treasury.set",
+        market = market,
+        treasury = treasury
+    ));
+    Ok(())
+}
+
+fn recreate_bank(config: &Config, market: Pubkey) -> Result<()> {
+    config.output.progress(message!("bank.recreating"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let instructions = &[instruction::recreate_bank(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &config.fee_payer.pubkey(),
+        &market,
+        &token_market.bank,
+        &token_market.mint_of_acceptable,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "bank.recreated",
+        market = market,
+        bank = find_bank_address(&token_market::id(), &market).0,
+    ));
+    Ok(())
+}
+
+/// Wormhole emitter address from hex, an EVM contract address of 20 bytes
+/// is left-padded with zeros the way Wormhole does
+fn parse_wormhole_emitter(emitter: &str) -> Result<[u8; 32]> {
+    let bytes = hex::decode(emitter.trim_start_matches("0x"))
+        .with_context(|| format!("Invalid Wormhole emitter {}", emitter))?;
+    if bytes.len() != 20 && bytes.len() != 32 {
+        bail!("Wormhole emitter {} must be 20 or 32 bytes", emitter);
+    }
+    let mut address = [0; 32];
+    address[32 - bytes.len()..].copy_from_slice(&bytes);
+    Ok(address)
+}
+
+fn set_wormhole_emitter(
+    config: &Config,
+    market: Pubkey,
+    chain: u16,
+    emitter: [u8; 32],
+) -> Result<()> {
+    config.output.progress(message!("wormhole.registering"));
+
+    let instructions = &[instruction::set_wormhole_emitter(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        chain,
+        emitter,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(if emitter == [0; 32] {
+        message!("wormhole.disabled", market = market)
+    } else {
+        message!(
+            "wormhole.registered",
+            market = market,
+            chain = chain,
+            emitter = hex::encode(emitter),
+        )
+    });
+    Ok(())
+}
+
+/// Mint the tokens of a purchase paid on another chain, from the message
+/// the Wormhole core bridge posted at `posted_vaa`
+fn redeem_wormhole(config: &Config, posted_vaa: Pubkey) -> Result<()> {
+    config.output.progress(message!("wormhole.redeeming"));
+
+    let vaa = PostedVaa::unpack(&config.rpc_client.get_account_data(&posted_vaa)?)?;
+    let payload = PurchasePayload::unpack(&vaa.payload)
+        .ok_or_else(|| anyhow!("Wormhole message {} is not a purchase", posted_vaa))?;
+    let market_data = config.rpc_client.get_account_data(&payload.market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+
+    let mut instructions = vec![];
+    let recipient = emitter_account(
+        config,
+        &mut instructions,
+        &payload.recipient,
+        &token_market.emitter_mint,
+    )?;
+    let mut redeem = instruction::redeem_wormhole_purchase(
+        &token_market::id(),
+        &payload.market,
+        &token_market.emitter_mint,
+        &recipient,
+        &posted_vaa,
+        &vaa,
+        &config.fee_payer.pubkey(),
+    )?;
+    // the fee payer pays the protocol fee from its own acceptable tokens
+    if token_market.fee_bps > 0 {
+        redeem = instruction::with_external_protocol_fee(
+            &token_market::id(),
+            redeem,
+            &token_market.fee_vault,
+            &token_market.owner,
+            &config.fee_payer.pubkey(),
+            &token_market.mint_of_acceptable,
+        );
+    }
+    instructions.push(redeem);
+    send(config, &instructions, &[])?;
+
+    config.output.done(message!(
+        "wormhole.redeemed",
+        amount = payload.amount,
+        recipient = payload.recipient,
+        sequence = vaa.sequence,
+    ));
+    Ok(())
+}
+
+fn set_onramp_signer(config: &Config, market: Pubkey, signer: Pubkey) -> Result<()> {
+    config.output.progress(message!("onramp.setting"));
+
+    let instructions = &[instruction::set_onramp_signer(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        &signer,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(if signer == Pubkey::default() {
+        message!("onramp.disabled", market = market)
+    } else {
+        message!("onramp.set", market = market, signer = signer)
+    });
+    Ok(())
+}
+
+/// Mint tokens for a card purchase the on-ramp provider settled off-chain,
+/// the provider's key attests the payment identified by `reference`
+fn buy_with_fiat(
+    config: &Config,
+    onramp_signer: &dyn MarketSigner,
+    market: Pubkey,
+    recipient: Pubkey,
+    amount: UiAmount,
+    reference: &str,
+) -> Result<()> {
+    config.output.progress(message!("fiat_purchase.buying"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let amount = to_raw(config, &amount, &token_market.mint_of_acceptable)?;
+
+    let mut instructions = vec![];
+    let recipient_acc = emitter_account(
+        config,
+        &mut instructions,
+        &recipient,
+        &token_market.emitter_mint,
+    )?;
+    let mut buy_tokens = instruction::buy_tokens_with_fiat(
+        &token_market::id(),
+        &onramp_signer.pubkey(),
+        &market,
+        &token_market.emitter_mint,
+        &recipient_acc,
+        &config.fee_payer.pubkey(),
+        AcceptableAmount(amount),
+        hash(reference.as_bytes()).to_bytes(),
+    )?;
+    // the provider pays the protocol fee from its own acceptable tokens
+    if token_market.fee_bps > 0 {
+        buy_tokens = instruction::with_external_protocol_fee(
+            &token_market::id(),
+            buy_tokens,
+            &token_market.fee_vault,
+            &token_market.owner,
+            &onramp_signer.pubkey(),
+            &token_market.mint_of_acceptable,
+        );
+    }
+    instructions.push(buy_tokens);
+    send(config, &instructions, &[onramp_signer])?;
+
+    config.output.done(message!(
+        "fiat_purchase.done",
+        amount = amount,
+        recipient = recipient,
+        reference = reference,
+    ));
+    Ok(())
+}
+
+fn set_dispute_escrow(
+    config: &Config,
+    market: Pubkey,
+    threshold: UiAmount,
+    window: Duration,
+    guardian: Option<Pubkey>,
+) -> Result<()> {
+    config.output.progress(message!("escrow.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let threshold = to_raw(config, &threshold, &token_market.emitter_mint)?;
+    let hours = window.as_secs_f64() / (60 * 60) as f64;
+    let window = seconds_of(window)?;
+    let guardian = guardian.unwrap_or_default();
+
+    let instructions = &[instruction::set_dispute_escrow(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        EmittedAmount(threshold),
+        window,
+        &guardian,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(if threshold == 0 {
+        message!("escrow.disabled", market = market)
+    } else {
+        message!(
+            "escrow.set",
+            market = market,
+            threshold = threshold,
+            hours = hours,
+        )
+    });
+    Ok(())
+}
+
+/// Buy tokens held in a new gift and print the claim that opens it
+fn buy_gift(
+    config: &Config,
+    market: Pubkey,
+    amount: UiAmount,
+    valid_for: Duration,
+    memo: Option<String>,
+) -> Result<()> {
+    config.output.progress(message!("gift.buying"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let amount = to_raw(config, &amount, &token_market.mint_of_acceptable)?;
+    if token_market.require_memo && memo.is_none() {
+        bail!("Market {} requires a memo, pass --memo", market);
+    }
+    screen_all(config.screener.as_deref(), &[&config.owner.pubkey()])?;
+
+    let write_off_account = spl_associated_token_account::get_associated_token_address(
+        &config.owner.pubkey(),
+        &token_market.mint_of_acceptable,
+    );
+    let claim = GiftClaim::generate(market);
+    let expiry =
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64 + seconds_of(valid_for)?;
+
+    let mut transaction = TransactionBuilder::new(&config.fee_payer.pubkey());
+    transaction
+        .expect_owner(&market, &token_market::id())
+        .expect_token_account(&write_off_account, &token_market.mint_of_acceptable)
+        .expect_rent(
+            Rent::default().minimum_balance(Gift::LEN)
+                + 2 * Rent::default().minimum_balance(Account::LEN),
+        );
+    if let Some(text) = memo {
+        transaction.instruction(memo::memo(&text));
+    }
+    let buy_gift = instruction::buy_gift(
+        &token_market::id(),
+        &market,
+        &token_market.bank,
+        &token_market.emitter_mint,
+        &token_market.mint_of_acceptable,
+        &write_off_account,
+        &config.fee_payer.pubkey(),
+        AcceptableAmount(amount),
+        claim.secret_hash(),
+        expiry,
+    )?;
+    for ix in instruction::bundle_buy_tokens(
+        &token_market::id(),
+        buy_gift,
+        &write_off_account,
+        &config.owner.pubkey(),
+        AcceptableAmount(amount),
+    )? {
+        transaction.instruction(ix);
+    }
+    send_checked(config, &transaction, &[config.owner.as_ref()])?;
+
+    let gift = claim.gift();
+    let gift_data = config.rpc_client.get_account_data(&gift)?;
+    let purchase = Gift::try_from_slice(gift_data.as_slice())?;
+    config.output.done(message!(
+        "gift.bought",
+        amount = purchase.emitted,
+        gift = gift,
+        expiry = purchase.expiry,
+    ));
+    println!("{}", claim);
+    Ok(())
+}
+
+fn claim_gift(config: &Config, claim: &GiftClaim, recipient: Option<Pubkey>) -> Result<()> {
+    config.output.progress(message!("gift.claiming"));
+
+    let gift = claim.gift();
+    let gift_data = config
+        .rpc_client
+        .get_account_data(&gift)
+        .map_err(|_| anyhow!("No gift of market {} opens with this claim", claim.market))?;
+    let purchase = Gift::try_from_slice(gift_data.as_slice())?;
+    if purchase.status != GiftStatus::Pending {
+        bail!("Gift {} was already {:?}", gift, purchase.status);
+    }
+    let market_data = config.rpc_client.get_account_data(&claim.market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let recipient = recipient.unwrap_or_else(|| config.owner.pubkey());
+    screen_all(config.screener.as_deref(), &[&recipient])?;
+
+    let mut instructions = vec![];
+    let recipient_acc = emitter_account(
+        config,
+        &mut instructions,
+        &recipient,
+        &token_market.emitter_mint,
+    )?;
+    let mut claim_gift = instruction::claim_gift(
+        &token_market::id(),
+        &claim.market,
+        &token_market.bank,
+        &recipient_acc,
+        claim.secret,
+    )?;
+    if purchase.fee > 0 {
+        claim_gift = instruction::with_protocol_fee(
+            &token_market::id(),
+            claim_gift,
+            &token_market.fee_vault,
+            &token_market.owner,
+        );
+    }
+    instructions.push(claim_gift);
+    send(config, &instructions, &[])?;
+
+    config.output.done(message!(
+        "gift.claimed",
+        amount = purchase.emitted,
+        recipient = recipient,
+    ));
+    Ok(())
+}
+
+fn refund_gift(config: &Config, gift: Pubkey) -> Result<()> {
+    config.output.progress(message!("gift.refunding"));
+
+    let gift_data = config.rpc_client.get_account_data(&gift)?;
+    let purchase = Gift::try_from_slice(gift_data.as_slice())?;
+    let market_data = config.rpc_client.get_account_data(&purchase.market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let instructions = &[instruction::refund_gift(
+        &token_market::id(),
+        &purchase.market,
+        &gift,
+        &token_market.emitter_mint,
+        &purchase.write_off,
+    )?];
+    send(config, instructions, &[])?;
+
+    config.output.done(message!(
+        "gift.refunded",
+        refund = purchase.paid + purchase.fee,
+        write_off = purchase.write_off,
+    ));
+    Ok(())
+}
+
+/// Issue a coupon of `market` under `code`, which only its hash reaches the chain
+fn create_coupon(
+    config: &Config,
+    market: Pubkey,
+    code: &str,
+    discount_bps: u16,
+    max_uses: u32,
+    expiry: Option<i64>,
+) -> Result<()> {
+    config.output.progress(message!("coupon.creating"));
+
+    let code_hash = coupon_code_hash(&market, code);
+    let instructions = &[instruction::create_coupon(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        &config.fee_payer.pubkey(),
+        code_hash,
+        discount_bps,
+        max_uses,
+        expiry.unwrap_or(0),
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "coupon.created",
+        code = code.trim().to_ascii_uppercase(),
+        coupon = find_coupon_address(&token_market::id(), &market, &code_hash).0,
+        discount_bps = discount_bps,
+        uses = max_uses,
+    ));
+    Ok(())
+}
+
+/// Buy tokens for `recipient` that wait in a new escrow until the dispute
+/// window of the market passes
+fn buy_escrowed(
+    config: &Config,
+    market: Pubkey,
+    recipient: Pubkey,
+    amount: UiAmount,
+    memo: Option<String>,
+) -> Result<()> {
+    config.output.progress(message!("escrow.buying"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let amount = to_raw(config, &amount, &token_market.mint_of_acceptable)?;
+    if token_market.require_memo && memo.is_none() {
+        bail!("Market {} requires a memo, pass --memo", market);
+    }
+    screen_all(
+        config.screener.as_deref(),
+        &[&config.owner.pubkey(), &recipient],
+    )?;
+
+    let write_off_account = spl_associated_token_account::get_associated_token_address(
+        &config.owner.pubkey(),
+        &token_market.mint_of_acceptable,
+    );
+    // any value unused by the write-off account will do, the time is one
+    let nonce = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let escrow = find_escrow_address(&token_market::id(), &market, &write_off_account, nonce).0;
+
+    let mut instructions = vec![];
+    let recipient_acc = emitter_account(
+        config,
+        &mut instructions,
+        &recipient,
+        &token_market.emitter_mint,
+    )?;
+    if let Some(text) = memo {
+        instructions.push(memo::memo(&text));
+    }
+    let buy_tokens = instruction::buy_tokens_escrowed(
+        &token_market::id(),
+        &market,
+        &token_market.bank,
+        &token_market.emitter_mint,
+        &token_market.mint_of_acceptable,
+        &recipient_acc,
+        &write_off_account,
+        &config.fee_payer.pubkey(),
+        AcceptableAmount(amount),
+        nonce,
+    )?;
+    instructions.extend(instruction::bundle_buy_tokens(
+        &token_market::id(),
+        buy_tokens,
+        &write_off_account,
+        &config.owner.pubkey(),
+        AcceptableAmount(amount),
+    )?);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    let escrow_data = config.rpc_client.get_account_data(&escrow)?;
+    let purchase = PurchaseEscrow::try_from_slice(escrow_data.as_slice())?;
+    config.output.done(message!(
+        "escrow.bought",
+        amount = purchase.emitted,
+        escrow = escrow,
+        release_after = purchase.release_after,
+    ));
+    Ok(())
+}
+
+/// Recipients paid for by one `BuyTokensMulti` transaction, which lists
+/// their token accounts next to the approval and the purchase accounts
+const RECIPIENTS_PER_TRANSACTION: usize = 16;
+
+/// Buy tokens for every wallet of `purchases`, the amount next to it paying,
+/// creating the recipient token accounts missing
+fn buy_multi(
+    config: &Config,
+    market: Pubkey,
+    purchases: &[(Pubkey, UiAmount)],
+    memo: Option<String>,
+) -> Result<()> {
+    config
+        .output
+        .progress(message!("batch_purchase.buying", count = purchases.len(),));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    if token_market.paused {
+        bail!("Market {} is paused by its owner", market);
+    }
+    if token_market.require_memo && memo.is_none() {
+        bail!("Market {} requires a memo, pass --memo", market);
+    }
+    if token_market.phased
+        || token_market.counts_sales()
+        || token_market.caps_buyers()
+        || token_market.allowlist_enabled
+    {
+        bail!(
+            "Market {} takes batch purchases for plain sales only, use buy-tokens",
+            market
+        );
+    }
+    let mut raw = vec![];
+    for (wallet, amount) in purchases {
+        let amount = to_raw(config, amount, &token_market.mint_of_acceptable)?;
+        if token_market.check_purchase(amount).is_err() || token_market.escrows(amount) {
+            bail!(
+                "Market {} doesn't take a batch purchase of {} for {}",
+                market,
+                amount,
+                wallet
+            );
+        }
+        raw.push((*wallet, amount));
+    }
+    let owner = config.owner.pubkey();
+    let mut screened = vec![&owner];
+    screened.extend(purchases.iter().map(|(wallet, _)| wallet));
+    screen_all(config.screener.as_deref(), &screened)?;
+
+    let write_off_account = spl_associated_token_account::get_associated_token_address(
+        &owner,
+        &token_market.mint_of_acceptable,
+    );
+    let (mut bought, mut transactions) = (0u64, 0);
+    for chunk in raw.chunks(RECIPIENTS_PER_TRANSACTION) {
+        // missing recipient accounts are created ahead, they don't fit with the purchase
+        let mut creations = vec![];
+        let mut chunk_purchases = vec![];
+        for (wallet, amount) in chunk {
+            let recipient_acc =
+                emitter_account(config, &mut creations, wallet, &token_market.emitter_mint)?;
+            chunk_purchases.push((recipient_acc, AcceptableAmount(*amount)));
+        }
+        for creations in creations.chunks(RECIPIENTS_PER_TRANSACTION / 2) {
+            send(config, creations, &[])?;
+        }
+
+        let total = chunk
+            .iter()
+            .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+            .ok_or_else(|| anyhow!("Amounts overflow"))?;
+        let mut instructions = vec![];
+        let mut buy_tokens = instruction::buy_tokens_multi(
+            &token_market::id(),
+            &market,
+            &token_market.bank,
+            &token_market.emitter_mint,
+            &write_off_account,
+            &chunk_purchases,
+        )?;
+        if token_market.fee_bps > 0 {
+            buy_tokens = instruction::with_protocol_fee(
+                &token_market::id(),
+                buy_tokens,
+                &token_market.fee_vault,
+                &token_market.owner,
+            );
+        }
+        if let Some(text) = &memo {
+            buy_tokens = instruction::with_instructions_sysvar(buy_tokens);
+            instructions.push(memo::memo(text));
+        }
+        instructions.extend(instruction::bundle_buy_tokens(
+            &token_market::id(),
+            buy_tokens,
+            &write_off_account,
+            &config.owner.pubkey(),
+            AcceptableAmount(total),
+        )?);
+        send(config, &instructions, &[config.owner.as_ref()])?;
+        bought += total;
+        transactions += 1;
+    }
+
+    config.output.done(message!(
+        "batch_purchase.done",
+        amount = bought,
+        count = purchases.len(),
+        transactions = transactions,
+    ));
+    Ok(())
+}
+
+/// Buy exactly `amount` emitted tokens for `recipient`, at most `max_in`
+/// paid tokens being delegated for them and the rest refunded
+fn buy_exact_out(
+    config: &Config,
+    market: Pubkey,
+    recipient: Pubkey,
+    amount: UiAmount,
+    max_in: UiAmount,
+    memo: Option<String>,
+) -> Result<()> {
+    config.output.progress(message!("purchase.buying"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    if token_market.paused {
+        bail!("Market {} is paused by its owner", market);
+    }
+    if token_market.require_memo && memo.is_none() {
+        bail!("Market {} requires a memo, pass --memo", market);
+    }
+    if token_market.phased || token_market.caps_buyers() || token_market.allowlist_enabled {
+        bail!(
+            "Market {} takes exact output purchases for open sales only, use buy-tokens",
+            market
+        );
+    }
+    let out_amount = to_raw(config, &amount, &token_market.emitter_mint)?;
+    let max_in = to_raw(config, &max_in, &token_market.mint_of_acceptable)?;
+    // the price may still move before the purchase lands, the program checks again
+    let cost = token_market
+        .cost_of(out_amount)
+        .ok_or_else(|| anyhow!("Market {} can't sell {} tokens", market, out_amount))?;
+    if cost > max_in {
+        bail!(
+            "Buying {} tokens costs {} now, more than --max-in {}",
+            out_amount,
+            cost,
+            max_in
+        );
+    }
+    if token_market.escrows(out_amount) {
+        bail!(
+            "Market {} holds purchases of {} or more in escrow, use buy-escrowed",
+            market,
+            token_market.escrow_threshold
+        );
+    }
+    screen_all(
+        config.screener.as_deref(),
+        &[&config.owner.pubkey(), &recipient],
+    )?;
+
+    let mut instructions = vec![];
+    let recipient_acc = emitter_account(
+        config,
+        &mut instructions,
+        &recipient,
+        &token_market.emitter_mint,
+    )?;
+    let write_off_account = spl_associated_token_account::get_associated_token_address(
+        &config.owner.pubkey(),
+        &token_market.mint_of_acceptable,
+    );
+    let mut buy_tokens = instruction::buy_tokens_exact_out(
+        &token_market::id(),
+        &market,
+        &token_market.bank,
+        &token_market.emitter_mint,
+        &recipient_acc,
+        &write_off_account,
+        EmittedAmount(out_amount),
+        AcceptableAmount(max_in),
+    )?;
+    if token_market.fee_bps > 0 {
+        buy_tokens = instruction::with_protocol_fee(
+            &token_market::id(),
+            buy_tokens,
+            &token_market.fee_vault,
+            &token_market.owner,
+        );
+    }
+    if let Some(text) = memo {
+        buy_tokens = instruction::with_instructions_sysvar(buy_tokens);
+        instructions.push(memo::memo(&text));
+    }
+    instructions.extend(instruction::bundle_buy_tokens(
+        &token_market::id(),
+        buy_tokens,
+        &write_off_account,
+        &config.owner.pubkey(),
+        AcceptableAmount(max_in),
+    )?);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "purchase.done",
+        amount = out_amount,
+        recipient = recipient,
+        account = recipient_acc,
+    ));
+    Ok(())
+}
+
+fn release_escrow(config: &Config, escrow: Pubkey) -> Result<()> {
+    config.output.progress(message!("escrow.releasing"));
+
+    let escrow_data = config.rpc_client.get_account_data(&escrow)?;
+    let purchase = PurchaseEscrow::try_from_slice(escrow_data.as_slice())?;
+    let market_data = config.rpc_client.get_account_data(&purchase.market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let mut release = instruction::release_escrow(
+        &token_market::id(),
+        &purchase.market,
+        &token_market.bank,
+        &escrow,
+        &purchase.recipient,
+    )?;
+    if purchase.fee > 0 {
+        release = instruction::with_protocol_fee(
+            &token_market::id(),
+            release,
+            &token_market.fee_vault,
+            &token_market.owner,
+        );
+    }
+    send(config, &[release], &[])?;
+
+    config.output.done(message!(
+        "escrow.released",
+        amount = purchase.emitted,
+        recipient = purchase.recipient,
+    ));
+    Ok(())
+}
+
+fn dispute_escrow(config: &Config, escrow: Pubkey) -> Result<()> {
+    config.output.progress(message!("escrow.disputing"));
+
+    let escrow_data = config.rpc_client.get_account_data(&escrow)?;
+    let purchase = PurchaseEscrow::try_from_slice(escrow_data.as_slice())?;
+    let market_data = config.rpc_client.get_account_data(&purchase.market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let instructions = &[instruction::dispute_escrow(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &purchase.market,
+        &escrow,
+        &token_market.emitter_mint,
+        &purchase.write_off,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "escrow.disputed",
+        refund = purchase.paid + purchase.fee,
+        write_off = purchase.write_off,
+    ));
+    Ok(())
+}
+
+fn set_overpayment_policy(
+    config: &Config,
+    market: Pubkey,
+    policy: OverpaymentPolicy,
+) -> Result<()> {
+    config
+        .output
+        .progress(message!("overpayment_policy.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let instructions = &[owner_or_delegate(
+        config,
+        &market,
+        &token_market,
+        instruction::set_overpayment_policy(
+            &token_market::id(),
+            &config.owner.pubkey(),
+            &market,
+            policy,
+        )?,
+    )];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "overpayment_policy.set",
+        market = market,
+        policy = format!("{:?}", policy),
+    ));
+    Ok(())
+}
+
+fn parse_overpayment_policy(policy: &str) -> Result<OverpaymentPolicy> {
+    match policy {
+        "reject" => Ok(OverpaymentPolicy::Reject),
+        "donate" => Ok(OverpaymentPolicy::Donate),
+        "refund" => Ok(OverpaymentPolicy::Refund),
+        _ => bail!("Unknown overpayment policy {}", policy),
+    }
+}
+
+fn set_receipt_minting(config: &Config, market: Pubkey, enabled: bool) -> Result<()> {
+    config.output.progress(message!("receipt_minting.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let instructions = &[owner_or_delegate(
+        config,
+        &market,
+        &token_market,
+        instruction::set_receipt_minting(
+            &token_market::id(),
+            &config.owner.pubkey(),
+            &market,
+            enabled,
+        )?,
+    )];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(if enabled {
+        message!("receipt_minting.enabled", market = market)
+    } else {
+        message!("receipt_minting.disabled", market = market)
+    });
+    Ok(())
+}
+
+fn set_memo_policy(config: &Config, market: Pubkey, required: bool) -> Result<()> {
+    config.output.progress(message!("memo_policy.setting"));
+
+    let instructions = &[instruction::set_memo_policy(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        required,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(if required {
+        message!("memo_policy.required", market = market)
+    } else {
+        message!("memo_policy.optional", market = market)
+    });
+    Ok(())
+}
+
+fn set_purchase_limits(
+    config: &Config,
+    market: Pubkey,
+    min_purchase: Option<UiAmount>,
+    max_purchase: Option<UiAmount>,
+) -> Result<()> {
+    config.output.progress(message!("purchase_limits.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let (min_purchase, max_purchase) = purchase_limits(
+        config,
+        &token_market.mint_of_acceptable,
+        min_purchase.as_ref(),
+        max_purchase.as_ref(),
+    )?;
+    let instructions = &[instruction::set_purchase_limits(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        AcceptableAmount(min_purchase),
+        AcceptableAmount(max_purchase),
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(match max_purchase {
+        0 => message!(
+            "purchase_limits.unbounded",
+            market = market,
+            min = min_purchase,
+        ),
+        max => message!(
+            "purchase_limits.set",
+            market = market,
+            min = min_purchase,
+            max = max,
+        ),
+    });
+    Ok(())
+}
+
+fn set_sale_window(
+    config: &Config,
+    market: Pubkey,
+    sale_start: Option<i64>,
+    sale_end: Option<i64>,
+) -> Result<()> {
+    config.output.progress(message!("sale_window.setting"));
+
+    let (sale_start, sale_end) = (sale_start.unwrap_or(0), sale_end.unwrap_or(0));
+    if sale_start != 0 && sale_end != 0 && sale_start >= sale_end {
+        bail!("The sale must end after it starts");
+    }
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    if token_market.sale_started(now) {
+        bail!(
+            "The sale of market {} started at {}, its window can't change",
+            market,
+            token_market.sale_start
+        );
+    }
+    let instructions = &[instruction::set_sale_window(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        sale_start,
+        sale_end,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    let bound = |timestamp: i64| match timestamp {
+        0 => "-".to_string(),
+        timestamp => timestamp.to_string(),
+    };
+    config.output.done(message!(
+        "sale_window.set",
+        market = market,
+        start = bound(sale_start),
+        end = bound(sale_end),
+    ));
+    Ok(())
+}
+
+/// Print the version and the features of the program at `program_id`
+fn probe_program(config: &Config, program_id: Pubkey) -> Result<()> {
+    let capabilities = probe::probe(&config.rpc_client, &program_id, &config.fee_payer.pubkey())?;
+    config.output.done(match capabilities.version {
+        Some(version) => {
+            let features: Vec<&str> = TokenMarket::FEATURE_NAMES
+                .iter()
+                .filter(|(_, bit)| capabilities.supports(*bit))
+                .map(|(name, _)| *name)
+                .collect();
+            message!(
+                "probe.version",
+                program = program_id,
+                version = version,
+                features = features.join(","),
+            )
+        }
+        None => message!("probe.legacy", program = program_id),
+    });
+    Ok(())
+}
+
+/// Publish the alert thresholds of the owner keypair for `market`, only
+/// the hash of `webhook` goes on chain
+fn set_alerts(config: &Config, args: SetAlertsArgs) -> Result<()> {
+    config.output.progress(message!("alerts.publishing"));
+
+    let market_data = config.rpc_client.get_account_data(&args.market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let raw = |amount: Option<UiAmount>, mint: &Pubkey| match amount {
+        Some(amount) => to_raw(config, &amount, mint),
+        None => Ok(0),
+    };
+    let thresholds = AlertThresholds {
+        large_purchase: raw(args.large_purchase, &token_market.emitter_mint)?,
+        daily_volume: raw(args.daily_volume, &token_market.mint_of_acceptable)?,
+        low_bank_balance: raw(args.low_bank_balance, &token_market.mint_of_acceptable)?,
+    };
+    let webhook_hash = match &args.webhook {
+        Some(webhook) => hash(webhook.as_bytes()).to_bytes(),
+        None => [0; 32],
+    };
+    let sealed = match &args.sealed {
+        Some(path) => fs::read(path)
+            .with_context(|| format!("Can't read sealed settings {}", path.display()))?,
+        None => vec![],
+    };
+    if sealed.len() > NotificationConfig::MAX_SEALED_LEN {
+        bail!(
+            "Sealed settings must be at most {} bytes",
+            NotificationConfig::MAX_SEALED_LEN
+        );
+    }
+    let instructions = &[instruction::set_notification_config(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &config.fee_payer.pubkey(),
+        &args.market,
+        webhook_hash,
+        thresholds,
+        sealed,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config
+        .output
+        .done(message!("alerts.published", market = args.market));
+    Ok(())
+}
+
+fn set_wallet_cap(config: &Config, market: Pubkey, wallet_cap: UiAmount) -> Result<()> {
+    config.output.progress(message!("wallet_cap.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let wallet_cap = to_raw(config, &wallet_cap, &token_market.emitter_mint)?;
+    let instructions = &[instruction::set_wallet_cap(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        EmittedAmount(wallet_cap),
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "wallet_cap.set",
+        market = market,
+        cap = wallet_cap,
+    ));
+    Ok(())
+}
+
+fn set_wallet_spend_cap(config: &Config, market: Pubkey, wallet_spend_cap: UiAmount) -> Result<()> {
+    config.output.progress(message!("wallet_spend_cap.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let wallet_spend_cap = to_raw(config, &wallet_spend_cap, &token_market.mint_of_acceptable)?;
+    let instructions = &[instruction::set_wallet_spend_cap(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        AcceptableAmount(wallet_spend_cap),
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "wallet_spend_cap.set",
+        market = market,
+        cap = wallet_spend_cap,
+    ));
+    Ok(())
+}
+
+fn set_purchase_cooldown(config: &Config, market: Pubkey, purchase_cooldown: i64) -> Result<()> {
+    config
+        .output
+        .progress(message!("purchase_cooldown.setting"));
+
+    if purchase_cooldown < 0 {
+        bail!("A purchase cooldown can't be negative");
+    }
+    let instructions = &[instruction::set_purchase_cooldown(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        purchase_cooldown,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "purchase_cooldown.set",
+        market = market,
+        seconds = purchase_cooldown,
+    ));
+    Ok(())
+}
+
+fn set_loyalty_tiers(config: &Config, market: Pubkey, tiers: &[LoyaltyArg]) -> Result<()> {
+    config.output.progress(message!("loyalty_tiers.setting"));
+
+    if tiers.len() > TokenMarket::MAX_LOYALTY_TIERS {
+        bail!(
+            "A market discounts by at most {} loyalty tiers",
+            TokenMarket::MAX_LOYALTY_TIERS
+        );
+    }
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let tiers = tiers
+        .iter()
+        .map(|tier| {
+            Ok(LoyaltyTier {
+                threshold: to_raw(config, &tier.from, &token_market.emitter_mint)?,
+                discount_bps: tier.discount_bps,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if pricing::check_loyalty_tiers(&tiers).is_err() {
+        bail!("Loyalty tiers need increasing amounts and increasing discounts below 10000 bps");
+    }
+    let instructions = &[instruction::set_loyalty_tiers(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        &tiers,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    match tiers.len() {
+        0 => config
+            .output
+            .done(message!("loyalty_tiers.off", market = market)),
+        count => config.output.done(message!(
+            "loyalty_tiers.set",
+            market = market,
+            count = count,
+        )),
+    }
+    Ok(())
+}
+
+fn set_stake_weight(config: &Config, args: SetStakeWeightArgs) -> Result<()> {
+    config.output.progress(message!("stake_weight.setting"));
+
+    let stake_weight = match (args.stake_mint, args.governance_program) {
+        (None, _) => StakeWeight::default(),
+        (Some(mint), None) => StakeWeight {
+            source: StakeSource::PoolTokens,
+            mint,
+            governance_program: Pubkey::default(),
+            bonus_bps: args.bonus_bps,
+        },
+        (Some(mint), Some(governance_program)) => StakeWeight {
+            source: StakeSource::GovernanceDeposit,
+            mint,
+            governance_program,
+            bonus_bps: args.bonus_bps,
+        },
+    };
+    if stake_weight.is_set() && stake_weight.bonus_bps == 0 {
+        bail!("A stake weight needs a bonus above 0");
+    }
+    let instructions = &[instruction::set_stake_weight(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &args.market,
+        stake_weight,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    match args.stake_mint {
+        Some(mint) => config.output.done(message!(
+            "stake_weight.set",
+            market = args.market,
+            mint = mint,
+            bonus_bps = args.bonus_bps,
+        )),
+        None => config
+            .output
+            .done(message!("stake_weight.off", market = args.market)),
+    }
+    Ok(())
+}
+
+fn set_withdraw_rate(config: &Config, market: Pubkey, withdraw_rate: UiAmount) -> Result<()> {
+    config.output.progress(message!("withdraw_rate.setting"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let withdraw_rate = to_raw(config, &withdraw_rate, &token_market.mint_of_acceptable)?;
+    if token_market.withdraw_rate != 0
+        && (withdraw_rate == 0 || withdraw_rate > token_market.withdraw_rate)
+    {
+        bail!(
+            "Market {} streams {} a day, which can only be lowered",
+            market,
+            token_market.withdraw_rate
+        );
+    }
+    let instructions = &[instruction::set_withdraw_rate(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        AcceptableAmount(withdraw_rate),
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "withdraw_rate.set",
+        market = market,
+        rate = withdraw_rate,
+    ));
+    Ok(())
+}
+
+fn set_allowlist(config: &Config, market: Pubkey, enabled: bool) -> Result<()> {
+    config.output.progress(message!("allowlist.setting"));
+
+    let instructions = &[instruction::set_allowlist(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        enabled,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(if enabled {
+        message!("allowlist.enabled", market = market)
+    } else {
+        message!("allowlist.disabled", market = market)
+    });
+    Ok(())
+}
+
+fn allowlist_add(config: &Config, market: Pubkey, buyer: Pubkey) -> Result<()> {
+    config.output.progress(message!("allowlist.adding"));
+
+    let instructions = &[instruction::add_to_allowlist(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &config.fee_payer.pubkey(),
+        &market,
+        &buyer,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config
+        .output
+        .done(message!("allowlist.added", market = market, buyer = buyer));
+    Ok(())
+}
+
+fn set_allowlist_root(config: &Config, market: Pubkey, path: &Path) -> Result<()> {
+    config.output.progress(message!("allowlist.setting"));
+
+    let allowlist = Allowlist::load(path)?;
+    let instructions = &[instruction::set_allowlist_root(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        allowlist.root(),
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "allowlist.root_set",
+        market = market,
+        count = allowlist.count(),
+        root = hex::encode(allowlist.root()),
+    ));
+    Ok(())
+}
+
+fn allowlist_remove(config: &Config, market: Pubkey, buyer: Pubkey) -> Result<()> {
+    config.output.progress(message!("allowlist.removing"));
+
+    // the rent goes back to the fee payer, which usually funded the entry
+    let instructions = &[instruction::remove_from_allowlist(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        &buyer,
+        &config.fee_payer.pubkey(),
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "allowlist.removed",
+        market = market,
+        buyer = buyer
+    ));
+    Ok(())
+}
+
+fn set_sandwich_guard(config: &Config, market: Pubkey, enabled: bool) -> Result<()> {
+    config.output.progress(message!("sandwich_guard.setting"));
+
+    let instructions = &[instruction::set_sandwich_guard(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        enabled,
+    )?];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(if enabled {
+        message!("sandwich_guard.enabled", market = market)
+    } else {
+        message!("sandwich_guard.disabled", market = market)
+    });
+    Ok(())
+}
+
+fn set_listing(
+    config: &Config,
+    market: Pubkey,
+    website: &str,
+    logo_uri: &str,
+    contact: &str,
+) -> Result<()> {
+    config.output.progress(message!("listing.publishing"));
+
+    if website.len() > ListingInfo::MAX_WEBSITE_LEN {
+        bail!(
+            "Website must be at most {} bytes",
+            ListingInfo::MAX_WEBSITE_LEN
+        );
+    }
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let instructions = &[owner_or_delegate(
+        config,
+        &market,
+        &token_market,
+        instruction::set_listing_info(
+            &token_market::id(),
+            &config.owner.pubkey(),
+            &config.fee_payer.pubkey(),
+            &market,
+            website.to_string(),
+            hash(logo_uri.as_bytes()).to_bytes(),
+            hash(contact.as_bytes()).to_bytes(),
+        )?,
+    )];
+    send(config, instructions, &[config.owner.as_ref()])?;
+
+    config
+        .output
+        .done(message!("listing.published", market = market));
+    Ok(())
+}
+
+fn create_holdings_migration(
+    config: &Config,
+    to_owner: &dyn MarketSigner,
+    from_market: Pubkey,
+    to_market: Pubkey,
+    numerator: u64,
+    denominator: u64,
+) -> Result<()> {
+    config
+        .output
+        .progress(message!("holdings_migration.creating"));
+
+    let migration = Keypair::new();
+    let instructions = &[
+        create_account(
+            &config.fee_payer.pubkey(),
+            &migration.pubkey(),
+            Rent::default().minimum_balance(HoldingsMigration::LEN),
+            HoldingsMigration::LEN as u64,
+            &token_market::id(),
+        ),
+        instruction::create_holdings_migration(
+            &token_market::id(),
+            &config.owner.pubkey(),
+            &to_owner.pubkey(),
+            &from_market,
+            &to_market,
+            &migration.pubkey(),
+            numerator,
+            denominator,
+        )?,
+    ];
+    send(
+        config,
+        instructions,
+        &[config.owner.as_ref(), to_owner, &migration],
+    )?;
+
+    config.output.done(message!(
+        "holdings_migration.created",
+        migration = migration.pubkey(),
+        from_market = from_market,
+        to_market = to_market,
+        numerator = numerator,
+        denominator = denominator,
+    ));
+    Ok(())
+}
+
+fn migrate_holdings(config: &Config, migration: Pubkey, amount: UiAmount) -> Result<()> {
+    config.output.progress(message!("holdings.migrating"));
+
+    let migration_data = config.rpc_client.get_account_data(&migration)?;
+    let holdings_migration = HoldingsMigration::try_from_slice(migration_data.as_slice())?;
+    let amount = to_raw(config, &amount, &holdings_migration.from_emitter)?;
+    let source = spl_associated_token_account::get_associated_token_address(
+        &config.owner.pubkey(),
+        &holdings_migration.from_emitter,
+    );
+
+    let mut instructions = vec![];
+    let destination = emitter_account(
+        config,
+        &mut instructions,
+        &config.owner.pubkey(),
+        &holdings_migration.to_emitter,
+    )?;
+    instructions.push(instruction::migrate_holdings(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &migration,
+        &source,
+        &holdings_migration.from_emitter,
+        &holdings_migration.to_emitter,
+        &destination,
+        EmittedAmount(amount),
+    )?);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "holdings.migrated",
+        amount = amount,
+        destination = destination,
+    ));
+    Ok(())
+}
+
+/// Sell emitted tokens of the owner keypair back to the bank of `market`
+fn sell_tokens(config: &Config, market: Pubkey, amount: UiAmount) -> Result<()> {
+    config.output.progress(message!("tokens.selling"));
+
+    let market_data = config.rpc_client.get_account_data(&market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    if !token_market.supports(TokenMarket::FEATURE_SELLING) {
+        bail!("Market {} doesn't buy tokens back", market);
+    }
+    if token_market.paused {
+        bail!("Market {} is paused by its owner", market);
+    }
+    let amount = to_raw(config, &amount, &token_market.emitter_mint)?;
+    let holding = spl_associated_token_account::get_associated_token_address(
+        &config.owner.pubkey(),
+        &token_market.emitter_mint,
+    );
+
+    let mut instructions = vec![];
+    // the associated account of the acceptable mint, created like an emitter one
+    let destination = emitter_account(
+        config,
+        &mut instructions,
+        &config.owner.pubkey(),
+        &token_market.mint_of_acceptable,
+    )?;
+    instructions.push(instruction::sell_tokens(
+        &token_market::id(),
+        &config.owner.pubkey(),
+        &market,
+        &token_market.bank,
+        &holding,
+        &token_market.emitter_mint,
+        &destination,
+        EmittedAmount(amount),
+    )?);
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    config.output.done(message!(
+        "tokens.sold",
+        amount = amount,
+        destination = destination,
+    ));
+    Ok(())
+}
+
+/// Parse `args`, suggesting the closest subcommand for an unknown one
+fn parse<I: IntoIterator<Item = OsString>>(help_template: &str, args: I) -> ArgMatches {
+    args::app(help_template)
+        .get_matches_from_safe(args)
+        .unwrap_or_else(
+            |err| match (err.kind, err.info.as_ref().and_then(|info| info.first())) {
+                (ErrorKind::InvalidSubcommand | ErrorKind::UnrecognizedSubcommand, Some(typed)) => {
+                    commands::unknown_subcommand(typed)
+                }
+                // Without a close enough name clap takes the word for a stray argument
+                (ErrorKind::UnknownArgument, Some(typed))
+                    if !typed.starts_with('-')
+                        && !env::args().any(|arg| commands::is_declared(&arg)) =>
+                {
+                    commands::unknown_subcommand(typed)
+                }
+                _ => err.exit(),
+            },
+        )
+}
+
+fn main() {
+    let help_template = commands::help_template();
+    let mut matches = parse(&help_template, env::args_os());
+    let mut cli = Cli::from_matches(&matches);
+    // The planned command is run as given, only its transactions are printed
+    let (planning, line) = match &cli.command {
+        Command::Plan(args) => (
+            Planning::Transactions,
+            args::planned_command_line(env::args_os().collect(), "plan", &[], &args.invocation),
+        ),
+        Command::GenProposal(args) => (
+            Planning::Proposal(args.governance),
+            args::planned_command_line(
+                env::args_os().collect(),
+                "gen-proposal",
+                &["--governance"],
+                &args.invocation,
+            ),
+        ),
+        _ => (Planning::Off, vec![]),
+    };
+    if planning != Planning::Off {
+        matches = parse(&help_template, line);
+        cli = Cli::from_matches(&matches);
+    }
+    let Cli { options, command } = cli;
+    logging::init(options.log_format);
+
+    let output = Output {
+        locale: options.locale.unwrap_or_else(Locale::from_env),
+        format: options.progress,
+        quiet: planning != Planning::Off,
+    };
+    if let Err(err) = run(&matches, options, command, output, planning) {
+        output.error(&err);
+        process::exit(1);
+    }
+}
+
+/// Print the hex instruction data of the instruction `json` describes,
+/// followed by the hex `trailing` fields
+fn encode_instruction(json: &str, trailing: Option<&str>) -> Result<()> {
+    let json = match json {
+        "-" => {
+            let mut json = String::new();
+            io::stdin().read_to_string(&mut json)?;
+            json
+        }
+        json => json.to_string(),
+    };
+    let trailing = match trailing {
+        Some(trailing) => instruction_data::parse_hex(trailing)?,
+        None => vec![],
+    };
+    println!(
+        "{}",
+        hex::encode(instruction_data::encode(&json, &trailing)?)
+    );
+    Ok(())
+}
+
+/// Print hex instruction `data` as the JSON
+/// [encode_instruction](fn.encode_instruction.html) takes
+fn decode_instruction_data(data: &str) -> Result<()> {
+    let decoded = instruction_data::decode(&instruction_data::parse_hex(data)?)?;
+    println!("{}", decoded.to_json()?);
+    Ok(())
+}
+
+/// Subcommands processed by [inspect](fn.inspect.html)
+const READ_ONLY_COMMANDS: &[&str] = &[
+    "find-market",
+    "list-markets",
+    "estimate-cost",
+    "campaign-stats",
+    "tenant-stats",
+    "show-listing",
+    "show-alerts",
+    "show-phases",
+    "phase-stats",
+    "history",
+    "doctor",
+    "reconcile",
+    "state-diff",
+    "verify-build",
+    "export-config",
+];
+
+/// Warn when the cluster at `json_rpc_url` doesn't host the program the
+/// address book names for it, or the book names another program than the one
+/// this CLI builds instructions for
+fn check_deployment(
+    output: &Output,
+    book: &AddressBook,
+    json_rpc_url: &str,
+    rpc_client: &RpcClient,
+) {
+    let cluster = Cluster::from_url(json_rpc_url);
+    let program_id = cluster
+        .and_then(|cluster| book.deployment(cluster))
+        .map_or_else(token_market::id, |deployment| deployment.program_id);
+    if program_id != token_market::id() {
+        output.warning(message!(
+            "addresses.other_program",
+            url = json_rpc_url,
+            program = program_id,
+            built = token_market::id(),
+        ));
+    }
+    match addresses::hosts_program(rpc_client, &program_id) {
+        Ok(true) => {}
+        Ok(false) => output.warning(message!(
+            "addresses.program_missing",
+            url = json_rpc_url,
+            program = program_id,
+        )),
+        Err(err) => debug!(%err, "deployment check failed"),
+    }
+}
+
+/// `matches` are only consulted by the keypair loading of `solana_clap_utils`
+fn run(
+    matches: &ArgMatches,
+    options: Options,
+    command: Command,
+    output: Output,
+    planning: Planning,
+) -> Result<()> {
+    if planning != Planning::Off
+        && (READ_ONLY_COMMANDS.contains(&command.name())
+            || matches!(
+                command,
+                Command::GenFixtures(_)
+                    | Command::EncodeInstruction(_)
+                    | Command::DecodeInstructionData(_)
+                    | Command::Plan(_)
+                    | Command::GenProposal(_)
+                    | Command::SignOwnershipProof(_)
+                    | Command::UpgradeRehearse(_)
+            ))
+    {
+        bail!("{} sends no transactions to plan", command.name());
+    }
+    // Offline commands need neither a configuration nor keypairs
+    match &command {
+        Command::GenFixtures(args) => return fixtures::generate(&args.out),
+        Command::EncodeInstruction(args) => {
+            return encode_instruction(&args.json, args.trailing.as_deref())
+        }
+        Command::DecodeInstructionData(args) => return decode_instruction_data(&args.data),
+        _ => {}
+    }
+
+    let mut wallet_manager = None;
+
+    let mut simulate_first = options.simulate_first;
+    let mut strict_preflight = options.strict_preflight;
+    let mut throttle_settings = ThrottleSettings::default();
+    let mut address_book = AddressBook::builtin();
+    let mut replicas = vec![];
+    let config_file = options
+        .connection
+        .config_file
+        .or_else(|| solana_cli_config::CONFIG_FILE.clone());
+    let cli_config = if let Some(config_file) = config_file {
+        // ops profiles can enforce the simulation guard rail next to the regular settings
+        let profile: serde_json::Value = solana_cli_config::load_config_file(&config_file)?;
+        simulate_first |= profile["simulate_first"].as_bool().unwrap_or(false);
+        strict_preflight |= profile["strict_preflight"].as_bool().unwrap_or(false);
+        throttle_settings = ThrottleSettings::from_profile(&profile)
+            .with_context(|| format!("Invalid RPC throttle in {}", config_file))?;
+        address_book = address_book
+            .with_profile(&profile)
+            .with_context(|| format!("Invalid {} in {}", addresses::PROFILE_KEY, config_file))?;
+        replicas = endpoints::replicas_from_profile(&profile)
+            .with_context(|| format!("Invalid {} in {}", endpoints::PROFILE_KEY, config_file))?;
+        solana_cli_config::Config::load(&config_file)?
+    } else {
+        output.progress(message!("config.missing"));
+        solana_cli_config::Config::default()
+    };
+    let json_rpc_url = match &command {
+        // mirror names the cluster it creates the copy on like the one it reads
+        Command::Mirror(MirrorArgs { to: Some(to), .. }) => addresses::url_or_moniker(to),
+        _ => options
+            .connection
+            .json_rpc_url
+            .unwrap_or_else(|| cli_config.json_rpc_url.clone()),
+    };
+    // the replicas of the profile only stand in for its own endpoint
+    let mut rpc_urls = vec![json_rpc_url.clone()];
+    if json_rpc_url == cli_config.json_rpc_url {
+        rpc_urls.extend(replicas);
+    }
+    let endpoint_pool = || EndpointPool::new(&rpc_urls, CommitmentConfig::confirmed());
+    let throttle = Throttle::new(throttle_settings);
+
+    let owner_path = options
+        .signers
+        .owner
+        .unwrap_or_else(|| cli_config.keypair_path.clone());
+    let fee_payer_path = options
+        .signers
+        .fee_payer
+        .unwrap_or_else(|| cli_config.keypair_path.clone());
+
+    let span = info_span!(
+        "command",
+        name = command.name(),
+        pid = process::id(),
+        url = json_rpc_url.as_str(),
+        fee_payer = field::Empty,
+    );
+    let _command = span.enter();
+
+    // Inspection works without a wallet, so keypairs are loaded only to sign
+    if READ_ONLY_COMMANDS.contains(&command.name()) {
+        let buyer_signs = fee_payer_path != owner_path;
+        let reader =
+            MarketReader::from_client(ThrottledClient::with_pool(endpoint_pool(), throttle));
+        check_deployment(&output, &address_book, &json_rpc_url, reader.rpc_client());
+        return inspect(&reader, command, buyer_signs);
+    }
+    // Rehearsals sign on the local validator with a key of their own
+    if let Command::UpgradeRehearse(args) = &command {
+        let source =
+            MarketReader::from_client(ThrottledClient::with_pool(endpoint_pool(), throttle));
+        let scenario = rehearsal::load(&args.scenario)?;
+        return rehearsal::rehearse(
+            &source,
+            &json_rpc_url,
+            &args.market,
+            &args.program_so,
+            &scenario,
+            args.rpc_port,
+            output,
+        );
+    }
+
+    // Keys held by an external service are named `external:PUBKEY=COMMAND`,
+    // every other path is loaded like the Solana CLI does
+    let mut load_signer = |path: &str, name: &str| -> Result<Box<dyn MarketSigner>> {
+        if let Some(external) = signer::external_signer_from_path(path) {
+            return Ok(Box::new(external?));
+        }
+        let wallet = signer_from_path(matches, path, name, &mut wallet_manager)
+            .map_err(|err| anyhow!("{}", err))?;
+        Ok(Box::new(WalletSigner::new(wallet, path)))
+    };
+    let (owner, fee_payer): (Box<dyn MarketSigner>, Box<dyn MarketSigner>) = match planning {
+        // the governance signs for itself when it executes the proposal
+        Planning::Proposal(governance) => (
+            Box::new(NullSigner::new(&governance)),
+            Box::new(NullSigner::new(&governance)),
+        ),
+        _ => (
+            load_signer(&owner_path, "owner")
+                .map_err(|err| anyhow!("Can't load owner keypair: {}", err))?,
+            load_signer(&fee_payer_path, "fee_payer")
+                .map_err(|err| anyhow!("Can't load fee payer keypair: {}", err))?,
+        ),
+    };
+    debug!(
+        owner = %owner.backend(),
+        fee_payer = %fee_payer.backend(),
+        "signers loaded"
+    );
+
+    let config = &Config {
+        owner,
+        fee_payer,
+        rpc_client: ThrottledClient::with_pool(endpoint_pool(), throttle),
+        screener: match options.denylist {
+            Some(path) => Some(Box::new(DenylistScreener::load(&path)?)),
+            None => None,
+        },
+        simulate_first,
+        strict_preflight,
+        planning,
+        output,
+    };
+    span.record("fee_payer", &field::display(config.fee_payer.pubkey()));
+    check_deployment(
+        &config.output,
+        &address_book,
+        &json_rpc_url,
+        &config.rpc_client,
+    );
+
+    match command {
+        Command::CreateMarket(args) => match args.from_config {
+            Some(path) => {
+                let file = reconcile::load(&path)?;
+                let params = reconcile::CreationParams::from_file(
+                    &file,
+                    args.acceptable,
+                    args.category,
+                    args.project_id,
+                    args.features,
+                )?;
+                let limits = purchase_limits(
+                    config,
+                    &params.acceptable,
+                    args.min_purchase.as_ref(),
+                    args.max_purchase.as_ref(),
+                )?;
+                let tiers = pricing_tiers(config, &params.acceptable, &args.tiers)?;
+                let curve = bonding_curve(config, &params.acceptable, &args.curve)?;
+                let market = create_market(
+                    config,
+                    params.acceptable,
+                    params.category,
+                    params.project_id,
+                    params.features,
+                    limits,
+                    &tiers,
+                    curve,
+                    args.purchase_cooldown.unwrap_or(0),
+                )?;
+                reconcile::apply_file(config, &file, &market)?;
+                if !args.phases.is_empty() {
+                    set_phases(config, market, &args.phases, args.sale_end)?;
+                }
+                if args.opens_at.is_some() || args.closes_at.is_some() {
+                    set_sale_window(config, market, args.opens_at, args.closes_at)?;
+                }
+                Ok(())
+            }
+            None => {
+                let acceptable = args.acceptable.expect("required without --from-config");
+                let limits = purchase_limits(
+                    config,
+                    &acceptable,
+                    args.min_purchase.as_ref(),
+                    args.max_purchase.as_ref(),
+                )?;
+                let tiers = pricing_tiers(config, &acceptable, &args.tiers)?;
+                let curve = bonding_curve(config, &acceptable, &args.curve)?;
+                let market = create_market(
+                    config,
+                    acceptable,
+                    args.category.unwrap_or_default(),
+                    args.project_id.unwrap_or_default(),
+                    args.features.unwrap_or(TokenMarket::DEFAULT_FEATURES),
+                    limits,
+                    &tiers,
+                    curve,
+                    args.purchase_cooldown.unwrap_or(0),
+                )?;
+                // The schedule doesn't fit in the transaction creating the market
+                if !args.phases.is_empty() {
+                    set_phases(config, market, &args.phases, args.sale_end)?;
+                }
+                if args.opens_at.is_some() || args.closes_at.is_some() {
+                    set_sale_window(config, market, args.opens_at, args.closes_at)?;
+                }
+                Ok(())
+            }
+        },
+        Command::BuyTokens(args) => buy_tokens(
+            config,
+            args.market,
+            args.recipient,
+            args.amount,
+            args.relayer_tip,
+            args.voucher,
+            args.quote,
+            args.campaign,
+            args.coupon,
+            args.memo,
+            args.allow_deprecated,
+            args.allowlist.as_deref(),
+            args.native_sol,
+        ),
+        Command::Deprecate(args) => {
+            deprecate(config, args.market, args.successor, &args.message_uri)
+        }
+        Command::SetVoucherSigner(args) => {
+            set_voucher_signer(config, args.market, args.eth_address)
+        }
+        Command::QuoteServer(args) => {
+            let prices = match (args.price, args.price_file) {
+                (Some(price), _) => PriceSource::Fixed(price),
+                (None, Some(path)) => PriceSource::File(path),
+                (None, None) => unreachable!(),
+            };
+
+            serve_quotes(
+                config,
+                &args.voucher_key,
+                args.market,
+                prices,
+                args.max_amount,
+                seconds_of(args.ttl)?,
+                &args.bind,
+            )
+        }
+        Command::SignVoucher(args) => sign_voucher(
+            config,
+            &args.voucher_key,
+            args.market,
+            args.buyer,
+            args.max_amount,
+            args.expiry,
+        ),
+        Command::SignOwnershipProof(args) => {
+            sign_ownership_proof(config, &args.market, &args.domain)
+        }
+        Command::CreateVesting(args) => {
+            let start = match args.start {
+                Some(start) => start,
+                None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+            };
+
+            create_vesting(
+                config,
+                args.market,
+                args.beneficiary,
+                args.total,
+                start,
+                seconds_of(args.cliff)?,
+                seconds_of(args.duration)?,
+                args.revocable,
+            )
+        }
+        Command::ClaimVesting(args) => claim_vesting(config, args.vesting),
+        Command::RevokeVesting(args) => revoke_vesting(config, args.vesting),
+        Command::CrankVesting(args) => crank_vesting(config, args.market),
+        Command::GarbageCollect(args) => garbage_collect(config, args.market),
+        Command::MigrateAcceptable(args) => {
+            migrate_acceptable(config, args.market, args.acceptable)
+        }
+        Command::RecreateBank(args) => recreate_bank(config, args.market),
+        Command::WithdrawBank(args) => {
+            withdraw_bank(config, args.market, args.amount, args.destination)
+        }
+        Command::WithdrawFees(args) => {
+            withdraw_fees(config, args.market, args.amount, args.destination)
+        }
+        Command::Pause(args) => pause(config, args.market),
+        Command::Resume(args) => resume(config, args.market),
+        Command::SetPhases(args) => set_phases(config, args.market, &args.phases, args.sale_end),
+        Command::AdvancePhase(args) => advance_phase(config, args.market),
+        Command::SetTreasury(args) => set_treasury(config, args.market, args.treasury),
+        Command::SetOverpaymentPolicy(args) => {
+            set_overpayment_policy(config, args.market, args.policy)
+        }
+        Command::SetReceiptMinting(args) => set_receipt_minting(config, args.market, args.enabled),
+        Command::SetMemoPolicy(args) => set_memo_policy(config, args.market, args.required),
+        Command::SetSandwichGuard(args) => set_sandwich_guard(config, args.market, args.enabled),
+        Command::SetProtocolFee(args) => set_protocol_fee(config, args.market, args.fee_bps),
+        Command::SetPurchaseLimits(args) => {
+            set_purchase_limits(config, args.market, args.min, args.max)
+        }
+        Command::SetWalletCap(args) => set_wallet_cap(config, args.market, args.wallet_cap),
+        Command::SetWalletSpendCap(args) => {
+            set_wallet_spend_cap(config, args.market, args.wallet_spend_cap)
+        }
+        Command::SetPurchaseCooldown(args) => {
+            set_purchase_cooldown(config, args.market, args.purchase_cooldown)
+        }
+        Command::SetLoyaltyTiers(args) => set_loyalty_tiers(config, args.market, &args.tiers),
+        Command::SetStakeWeight(args) => set_stake_weight(config, args),
+        Command::SetWithdrawRate(args) => {
+            set_withdraw_rate(config, args.market, args.withdraw_rate)
+        }
+        Command::SetAlerts(args) => set_alerts(config, args),
+        Command::SetSaleWindow(args) => set_sale_window(config, args.market, args.start, args.end),
+        Command::SetAllowlist(args) => set_allowlist(config, args.market, args.enabled),
+        Command::AllowlistAdd(args) => allowlist_add(config, args.market, args.buyer),
+        Command::AllowlistRemove(args) => allowlist_remove(config, args.market, args.buyer),
+        Command::SetAllowlistRoot(args) => set_allowlist_root(config, args.market, &args.file),
+        Command::Probe(args) => {
+            probe_program(config, args.program_id.unwrap_or_else(token_market::id))
+        }
+        Command::SetWormholeEmitter(args) => {
+            set_wormhole_emitter(config, args.market, args.chain, args.emitter)
+        }
+        Command::RedeemWormhole(args) => redeem_wormhole(config, args.posted_vaa),
+        Command::SetOnrampSigner(args) => set_onramp_signer(config, args.market, args.signer),
+        Command::BuyWithFiat(args) => {
+            let onramp_signer = load_signer(&args.onramp_key, "onramp_key")
+                .map_err(|err| anyhow!("Can't load on-ramp keypair: {}", err))?;
+
+            buy_with_fiat(
+                config,
+                onramp_signer.as_ref(),
+                args.market,
+                args.recipient,
+                args.amount,
+                &args.reference,
+            )
+        }
+        Command::SetDisputeEscrow(args) => set_dispute_escrow(
+            config,
+            args.market,
+            args.threshold,
+            args.window,
+            args.guardian,
+        ),
+        Command::BuyEscrowed(args) => {
+            buy_escrowed(config, args.market, args.recipient, args.amount, args.memo)
+        }
+        Command::BuyMulti(args) => {
+            let purchases = recipients::load(&args.recipients)?;
+            buy_multi(config, args.market, &purchases, args.memo)
+        }
+        Command::BuyExactOut(args) => buy_exact_out(
+            config,
+            args.market,
+            args.recipient,
+            args.amount,
+            args.max_in,
+            args.memo,
+        ),
+        Command::ReleaseEscrow(args) => release_escrow(config, args.escrow),
+        Command::DisputeEscrow(args) => dispute_escrow(config, args.escrow),
+        Command::BuyGift(args) => {
+            buy_gift(config, args.market, args.amount, args.valid_for, args.memo)
+        }
+        Command::ClaimGift(args) => claim_gift(config, &args.claim, args.recipient),
+        Command::RefundGift(args) => refund_gift(config, args.gift),
+        Command::SellTokens(args) => sell_tokens(config, args.market, args.amount),
+        Command::CreateCoupon(args) => create_coupon(
+            config,
+            args.market,
+            &args.code,
+            args.discount_bps,
+            args.max_uses,
+            args.expiry,
+        ),
+        Command::SetDelegation(args) => {
+            let calls_per_day = [
+                args.listing_per_day.unwrap_or(0),
+                args.overpayment_policy_per_day.unwrap_or(0),
+                args.receipt_minting_per_day.unwrap_or(0),
+            ];
+
+            set_delegation(
+                config,
+                args.market,
+                args.delegate,
+                args.vesting_per_day,
+                calls_per_day,
+            )
+        }
+        Command::SetListing(args) => set_listing(
+            config,
+            args.market,
+            &args.website,
+            &args.logo_uri,
+            &args.contact,
+        ),
+        Command::CreateHoldingsMigration(args) => {
+            let to_owner = load_signer(&args.to_owner, "to_owner")
+                .map_err(|err| anyhow!("Can't load new market owner: {}", err))?;
+
+            create_holdings_migration(
+                config,
+                to_owner.as_ref(),
+                args.from_market,
+                args.to_market,
+                args.numerator,
+                args.denominator,
+            )
+        }
+        Command::MigrateHoldings(args) => migrate_holdings(config, args.migration, args.amount),
+        Command::Diff(args) => reconcile::diff(config, &args.file, args.market),
+        Command::Apply(args) => reconcile::apply(config, &args.file, args.market),
+        Command::Mirror(args) => {
+            let from = addresses::url_or_moniker(&args.from);
+            let source =
+                MarketReader::with_throttle(from.clone(), Throttle::new(throttle_settings));
+            mirror::mirror(
+                config,
+                &address_book,
+                &source,
+                &from,
+                &json_rpc_url,
+                &args.market,
+                args.acceptable,
+                &args.mapping,
+            )
+        }
+        Command::MultiExec(args) => {
+            let plan = plan::load(&args.file)?;
+
+            plan::execute(config, &plan, args.dry_run)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Whole seconds of a duration argument, as the program stores them
+fn seconds_of(duration: Duration) -> Result<i64> {
+    i64::try_from(duration.as_secs())
+        .map_err(|_| anyhow!("Duration of {} seconds is too long", duration.as_secs()))
+}
+
+/// Process the commands that only read the chain and need no keypair.
+/// `buyer_signs` when the purchases estimated are paid by a separate fee payer.
+fn inspect(reader: &MarketReader, command: Command, buyer_signs: bool) -> Result<()> {
+    match command {
+        Command::FindMarket(args) => listing::find_market(reader, &args.mint),
+        Command::ListMarkets(args) => {
+            let mut memcmp = vec![];
+            if let Some(owner) = args.market_owner {
+                memcmp.push(filters::by_owner(&owner));
+            }
+            if let Some(category) = args.category {
+                memcmp.push(filters::by_category(category));
+            }
+            if let Some(project_id) = args.project_id {
+                memcmp.push(filters::by_project(project_id));
+            }
+
+            listing::list_markets(reader, &memcmp)
+        }
+        Command::EstimateCost(args) => estimate_cost(
+            reader,
+            args.market,
+            args.recipient,
+            args.amount,
+            args.relayer_tip,
+            args.campaign,
+            buyer_signs,
+            args.explain_quote,
+        ),
+        Command::CampaignStats(args) => {
+            campaign::show_campaign_stats(reader, &args.market, &args.campaign)
+        }
+        Command::TenantStats(args) => tenants::show_tenant_stats(reader, args.market_owner),
+        Command::ShowListing(args) => listing::show_listing(reader, &args.market),
+        Command::ShowAlerts(args) => alerts::show_alerts(reader, &args.market, args.owner),
+        Command::ShowPhases(args) => listing::show_phases(reader, &args.market),
+        Command::PhaseStats(args) => {
+            let currency = match &args.value_in {
+                Some(currency) => Some(listing::ReportCurrency::new(
+                    reader,
+                    currency,
+                    &args.prices,
+                    &args.pyth_feeds,
+                )?),
+                None => None,
+            };
+            listing::show_phase_stats(reader, &args.markets, args.json, currency.as_ref())
+        }
+        Command::Doctor(args) => doctor::doctor(reader.rpc_client(), &args.market, args.json),
+        Command::VerifyBuild(args) => verify::verify_build(
+            reader.rpc_client(),
+            &args.program_id.unwrap_or_else(token_market::id),
+            &args.repo,
+            &args.commit,
+            args.artifact,
+        ),
+        Command::History(args) => show_history(reader, &args.market, args.limit),
+        Command::Reconcile(args) => reconcile_supply(reader, &args.market),
+        Command::StateDiff(args) => match args.before {
+            Some(before) => {
+                state_diff::state_diff(reader, &args.market, &args.snapshots, before, args.after)
+            }
+            None => state_diff::record(reader, &args.market, &args.snapshots),
+        },
+        Command::ExportConfig(args) => reconcile::export(reader, &args.market, args.out.as_deref()),
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,465 @@
+//! User facing messages of the commands that send transactions.
+//!
+//! Every message has a stable id and named arguments. In text mode the id
+//! selects a template of the chosen locale, falling back to English. With
+//! `--progress json` each message is printed as one JSON event per line
+//! instead, so that wrappers don't have to parse the prose:
+//!
+//! ```json
+//! {"event":"progress","id":"market.creating","args":{},"text":"Creating market..."}
+//! {"event":"done","id":"market.created","args":{"market":"..."},"text":"Market created: ..."}
+//! {"event":"error","id":"error","args":{"message":"..."},"text":"..."}
+//! ```
+//!
+//! Ids and argument names are part of the CLI interface, so don't rename them.
+//! Add a new id when the meaning of a message changes.
+
+use anyhow::Error;
+use serde_json::{json, Map, Value};
+use std::env;
+
+/// A message id with its arguments, built with [message!](macro.message.html)
+pub struct Message {
+    pub id: &'static str,
+    pub args: Vec<(&'static str, String)>,
+}
+
+/// Build a [Message](struct.Message.html): `message!("market.deprecated", market = market)`
+macro_rules! message {
+    ($id:expr $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::messages::Message {
+            id: $id,
+            args: vec![$((stringify!($name), $value.to_string())),*],
+        }
+    };
+}
+pub(crate) use message;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    Ru,
+}
+
+impl Locale {
+    pub const NAMES: &'static [&'static str] = &["en", "ru"];
+
+    pub fn parse(name: &str) -> Option<Locale> {
+        match name {
+            "en" => Some(Locale::En),
+            "ru" => Some(Locale::Ru),
+            _ => None,
+        }
+    }
+
+    /// The locale from `LC_ALL`, `LC_MESSAGES` or `LANG`, English if none is supported
+    pub fn from_env() -> Locale {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Locale::parse(value.get(..2)?))
+            .unwrap_or(Locale::En)
+    }
+
+    fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Locale::En => EN,
+            Locale::Ru => RU,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    Text,
+    Json,
+}
+
+impl ProgressFormat {
+    pub const NAMES: &'static [&'static str] = &["text", "json"];
+
+    pub fn parse(name: &str) -> Option<ProgressFormat> {
+        match name {
+            "text" => Some(ProgressFormat::Text),
+            "json" => Some(ProgressFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Where commands report progress, results, warnings and errors
+#[derive(Clone, Copy)]
+pub struct Output {
+    pub locale: Locale,
+    pub format: ProgressFormat,
+    /// Drop progress and results, keeping warnings and errors. `plan` leaves
+    /// stdout to the transactions the command would send
+    pub quiet: bool,
+}
+
+impl Output {
+    /// The message text in the output locale
+    pub fn render(&self, message: &Message) -> String {
+        let template = find(self.locale.catalog(), message.id)
+            .or_else(|| find(EN, message.id))
+            .unwrap_or(message.id);
+        message
+            .args
+            .iter()
+            .fold(template.to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), value)
+            })
+    }
+
+    /// A step of a command is starting
+    pub fn progress(&self, message: Message) {
+        self.emit("progress", &message, false);
+    }
+
+    /// A command step succeeded
+    pub fn done(&self, message: Message) {
+        self.emit("done", &message, false);
+    }
+
+    /// Something the user should know before relying on the result
+    pub fn warning(&self, message: Message) {
+        self.emit("warning", &message, true);
+    }
+
+    /// The command failed
+    pub fn error(&self, error: &Error) {
+        let message = message!("error", message = format!("{:#}", error));
+        self.emit("error", &message, true);
+    }
+
+    fn emit(&self, event: &str, message: &Message, to_stderr: bool) {
+        if self.quiet && !to_stderr {
+            return;
+        }
+        let text = self.render(message);
+        match self.format {
+            ProgressFormat::Text if to_stderr => eprintln!("{}", text),
+            ProgressFormat::Text => println!("{}", text),
+            // The whole event stream goes to stdout so that it stays ordered
+            ProgressFormat::Json => {
+                let args: Map<String, Value> = message
+                    .args
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), Value::String(value.clone())))
+                    .collect();
+                println!(
+                    "{}",
+                    json!({ "event": event, "id": message.id, "args": args, "text": text })
+                );
+            }
+        }
+    }
+}
+
+fn find(catalog: &[(&'static str, &'static str)], id: &str) -> Option<&'static str> {
+    catalog
+        .iter()
+        .find(|(entry, _)| *entry == id)
+        .map(|(_, template)| *template)
+}
+
+const EN: &[(&str, &str)] = &[
+    ("error", "Error: {message}"),
+    ("config.missing", "Config file not provided and default config unexist. Create config"),
+    ("addresses.program_missing", "{url} does not host the token market program {program}, check the cluster or token_market_addresses of the config profile"),
+    ("addresses.other_program", "The address book puts the token market of {url} at {program}, this CLI is built for {built}"),
+    ("transaction.simulating", "Simulating transaction..."),
+    ("transaction.instruction", "  #{index} token market: {instruction}"),
+    ("transaction.instruction_undecodable", "  #{index} token market: undecodable data"),
+    ("transaction.instruction_foreign", "  #{index} program {program}"),
+    ("transaction.log", "  {log}"),
+    ("transaction.simulated", "Simulation succeeded, sending..."),
+    ("market.creating", "Creating market..."),
+    (
+        "market.created",
+        "Market created: market {market}, accepted tokens: {acceptable}, tradable tokens: {emitter}, bank: {bank}",
+    ),
+    ("market.deprecated", "WARNING: market {market} is DEPRECATED"),
+    ("market.successor", "WARNING: its successor is {successor}"),
+    ("market.deprecation_notice", "WARNING: see {uri}"),
+    ("deprecation.setting", "Deprecating market..."),
+    ("deprecation.set", "Market {market} is deprecated"),
+    ("purchase.buying", "Buying tokens..."),
+    ("purchase.done", "Purchased {amount} tokens. Recipient user {recipient}. Target ATA {account}"),
+    ("purchase.receipt", "Receipt {mint} held in {holding}"),
+    ("batch_purchase.buying", "Buying tokens for {count} recipients..."),
+    ("batch_purchase.done", "Purchased {amount} tokens for {count} recipients in {transactions} transactions"),
+    ("delegation.setting", "Setting delegation..."),
+    ("delegation.revoked", "{delegate} may no longer act for the owner of {market}"),
+    ("delegation.set", "{delegate} may act for the owner of {market}, daily limits: {limits}"),
+    ("vesting.creating", "Creating vesting..."),
+    ("vesting.created", "Vesting created: vesting {vesting}, vault {vault}, beneficiary {beneficiary}"),
+    ("vesting.claiming", "Claiming vested tokens..."),
+    ("vesting.claimed", "Vested tokens claimed to {destination}"),
+    ("vesting.revoking", "Revoking vesting..."),
+    ("vesting.revoked", "Vesting revoked, unvested tokens returned to {destination}"),
+    ("vesting.cranking", "Looking for vestings with unclaimed tokens..."),
+    ("vesting.crank_sent", "Claimed {cranked} of {total} vestings: {signature}"),
+    ("vesting.cranked", "Vested tokens of {count} vestings pushed to their beneficiaries"),
+    ("garbage.collecting", "Looking for records of the market to close..."),
+    ("garbage.skipped", "{count} receipts left open, their buyers no longer hold the receipt mints"),
+    ("garbage.collected", "Closed {records} buyer records and {receipts} receipts, {sol} SOL of rent returned to buyers"),
+    ("voucher_signer.setting", "Setting voucher signer..."),
+    ("voucher_signer.set", "Market {market} accepts vouchers signed by 0x{signer}"),
+    ("acceptable.migrating", "Migrating acceptable mint..."),
+    (
+        "acceptable.migrated",
+        "Market {market} now accepts {acceptable}: bank {bank}, legacy bank {legacy_bank}",
+    ),
+    ("bank.recreating", "Recreating bank..."),
+    ("bank.recreated", "Market {market} bank is now {bank}"),
+    ("bank.withdrawing", "Withdrawing payments..."),
+    ("bank.withdrawn", "Withdrew {amount} from the bank to {destination}"),
+    ("fees.setting", "Setting protocol fee..."),
+    ("fees.set", "Market {market} keeps {fee_bps} bps of every payment in {fee_vault}"),
+    ("fees.withdrawing", "Withdrawing protocol fees..."),
+    ("fees.withdrawn", "Withdrew {amount} of fees to {destination}"),
+    ("purchase_limits.setting", "Setting purchase limits..."),
+    ("purchase_limits.set", "Purchases on market {market} pay from {min} to {max}"),
+    ("purchase_limits.unbounded", "Purchases on market {market} pay at least {min}"),
+    ("wallet_cap.setting", "Setting wallet cap..."),
+    ("wallet_cap.set", "Wallets buy at most {cap} on market {market}"),
+    ("wallet_spend_cap.setting", "Setting wallet spend cap..."),
+    ("wallet_spend_cap.set", "Wallets spend at most {cap} on market {market}"),
+    ("purchase_cooldown.setting", "Setting purchase cooldown..."),
+    ("purchase_cooldown.set", "Wallets wait {seconds} seconds between purchases on market {market}"),
+    ("loyalty_tiers.setting", "Setting loyalty tiers..."),
+    ("loyalty_tiers.set", "Buyers on market {market} are discounted by {count} loyalty tiers"),
+    ("loyalty_tiers.off", "Buyers on market {market} are no longer discounted for loyalty"),
+    ("stake_weight.setting", "Setting stake weight..."),
+    ("stake_weight.set", "Stakes of {mint} raise wallet caps on market {market} by {bonus_bps} bps"),
+    ("stake_weight.off", "Stakes no longer raise wallet caps on market {market}"),
+    ("withdraw_rate.setting", "Setting withdraw rate..."),
+    ("withdraw_rate.set", "The bank of market {market} releases {rate} a day"),
+    ("probe.version", "Program {program} runs version {version} with features {features}"),
+    ("probe.legacy", "Program {program} predates version probing, new markets get every feature it knows"),
+    ("alerts.publishing", "Publishing alert thresholds..."),
+    ("alerts.published", "Alert thresholds of market {market} published"),
+    ("sale_window.setting", "Scheduling the sale..."),
+    ("sale_window.set", "Market {market} sells from {start} until {end}"),
+    ("allowlist.setting", "Setting allowlist..."),
+    ("allowlist.enabled", "Market {market} sells to allowlisted buyers only"),
+    ("allowlist.disabled", "Market {market} sells to anyone"),
+    ("allowlist.adding", "Adding buyer to the allowlist..."),
+    ("allowlist.added", "{buyer} may buy on market {market}"),
+    ("allowlist.removing", "Removing buyer from the allowlist..."),
+    ("allowlist.removed", "{buyer} is off the allowlist of market {market}"),
+    ("allowlist.root_set", "Market {market} admits {count} buyers by the Merkle root {root}"),
+    ("market.pausing", "Pausing market..."),
+    ("market.paused", "Market {market} is paused"),
+    ("market.resuming", "Resuming market..."),
+    ("market.resumed", "Market {market} trades again"),
+    ("phases.setting", "Setting sale phases..."),
+    ("phases.set", "Market {market} sells in {count} phases"),
+    ("phases.advancing", "Advancing sale phase..."),
+    ("phases.opened", "Market {market} sale phase {phase} is open"),
+    ("phases.closed", "Market {market} sale is closed"),
+    ("treasury.setting", "Setting treasury..."),
+    ("treasury.set", "Market {market} treasury is {treasury}"),
+    ("overpayment_policy.setting", "Setting overpayment policy..."),
+    ("overpayment_policy.set", "Market {market} overpayment policy is {policy}"),
+    ("receipt_minting.setting", "Setting receipt minting..."),
+    ("receipt_minting.enabled", "Market {market} mints receipts"),
+    ("receipt_minting.disabled", "Market {market} does not mint receipts"),
+    ("memo_policy.setting", "Setting memo policy..."),
+    ("memo_policy.required", "Market {market} requires a memo in every purchase"),
+    ("memo_policy.optional", "Market {market} does not require memos"),
+    ("sandwich_guard.setting", "Setting sandwich guard..."),
+    ("sandwich_guard.enabled", "Market {market} guards quoted purchases"),
+    ("sandwich_guard.disabled", "Market {market} does not guard quoted purchases"),
+    ("wormhole.registering", "Registering Wormhole emitter..."),
+    ("wormhole.registered", "Market {market} accepts purchases from emitter {emitter} on chain {chain}"),
+    ("wormhole.disabled", "Market {market} does not accept cross-chain purchases"),
+    ("wormhole.redeeming", "Redeeming cross-chain purchase..."),
+    ("wormhole.redeemed", "Minted {amount} to {recipient} for Wormhole message {sequence}"),
+    ("onramp.setting", "Setting on-ramp signer..."),
+    ("onramp.set", "Market {market} accepts card purchases attested by {signer}"),
+    ("onramp.disabled", "Market {market} does not accept card purchases"),
+    ("fiat_purchase.buying", "Minting card purchase..."),
+    ("fiat_purchase.done", "Minted {amount} to {recipient} for payment {reference}"),
+    ("escrow.setting", "Setting dispute escrow..."),
+    ("escrow.set", "Market {market} escrows purchases of {threshold} or more for {hours} hours"),
+    ("escrow.disabled", "Market {market} does not escrow purchases"),
+    ("escrow.buying", "Buying tokens into escrow..."),
+    ("escrow.bought", "Bought {amount}, held in escrow {escrow} until {release_after}"),
+    ("escrow.releasing", "Releasing escrowed purchase..."),
+    ("escrow.released", "Released {amount} to {recipient}"),
+    ("escrow.disputing", "Disputing escrowed purchase..."),
+    ("escrow.disputed", "Refunded {refund} to {write_off}"),
+    ("coupon.creating", "Creating coupon..."),
+    ("coupon.created", "Coupon {code} at {coupon} waives {discount_bps} bps of {uses} purchases"),
+    ("coupon.applying", "Applying coupon: {discount_bps} bps off, {uses} uses left"),
+    ("gift.buying", "Buying gift..."),
+    ("gift.bought", "Bought {amount} as gift {gift}, claimable until {expiry} with:"),
+    ("gift.claiming", "Claiming gift..."),
+    ("gift.claimed", "Claimed {amount} to {recipient}"),
+    ("gift.refunding", "Refunding expired gift..."),
+    ("gift.refunded", "Refunded {refund} to {write_off}"),
+    ("listing.publishing", "Publishing listing..."),
+    ("listing.published", "Market {market} listing published"),
+    ("holdings_migration.creating", "Creating holdings migration..."),
+    (
+        "holdings_migration.created",
+        "Holdings migration created: migration {migration}, {from_market} -> {to_market} at {numerator}/{denominator}",
+    ),
+    ("holdings.migrating", "Migrating holdings..."),
+    ("holdings.migrated", "Migrated {amount} tokens to {destination}"),
+    ("tokens.selling", "Selling tokens..."),
+    ("tokens.sold", "Sold {amount} tokens, payment sent to {destination}"),
+];
+
+const RU: &[(&str, &str)] = &[
+    ("error", "Ошибка: {message}"),
+    ("config.missing", "Файл конфигурации не указан, а конфигурации по умолчанию нет. Создайте её"),
+    ("addresses.program_missing", "{url} не содержит программу маркета {program}, проверьте кластер или token_market_addresses профиля конфигурации"),
+    ("addresses.other_program", "По адресной книге программа маркета на {url} находится по адресу {program}, а этот CLI собран для {built}"),
+    ("transaction.simulating", "Симуляция транзакции..."),
+    ("transaction.instruction", "  #{index} token market: {instruction}"),
+    ("transaction.instruction_undecodable", "  #{index} token market: данные не декодируются"),
+    ("transaction.instruction_foreign", "  #{index} программа {program}"),
+    ("transaction.log", "  {log}"),
+    ("transaction.simulated", "Симуляция прошла успешно, отправка..."),
+    ("market.creating", "Создание маркета..."),
+    (
+        "market.created",
+        "Маркет создан: маркет {market}, принимаемые токены: {acceptable}, продаваемые токены: {emitter}, банк: {bank}",
+    ),
+    ("market.deprecated", "ВНИМАНИЕ: маркет {market} УСТАРЕЛ"),
+    ("market.successor", "ВНИМАНИЕ: его преемник {successor}"),
+    ("market.deprecation_notice", "ВНИМАНИЕ: подробнее {uri}"),
+    ("deprecation.setting", "Вывод маркета из обращения..."),
+    ("deprecation.set", "Маркет {market} устарел"),
+    ("purchase.buying", "Покупка токенов..."),
+    ("purchase.done", "Куплено {amount} токенов. Получатель {recipient}. Счёт {account}"),
+    ("purchase.receipt", "Квитанция {mint} хранится на {holding}"),
+    ("batch_purchase.buying", "Покупка токенов для {count} получателей..."),
+    ("batch_purchase.done", "Куплено {amount} токенов для {count} получателей в {transactions} транзакциях"),
+    ("delegation.setting", "Настройка делегирования..."),
+    ("delegation.revoked", "{delegate} больше не действует от имени владельца {market}"),
+    ("delegation.set", "{delegate} действует от имени владельца {market}, дневные лимиты: {limits}"),
+    ("vesting.creating", "Создание вестинга..."),
+    ("vesting.created", "Вестинг создан: вестинг {vesting}, хранилище {vault}, бенефициар {beneficiary}"),
+    ("vesting.claiming", "Получение разблокированных токенов..."),
+    ("vesting.claimed", "Разблокированные токены переведены на {destination}"),
+    ("vesting.revoking", "Отзыв вестинга..."),
+    ("vesting.revoked", "Вестинг отозван, заблокированные токены возвращены на {destination}"),
+    ("vesting.cranking", "Поиск вестингов с неполученными токенами..."),
+    ("vesting.crank_sent", "Получено {cranked} из {total} вестингов: {signature}"),
+    ("vesting.cranked", "Разблокированные токены {count} вестингов переведены бенефициарам"),
+    ("garbage.collecting", "Поиск записей маркета для закрытия..."),
+    ("garbage.skipped", "Оставлено квитанций: {count}, их покупатели больше не держат минты квитанций"),
+    ("garbage.collected", "Закрыто записей покупателей: {records}, квитанций: {receipts}, покупателям возвращено {sol} SOL ренты"),
+    ("voucher_signer.setting", "Установка подписанта ваучеров..."),
+    ("voucher_signer.set", "Маркет {market} принимает ваучеры, подписанные 0x{signer}"),
+    ("acceptable.migrating", "Смена принимаемого токена..."),
+    (
+        "acceptable.migrated",
+        "Маркет {market} теперь принимает {acceptable}: банк {bank}, прежний банк {legacy_bank}",
+    ),
+    ("bank.recreating", "Пересоздание банка..."),
+    ("bank.recreated", "Банк маркета {market} теперь {bank}"),
+    ("bank.withdrawing", "Вывод платежей..."),
+    ("bank.withdrawn", "Выведено {amount} из банка на {destination}"),
+    ("fees.setting", "Настройка комиссии протокола..."),
+    ("fees.set", "Маркет {market} оставляет {fee_bps} б.п. каждого платежа в {fee_vault}"),
+    ("fees.withdrawing", "Вывод комиссий протокола..."),
+    ("fees.withdrawn", "Выведено {amount} комиссий на {destination}"),
+    ("purchase_limits.setting", "Настройка лимитов покупки..."),
+    ("purchase_limits.set", "Покупки на маркете {market} оплачивают от {min} до {max}"),
+    ("purchase_limits.unbounded", "Покупки на маркете {market} оплачивают не меньше {min}"),
+    ("wallet_cap.setting", "Настройка лимита на кошелёк..."),
+    ("wallet_cap.set", "Кошельки покупают не больше {cap} на маркете {market}"),
+    ("wallet_spend_cap.setting", "Настройка лимита трат на кошелёк..."),
+    ("wallet_spend_cap.set", "Кошельки тратят не больше {cap} на маркете {market}"),
+    ("purchase_cooldown.setting", "Настройка паузы между покупками..."),
+    ("purchase_cooldown.set", "Кошельки ждут {seconds} секунд между покупками на маркете {market}"),
+    ("loyalty_tiers.setting", "Настройка уровней лояльности..."),
+    ("loyalty_tiers.set", "Покупатели на маркете {market} получают скидки по {count} уровням лояльности"),
+    ("loyalty_tiers.off", "Покупатели на маркете {market} больше не получают скидок за лояльность"),
+    ("stake_weight.setting", "Настройка веса стейка..."),
+    ("stake_weight.set", "Стейки {mint} поднимают лимиты кошельков на маркете {market} на {bonus_bps} bps"),
+    ("stake_weight.off", "Стейки больше не поднимают лимиты кошельков на маркете {market}"),
+    ("withdraw_rate.setting", "Настройка скорости вывода..."),
+    ("withdraw_rate.set", "Банк маркета {market} выдаёт {rate} в день"),
+    ("probe.version", "Программа {program} версии {version} с возможностями {features}"),
+    ("probe.legacy", "Программа {program} старше проверки версий, новые маркеты получают все её возможности"),
+    ("alerts.publishing", "Публикация порогов оповещений..."),
+    ("alerts.published", "Пороги оповещений маркета {market} опубликованы"),
+    ("sale_window.setting", "Планирование продажи..."),
+    ("sale_window.set", "Маркет {market} продаёт с {start} до {end}"),
+    ("allowlist.setting", "Настройка списка допуска..."),
+    ("allowlist.enabled", "Маркет {market} продаёт только покупателям из списка допуска"),
+    ("allowlist.disabled", "Маркет {market} продаёт всем"),
+    ("allowlist.adding", "Добавление покупателя в список допуска..."),
+    ("allowlist.added", "{buyer} может покупать на маркете {market}"),
+    ("allowlist.removing", "Удаление покупателя из списка допуска..."),
+    ("allowlist.removed", "{buyer} исключён из списка допуска маркета {market}"),
+    ("allowlist.root_set", "Маркет {market} допускает {count} покупателей по корню Меркла {root}"),
+    ("market.pausing", "Приостановка маркета..."),
+    ("market.paused", "Маркет {market} приостановлен"),
+    ("market.resuming", "Возобновление маркета..."),
+    ("market.resumed", "Маркет {market} снова торгует"),
+    ("phases.setting", "Установка фаз продажи..."),
+    ("phases.set", "Маркет {market} продаёт в {count} фаз(ы)"),
+    ("phases.advancing", "Переход к следующей фазе продажи..."),
+    ("phases.opened", "Фаза продажи {phase} маркета {market} открыта"),
+    ("phases.closed", "Продажа маркета {market} закрыта"),
+    ("treasury.setting", "Установка казны..."),
+    ("treasury.set", "Казна маркета {market}: {treasury}"),
+    ("overpayment_policy.setting", "Установка политики переплаты..."),
+    ("overpayment_policy.set", "Политика переплаты маркета {market}: {policy}"),
+    ("receipt_minting.setting", "Настройка выпуска квитанций..."),
+    ("receipt_minting.enabled", "Маркет {market} выпускает квитанции"),
+    ("receipt_minting.disabled", "Маркет {market} не выпускает квитанции"),
+    ("memo_policy.setting", "Настройка политики мемо..."),
+    ("memo_policy.required", "Маркет {market} требует мемо в каждой покупке"),
+    ("memo_policy.optional", "Маркет {market} не требует мемо"),
+    ("sandwich_guard.setting", "Настройка защиты от сэндвич-атак..."),
+    ("sandwich_guard.enabled", "Маркет {market} защищает покупки по котировке"),
+    ("sandwich_guard.disabled", "Маркет {market} не защищает покупки по котировке"),
+    ("wormhole.registering", "Регистрация эмиттера Wormhole..."),
+    ("wormhole.registered", "Маркет {market} принимает покупки от эмиттера {emitter} в сети {chain}"),
+    ("wormhole.disabled", "Маркет {market} не принимает покупки из других сетей"),
+    ("wormhole.redeeming", "Получение покупки из другой сети..."),
+    ("wormhole.redeemed", "Выпущено {amount} для {recipient} по сообщению Wormhole {sequence}"),
+    ("onramp.setting", "Установка подписанта on-ramp..."),
+    ("onramp.set", "Маркет {market} принимает покупки картой, подтверждённые {signer}"),
+    ("onramp.disabled", "Маркет {market} не принимает покупки картой"),
+    ("fiat_purchase.buying", "Выпуск покупки картой..."),
+    ("fiat_purchase.done", "Выпущено {amount} для {recipient} по платежу {reference}"),
+    ("escrow.setting", "Настройка эскроу для споров..."),
+    ("escrow.set", "Маркет {market} удерживает покупки от {threshold} на {hours} ч"),
+    ("escrow.disabled", "Маркет {market} не удерживает покупки"),
+    ("escrow.buying", "Покупка токенов в эскроу..."),
+    ("escrow.bought", "Куплено {amount}, удерживается в эскроу {escrow} до {release_after}"),
+    ("escrow.releasing", "Выдача удержанной покупки..."),
+    ("escrow.released", "Выдано {amount} на {recipient}"),
+    ("escrow.disputing", "Оспаривание удержанной покупки..."),
+    ("escrow.disputed", "Возвращено {refund} на {write_off}"),
+    ("coupon.creating", "Создание купона..."),
+    ("coupon.created", "Купон {code} по адресу {coupon} дает скидку {discount_bps} б.п. на {uses} покупок"),
+    ("coupon.applying", "Применение купона: скидка {discount_bps} б.п., осталось использований: {uses}"),
+    ("gift.buying", "Покупка подарка..."),
+    ("gift.bought", "Куплено {amount} в подарок {gift}, получить до {expiry} можно с кодом:"),
+    ("gift.claiming", "Получение подарка..."),
+    ("gift.claimed", "Получено {amount} на {recipient}"),
+    ("gift.refunding", "Возврат просроченного подарка..."),
+    ("gift.refunded", "Возвращено {refund} на {write_off}"),
+    ("listing.publishing", "Публикация листинга..."),
+    ("listing.published", "Листинг маркета {market} опубликован"),
+    ("holdings_migration.creating", "Создание миграции активов..."),
+    (
+        "holdings_migration.created",
+        "Миграция активов создана: миграция {migration}, {from_market} -> {to_market} по курсу {numerator}/{denominator}",
+    ),
+    ("holdings.migrating", "Миграция активов..."),
+    ("holdings.migrated", "Перенесено {amount} токенов на {destination}"),
+    ("tokens.selling", "Продажа токенов..."),
+    ("tokens.sold", "Продано {amount} токенов, оплата отправлена на {destination}"),
+];
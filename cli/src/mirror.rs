@@ -0,0 +1,215 @@
+//! Copies of production markets on other clusters.
+//!
+//! Frontend and QA environments run against markets configured like the
+//! production ones. `mirror` reads a market on one cluster and creates a
+//! market with the same configuration, not its balances, on the cluster the
+//! CLI talks to, then records both in a mapping file:
+//!
+//! ```json
+//! {
+//!   "4uQeVj5tqViQh7yWWGStvkEG1Zmhx6uasJtWCJziofM": {
+//!     "source": { "url": "https://api.mainnet-beta.solana.com", "market": "4uQe…", "acceptable": "EPjF…", "emitter": "9xQe…" },
+//!     "target": { "url": "https://api.devnet.solana.com", "market": "7sPa…", "acceptable": "4zMM…", "emitter": "Gh9Z…" },
+//!     "mirrored_at": 1700000000
+//!   }
+//! }
+//! ```
+//!
+//! Mirroring a market the file already maps on the same cluster updates the
+//! settings of the existing copy instead, so running it on a schedule keeps
+//! the copies tracking production. What is fixed at creation, the features,
+//! tiers and bonding curve, is only copied once. Sale windows and phases are
+//! timestamps of the production schedule and aren't copied. A mapping file
+//! tracks the copies on one cluster, keep one per environment.
+
+use crate::{create_market, reconcile, Config};
+use anyhow::{anyhow, Context, Result};
+use borsh::de::BorshDeserialize;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use token_market::state::TokenMarket;
+use token_market_cli::{
+    addresses::{self, AddressBook, Cluster},
+    reader::MarketReader,
+};
+
+/// A market on one cluster
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MarketCopy {
+    /// RPC URL of the cluster
+    pub url: String,
+    pub market: String,
+    pub acceptable: String,
+    pub emitter: String,
+}
+
+/// A production market and its copy
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct MirrorEntry {
+    pub source: MarketCopy,
+    pub target: MarketCopy,
+    /// Unix timestamp of the last mirroring
+    pub mirrored_at: i64,
+}
+
+/// Mapping file, its entries by source market
+type Mapping = BTreeMap<String, MirrorEntry>;
+
+fn load_mapping(path: &Path) -> Result<Mapping> {
+    if !path.exists() {
+        return Ok(Mapping::new());
+    }
+    let text = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+    serde_json::from_str(&text).with_context(|| format!("Can't parse mapping file {:?}", path))
+}
+
+/// Copy of `market` the mapping already has on the cluster at `url`
+fn existing_copy(mapping: &Mapping, market: &Pubkey, url: &str) -> Result<Option<Pubkey>> {
+    match mapping.get(&market.to_string()) {
+        Some(entry) if entry.target.url == url => Ok(Some(
+            Pubkey::from_str(&entry.target.market)
+                .map_err(|_| anyhow!("Invalid market {} in the mapping", entry.target.market))?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Process `mirror` command: copy `market` of the cluster `source` reads onto
+/// the cluster of `config`, at `target_url`, paying in `acceptable` or else
+/// the mint of the same symbol in `book`
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn mirror(
+    config: &Config,
+    book: &AddressBook,
+    source: &MarketReader,
+    source_url: &str,
+    target_url: &str,
+    market: &Pubkey,
+    acceptable: Option<Pubkey>,
+    mapping_path: &Path,
+) -> Result<()> {
+    let token_market = source.market(market)?;
+    let mut file = reconcile::export_file(source, market)?;
+    let mut mapping = load_mapping(mapping_path)?;
+
+    let copy = match existing_copy(&mapping, market, target_url)? {
+        Some(copy) => copy,
+        None => {
+            let acceptable = match acceptable {
+                Some(acceptable) => acceptable,
+                None => Cluster::from_url(source_url)
+                    .zip(Cluster::from_url(target_url))
+                    .and_then(|(from, to)| {
+                        addresses::mirrored_mint(book, from, to, &token_market.mint_of_acceptable)
+                    })
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "No known counterpart of mint {} on {}, pass --acceptable",
+                            token_market.mint_of_acceptable,
+                            target_url
+                        )
+                    })?,
+            };
+            let params =
+                reconcile::CreationParams::from_file(&file, Some(acceptable), None, None, None)?;
+            let tiers: Vec<_> = token_market
+                .pricing_tiers
+                .iter()
+                .copied()
+                .filter(|tier| tier.is_set())
+                .collect();
+            create_market(
+                config,
+                params.acceptable,
+                params.category,
+                params.project_id,
+                params.features,
+                (token_market.min_purchase, token_market.max_purchase),
+                &tiers,
+                token_market.bonding_curve,
+                token_market.purchase_cooldown_secs,
+            )?
+        }
+    };
+    // the copy keeps its own acceptable mint
+    file.market = None;
+    file.acceptable = None;
+    reconcile::apply_file(config, &file, &copy)?;
+
+    let copied = TokenMarket::try_from_slice(&config.rpc_client.get_account_data(&copy)?)?;
+    mapping.insert(
+        market.to_string(),
+        MirrorEntry {
+            source: MarketCopy {
+                url: source_url.to_string(),
+                market: market.to_string(),
+                acceptable: token_market.mint_of_acceptable.to_string(),
+                emitter: token_market.emitter_mint.to_string(),
+            },
+            target: MarketCopy {
+                url: target_url.to_string(),
+                market: copy.to_string(),
+                acceptable: copied.mint_of_acceptable.to_string(),
+                emitter: copied.emitter_mint.to_string(),
+            },
+            mirrored_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+        },
+    );
+    fs::write(mapping_path, serde_json::to_string_pretty(&mapping)?)
+        .with_context(|| format!("Can't write {:?}", mapping_path))?;
+    println!(
+        "Market {} is mirrored as {} on {}",
+        market, copy, target_url
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(url: &str, market: &Pubkey) -> MirrorEntry {
+        let copy = |url: &str, market: &Pubkey| MarketCopy {
+            url: url.to_string(),
+            market: market.to_string(),
+            acceptable: Pubkey::new_unique().to_string(),
+            emitter: Pubkey::new_unique().to_string(),
+        };
+        MirrorEntry {
+            source: copy("https://api.mainnet-beta.solana.com", &Pubkey::new_unique()),
+            target: copy(url, market),
+            mirrored_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_existing_copy() {
+        let (market, copy) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let devnet = Cluster::Devnet.rpc_url();
+        let mut mapping = Mapping::new();
+        mapping.insert(market.to_string(), entry(devnet, &copy));
+        assert_eq!(
+            existing_copy(&mapping, &market, devnet).unwrap(),
+            Some(copy)
+        );
+        // a copy on another cluster is mirrored anew
+        assert_eq!(
+            existing_copy(&mapping, &market, Cluster::Testnet.rpc_url()).unwrap(),
+            None
+        );
+        assert_eq!(
+            existing_copy(&mapping, &Pubkey::new_unique(), devnet).unwrap(),
+            None
+        );
+
+        let text = serde_json::to_string(&mapping).unwrap();
+        assert_eq!(serde_json::from_str::<Mapping>(&text).unwrap(), mapping);
+    }
+}
@@ -0,0 +1,180 @@
+//! Sales of phased markets compared phase by phase.
+//!
+//! Every purchase on a phased market is counted in the
+//! [SalePhases](../../token_market/state/struct.SalePhases.html) of its
+//! market, so how a presale did against the public sale takes a single
+//! account read instead of a pass over the transaction history. Each purchase
+//! also logs a `PhaseSale` [event](../../token_market/events/enum.Event.html)
+//! for indexers that keep the history anyway.
+//!
+//! ```no_run
+//! use solana_sdk::pubkey::Pubkey;
+//! use token_market_cli::{phases::market_phase_stats, reader::MarketReader};
+//!
+//! let reader = MarketReader::new("https://api.mainnet-beta.solana.com");
+//! let stats = market_phase_stats(&reader, &Pubkey::new_unique()).unwrap();
+//! for phase in &stats.phases {
+//!     println!("phase {} sold {} in {} purchases", phase.phase, phase.sold, phase.purchases);
+//! }
+//! ```
+
+use crate::reader::MarketReader;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use token_market::state::SalePhases;
+
+/// Sales of one phase, or of the phases at the same index of several markets
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PhaseStats {
+    /// Index of the phase, counted from 0
+    pub phase: usize,
+    pub purchases: u64,
+    /// Emitted tokens sold
+    pub sold: u64,
+    /// Acceptable tokens the bank kept
+    pub paid: u64,
+}
+
+impl PhaseStats {
+    /// Emitted tokens of the average purchase, `None` before the first one
+    pub fn average_purchase(&self) -> Option<u64> {
+        self.sold.checked_div(self.purchases)
+    }
+
+    /// Acceptable tokens paid per emitted token, `None` before the first purchase
+    pub fn average_price(&self) -> Option<f64> {
+        match self.sold {
+            0 => None,
+            sold => Some(self.paid as f64 / sold as f64),
+        }
+    }
+
+    fn add(&mut self, other: &PhaseStats) {
+        self.purchases = self.purchases.saturating_add(other.purchases);
+        self.sold = self.sold.saturating_add(other.sold);
+        self.paid = self.paid.saturating_add(other.paid);
+    }
+}
+
+/// Sales of every phase of a market
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MarketPhaseStats {
+    pub market: Pubkey,
+    pub phases: Vec<PhaseStats>,
+}
+
+impl MarketPhaseStats {
+    /// Sales of `phases`, as counted on chain
+    pub fn from_phases(phases: &SalePhases) -> Self {
+        let stats = (0..phases.phases.len())
+            .map(|phase| PhaseStats {
+                phase,
+                purchases: phases.purchases[phase],
+                sold: phases.sold[phase],
+                paid: phases.paid[phase],
+            })
+            .collect();
+        Self {
+            market: phases.market,
+            phases: stats,
+        }
+    }
+
+    /// Sales of the whole market, every phase together
+    pub fn total(&self) -> PhaseStats {
+        let mut total = PhaseStats::default();
+        for phase in &self.phases {
+            total.add(phase);
+        }
+        total
+    }
+
+    /// Share of the emitted tokens of the market the phase at `phase` sold,
+    /// in basis points
+    pub fn share_bps(&self, phase: usize) -> u64 {
+        let total = self.total().sold;
+        match self.phases.get(phase) {
+            Some(stats) if total > 0 => (stats.sold as u128 * 10_000 / total as u128) as u64,
+            _ => 0,
+        }
+    }
+}
+
+/// Sales of every phase of the phased market `market`
+pub fn market_phase_stats(reader: &MarketReader, market: &Pubkey) -> Result<MarketPhaseStats> {
+    let phases = reader
+        .sale_phases(market)?
+        .ok_or_else(|| anyhow!("Market {} does not sell in phases", market))?;
+    Ok(MarketPhaseStats::from_phases(&phases))
+}
+
+/// Sales of several markets added up phase by phase, e.g. every presale
+/// against every public sale of an issuer
+pub fn aggregate_by_phase(markets: &[MarketPhaseStats]) -> Vec<PhaseStats> {
+    let mut phases: Vec<PhaseStats> = vec![];
+    for stats in markets.iter().flat_map(|market| &market.phases) {
+        while phases.len() <= stats.phase {
+            phases.push(PhaseStats {
+                phase: phases.len(),
+                ..PhaseStats::default()
+            });
+        }
+        phases[stats.phase].add(stats);
+    }
+    phases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use token_market::state::SalePhase;
+
+    fn market(sales: &[(u64, u64, u64)]) -> MarketPhaseStats {
+        let mut phases = SalePhases::new(
+            Pubkey::new_unique(),
+            vec![SalePhase::default(); sales.len()],
+            0,
+        );
+        for (index, (purchases, sold, paid)) in sales.iter().enumerate() {
+            phases.purchases[index] = *purchases;
+            phases.sold[index] = *sold;
+            phases.paid[index] = *paid;
+        }
+        MarketPhaseStats::from_phases(&phases)
+    }
+
+    #[test]
+    fn test_market_phase_stats() {
+        let stats = market(&[(4, 300, 150), (2, 100, 100)]);
+        assert_eq!(stats.phases[0].average_purchase(), Some(75));
+        assert_eq!(stats.phases[0].average_price(), Some(0.5));
+        assert_eq!(stats.share_bps(0), 7_500);
+        assert_eq!(stats.share_bps(2), 0);
+        assert_eq!(
+            stats.total(),
+            PhaseStats {
+                phase: 0,
+                purchases: 6,
+                sold: 400,
+                paid: 250,
+            }
+        );
+        assert_eq!(market(&[(0, 0, 0)]).phases[0].average_purchase(), None);
+    }
+
+    #[test]
+    fn test_aggregate_by_phase() {
+        let markets = [
+            market(&[(4, 300, 150)]),
+            market(&[(1, 10, 5), (2, 100, 100)]),
+        ];
+        let phases = aggregate_by_phase(&markets);
+        assert_eq!(phases.len(), 2);
+        assert_eq!(
+            (phases[0].phase, phases[0].purchases, phases[0].sold),
+            (0, 5, 310)
+        );
+        assert_eq!((phases[1].phase, phases[1].paid), (1, 100));
+    }
+}
@@ -0,0 +1,511 @@
+//! Declarative plans for `multi-exec`.
+//!
+//! A plan is a YAML list of steps, each one an owner operation on a market:
+//!
+//! ```yaml
+//! steps:
+//!   - id: sale
+//!     create-market:
+//!       acceptable: So11111111111111111111111111111111111111112
+//!       category: IDO
+//!       features: vesting, receipts
+//!   - set-treasury:
+//!       market: $sale
+//!       treasury: 9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin
+//!   - deprecate:
+//!       market: 4uQeVj5tqViQh7yWWGStvkEG1Zmhx6uasJtWCJziofM
+//!       successor: $sale
+//!       message-uri: https://example.com/moved
+//! ```
+//!
+//! `$id` stands for the market created by the step with that id and makes
+//! the referring step depend on it. Steps on the same market keep their
+//! order in the file. Independent steps are packed into as few transactions
+//! as fit a packet; every `create-market` gets a transaction of its own since
+//! it carries three new signers.
+
+use crate::{create_market_instructions, listing, parse_overpayment_policy, send, voucher, Config};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use solana_sdk::{
+    instruction::Instruction,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::Transaction,
+};
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+use token_market::{
+    instruction,
+    state::{BondingCurve, DeprecationNotice, TokenMarket},
+};
+use token_market_cli::signer::MarketSigner;
+use tracing::info_span;
+
+#[derive(Deserialize)]
+pub struct Plan {
+    pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize)]
+pub struct Step {
+    /// Name other steps use to refer to the market this step creates
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    #[serde(rename_all = "kebab-case")]
+    CreateMarket {
+        acceptable: String,
+        category: Option<String>,
+        project_id: Option<String>,
+        /// Comma separated, all features but selling if omitted
+        features: Option<String>,
+    },
+    SetTreasury {
+        market: String,
+        treasury: String,
+    },
+    SetOverpaymentPolicy {
+        market: String,
+        policy: String,
+    },
+    SetReceiptMinting {
+        market: String,
+        enabled: bool,
+    },
+    SetMemoPolicy {
+        market: String,
+        required: bool,
+    },
+    SetSandwichGuard {
+        market: String,
+        enabled: bool,
+    },
+    #[serde(rename_all = "kebab-case")]
+    SetVoucherSigner {
+        market: String,
+        eth_address: String,
+    },
+    #[serde(rename_all = "kebab-case")]
+    Deprecate {
+        market: String,
+        successor: Option<String>,
+        message_uri: String,
+    },
+}
+
+pub fn load(path: &Path) -> Result<Plan> {
+    let text = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+    serde_yaml::from_str(&text).with_context(|| format!("Can't parse plan {:?}", path))
+}
+
+/// A step with its addresses resolved and instructions built.
+struct Prepared {
+    description: String,
+    instructions: Vec<Instruction>,
+    /// Keypairs of the accounts a `create-market` step allocates
+    new_accounts: Vec<Keypair>,
+    depends_on: Vec<usize>,
+}
+
+/// Steps sent together in one transaction.
+struct Batch {
+    steps: Vec<usize>,
+}
+
+/// Addresses created by the plan, by step id.
+struct Resolver {
+    created: HashMap<String, (usize, Pubkey)>,
+}
+
+impl Resolver {
+    /// Resolve an address or `$id` reference, recording the dependency.
+    fn address(&self, value: &str, depends_on: &mut Vec<usize>) -> Result<Pubkey> {
+        match value.strip_prefix('$') {
+            Some(id) => {
+                let (step, address) = self
+                    .created
+                    .get(id)
+                    .ok_or_else(|| anyhow!("No create-market step has id {}", id))?;
+                depends_on.push(*step);
+                Ok(*address)
+            }
+            None => Pubkey::from_str(value).map_err(|_| anyhow!("Invalid address {}", value)),
+        }
+    }
+}
+
+fn prepare(config: &Config, plan: &Plan) -> Result<Vec<Prepared>> {
+    let mut resolver = Resolver {
+        created: HashMap::new(),
+    };
+    let mut keypairs = vec![];
+    for (index, step) in plan.steps.iter().enumerate() {
+        let accounts = match step.action {
+            Action::CreateMarket { .. } => vec![Keypair::new(), Keypair::new(), Keypair::new()],
+            _ => vec![],
+        };
+        if let Some(id) = &step.id {
+            let market = accounts
+                .first()
+                .ok_or_else(|| anyhow!("Step {}: only create-market steps take an id", id))?;
+            if resolver
+                .created
+                .insert(id.clone(), (index, market.pubkey()))
+                .is_some()
+            {
+                bail!("Step id {} is used twice", id);
+            }
+        }
+        keypairs.push(accounts);
+    }
+
+    let owner = config.owner.pubkey();
+    let mut last_on_market: HashMap<Pubkey, usize> = HashMap::new();
+    let mut prepared = vec![];
+    for (index, (step, new_accounts)) in plan.steps.iter().zip(keypairs).enumerate() {
+        let mut depends_on = vec![];
+        let (market, description, instructions) = match &step.action {
+            Action::CreateMarket {
+                acceptable,
+                category,
+                project_id,
+                features,
+            } => {
+                let acceptable = resolver.address(acceptable, &mut depends_on)?;
+                let category = match category {
+                    Some(category) => listing::parse_category(category)?,
+                    None => [0; 4],
+                };
+                let project_id = match project_id {
+                    Some(project_id) => listing::parse_project_id(project_id)?,
+                    None => [0; 32],
+                };
+                let features = match features {
+                    Some(features) => listing::parse_features(features)?,
+                    None => TokenMarket::DEFAULT_FEATURES,
+                };
+                let market = new_accounts[0].pubkey();
+                let instructions = create_market_instructions(
+                    config,
+                    &market,
+                    &new_accounts[1].pubkey(),
+                    &new_accounts[2].pubkey(),
+                    &acceptable,
+                    category,
+                    project_id,
+                    features,
+                    (0, 0),
+                    &[],
+                    BondingCurve::default(),
+                    0,
+                )?;
+                let description = format!("create market {} accepting {}", market, acceptable);
+                (market, description, instructions)
+            }
+            Action::SetTreasury { market, treasury } => {
+                let market = resolver.address(market, &mut depends_on)?;
+                let treasury = resolver.address(treasury, &mut depends_on)?;
+                let instruction =
+                    instruction::set_treasury(&token_market::id(), &owner, &market, &treasury)?;
+                let description = format!("set treasury of {} to {}", market, treasury);
+                (market, description, vec![instruction])
+            }
+            Action::SetOverpaymentPolicy { market, policy } => {
+                let market = resolver.address(market, &mut depends_on)?;
+                let policy = parse_overpayment_policy(policy)?;
+                let instruction = instruction::set_overpayment_policy(
+                    &token_market::id(),
+                    &owner,
+                    &market,
+                    policy,
+                )?;
+                let description = format!("set overpayment policy of {} to {:?}", market, policy);
+                (market, description, vec![instruction])
+            }
+            Action::SetReceiptMinting { market, enabled } => {
+                let market = resolver.address(market, &mut depends_on)?;
+                let instruction = instruction::set_receipt_minting(
+                    &token_market::id(),
+                    &owner,
+                    &market,
+                    *enabled,
+                )?;
+                let description = format!(
+                    "{} receipt minting on {}",
+                    if *enabled { "enable" } else { "disable" },
+                    market
+                );
+                (market, description, vec![instruction])
+            }
+            Action::SetMemoPolicy { market, required } => {
+                let market = resolver.address(market, &mut depends_on)?;
+                let instruction =
+                    instruction::set_memo_policy(&token_market::id(), &owner, &market, *required)?;
+                let description = format!(
+                    "{} memos on {}",
+                    if *required {
+                        "require"
+                    } else {
+                        "stop requiring"
+                    },
+                    market
+                );
+                (market, description, vec![instruction])
+            }
+            Action::SetSandwichGuard { market, enabled } => {
+                let market = resolver.address(market, &mut depends_on)?;
+                let instruction = instruction::set_sandwich_guard(
+                    &token_market::id(),
+                    &owner,
+                    &market,
+                    *enabled,
+                )?;
+                let description = format!(
+                    "{} sandwich guard on {}",
+                    if *enabled { "enable" } else { "disable" },
+                    market
+                );
+                (market, description, vec![instruction])
+            }
+            Action::SetVoucherSigner {
+                market,
+                eth_address,
+            } => {
+                let market = resolver.address(market, &mut depends_on)?;
+                let eth_address = voucher::parse_eth_address(eth_address)?;
+                let instruction = instruction::set_voucher_signer(
+                    &token_market::id(),
+                    &owner,
+                    &market,
+                    eth_address,
+                )?;
+                let description = format!(
+                    "set voucher signer of {} to 0x{}",
+                    market,
+                    hex::encode(eth_address)
+                );
+                (market, description, vec![instruction])
+            }
+            Action::Deprecate {
+                market,
+                successor,
+                message_uri,
+            } => {
+                let market = resolver.address(market, &mut depends_on)?;
+                let successor = successor
+                    .as_deref()
+                    .map(|successor| resolver.address(successor, &mut depends_on))
+                    .transpose()?;
+                if message_uri.len() > DeprecationNotice::MAX_MESSAGE_URI_LEN {
+                    bail!(
+                        "Step {}: message URI must be at most {} bytes",
+                        index + 1,
+                        DeprecationNotice::MAX_MESSAGE_URI_LEN
+                    );
+                }
+                let instruction = instruction::deprecate(
+                    &token_market::id(),
+                    &owner,
+                    &config.fee_payer.pubkey(),
+                    &market,
+                    successor,
+                    message_uri.clone(),
+                )?;
+                let description = match successor {
+                    Some(successor) => format!("deprecate {} in favour of {}", market, successor),
+                    None => format!("deprecate {}", market),
+                };
+                (market, description, vec![instruction])
+            }
+        };
+        if let Some(previous) = last_on_market.insert(market, index) {
+            depends_on.push(previous);
+        }
+        prepared.push(Prepared {
+            description,
+            instructions,
+            new_accounts,
+            depends_on,
+        });
+    }
+    Ok(prepared)
+}
+
+/// Order the steps into levels: every step runs after all steps it depends on.
+fn levels(steps: &[Prepared]) -> Result<Vec<Vec<usize>>> {
+    let mut level: Vec<Option<usize>> = vec![None; steps.len()];
+    // No dependency chain is longer than the plan, so this settles unless there is a cycle
+    for _ in 0..=steps.len() {
+        let mut changed = false;
+        for (index, step) in steps.iter().enumerate() {
+            let deps: Option<Vec<usize>> = step.depends_on.iter().map(|dep| level[*dep]).collect();
+            if let Some(deps) = deps {
+                let own = deps.into_iter().map(|dep| dep + 1).max().unwrap_or(0);
+                if level[index] != Some(own) {
+                    level[index] = Some(own);
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let mut levels: Vec<Vec<usize>> = vec![];
+    for (index, level) in level.into_iter().enumerate() {
+        let level =
+            level.ok_or_else(|| anyhow!("Step {} is part of a dependency cycle", index + 1))?;
+        if level >= steps.len() {
+            bail!("Step {} is part of a dependency cycle", index + 1);
+        }
+        if levels.len() <= level {
+            levels.resize(level + 1, vec![]);
+        }
+        levels[level].push(index);
+    }
+    Ok(levels)
+}
+
+fn fits(fee_payer: &Pubkey, instructions: &[Instruction]) -> bool {
+    let transaction = Transaction::new_with_payer(instructions, Some(fee_payer));
+    bincode::serialized_size(&transaction)
+        .map(|size| size as usize <= PACKET_DATA_SIZE)
+        .unwrap_or(false)
+}
+
+/// Pack each level into as few transactions as fit a packet.
+fn batches(fee_payer: &Pubkey, steps: &[Prepared], levels: &[Vec<usize>]) -> Vec<Batch> {
+    let mut batches = vec![];
+    for level in levels {
+        let mut current: Vec<usize> = vec![];
+        let mut instructions: Vec<Instruction> = vec![];
+        for &index in level {
+            let step = &steps[index];
+            if !step.new_accounts.is_empty() {
+                batches.push(Batch { steps: vec![index] });
+                continue;
+            }
+            let mut candidate = instructions.clone();
+            candidate.extend_from_slice(&step.instructions);
+            if !current.is_empty() && !fits(fee_payer, &candidate) {
+                batches.push(Batch {
+                    steps: std::mem::take(&mut current),
+                });
+                candidate = step.instructions.clone();
+            }
+            current.push(index);
+            instructions = candidate;
+        }
+        if !current.is_empty() {
+            batches.push(Batch { steps: current });
+        }
+    }
+    batches
+}
+
+pub(crate) fn execute(config: &Config, plan: &Plan, dry_run: bool) -> Result<()> {
+    let steps = prepare(config, plan)?;
+    let levels = levels(&steps)?;
+    let fee_payer = config.fee_payer.pubkey();
+    let batches = batches(&fee_payer, &steps, &levels);
+
+    for (number, batch) in batches.iter().enumerate() {
+        let instructions: Vec<Instruction> = batch
+            .steps
+            .iter()
+            .flat_map(|index| steps[*index].instructions.clone())
+            .collect();
+        if !fits(&fee_payer, &instructions) {
+            bail!("Step {} doesn't fit in a transaction", batch.steps[0] + 1);
+        }
+        println!("Transaction {}:", number + 1);
+        for index in &batch.steps {
+            println!("  step {}: {}", index + 1, steps[*index].description);
+        }
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    let mut landed: Vec<(usize, Signature)> = vec![];
+    for (number, batch) in batches.iter().enumerate() {
+        let instructions: Vec<Instruction> = batch
+            .steps
+            .iter()
+            .flat_map(|index| steps[*index].instructions.clone())
+            .collect();
+        let mut signers: Vec<&dyn MarketSigner> = vec![config.owner.as_ref()];
+        for index in &batch.steps {
+            signers.extend(
+                steps[*index]
+                    .new_accounts
+                    .iter()
+                    .map(|k| k as &dyn MarketSigner),
+            );
+        }
+
+        let span = info_span!(
+            "plan_transaction",
+            number = number + 1,
+            steps = batch.steps.len()
+        );
+        match span.in_scope(|| send(config, &instructions, &signers)) {
+            Ok(signature) => {
+                println!("Transaction {} confirmed: {}", number + 1, signature);
+                landed.push((number, signature));
+            }
+            Err(err) => {
+                report_failure(&steps, &batches, &landed, number);
+                return Err(err.context(format!("Transaction {} failed", number + 1)));
+            }
+        }
+    }
+    println!(
+        "Plan executed: {} steps in {} transactions",
+        steps.len(),
+        batches.len()
+    );
+    Ok(())
+}
+
+/// Transactions that landed before `failed` stay applied; list them next to
+/// the steps that never ran so the operator can revert or resume by hand.
+fn report_failure(
+    steps: &[Prepared],
+    batches: &[Batch],
+    landed: &[(usize, Signature)],
+    failed: usize,
+) {
+    eprintln!(
+        "Plan stopped at transaction {}. Nothing is rolled back.",
+        failed + 1
+    );
+    if landed.is_empty() {
+        eprintln!("No step was applied");
+    } else {
+        eprintln!("Applied:");
+        for (number, signature) in landed {
+            for index in &batches[*number].steps {
+                eprintln!(
+                    "  step {}: {} ({})",
+                    index + 1,
+                    steps[*index].description,
+                    signature
+                );
+            }
+        }
+    }
+    eprintln!("Not applied:");
+    for batch in &batches[failed..] {
+        for index in &batch.steps {
+            eprintln!("  step {}: {}", index + 1, steps[*index].description);
+        }
+    }
+}
@@ -0,0 +1,406 @@
+//! Instruction encoding negotiated with the deployed program.
+//!
+//! Clusters don't upgrade the program at the same time, so a client can talk
+//! to a program older than the one it was built with. [probe](fn.probe.html)
+//! simulates `GetVersion` to learn the
+//! [Capabilities](../../token_market/version/struct.Capabilities.html) of the
+//! deployment, and the encoding helpers leave out what it wouldn't accept:
+//!
+//! ```no_run
+//! use solana_client::rpc_client::RpcClient;
+//! use solana_sdk::pubkey::Pubkey;
+//! use token_market_cli::probe::probe;
+//!
+//! let rpc_client = RpcClient::new("https://api.devnet.solana.com".to_string());
+//! let fee_payer = Pubkey::new_unique();
+//! let capabilities = probe(&rpc_client, &token_market::id(), &fee_payer).unwrap();
+//! match capabilities.version {
+//!     Some(version) => println!("devnet runs {}", version),
+//!     None => println!("devnet runs a program older than GetVersion"),
+//! }
+//! ```
+
+use anyhow::{anyhow, bail, Result};
+use solana_client::{rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig};
+use solana_sdk::{
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    transaction::{Transaction, TransactionError},
+};
+use token_market::{
+    instruction,
+    state::{BondingCurve, PricingTier, TokenMarket},
+    version::Capabilities,
+};
+
+/// Capabilities of the program at `program_id`. The simulation is charged to
+/// `payer`, which must hold lamports but doesn't sign.
+pub fn probe(rpc_client: &RpcClient, program_id: &Pubkey, payer: &Pubkey) -> Result<Capabilities> {
+    let mut transaction =
+        Transaction::new_with_payer(&[instruction::get_version(program_id)], Some(payer));
+    transaction.message.recent_blockhash = rpc_client.get_recent_blockhash()?.0;
+    let result = rpc_client
+        .simulate_transaction_with_config(
+            &transaction,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )?
+        .value;
+    match result.err {
+        None => result
+            .logs
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|line| line.strip_prefix("Program log: "))
+            .find_map(Capabilities::parse)
+            .ok_or_else(|| anyhow!("{} answered GetVersion without its version", program_id)),
+        // Programs that predate the instruction can't decode it
+        Some(TransactionError::InstructionError(0, InstructionError::InvalidInstructionData)) => {
+            Ok(Capabilities::LEGACY)
+        }
+        Some(err) => bail!("Can't probe the program at {}: {}", program_id, err),
+    }
+}
+
+/// Append the trailing fields of `Initialize` the program reads. Requesting
+/// [ALL_FEATURES](../../token_market/state/struct.TokenMarket.html#associatedconstant.ALL_FEATURES)
+/// gets every feature the program knows, naming a feature it doesn't know fails.
+/// `pricing_tiers` are left out when empty, `bonding_curve` when off and
+/// `purchase_cooldown_secs` when 0.
+pub fn initialize_fields(
+    capabilities: &Capabilities,
+    initialize: Instruction,
+    features: u64,
+    purchase_limits: (u64, u64),
+    pricing_tiers: &[PricingTier],
+    bonding_curve: BondingCurve,
+    purchase_cooldown_secs: i64,
+) -> Result<Instruction> {
+    // a program that doesn't know the tiers would ignore them and sell at par
+    if !pricing_tiers.is_empty() && !capabilities.supports(TokenMarket::FEATURE_TIERED_PRICING) {
+        bail!("The deployed program predates pricing tiers");
+    }
+    if bonding_curve.is_set() && !capabilities.supports(TokenMarket::FEATURE_BONDING_CURVE) {
+        bail!("The deployed program predates bonding curves");
+    }
+    if purchase_cooldown_secs != 0 && !capabilities.supports(TokenMarket::FEATURE_PURCHASE_COOLDOWN)
+    {
+        bail!("The deployed program predates purchase cooldowns");
+    }
+    if !capabilities.reads_initialize_fields() {
+        // the program enables all it knows and limits no purchase
+        if features != TokenMarket::ALL_FEATURES {
+            bail!("The deployed program enables every feature of a new market");
+        }
+        if purchase_limits != (0, 0) {
+            bail!("The deployed program predates purchase limits");
+        }
+        return Ok(initialize);
+    }
+
+    let features = match features {
+        TokenMarket::ALL_FEATURES => TokenMarket::ALL_FEATURES & capabilities.features,
+        features if capabilities.supports(features) => features,
+        features => {
+            let unknown: Vec<&str> = TokenMarket::FEATURE_NAMES
+                .iter()
+                .filter(|(_, bit)| features & bit != 0 && !capabilities.supports(*bit))
+                .map(|(name, _)| *name)
+                .collect();
+            bail!(
+                "The deployed program doesn't know the features {}",
+                unknown.join(",")
+            );
+        }
+    };
+    // Trailing fields go in the order the program reads them
+    let (min_purchase, max_purchase) = purchase_limits;
+    let initialize = instruction::with_trailing_field(initialize, &features)?;
+    let initialize = instruction::with_trailing_field(initialize, &min_purchase)?;
+    let initialize = instruction::with_trailing_field(initialize, &max_purchase)?;
+    if pricing_tiers.is_empty() && !bonding_curve.is_set() && purchase_cooldown_secs == 0 {
+        return Ok(initialize);
+    }
+    let initialize = instruction::with_trailing_field(initialize, &pricing_tiers.to_vec())?;
+    if !bonding_curve.is_set() && purchase_cooldown_secs == 0 {
+        return Ok(initialize);
+    }
+    let initialize = instruction::with_trailing_field(initialize, &bonding_curve)?;
+    if purchase_cooldown_secs == 0 {
+        return Ok(initialize);
+    }
+    Ok(instruction::with_trailing_field(
+        initialize,
+        &purchase_cooldown_secs,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshDeserialize;
+    use token_market::{state::CurveKind, version::ProgramVersion};
+
+    fn initialize() -> Instruction {
+        let key = Pubkey::default();
+        instruction::initialize(
+            &token_market::id(),
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &key,
+            &spl_token::id(),
+            [0; 4],
+            [0; 32],
+        )
+        .unwrap()
+    }
+
+    /// Trailing fields of an encoded `Initialize`
+    fn fields(instruction: &Instruction) -> Vec<u64> {
+        let bare = initialize().data.len();
+        instruction.data[bare..]
+            .chunks(8)
+            .map(|field| u64::try_from_slice(field).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_initialize_fields_of_legacy_program() {
+        let legacy = Capabilities::LEGACY;
+        let encoded = initialize_fields(
+            &legacy,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(encoded, initialize());
+        assert!(initialize_fields(
+            &legacy,
+            initialize(),
+            0,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            0
+        )
+        .is_err());
+        assert!(initialize_fields(
+            &legacy,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (1, 0),
+            &[],
+            BondingCurve::default(),
+            0
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_initialize_fields_of_older_program() {
+        let older = Capabilities {
+            version: Some(ProgramVersion::new(0, 1, 0)),
+            features: TokenMarket::FEATURE_RELAYER_TIPS | TokenMarket::FEATURE_COUPONS,
+        };
+        let encoded = initialize_fields(
+            &older,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (5, 10),
+            &[],
+            BondingCurve::default(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(fields(&encoded), vec![older.features, 5, 10]);
+
+        let encoded = initialize_fields(
+            &older,
+            initialize(),
+            TokenMarket::FEATURE_COUPONS,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(fields(&encoded), vec![TokenMarket::FEATURE_COUPONS, 0, 0]);
+
+        let error = initialize_fields(
+            &older,
+            initialize(),
+            TokenMarket::FEATURE_COUPONS | TokenMarket::FEATURE_WALLET_CAPS,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            0,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The deployed program doesn't know the features wallet-caps"
+        );
+    }
+
+    #[test]
+    fn test_initialize_fields_with_pricing_tiers() {
+        let tiers = [
+            PricingTier {
+                up_to: 1_000_000,
+                price_numerator: 2,
+                price_denominator: 1,
+            },
+            PricingTier {
+                up_to: 0,
+                price_numerator: 1,
+                price_denominator: 1,
+            },
+        ];
+        let current = Capabilities {
+            version: Some(ProgramVersion::new(0, 2, 0)),
+            features: TokenMarket::ALL_FEATURES,
+        };
+        let encoded = initialize_fields(
+            &current,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &tiers,
+            BondingCurve::default(),
+            0,
+        )
+        .unwrap();
+        let bare = initialize_fields(
+            &current,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            0,
+        )
+        .unwrap();
+        let mut trailing = &encoded.data[bare.data.len()..];
+        assert_eq!(
+            Vec::<PricingTier>::deserialize(&mut trailing).unwrap(),
+            tiers.to_vec()
+        );
+        assert!(trailing.is_empty());
+
+        let older = Capabilities {
+            version: Some(ProgramVersion::new(0, 1, 0)),
+            features: TokenMarket::FEATURE_COUPONS,
+        };
+        assert!(initialize_fields(
+            &older,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &tiers,
+            BondingCurve::default(),
+            0
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_initialize_fields_with_bonding_curve() {
+        let curve = BondingCurve {
+            kind: CurveKind::Linear,
+            base_price: 1,
+            growth: 1,
+            step: 0,
+        };
+        let current = Capabilities {
+            version: Some(ProgramVersion::new(0, 2, 0)),
+            features: TokenMarket::ALL_FEATURES,
+        };
+        let encoded = initialize_fields(
+            &current,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &[],
+            curve,
+            0,
+        )
+        .unwrap();
+        let bare = initialize_fields(
+            &current,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            0,
+        )
+        .unwrap();
+        // the curve comes after an empty list of tiers
+        let mut trailing = &encoded.data[bare.data.len()..];
+        assert!(Vec::<PricingTier>::deserialize(&mut trailing)
+            .unwrap()
+            .is_empty());
+        assert_eq!(BondingCurve::deserialize(&mut trailing).unwrap(), curve);
+        assert!(trailing.is_empty());
+    }
+
+    #[test]
+    fn test_initialize_fields_with_purchase_cooldown() {
+        let current = Capabilities {
+            version: Some(ProgramVersion::new(0, 2, 0)),
+            features: TokenMarket::ALL_FEATURES,
+        };
+        let encoded = initialize_fields(
+            &current,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            60,
+        )
+        .unwrap();
+        let bare = initialize_fields(
+            &current,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            0,
+        )
+        .unwrap();
+        // the cooldown comes after no tiers and an unset curve
+        let mut trailing = &encoded.data[bare.data.len()..];
+        assert!(Vec::<PricingTier>::deserialize(&mut trailing)
+            .unwrap()
+            .is_empty());
+        assert!(!BondingCurve::deserialize(&mut trailing).unwrap().is_set());
+        assert_eq!(i64::deserialize(&mut trailing).unwrap(), 60);
+        assert!(trailing.is_empty());
+
+        let older = Capabilities {
+            version: Some(ProgramVersion::new(0, 1, 0)),
+            features: TokenMarket::FEATURE_COUPONS,
+        };
+        assert!(initialize_fields(
+            &older,
+            initialize(),
+            TokenMarket::ALL_FEATURES,
+            (0, 0),
+            &[],
+            BondingCurve::default(),
+            60
+        )
+        .is_err());
+    }
+}
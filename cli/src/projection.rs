@@ -0,0 +1,239 @@
+//! Partial views of many markets at once.
+//!
+//! Analytics across thousands of markets rarely read more than a few fields
+//! of each, yet a plain `getProgramAccounts` returns every byte of every
+//! market. [MarketReader::market_views](../reader/struct.MarketReader.html#method.market_views)
+//! asks for the byte ranges of the requested fields only, with the
+//! `dataSlice` of the RPC, and reads them into a
+//! [MarketView](struct.MarketView.html) leaving the other fields `None`:
+//!
+//! ```no_run
+//! use token_market_cli::{projection::MarketField, reader::MarketReader};
+//!
+//! let reader = MarketReader::new("https://api.mainnet-beta.solana.com");
+//! let fields = [MarketField::Owner, MarketField::TotalSold];
+//! for (address, view) in reader.market_views(&fields, &[]).unwrap() {
+//!     println!("{} owned by {:?} sold {:?}", address, view.owner, view.total_sold);
+//! }
+//! ```
+//!
+//! A call returns a single range of every account, so fields lying far apart
+//! are loaded by a call each while close ones share a range, see
+//! [slices](fn.slices.html). Views are joined across calls by address: a
+//! market created or closed between two calls is left out.
+
+use anyhow::{anyhow, Result};
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::BTreeMap, convert::TryInto, ops::Range};
+use token_market::state::TokenMarket;
+
+/// Bytes between two fields below which loading them in one range costs
+/// less than a call of their own, which repeats the address, owner and
+/// lamports of every account it returns
+pub const MAX_GAP: usize = 128;
+
+/// Field of a market a [MarketView](struct.MarketView.html) can hold
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MarketField {
+    Owner,
+    EmitterMint,
+    MintOfAcceptable,
+    Category,
+    ProjectId,
+    Features,
+    Paused,
+    FeeBps,
+    TotalSold,
+}
+
+impl MarketField {
+    /// Bytes of the field in the account data
+    pub fn range(self) -> Range<usize> {
+        let (offset, len) = match self {
+            Self::Owner => (TokenMarket::OWNER_OFFSET, 32),
+            Self::EmitterMint => (TokenMarket::EMITTER_MINT_OFFSET, 32),
+            Self::MintOfAcceptable => (TokenMarket::MINT_OF_ACCEPTABLE_OFFSET, 32),
+            Self::Category => (TokenMarket::CATEGORY_OFFSET, 4),
+            Self::ProjectId => (TokenMarket::PROJECT_ID_OFFSET, 32),
+            Self::Features => (TokenMarket::FEATURES_OFFSET, 8),
+            Self::Paused => (TokenMarket::PAUSED_OFFSET, 1),
+            Self::FeeBps => (TokenMarket::FEE_BPS_OFFSET, 2),
+            Self::TotalSold => (TokenMarket::TOTAL_SOLD_OFFSET, 8),
+        };
+        offset..offset + len
+    }
+
+    /// Set the field of `view` from its `bytes`
+    fn read(self, bytes: &[u8], view: &mut MarketView) {
+        let pubkey = || Pubkey::new(bytes);
+        let number = || u64::from_le_bytes(bytes.try_into().unwrap());
+        match self {
+            Self::Owner => view.owner = Some(pubkey()),
+            Self::EmitterMint => view.emitter_mint = Some(pubkey()),
+            Self::MintOfAcceptable => view.mint_of_acceptable = Some(pubkey()),
+            Self::Category => view.category = Some(bytes.try_into().unwrap()),
+            Self::ProjectId => view.project_id = Some(bytes.try_into().unwrap()),
+            Self::Features => view.features = Some(number()),
+            Self::Paused => view.paused = Some(bytes[0] != 0),
+            Self::FeeBps => view.fee_bps = Some(u16::from_le_bytes(bytes.try_into().unwrap())),
+            Self::TotalSold => view.total_sold = Some(number()),
+        }
+    }
+}
+
+/// Fields of a [TokenMarket](../../token_market/state/struct.TokenMarket.html),
+/// `None` for those not loaded
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MarketView {
+    pub owner: Option<Pubkey>,
+    pub emitter_mint: Option<Pubkey>,
+    pub mint_of_acceptable: Option<Pubkey>,
+    pub category: Option<[u8; 4]>,
+    pub project_id: Option<[u8; 32]>,
+    pub features: Option<u64>,
+    pub paused: Option<bool>,
+    pub fee_bps: Option<u16>,
+    pub total_sold: Option<u64>,
+}
+
+/// Byte range of the account data loaded by one call, and the fields in it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Slice {
+    pub range: Range<usize>,
+    pub fields: Vec<MarketField>,
+}
+
+impl Slice {
+    /// Set the fields of `view` from `data`, the bytes of the slice
+    pub fn read(&self, data: &[u8], view: &mut MarketView) -> Result<()> {
+        if data.len() != self.range.len() {
+            return Err(anyhow!(
+                "Slice of {} bytes returned for {} requested",
+                data.len(),
+                self.range.len()
+            ));
+        }
+        for field in &self.fields {
+            let range = field.range();
+            field.read(
+                &data[range.start - self.range.start..range.end - self.range.start],
+                view,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Ranges loading `fields`, fields less than [MAX_GAP](constant.MAX_GAP.html)
+/// bytes apart sharing one
+pub fn slices(fields: &[MarketField]) -> Vec<Slice> {
+    let mut fields = fields.to_vec();
+    fields.sort_by_key(|field| field.range().start);
+    fields.dedup();
+
+    let mut slices: Vec<Slice> = vec![];
+    for field in fields {
+        let range = field.range();
+        match slices.last_mut() {
+            Some(slice) if range.start <= slice.range.end + MAX_GAP => {
+                slice.range.end = slice.range.end.max(range.end);
+                slice.fields.push(field);
+            }
+            _ => slices.push(Slice {
+                range,
+                fields: vec![field],
+            }),
+        }
+    }
+    slices
+}
+
+/// Views of the markets every slice was loaded for, `loaded` holding the
+/// accounts returned for each of `slices` in turn
+pub fn join(
+    slices: &[Slice],
+    loaded: Vec<Vec<(Pubkey, Vec<u8>)>>,
+) -> Result<Vec<(Pubkey, MarketView)>> {
+    let mut views: BTreeMap<Pubkey, (usize, MarketView)> = BTreeMap::new();
+    for (slice, accounts) in slices.iter().zip(loaded) {
+        for (address, data) in accounts {
+            let (count, view) = views.entry(address).or_default();
+            slice.read(&data, view)?;
+            *count += 1;
+        }
+    }
+    Ok(views
+        .into_iter()
+        .filter(|(_, (count, _))| *count == slices.len())
+        .map(|(address, (_, view))| (address, view))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slices() {
+        let slices = slices(&[
+            MarketField::TotalSold,
+            MarketField::Owner,
+            MarketField::EmitterMint,
+            MarketField::Owner,
+            MarketField::FeeBps,
+        ]);
+        assert_eq!(
+            slices,
+            vec![
+                Slice {
+                    range: TokenMarket::OWNER_OFFSET..TokenMarket::EMITTER_MINT_OFFSET + 32,
+                    fields: vec![MarketField::Owner, MarketField::EmitterMint],
+                },
+                Slice {
+                    range: TokenMarket::FEE_BPS_OFFSET..TokenMarket::FEE_BPS_OFFSET + 2,
+                    fields: vec![MarketField::FeeBps],
+                },
+                Slice {
+                    range: TokenMarket::TOTAL_SOLD_OFFSET..TokenMarket::TOTAL_SOLD_OFFSET + 8,
+                    fields: vec![MarketField::TotalSold],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join() {
+        let slices = slices(&[MarketField::Owner, MarketField::FeeBps]);
+        let (first, second, created) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let owner = Pubkey::new_unique();
+        let loaded = vec![
+            vec![
+                (first, owner.to_bytes().to_vec()),
+                (second, owner.to_bytes().to_vec()),
+            ],
+            vec![
+                (first, 250u16.to_le_bytes().to_vec()),
+                (second, 0u16.to_le_bytes().to_vec()),
+                (created, 0u16.to_le_bytes().to_vec()),
+            ],
+        ];
+
+        let views = join(&slices, loaded).unwrap();
+        assert_eq!(views.len(), 2);
+        let (_, view) = views.iter().find(|(address, _)| *address == first).unwrap();
+        assert_eq!(
+            view,
+            &MarketView {
+                owner: Some(owner),
+                fee_bps: Some(250),
+                ..MarketView::default()
+            }
+        );
+
+        assert!(join(&slices, vec![vec![(first, vec![0; 4])], vec![]]).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Proposal payloads for markets owned by an SPL Governance account.
+//!
+//! A DAO administers its markets by voting on proposals that execute owner
+//! instructions with the governance account as the owner. `gen-proposal`
+//! runs a command with that account as owner and fee payer and prints the
+//! instructions it would send, each one with the base64 of its borsh encoded
+//! `InstructionData`, the form Realms takes for custom instructions.
+
+use anyhow::{bail, Result};
+use borsh::BorshSerialize;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey};
+
+/// `AccountMetaData` of the governance program
+#[derive(BorshSerialize)]
+struct AccountMetaData {
+    pubkey: [u8; 32],
+    is_signer: bool,
+    is_writable: bool,
+}
+
+/// `InstructionData` of the governance program
+#[derive(BorshSerialize)]
+struct InstructionData {
+    program_id: [u8; 32],
+    accounts: Vec<AccountMetaData>,
+    data: Vec<u8>,
+}
+
+/// JSON payload of a proposal executing `instructions` signed by `governance`.
+/// Fails for instructions needing any other signature, e.g. of a new account.
+pub fn payload(governance: &Pubkey, instructions: &[Instruction]) -> Result<String> {
+    let mut items = vec![];
+    for ix in instructions {
+        if let Some(meta) = ix
+            .accounts
+            .iter()
+            .find(|meta| meta.is_signer && meta.pubkey != *governance)
+        {
+            bail!(
+                "The command needs the signature of {}, which a proposal can't give",
+                meta.pubkey
+            );
+        }
+        let serialized = InstructionData {
+            program_id: ix.program_id.to_bytes(),
+            accounts: ix
+                .accounts
+                .iter()
+                .map(|meta| AccountMetaData {
+                    pubkey: meta.pubkey.to_bytes(),
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: ix.data.clone(),
+        }
+        .try_to_vec()?;
+        items.push(serde_json::json!({
+            "program_id": ix.program_id.to_string(),
+            "accounts": ix.accounts.iter().map(|meta| serde_json::json!({
+                "pubkey": meta.pubkey.to_string(),
+                "is_signer": meta.is_signer,
+                "is_writable": meta.is_writable,
+            })).collect::<Vec<_>>(),
+            "data": hex::encode(&ix.data),
+            "serialized": base64::encode(serialized),
+        }));
+    }
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "governance": governance.to_string(),
+        "instructions": items,
+    }))?)
+}
@@ -0,0 +1,155 @@
+//! `quote-server`: signed price quotes for RFQ integrations.
+//!
+//! `GET /quote` answers with a JSON quote signed by the market voucher key:
+//!
+//! ```json
+//! {"market": "...", "numerator": 3, "denominator": 2, "max_amount": 1000,
+//!  "expiry": 1700000000, "quote": "<hex>"}
+//! ```
+//!
+//! `quote` goes to `buy-tokens --quote`, or straight into the first
+//! instruction of a purchase transaction as the secp256k1 instruction data.
+//! The price is either fixed or read from a file on every request, so an
+//! off-chain pricing engine only has to keep that file current.
+
+use crate::voucher;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use token_market::{pricing::Price, state::PriceQuote};
+use tracing::{debug, info, warn};
+
+/// Where quoted prices come from
+pub enum PriceSource {
+    Fixed(Price),
+    /// File holding a price in the format of [parse_price](fn.parse_price.html)
+    File(PathBuf),
+}
+
+impl PriceSource {
+    fn current(&self) -> Result<Price> {
+        match self {
+            PriceSource::Fixed(price) => Ok(*price),
+            PriceSource::File(path) => {
+                let price = fs::read_to_string(path)
+                    .with_context(|| format!("Can't read price file {}", path.display()))?;
+                parse_price(price.trim())
+            }
+        }
+    }
+}
+
+/// Parse a price of emitted tokens per acceptable token given as
+/// `NUMERATOR/DENOMINATOR` or as a whole number
+pub fn parse_price(price: &str) -> Result<Price> {
+    let (numerator, denominator) = match price.find('/') {
+        Some(slash) => (&price[..slash], &price[slash + 1..]),
+        None => (price, "1"),
+    };
+    let price = Price {
+        numerator: numerator
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid price {}", price))?,
+        denominator: denominator
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid price {}", price))?,
+    };
+    if price.numerator == 0 || price.denominator == 0 {
+        bail!("Price must be positive");
+    }
+    Ok(price)
+}
+
+pub struct QuoteServer {
+    pub key: secp256k1::SecretKey,
+    pub market: Pubkey,
+    pub prices: PriceSource,
+    /// Largest purchase a quote covers, in raw acceptable tokens
+    pub max_amount: u64,
+    /// Seconds a quote stays valid
+    pub ttl: i64,
+}
+
+impl QuoteServer {
+    fn quote(&self) -> Result<serde_json::Value> {
+        let price = self.prices.current()?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let quote = PriceQuote {
+            market: self.market,
+            numerator: price.numerator,
+            denominator: price.denominator,
+            max_amount: self.max_amount,
+            expiry: now + self.ttl,
+        };
+        Ok(json!({
+            "market": quote.market.to_string(),
+            "numerator": quote.numerator,
+            "denominator": quote.denominator,
+            "max_amount": quote.max_amount,
+            "expiry": quote.expiry,
+            "quote": voucher::sign(&self.key, &quote)?,
+        }))
+    }
+
+    fn respond(&self, stream: &mut TcpStream) -> Result<()> {
+        let mut request = vec![0; 4096];
+        let len = stream.read(&mut request)?;
+        let request = String::from_utf8_lossy(&request[..len]);
+        let target = request
+            .lines()
+            .next()
+            .and_then(|line| {
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next()) {
+                    (Some("GET"), Some(target)) => Some(target),
+                    _ => None,
+                }
+            })
+            .ok_or_else(|| anyhow!("Malformed request"))?;
+
+        let (status, body) = match target.split('?').next() {
+            Some("/quote") => match self.quote() {
+                Ok(quote) => ("200 OK", quote),
+                Err(err) => (
+                    "503 Service Unavailable",
+                    json!({ "error": err.to_string() }),
+                ),
+            },
+            _ => ("404 Not Found", json!({ "error": "not found" })),
+        };
+        debug!(path = target, status, "quote request");
+        let body = body.to_string();
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        Ok(())
+    }
+
+    /// Serve quotes on `bind` until the process is stopped
+    pub fn serve(&self, bind: &str) -> Result<()> {
+        let listener = TcpListener::bind(bind).with_context(|| format!("Can't bind {}", bind))?;
+        info!(market = %self.market, bind, "serving quotes");
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(anyhow::Error::from)
+                .and_then(|mut stream| self.respond(&mut stream));
+            if let Err(err) = result {
+                warn!(error = %err, "quote request failed");
+            }
+        }
+        Ok(())
+    }
+}
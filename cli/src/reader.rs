@@ -0,0 +1,413 @@
+//! Read-only access to token markets.
+//!
+//! A [MarketReader](struct.MarketReader.html) needs nothing but an RPC URL:
+//! no keypair, no CLI config. Analytics and other integrations that never sign
+//! can look markets up, quote purchases and read their history with it.
+//!
+//! ```no_run
+//! use token_market_cli::reader::MarketReader;
+//!
+//! let reader = MarketReader::new("https://api.mainnet-beta.solana.com");
+//! for (address, market) in reader.markets(&[]).unwrap() {
+//!     println!("{} sells {}", address, market.emitter_mint);
+//! }
+//! ```
+
+use crate::{
+    projection::{self, MarketField, MarketView},
+    throttle::{Throttle, ThrottleSettings, ThrottledClient},
+};
+use anyhow::{anyhow, bail, Result};
+use borsh::de::BorshDeserialize;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::{
+    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
+};
+use solana_sdk::{
+    account::Account, commitment_config::CommitmentConfig, program_pack::Pack, pubkey::Pubkey,
+    rent::Rent, signature::Signature,
+};
+use solana_transaction_status::UiTransactionEncoding;
+use spl_token::state::Mint;
+use std::str::FromStr;
+use token_market::{
+    amounts::{AcceptableAmount, EmittedAmount},
+    curve,
+    events::Event,
+    filters::{self, MemcmpFilter},
+    pricing::emitted_across_tiers,
+    quote::{quote_purchase, PurchaseSetup, Quote},
+    state::{
+        find_campaign_address, find_listing_address, find_notification_address,
+        find_sale_phases_address, find_tenant_address, CampaignStats, ListingInfo,
+        NotificationConfig, SalePhases, TenantStats, TokenMarket,
+    },
+    validation::FetchedAccount,
+};
+use tracing::instrument;
+
+/// A confirmed transaction that touched a market, with the events it logged for it
+pub struct MarketTransaction {
+    pub signature: Signature,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    /// The transaction failed, so its events were rolled back
+    pub failed: bool,
+    pub events: Vec<Event>,
+}
+
+pub struct MarketReader {
+    rpc_client: ThrottledClient,
+}
+
+impl MarketReader {
+    /// Reader of the cluster at `json_rpc_url`, at confirmed commitment,
+    /// throttled to the default limits
+    pub fn new(json_rpc_url: impl ToString) -> Self {
+        Self::with_throttle(json_rpc_url, Throttle::new(ThrottleSettings::default()))
+    }
+
+    /// Reader of the cluster at `json_rpc_url` sharing `throttle` with other clients
+    pub fn with_throttle(json_rpc_url: impl ToString, throttle: Throttle) -> Self {
+        Self::from_client(ThrottledClient::new(
+            RpcClient::new_with_commitment(json_rpc_url.to_string(), CommitmentConfig::confirmed()),
+            throttle,
+        ))
+    }
+
+    pub fn from_client(rpc_client: ThrottledClient) -> Self {
+        Self { rpc_client }
+    }
+
+    pub fn rpc_client(&self) -> &ThrottledClient {
+        &self.rpc_client
+    }
+
+    /// The account at `address`, `None` if there is none
+    #[instrument(level = "debug", skip(self))]
+    fn account(&self, address: &Pubkey) -> Result<Option<Account>> {
+        Ok(self
+            .rpc_client
+            .get_account_with_commitment(address, self.rpc_client.commitment())?
+            .value)
+    }
+
+    /// An account at `address` the token market program owns, `None` if there is none
+    fn program_account(&self, address: &Pubkey) -> Result<Option<Account>> {
+        match self.account(address)? {
+            Some(account) if account.owner != token_market::id() => {
+                bail!("{} is not owned by the token market program", address)
+            }
+            account => Ok(account),
+        }
+    }
+
+    pub fn market(&self, market: &Pubkey) -> Result<TokenMarket> {
+        let account = self
+            .program_account(market)?
+            .ok_or_else(|| anyhow!("Market {} does not exist", market))?;
+        Ok(TokenMarket::try_from_slice(&account.data)?)
+    }
+
+    /// Initialized markets matching every filter of [filters](../../token_market/filters/index.html)
+    #[instrument(level = "debug", skip(self))]
+    pub fn markets(&self, memcmp: &[MemcmpFilter]) -> Result<Vec<(Pubkey, TokenMarket)>> {
+        let mut rpc_filters = vec![RpcFilterType::DataSize(filters::MARKET_DATA_SIZE)];
+        rpc_filters.extend(memcmp.iter().map(|filter| {
+            RpcFilterType::Memcmp(Memcmp {
+                offset: filter.offset,
+                bytes: MemcmpEncodedBytes::Binary(filter.encoded_bytes()),
+                encoding: None,
+            })
+        }));
+        let accounts = self.rpc_client.retrying(|client| {
+            client.get_program_accounts_with_config(
+                &token_market::id(),
+                RpcProgramAccountsConfig {
+                    filters: Some(rpc_filters.clone()),
+                    account_config: RpcAccountInfoConfig::default(),
+                },
+            )
+        })?;
+
+        Ok(accounts
+            .into_iter()
+            .filter_map(
+                |(address, account)| match TokenMarket::try_from_slice(&account.data) {
+                    Ok(market) if market.is_initialized => Some((address, market)),
+                    _ => None,
+                },
+            )
+            .collect())
+    }
+
+    /// Views holding `fields` of the initialized markets matching every
+    /// filter of [filters](../../token_market/filters/index.html), loading
+    /// only the bytes of the fields, see [projection](../projection/index.html)
+    #[instrument(level = "debug", skip(self))]
+    pub fn market_views(
+        &self,
+        fields: &[MarketField],
+        memcmp: &[MemcmpFilter],
+    ) -> Result<Vec<(Pubkey, MarketView)>> {
+        let mut rpc_filters = vec![RpcFilterType::DataSize(filters::MARKET_DATA_SIZE)];
+        rpc_filters.extend(
+            memcmp
+                .iter()
+                .chain(Some(&filters::initialized()))
+                .map(|filter| {
+                    RpcFilterType::Memcmp(Memcmp {
+                        offset: filter.offset,
+                        bytes: MemcmpEncodedBytes::Binary(filter.encoded_bytes()),
+                        encoding: None,
+                    })
+                }),
+        );
+        let slices = projection::slices(fields);
+        let mut loaded = vec![];
+        for slice in &slices {
+            let accounts = self.rpc_client.retrying(|client| {
+                client.get_program_accounts_with_config(
+                    &token_market::id(),
+                    RpcProgramAccountsConfig {
+                        filters: Some(rpc_filters.clone()),
+                        account_config: RpcAccountInfoConfig {
+                            encoding: Some(UiAccountEncoding::Base64),
+                            data_slice: Some(UiDataSliceConfig {
+                                offset: slice.range.start,
+                                length: slice.range.len(),
+                            }),
+                            ..RpcAccountInfoConfig::default()
+                        },
+                    },
+                )
+            })?;
+            loaded.push(
+                accounts
+                    .into_iter()
+                    .map(|(address, account)| (address, account.data))
+                    .collect(),
+            );
+        }
+        projection::join(&slices, loaded)
+    }
+
+    /// The market issuing `emitter_mint`, `None` if no market does
+    #[instrument(level = "debug", skip(self))]
+    pub fn find_market(&self, emitter_mint: &Pubkey) -> Option<Pubkey> {
+        filters::find_market_by_emitter(
+            &|address: &Pubkey| {
+                self.rpc_client
+                    .get_account(address)
+                    .ok()
+                    .map(FetchedAccount::from)
+            },
+            &token_market::id(),
+            emitter_mint,
+        )
+    }
+
+    /// The listing published for `market`, `None` if there is none
+    pub fn listing(&self, market: &Pubkey) -> Result<Option<ListingInfo>> {
+        let address = find_listing_address(&token_market::id(), market).0;
+        match self.program_account(&address)? {
+            Some(account) => Ok(Some(ListingInfo::load(&account.data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Alert thresholds and webhook `owner` published for `market`, `None`
+    /// if it published none
+    pub fn notification_config(
+        &self,
+        market: &Pubkey,
+        owner: &Pubkey,
+    ) -> Result<Option<NotificationConfig>> {
+        let address = find_notification_address(&token_market::id(), market, owner).0;
+        match self.program_account(&address)? {
+            Some(account) => Ok(Some(NotificationConfig::load(&account.data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The phases `market` sells in, `None` if it never had any
+    pub fn sale_phases(&self, market: &Pubkey) -> Result<Option<SalePhases>> {
+        let address = find_sale_phases_address(&token_market::id(), market).0;
+        match self.program_account(&address)? {
+            Some(account) => Ok(Some(SalePhases::load(&account.data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Sales of `campaign` on `market`, `None` before its first sale
+    pub fn campaign_stats(
+        &self,
+        market: &Pubkey,
+        campaign: &[u8; 8],
+    ) -> Result<Option<CampaignStats>> {
+        let address = find_campaign_address(&token_market::id(), market, campaign).0;
+        match self.program_account(&address)? {
+            Some(account) => Ok(Some(CampaignStats::try_from_slice(&account.data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Usage of the deployment by the market owner `owner`, `None` before
+    /// its first market counted in them
+    pub fn tenant_stats(&self, owner: &Pubkey) -> Result<Option<TenantStats>> {
+        let address = find_tenant_address(&token_market::id(), owner).0;
+        match self.program_account(&address)? {
+            Some(account) => Ok(Some(TenantStats::try_from_slice(&account.data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Usage of the deployment by every market owner counted in tenant stats
+    #[instrument(level = "debug", skip(self))]
+    pub fn tenants(&self) -> Result<Vec<TenantStats>> {
+        let accounts = self.rpc_client.retrying(|client| {
+            client.get_program_accounts_with_config(
+                &token_market::id(),
+                RpcProgramAccountsConfig {
+                    filters: Some(vec![RpcFilterType::DataSize(filters::TENANT_DATA_SIZE)]),
+                    account_config: RpcAccountInfoConfig::default(),
+                },
+            )
+        })?;
+
+        Ok(accounts
+            .into_iter()
+            .filter_map(|(_, account)| TenantStats::try_from_slice(&account.data).ok())
+            .collect())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    pub fn decimals(&self, mint: &Pubkey) -> Result<u8> {
+        Ok(Mint::unpack(&self.rpc_client.get_account_data(mint)?)?.decimals)
+    }
+
+    /// Quote buying `amount` on `market` for `recipient` as things are on chain now,
+    /// at the tier or bonding curve price of the market if it has one.
+    /// `buyer_signs` when the buyer is not the fee payer.
+    #[instrument(level = "debug", skip(self))]
+    pub fn quote(
+        &self,
+        market: &Pubkey,
+        recipient: &Pubkey,
+        amount: AcceptableAmount,
+        tip: AcceptableAmount,
+        campaign: Option<[u8; 8]>,
+        buyer_signs: bool,
+    ) -> Result<Quote> {
+        let token_market = self.market(market)?;
+        let recipient_acc = spl_associated_token_account::get_associated_token_address(
+            recipient,
+            &token_market.emitter_mint,
+        );
+        let open_campaign = match campaign {
+            Some(campaign) => self.campaign_stats(market, &campaign)?.is_none(),
+            None => false,
+        };
+        let (_, fee_calculator) = self.rpc_client.get_recent_blockhash()?;
+
+        let mut quote = quote_purchase(
+            amount,
+            tip,
+            PurchaseSetup {
+                create_recipient: self.account(&recipient_acc)?.is_none(),
+                mint_receipt: token_market.mint_receipts,
+                open_campaign,
+                buyer_signs,
+            },
+            &Rent::default(),
+            fee_calculator.lamports_per_signature,
+        );
+        let emitted = if token_market.has_bonding_curve() {
+            curve::emitted_for(
+                &token_market.bonding_curve,
+                token_market.total_sold,
+                amount.0,
+            )
+        } else if token_market.has_pricing_tiers() {
+            emitted_across_tiers(
+                &token_market.pricing_tiers,
+                token_market.total_sold,
+                amount.0,
+            )
+        } else {
+            Some(quote.emitted.0)
+        };
+        quote.emitted =
+            EmittedAmount(emitted.ok_or_else(|| {
+                anyhow!("Market {} can't price a purchase of {}", market, amount.0)
+            })?);
+        Ok(quote)
+    }
+
+    /// Up to `limit` latest transactions of `market`, newest first
+    #[instrument(level = "debug", skip(self))]
+    pub fn history(&self, market: &Pubkey, limit: usize) -> Result<Vec<MarketTransaction>> {
+        let signatures = self.rpc_client.retrying(|client| {
+            client.get_confirmed_signatures_for_address2_with_config(
+                market,
+                GetConfirmedSignaturesForAddress2Config {
+                    limit: Some(limit),
+                    ..GetConfirmedSignaturesForAddress2Config::default()
+                },
+            )
+        })?;
+
+        signatures
+            .into_iter()
+            .map(|status| {
+                let signature = Signature::from_str(&status.signature)?;
+                let transaction = self.rpc_client.retrying(|client| {
+                    client.get_confirmed_transaction(&signature, UiTransactionEncoding::Json)
+                })?;
+                let logs = transaction
+                    .transaction
+                    .meta
+                    .and_then(|meta| meta.log_messages)
+                    .unwrap_or_default();
+                Ok(MarketTransaction {
+                    signature,
+                    slot: status.slot,
+                    block_time: status.block_time,
+                    failed: status.err.is_some(),
+                    events: program_events(&logs)
+                        .into_iter()
+                        .filter(|event| event.market() == market)
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Events the token market program logged, skipping look-alike lines of other
+/// programs it calls or is called by
+pub fn program_events(logs: &[String]) -> Vec<Event> {
+    let program_id = token_market::id().to_string();
+    // Programs being executed, innermost last
+    let mut invoked: Vec<&str> = vec![];
+    let mut events = vec![];
+    for line in logs {
+        if let Some(message) = line.strip_prefix("Program log: ") {
+            if invoked.last() == Some(&program_id.as_str()) {
+                events.extend(Event::parse(message));
+            }
+        } else if let Some(rest) = line.strip_prefix("Program ") {
+            let mut words = rest.split(' ');
+            match (words.next(), words.next()) {
+                (Some(program), Some("invoke")) => invoked.push(program),
+                (Some(_), Some("success")) | (Some(_), Some("failed:")) => {
+                    invoked.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    events
+}
@@ -0,0 +1,62 @@
+//! Recipient files of `buy-multi`
+
+use anyhow::{anyhow, Context, Result};
+use solana_sdk::pubkey::Pubkey;
+use std::{fs, path::Path, str::FromStr};
+use token_market::amounts::UiAmount;
+
+/// Recipient file with one wallet address and the amount paid for it per
+/// line, separated by whitespace or a comma. Empty lines and lines starting
+/// with `#` are ignored.
+pub fn load(path: &Path) -> Result<Vec<(Pubkey, UiAmount)>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Can't read recipients {}", path.display()))?;
+    parse(&content).with_context(|| format!("Invalid recipients in {}", path.display()))
+}
+
+fn parse(content: &str) -> Result<Vec<(Pubkey, UiAmount)>> {
+    let mut purchases = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|field| !field.is_empty());
+        let (wallet, amount) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(wallet), Some(amount), None) => (wallet, amount),
+            _ => return Err(anyhow!("Line {} isn't WALLET AMOUNT", number + 1)),
+        };
+        let wallet = Pubkey::from_str(wallet)
+            .map_err(|_| anyhow!("Invalid address {} at line {}", wallet, number + 1))?;
+        let amount = UiAmount::from_str(amount)
+            .map_err(|_| anyhow!("Invalid amount {} at line {}", amount, number + 1))?;
+        purchases.push((wallet, amount));
+    }
+    if purchases.is_empty() {
+        return Err(anyhow!("No recipients listed"));
+    }
+    Ok(purchases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let content = format!("# airdrop\n{} 1.5\n\n{},2\n", first, second);
+        assert_eq!(
+            parse(&content).unwrap(),
+            vec![
+                (first, UiAmount::from_str("1.5").unwrap()),
+                (second, UiAmount::from_str("2").unwrap()),
+            ]
+        );
+        assert!(parse(&format!("{}\n", first)).is_err());
+        assert!(parse(&format!("{} 1 2\n", first)).is_err());
+        assert!(parse("# nobody\n").is_err());
+    }
+}
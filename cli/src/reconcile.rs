@@ -0,0 +1,557 @@
+//! Declarative market settings for `diff`, `apply`, `export-config` and
+//! `create-market --from-config`.
+//!
+//! The file lists the mutable settings the owner wants, for example:
+//!
+//! ```toml
+//! market = "4uQeVj5tqViQh7yWWGStvkEG1Zmhx6uasJtWCJziofM"
+//! treasury = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"
+//! overpayment-policy = "refund"
+//! mint-receipts = true
+//! require-memo = true
+//! sandwich-guard = true
+//! voucher-signer = "0x0000000000000000000000000000000000000000"
+//! onramp-signer = "11111111111111111111111111111111"
+//!
+//! [listing]
+//! website = "https://example.com"
+//! logo-uri = "https://example.com/logo.png"
+//! contact = "team@example.com"
+//!
+//! [wormhole]
+//! chain = 2
+//! emitter = "0x0000000000000000000000000000000000000000"
+//!
+//! [dispute-escrow]
+//! threshold = 1000000000
+//! window = 86400
+//! guardian = "11111111111111111111111111111111"
+//!
+//! [purchase-limits]
+//! min = 1000000
+//! max = 0
+//! ```
+//!
+//! Settings left out of the file are not managed and keep their on-chain value.
+//! `acceptable`, `category`, `project-id` and `features` are fixed when a
+//! market is created, only `create-market --from-config` reads them.
+//! Amounts are raw and the dispute window is in seconds, as the program stores them.
+
+use crate::{listing, parse_overpayment_policy, parse_wormhole_emitter, send, voucher, Config};
+use anyhow::{anyhow, bail, Context, Result};
+use borsh::de::BorshDeserialize;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{hash::hash, instruction::Instruction, pubkey::Pubkey};
+use std::{fs, path::Path, str::FromStr};
+use token_market::{
+    amounts::{AcceptableAmount, EmittedAmount},
+    instruction,
+    state::{find_listing_address, ListingInfo, TokenMarket},
+};
+use token_market_cli::reader::MarketReader;
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MarketFile {
+    /// Market the file describes, unless given on the command line
+    pub market: Option<String>,
+    /// Mint of the payment token, usually differs between clusters
+    pub acceptable: Option<String>,
+    pub category: Option<String>,
+    /// 32 byte project identifier, as hex
+    pub project_id: Option<String>,
+    /// Comma separated feature names
+    pub features: Option<String>,
+    pub treasury: Option<String>,
+    pub overpayment_policy: Option<String>,
+    pub mint_receipts: Option<bool>,
+    pub require_memo: Option<bool>,
+    pub sandwich_guard: Option<bool>,
+    pub voucher_signer: Option<String>,
+    pub onramp_signer: Option<String>,
+    pub listing: Option<Listing>,
+    pub wormhole: Option<Wormhole>,
+    pub dispute_escrow: Option<DisputeEscrow>,
+    pub purchase_limits: Option<PurchaseLimits>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Listing {
+    pub website: String,
+    /// Only the hash is on chain, the published one is kept when omitted
+    pub logo_uri: Option<String>,
+    /// Only the hash is on chain, the published one is kept when omitted
+    pub contact: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Wormhole {
+    pub chain: u16,
+    /// Emitter address on `chain`, as hex
+    pub emitter: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DisputeEscrow {
+    /// Raw emitted amount from which purchases are escrowed, 0 stops escrowing
+    pub threshold: u64,
+    /// Seconds an escrowed purchase can be disputed
+    pub window: i64,
+    pub guardian: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PurchaseLimits {
+    /// Raw acceptable amount a purchase must pay at least
+    pub min: u64,
+    /// Raw acceptable amount a purchase may pay at most, 0 for no limit
+    pub max: u64,
+}
+
+pub fn load(path: &Path) -> Result<MarketFile> {
+    let text = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+    toml::from_str(&text).with_context(|| format!("Can't parse market settings {:?}", path))
+}
+
+fn parse_pubkey(what: &str, address: &str) -> Result<Pubkey> {
+    Pubkey::from_str(address).map_err(|_| anyhow!("Invalid {} {}", what, address))
+}
+
+/// A setting whose on-chain value differs from the file.
+struct Change {
+    description: String,
+    instruction: Instruction,
+}
+
+/// The market to reconcile, from the command line or else the file.
+fn market_address(file: &MarketFile, market: Option<Pubkey>) -> Result<Pubkey> {
+    match (market, &file.market) {
+        (Some(market), _) => Ok(market),
+        (None, Some(market)) => parse_pubkey("market address", market),
+        (None, None) => bail!("The market is neither given nor named in the file"),
+    }
+}
+
+/// The market owner and the changes that bring the market in line with `file`
+fn changes(config: &Config, file: &MarketFile, market: &Pubkey) -> Result<(Pubkey, Vec<Change>)> {
+    let market_data = config.rpc_client.get_account_data(market)?;
+    let token_market = TokenMarket::try_from_slice(market_data.as_slice())?;
+    let owner = token_market.owner;
+    let mut changes = vec![];
+
+    if let Some(treasury) = &file.treasury {
+        let treasury = parse_pubkey("treasury", treasury)?;
+        if token_market.treasury != treasury {
+            changes.push(Change {
+                description: format!("treasury: {} -> {}", token_market.treasury, treasury),
+                instruction: instruction::set_treasury(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    &treasury,
+                )?,
+            });
+        }
+    }
+
+    if let Some(policy) = &file.overpayment_policy {
+        let policy = parse_overpayment_policy(policy)?;
+        if token_market.overpayment_policy != policy {
+            changes.push(Change {
+                description: format!(
+                    "overpayment policy: {:?} -> {:?}",
+                    token_market.overpayment_policy, policy
+                ),
+                instruction: instruction::set_overpayment_policy(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    policy,
+                )?,
+            });
+        }
+    }
+
+    if let Some(enabled) = file.mint_receipts {
+        if token_market.mint_receipts != enabled {
+            changes.push(Change {
+                description: format!(
+                    "mint receipts: {} -> {}",
+                    token_market.mint_receipts, enabled
+                ),
+                instruction: instruction::set_receipt_minting(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    enabled,
+                )?,
+            });
+        }
+    }
+
+    if let Some(required) = file.require_memo {
+        if token_market.require_memo != required {
+            changes.push(Change {
+                description: format!(
+                    "require memo: {} -> {}",
+                    token_market.require_memo, required
+                ),
+                instruction: instruction::set_memo_policy(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    required,
+                )?,
+            });
+        }
+    }
+
+    if let Some(enabled) = file.sandwich_guard {
+        if token_market.sandwich_guard != enabled {
+            changes.push(Change {
+                description: format!(
+                    "sandwich guard: {} -> {}",
+                    token_market.sandwich_guard, enabled
+                ),
+                instruction: instruction::set_sandwich_guard(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    enabled,
+                )?,
+            });
+        }
+    }
+
+    if let Some(eth_address) = &file.voucher_signer {
+        let eth_address = voucher::parse_eth_address(eth_address)?;
+        if token_market.voucher_signer != eth_address {
+            changes.push(Change {
+                description: format!(
+                    "voucher signer: 0x{} -> 0x{}",
+                    hex::encode(token_market.voucher_signer),
+                    hex::encode(eth_address)
+                ),
+                instruction: instruction::set_voucher_signer(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    eth_address,
+                )?,
+            });
+        }
+    }
+
+    if let Some(signer) = &file.onramp_signer {
+        let signer = parse_pubkey("on-ramp signer", signer)?;
+        if token_market.onramp_signer != signer {
+            changes.push(Change {
+                description: format!(
+                    "on-ramp signer: {} -> {}",
+                    token_market.onramp_signer, signer
+                ),
+                instruction: instruction::set_onramp_signer(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    &signer,
+                )?,
+            });
+        }
+    }
+
+    if let Some(listing) = &file.listing {
+        if listing.website.len() > ListingInfo::MAX_WEBSITE_LEN {
+            bail!(
+                "Website must be at most {} bytes",
+                ListingInfo::MAX_WEBSITE_LEN
+            );
+        }
+        let address = find_listing_address(&token_market::id(), market).0;
+        let current = match config.rpc_client.get_account(&address) {
+            Ok(account) if account.owner == token_market::id() => {
+                Some(ListingInfo::load(&account.data)?)
+            }
+            _ => None,
+        };
+        let hash_or_current =
+            |text: &Option<String>, current_hash: fn(&ListingInfo) -> [u8; 32]| match (
+                text, &current,
+            ) {
+                (Some(text), _) => hash(text.as_bytes()).to_bytes(),
+                (None, Some(current)) => current_hash(current),
+                (None, None) => [0; 32],
+            };
+        let logo_uri_hash = hash_or_current(&listing.logo_uri, |current| current.logo_uri_hash);
+        let contact_hash = hash_or_current(&listing.contact, |current| current.contact_hash);
+        let up_to_date = current.as_ref().map_or(false, |current| {
+            current.website == listing.website
+                && current.logo_uri_hash == logo_uri_hash
+                && current.contact_hash == contact_hash
+        });
+        if !up_to_date {
+            let description = match current {
+                Some(current) if current.website != listing.website => {
+                    format!("listing: {} -> {}", current.website, listing.website)
+                }
+                Some(_) => format!("listing: update logo or contact of {}", listing.website),
+                None => format!("listing: publish {}", listing.website),
+            };
+            changes.push(Change {
+                description,
+                instruction: instruction::set_listing_info(
+                    &token_market::id(),
+                    &owner,
+                    &config.fee_payer.pubkey(),
+                    market,
+                    listing.website.clone(),
+                    logo_uri_hash,
+                    contact_hash,
+                )?,
+            });
+        }
+    }
+
+    if let Some(wormhole) = &file.wormhole {
+        let emitter = parse_wormhole_emitter(&wormhole.emitter)?;
+        if token_market.wormhole_chain != wormhole.chain || token_market.wormhole_emitter != emitter
+        {
+            changes.push(Change {
+                description: format!(
+                    "wormhole emitter: {}:0x{} -> {}:0x{}",
+                    token_market.wormhole_chain,
+                    hex::encode(token_market.wormhole_emitter),
+                    wormhole.chain,
+                    hex::encode(emitter)
+                ),
+                instruction: instruction::set_wormhole_emitter(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    wormhole.chain,
+                    emitter,
+                )?,
+            });
+        }
+    }
+
+    if let Some(escrow) = &file.dispute_escrow {
+        let guardian = match &escrow.guardian {
+            Some(guardian) => parse_pubkey("dispute guardian", guardian)?,
+            None => Pubkey::default(),
+        };
+        if token_market.escrow_threshold != escrow.threshold
+            || token_market.dispute_window != escrow.window
+            || token_market.dispute_guardian != guardian
+        {
+            changes.push(Change {
+                description: format!(
+                    "dispute escrow: {} for {}s by {} -> {} for {}s by {}",
+                    token_market.escrow_threshold,
+                    token_market.dispute_window,
+                    token_market.dispute_guardian,
+                    escrow.threshold,
+                    escrow.window,
+                    guardian
+                ),
+                instruction: instruction::set_dispute_escrow(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    EmittedAmount(escrow.threshold),
+                    escrow.window,
+                    &guardian,
+                )?,
+            });
+        }
+    }
+
+    if let Some(limits) = &file.purchase_limits {
+        if token_market.min_purchase != limits.min || token_market.max_purchase != limits.max {
+            changes.push(Change {
+                description: format!(
+                    "purchase limits: {}..{} -> {}..{}",
+                    token_market.min_purchase, token_market.max_purchase, limits.min, limits.max
+                ),
+                instruction: instruction::set_purchase_limits(
+                    &token_market::id(),
+                    &owner,
+                    market,
+                    AcceptableAmount(limits.min),
+                    AcceptableAmount(limits.max),
+                )?,
+            });
+        }
+    }
+
+    Ok((owner, changes))
+}
+
+fn print_changes(market: &Pubkey, changes: &[Change]) {
+    if changes.is_empty() {
+        println!("Market {} matches the file", market);
+        return;
+    }
+    println!("Market {} differs from the file:", market);
+    for change in changes {
+        println!("  {}", change.description);
+    }
+}
+
+/// Process `diff` command
+pub(crate) fn diff(config: &Config, path: &Path, market: Option<Pubkey>) -> Result<()> {
+    let file = load(path)?;
+    let market = market_address(&file, market)?;
+    let (_, changes) = changes(config, &file, &market)?;
+    print_changes(&market, &changes);
+    Ok(())
+}
+
+/// Process `apply` command, sending only the instructions for settings that differ
+pub(crate) fn apply(config: &Config, path: &Path, market: Option<Pubkey>) -> Result<()> {
+    let file = load(path)?;
+    let market = market_address(&file, market)?;
+    apply_file(config, &file, &market)
+}
+
+/// Bring `market` in line with `file`
+pub(crate) fn apply_file(config: &Config, file: &MarketFile, market: &Pubkey) -> Result<()> {
+    let (owner, changes) = changes(config, file, market)?;
+    print_changes(market, &changes);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    if owner != config.owner.pubkey() {
+        bail!("{} doesn't own market {}", config.owner.pubkey(), market);
+    }
+
+    let instructions: Vec<Instruction> = changes
+        .into_iter()
+        .map(|change| change.instruction)
+        .collect();
+    send(config, &instructions, &[config.owner.as_ref()])?;
+
+    println!("Market {} updated", market);
+    Ok(())
+}
+
+/// Parameters `create-market --from-config` creates the market with, those
+/// given on the command line win over the file
+pub(crate) struct CreationParams {
+    pub acceptable: Pubkey,
+    pub category: [u8; 4],
+    pub project_id: [u8; 32],
+    pub features: u64,
+}
+
+impl CreationParams {
+    pub(crate) fn from_file(
+        file: &MarketFile,
+        acceptable: Option<Pubkey>,
+        category: Option<[u8; 4]>,
+        project_id: Option<[u8; 32]>,
+        features: Option<u64>,
+    ) -> Result<CreationParams> {
+        let acceptable = match (acceptable, &file.acceptable) {
+            (Some(acceptable), _) => acceptable,
+            (None, Some(acceptable)) => parse_pubkey("acceptable mint", acceptable)?,
+            (None, None) => bail!("The acceptable mint is neither given nor named in the file"),
+        };
+        let category = match (category, &file.category) {
+            (Some(category), _) => category,
+            (None, Some(category)) => listing::parse_category(category)?,
+            (None, None) => [0; 4],
+        };
+        let project_id = match (project_id, &file.project_id) {
+            (Some(project_id), _) => project_id,
+            (None, Some(project_id)) => listing::parse_project_id(project_id)?,
+            (None, None) => [0; 32],
+        };
+        let features = match (features, &file.features) {
+            (Some(features), _) => features,
+            (None, Some(features)) => listing::parse_features(features)?,
+            (None, None) => TokenMarket::DEFAULT_FEATURES,
+        };
+        Ok(CreationParams {
+            acceptable,
+            category,
+            project_id,
+            features,
+        })
+    }
+}
+
+/// Settings of `market` as a file `apply` and `create-market --from-config` read
+pub(crate) fn export_file(reader: &MarketReader, market: &Pubkey) -> Result<MarketFile> {
+    let token_market = reader.market(market)?;
+    let features: Vec<&str> = TokenMarket::FEATURE_NAMES
+        .iter()
+        .filter(|(_, bit)| token_market.supports(*bit))
+        .map(|(name, _)| *name)
+        .collect();
+    let optional = |address: Pubkey| {
+        if address == Pubkey::default() {
+            None
+        } else {
+            Some(address.to_string())
+        }
+    };
+
+    Ok(MarketFile {
+        market: Some(market.to_string()),
+        acceptable: Some(token_market.mint_of_acceptable.to_string()),
+        category: Some(
+            String::from_utf8_lossy(&token_market.category)
+                .trim_end_matches('\0')
+                .to_string(),
+        ),
+        project_id: Some(hex::encode(token_market.project_id)),
+        features: Some(features.join(",")),
+        treasury: Some(token_market.treasury.to_string()),
+        overpayment_policy: Some(format!("{:?}", token_market.overpayment_policy).to_lowercase()),
+        mint_receipts: Some(token_market.mint_receipts),
+        require_memo: Some(token_market.require_memo),
+        sandwich_guard: Some(token_market.sandwich_guard),
+        voucher_signer: Some(format!("0x{}", hex::encode(token_market.voucher_signer))),
+        onramp_signer: Some(token_market.onramp_signer.to_string()),
+        listing: reader.listing(market)?.map(|listing| Listing {
+            website: listing.website,
+            logo_uri: None,
+            contact: None,
+        }),
+        wormhole: Some(Wormhole {
+            chain: token_market.wormhole_chain,
+            emitter: format!("0x{}", hex::encode(token_market.wormhole_emitter)),
+        }),
+        dispute_escrow: Some(DisputeEscrow {
+            threshold: token_market.escrow_threshold,
+            window: token_market.dispute_window,
+            guardian: optional(token_market.dispute_guardian),
+        }),
+        purchase_limits: Some(PurchaseLimits {
+            min: token_market.min_purchase,
+            max: token_market.max_purchase,
+        }),
+    })
+}
+
+/// Process `export-config` command, printing the file unless `out` is given
+pub(crate) fn export(reader: &MarketReader, market: &Pubkey, out: Option<&Path>) -> Result<()> {
+    let file = export_file(reader, market)?;
+    let text = toml::to_string(&file).context("Can't encode market settings")?;
+    match out {
+        Some(out) => fs::write(out, &text).with_context(|| format!("Can't write {:?}", out))?,
+        None => print!("{}", text),
+    }
+    if file.listing.is_some() {
+        eprintln!(
+            "The listing logo URI and contact are only hashed on chain, \
+             add logo-uri and contact to [listing] before creating the market elsewhere"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,555 @@
+//! What-if testing of program upgrades.
+//!
+//! `upgrade-rehearse` clones a live market into a local
+//! `solana-test-validator` twice, once running the bytecode deployed on the
+//! cluster and once running the new build under the same program id, and
+//! replays a scenario on both:
+//!
+//! ```yaml
+//! steps:
+//!   - buy: 1.5
+//!   - sell: 0.5
+//!   - withdraw: 1
+//! ```
+//!
+//! Amounts are in UI units of the mint each step pays in. Every step is the
+//! command of the same name run by a rehearsal key, so the buyer and the
+//! market owner are played by a key the rehearsal holds: the clone of the
+//! market names it as owner and, unless the market accepts wrapped SOL, the
+//! clone of the acceptable mint lets it mint the payments. Nothing is sent
+//! to the cluster the market is read from.
+//!
+//! The upgrade is compatible when every step fails with the same error, or
+//! succeeds leaving the same balances, on both builds.
+
+use crate::{
+    buy_tokens, emitter_account, messages::Output, sell_tokens, send, to_raw, verify,
+    withdraw_bank, Config, Planning,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use borsh::BorshSerialize;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::json;
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{
+    account::Account,
+    commitment_config::CommitmentConfig,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_instruction,
+};
+use spl_token::{
+    native_mint,
+    state::{Account as TokenAccount, Mint},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+use token_market::{amounts::UiAmount, state::find_sale_phases_address};
+use token_market_cli::{
+    reader::MarketReader,
+    signer::MarketSigner,
+    throttle::{Throttle, ThrottleSettings, ThrottledClient},
+};
+
+/// Time the local validator gets to answer after it is started
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+pub struct Scenario {
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Step {
+    /// Buy paying this amount of the acceptable mint
+    #[serde(deserialize_with = "ui_amount")]
+    Buy(UiAmount),
+    /// Sell this amount of emitted tokens back to the bank
+    #[serde(deserialize_with = "ui_amount")]
+    Sell(UiAmount),
+    /// Withdraw this amount of the acceptable mint from the bank
+    #[serde(deserialize_with = "ui_amount")]
+    Withdraw(UiAmount),
+}
+
+/// Amounts are written as YAML numbers or strings alike
+fn ui_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<UiAmount, D::Error> {
+    let text = match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::Number(number) => number.to_string(),
+        serde_yaml::Value::String(text) => text,
+        _ => return Err(de::Error::custom("expected an amount")),
+    };
+    UiAmount::from_str(&text).map_err(|_| de::Error::custom(format!("Invalid amount {}", text)))
+}
+
+pub fn load(path: &Path) -> Result<Scenario> {
+    let text = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+    serde_yaml::from_str(&text).with_context(|| format!("Can't parse scenario {:?}", path))
+}
+
+/// Balances a step leaves behind
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Balances {
+    /// Acceptable tokens of the rehearsal key
+    pub acceptable: u64,
+    /// Emitted tokens of the rehearsal key
+    pub emitted: u64,
+    pub bank: u64,
+    pub supply: u64,
+}
+
+/// What a step of the scenario did on one build
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    /// Error of the failed step, the transaction error when the program
+    /// rejected it
+    pub error: Option<String>,
+    pub balances: Balances,
+}
+
+/// Steps whose outcome on the deployed build differs from the one on the
+/// candidate build, with both outcomes
+pub fn differences<'a>(
+    deployed: &'a [Outcome],
+    candidate: &'a [Outcome],
+) -> Vec<(usize, &'a Outcome, &'a Outcome)> {
+    deployed
+        .iter()
+        .zip(candidate)
+        .enumerate()
+        .filter(|(_, (deployed, candidate))| deployed != candidate)
+        .map(|(index, (deployed, candidate))| (index, deployed, candidate))
+        .collect()
+}
+
+/// A `solana-test-validator` process, killed when dropped
+struct LocalValidator {
+    child: Child,
+    url: String,
+}
+
+impl Drop for LocalValidator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Accounts the local validator starts with
+struct Genesis<'a> {
+    source_url: &'a str,
+    program: &'a Path,
+    mint: Pubkey,
+    clones: Vec<Pubkey>,
+    /// Addresses loaded from account files instead of cloned
+    overrides: Vec<(Pubkey, PathBuf)>,
+}
+
+impl LocalValidator {
+    fn start(genesis: &Genesis, ledger: &Path, rpc_port: u16) -> Result<Self> {
+        let mut command = Command::new("solana-test-validator");
+        command
+            .arg("--ledger")
+            .arg(ledger)
+            .args(&["--reset", "--quiet", "--rpc-port", &rpc_port.to_string()])
+            .args(&["--mint", &genesis.mint.to_string()])
+            .args(&["--url", genesis.source_url])
+            .args(&["--bpf-program", &token_market::id().to_string()])
+            .arg(genesis.program);
+        for address in &genesis.clones {
+            command.args(&["--clone", &address.to_string()]);
+        }
+        for (address, file) in &genesis.overrides {
+            command.args(&["--account", &address.to_string()]).arg(file);
+        }
+        let child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Can't start solana-test-validator, is it on the PATH?")?;
+        let validator = Self {
+            child,
+            url: format!("http://127.0.0.1:{}", rpc_port),
+        };
+
+        let rpc_client = RpcClient::new(validator.url.clone());
+        let started = Instant::now();
+        while rpc_client.get_version().is_err() {
+            if started.elapsed() > STARTUP_TIMEOUT {
+                bail!("Local validator didn't answer on {}", validator.url);
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+        Ok(validator)
+    }
+}
+
+/// Write `account` at `address` where `--account` of the validator reads it
+pub(crate) fn account_file(dir: &Path, address: &Pubkey, account: &Account) -> Result<PathBuf> {
+    let path = dir.join(format!("{}.json", address));
+    let file = json!({
+        "pubkey": address.to_string(),
+        "account": {
+            "lamports": account.lamports,
+            "data": [base64::encode(&account.data), "base64"],
+            "owner": account.owner.to_string(),
+            "executable": account.executable,
+            "rentEpoch": account.rent_epoch,
+        },
+    });
+    fs::write(&path, serde_json::to_string(&file)?)?;
+    Ok(path)
+}
+
+/// The transaction error of a rejected step, else the error the command gave
+fn step_error(error: &anyhow::Error) -> String {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<ClientError>())
+        .find_map(|error| error.get_transaction_error())
+        .map_or_else(|| error.to_string(), |error| format!("{:?}", error))
+}
+
+fn token_amount(rpc_client: &RpcClient, address: &Pubkey) -> u64 {
+    rpc_client
+        .get_account_data(address)
+        .ok()
+        .and_then(|data| spl_token::state::Account::unpack(&data).ok())
+        .map_or(0, |account| account.amount)
+}
+
+/// Replay `scenario` on `market` cloned from `source` into a local validator
+/// running `program`
+#[allow(clippy::too_many_arguments)]
+fn replay(
+    source: &MarketReader,
+    source_url: &str,
+    market: &Pubkey,
+    program: &Path,
+    scenario: &Scenario,
+    work_dir: &Path,
+    rpc_port: u16,
+    output: Output,
+) -> Result<Vec<Outcome>> {
+    let rehearser = Keypair::new();
+    let token_market = source.market(market)?;
+    let native = token_market.mint_of_acceptable == native_mint::id();
+
+    let mut market_account = source.rpc_client().get_account(market)?;
+    let mut rehearsed_market = source.market(market)?;
+    rehearsed_market.owner = rehearser.pubkey();
+    let data = rehearsed_market.try_to_vec()?;
+    market_account.data[..data.len()].copy_from_slice(&data);
+    let mut overrides = vec![(*market, account_file(work_dir, market, &market_account)?)];
+    let mut clones = vec![token_market.bank, token_market.emitter_mint];
+    // with wrapped SOL the rehearsal key wraps its own SOL to pay instead
+    if !native {
+        let acceptable = &token_market.mint_of_acceptable;
+        let mut mint_account = source.rpc_client().get_account(acceptable)?;
+        let mut mint = Mint::unpack(&mint_account.data)?;
+        mint.mint_authority = COption::Some(rehearser.pubkey());
+        mint.pack_into_slice(&mut mint_account.data);
+        overrides.push((
+            *acceptable,
+            account_file(work_dir, acceptable, &mint_account)?,
+        ));
+    }
+    if token_market.fee_bps > 0 {
+        clones.push(token_market.fee_vault);
+    }
+    if token_market.phased {
+        clones.push(find_sale_phases_address(&token_market::id(), market).0);
+    }
+    if token_market.has_treasury() {
+        clones.push(token_market.treasury);
+    }
+
+    let genesis = Genesis {
+        source_url,
+        program,
+        mint: rehearser.pubkey(),
+        clones,
+        overrides,
+    };
+    let validator = LocalValidator::start(&genesis, &work_dir.join("ledger"), rpc_port)?;
+    let config = Config {
+        owner: Box::new(Keypair::from_bytes(&rehearser.to_bytes())?),
+        fee_payer: Box::new(Keypair::from_bytes(&rehearser.to_bytes())?),
+        rpc_client: ThrottledClient::new(
+            RpcClient::new_with_commitment(validator.url.clone(), CommitmentConfig::confirmed()),
+            Throttle::new(ThrottleSettings::default()),
+        ),
+        screener: None,
+        simulate_first: false,
+        strict_preflight: false,
+        planning: Planning::Off,
+        output: Output {
+            quiet: true,
+            ..output
+        },
+    };
+
+    // fund every purchase of the scenario up front
+    let mut funding = 0u64;
+    for step in &scenario.steps {
+        if let Step::Buy(amount) = step {
+            funding = funding
+                .checked_add(to_raw(&config, amount, &token_market.mint_of_acceptable)?)
+                .ok_or_else(|| anyhow!("Purchases of the scenario overflow"))?;
+        }
+    }
+    let mut instructions = vec![];
+    let wallet = rehearser.pubkey();
+    emitter_account(
+        &config,
+        &mut instructions,
+        &wallet,
+        &token_market.emitter_mint,
+    )?;
+    let paying = emitter_account(
+        &config,
+        &mut instructions,
+        &wallet,
+        &token_market.mint_of_acceptable,
+    )?;
+    // spl-token 3.1 can't sync lamports sent to a wrapped SOL account, so
+    // they are wrapped by initializing an account holding them, which pays
+    // the rehearsal account and is closed again
+    let wrapping = Keypair::new();
+    let mut signers: Vec<&dyn MarketSigner> = vec![config.owner.as_ref()];
+    if native {
+        instructions.push(system_instruction::create_account(
+            &wallet,
+            &wrapping.pubkey(),
+            Rent::default().minimum_balance(TokenAccount::LEN) + funding,
+            TokenAccount::LEN as u64,
+            &spl_token::id(),
+        ));
+        instructions.push(spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &wrapping.pubkey(),
+            &native_mint::id(),
+            &wallet,
+        )?);
+        instructions.push(spl_token::instruction::transfer(
+            &spl_token::id(),
+            &wrapping.pubkey(),
+            &paying,
+            &wallet,
+            &[],
+            funding,
+        )?);
+        instructions.push(spl_token::instruction::close_account(
+            &spl_token::id(),
+            &wrapping.pubkey(),
+            &wallet,
+            &wallet,
+            &[],
+        )?);
+        signers.push(&wrapping);
+    } else {
+        instructions.push(spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &token_market.mint_of_acceptable,
+            &paying,
+            &wallet,
+            &[],
+            funding,
+        )?);
+    }
+    send(&config, &instructions, &signers)?;
+
+    let holding = spl_associated_token_account::get_associated_token_address(
+        &wallet,
+        &token_market.emitter_mint,
+    );
+    let mut outcomes = vec![];
+    for step in &scenario.steps {
+        let result = match *step {
+            Step::Buy(amount) => buy_tokens(
+                &config,
+                *market,
+                wallet,
+                amount,
+                None,
+                None,
+                None,
+                None,
+                None,
+                token_market
+                    .require_memo
+                    .then(|| "upgrade rehearsal".to_string()),
+                true,
+                None,
+                false,
+            ),
+            Step::Sell(amount) => sell_tokens(&config, *market, amount),
+            Step::Withdraw(amount) => withdraw_bank(&config, *market, amount, None),
+        };
+        let rpc_client: &RpcClient = &config.rpc_client;
+        let supply = rpc_client
+            .get_account_data(&token_market.emitter_mint)
+            .ok()
+            .and_then(|data| Mint::unpack(&data).ok())
+            .map_or(0, |mint| mint.supply);
+        outcomes.push(Outcome {
+            error: result.err().map(|error| step_error(&error)),
+            balances: Balances {
+                acceptable: token_amount(rpc_client, &paying),
+                emitted: token_amount(rpc_client, &holding),
+                bank: token_amount(rpc_client, &token_market.bank),
+                supply,
+            },
+        });
+    }
+    Ok(outcomes)
+}
+
+fn describe(outcome: &Outcome) -> String {
+    let balances = &outcome.balances;
+    let balances = format!(
+        "acceptable {}, emitted {}, bank {}, supply {}",
+        balances.acceptable, balances.emitted, balances.bank, balances.supply
+    );
+    match &outcome.error {
+        Some(error) => format!("failed with {} ({})", error, balances),
+        None => format!("succeeded ({})", balances),
+    }
+}
+
+/// Process `upgrade-rehearse` command: replay `scenario` on `market` of the
+/// cluster `source` reads, with the deployed program and with `program`
+pub(crate) fn rehearse(
+    source: &MarketReader,
+    source_url: &str,
+    market: &Pubkey,
+    program: &Path,
+    scenario: &Scenario,
+    rpc_port: u16,
+    output: Output,
+) -> Result<()> {
+    if !program.exists() {
+        bail!("No program build at {:?}", program);
+    }
+    let work_dir = std::env::temp_dir().join(format!("token-market-rehearsal-{}", market));
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir)?;
+    }
+    let deployed_dir = work_dir.join("deployed");
+    let candidate_dir = work_dir.join("candidate");
+    fs::create_dir_all(&deployed_dir)?;
+    fs::create_dir_all(&candidate_dir)?;
+    let deployed_program = deployed_dir.join("token_market.so");
+    fs::write(
+        &deployed_program,
+        verify::fetch_program_data(source.rpc_client(), &token_market::id())?,
+    )?;
+
+    println!(
+        "Replaying {} steps on the deployed program...",
+        scenario.steps.len()
+    );
+    let deployed = replay(
+        source,
+        source_url,
+        market,
+        &deployed_program,
+        scenario,
+        &deployed_dir,
+        rpc_port,
+        output,
+    )?;
+    println!(
+        "Replaying {} steps on {}...",
+        scenario.steps.len(),
+        program.display()
+    );
+    let candidate = replay(
+        source,
+        source_url,
+        market,
+        program,
+        scenario,
+        &candidate_dir,
+        rpc_port,
+        output,
+    )?;
+
+    for (index, (step, outcome)) in scenario.steps.iter().zip(&candidate).enumerate() {
+        println!("{}. {:?} {}", index + 1, step, describe(outcome));
+    }
+    let differences = differences(&deployed, &candidate);
+    if differences.is_empty() {
+        println!(
+            "The upgrade behaves like the deployed program on market {}",
+            market
+        );
+        return Ok(());
+    }
+    for (index, deployed, candidate) in &differences {
+        println!(
+            "Step {} {} on the deployed program but {} after the upgrade",
+            index + 1,
+            describe(deployed),
+            describe(candidate)
+        );
+    }
+    bail!(
+        "The upgrade changes {} of {} steps on market {}",
+        differences.len(),
+        scenario.steps.len(),
+        market
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenario() {
+        let scenario: Scenario =
+            serde_yaml::from_str("steps:\n  - buy: 1.5\n  - sell: \"0.5\"\n  - withdraw: 1\n")
+                .unwrap();
+        assert_eq!(
+            scenario.steps,
+            vec![
+                Step::Buy(UiAmount::from_str("1.5").unwrap()),
+                Step::Sell(UiAmount::from_str("0.5").unwrap()),
+                Step::Withdraw(UiAmount::from_str("1").unwrap()),
+            ]
+        );
+        assert!(serde_yaml::from_str::<Scenario>("steps:\n  - buy: -1\n").is_err());
+        assert!(serde_yaml::from_str::<Scenario>("steps:\n  - burn: 1\n").is_err());
+    }
+
+    #[test]
+    fn test_differences() {
+        let succeeded = |bank| Outcome {
+            error: None,
+            balances: Balances {
+                bank,
+                ..Balances::default()
+            },
+        };
+        let failed = Outcome {
+            error: Some("InstructionError(0, Custom(3))".to_string()),
+            balances: Balances::default(),
+        };
+        let deployed = vec![succeeded(10), succeeded(5), failed.clone()];
+        let candidate = vec![succeeded(10), succeeded(4), failed];
+        let differences = differences(&deployed, &candidate);
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].0, 1);
+    }
+}
@@ -0,0 +1,59 @@
+//! Address screening hooks
+
+use anyhow::{bail, Context, Result};
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::HashSet, fs, path::Path, str::FromStr};
+
+/// Hook invoked for every address taking part in a purchase before anything is sent.
+///
+/// Operators with compliance requirements plug their own implementation here,
+/// [DenylistScreener](struct.DenylistScreener.html) covers the local file case.
+pub trait AddressScreener {
+    /// Returns an error if `address` must not take part in the operation
+    fn screen(&self, address: &Pubkey) -> Result<()>;
+}
+
+/// Screener that rejects addresses listed in a local denylist
+#[derive(Debug, Default)]
+pub struct DenylistScreener {
+    denied: HashSet<Pubkey>,
+}
+
+impl DenylistScreener {
+    /// Load a denylist with one base58 address per line.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Can't read denylist {}", path.display()))?;
+        let mut denied = HashSet::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let address = Pubkey::from_str(line)
+                .with_context(|| format!("Invalid address at {}:{}", path.display(), number + 1))?;
+            denied.insert(address);
+        }
+        Ok(Self { denied })
+    }
+}
+
+impl AddressScreener for DenylistScreener {
+    fn screen(&self, address: &Pubkey) -> Result<()> {
+        if self.denied.contains(address) {
+            bail!("Address {} is denied by the screening policy", address);
+        }
+        Ok(())
+    }
+}
+
+/// Run `screener` over every address, failing on the first rejected one
+pub fn screen_all(screener: Option<&dyn AddressScreener>, addresses: &[&Pubkey]) -> Result<()> {
+    if let Some(screener) = screener {
+        for address in addresses {
+            screener.screen(address)?;
+        }
+    }
+    Ok(())
+}
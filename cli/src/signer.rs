@@ -0,0 +1,309 @@
+//! Signing backends of the transaction helpers.
+//!
+//! Every transaction the CLI sends is signed through
+//! [MarketSigner](trait.MarketSigner.html), whatever holds the key:
+//!
+//! - keypair files and remote wallets such as a Ledger, loaded like the
+//!   Solana CLI loads them, are wrapped in a [WalletSigner](struct.WalletSigner.html),
+//! - keypairs generated for new accounts sign directly,
+//! - keys that never leave a KMS, an HSM or a custody service sign through the
+//!   async callback of an [ExternalSigner](struct.ExternalSigner.html).
+//!
+//! On the command line `--owner` and `--fee-payer` name an external signer as
+//! `external:PUBKEY=COMMAND`. The command gets the hex encoded message on its
+//! standard input and prints the base58 signature.
+//!
+//! ```no_run
+//! use solana_sdk::{pubkey::Pubkey, signature::Signature};
+//! use token_market_cli::signer::ExternalSigner;
+//!
+//! # async fn sign_with_kms(_message: Vec<u8>) -> Result<Signature, String> { unimplemented!() }
+//! let treasury_key = Pubkey::new_unique();
+//! let signer = ExternalSigner::new(treasury_key, "kms", sign_with_kms);
+//! ```
+
+use anyhow::{anyhow, bail, Result};
+use solana_sdk::{
+    hash::Hash,
+    message::Message,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer, SignerError},
+    signer::null_signer::NullSigner,
+    transaction::Transaction,
+};
+use std::{
+    future::Future,
+    io::Write,
+    pin::Pin,
+    process::{Command, Stdio},
+    str::FromStr,
+};
+use tracing::debug;
+
+/// Prefix of the signer paths that name an external signing command
+pub const EXTERNAL_SIGNER_PREFIX: &str = "external:";
+
+/// A key the transaction helpers can sign with
+pub trait MarketSigner: Signer {
+    /// Where the key is held, for logs
+    fn backend(&self) -> String;
+}
+
+impl MarketSigner for Keypair {
+    fn backend(&self) -> String {
+        "keypair".to_string()
+    }
+}
+
+/// Stands for an account that signs elsewhere, e.g. a governance executing a proposal
+impl MarketSigner for NullSigner {
+    fn backend(&self) -> String {
+        "none".to_string()
+    }
+}
+
+/// A signer `solana_clap_utils` loaded from a path, e.g. a `usb://` locator
+pub struct WalletSigner {
+    signer: Box<dyn Signer>,
+    path: String,
+}
+
+impl WalletSigner {
+    pub fn new(signer: Box<dyn Signer>, path: impl Into<String>) -> Self {
+        Self {
+            signer,
+            path: path.into(),
+        }
+    }
+}
+
+impl Signer for WalletSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        self.signer.try_pubkey()
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        self.signer.try_sign_message(message)
+    }
+}
+
+impl MarketSigner for WalletSigner {
+    fn backend(&self) -> String {
+        format!("wallet {}", self.path)
+    }
+}
+
+/// Signature of a message, as produced by an external service
+pub type SigningFuture = Pin<Box<dyn Future<Output = Result<Signature, String>> + Send>>;
+
+/// A key held by an external service, signing through an async callback.
+///
+/// The callback runs on a runtime of its own, so signing must not be called
+/// from async code.
+pub struct ExternalSigner {
+    pubkey: Pubkey,
+    name: String,
+    sign: Box<dyn Fn(Vec<u8>) -> SigningFuture + Send + Sync>,
+}
+
+impl ExternalSigner {
+    /// Signer of `pubkey` calling `sign` with every message to sign,
+    /// `name` tells the service apart in logs
+    pub fn new<F, Fut>(pubkey: Pubkey, name: impl Into<String>, sign: F) -> Self
+    where
+        F: Fn(Vec<u8>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Signature, String>> + Send + 'static,
+    {
+        Self {
+            pubkey,
+            name: name.into(),
+            sign: Box::new(move |message| Box::pin(sign(message))),
+        }
+    }
+
+    /// Signer of `pubkey` running `command` for every message
+    pub fn command(pubkey: Pubkey, command: &str) -> Result<Self> {
+        let mut words = command.split_whitespace().map(str::to_string);
+        let program = words
+            .next()
+            .ok_or_else(|| anyhow!("External signer of {} has no command", pubkey))?;
+        let args: Vec<String> = words.collect();
+        Ok(Self::new(pubkey, command, move |message| {
+            let (program, args) = (program.clone(), args.clone());
+            async move { run_signing_command(&program, &args, &message) }
+        }))
+    }
+}
+
+impl Signer for ExternalSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        debug!(signer = %self.name, "external signing");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| SignerError::Custom(err.to_string()))?;
+        let signature = runtime
+            .block_on((self.sign)(message.to_vec()))
+            .map_err(SignerError::Custom)?;
+        // A service signing with another key would only be caught by the cluster
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(SignerError::KeypairPubkeyMismatch);
+        }
+        Ok(signature)
+    }
+}
+
+impl MarketSigner for ExternalSigner {
+    fn backend(&self) -> String {
+        format!("external {}", self.name)
+    }
+}
+
+/// Run `program` with `message` hex encoded on its standard input and read
+/// the signature it prints
+fn run_signing_command(
+    program: &str,
+    args: &[String],
+    message: &[u8],
+) -> Result<Signature, String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Can't run {}: {}", program, err))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(hex::encode(message).as_bytes())
+        .map_err(|err| format!("Can't pass the message to {}: {}", program, err))?;
+    let output = child.wait_with_output().map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(format!("{} failed with {}", program, output.status));
+    }
+    let printed = String::from_utf8_lossy(&output.stdout);
+    Signature::from_str(printed.trim())
+        .map_err(|_| format!("{} printed no signature: {}", program, printed.trim()))
+}
+
+/// The external signer a signer path names, `None` for paths of other signers
+pub fn external_signer_from_path(path: &str) -> Option<Result<ExternalSigner>> {
+    let source = path.strip_prefix(EXTERNAL_SIGNER_PREFIX)?;
+    Some((|| {
+        let mut parts = source.splitn(2, '=');
+        let (pubkey, command) = match (parts.next(), parts.next()) {
+            (Some(pubkey), Some(command)) => (pubkey, command),
+            _ => bail!("External signer must look like external:PUBKEY=COMMAND"),
+        };
+        let pubkey = Pubkey::from_str(pubkey)
+            .map_err(|_| anyhow!("External signer names an invalid key {}", pubkey))?;
+        ExternalSigner::command(pubkey, command)
+    })())
+}
+
+/// Pick a signer for every signature `message` requires, in message order.
+/// The same key may be provided by several signers, the first one is used.
+pub fn required_signers<'a>(
+    message: &Message,
+    available: &[&'a dyn MarketSigner],
+) -> Result<Vec<&'a dyn MarketSigner>> {
+    message.account_keys[..message.header.num_required_signatures as usize]
+        .iter()
+        .map(|key| {
+            available
+                .iter()
+                .find(|signer| signer.pubkey() == *key)
+                .copied()
+                .ok_or_else(|| anyhow!("Missing signature of {}", key))
+        })
+        .collect()
+}
+
+/// Sign `transaction` at `recent_blockhash` with whichever of `available`
+/// its message requires
+pub fn sign_transaction(
+    transaction: &mut Transaction,
+    available: &[&dyn MarketSigner],
+    recent_blockhash: Hash,
+) -> Result<()> {
+    let required = required_signers(&transaction.message, available)?;
+    transaction.message.recent_blockhash = recent_blockhash;
+    let message = transaction.message_data();
+    for (index, signer) in required.into_iter().enumerate() {
+        transaction.signatures[index] = signer
+            .try_sign_message(&message)
+            .map_err(|err| anyhow!("{} can't sign: {}", signer.backend(), err))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::system_instruction;
+
+    /// External signer of `key` signing in its callback
+    fn external(key: Keypair) -> ExternalSigner {
+        ExternalSigner::new(key.pubkey(), "test", move |message| {
+            let signature = key.sign_message(&message);
+            async move { Ok(signature) }
+        })
+    }
+
+    #[test]
+    fn test_sign_transaction_with_every_backend() {
+        let (payer, from) = (Keypair::new(), Keypair::new());
+        let from_external = external(Keypair::from_bytes(&from.to_bytes()).unwrap());
+        let transfer = system_instruction::transfer(&from.pubkey(), &Pubkey::new_unique(), 1);
+        let mut transaction = Transaction::new_with_payer(&[transfer], Some(&payer.pubkey()));
+
+        let unrelated = Keypair::new();
+        sign_transaction(
+            &mut transaction,
+            &[&unrelated, &payer, &from_external],
+            Hash::new_unique(),
+        )
+        .unwrap();
+        transaction.verify().unwrap();
+
+        let error = sign_transaction(&mut transaction, &[&payer], Hash::new_unique()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Missing signature of {}", from.pubkey())
+        );
+    }
+
+    #[test]
+    fn test_external_signer_with_another_key() {
+        let impostor = ExternalSigner::new(Pubkey::new_unique(), "test", |message| {
+            let signature = Keypair::new().sign_message(&message);
+            async move { Ok(signature) }
+        });
+        assert_eq!(
+            impostor.try_sign_message(b"message"),
+            Err(SignerError::KeypairPubkeyMismatch)
+        );
+    }
+
+    #[test]
+    fn test_external_signer_from_path() {
+        let pubkey = Pubkey::new_unique();
+        let path = format!(
+            "{}{}=kms-sign --key-id treasury",
+            EXTERNAL_SIGNER_PREFIX, pubkey
+        );
+        let signer = external_signer_from_path(&path).unwrap().unwrap();
+        assert_eq!(signer.pubkey(), pubkey);
+        assert_eq!(signer.backend(), "external kms-sign --key-id treasury");
+
+        assert!(external_signer_from_path("usb://ledger").is_none());
+        assert!(external_signer_from_path("external:kms-sign")
+            .unwrap()
+            .is_err());
+    }
+}
@@ -0,0 +1,349 @@
+//! Field-level diffs of a market and its child accounts between two slots.
+//!
+//! RPC nodes only serve the latest state of an account, so past states are
+//! read from a snapshot directory holding one directory per slot:
+//!
+//! ```text
+//! snapshots/
+//!   245000000/
+//!     4uQeVj5tqViQh7yWWGStvkEG1Zmhx6uasJtWCJziofM.json
+//!     ...
+//!   245100000/
+//! ```
+//!
+//! Every file is an account as `solana account --output json` writes it.
+//! `state-diff MARKET --record` writes the market and its child accounts at
+//! the current slot; run on a schedule, or fed from accounts extracted from
+//! ledger snapshots, the directory keeps the history incident responders
+//! diff. The state at a slot is the one of the latest snapshot at or before
+//! it. Without `--after` the market is diffed against its live state.
+
+use crate::rehearsal::account_file;
+use anyhow::{anyhow, bail, Context, Result};
+use borsh::BorshDeserialize;
+use serde_json::{json, Value};
+use solana_sdk::{account::Account, program_pack::Pack, pubkey::Pubkey};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use token_market::state::{
+    find_deprecation_address, find_fee_vault_address, find_listing_address,
+    find_notification_address, find_sale_phases_address, DeprecationNotice, ListingInfo,
+    NotificationConfig, SalePhases, TokenMarket,
+};
+use token_market_cli::reader::MarketReader;
+
+/// Accounts of a market, decoded, by name
+type State = BTreeMap<&'static str, Value>;
+
+/// A field that differs, `Value::Null` where the account doesn't exist
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+/// Child accounts of `market` as `token_market` names them, by name
+fn children(market: &Pubkey, token_market: &TokenMarket) -> Vec<(&'static str, Pubkey)> {
+    let program_id = token_market::id();
+    vec![
+        ("bank", token_market.bank),
+        (
+            "fee-vault",
+            find_fee_vault_address(&program_id, market, &token_market.mint_of_acceptable).0,
+        ),
+        ("listing", find_listing_address(&program_id, market).0),
+        (
+            "deprecation",
+            find_deprecation_address(&program_id, market).0,
+        ),
+        (
+            "sale-phases",
+            find_sale_phases_address(&program_id, market).0,
+        ),
+        (
+            "notifications",
+            find_notification_address(&program_id, market, &token_market.owner).0,
+        ),
+    ]
+}
+
+fn decode(name: &str, data: &[u8]) -> Result<Value> {
+    Ok(match name {
+        "bank" | "fee-vault" => {
+            let account = spl_token::state::Account::unpack(data)?;
+            json!({
+                "mint": account.mint.to_string(),
+                "owner": account.owner.to_string(),
+                "amount": account.amount,
+                "delegate": Option::<Pubkey>::from(account.delegate).map(|delegate| delegate.to_string()),
+                "delegated_amount": account.delegated_amount,
+                "frozen": account.is_frozen(),
+            })
+        }
+        "listing" => serde_json::to_value(ListingInfo::load(data)?)?,
+        "deprecation" => serde_json::to_value(DeprecationNotice::load(data)?)?,
+        "sale-phases" => serde_json::to_value(SalePhases::load(data)?)?,
+        "notifications" => serde_json::to_value(NotificationConfig::load(data)?)?,
+        _ => unreachable!("unknown child account {}", name),
+    })
+}
+
+/// Decoded state of `market`, reading the data of accounts with `fetch`
+fn state(market: &Pubkey, fetch: impl Fn(&Pubkey) -> Result<Option<Vec<u8>>>) -> Result<State> {
+    let data = fetch(market)?.ok_or_else(|| anyhow!("Market {} does not exist", market))?;
+    let token_market = TokenMarket::try_from_slice(&data)?;
+    let mut state = State::new();
+    state.insert("market", serde_json::to_value(&token_market)?);
+    for (name, address) in children(market, &token_market) {
+        if let Some(data) = fetch(&address)? {
+            let value = decode(name, &data)
+                .with_context(|| format!("Can't decode {} {}", name, address))?;
+            state.insert(name, value);
+        }
+    }
+    Ok(state)
+}
+
+/// Append the fields of `after` differing from `before` under `path`
+fn diff_values(path: &str, before: &Value, after: &Value, changes: &mut Vec<Change>) {
+    let field = |key: &str| match path {
+        "" => key.to_string(),
+        _ => format!("{}.{}", path, key),
+    };
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let mut keys: Vec<_> = before.keys().chain(after.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                diff_values(
+                    &field(key),
+                    before.get(key).unwrap_or(&Value::Null),
+                    after.get(key).unwrap_or(&Value::Null),
+                    changes,
+                );
+            }
+        }
+        // arrays of bytes read better whole
+        (Value::Array(before_items), Value::Array(after_items))
+            if before_items.len() == after_items.len()
+                && before_items.iter().any(|item| !item.is_number()) =>
+        {
+            for (index, (before, after)) in before_items.iter().zip(after_items).enumerate() {
+                diff_values(&format!("{}[{}]", path, index), before, after, changes);
+            }
+        }
+        _ if before != after => changes.push(Change {
+            path: path.to_string(),
+            before: before.clone(),
+            after: after.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// Fields differing between two states of a market
+pub fn diff(before: &State, after: &State) -> Vec<Change> {
+    let mut names: Vec<_> = before.keys().chain(after.keys()).collect();
+    names.sort();
+    names.dedup();
+    let mut changes = vec![];
+    for name in names {
+        diff_values(
+            name,
+            before.get(name).unwrap_or(&Value::Null),
+            after.get(name).unwrap_or(&Value::Null),
+            &mut changes,
+        );
+    }
+    changes
+}
+
+/// Snapshot directories by slot
+struct Snapshots {
+    dir: PathBuf,
+}
+
+impl Snapshots {
+    fn slots(&self) -> Result<Vec<u64>> {
+        let mut slots = vec![];
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("Can't read snapshots {:?}", self.dir))?
+        {
+            let entry = entry?;
+            if let Some(slot) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok())
+            {
+                slots.push(slot);
+            }
+        }
+        slots.sort_unstable();
+        Ok(slots)
+    }
+
+    /// Slot of the latest snapshot at or before `slot`
+    fn at(&self, slot: u64) -> Result<u64> {
+        self.slots()?
+            .into_iter()
+            .rev()
+            .find(|snapshot| *snapshot <= slot)
+            .ok_or_else(|| anyhow!("No snapshot at or before slot {} in {:?}", slot, self.dir))
+    }
+
+    /// Data of the account at `address` in the snapshot of `slot`
+    fn account(&self, slot: u64, address: &Pubkey) -> Result<Option<Vec<u8>>> {
+        let path = self
+            .dir
+            .join(slot.to_string())
+            .join(format!("{}.json", address));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path).with_context(|| format!("Can't read {:?}", path))?;
+        read_account_data(&text)
+            .with_context(|| format!("Can't parse account file {:?}", path))
+            .map(Some)
+    }
+}
+
+/// Data of an account file
+fn read_account_data(text: &str) -> Result<Vec<u8>> {
+    let file: Value = serde_json::from_str(text)?;
+    match file["account"]["data"].as_array().map(Vec::as_slice) {
+        Some([Value::String(data), Value::String(encoding)]) if encoding == "base64" => {
+            Ok(base64::decode(data)?)
+        }
+        _ => bail!("Account data isn't base64 encoded"),
+    }
+}
+
+/// Process `state-diff --record`: write the accounts of `market` at the
+/// current slot into a snapshot directory of `dir`
+pub(crate) fn record(reader: &MarketReader, market: &Pubkey, dir: &Path) -> Result<()> {
+    let rpc_client = reader.rpc_client();
+    let slot = rpc_client.get_slot()?;
+    let slot_dir = dir.join(slot.to_string());
+    fs::create_dir_all(&slot_dir).with_context(|| format!("Can't create {:?}", slot_dir))?;
+    let token_market = reader.market(market)?;
+    let mut addresses = vec![*market];
+    addresses.extend(
+        children(market, &token_market)
+            .into_iter()
+            .map(|(_, address)| address),
+    );
+    for address in addresses {
+        let account: Option<Account> = rpc_client
+            .get_account_with_commitment(&address, rpc_client.commitment())?
+            .value;
+        if let Some(account) = account {
+            account_file(&slot_dir, &address, &account)?;
+        }
+    }
+    println!(
+        "Recorded market {} at slot {} in {:?}",
+        market, slot, slot_dir
+    );
+    Ok(())
+}
+
+/// Process `state-diff` command: print what changed on `market` from the
+/// snapshot at or before slot `before` to the one at or before `after`, or
+/// else to the live state
+pub(crate) fn state_diff(
+    reader: &MarketReader,
+    market: &Pubkey,
+    dir: &Path,
+    before: u64,
+    after: Option<u64>,
+) -> Result<()> {
+    let snapshots = Snapshots {
+        dir: dir.to_path_buf(),
+    };
+    let before_slot = snapshots.at(before)?;
+    let before_state = state(market, |address| snapshots.account(before_slot, address))?;
+    let (after_label, after_state) = match after {
+        Some(after) => {
+            let after_slot = snapshots.at(after)?;
+            (
+                format!("slot {}", after_slot),
+                state(market, |address| snapshots.account(after_slot, address))?,
+            )
+        }
+        None => {
+            let rpc_client = reader.rpc_client();
+            (
+                "now".to_string(),
+                state(market, |address| {
+                    Ok(rpc_client
+                        .get_account_with_commitment(address, rpc_client.commitment())?
+                        .value
+                        .map(|account| account.data))
+                })?,
+            )
+        }
+    };
+
+    let changes = diff(&before_state, &after_state);
+    println!(
+        "Market {} from slot {} to {}: {} changes",
+        market,
+        before_slot,
+        after_label,
+        changes.len()
+    );
+    for change in changes {
+        println!("  {}: {} -> {}", change.path, change.before, change.after);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let mut before = State::new();
+        before.insert(
+            "market",
+            json!({ "fee_bps": 0, "paused": false, "project_id": [1, 2], "pricing_tiers": [{ "price": 1 }, { "price": 2 }] }),
+        );
+        let mut after = State::new();
+        after.insert(
+            "market",
+            json!({ "fee_bps": 250, "paused": false, "project_id": [1, 3], "pricing_tiers": [{ "price": 1 }, { "price": 5 }] }),
+        );
+        after.insert("listing", json!({ "website": "https://example.com" }));
+
+        let changes = diff(&before, &after);
+        let paths: Vec<_> = changes.iter().map(|change| change.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "listing",
+                "market.fee_bps",
+                "market.pricing_tiers[1].price",
+                "market.project_id",
+            ]
+        );
+        assert_eq!(changes[0].before, Value::Null);
+        assert_eq!(
+            (&changes[1].before, &changes[1].after),
+            (&json!(0), &json!(250))
+        );
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn test_read_account_data() {
+        let text = r#"{"pubkey":"11111111111111111111111111111111","account":{"lamports":1,"data":["AQID","base64"],"owner":"11111111111111111111111111111111","executable":false,"rentEpoch":0}}"#;
+        assert_eq!(read_account_data(text).unwrap(), vec![1, 2, 3]);
+        assert!(read_account_data(r#"{"account":{"data":"AQID"}}"#).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Reconciliation of the emitter supply of a market with its records.
+//!
+//! Every confirmed transaction that touched the emitter mint is checked: the
+//! supply change its token balances show must be the change its records
+//! account for. Purchases mint and sales burn, as do refunded gifts and
+//! disputed escrows, all logged as [events](../../token_market/events/enum.Event.html).
+//! Vesting grants and holdings migrations log no event and are read from the
+//! token market instructions of the transaction instead. Anything else, like
+//! holders burning their own tokens, shows up as a discrepancy to look into.
+//!
+//! ```no_run
+//! use solana_sdk::pubkey::Pubkey;
+//! use token_market_cli::{reader::MarketReader, supply::reconcile_supply};
+//!
+//! let reader = MarketReader::new("https://api.mainnet-beta.solana.com");
+//! let report = reconcile_supply(&reader, &Pubkey::new_unique()).unwrap();
+//! for change in &report.discrepancies {
+//!     println!("{} minted {}, {} recorded", change.signature, change.actual, change.recorded);
+//! }
+//! ```
+
+use crate::reader::{program_events, MarketReader};
+use anyhow::{anyhow, Result};
+use borsh::de::BorshDeserialize;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_sdk::{
+    program_pack::Pack, pubkey::Pubkey, signature::Signature, transaction::Transaction,
+};
+use solana_transaction_status::{UiTransactionEncoding, UiTransactionTokenBalance};
+use spl_token::state::Mint;
+use std::str::FromStr;
+use token_market::{
+    accounts::{CreateVestingVaultAccounts, MigrateHoldingsAccounts},
+    events::Event,
+    instruction::TokenMarketInstructions,
+    state::{Gift, HoldingsMigration, PurchaseEscrow},
+};
+use tracing::instrument;
+
+/// Supply change of one transaction
+pub struct SupplyChange {
+    pub signature: Signature,
+    pub slot: u64,
+    /// Change of the emitter supply in the token balances of the transaction
+    pub actual: i128,
+    /// Change the records of the market account for
+    pub recorded: i128,
+}
+
+/// Outcome of [reconcile_supply](fn.reconcile_supply.html)
+pub struct SupplyReport {
+    pub emitter: Pubkey,
+    /// Current supply of the emitter mint
+    pub supply: u64,
+    /// Sum of the recorded changes of every transaction
+    pub recorded: i128,
+    /// Transactions whose supply change differs from the recorded one
+    pub discrepancies: Vec<SupplyChange>,
+    /// Transactions the node kept no token balances of
+    pub unverifiable: Vec<Signature>,
+}
+
+impl SupplyReport {
+    /// Whether every transaction and the supply itself match the records
+    pub fn balanced(&self) -> bool {
+        self.discrepancies.is_empty()
+            && self.unverifiable.is_empty()
+            && self.recorded == self.supply as i128
+    }
+}
+
+/// Compare the supply of the emitter of `market` with its records over the
+/// whole history the RPC node keeps
+#[instrument(level = "debug", skip(reader))]
+pub fn reconcile_supply(reader: &MarketReader, market: &Pubkey) -> Result<SupplyReport> {
+    let emitter = reader.market(market)?.emitter_mint;
+    let supply = Mint::unpack(&reader.rpc_client().get_account_data(&emitter)?)?.supply;
+    let mut report = SupplyReport {
+        emitter,
+        supply,
+        recorded: 0,
+        discrepancies: vec![],
+        unverifiable: vec![],
+    };
+
+    let mut before = None;
+    loop {
+        let page = reader.rpc_client().retrying(|client| {
+            client.get_confirmed_signatures_for_address2_with_config(
+                &emitter,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    ..GetConfirmedSignaturesForAddress2Config::default()
+                },
+            )
+        })?;
+        let last = match page.last() {
+            Some(last) => Signature::from_str(&last.signature)?,
+            None => break,
+        };
+        for status in page.iter().filter(|status| status.err.is_none()) {
+            let signature = Signature::from_str(&status.signature)?;
+            match supply_change(reader, market, &emitter, signature, status.slot)? {
+                Some(change) => {
+                    report.recorded += change.recorded;
+                    if change.actual != change.recorded {
+                        report.discrepancies.push(change);
+                    }
+                }
+                None => report.unverifiable.push(signature),
+            }
+        }
+        before = Some(last);
+    }
+    // Pages come newest first
+    report.discrepancies.reverse();
+    report.unverifiable.reverse();
+    Ok(report)
+}
+
+/// Actual and recorded supply change of the transaction `signature`,
+/// `None` if the node has no token balances of it
+fn supply_change(
+    reader: &MarketReader,
+    market: &Pubkey,
+    emitter: &Pubkey,
+    signature: Signature,
+    slot: u64,
+) -> Result<Option<SupplyChange>> {
+    let confirmed = reader.rpc_client().retrying(|client| {
+        client.get_confirmed_transaction(&signature, UiTransactionEncoding::Base64)
+    })?;
+    let transaction = confirmed
+        .transaction
+        .transaction
+        .decode()
+        .ok_or_else(|| anyhow!("Transaction {} can't be decoded", signature))?;
+    let meta = match confirmed.transaction.meta {
+        Some(meta) => meta,
+        None => return Ok(None),
+    };
+    let (pre, post) = match (meta.pre_token_balances, meta.post_token_balances) {
+        (Some(pre), Some(post)) => (pre, post),
+        _ => return Ok(None),
+    };
+    let emitter_name = emitter.to_string();
+    let actual = mint_balance(&post, &emitter_name)? - mint_balance(&pre, &emitter_name)?;
+
+    let mut recorded = instruction_change(reader, market, emitter, &transaction)?;
+    for event in program_events(&meta.log_messages.unwrap_or_default())
+        .iter()
+        .filter(|event| event.market() == market)
+    {
+        recorded += event_change(reader, event)?;
+    }
+
+    Ok(Some(SupplyChange {
+        signature,
+        slot,
+        actual,
+        recorded,
+    }))
+}
+
+/// Total of the `mint` token accounts in `balances`
+fn mint_balance(balances: &[UiTransactionTokenBalance], mint: &str) -> Result<i128> {
+    balances
+        .iter()
+        .filter(|balance| balance.mint == mint)
+        .map(|balance| -> Result<i128> {
+            Ok(balance.ui_token_amount.amount.parse::<u64>()? as i128)
+        })
+        .sum()
+}
+
+/// Supply change `event` records. Refunds burn what the gift or escrow
+/// held, which is looked up since the event names the refunded payment only
+fn event_change(reader: &MarketReader, event: &Event) -> Result<i128> {
+    Ok(match event {
+        Event::Purchase { amount, .. }
+        | Event::WormholePurchase { amount, .. }
+        | Event::FiatPurchase { amount, .. }
+        | Event::PurchaseEscrowed { amount, .. }
+        | Event::GiftPurchased { amount, .. } => *amount as i128,
+        Event::TokensSold { amount, .. } => -(*amount as i128),
+        Event::EscrowDisputed { escrow, .. } => {
+            let data = reader.rpc_client().get_account_data(escrow)?;
+            -(PurchaseEscrow::try_from_slice(&data)?.emitted as i128)
+        }
+        Event::GiftRefunded { gift, .. } => {
+            let data = reader.rpc_client().get_account_data(gift)?;
+            -(Gift::try_from_slice(&data)?.emitted as i128)
+        }
+        _ => 0,
+    })
+}
+
+/// Supply change of the instructions of `transaction` that log no event:
+/// vesting grants of `market` and holdings migrations from or to `emitter`.
+/// Only instructions at the top level of the transaction are seen
+fn instruction_change(
+    reader: &MarketReader,
+    market: &Pubkey,
+    emitter: &Pubkey,
+    transaction: &Transaction,
+) -> Result<i128> {
+    let keys = &transaction.message.account_keys;
+    let mut change = 0;
+    for ix in &transaction.message.instructions {
+        if keys[ix.program_id_index as usize] != token_market::id() {
+            continue;
+        }
+        let account = |index: usize| ix.accounts.get(index).map(|key| keys[*key as usize]);
+        match TokenMarketInstructions::unpack(&ix.data) {
+            Ok((TokenMarketInstructions::CreateVestingVault { total, .. }, _))
+                if account(CreateVestingVaultAccounts::MARKET) == Some(*market) =>
+            {
+                change += total as i128;
+            }
+            Ok((TokenMarketInstructions::MigrateHoldings { amount }, _)) => {
+                let address = account(MigrateHoldingsAccounts::MIGRATION)
+                    .ok_or_else(|| anyhow!("MigrateHoldings without a migration account"))?;
+                let data = reader.rpc_client().get_account_data(&address)?;
+                let migration = HoldingsMigration::try_from_slice(&data)?;
+                if migration.from_emitter == *emitter {
+                    change -= amount as i128;
+                }
+                if migration.to_emitter == *emitter {
+                    change += migration.converted_amount(amount).unwrap_or_default() as i128;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(change)
+}
@@ -0,0 +1,33 @@
+//! Usage of a shared deployment by market owner, for usage-based billing
+
+use anyhow::{anyhow, Result};
+use solana_sdk::pubkey::Pubkey;
+use token_market::state::TenantStats;
+use token_market_cli::reader::MarketReader;
+
+fn print_tenant(stats: &TenantStats) {
+    println!(
+        "{} markets {} protocol fees {}",
+        stats.owner, stats.markets_created, stats.protocol_fees
+    );
+}
+
+/// Process `tenant-stats` command, every tenant of the deployment without `owner`
+pub fn show_tenant_stats(reader: &MarketReader, owner: Option<Pubkey>) -> Result<()> {
+    match owner {
+        Some(owner) => {
+            let stats = reader.tenant_stats(&owner)?.ok_or_else(|| {
+                anyhow!("{} has created no market counted in tenant stats", owner)
+            })?;
+            print_tenant(&stats);
+        }
+        None => {
+            let tenants = reader.tenants()?;
+            for stats in &tenants {
+                print_tenant(stats);
+            }
+            println!("{} tenant(s) found", tenants.len());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! Client side rate limiting of RPC calls.
+//!
+//! Analytics commands scan program accounts and page through long histories,
+//! enough to get the RPC key of an operator banned. A
+//! [ThrottledClient](struct.ThrottledClient.html) takes a token from a shared
+//! bucket before every call made through it, and pauses every caller at once
+//! when the node still answers `429 Too Many Requests` after the HTTP sender
+//! waited out its `Retry-After`.
+//!
+//! The bucket is set up by the profile of the CLI config:
+//!
+//! ```yaml
+//! rpc_requests_per_second: 10
+//! rpc_burst: 20
+//! rpc_retries: 5
+//! ```
+//!
+//! The bucket is shared by every [endpoint](../endpoints/index.html) of the client.
+
+use crate::endpoints::EndpointPool;
+use serde::Deserialize;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_client::RpcClient,
+};
+use std::{
+    ops::Deref,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Pause after the first rate limited call that outlived the retries of the
+/// HTTP sender, doubled on every further one
+const FIRST_BACK_OFF: Duration = Duration::from_secs(2);
+
+/// Limits of the shared bucket
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct ThrottleSettings {
+    /// Sustained call rate, no limit when zero
+    #[serde(rename = "rpc_requests_per_second")]
+    pub requests_per_second: f64,
+    /// Calls made at once after a quiet period
+    #[serde(rename = "rpc_burst")]
+    pub burst: u32,
+    /// Retries of a rate limited call before its error is returned
+    #[serde(rename = "rpc_retries")]
+    pub retries: u32,
+}
+
+impl Default for ThrottleSettings {
+    /// The limits public mainnet endpoints allow a single IP
+    fn default() -> Self {
+        Self {
+            requests_per_second: 10.0,
+            burst: 20,
+            retries: 5,
+        }
+    }
+}
+
+impl ThrottleSettings {
+    /// Settings of a CLI config profile, the defaults for the keys it lacks
+    pub fn from_profile(profile: &serde_json::Value) -> serde_json::Result<Self> {
+        Self::deserialize(profile)
+    }
+}
+
+struct Bucket {
+    /// Calls that can be made right away, negative when callers wait for refills
+    tokens: f64,
+    refilled: Instant,
+    /// Nobody calls before this once the node asked to back off
+    paused_until: Option<Instant>,
+}
+
+/// Token bucket shared by every clone
+#[derive(Clone)]
+pub struct Throttle {
+    settings: ThrottleSettings,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl Throttle {
+    pub fn new(settings: ThrottleSettings) -> Self {
+        Self {
+            settings,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: settings.burst as f64,
+                refilled: Instant::now(),
+                paused_until: None,
+            })),
+        }
+    }
+
+    pub fn settings(&self) -> &ThrottleSettings {
+        &self.settings
+    }
+
+    /// Take a token, sleeping until there is one and any pause is over
+    pub fn acquire(&self) {
+        let wait = self.reserve(Instant::now());
+        if wait > Duration::default() {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Take a token at `now`, returning how long its call has to wait
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        let mut wait = match bucket.paused_until {
+            Some(until) => until.saturating_duration_since(now),
+            None => Duration::default(),
+        };
+        let rate = self.settings.requests_per_second;
+        if rate > 0.0 {
+            let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(self.settings.burst.max(1) as f64);
+            bucket.refilled = now;
+            bucket.tokens -= 1.0;
+            if bucket.tokens < 0.0 {
+                wait = wait.max(Duration::from_secs_f64(-bucket.tokens / rate));
+            }
+        }
+        wait
+    }
+
+    /// Hold every caller for `duration` from now
+    pub fn pause(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut bucket = self.bucket.lock().unwrap();
+        if bucket.paused_until.map_or(true, |paused| paused < until) {
+            bucket.paused_until = Some(until);
+        }
+    }
+}
+
+/// Whether the node refused `error` for too many requests
+pub fn is_rate_limited(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Reqwest(error) => {
+            error.status().map(|status| status.as_u16()) == Some(429)
+        }
+        _ => false,
+    }
+}
+
+/// An RPC client every call of which goes through a [Throttle](struct.Throttle.html).
+///
+/// Each method call made through it takes a token on dereference, so code
+/// written against `RpcClient` is throttled as is, and goes to the
+/// preferred endpoint of its pool. Calls that may run into the rate limit in
+/// a long scan go through [retrying](#method.retrying), calls that must
+/// survive an endpoint going down through [failover](#method.failover).
+pub struct ThrottledClient {
+    pool: EndpointPool,
+    throttle: Throttle,
+}
+
+impl ThrottledClient {
+    pub fn new(client: RpcClient, throttle: Throttle) -> Self {
+        Self::with_pool(EndpointPool::single(client), throttle)
+    }
+
+    pub fn with_pool(pool: EndpointPool, throttle: Throttle) -> Self {
+        Self { pool, throttle }
+    }
+
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    pub fn pool(&self) -> &EndpointPool {
+        &self.pool
+    }
+
+    /// Make `call`, on the next endpoint of the pool while the previous one
+    /// can't be reached
+    pub fn failover<T>(
+        &self,
+        mut call: impl FnMut(&RpcClient) -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        self.pool.call(|client| {
+            self.throttle.acquire();
+            call(client)
+        })
+    }
+
+    /// Make `call`, pausing every caller and making it again while the node
+    /// keeps refusing it for too many requests
+    pub fn retrying<T>(
+        &self,
+        mut call: impl FnMut(&RpcClient) -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        let mut back_off = FIRST_BACK_OFF;
+        let mut retries = self.throttle.settings.retries;
+        loop {
+            match self.failover(&mut call) {
+                Err(error) if retries > 0 && is_rate_limited(&error) => {
+                    warn!(retries, ?back_off, "rate limited");
+                    self.throttle.pause(back_off);
+                    back_off *= 2;
+                    retries -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Deref for ThrottledClient {
+    type Target = RpcClient;
+
+    fn deref(&self) -> &RpcClient {
+        self.throttle.acquire();
+        self.pool.preferred().client()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(requests_per_second: f64, burst: u32) -> Throttle {
+        Throttle::new(ThrottleSettings {
+            requests_per_second,
+            burst,
+            retries: 0,
+        })
+    }
+
+    #[test]
+    fn test_bucket_refills_at_rate() {
+        let throttle = throttle(10.0, 2);
+        let start = throttle.bucket.lock().unwrap().refilled;
+        assert_eq!(throttle.reserve(start), Duration::default());
+        assert_eq!(throttle.reserve(start), Duration::default());
+        assert_eq!(throttle.reserve(start), Duration::from_millis(100));
+        assert_eq!(throttle.reserve(start), Duration::from_millis(200));
+
+        // A quiet period refills no more than the burst
+        let later = start + Duration::from_secs(1);
+        assert_eq!(throttle.reserve(later), Duration::default());
+        assert_eq!(throttle.bucket.lock().unwrap().tokens, 1.0);
+    }
+
+    #[test]
+    fn test_pause_holds_every_caller() {
+        let throttle = throttle(0.0, 0);
+        let now = Instant::now();
+        assert_eq!(throttle.reserve(now), Duration::default());
+
+        throttle.clone().pause(Duration::from_secs(3));
+        assert!(throttle.reserve(now) > Duration::from_secs(2));
+        throttle.pause(Duration::from_secs(1));
+        assert!(throttle.reserve(now) > Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_settings_from_profile() {
+        let profile = serde_json::json!({
+            "json_rpc_url": "http://localhost:8899",
+            "rpc_requests_per_second": 2.5,
+        });
+        let settings = ThrottleSettings::from_profile(&profile).unwrap();
+        assert_eq!(settings.requests_per_second, 2.5);
+        assert_eq!(settings.burst, ThrottleSettings::default().burst);
+    }
+}
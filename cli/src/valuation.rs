@@ -0,0 +1,349 @@
+//! Token amounts valued in a reference currency, e.g. USD, for reports.
+//!
+//! Reports count raw amounts of many mints. A [PriceFeed](trait.PriceFeed.html)
+//! prices whole tokens of a mint in a currency and every valued row keeps the
+//! [PriceSnapshot](struct.PriceSnapshot.html) it was valued at, so an auditor
+//! can redo the conversion later. Feeds come from a Pyth price account read on
+//! chain, the CoinGecko API and a file of fixed prices; integrations plug in
+//! their own by implementing the trait.
+//!
+//! ```no_run
+//! use token_market_cli::{reader::MarketReader, valuation::{value, Coingecko, PriceFeed}};
+//!
+//! let reader = MarketReader::new("https://api.mainnet-beta.solana.com");
+//! # let market = solana_sdk::pubkey::Pubkey::new_unique();
+//! let mint = reader.market(&market).unwrap().mint_of_acceptable;
+//! let snapshot = Coingecko::new().price(&mint, "usd").unwrap();
+//! let proceeds = value(1_500_000, reader.decimals(&mint).unwrap(), &snapshot);
+//! println!("{} {}", proceeds.value, snapshot.currency);
+//! ```
+
+use crate::reader::MarketReader;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Price of one whole token of a mint as a feed published it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PriceSnapshot {
+    pub mint: String,
+    /// Reference currency, lowercase, e.g. `usd`
+    pub currency: String,
+    pub price: f64,
+    /// Feed the price comes from, e.g. `pyth:ACCOUNT`
+    pub source: String,
+    /// Unix timestamp the feed published the price at
+    pub published_at: i64,
+}
+
+/// Amount of a mint valued at a snapshot
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Valuation {
+    /// Whole tokens
+    pub amount: f64,
+    /// `amount` times the price, in the currency of the snapshot
+    pub value: f64,
+    pub snapshot: PriceSnapshot,
+}
+
+/// Value `raw` units of a mint with `decimals` at `snapshot`
+pub fn value(raw: u64, decimals: u8, snapshot: &PriceSnapshot) -> Valuation {
+    let amount = raw as f64 / 10f64.powi(decimals as i32);
+    Valuation {
+        amount,
+        value: amount * snapshot.price,
+        snapshot: snapshot.clone(),
+    }
+}
+
+/// Source of prices in reference currencies
+pub trait PriceFeed {
+    /// Current price of one whole token of `mint` in `currency`
+    fn price(&self, mint: &Pubkey, currency: &str) -> Result<PriceSnapshot>;
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+/// Prices fixed in a file, one `MINT=PRICE` line per mint, for reports that
+/// must be reproduced with the prices of a closing date. Empty lines and
+/// lines starting with `#` are ignored.
+pub struct FixedPrices {
+    currency: String,
+    prices: HashMap<Pubkey, f64>,
+    source: String,
+    published_at: i64,
+}
+
+impl FixedPrices {
+    /// Prices in `currency` listed in `content`, read from `source`
+    pub fn parse(content: &str, currency: &str, source: &str, published_at: i64) -> Result<Self> {
+        let mut prices = HashMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (mint, price) = line
+                .find('=')
+                .map(|equals| (line[..equals].trim(), line[equals + 1..].trim()))
+                .ok_or_else(|| anyhow!("Expected MINT=PRICE at {}:{}", source, number + 1))?;
+            let mint = Pubkey::from_str(mint)
+                .map_err(|_| anyhow!("Invalid mint at {}:{}", source, number + 1))?;
+            let price: f64 = price
+                .parse()
+                .with_context(|| format!("Invalid price at {}:{}", source, number + 1))?;
+            prices.insert(mint, price);
+        }
+        Ok(Self {
+            currency: currency.to_lowercase(),
+            prices,
+            source: source.to_string(),
+            published_at,
+        })
+    }
+
+    /// Prices in `currency` of the file at `path`, published when it was last modified
+    pub fn load(path: &Path, currency: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Can't read price file {}", path.display()))?;
+        let published_at = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or_else(now, |modified| modified.as_secs() as i64);
+        Self::parse(
+            &content,
+            currency,
+            &format!("file:{}", path.display()),
+            published_at,
+        )
+    }
+}
+
+impl PriceFeed for FixedPrices {
+    fn price(&self, mint: &Pubkey, currency: &str) -> Result<PriceSnapshot> {
+        if !currency.eq_ignore_ascii_case(&self.currency) {
+            bail!(
+                "{} holds prices in {}, not {}",
+                self.source,
+                self.currency,
+                currency
+            );
+        }
+        let price = self
+            .prices
+            .get(mint)
+            .ok_or_else(|| anyhow!("{} has no price of {}", self.source, mint))?;
+        Ok(PriceSnapshot {
+            mint: mint.to_string(),
+            currency: self.currency.clone(),
+            price: *price,
+            source: self.source.clone(),
+            published_at: self.published_at,
+        })
+    }
+}
+
+/// Magic number of Pyth accounts
+const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+/// Account type of Pyth price accounts
+const PYTH_PRICE_ACCOUNT: u32 = 3;
+/// Aggregate status of a price being traded
+const PYTH_TRADING: u32 = 1;
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_i64(data: &[u8], offset: usize) -> Option<i64> {
+    Some(i64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Aggregate price and publish time of a Pyth v2 price account
+pub fn parse_pyth_price(data: &[u8]) -> Result<(f64, i64)> {
+    let field = |value: Option<_>| value.ok_or_else(|| anyhow!("Pyth price account is truncated"));
+    if field(read_u32(data, 0))? != PYTH_MAGIC || field(read_u32(data, 8))? != PYTH_PRICE_ACCOUNT {
+        bail!("Not a Pyth price account");
+    }
+    let exponent = field(read_u32(data, 20))? as i32;
+    let timestamp = field(read_i64(data, 96))?;
+    let price = field(read_i64(data, 208))?;
+    if field(read_u32(data, 224))? != PYTH_TRADING {
+        bail!("Pyth price isn't trading");
+    }
+    Ok((price as f64 * 10f64.powi(exponent), timestamp))
+}
+
+/// USD prices of Pyth price accounts, one per mint
+pub struct PythFeed<'a> {
+    reader: &'a MarketReader,
+    accounts: HashMap<Pubkey, Pubkey>,
+}
+
+impl<'a> PythFeed<'a> {
+    /// Feed reading the price account `accounts` maps each mint to
+    pub fn new(reader: &'a MarketReader, accounts: HashMap<Pubkey, Pubkey>) -> Self {
+        Self { reader, accounts }
+    }
+}
+
+impl PriceFeed for PythFeed<'_> {
+    fn price(&self, mint: &Pubkey, currency: &str) -> Result<PriceSnapshot> {
+        if !currency.eq_ignore_ascii_case("usd") {
+            bail!("Pyth prices are in usd, not {}", currency);
+        }
+        let account = self
+            .accounts
+            .get(mint)
+            .ok_or_else(|| anyhow!("No Pyth price account given for {}", mint))?;
+        let data = self.reader.rpc_client().get_account_data(account)?;
+        let (price, published_at) =
+            parse_pyth_price(&data).with_context(|| format!("Invalid Pyth price {}", account))?;
+        Ok(PriceSnapshot {
+            mint: mint.to_string(),
+            currency: "usd".to_string(),
+            price,
+            source: format!("pyth:{}", account),
+            published_at,
+        })
+    }
+}
+
+/// Price of `mint` in `currency` in a CoinGecko `simple/token_price` answer
+pub fn parse_coingecko_price(body: &str, mint: &Pubkey, currency: &str) -> Result<(f64, i64)> {
+    let answer: serde_json::Value = serde_json::from_str(body)?;
+    let mint = mint.to_string();
+    let prices = answer
+        .as_object()
+        .and_then(|tokens| {
+            tokens
+                .iter()
+                .find(|(address, _)| address.eq_ignore_ascii_case(&mint))
+        })
+        .map(|(_, prices)| prices)
+        .ok_or_else(|| anyhow!("CoinGecko has no price of {}", mint))?;
+    let price = prices[currency.to_lowercase().as_str()]
+        .as_f64()
+        .ok_or_else(|| anyhow!("CoinGecko has no {} price of {}", currency, mint))?;
+    let published_at = prices["last_updated_at"].as_i64().unwrap_or_else(now);
+    Ok((price, published_at))
+}
+
+/// Prices of the CoinGecko API, by the address of the mint on Solana
+pub struct Coingecko {
+    base_url: String,
+}
+
+impl Coingecko {
+    /// Feed of the public API
+    pub fn new() -> Self {
+        Self::with_base_url("https://api.coingecko.com/api/v3")
+    }
+
+    /// Feed of a CoinGecko compatible API, e.g. the pro one
+    pub fn with_base_url(base_url: impl ToString) -> Self {
+        Self {
+            base_url: base_url.to_string(),
+        }
+    }
+}
+
+impl Default for Coingecko {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PriceFeed for Coingecko {
+    fn price(&self, mint: &Pubkey, currency: &str) -> Result<PriceSnapshot> {
+        let url = format!(
+            "{}/simple/token_price/solana?contract_addresses={}&vs_currencies={}&include_last_updated_at=true",
+            self.base_url,
+            mint,
+            currency.to_lowercase()
+        );
+        let body = reqwest::blocking::get(&url)
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .with_context(|| format!("Can't fetch the price of {} from CoinGecko", mint))?;
+        let (price, published_at) = parse_coingecko_price(&body, mint, currency)?;
+        Ok(PriceSnapshot {
+            mint: mint.to_string(),
+            currency: currency.to_lowercase(),
+            price,
+            source: format!("coingecko:{}", self.base_url),
+            published_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_prices() {
+        let (usdc, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let content = format!("# closing prices\n{} = 0.9998\n\n", usdc);
+        let prices = FixedPrices::parse(&content, "USD", "file:prices", 1_700_000_000).unwrap();
+        let snapshot = prices.price(&usdc, "usd").unwrap();
+        assert_eq!(snapshot.price, 0.9998);
+        assert_eq!(snapshot.published_at, 1_700_000_000);
+        assert!(prices.price(&other, "usd").is_err());
+        assert!(prices.price(&usdc, "eur").is_err());
+        assert!(FixedPrices::parse("not a line", "usd", "file:prices", 0).is_err());
+
+        let valued = value(2_500_000, 6, &snapshot);
+        assert_eq!(valued.amount, 2.5);
+        assert_eq!(valued.value, 2.5 * 0.9998);
+    }
+
+    #[test]
+    fn test_parse_pyth_price() {
+        let mut data = vec![0; 240];
+        data[0..4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+        data[8..12].copy_from_slice(&PYTH_PRICE_ACCOUNT.to_le_bytes());
+        data[20..24].copy_from_slice(&(-8i32).to_le_bytes());
+        data[96..104].copy_from_slice(&1_700_000_000i64.to_le_bytes());
+        data[208..216].copy_from_slice(&2_512_345_678i64.to_le_bytes());
+        data[224..228].copy_from_slice(&PYTH_TRADING.to_le_bytes());
+        let (price, published_at) = parse_pyth_price(&data).unwrap();
+        assert!((price - 25.12345678).abs() < 1e-9);
+        assert_eq!(published_at, 1_700_000_000);
+
+        data[224..228].copy_from_slice(&0u32.to_le_bytes());
+        assert!(parse_pyth_price(&data).is_err());
+        assert!(parse_pyth_price(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn test_parse_coingecko_price() {
+        let mint = Pubkey::new_unique();
+        let body = format!(
+            r#"{{"{}": {{"usd": 1.01, "last_updated_at": 1700000000}}}}"#,
+            mint.to_string().to_lowercase()
+        );
+        assert_eq!(
+            parse_coingecko_price(&body, &mint, "USD").unwrap(),
+            (1.01, 1_700_000_000)
+        );
+        assert!(parse_coingecko_price(&body, &mint, "eur").is_err());
+        assert!(parse_coingecko_price("{}", &mint, "usd").is_err());
+    }
+}
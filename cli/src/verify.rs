@@ -0,0 +1,131 @@
+//! Reproducible build verification
+
+use anyhow::{anyhow, bail, Result};
+use solana_sdk::{
+    bpf_loader, bpf_loader_deprecated,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    hash::hash,
+    pubkey::Pubkey,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use token_market_cli::throttle::ThrottledClient;
+
+/// Name of the artifact produced by `cargo build-bpf` for the program crate
+const ARTIFACT_NAME: &str = "token_market.so";
+
+/// Fetch the bytecode currently deployed under `program_id`.
+///
+/// For upgradeable programs the bytes are read from the program data account,
+/// skipping the loader header. The returned buffer keeps the zero padding the
+/// loader leaves after the ELF.
+pub fn fetch_program_data(rpc_client: &ThrottledClient, program_id: &Pubkey) -> Result<Vec<u8>> {
+    let account = rpc_client.get_account(program_id)?;
+
+    if account.owner == bpf_loader::id() || account.owner == bpf_loader_deprecated::id() {
+        return Ok(account.data);
+    }
+    if account.owner != bpf_loader_upgradeable::id() {
+        bail!("{} is not owned by a BPF loader", program_id);
+    }
+
+    let programdata_address = match bincode::deserialize(&account.data)? {
+        UpgradeableLoaderState::Program {
+            programdata_address,
+        } => programdata_address,
+        _ => bail!("{} is not an upgradeable program account", program_id),
+    };
+    let programdata = rpc_client.get_account(&programdata_address)?;
+    let offset = UpgradeableLoaderState::programdata_data_offset()
+        .map_err(|err| anyhow!("Can't compute program data offset: {}", err))?;
+    if programdata.data.len() < offset {
+        bail!("Program data account {} is too short", programdata_address);
+    }
+
+    Ok(programdata.data[offset..].to_vec())
+}
+
+/// Rebuild the program from the given commit of the repository at `repo`
+/// and return the produced BPF artifact.
+pub fn build_artifact(repo: &Path, commit: &str) -> Result<Vec<u8>> {
+    let work_dir = std::env::temp_dir().join(format!("token-market-verify-{}", commit));
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir)?;
+    }
+    fs::create_dir_all(&work_dir)?;
+
+    let archive = work_dir.join("source.tar");
+    run(Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(&["archive", "--format=tar", "--output"])
+        .arg(&archive)
+        .arg(commit))?;
+    run(Command::new("tar")
+        .arg("-xf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&work_dir))?;
+
+    let out_dir = work_dir.join("deploy");
+    run(Command::new("cargo")
+        .arg("build-bpf")
+        .arg("--manifest-path")
+        .arg(work_dir.join("program").join("Cargo.toml"))
+        .arg("--bpf-out-dir")
+        .arg(&out_dir))?;
+
+    Ok(fs::read(out_dir.join(ARTIFACT_NAME))?)
+}
+
+/// Compare a locally built (or downloaded) artifact against the deployed bytecode.
+///
+/// Deployed program data is allocated with some headroom, so everything past
+/// the artifact length must be zero padding.
+pub fn matches_deployed(artifact: &[u8], deployed: &[u8]) -> bool {
+    deployed.len() >= artifact.len()
+        && &deployed[..artifact.len()] == artifact
+        && deployed[artifact.len()..].iter().all(|byte| *byte == 0)
+}
+
+/// Process `verify-build` command
+pub fn verify_build(
+    rpc_client: &ThrottledClient,
+    program_id: &Pubkey,
+    repo: &Path,
+    commit: &str,
+    artifact_path: Option<PathBuf>,
+) -> Result<()> {
+    let artifact = match artifact_path {
+        Some(path) => {
+            println!("Using artifact {}", path.display());
+            fs::read(path)?
+        }
+        None => {
+            println!("Building commit {}...", commit);
+            build_artifact(repo, commit)?
+        }
+    };
+    let deployed = fetch_program_data(rpc_client, program_id)?;
+    let deployed_code = &deployed[..artifact.len().min(deployed.len())];
+
+    println!("Artifact hash: {}", hash(&artifact));
+    println!("On-chain hash: {}", hash(deployed_code));
+
+    if !matches_deployed(&artifact, &deployed) {
+        bail!("Program {} does not match commit {}", program_id, commit);
+    }
+    println!("Program {} matches commit {}", program_id, commit);
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let status = command.status()?;
+    if !status.success() {
+        bail!("Command {:?} failed with {}", command, status);
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! Purchase vouchers and price quotes signed off-chain with a secp256k1 key
+
+use anyhow::{anyhow, bail, Context, Result};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_sdk::{
+    instruction::Instruction,
+    secp256k1_instruction::{construct_eth_pubkey, new_secp256k1_instruction},
+    secp256k1_program,
+};
+use std::{fs, path::Path};
+
+/// Load a secp256k1 secret key stored as 64 hex characters
+pub fn load_key(path: &Path) -> Result<secp256k1::SecretKey> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Can't read voucher key {}", path.display()))?;
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(content.trim(), &mut bytes)
+        .with_context(|| format!("{} doesn't hold a hex encoded key", path.display()))?;
+    secp256k1::SecretKey::parse(&bytes)
+        .map_err(|err| anyhow!("Invalid voucher key {}: {:?}", path.display(), err))
+}
+
+/// Ethereum address markets store to accept vouchers signed with `key`
+pub fn eth_address(key: &secp256k1::SecretKey) -> [u8; 20] {
+    construct_eth_pubkey(&secp256k1::PublicKey::from_secret_key(key))
+}
+
+/// Parse an Ethereum address given as 40 hex characters, with or without `0x`
+pub fn parse_eth_address(address: &str) -> Result<[u8; 20]> {
+    let mut bytes = [0; 20];
+    hex::decode_to_slice(address.trim_start_matches("0x"), &mut bytes)
+        .with_context(|| format!("Invalid Ethereum address {}", address))?;
+    Ok(bytes)
+}
+
+/// Sign a voucher or quote with `key`. It is handed to the buyer as the hex
+/// encoded data of the secp256k1 program instruction verifying it.
+pub fn sign<T: BorshSerialize>(key: &secp256k1::SecretKey, message: &T) -> Result<String> {
+    let instruction = new_secp256k1_instruction(key, &message.try_to_vec()?);
+    Ok(hex::encode(instruction.data))
+}
+
+/// Decode a voucher or quote produced by [sign](fn.sign.html) into the secp256k1
+/// instruction to place first in the purchase transaction and the message it signs
+pub fn decode<T: BorshDeserialize>(signed: &str) -> Result<(Instruction, T)> {
+    let data = hex::decode(signed.trim()).context("Signed message is not hex encoded")?;
+    // count followed by the offsets of the only signature
+    if data.len() < 12 || data[0] != 1 {
+        bail!("Signed message must carry exactly one signature");
+    }
+    let message_offset = u16::from_le_bytes([data[7], data[8]]) as usize;
+    let message_size = u16::from_le_bytes([data[9], data[10]]) as usize;
+    let message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or_else(|| anyhow!("Signed message is truncated"))?;
+    let decoded = T::try_from_slice(message).context("Signed message is malformed")?;
+
+    let instruction = Instruction {
+        program_id: secp256k1_program::id(),
+        accounts: vec![],
+        data,
+    };
+    Ok((instruction, decoded))
+}
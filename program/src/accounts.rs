@@ -0,0 +1,795 @@
+//! Account lists of every instruction by role
+//!
+//! Each instruction has a struct naming its accounts and index constants
+//! for their positions, so account lists are built by role instead of by
+//! position. The builders in [instruction](../instruction/index.html) use them too.
+
+use solana_program::{instruction::AccountMeta, pubkey::Pubkey};
+
+fn meta(pubkey: Pubkey, is_writable: bool, is_signer: bool) -> AccountMeta {
+    if is_writable {
+        AccountMeta::new(pubkey, is_signer)
+    } else {
+        AccountMeta::new_readonly(pubkey, is_signer)
+    }
+}
+
+/// Declare the accounts struct of an instruction.
+/// Every account is listed in instruction order as
+/// `INDEX_CONSTANT field: (writable, signer)`.
+macro_rules! instruction_accounts {
+    (
+        $(#[$doc:meta])*
+        $name:ident {
+            $($index:ident $field:ident: ($writable:expr, $signer:expr)),* $(,)?
+        }
+    ) => {
+        $(#[$doc])*
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct $name {
+            $(pub $field: Pubkey,)*
+        }
+
+        impl $name {
+            instruction_accounts!(@indices 0usize, $($index)*);
+
+            /// Account metas in instruction order
+            pub fn to_metas(&self) -> Vec<AccountMeta> {
+                vec![$(meta(self.$field, $writable, $signer)),*]
+            }
+        }
+    };
+    (@indices $position:expr, $index:ident $($rest:ident)*) => {
+        pub const $index: usize = $position;
+        instruction_accounts!(@indices $position + 1, $($rest)*);
+    };
+    (@indices $position:expr,) => {};
+}
+
+instruction_accounts! {
+    /// Accounts of [Initialize](../instruction/enum.TokenMarketInstructions.html#variant.Initialize)
+    InitializeAccounts {
+        OWNER owner: (true, false),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (true, false),
+        BANK bank: (true, false),
+        EMITTER emitter: (true, false),
+        ACCEPTABLE acceptable: (true, false),
+        TOKEN_PROGRAM token_program: (true, false),
+        RENT rent: (false, false),
+        MINT_INDEX mint_index: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokens](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokens)
+    BuyTokensAccounts {
+        MARKET market: (true, false),
+        BANK bank: (true, false),
+        RECIPIENT recipient: (true, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensWithTip](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensWithTip)
+    BuyTokensWithTipAccounts {
+        MARKET market: (true, false),
+        BANK bank: (true, false),
+        RECIPIENT recipient: (true, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+        TIP tip: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [CreateVestingVault](../instruction/enum.TokenMarketInstructions.html#variant.CreateVestingVault)
+    CreateVestingVaultAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (false, false),
+        VESTING vesting: (true, false),
+        VAULT vault: (true, false),
+        EMITTER emitter: (true, false),
+        BENEFICIARY beneficiary: (false, false),
+        AUTHORITY authority: (false, false),
+        RENT rent: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [ClaimVested](../instruction/enum.TokenMarketInstructions.html#variant.ClaimVested)
+    ClaimVestedAccounts {
+        BENEFICIARY beneficiary: (false, true),
+        VESTING vesting: (true, false),
+        VAULT vault: (true, false),
+        DESTINATION destination: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [RevokeVesting](../instruction/enum.TokenMarketInstructions.html#variant.RevokeVesting)
+    RevokeVestingAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (false, false),
+        VESTING vesting: (true, false),
+        VAULT vault: (true, false),
+        DESTINATION destination: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [MigrateAcceptableMint](../instruction/enum.TokenMarketInstructions.html#variant.MigrateAcceptableMint)
+    MigrateAcceptableMintAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+        NEW_BANK new_bank: (true, false),
+        NEW_ACCEPTABLE new_acceptable: (false, false),
+        EMITTER emitter: (false, false),
+        RENT rent: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [CreateHoldingsMigration](../instruction/enum.TokenMarketInstructions.html#variant.CreateHoldingsMigration)
+    CreateHoldingsMigrationAccounts {
+        FROM_OWNER from_owner: (false, true),
+        TO_OWNER to_owner: (false, true),
+        FROM_MARKET from_market: (false, false),
+        TO_MARKET to_market: (false, false),
+        MIGRATION migration: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [MigrateHoldings](../instruction/enum.TokenMarketInstructions.html#variant.MigrateHoldings)
+    MigrateHoldingsAccounts {
+        HOLDER holder: (false, true),
+        MIGRATION migration: (false, false),
+        SOURCE source: (true, false),
+        FROM_EMITTER from_emitter: (true, false),
+        TO_EMITTER to_emitter: (true, false),
+        DESTINATION destination: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetVoucherSigner](../instruction/enum.TokenMarketInstructions.html#variant.SetVoucherSigner)
+    SetVoucherSignerAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensWithVoucher](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensWithVoucher)
+    BuyTokensWithVoucherAccounts {
+        MARKET market: (true, false),
+        BANK bank: (true, false),
+        RECIPIENT recipient: (true, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+        INSTRUCTIONS instructions: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetTreasury](../instruction/enum.TokenMarketInstructions.html#variant.SetTreasury)
+    SetTreasuryAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+        TREASURY treasury: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetListingInfo](../instruction/enum.TokenMarketInstructions.html#variant.SetListingInfo)
+    SetListingInfoAccounts {
+        OWNER owner: (false, true),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (false, false),
+        LISTING listing: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [Deprecate](../instruction/enum.TokenMarketInstructions.html#variant.Deprecate)
+    DeprecateAccounts {
+        OWNER owner: (false, true),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (true, false),
+        NOTICE notice: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetOverpaymentPolicy](../instruction/enum.TokenMarketInstructions.html#variant.SetOverpaymentPolicy)
+    SetOverpaymentPolicyAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts that follow the accounts of any purchase instruction on markets
+    /// [minting receipts](../state/struct.TokenMarket.html#structfield.mint_receipts)
+    PurchaseReceiptAccounts {
+        FEE_PAYER fee_payer: (true, true),
+        RECEIPT_MINT receipt_mint: (true, true),
+        RECEIPT_HOLDING receipt_holding: (true, true),
+        RECEIPT receipt: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetReceiptMinting](../instruction/enum.TokenMarketInstructions.html#variant.SetReceiptMinting)
+    SetReceiptMintingAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensWithCampaign](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensWithCampaign)
+    BuyTokensWithCampaignAccounts {
+        MARKET market: (true, false),
+        BANK bank: (true, false),
+        RECIPIENT recipient: (true, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+        FEE_PAYER fee_payer: (true, true),
+        CAMPAIGN campaign: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensWithQuote](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensWithQuote)
+    BuyTokensWithQuoteAccounts {
+        MARKET market: (true, false),
+        BANK bank: (true, false),
+        RECIPIENT recipient: (true, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+        INSTRUCTIONS instructions: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetDelegation](../instruction/enum.TokenMarketInstructions.html#variant.SetDelegation)
+    SetDelegationAccounts {
+        OWNER owner: (false, true),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (false, false),
+        DELEGATE delegate: (false, false),
+        DELEGATION delegation: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetMemoPolicy](../instruction/enum.TokenMarketInstructions.html#variant.SetMemoPolicy)
+    SetMemoPolicyAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetSandwichGuard](../instruction/enum.TokenMarketInstructions.html#variant.SetSandwichGuard)
+    SetSandwichGuardAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetWormholeEmitter](../instruction/enum.TokenMarketInstructions.html#variant.SetWormholeEmitter)
+    SetWormholeEmitterAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [RedeemWormholePurchase](../instruction/enum.TokenMarketInstructions.html#variant.RedeemWormholePurchase)
+    RedeemWormholePurchaseAccounts {
+        MARKET market: (false, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        RECIPIENT recipient: (true, false),
+        POSTED_VAA posted_vaa: (false, false),
+        CONSUMED_VAA consumed_vaa: (true, false),
+        FEE_PAYER fee_payer: (true, true),
+        TOKEN_PROGRAM token_program: (false, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetOnrampSigner](../instruction/enum.TokenMarketInstructions.html#variant.SetOnrampSigner)
+    SetOnrampSignerAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensWithFiat](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensWithFiat)
+    BuyTokensWithFiatAccounts {
+        ONRAMP_SIGNER onramp_signer: (false, true),
+        MARKET market: (false, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        RECIPIENT recipient: (true, false),
+        SETTLEMENT settlement: (true, false),
+        FEE_PAYER fee_payer: (true, true),
+        TOKEN_PROGRAM token_program: (false, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetDisputeEscrow](../instruction/enum.TokenMarketInstructions.html#variant.SetDisputeEscrow)
+    SetDisputeEscrowAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensEscrowed](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensEscrowed)
+    BuyTokensEscrowedAccounts {
+        MARKET market: (false, false),
+        BANK bank: (true, false),
+        RECIPIENT recipient: (false, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        ACCEPTABLE acceptable: (false, false),
+        AUTHORITY authority: (false, false),
+        ESCROW escrow: (true, false),
+        VAULT vault: (true, false),
+        PAYMENT payment: (true, false),
+        FEE_PAYER fee_payer: (true, true),
+        TOKEN_PROGRAM token_program: (false, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+        INSTRUCTIONS instructions: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [ReleaseEscrow](../instruction/enum.TokenMarketInstructions.html#variant.ReleaseEscrow)
+    ReleaseEscrowAccounts {
+        MARKET market: (false, false),
+        ESCROW escrow: (true, false),
+        VAULT vault: (true, false),
+        PAYMENT payment: (true, false),
+        RECIPIENT recipient: (true, false),
+        BANK bank: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [DisputeEscrow](../instruction/enum.TokenMarketInstructions.html#variant.DisputeEscrow)
+    DisputeEscrowAccounts {
+        DISPUTER disputer: (false, true),
+        MARKET market: (false, false),
+        ESCROW escrow: (true, false),
+        VAULT vault: (true, false),
+        PAYMENT payment: (true, false),
+        EMITTER emitter: (true, false),
+        WRITE_OFF write_off: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [RecreateBank](../instruction/enum.TokenMarketInstructions.html#variant.RecreateBank)
+    RecreateBankAccounts {
+        OWNER owner: (false, true),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (true, false),
+        MISSING_BANK missing_bank: (false, false),
+        BANK bank: (true, false),
+        ACCEPTABLE acceptable: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [CreateCoupon](../instruction/enum.TokenMarketInstructions.html#variant.CreateCoupon)
+    CreateCouponAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (false, false),
+        COUPON coupon: (true, false),
+        FEE_PAYER fee_payer: (true, true),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyGift](../instruction/enum.TokenMarketInstructions.html#variant.BuyGift)
+    BuyGiftAccounts {
+        MARKET market: (false, false),
+        BANK bank: (true, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        ACCEPTABLE acceptable: (false, false),
+        AUTHORITY authority: (false, false),
+        GIFT gift: (true, false),
+        VAULT vault: (true, false),
+        PAYMENT payment: (true, false),
+        FEE_PAYER fee_payer: (true, true),
+        TOKEN_PROGRAM token_program: (false, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+        INSTRUCTIONS instructions: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [ClaimGift](../instruction/enum.TokenMarketInstructions.html#variant.ClaimGift)
+    ClaimGiftAccounts {
+        MARKET market: (false, false),
+        GIFT gift: (true, false),
+        VAULT vault: (true, false),
+        PAYMENT payment: (true, false),
+        RECIPIENT recipient: (true, false),
+        BANK bank: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [RefundGift](../instruction/enum.TokenMarketInstructions.html#variant.RefundGift)
+    RefundGiftAccounts {
+        MARKET market: (false, false),
+        GIFT gift: (true, false),
+        VAULT vault: (true, false),
+        PAYMENT payment: (true, false),
+        EMITTER emitter: (true, false),
+        WRITE_OFF write_off: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SellTokens](../instruction/enum.TokenMarketInstructions.html#variant.SellTokens)
+    SellTokensAccounts {
+        SELLER seller: (false, true),
+        MARKET market: (false, false),
+        BANK bank: (true, false),
+        HOLDING holding: (true, false),
+        EMITTER emitter: (true, false),
+        DESTINATION destination: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [WithdrawBank](../instruction/enum.TokenMarketInstructions.html#variant.WithdrawBank)
+    WithdrawBankAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+        BANK bank: (true, false),
+        DESTINATION destination: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [Pause](../instruction/enum.TokenMarketInstructions.html#variant.Pause)
+    PauseAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [Resume](../instruction/enum.TokenMarketInstructions.html#variant.Resume)
+    ResumeAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [CrankVesting](../instruction/enum.TokenMarketInstructions.html#variant.CrankVesting),
+    /// followed by the [VestingClaimAccounts](struct.VestingClaimAccounts.html) of every vesting
+    CrankVestingAccounts {
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of one vesting claimed by [CrankVesting](../instruction/enum.TokenMarketInstructions.html#variant.CrankVesting)
+    VestingClaimAccounts {
+        VESTING vesting: (true, false),
+        VAULT vault: (true, false),
+        DESTINATION destination: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetSalePhases](../instruction/enum.TokenMarketInstructions.html#variant.SetSalePhases)
+    SetSalePhasesAccounts {
+        OWNER owner: (false, true),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (true, false),
+        PHASES phases: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [AdvanceSalePhase](../instruction/enum.TokenMarketInstructions.html#variant.AdvanceSalePhase)
+    AdvanceSalePhaseAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (false, false),
+        PHASES phases: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetProtocolFee](../instruction/enum.TokenMarketInstructions.html#variant.SetProtocolFee)
+    SetProtocolFeeAccounts {
+        OWNER owner: (false, true),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (true, false),
+        FEE_VAULT fee_vault: (true, false),
+        ACCEPTABLE acceptable: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [WithdrawFees](../instruction/enum.TokenMarketInstructions.html#variant.WithdrawFees)
+    WithdrawFeesAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (false, false),
+        FEE_VAULT fee_vault: (true, false),
+        DESTINATION destination: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetPurchaseLimits](../instruction/enum.TokenMarketInstructions.html#variant.SetPurchaseLimits)
+    SetPurchaseLimitsAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetWalletCap](../instruction/enum.TokenMarketInstructions.html#variant.SetWalletCap)
+    SetWalletCapAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetSaleWindow](../instruction/enum.TokenMarketInstructions.html#variant.SetSaleWindow)
+    SetSaleWindowAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetAllowlist](../instruction/enum.TokenMarketInstructions.html#variant.SetAllowlist)
+    SetAllowlistAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetAllowlistRoot](../instruction/enum.TokenMarketInstructions.html#variant.SetAllowlistRoot)
+    SetAllowlistRootAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [AddToAllowlist](../instruction/enum.TokenMarketInstructions.html#variant.AddToAllowlist)
+    AddToAllowlistAccounts {
+        OWNER owner: (false, true),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (false, false),
+        ENTRY entry: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [RemoveFromAllowlist](../instruction/enum.TokenMarketInstructions.html#variant.RemoveFromAllowlist)
+    RemoveFromAllowlistAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (false, false),
+        ENTRY entry: (true, false),
+        RECEIVER receiver: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetNotificationConfig](../instruction/enum.TokenMarketInstructions.html#variant.SetNotificationConfig)
+    SetNotificationConfigAccounts {
+        OWNER owner: (false, true),
+        FEE_PAYER fee_payer: (true, true),
+        MARKET market: (false, false),
+        CONFIG config: (true, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts following a purchase on a market tracking its buyers, see
+    /// [with_buyer_record](../instruction/fn.with_buyer_record.html)
+    BuyerRecordAccounts {
+        RECORD record: (true, false),
+        FEE_PAYER fee_payer: (true, true),
+        SYSTEM_PROGRAM system_program: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensWithSol](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensWithSol)
+    BuyTokensWithSolAccounts {
+        BUYER buyer: (true, true),
+        MARKET market: (false, false),
+        BANK bank: (true, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        RECIPIENT recipient: (true, false),
+        TOKEN_PROGRAM token_program: (false, false),
+        SYSTEM_PROGRAM system_program: (false, false),
+        WRAP wrap: (true, false),
+        NATIVE_MINT native_mint: (false, false),
+        RENT rent: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensMulti](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensMulti),
+    /// followed by the recipient token accounts
+    BuyTokensMultiAccounts {
+        MARKET market: (false, false),
+        BANK bank: (true, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [BuyTokensExactOut](../instruction/enum.TokenMarketInstructions.html#variant.BuyTokensExactOut)
+    BuyTokensExactOutAccounts {
+        MARKET market: (true, false),
+        BANK bank: (true, false),
+        RECIPIENT recipient: (true, false),
+        WRITE_OFF write_off: (true, false),
+        EMITTER emitter: (true, false),
+        AUTHORITY authority: (false, false),
+        TOKEN_PROGRAM token_program: (false, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [CloseBuyerRecord](../instruction/enum.TokenMarketInstructions.html#variant.CloseBuyerRecord)
+    CloseBuyerRecordAccounts {
+        MARKET market: (false, false),
+        RECORD record: (true, false),
+        BUYER buyer: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [CloseReceipt](../instruction/enum.TokenMarketInstructions.html#variant.CloseReceipt)
+    CloseReceiptAccounts {
+        MARKET market: (false, false),
+        RECEIPT_MINT receipt_mint: (false, false),
+        RECEIPT receipt: (true, false),
+        BUYER buyer: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetStakeWeight](../instruction/enum.TokenMarketInstructions.html#variant.SetStakeWeight)
+    SetStakeWeightAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetWithdrawRate](../instruction/enum.TokenMarketInstructions.html#variant.SetWithdrawRate)
+    SetWithdrawRateAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetWalletSpendCap](../instruction/enum.TokenMarketInstructions.html#variant.SetWalletSpendCap)
+    SetWalletSpendCapAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetPurchaseCooldown](../instruction/enum.TokenMarketInstructions.html#variant.SetPurchaseCooldown)
+    SetPurchaseCooldownAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [SetLoyaltyTiers](../instruction/enum.TokenMarketInstructions.html#variant.SetLoyaltyTiers)
+    SetLoyaltyTiersAccounts {
+        OWNER owner: (false, true),
+        MARKET market: (true, false),
+    }
+}
+
+instruction_accounts! {
+    /// Account that follows the accounts of an owner instruction a delegate
+    /// signs in place of the owner, see [DelegatedAction](../state/enum.DelegatedAction.html)
+    DelegateAccounts {
+        DELEGATION delegation: (true, false),
+    }
+}
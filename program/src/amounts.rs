@@ -0,0 +1,122 @@
+//! Typed token amounts
+//!
+//! Raw amounts of the two mints a market deals with, and human readable
+//! amounts, are distinct types so they can't be mixed up by accident.
+
+use std::{fmt, str::FromStr};
+
+/// Raw amount of the token the market accepts as payment
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct AcceptableAmount(pub u64);
+
+/// Raw amount of the token the market emits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct EmittedAmount(pub u64);
+
+/// Human readable amount, e.g. `1.5`, converted to a raw amount with the
+/// decimals of the mint it refers to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UiAmount {
+    /// All digits of the amount without the decimal point
+    digits: u128,
+    /// Number of digits after the decimal point
+    scale: u8,
+}
+
+impl UiAmount {
+    /// Human readable form of the raw `amount` of a mint with `decimals`
+    pub fn from_raw(amount: u64, decimals: u8) -> Self {
+        Self {
+            digits: amount as u128,
+            scale: decimals,
+        }
+    }
+
+    /// Raw amount of a mint with `decimals`.
+    /// Fails if the amount has more fractional digits than the mint or doesn't fit `u64`.
+    pub fn to_raw(&self, decimals: u8) -> Option<u64> {
+        let extra = decimals.checked_sub(self.scale)?;
+        let raw = self.digits.checked_mul(10u128.checked_pow(extra as u32)?)?;
+        if raw > u64::MAX as u128 {
+            None
+        } else {
+            Some(raw as u64)
+        }
+    }
+
+    /// Raw amount of the acceptable mint with `decimals`
+    pub fn to_acceptable(&self, decimals: u8) -> Option<AcceptableAmount> {
+        self.to_raw(decimals).map(AcceptableAmount)
+    }
+
+    /// Raw amount of the emitter mint with `decimals`
+    pub fn to_emitted(&self, decimals: u8) -> Option<EmittedAmount> {
+        self.to_raw(decimals).map(EmittedAmount)
+    }
+}
+
+/// Error returned when a string is not a valid [UiAmount](struct.UiAmount.html)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseUiAmountError;
+
+impl fmt::Display for ParseUiAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid token amount")
+    }
+}
+
+impl std::error::Error for ParseUiAmountError {}
+
+impl FromStr for UiAmount {
+    type Err = ParseUiAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (integer, fraction) = match s.find('.') {
+            Some(point) => (&s[..point], &s[point + 1..]),
+            None => (s, ""),
+        };
+        let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if integer.is_empty() && fraction.is_empty()
+            || !all_digits(integer)
+            || !all_digits(fraction)
+            || fraction.len() > u8::MAX as usize
+        {
+            return Err(ParseUiAmountError);
+        }
+
+        let mut digits = 0u128;
+        for byte in integer.bytes().chain(fraction.bytes()) {
+            digits = digits
+                .checked_mul(10)
+                .and_then(|digits| digits.checked_add((byte - b'0') as u128))
+                .ok_or(ParseUiAmountError)?;
+        }
+        Ok(Self {
+            digits,
+            scale: fraction.len() as u8,
+        })
+    }
+}
+
+impl fmt::Display for UiAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = format!("{:0>width$}", self.digits, width = self.scale as usize + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            f.write_str(integer)
+        } else {
+            write!(f, "{}.{}", integer, fraction)
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Bonding curve pricing shared by the program and off-chain tools
+//!
+//! A market on a [BondingCurve](../state/struct.BondingCurve.html) prices
+//! every emitted token by the emitted tokens it sold before, its `total_sold`.
+//! The cost of a purchase is the integral of the price over the tokens it
+//! buys, rounded up to whole acceptable tokens:
+//!
+//! - on a linear curve the price at `sold` is `base_price + growth * sold / PRICE_SCALE`
+//! - on an exponential curve the price is `base_price` grown by `growth`
+//!   basis points, compounded once every `step` tokens sold
+//!
+//! Purchases pay an amount of acceptable tokens, so the program emits the
+//! most tokens that amount covers. Clients call [quote](fn.quote.html) with
+//! the same numbers to learn the cost of buying a given amount:
+//!
+//! ```
+//! use token_market::{
+//!     curve::{emitted_for, quote, PRICE_SCALE},
+//!     state::{BondingCurve, CurveKind},
+//! };
+//!
+//! let curve = BondingCurve {
+//!     kind: CurveKind::Linear,
+//!     base_price: PRICE_SCALE,
+//!     growth: PRICE_SCALE / 1000,
+//!     step: 0,
+//! };
+//! let cost = quote(&curve, 0, 1000).unwrap();
+//! assert_eq!(emitted_for(&curve, 0, cost), Some(1000));
+//! ```
+
+use crate::{
+    error::TokenMarketError,
+    state::{BondingCurve, CurveKind},
+};
+use std::convert::TryFrom;
+
+/// Fixed point scale of curve prices, a `base_price` of `PRICE_SCALE` sells at par
+pub const PRICE_SCALE: u64 = 1_000_000_000;
+
+/// Fixed point scale of the growth factor of exponential curves
+const ONE: u128 = 1_000_000_000_000_000_000;
+
+/// Basis points of a whole
+const BPS: u128 = 10_000;
+
+/// Fail unless `curve` can price purchases: a nonzero base price, and for
+/// an exponential curve a nonzero step
+pub fn check_bonding_curve(curve: &BondingCurve) -> Result<(), TokenMarketError> {
+    let valid = match curve.kind {
+        CurveKind::Off => true,
+        CurveKind::Linear => curve.base_price != 0,
+        CurveKind::Exponential => curve.base_price != 0 && curve.step != 0,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(TokenMarketError::InvalidBondingCurve)
+    }
+}
+
+/// Acceptable tokens buying `emitted` tokens costs once `sold` are sold,
+/// `None` if the curve is off or the cost overflows
+pub fn quote(curve: &BondingCurve, sold: u64, emitted: u64) -> Option<u64> {
+    let scaled = match curve.kind {
+        CurveKind::Off => return None,
+        CurveKind::Linear => linear_cost(curve, sold as u128, emitted as u128)?,
+        CurveKind::Exponential => exponential_cost(curve, sold as u128, emitted as u128)?,
+    };
+    let scale = PRICE_SCALE as u128;
+    u64::try_from(scaled.checked_add(scale - 1)? / scale).ok()
+}
+
+/// Most emitted tokens `amount` acceptable tokens buy once `sold` are sold,
+/// `None` if the curve is off. What is left of `amount` stays with the market.
+pub fn emitted_for(curve: &BondingCurve, sold: u64, amount: u64) -> Option<u64> {
+    if check_bonding_curve(curve).is_err() || curve.kind == CurveKind::Off {
+        return None;
+    }
+    // the price never falls below the base price
+    let most = amount as u128 * PRICE_SCALE as u128 / curve.base_price as u128;
+    let (mut low, mut high) = (0, most.min((u64::MAX - sold) as u128) as u64);
+    while low < high {
+        let middle = low + (high - low + 1) / 2;
+        match quote(curve, sold, middle) {
+            Some(cost) if cost <= amount => low = middle,
+            _ => high = middle - 1,
+        }
+    }
+    Some(low)
+}
+
+/// `a * b / d` rounded down, `None` if it overflows
+fn mul_div(a: u128, b: u128, d: u128) -> Option<u128> {
+    (a / d)
+        .checked_mul(b)?
+        .checked_add((a % d).checked_mul(b)? / d)
+}
+
+/// Scaled cost of `n` tokens from `sold` on a linear curve
+fn linear_cost(curve: &BondingCurve, sold: u128, n: u128) -> Option<u128> {
+    // (sold + n)^2 - sold^2 without squaring sold
+    let area = n.checked_mul(sold.checked_mul(2)?.checked_add(n)?)?;
+    let growth = mul_div(area, curve.growth as u128, 2 * PRICE_SCALE as u128)?;
+    (curve.base_price as u128)
+        .checked_mul(n)?
+        .checked_add(growth)
+}
+
+/// `factor` to the `exponent`, both and the result scaled by `ONE`
+fn pow(factor: u128, mut exponent: u128) -> Option<u128> {
+    let (mut base, mut result) = (factor, ONE);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_div(result, base, ONE)?;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = mul_div(base, base, ONE)?;
+        }
+    }
+    Some(result)
+}
+
+/// Scaled cost of `n` tokens from `sold` on an exponential curve: the rest
+/// of the current step, the whole steps after it as a geometric series and
+/// the part of the last step
+fn exponential_cost(curve: &BondingCurve, sold: u128, n: u128) -> Option<u128> {
+    let step = curve.step as u128;
+    let factor = ONE + curve.growth as u128 * (ONE / BPS);
+    let price = |index: u128| mul_div(curve.base_price as u128, pow(factor, index)?, ONE);
+
+    let index = sold / step;
+    let first = n.min(step - sold % step);
+    let mut cost = price(index)?.checked_mul(first)?;
+    let rest = n - first;
+    let (steps, last) = (rest / step, rest % step);
+    if steps > 0 {
+        // 1 + factor + ... + factor^(steps - 1)
+        let series = match curve.growth {
+            0 => steps.checked_mul(ONE)?,
+            _ => mul_div(pow(factor, steps)? - ONE, ONE, factor - ONE)?,
+        };
+        let whole = mul_div(price(index + 1)?.checked_mul(step)?, series, ONE)?;
+        cost = cost.checked_add(whole)?;
+    }
+    if last > 0 {
+        cost = cost.checked_add(price(index + 1 + steps)?.checked_mul(last)?)?;
+    }
+    Some(cost)
+}
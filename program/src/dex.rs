@@ -0,0 +1,35 @@
+//! Program ids of exchanges a purchase transaction may trade on
+//!
+//! A transaction trading on one of them around a quoted purchase could move
+//! the market the quote was priced on, so guarded markets refuse it, see
+//! [SetSandwichGuard](../instruction/enum.TokenMarketInstructions.html#variant.SetSandwichGuard).
+
+use solana_program::pubkey::Pubkey;
+
+/// Serum DEX v3
+pub mod serum_v3 {
+    solana_program::declare_id!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
+}
+
+/// Raydium AMM v4
+pub mod raydium_v4 {
+    solana_program::declare_id!("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8");
+}
+
+/// Orca token swap v2
+pub mod orca_v2 {
+    solana_program::declare_id!("9W959DqEETiGZocYWCQPaJ6sBmUzgfxXfqGeTEdp3aQP");
+}
+
+/// Saber stable swap
+pub mod saber {
+    solana_program::declare_id!("SSwpkEEcbUqx4vtoEByFjSkhKdCT862DNVb52nZg6UZ");
+}
+
+/// Whether `program_id` is a known exchange
+pub fn is_dex_program(program_id: &Pubkey) -> bool {
+    serum_v3::check_id(program_id)
+        || raydium_v4::check_id(program_id)
+        || orca_v2::check_id(program_id)
+        || saber::check_id(program_id)
+}
@@ -14,6 +14,144 @@ use thiserror::Error;
 pub enum TokenMarketError {
     #[error("insufficient funds")]
     InsufficientFundsError,
+    #[error("invalid vesting schedule")]
+    InvalidVestingSchedule,
+    #[error("vesting is not revocable")]
+    VestingNotRevocable,
+    #[error("vesting already revoked")]
+    VestingAlreadyRevoked,
+    #[error("mint decimals mismatch")]
+    MintDecimalsMismatch,
+    #[error("legacy bank still tracked")]
+    LegacyBankExists,
+    #[error("invalid migration ratio")]
+    InvalidMigrationRatio,
+    #[error("delegation mismatch")]
+    DelegationMismatch,
+    #[error("invalid voucher")]
+    InvalidVoucher,
+    #[error("voucher expired")]
+    VoucherExpired,
+    #[error("invalid quote")]
+    InvalidQuote,
+    #[error("quote expired")]
+    QuoteExpired,
+    #[error("delegation limit exceeded")]
+    DelegationLimitExceeded,
+    #[error("feature disabled")]
+    FeatureDisabled,
+    #[error("recipient frozen")]
+    RecipientFrozen,
+    #[error("bank missing")]
+    BankMissing,
+    #[error("memo required")]
+    MemoRequired,
+    #[error("transaction not isolated")]
+    TransactionNotIsolated,
+    #[error("invalid vaa")]
+    InvalidVaa,
+    #[error("vaa consumed")]
+    VaaConsumed,
+    #[error("invalid onramp attestation")]
+    InvalidOnrampAttestation,
+    #[error("fiat payment settled")]
+    FiatPaymentSettled,
+    #[error("escrow required")]
+    EscrowRequired,
+    #[error("invalid dispute window")]
+    InvalidDisputeWindow,
+    #[error("dispute window open")]
+    DisputeWindowOpen,
+    #[error("dispute window closed")]
+    DisputeWindowClosed,
+    #[error("escrow settled")]
+    EscrowSettled,
+    #[error("invalid discount")]
+    InvalidDiscount,
+    #[error("coupon expired")]
+    CouponExpired,
+    #[error("coupon exhausted")]
+    CouponExhausted,
+    #[error("invalid gift expiry")]
+    InvalidGiftExpiry,
+    #[error("gift expired")]
+    GiftExpired,
+    #[error("gift not expired")]
+    GiftNotExpired,
+    #[error("gift settled")]
+    GiftSettled,
+    #[error("unbacked selling")]
+    UnbackedSelling,
+    #[error("market paused")]
+    MarketPaused,
+    #[error("invalid sale phases")]
+    InvalidSalePhases,
+    #[error("sale not open")]
+    SaleNotOpen,
+    #[error("not allowlisted")]
+    NotAllowlisted,
+    #[error("phase limit exceeded")]
+    PhaseLimitExceeded,
+    #[error("phased sale")]
+    PhasedSale,
+    #[error("invalid protocol fee")]
+    InvalidProtocolFee,
+    #[error("purchase below minimum")]
+    PurchaseBelowMinimum,
+    #[error("purchase above maximum")]
+    PurchaseAboveMaximum,
+    #[error("invalid purchase limits")]
+    InvalidPurchaseLimits,
+    #[error("allowance not spent")]
+    AllowanceNotSpent,
+    #[error("wallet cap exceeded")]
+    WalletCapExceeded,
+    #[error("wallet capped")]
+    WalletCapped,
+    #[error("sale not started")]
+    SaleNotStarted,
+    #[error("sale ended")]
+    SaleEnded,
+    #[error("invalid sale window")]
+    InvalidSaleWindow,
+    #[error("sale window locked")]
+    SaleWindowLocked,
+    #[error("not on allowlist")]
+    NotOnAllowlist,
+    #[error("allowlisted market")]
+    AllowlistedMarket,
+    #[error("invalid allowlist proof")]
+    InvalidAllowlistProof,
+    #[error("invalid pricing tiers")]
+    InvalidPricingTiers,
+    #[error("pricing tiers sold out")]
+    TiersSoldOut,
+    #[error("tiered market")]
+    TieredMarket,
+    #[error("invalid bonding curve")]
+    InvalidBondingCurve,
+    #[error("acceptable mint not native")]
+    NotNativeMint,
+    #[error("slippage exceeded")]
+    SlippageExceeded,
+    #[error("record still needed")]
+    RecordStillNeeded,
+    #[error("invariant violated")]
+    InvariantViolated,
+    #[error("invalid stake weight")]
+    InvalidStakeWeight,
+    #[error("withdraw rate exceeded")]
+    WithdrawRateExceeded,
+    #[error("withdraw rate locked")]
+    WithdrawRateLocked,
+    #[error("spend cap exceeded")]
+    SpendCapExceeded,
+    #[error("purchase cooldown")]
+    PurchaseCooldown,
+    #[error("invalid purchase cooldown")]
+    InvalidPurchaseCooldown,
+    #[error("invalid loyalty tiers")]
+    InvalidLoyaltyTiers,
 }
 impl From<TokenMarketError> for ProgramError {
     fn from(e: TokenMarketError) -> Self {
@@ -33,6 +171,203 @@ impl PrintProgramError for TokenMarketError {
     {
         match self {
             TokenMarketError::InsufficientFundsError => msg!("Example error message"),
+            TokenMarketError::InvalidVestingSchedule => {
+                msg!("Error: cliff must not exceed a positive duration")
+            }
+            TokenMarketError::VestingNotRevocable => msg!("Error: vesting is not revocable"),
+            TokenMarketError::VestingAlreadyRevoked => msg!("Error: vesting already revoked"),
+            TokenMarketError::MintDecimalsMismatch => {
+                msg!("Error: new acceptable mint must have the same decimals")
+            }
+            TokenMarketError::LegacyBankExists => {
+                msg!("Error: market already tracks a legacy bank from a previous migration")
+            }
+            TokenMarketError::InvalidMigrationRatio => {
+                msg!("Error: migration ratio must be positive and convert to a non-zero amount")
+            }
+            TokenMarketError::DelegationMismatch => {
+                msg!("Error: market authority must be delegated exactly the paid amount")
+            }
+            TokenMarketError::InvalidVoucher => {
+                msg!("Error: purchase is not covered by a voucher signed by the market voucher signer")
+            }
+            TokenMarketError::VoucherExpired => msg!("Error: voucher expired"),
+            TokenMarketError::InvalidQuote => {
+                msg!(
+                    "Error: purchase is not covered by a quote signed by the market voucher signer"
+                )
+            }
+            TokenMarketError::QuoteExpired => msg!("Error: quote expired"),
+            TokenMarketError::DelegationLimitExceeded => {
+                msg!("Error: delegate is not allowed this much of the action today")
+            }
+            TokenMarketError::FeatureDisabled => {
+                msg!("Error: the market was created without this feature")
+            }
+            TokenMarketError::RecipientFrozen => {
+                msg!("Error: the recipient token account is frozen")
+            }
+            TokenMarketError::BankMissing => {
+                msg!("Error: the market bank was closed, recreate it with RecreateBank")
+            }
+            TokenMarketError::MemoRequired => {
+                msg!("Error: the market requires a memo in every purchase transaction")
+            }
+            TokenMarketError::InvalidVaa => {
+                msg!("Error: not a purchase on this market posted by Wormhole from the market emitter")
+            }
+            TokenMarketError::VaaConsumed => {
+                msg!("Error: the Wormhole message was already redeemed")
+            }
+            TokenMarketError::InvalidOnrampAttestation => {
+                msg!("Error: fiat purchases must be signed by the market on-ramp signer")
+            }
+            TokenMarketError::FiatPaymentSettled => {
+                msg!("Error: tokens were already minted for this fiat payment reference")
+            }
+            TokenMarketError::EscrowRequired => {
+                msg!("Error: purchases of this size must be bought with BuyTokensEscrowed")
+            }
+            TokenMarketError::InvalidDisputeWindow => {
+                msg!("Error: escrowed purchases need a positive dispute window")
+            }
+            TokenMarketError::DisputeWindowOpen => {
+                msg!("Error: the escrowed purchase can still be disputed")
+            }
+            TokenMarketError::DisputeWindowClosed => {
+                msg!("Error: the dispute window of the escrowed purchase has passed")
+            }
+            TokenMarketError::EscrowSettled => {
+                msg!("Error: the escrowed purchase was already released or disputed")
+            }
+            TokenMarketError::InvalidDiscount => {
+                msg!("Error: coupon discounts must be between 1 and 9999 basis points")
+            }
+            TokenMarketError::CouponExpired => msg!("Error: the coupon has expired"),
+            TokenMarketError::CouponExhausted => msg!("Error: the coupon has no uses left"),
+            TokenMarketError::InvalidGiftExpiry => {
+                msg!("Error: gifts must expire in the future")
+            }
+            TokenMarketError::GiftExpired => {
+                msg!("Error: the gift has expired, it can only be refunded")
+            }
+            TokenMarketError::GiftNotExpired => {
+                msg!("Error: the gift can still be claimed")
+            }
+            TokenMarketError::GiftSettled => {
+                msg!("Error: the gift was already claimed or refunded")
+            }
+            TokenMarketError::UnbackedSelling => {
+                msg!("Error: selling can't be combined with features emitting tokens not paid at par into the bank")
+            }
+            TokenMarketError::TransactionNotIsolated => {
+                msg!("Error: quoted purchases can't share a transaction with other market or exchange instructions")
+            }
+            TokenMarketError::MarketPaused => {
+                msg!("Error: the market owner paused trading")
+            }
+            TokenMarketError::InvalidSalePhases => {
+                msg!("Error: sale phases must open in order at nonzero prices and end after the last one opens")
+            }
+            TokenMarketError::SaleNotOpen => {
+                msg!("Error: no sale phase is open")
+            }
+            TokenMarketError::NotAllowlisted => {
+                msg!("Error: the open sale phase only accepts purchases under a voucher or a quote")
+            }
+            TokenMarketError::PhaseLimitExceeded => {
+                msg!("Error: the purchase exceeds the per-purchase limit or the cap of the open sale phase")
+            }
+            TokenMarketError::PhasedSale => {
+                msg!("Error: purchases of a phased sale go through BuyTokens")
+            }
+            TokenMarketError::InvalidProtocolFee => {
+                msg!("Error: the protocol fee must be below 10000 basis points")
+            }
+            TokenMarketError::PurchaseBelowMinimum => {
+                msg!("Error: the purchase pays less than the market minimum")
+            }
+            TokenMarketError::PurchaseAboveMaximum => {
+                msg!("Error: the purchase pays more than the market maximum")
+            }
+            TokenMarketError::InvalidPurchaseLimits => {
+                msg!("Error: the minimum purchase is above the maximum")
+            }
+            TokenMarketError::AllowanceNotSpent => {
+                msg!("Error: the purchase left part of the write-off delegation unspent")
+            }
+            TokenMarketError::WalletCapExceeded => {
+                msg!("Error: the purchase exceeds what the buyer may purchase in total")
+            }
+            TokenMarketError::WalletCapped => {
+                msg!("Error: purchases of a market with wallet caps go through BuyTokens")
+            }
+            TokenMarketError::SaleNotStarted => {
+                msg!("Error: the sale of the market has not started yet")
+            }
+            TokenMarketError::SaleEnded => {
+                msg!("Error: the sale of the market has ended")
+            }
+            TokenMarketError::InvalidSaleWindow => {
+                msg!("Error: the sale must end after it starts")
+            }
+            TokenMarketError::SaleWindowLocked => {
+                msg!("Error: the sale window can't change once the sale started")
+            }
+            TokenMarketError::NotOnAllowlist => {
+                msg!("Error: the buyer has no allowlist entry on the market")
+            }
+            TokenMarketError::AllowlistedMarket => {
+                msg!("Error: purchases of a market with an allowlist go through BuyTokens")
+            }
+            TokenMarketError::InvalidAllowlistProof => {
+                msg!("Error: the proof doesn't lead to the allowlist root of the market")
+            }
+            TokenMarketError::InvalidPricingTiers => {
+                msg!("Error: pricing tiers need prices and increasing bounds, only the last may be unbounded")
+            }
+            TokenMarketError::TiersSoldOut => {
+                msg!("Error: the pricing tiers of the market can't sell that much")
+            }
+            TokenMarketError::TieredMarket => {
+                msg!("Error: purchases of a tiered or bonding curve market go through BuyTokens or BuyTokensExactOut")
+            }
+            TokenMarketError::InvalidBondingCurve => {
+                msg!("Error: bonding curves need a base price, exponential ones a step, and no pricing tiers")
+            }
+            TokenMarketError::NotNativeMint => {
+                msg!("Error: only markets accepting wrapped SOL take native SOL")
+            }
+            TokenMarketError::SlippageExceeded => {
+                msg!("Error: buying the requested amount costs more than the maximum input")
+            }
+            TokenMarketError::RecordStillNeeded => {
+                msg!("Error: records close once the sale ended for good and disputes are over")
+            }
+            TokenMarketError::InvariantViolated => {
+                msg!("Error: an invariant of the program failed, see the log above")
+            }
+            TokenMarketError::InvalidStakeWeight => {
+                msg!("Error: a stake weight needs a mint, a bonus and for governance deposits the governance program")
+            }
+            TokenMarketError::WithdrawRateExceeded => {
+                msg!("Error: the withdraw stream hasn't released that much yet")
+            }
+            TokenMarketError::WithdrawRateLocked => {
+                msg!("Error: a withdraw stream can only be slowed down")
+            }
+            TokenMarketError::SpendCapExceeded => {
+                msg!("Error: the purchase would take the buyer past the spending cap of the market")
+            }
+            TokenMarketError::PurchaseCooldown => {
+                msg!("Error: the buyer purchased too recently, wait for the cooldown of the market")
+            }
+            TokenMarketError::InvalidPurchaseCooldown => {
+                msg!("Error: a purchase cooldown can't be negative")
+            }
+            TokenMarketError::InvalidLoyaltyTiers => {
+                msg!("Error: loyalty tiers need rising thresholds and rising discounts below 10000 bps")
+            }
         }
     }
 }
@@ -0,0 +1,477 @@
+//! Events the program logs as `Event: <name> <fields>`, fields separated by spaces
+
+use crate::state::OverpaymentPolicy;
+use solana_program::{hash::Hash, msg, pubkey::Pubkey};
+use std::{fmt, str::FromStr};
+
+/// Event logged by an instruction
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub enum Event {
+    /// The treasury changed, `Pubkey::default()` when it was cleared
+    TreasuryChanged {
+        market: Pubkey,
+        old: Pubkey,
+        new: Pubkey,
+    },
+    /// A missing bank was replaced by a new one
+    BankRecreated {
+        market: Pubkey,
+        old: Pubkey,
+        new: Pubkey,
+    },
+    /// `buyer` bought `amount` of emitted tokens, `memo_hash` hashes the memo
+    /// of the purchase transaction, `Hash::default()` if none was seen
+    Purchase {
+        market: Pubkey,
+        buyer: Pubkey,
+        amount: u64,
+        memo_hash: Hash,
+    },
+    /// `buyer` was minted `amount` for a payment on another chain attested
+    /// by the Wormhole message `sequence`
+    WormholePurchase {
+        market: Pubkey,
+        buyer: Pubkey,
+        amount: u64,
+        sequence: u64,
+    },
+    /// `buyer` was minted `amount` for a fiat payment the on-ramp provider
+    /// attested under `reference`
+    FiatPurchase {
+        market: Pubkey,
+        buyer: Pubkey,
+        amount: u64,
+        reference: Hash,
+    },
+    /// `buyer` bought `amount` of emitted tokens that wait in `escrow`
+    /// until its dispute window passes
+    PurchaseEscrowed {
+        market: Pubkey,
+        buyer: Pubkey,
+        escrow: Pubkey,
+        amount: u64,
+    },
+    /// The `amount` held in `escrow` went to its recipient
+    EscrowReleased {
+        market: Pubkey,
+        escrow: Pubkey,
+        amount: u64,
+    },
+    /// The purchase held in `escrow` was disputed and `refund` paid back
+    EscrowDisputed {
+        market: Pubkey,
+        escrow: Pubkey,
+        refund: u64,
+    },
+    /// A purchase was delegated `excess` over its cost, handled by `policy`
+    Overpayment {
+        market: Pubkey,
+        policy: OverpaymentPolicy,
+        excess: u64,
+    },
+    /// A purchase minted a receipt NFT
+    ReceiptMinted {
+        market: Pubkey,
+        receipt_mint: Pubkey,
+        buyer: Pubkey,
+    },
+    /// A purchase of `amount` was attributed to `campaign`, logged as text
+    CampaignSale {
+        market: Pubkey,
+        campaign: [u8; 8],
+        amount: u64,
+    },
+    /// A purchase redeemed `coupon`, emitting `bonus` over the undiscounted amount
+    CouponRedeemed {
+        market: Pubkey,
+        coupon: Pubkey,
+        bonus: u64,
+    },
+    /// `buyer` bought `amount` of emitted tokens that wait in `gift` until claimed
+    GiftPurchased {
+        market: Pubkey,
+        buyer: Pubkey,
+        gift: Pubkey,
+        amount: u64,
+    },
+    /// The `amount` held in `gift` was claimed to `recipient`
+    GiftClaimed {
+        market: Pubkey,
+        gift: Pubkey,
+        recipient: Pubkey,
+        amount: u64,
+    },
+    /// The unclaimed `gift` expired and `refund` was paid back
+    GiftRefunded {
+        market: Pubkey,
+        gift: Pubkey,
+        refund: u64,
+    },
+    /// `seller` sold `amount` of emitted tokens back to the bank
+    TokensSold {
+        market: Pubkey,
+        seller: Pubkey,
+        amount: u64,
+    },
+    /// The owner withdrew `amount` of payments from the bank to `destination`
+    BankWithdrawn {
+        market: Pubkey,
+        destination: Pubkey,
+        amount: u64,
+    },
+    /// A purchase of `amount` paying `paid` into the bank was made in the
+    /// sale phase at index `phase`
+    PhaseSale {
+        market: Pubkey,
+        phase: u8,
+        amount: u64,
+        paid: u64,
+    },
+    /// A purchase paid `amount` of protocol fee into `fee_vault`
+    ProtocolFee {
+        market: Pubkey,
+        fee_vault: Pubkey,
+        amount: u64,
+    },
+    /// The owner withdrew `amount` of protocol fees from `fee_vault` to `destination`
+    FeesWithdrawn {
+        market: Pubkey,
+        fee_vault: Pubkey,
+        destination: Pubkey,
+        amount: u64,
+    },
+}
+
+impl Event {
+    /// Write the event to the program log
+    pub fn log(&self) {
+        msg!("{}", self);
+    }
+
+    /// Market the event happened on
+    pub fn market(&self) -> &Pubkey {
+        match self {
+            Event::TreasuryChanged { market, .. }
+            | Event::BankRecreated { market, .. }
+            | Event::Purchase { market, .. }
+            | Event::WormholePurchase { market, .. }
+            | Event::FiatPurchase { market, .. }
+            | Event::PurchaseEscrowed { market, .. }
+            | Event::EscrowReleased { market, .. }
+            | Event::EscrowDisputed { market, .. }
+            | Event::Overpayment { market, .. }
+            | Event::ReceiptMinted { market, .. }
+            | Event::CampaignSale { market, .. }
+            | Event::CouponRedeemed { market, .. }
+            | Event::GiftPurchased { market, .. }
+            | Event::GiftClaimed { market, .. }
+            | Event::GiftRefunded { market, .. }
+            | Event::TokensSold { market, .. }
+            | Event::BankWithdrawn { market, .. }
+            | Event::PhaseSale { market, .. }
+            | Event::ProtocolFee { market, .. }
+            | Event::FeesWithdrawn { market, .. } => market,
+        }
+    }
+
+    /// Parse an event from its log line, the message written by [log](#method.log)
+    /// without the runtime's `Program log: ` prefix. `None` if the line is no event.
+    pub fn parse(line: &str) -> Option<Event> {
+        let mut fields = line.strip_prefix("Event: ")?.split(' ');
+        let mut next = || fields.next();
+        let pubkey = |field: Option<&str>| Pubkey::from_str(field?).ok();
+        let number = |field: Option<&str>| field?.parse::<u64>().ok();
+
+        let event = match next()? {
+            "TreasuryChanged" => Event::TreasuryChanged {
+                market: pubkey(next())?,
+                old: pubkey(next())?,
+                new: pubkey(next())?,
+            },
+            "BankRecreated" => Event::BankRecreated {
+                market: pubkey(next())?,
+                old: pubkey(next())?,
+                new: pubkey(next())?,
+            },
+            "Purchase" => Event::Purchase {
+                market: pubkey(next())?,
+                buyer: pubkey(next())?,
+                amount: number(next())?,
+                memo_hash: Hash::from_str(next()?).ok()?,
+            },
+            "WormholePurchase" => Event::WormholePurchase {
+                market: pubkey(next())?,
+                buyer: pubkey(next())?,
+                amount: number(next())?,
+                sequence: number(next())?,
+            },
+            "FiatPurchase" => Event::FiatPurchase {
+                market: pubkey(next())?,
+                buyer: pubkey(next())?,
+                amount: number(next())?,
+                reference: Hash::from_str(next()?).ok()?,
+            },
+            "PurchaseEscrowed" => Event::PurchaseEscrowed {
+                market: pubkey(next())?,
+                buyer: pubkey(next())?,
+                escrow: pubkey(next())?,
+                amount: number(next())?,
+            },
+            "EscrowReleased" => Event::EscrowReleased {
+                market: pubkey(next())?,
+                escrow: pubkey(next())?,
+                amount: number(next())?,
+            },
+            "EscrowDisputed" => Event::EscrowDisputed {
+                market: pubkey(next())?,
+                escrow: pubkey(next())?,
+                refund: number(next())?,
+            },
+            "Overpayment" => Event::Overpayment {
+                market: pubkey(next())?,
+                policy: match next()? {
+                    "Reject" => OverpaymentPolicy::Reject,
+                    "Donate" => OverpaymentPolicy::Donate,
+                    "Refund" => OverpaymentPolicy::Refund,
+                    _ => return None,
+                },
+                excess: number(next())?,
+            },
+            "ReceiptMinted" => Event::ReceiptMinted {
+                market: pubkey(next())?,
+                receipt_mint: pubkey(next())?,
+                buyer: pubkey(next())?,
+            },
+            "CampaignSale" => {
+                let market = pubkey(next())?;
+                let code = next()?.as_bytes();
+                if code.len() > 8 {
+                    return None;
+                }
+                let mut campaign = [0; 8];
+                campaign[..code.len()].copy_from_slice(code);
+                Event::CampaignSale {
+                    market,
+                    campaign,
+                    amount: number(next())?,
+                }
+            }
+            "CouponRedeemed" => Event::CouponRedeemed {
+                market: pubkey(next())?,
+                coupon: pubkey(next())?,
+                bonus: number(next())?,
+            },
+            "GiftPurchased" => Event::GiftPurchased {
+                market: pubkey(next())?,
+                buyer: pubkey(next())?,
+                gift: pubkey(next())?,
+                amount: number(next())?,
+            },
+            "GiftClaimed" => Event::GiftClaimed {
+                market: pubkey(next())?,
+                gift: pubkey(next())?,
+                recipient: pubkey(next())?,
+                amount: number(next())?,
+            },
+            "GiftRefunded" => Event::GiftRefunded {
+                market: pubkey(next())?,
+                gift: pubkey(next())?,
+                refund: number(next())?,
+            },
+            "TokensSold" => Event::TokensSold {
+                market: pubkey(next())?,
+                seller: pubkey(next())?,
+                amount: number(next())?,
+            },
+            "BankWithdrawn" => Event::BankWithdrawn {
+                market: pubkey(next())?,
+                destination: pubkey(next())?,
+                amount: number(next())?,
+            },
+            "PhaseSale" => Event::PhaseSale {
+                market: pubkey(next())?,
+                phase: next()?.parse().ok()?,
+                amount: number(next())?,
+                paid: number(next())?,
+            },
+            "ProtocolFee" => Event::ProtocolFee {
+                market: pubkey(next())?,
+                fee_vault: pubkey(next())?,
+                amount: number(next())?,
+            },
+            "FeesWithdrawn" => Event::FeesWithdrawn {
+                market: pubkey(next())?,
+                fee_vault: pubkey(next())?,
+                destination: pubkey(next())?,
+                amount: number(next())?,
+            },
+            _ => return None,
+        };
+        match next() {
+            Some(_) => None,
+            None => Some(event),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::TreasuryChanged { market, old, new } => {
+                write!(f, "Event: TreasuryChanged {} {} {}", market, old, new)
+            }
+            Event::BankRecreated { market, old, new } => {
+                write!(f, "Event: BankRecreated {} {} {}", market, old, new)
+            }
+            Event::Purchase {
+                market,
+                buyer,
+                amount,
+                memo_hash,
+            } => write!(
+                f,
+                "Event: Purchase {} {} {} {}",
+                market, buyer, amount, memo_hash
+            ),
+            Event::WormholePurchase {
+                market,
+                buyer,
+                amount,
+                sequence,
+            } => write!(
+                f,
+                "Event: WormholePurchase {} {} {} {}",
+                market, buyer, amount, sequence
+            ),
+            Event::FiatPurchase {
+                market,
+                buyer,
+                amount,
+                reference,
+            } => write!(
+                f,
+                "Event: FiatPurchase {} {} {} {}",
+                market, buyer, amount, reference
+            ),
+            Event::PurchaseEscrowed {
+                market,
+                buyer,
+                escrow,
+                amount,
+            } => write!(
+                f,
+                "Event: PurchaseEscrowed {} {} {} {}",
+                market, buyer, escrow, amount
+            ),
+            Event::EscrowReleased {
+                market,
+                escrow,
+                amount,
+            } => write!(f, "Event: EscrowReleased {} {} {}", market, escrow, amount),
+            Event::EscrowDisputed {
+                market,
+                escrow,
+                refund,
+            } => write!(f, "Event: EscrowDisputed {} {} {}", market, escrow, refund),
+            Event::Overpayment {
+                market,
+                policy,
+                excess,
+            } => write!(f, "Event: Overpayment {} {:?} {}", market, policy, excess),
+            Event::ReceiptMinted {
+                market,
+                receipt_mint,
+                buyer,
+            } => write!(
+                f,
+                "Event: ReceiptMinted {} {} {}",
+                market, receipt_mint, buyer
+            ),
+            Event::CampaignSale {
+                market,
+                campaign,
+                amount,
+            } => write!(
+                f,
+                "Event: CampaignSale {} {} {}",
+                market,
+                String::from_utf8_lossy(campaign).trim_end_matches('\0'),
+                amount
+            ),
+            Event::CouponRedeemed {
+                market,
+                coupon,
+                bonus,
+            } => write!(f, "Event: CouponRedeemed {} {} {}", market, coupon, bonus),
+            Event::GiftPurchased {
+                market,
+                buyer,
+                gift,
+                amount,
+            } => write!(
+                f,
+                "Event: GiftPurchased {} {} {} {}",
+                market, buyer, gift, amount
+            ),
+            Event::GiftClaimed {
+                market,
+                gift,
+                recipient,
+                amount,
+            } => write!(
+                f,
+                "Event: GiftClaimed {} {} {} {}",
+                market, gift, recipient, amount
+            ),
+            Event::GiftRefunded {
+                market,
+                gift,
+                refund,
+            } => write!(f, "Event: GiftRefunded {} {} {}", market, gift, refund),
+            Event::TokensSold {
+                market,
+                seller,
+                amount,
+            } => write!(f, "Event: TokensSold {} {} {}", market, seller, amount),
+            Event::BankWithdrawn {
+                market,
+                destination,
+                amount,
+            } => write!(
+                f,
+                "Event: BankWithdrawn {} {} {}",
+                market, destination, amount
+            ),
+            Event::PhaseSale {
+                market,
+                phase,
+                amount,
+                paid,
+            } => write!(
+                f,
+                "Event: PhaseSale {} {} {} {}",
+                market, phase, amount, paid
+            ),
+            Event::ProtocolFee {
+                market,
+                fee_vault,
+                amount,
+            } => write!(f, "Event: ProtocolFee {} {} {}", market, fee_vault, amount),
+            Event::FeesWithdrawn {
+                market,
+                fee_vault,
+                destination,
+                amount,
+            } => write!(
+                f,
+                "Event: FeesWithdrawn {} {} {} {}",
+                market, fee_vault, destination, amount
+            ),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Filters for finding markets with `getProgramAccounts`
+//!
+//! Every filter compares the bytes of a [TokenMarket](../state/struct.TokenMarket.html)
+//! field at its fixed offset. Combine them with a `dataSize` filter of
+//! [MARKET_DATA_SIZE](constant.MARKET_DATA_SIZE.html) to skip other program accounts.
+//! Markets issuing a given mint are found directly through its mint index instead.
+//! The vestings of a market are found with [vestings_of](fn.vestings_of.html)
+//! and a `dataSize` filter of [VESTING_DATA_SIZE](constant.VESTING_DATA_SIZE.html),
+//! its buyer records and receipts with [records_of](fn.records_of.html).
+
+use crate::{
+    state::{
+        find_mint_index_address, BuyerRecord, MintIndex, PurchaseReceipt, TenantStats, TokenMarket,
+        VestingVault,
+    },
+    validation::AccountSource,
+};
+use borsh::BorshDeserialize;
+use solana_program::pubkey::Pubkey;
+
+/// Size of every market account
+pub const MARKET_DATA_SIZE: u64 = TokenMarket::LEN as u64;
+
+/// Size of every [TenantStats](../state/struct.TenantStats.html) account,
+/// a `dataSize` filter of it alone lists every owner of the deployment
+pub const TENANT_DATA_SIZE: u64 = TenantStats::LEN as u64;
+
+/// Size of every [VestingVault](../state/struct.VestingVault.html) account
+pub const VESTING_DATA_SIZE: u64 = VestingVault::LEN as u64;
+
+/// Size of every [BuyerRecord](../state/struct.BuyerRecord.html) account
+pub const BUYER_RECORD_DATA_SIZE: u64 = BuyerRecord::LEN as u64;
+
+/// Size of every [PurchaseReceipt](../state/struct.PurchaseReceipt.html) account
+pub const RECEIPT_DATA_SIZE: u64 = PurchaseReceipt::LEN as u64;
+
+/// Account data must hold `bytes` at `offset`
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemcmpFilter {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl MemcmpFilter {
+    /// `bytes` in the base58 form RPC memcmp filters expect
+    pub fn encoded_bytes(&self) -> String {
+        solana_sdk::bs58::encode(&self.bytes).into_string()
+    }
+
+    /// Whether the account `data` passes the filter
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.get(self.offset..self.offset + self.bytes.len()) == Some(&self.bytes[..])
+    }
+}
+
+/// Initialized markets, for loads that don't read `is_initialized` itself
+pub fn initialized() -> MemcmpFilter {
+    MemcmpFilter {
+        offset: 0,
+        bytes: vec![1],
+    }
+}
+
+/// Markets owned by `owner`
+pub fn by_owner(owner: &Pubkey) -> MemcmpFilter {
+    MemcmpFilter {
+        offset: TokenMarket::OWNER_OFFSET,
+        bytes: owner.to_bytes().to_vec(),
+    }
+}
+
+/// Markets tagged with `category`
+pub fn by_category(category: [u8; 4]) -> MemcmpFilter {
+    MemcmpFilter {
+        offset: TokenMarket::CATEGORY_OFFSET,
+        bytes: category.to_vec(),
+    }
+}
+
+/// Markets selling for the project `project_id`
+pub fn by_project(project_id: [u8; 32]) -> MemcmpFilter {
+    MemcmpFilter {
+        offset: TokenMarket::PROJECT_ID_OFFSET,
+        bytes: project_id.to_vec(),
+    }
+}
+
+/// Vestings granted in `market`
+pub fn vestings_of(market: &Pubkey) -> MemcmpFilter {
+    MemcmpFilter {
+        offset: VestingVault::MARKET_OFFSET,
+        bytes: market.to_bytes().to_vec(),
+    }
+}
+
+/// Buyer records or purchase receipts of `market`, both start with it. Other
+/// accounts of the same size may match too, check their address.
+pub fn records_of(market: &Pubkey) -> MemcmpFilter {
+    MemcmpFilter {
+        offset: 0,
+        bytes: market.to_bytes().to_vec(),
+    }
+}
+
+/// Market issuing `emitter_mint`, `None` if no market was initialized with it
+pub fn find_market_by_emitter(
+    source: &impl AccountSource,
+    program_id: &Pubkey,
+    emitter_mint: &Pubkey,
+) -> Option<Pubkey> {
+    let (address, _) = find_mint_index_address(program_id, emitter_mint);
+    let fetched = source.fetch(&address)?;
+    if fetched.owner != *program_id {
+        return None;
+    }
+    MintIndex::try_from_slice(&fetched.data)
+        .ok()
+        .map(|index| index.market)
+}
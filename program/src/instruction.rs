@@ -1,38 +1,839 @@
 //! Instruction types
 
+use crate::accounts::*;
+use crate::amounts::{AcceptableAmount, EmittedAmount};
+use crate::state::{
+    find_allowlist_address, find_associated_token_address, find_authority_address,
+    find_bank_address, find_buyer_record_address, find_campaign_address, find_consumed_vaa_address,
+    find_coupon_address, find_delegation_address, find_deprecation_address, find_escrow_address,
+    find_escrow_payment_address, find_escrow_vault_address, find_fee_vault_address,
+    find_fiat_settlement_address, find_gift_address, find_gift_payment_address,
+    find_gift_vault_address, find_listing_address, find_mint_index_address,
+    find_notification_address, find_receipt_address, find_sale_phases_address, find_tenant_address,
+    find_wrap_address, gift_secret_hash, AlertThresholds, DelegatedAction, LoyaltyTier,
+    OverpaymentPolicy, PriceQuote, PurchaseVoucher, SalePhase, StakeWeight,
+};
+use crate::wormhole::PostedVaa;
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
     pubkey::Pubkey,
+    system_program, sysvar,
 };
 
 /// Instruction definition
 #[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
 pub enum TokenMarketInstructions {
     /// Initialize the market
     ///
     /// Accounts expected:
     ///
     /// 0. `[]` Person that own token market.
-    /// 1  `[]` Fee payer
+    /// 1  `[WRITE, SIGNER]` Fee payer, funds the mint index
     /// 2. `[WRITE]` Market itself, it will hold all necessary info for trading.
     /// 3. `[WRITE]` Bank account that collect gotten token
     /// 4. `[]` Mint that emit token
     /// 5. `[]` Mint of that token we accept for trade
-    /// 6. `[]` Token program 
-    Initialize,
-    /// Buy tokens
+    /// 6. `[]` Token program
+    /// 7. `[]` Rent sysvar
+    /// 8. `[WRITE]` Optional mint index of the emitter, see [find_mint_index_address](../state/fn.find_mint_index_address.html)
+    /// 9. `[]` System program, given along with the mint index
+    /// 10. `[WRITE]` Optional usage stats of the owner, see [with_tenant_stats](fn.with_tenant_stats.html)
+    ///
+    /// Trailing fields:
+    ///
+    /// 0. `features: u64` [Features](../state/struct.TokenMarket.html#associatedconstant.ALL_FEATURES)
+    ///    of the market, [the default ones](../state/struct.TokenMarket.html#associatedconstant.DEFAULT_FEATURES)
+    ///    if omitted
+    /// 1. `min_purchase: u64` Fewest acceptable tokens a purchase may pay, 0 if omitted
+    /// 2. `max_purchase: u64` Most acceptable tokens a purchase may pay, no limit if omitted or 0
+    /// 3. `pricing_tiers: Vec<PricingTier>` [Tiers](../state/struct.PricingTier.html)
+    ///    the market prices by, in order, none if omitted
+    /// 4. `bonding_curve: BondingCurve` [Curve](../curve/index.html) the
+    ///    market prices by instead, off if omitted
+    /// 5. `purchase_cooldown_secs: i64` Seconds a buyer waits between its
+    ///    purchases, no wait if omitted or 0
+    Initialize {
+        category: [u8; 4],
+        project_id: [u8; 32],
+    },
+    /// Buy tokens. The market authority must be delegated `amount` on the
+    /// write-off account, see [approve_and_buy_tokens](fn.approve_and_buy_tokens.html).
+    /// A larger delegation is handled by the market
+    /// [overpayment policy](../state/enum.OverpaymentPolicy.html). The whole
+    /// delegation is spent, a purchase leaving any of it fails with `AllowanceNotSpent`.
     ///
     /// 0. `[]` Tokens market
-    /// 1. `[]` Bank
-    /// 2. `[]` Tokens recipient
-    /// 3. `[]` Write-off account 
-    /// 4. `[]` The token program
+    /// 1. `[WRITE]` Bank
+    /// 2. `[WRITE]` Tokens recipient
+    /// 3. `[WRITE]` Write-off account
+    /// 4. `[WRITE]` Mint that emit token
+    /// 5. `[]` Market authority
+    /// 6. `[]` The token program
+    ///
+    /// Markets that mint receipts expect the
+    /// [PurchaseReceiptAccounts](../accounts/struct.PurchaseReceiptAccounts.html)
+    /// after the accounts of every purchase instruction, see [with_receipt](fn.with_receipt.html).
+    /// A writable [Coupon](../state/struct.Coupon.html) of the market among
+    /// the trailing accounts is redeemed, see [with_coupon](fn.with_coupon.html).
+    /// Markets with wallet caps, a purchase cooldown or loyalty tiers expect the
+    /// [BuyerRecordAccounts](../accounts/struct.BuyerRecordAccounts.html)
+    /// among the trailing accounts, see [with_buyer_record](fn.with_buyer_record.html),
+    /// and on markets with a [StakeWeight](../state/struct.StakeWeight.html)
+    /// the stake of the buyer may follow, see [with_stake](fn.with_stake.html).
+    /// On markets with the allowlist enabled the buyer shows its
+    /// [AllowlistEntry](../state/struct.AllowlistEntry.html) among the
+    /// trailing accounts, see [with_allowlist_entry](fn.with_allowlist_entry.html),
+    /// or a Merkle proof in the trailing fields, see [with_allowlist_proof](fn.with_allowlist_proof.html).
+    /// Markets with [pricing tiers](../state/struct.PricingTier.html) or a
+    /// [bonding curve](../curve/index.html) expect the market writable, see
+    /// [with_tiered_pricing](fn.with_tiered_pricing.html).
     BuyTokens { amount: u64 },
+    /// Lock emitted tokens for a beneficiary under a cliff + linear schedule
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Vesting account, it will hold the schedule
+    /// 3. `[WRITE]` Vault token account that receives the minted tokens
+    /// 4. `[WRITE]` Mint that emit token
+    /// 5. `[]` Beneficiary
+    /// 6. `[]` Market authority
+    /// 7. `[]` Rent sysvar
+    /// 8. `[]` The token program
+    ///
+    /// A delegate may sign in place of the owner, see [with_delegation](fn.with_delegation.html).
+    CreateVestingVault {
+        total: u64,
+        start: i64,
+        cliff: i64,
+        duration: i64,
+        revocable: bool,
+    },
+    /// Transfer the vested and not yet claimed tokens to the beneficiary
+    ///
+    /// 0. `[SIGNER]` Beneficiary
+    /// 1. `[WRITE]` Vesting account
+    /// 2. `[WRITE]` Vault token account
+    /// 3. `[WRITE]` Destination token account
+    /// 4. `[]` Market authority
+    /// 5. `[]` The token program
+    ClaimVested,
+    /// Stop a revocable vesting and return the unvested tokens
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Vesting account
+    /// 3. `[WRITE]` Vault token account
+    /// 4. `[WRITE]` Token account that receives the unvested tokens
+    /// 5. `[]` Market authority
+    /// 6. `[]` The token program
+    RevokeVesting,
+    /// Replace the acceptable mint with a successor. New purchases go to the
+    /// new bank while the current bank is kept as the legacy one.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    /// 2. `[WRITE]` New bank account
+    /// 3. `[]` New mint of that token we accept for trade
+    /// 4. `[]` Mint that emit token
+    /// 5. `[]` Rent sysvar
+    /// 6. `[]` The token program
+    MigrateAcceptableMint,
+    /// Open a swap route from the emitter token of a deprecated market to
+    /// the emitter token of its replacement at `numerator / denominator`
+    ///
+    /// 0. `[SIGNER]` Owner of the deprecated market
+    /// 1. `[SIGNER]` Owner of the new market
+    /// 2. `[]` Deprecated tokens market
+    /// 3. `[]` New tokens market
+    /// 4. `[WRITE]` Migration account, it will hold the route
+    CreateHoldingsMigration { numerator: u64, denominator: u64 },
+    /// Burn deprecated emitter tokens and receive the new ones
+    ///
+    /// 0. `[SIGNER]` Holder of the deprecated tokens
+    /// 1. `[]` Migration account
+    /// 2. `[WRITE]` Holder token account of the deprecated emitter
+    /// 3. `[WRITE]` Deprecated emitter mint
+    /// 4. `[WRITE]` New emitter mint
+    /// 5. `[WRITE]` Token account that receives the new tokens
+    /// 6. `[]` Market authority
+    /// 7. `[]` The token program
+    MigrateHoldings { amount: u64 },
+    /// Buy tokens on behalf of a buyer without SOL, paying `tip` acceptable
+    /// tokens to the relayer that submits the transaction. The market
+    /// authority must be delegated exactly `amount + tip`.
+    ///
+    /// 0-6. Same as [BuyTokens](#variant.BuyTokens)
+    /// 7. `[WRITE]` Relayer token account of the acceptable mint that receives the tip
+    BuyTokensWithTip { amount: u64, tip: u64 },
+    /// Set the Ethereum address that signs purchase vouchers, zero disables vouchers
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetVoucherSigner { eth_address: [u8; 20] },
+    /// Buy tokens under a [PurchaseVoucher](../state/struct.PurchaseVoucher.html).
+    /// An earlier instruction of the transaction must be a secp256k1 program
+    /// instruction with the voucher signer's signature of the borsh encoded
+    /// voucher, keeping all its data inline.
+    ///
+    /// 0-6. Same as [BuyTokens](#variant.BuyTokens)
+    /// 7. `[]` Instructions sysvar
+    BuyTokensWithVoucher {
+        amount: u64,
+        max_amount: u64,
+        expiry: i64,
+    },
+    /// Set the treasury that receives withdrawn payments.
+    /// Logs `Event: TreasuryChanged <market> <old> <new>`.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    /// 2. `[]` Treasury, token account of the acceptable mint
+    SetTreasury,
+    /// Publish listing metadata of the market, creating its
+    /// [ListingInfo](../state/struct.ListingInfo.html) on first use
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE, SIGNER]` Fee payer, funds the listing info
+    /// 2. `[]` Tokens market
+    /// 3. `[WRITE]` Listing info, see [find_listing_address](../state/fn.find_listing_address.html)
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    ///
+    /// A delegate may sign in place of the owner, see [with_delegation](fn.with_delegation.html).
+    SetListingInfo {
+        website: String,
+        logo_uri_hash: [u8; 32],
+        contact_hash: [u8; 32],
+    },
+    /// Mark the market deprecated and publish a
+    /// [DeprecationNotice](../state/struct.DeprecationNotice.html).
+    /// Purchases still settle on-chain, clients refuse them unless told otherwise.
+    /// Calling it again updates the successor and the notice.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE, SIGNER]` Fee payer, funds the notice
+    /// 2. `[WRITE]` Tokens market
+    /// 3. `[WRITE]` Deprecation notice, see [find_deprecation_address](../state/fn.find_deprecation_address.html)
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    Deprecate {
+        successor: Option<Pubkey>,
+        message_uri: String,
+    },
+    /// Choose what purchases do with a delegation larger than their cost
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    ///
+    /// A delegate may sign in place of the owner, see [with_delegation](fn.with_delegation.html).
+    SetOverpaymentPolicy { policy: OverpaymentPolicy },
+    /// Enable or disable minting a receipt NFT on every purchase
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    ///
+    /// A delegate may sign in place of the owner, see [with_delegation](fn.with_delegation.html).
+    SetReceiptMinting { enabled: bool },
+    /// Buy tokens attributing the sale to the non-zero `campaign` code, counted in its
+    /// [CampaignStats](../state/struct.CampaignStats.html) and any receipt
+    ///
+    /// 0-6. Same as [BuyTokens](#variant.BuyTokens)
+    /// 7. `[WRITE, SIGNER]` Fee payer, funds the campaign stats of the first sale
+    /// 8. `[WRITE]` Campaign stats
+    /// 9. `[]` System program
+    /// 10. `[]` Rent sysvar
+    BuyTokensWithCampaign { amount: u64, campaign: [u8; 8] },
+    /// Buy tokens at the price of a [PriceQuote](../state/struct.PriceQuote.html)
+    /// signed by the market voucher signer in an earlier secp256k1
+    /// instruction of the transaction
+    ///
+    /// 0-6. Same as [BuyTokens](#variant.BuyTokens)
+    /// 7. `[]` Instructions sysvar
+    BuyTokensWithQuote {
+        amount: u64,
+        numerator: u64,
+        denominator: u64,
+        max_amount: u64,
+        expiry: i64,
+    },
+    /// Let `delegate` sign owner instructions up to `daily_limits`, indexed by
+    /// [DelegatedAction](../state/enum.DelegatedAction.html). Creates the
+    /// [Delegation](../state/struct.Delegation.html) on first use, zero limits revoke it.
+    /// Usage counted so far today is kept.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE, SIGNER]` Fee payer, funds the delegation record
+    /// 2. `[]` Tokens market
+    /// 3. `[]` Delegate
+    /// 4. `[WRITE]` Delegation record, see [find_delegation_address](../state/fn.find_delegation_address.html)
+    /// 5. `[]` System program
+    /// 6. `[]` Rent sysvar
+    SetDelegation {
+        daily_limits: [u64; DelegatedAction::COUNT],
+    },
+    /// Replace a bank that was closed with a new one owned by the market authority
+    /// at [find_bank_address](../state/fn.find_bank_address.html).
+    /// Fails unless the current bank is missing.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE, SIGNER]` Fee payer, funds the new bank
+    /// 2. `[WRITE]` Tokens market
+    /// 3. `[]` Missing bank the market records
+    /// 4. `[WRITE]` New bank
+    /// 5. `[]` Mint of that token we accept for trade
+    /// 6. `[]` Token program
+    /// 7. `[]` System program
+    /// 8. `[]` Rent sysvar
+    RecreateBank,
+    /// Require every purchase transaction to carry an SPL [memo](../memo/index.html),
+    /// or stop requiring it. Purchases then also take the instructions sysvar,
+    /// see [with_instructions_sysvar](fn.with_instructions_sysvar.html).
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetMemoPolicy { required: bool },
+    /// Make quoted purchases fail with `TransactionNotIsolated` when their
+    /// transaction has other instructions on the market or instructions of a
+    /// known [exchange](../dex/index.html), or stop doing so. Instructions
+    /// invoked by other programs are not seen.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetSandwichGuard { enabled: bool },
+    /// Accept purchases paid on Wormhole chain `chain` and attested by
+    /// messages of `emitter`, a zero `emitter` stops accepting them
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetWormholeEmitter { chain: u16, emitter: [u8; 32] },
+    /// Mint the emitted tokens of a purchase paid on another chain, at one
+    /// emitted token per acceptable token, to a token account of the
+    /// [payload](../wormhole/struct.PurchasePayload.html) recipient.
+    /// The message is recorded in a [ConsumedVaa](../state/struct.ConsumedVaa.html)
+    /// paid by the fee payer and can't be redeemed again. On markets with a
+    /// protocol fee the fee payer pays the fee, see
+    /// [with_external_protocol_fee](fn.with_external_protocol_fee.html).
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[WRITE]` Emitter mint
+    /// 2. `[]` Market authority
+    /// 3. `[WRITE]` Recipient token account of the emitter mint
+    /// 4. `[]` Message posted by the Wormhole core bridge
+    /// 5. `[WRITE]` ConsumedVaa PDA of the message
+    /// 6. `[WRITE, SIGNER]` Fee payer
+    /// 7. `[]` Token program
+    /// 8. `[]` System program
+    /// 9. `[]` Rent sysvar
+    RedeemWormholePurchase,
+    /// Let `signer`, the key of a fiat on-ramp provider, attest card
+    /// purchases, `Pubkey::default()` stops accepting them
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetOnrampSigner { signer: Pubkey },
+    /// Mint `amount` emitted tokens, at one per acceptable token, for a
+    /// purchase the on-ramp provider settled in fiat. The provider's
+    /// signature is the attestation, no payment moves on-chain. The payment
+    /// `reference` is recorded in a [FiatSettlement](../state/struct.FiatSettlement.html)
+    /// paid by the fee payer and can't be minted for again. On markets with a
+    /// protocol fee the provider pays the fee, see
+    /// [with_external_protocol_fee](fn.with_external_protocol_fee.html).
+    ///
+    /// 0. `[SIGNER]` Market on-ramp signer
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Emitter mint
+    /// 3. `[]` Market authority
+    /// 4. `[WRITE]` Recipient token account of the emitter mint
+    /// 5. `[WRITE]` Fiat settlement, see [find_fiat_settlement_address](../state/fn.find_fiat_settlement_address.html)
+    /// 6. `[WRITE, SIGNER]` Fee payer
+    /// 7. `[]` Token program
+    /// 8. `[]` System program
+    /// 9. `[]` Rent sysvar
+    BuyTokensWithFiat { amount: u64, reference: [u8; 32] },
+    /// Hold purchases emitting at least `threshold` tokens in escrow for
+    /// `window` seconds, during which the owner or `guardian` may dispute
+    /// them. A zero `threshold` stops escrowing, `Pubkey::default()` leaves
+    /// disputes to the owner alone.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetDisputeEscrow {
+        threshold: u64,
+        window: i64,
+        guardian: Pubkey,
+    },
+    /// Buy tokens like `BuyTokens` at one emitted token per acceptable
+    /// token, but mint them into a vault of a new
+    /// [PurchaseEscrow](../state/struct.PurchaseEscrow.html) instead of the
+    /// recipient. The payment waits in an escrow payment account until
+    /// release, so the bank can't pay it out while a dispute may refund it.
+    /// Purchases at or above the market escrow threshold must be bought this
+    /// way, `nonce` tells apart escrows of the same write-off account.
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[WRITE]` Bank
+    /// 2. `[]` Recipient token account of the emitter mint
+    /// 3. `[WRITE]` Write-off account, delegated the payment to the market authority
+    /// 4. `[WRITE]` Emitter mint
+    /// 5. `[]` Acceptable mint
+    /// 6. `[]` Market authority
+    /// 7. `[WRITE]` Escrow, see [find_escrow_address](../state/fn.find_escrow_address.html)
+    /// 8. `[WRITE]` Escrow vault, see [find_escrow_vault_address](../state/fn.find_escrow_vault_address.html)
+    /// 9. `[WRITE]` Escrow payment, see [find_escrow_payment_address](../state/fn.find_escrow_payment_address.html)
+    /// 10. `[WRITE, SIGNER]` Fee payer
+    /// 11. `[]` Token program
+    /// 12. `[]` System program
+    /// 13. `[]` Rent sysvar
+    /// 14. `[]` Instructions sysvar
+    BuyTokensEscrowed { amount: u64, nonce: u64 },
+    /// Move the tokens of an escrowed purchase to its recipient and its
+    /// payment to the bank once the dispute window passed, anyone may crank it.
+    /// An escrow holding a protocol fee expects the fee vault among the
+    /// trailing accounts, see [with_protocol_fee](fn.with_protocol_fee.html).
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[WRITE]` Escrow
+    /// 2. `[WRITE]` Escrow vault
+    /// 3. `[WRITE]` Escrow payment
+    /// 4. `[WRITE]` Recipient token account the escrow records
+    /// 5. `[WRITE]` Bank
+    /// 6. `[]` Market authority
+    /// 7. `[]` Token program
+    ReleaseEscrow,
+    /// Burn the tokens of an escrowed purchase and refund its payment, with
+    /// any protocol fee, to the write-off account while the dispute window
+    /// is open
+    ///
+    /// 0. `[SIGNER]` Market owner or dispute guardian
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Escrow
+    /// 3. `[WRITE]` Escrow vault
+    /// 4. `[WRITE]` Escrow payment
+    /// 5. `[WRITE]` Emitter mint
+    /// 6. `[WRITE]` Write-off account the escrow records
+    /// 7. `[]` Market authority
+    /// 8. `[]` Token program
+    DisputeEscrow,
+    /// Issue a [Coupon](../state/struct.Coupon.html) waiving `discount_bps`
+    /// of the price of up to `max_uses` purchases until `expiry`, 0 for
+    /// never. `code_hash` is the [coupon_code_hash](../state/fn.coupon_code_hash.html)
+    /// of the code buyers redeem, see [with_coupon](fn.with_coupon.html).
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Coupon, see [find_coupon_address](../state/fn.find_coupon_address.html)
+    /// 3. `[WRITE, SIGNER]` Fee payer
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    CreateCoupon {
+        code_hash: [u8; 32],
+        discount_bps: u16,
+        max_uses: u32,
+        expiry: i64,
+    },
+    /// Buy tokens like `BuyTokens` at one emitted token per acceptable
+    /// token for someone else: they are minted into the vault of a new
+    /// [Gift](../state/struct.Gift.html) claimable until `expiry` with the
+    /// secret hashing to `secret_hash`, see [gift_secret_hash](../state/fn.gift_secret_hash.html).
+    /// The payment waits in a gift payment account until claimed.
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[WRITE]` Bank
+    /// 2. `[WRITE]` Write-off account, delegated the payment to the market authority
+    /// 3. `[WRITE]` Emitter mint
+    /// 4. `[]` Acceptable mint
+    /// 5. `[]` Market authority
+    /// 6. `[WRITE]` Gift, see [find_gift_address](../state/fn.find_gift_address.html)
+    /// 7. `[WRITE]` Gift vault, see [find_gift_vault_address](../state/fn.find_gift_vault_address.html)
+    /// 8. `[WRITE]` Gift payment, see [find_gift_payment_address](../state/fn.find_gift_payment_address.html)
+    /// 9. `[WRITE, SIGNER]` Fee payer
+    /// 10. `[]` Token program
+    /// 11. `[]` System program
+    /// 12. `[]` Rent sysvar
+    /// 13. `[]` Instructions sysvar
+    BuyGift {
+        amount: u64,
+        secret_hash: [u8; 32],
+        expiry: i64,
+    },
+    /// Move the tokens of a gift to `recipient` and its payment to the bank
+    /// before it expires, anyone presenting its `secret` may. The secret
+    /// shows in the claim transaction, so the first claim to land wins.
+    /// A gift holding a protocol fee expects the fee vault among the
+    /// trailing accounts, see [with_protocol_fee](fn.with_protocol_fee.html).
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[WRITE]` Gift
+    /// 2. `[WRITE]` Gift vault
+    /// 3. `[WRITE]` Gift payment
+    /// 4. `[WRITE]` Recipient token account of the emitter mint
+    /// 5. `[WRITE]` Bank
+    /// 6. `[]` Market authority
+    /// 7. `[]` Token program
+    ClaimGift { secret: [u8; 32] },
+    /// Burn the tokens of an expired unclaimed gift and refund its payment,
+    /// with any protocol fee, to the write-off account, anyone may crank it
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[WRITE]` Gift
+    /// 2. `[WRITE]` Gift vault
+    /// 3. `[WRITE]` Gift payment
+    /// 4. `[WRITE]` Emitter mint
+    /// 5. `[WRITE]` Write-off account the gift records
+    /// 6. `[]` Market authority
+    /// 7. `[]` Token program
+    RefundGift,
+    /// Sell `amount` emitted tokens back at one acceptable token per emitted
+    /// token: the seller's tokens are burned and the bank pays the seller.
+    /// Only markets whose features all emit tokens paid at par into the bank
+    /// buy them back, see [SELLABLE_FEATURES](../state/struct.TokenMarket.html#associatedconstant.SELLABLE_FEATURES).
+    ///
+    /// 0. `[SIGNER]` Seller, owner of the sold tokens
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Bank
+    /// 3. `[WRITE]` Seller token account of the emitter mint
+    /// 4. `[WRITE]` Emitter mint
+    /// 5. `[WRITE]` Token account of the acceptable mint that receives the payment
+    /// 6. `[]` Market authority
+    /// 7. `[]` Token program
+    SellTokens { amount: u64 },
+    /// Move `amount` of collected payments out of the bank. Once the owner
+    /// set a treasury, payments are only withdrawn to it. Payments of held
+    /// escrows and pending gifts only reach the bank once settled. With a
+    /// [withdraw rate](#variant.SetWithdrawRate) only what the stream
+    /// released may be withdrawn.
+    /// Logs `Event: BankWithdrawn <market> <destination> <amount>`.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    /// 2. `[WRITE]` Bank
+    /// 3. `[WRITE]` Token account of the acceptable mint that receives the payments
+    /// 4. `[]` Market authority
+    /// 5. `[]` Token program
+    WithdrawBank { amount: u64 },
+    /// Halt purchases and sales until [Resume](#variant.Resume)
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    Pause,
+    /// Let a [paused](#variant.Pause) market trade again
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    Resume,
+    /// Transfer the vested and not yet claimed tokens of several vestings to
+    /// the associated token accounts of their beneficiaries. Anyone may crank
+    /// it, vestings with nothing to claim are skipped.
+    ///
+    /// 0. `[]` Market authority
+    /// 1. `[]` The token program
+    /// 2. .. For every vesting:
+    ///    `[WRITE]` Vesting account,
+    ///    `[WRITE]` Vault token account,
+    ///    `[WRITE]` Associated emitter token account of the beneficiary
+    CrankVesting,
+    /// Sell in `phases`, each with its own price, caps and allowlist
+    /// requirement, closing the sale at `end` unless it is 0. Calling it again
+    /// replaces the schedule, phases kept at the same position keep their sales.
+    /// Purchases of a phased market then need the phases account, see
+    /// [with_sale_phases](fn.with_sale_phases.html).
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE, SIGNER]` Fee payer, funds the phases account
+    /// 2. `[WRITE]` Tokens market
+    /// 3. `[WRITE]` Sale phases, see [find_sale_phases_address](../state/fn.find_sale_phases_address.html)
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    SetSalePhases { phases: Vec<SalePhase>, end: i64 },
+    /// Open the phase after the one open now, or the first one before it
+    /// starts. Advancing past the last phase closes the sale.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Sale phases
+    AdvanceSalePhase,
+    /// Take `fee_bps` basis points of the payment of every purchase into the
+    /// fee vault of the market, creating the vault on first use. Purchases of
+    /// a market with a fee then need the vault, see
+    /// [with_protocol_fee](fn.with_protocol_fee.html).
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE, SIGNER]` Fee payer, funds the fee vault
+    /// 2. `[WRITE]` Tokens market
+    /// 3. `[WRITE]` Fee vault of the acceptable mint, see [find_fee_vault_address](../state/fn.find_fee_vault_address.html)
+    /// 4. `[]` Mint of that token we accept for trade
+    /// 5. `[]` Token program
+    /// 6. `[]` System program
+    /// 7. `[]` Rent sysvar
+    SetProtocolFee { fee_bps: u16 },
+    /// Move `amount` of collected protocol fees out of a fee vault of the
+    /// market. Once the owner set a treasury, fees in the acceptable mint are
+    /// only withdrawn to it.
+    /// Logs `Event: FeesWithdrawn <market> <destination> <amount>`.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Fee vault
+    /// 3. `[WRITE]` Token account of the acceptable mint that receives the fees
+    /// 4. `[]` Market authority
+    /// 5. `[]` Token program
+    WithdrawFees { amount: u64 },
+    /// Limit the acceptable tokens a single purchase may pay, `max_purchase`
+    /// 0 for no upper limit. Purchases outside the limits fail with
+    /// `PurchaseBelowMinimum` or `PurchaseAboveMaximum`, including Wormhole
+    /// messages, which stay unconsumed until the limits admit them.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetPurchaseLimits {
+        min_purchase: u64,
+        max_purchase: u64,
+    },
+    /// Cap the emitted tokens a single buyer may purchase in total, 0 for no
+    /// cap. Purchases are counted from the first one under a cap, in the
+    /// [BuyerRecord](../state/struct.BuyerRecord.html) of the buyer.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetWalletCap { wallet_cap: u64 },
+    /// Log the version and the feature bits of the program, see
+    /// [Capabilities](../version/struct.Capabilities.html). Takes no accounts,
+    /// clients simulate it to learn what a cluster runs.
+    GetVersion,
+    /// Publish the [NotificationConfig](../state/struct.NotificationConfig.html)
+    /// of the market owner, replacing the previous one
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE, SIGNER]` Fee payer, funds the config
+    /// 2. `[]` Tokens market
+    /// 3. `[WRITE]` Notification config, see [find_notification_address](../state/fn.find_notification_address.html)
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    SetNotificationConfig {
+        webhook_hash: [u8; 32],
+        thresholds: AlertThresholds,
+        sealed: Vec<u8>,
+    },
+    /// Accept purchases from `sale_start` until `sale_end`, unix timestamps,
+    /// either 0 for no bound. Purchases outside the window fail with
+    /// `SaleNotStarted` or `SaleEnded`. The window can change until the
+    /// scheduled start passes.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetSaleWindow { sale_start: i64, sale_end: i64 },
+    /// Restrict purchases to buyers with an
+    /// [AllowlistEntry](../state/struct.AllowlistEntry.html), or lift the
+    /// restriction. Purchases then also take the entry of the buyer, see
+    /// [with_allowlist_entry](fn.with_allowlist_entry.html).
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetAllowlist { enabled: bool },
+    /// Let `buyer` purchase on the market while its allowlist is enabled
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE, SIGNER]` Fee payer, funds the entry
+    /// 2. `[]` Tokens market
+    /// 3. `[WRITE]` Allowlist entry, see [find_allowlist_address](../state/fn.find_allowlist_address.html)
+    /// 4. `[]` System program
+    /// 5. `[]` Rent sysvar
+    AddToAllowlist { buyer: Pubkey },
+    /// Close the allowlist entry of `buyer`, returning its rent
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Allowlist entry
+    /// 3. `[WRITE]` Receiver of the rent
+    RemoveFromAllowlist { buyer: Pubkey },
+    /// Set the root of the [Merkle allowlist](../merkle/index.html) of the
+    /// market, all zeros drop it. It admits buyers while the allowlist is
+    /// [enabled](#variant.SetAllowlist), next to the allowlist entries.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetAllowlistRoot { root: [u8; 32] },
+    /// Buy emitted tokens at par with `lamports` of native SOL on a market
+    /// accepting wrapped SOL. The lamports move from the buyer into a wrapped
+    /// SOL account of the buyer's own, created for the instruction, which
+    /// pays the wSOL bank and is closed again, its rent going back to the
+    /// buyer. The buyer needs no wrapped SOL account.
+    /// Markets with a protocol fee expect the fee vault among the trailing
+    /// accounts, see [with_protocol_fee](fn.with_protocol_fee.html), the
+    /// fee is paid from the wrapped SOL account as well. A memo is looked up
+    /// through the instructions sysvar, see [with_instructions_sysvar](fn.with_instructions_sysvar.html).
+    ///
+    /// 0. `[WRITE, SIGNER]` Buyer, pays the lamports
+    /// 1. `[]` Tokens market
+    /// 2. `[WRITE]` Bank, a wrapped SOL token account
+    /// 3. `[WRITE]` Emitter mint
+    /// 4. `[]` Market authority
+    /// 5. `[WRITE]` Recipient token account of the emitter mint
+    /// 6. `[]` Token program
+    /// 7. `[]` System program
+    /// 8. `[WRITE]` Wrapped SOL account of the buyer, see [find_wrap_address](../state/fn.find_wrap_address.html)
+    /// 9. `[]` Native mint
+    /// 10. `[]` Rent sysvar
+    BuyTokensWithSol { lamports: u64 },
+    /// Buy tokens at par for several recipients at once, `amounts[i]` paying
+    /// for the `i`th recipient. The payment moves to the bank in one transfer
+    /// and every recipient is minted its tokens. The market authority must be
+    /// delegated the sum of the amounts on the write-off account, see
+    /// [bundle_buy_tokens](fn.bundle_buy_tokens.html), the whole delegation is
+    /// spent. Every amount is a purchase of its own to the purchase limits
+    /// and the escrow threshold. Like fiat and gift purchases it isn't taken
+    /// by phased, tiered, wallet capped or allowlisted markets.
+    /// Markets with a protocol fee expect the fee vault among the trailing
+    /// accounts, see [with_protocol_fee](fn.with_protocol_fee.html). A memo
+    /// is looked up through the instructions sysvar, see
+    /// [with_instructions_sysvar](fn.with_instructions_sysvar.html).
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[WRITE]` Bank
+    /// 2. `[WRITE]` Write-off account
+    /// 3. `[WRITE]` Emitter mint
+    /// 4. `[]` Market authority
+    /// 5. `[]` Token program
+    /// 6. ..`6 + amounts.len()` `[WRITE]` Recipient token accounts
+    BuyTokensMulti { amounts: Vec<u64> },
+    /// Buy exactly `out_amount` emitted tokens, paying what they cost at the
+    /// price of the market: at par, or by its tiers or bonding curve from
+    /// `total_sold`. Fails with `SlippageExceeded` if that is more than
+    /// `max_in` acceptable tokens. The market authority must be delegated at
+    /// least the cost on the write-off account, usually `max_in`, see
+    /// [bundle_buy_tokens](fn.bundle_buy_tokens.html). The whole delegation
+    /// is pulled and the part over the cost refunded whatever the
+    /// overpayment policy. The cost is checked against the purchase limits,
+    /// `out_amount` against the escrow threshold. It isn't taken by phased,
+    /// wallet capped or allowlisted markets.
+    /// Markets with a protocol fee expect the fee vault among the trailing
+    /// accounts, see [with_protocol_fee](fn.with_protocol_fee.html). A memo
+    /// is looked up through the instructions sysvar, see
+    /// [with_instructions_sysvar](fn.with_instructions_sysvar.html).
+    ///
+    /// 0. `[WRITE]` Tokens market
+    /// 1. `[WRITE]` Bank
+    /// 2. `[WRITE]` Recipient token account of the emitter mint
+    /// 3. `[WRITE]` Write-off account
+    /// 4. `[WRITE]` Emitter mint
+    /// 5. `[]` Market authority
+    /// 6. `[]` Token program
+    BuyTokensExactOut { out_amount: u64, max_in: u64 },
+    /// Close the [BuyerRecord](../state/struct.BuyerRecord.html) of a buyer
+    /// once the sale of the market is over for good, see
+    /// [TokenMarket::sale_finalized](../state/struct.TokenMarket.html#method.sale_finalized).
+    /// Anyone may close it, the rent goes back to the buyer, whose first
+    /// purchase funds the record unless a relayer paid for it.
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[WRITE]` Buyer record
+    /// 2. `[WRITE]` Buyer, receives the rent
+    CloseBuyerRecord,
+    /// Close the [PurchaseReceipt](../state/struct.PurchaseReceipt.html) of
+    /// a receipt mint once the sale of the market is over for good and its
+    /// dispute window passed as well. Anyone may close it, the rent goes back
+    /// to the buyer. The receipt NFT stays with its holder.
+    ///
+    /// 0. `[]` Tokens market
+    /// 1. `[]` Receipt mint
+    /// 2. `[WRITE]` Purchase receipt
+    /// 3. `[WRITE]` Buyer, receives the rent
+    CloseReceipt,
+    /// Raise the wallet cap of buyers by `stake_weight.bonus_bps` of what
+    /// they stake in the stake pool or the governance realm of
+    /// `stake_weight.mint`, or stop it with a weight that is off. Stakes
+    /// only raise a wallet cap the market has.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetStakeWeight { stake_weight: StakeWeight },
+    /// Stream withdrawals from the bank: WithdrawBank releases at most
+    /// `withdraw_rate` acceptable tokens a day, accrued by the second and up
+    /// to a day's worth. A new stream starts empty. Once set the rate can
+    /// only be lowered, so buyers can count on it.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetWithdrawRate { withdraw_rate: u64 },
+    /// Cap the acceptable tokens a single buyer may pay for purchases in
+    /// total, 0 for no cap, next to the cap on emitted tokens of
+    /// [SetWalletCap](#variant.SetWalletCap). Payments are counted from the
+    /// first purchase under either cap, in the
+    /// [BuyerRecord](../state/struct.BuyerRecord.html) of the buyer.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetWalletSpendCap { wallet_spend_cap: u64 },
+    /// Make a buyer wait `purchase_cooldown_secs` after a purchase before
+    /// its next one, 0 for no wait. Purchases are stamped in the
+    /// [BuyerRecord](../state/struct.BuyerRecord.html) of the buyer, which
+    /// BuyTokens takes as with a wallet cap.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetPurchaseCooldown { purchase_cooldown_secs: i64 },
+    /// Discount the purchases of buyers by the emitted tokens they purchased
+    /// before, as their [BuyerRecord](../state/struct.BuyerRecord.html)
+    /// counts them: the discount of the last of the `loyalty_tiers` whose
+    /// threshold they reached. BuyTokens takes the record as with a wallet
+    /// cap. A coupon and a loyalty discount don't add up, the larger one
+    /// applies. Tiered and bonding curve prices aren't discounted. No tiers
+    /// stop the discounts.
+    ///
+    /// 0. `[SIGNER]` Market owner
+    /// 1. `[WRITE]` Tokens market
+    SetLoyaltyTiers { loyalty_tiers: Vec<LoyaltyTier> },
+}
+
+impl TokenMarketInstructions {
+    /// Decode the instruction at the start of `input`, returning the
+    /// [trailing fields](struct.TrailingFields.html) after it
+    pub fn unpack(mut input: &[u8]) -> Result<(Self, TrailingFields), ProgramError> {
+        let instruction =
+            Self::deserialize(&mut input).map_err(|_| ProgramError::InvalidInstructionData)?;
+        Ok((instruction, TrailingFields(input)))
+    }
+}
+
+/// Optional fields after the borsh encoded instruction.
+///
+/// Payloads are append-only: a later version of an instruction adds a field
+/// by appending it after the fields added before. The program reads the
+/// fields it knows in order and ignores the rest, clients built before a
+/// field existed omit it, so client and program upgrades need not be atomic.
+pub struct TrailingFields<'a>(&'a [u8]);
+
+impl<'a> TrailingFields<'a> {
+    /// Next field, `None` if the client omitted it
+    pub fn read<T: BorshDeserialize>(&mut self) -> Result<Option<T>, ProgramError> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        T::deserialize(&mut self.0)
+            .map(Some)
+            .map_err(|_| ProgramError::InvalidInstructionData)
+    }
+}
+
+/// Append an optional `field` to the payload of `instruction`, after any
+/// appended before, see [TrailingFields](struct.TrailingFields.html)
+pub fn with_trailing_field(
+    mut instruction: Instruction,
+    field: &impl BorshSerialize,
+) -> Result<Instruction, ProgramError> {
+    instruction.data.extend(field.try_to_vec()?);
+    Ok(instruction)
 }
 
 /// Create `Example` instruction
+#[allow(clippy::too_many_arguments)]
 pub fn initialize(
     program_id: &Pubkey,
     owner: &Pubkey,
@@ -42,45 +843,1923 @@ pub fn initialize(
     emitter: &Pubkey,
     acceptable: &Pubkey,
     token_program: &Pubkey,
+    category: [u8; 4],
+    project_id: [u8; 32],
 ) -> Result<Instruction, ProgramError> {
-    let accounts = vec![
-        AccountMeta::new(*owner, false),
-        AccountMeta::new(*fee_payer, false),
-        AccountMeta::new(*market, false),
-        AccountMeta::new(*bank, false),
-        AccountMeta::new(*emitter, false),
-        AccountMeta::new(*acceptable, false),
-        AccountMeta::new(*token_program, false),
-    ];
+    let accounts = InitializeAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        bank: *bank,
+        emitter: *emitter,
+        acceptable: *acceptable,
+        token_program: *token_program,
+        rent: sysvar::rent::id(),
+        mint_index: find_mint_index_address(program_id, emitter).0,
+        system_program: system_program::id(),
+    }
+    .to_metas();
 
     Ok(Instruction::new_with_borsh(
         *program_id,
-        &TokenMarketInstructions::Initialize,
+        &TokenMarketInstructions::Initialize {
+            category,
+            project_id,
+        },
         accounts,
     ))
 }
 
 /// Create `BuyTokens` instruction
+#[allow(clippy::too_many_arguments)]
 pub fn buy_tokens(
     program_id: &Pubkey,
     market: &Pubkey,
     bank: &Pubkey,
+    emitter: &Pubkey,
     recipient: &Pubkey,
     write_off_acc: &Pubkey,
     token_program: &Pubkey,
-    amount: u64,
+    amount: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = BuyTokensAccounts {
+        market: *market,
+        bank: *bank,
+        recipient: *recipient,
+        write_off: *write_off_acc,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        token_program: *token_program,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokens { amount: amount.0 },
+        accounts,
+    ))
+}
+
+/// Surround a `BuyTokens` instruction with the approval it needs and its revocation.
+///
+/// The market authority is delegated exactly `amount` on `write_off_acc`
+/// (including the tip for `BuyTokensWithTip`) and the delegation is revoked
+/// in the same transaction. The purchase already spends all of it, the
+/// revocation keeps bundles of instructions that pay less safe as well.
+pub fn bundle_buy_tokens(
+    program_id: &Pubkey,
+    buy_tokens: Instruction,
+    write_off_acc: &Pubkey,
+    write_off_owner: &Pubkey,
+    amount: AcceptableAmount,
+) -> Result<Vec<Instruction>, ProgramError> {
+    Ok(vec![
+        spl_token::instruction::approve(
+            &spl_token::id(),
+            write_off_acc,
+            &find_authority_address(program_id).0,
+            write_off_owner,
+            &[],
+            amount.0,
+        )?,
+        buy_tokens,
+        spl_token::instruction::revoke(&spl_token::id(), write_off_acc, write_off_owner, &[])?,
+    ])
+}
+
+/// Append the accounts minting a receipt NFT to a purchase instruction.
+///
+/// `receipt_mint` and `receipt_holding` are new accounts that sign the
+/// transaction, the holding is owned by the owner of the tokens recipient.
+pub fn with_receipt(
+    program_id: &Pubkey,
+    mut buy_tokens: Instruction,
+    fee_payer: &Pubkey,
+    receipt_mint: &Pubkey,
+    receipt_holding: &Pubkey,
+) -> Instruction {
+    buy_tokens.accounts.extend(
+        PurchaseReceiptAccounts {
+            fee_payer: *fee_payer,
+            receipt_mint: *receipt_mint,
+            receipt_holding: *receipt_holding,
+            receipt: find_receipt_address(program_id, receipt_mint).0,
+            system_program: system_program::id(),
+            rent: sysvar::rent::id(),
+        }
+        .to_metas(),
+    );
+    buy_tokens
+}
+
+/// Sign the owner instruction `owner_instruction` of `market` as `delegate`,
+/// which must be its owner account, under the delegate's
+/// [Delegation](../state/struct.Delegation.html)
+pub fn with_delegation(
+    program_id: &Pubkey,
+    mut owner_instruction: Instruction,
+    market: &Pubkey,
+    delegate: &Pubkey,
+) -> Instruction {
+    owner_instruction.accounts.extend(
+        DelegateAccounts {
+            delegation: find_delegation_address(program_id, market, delegate).0,
+        }
+        .to_metas(),
+    );
+    owner_instruction
+}
+
+/// Append the owner's [TenantStats](../state/struct.TenantStats.html) to an
+/// `Initialize` instruction, which counts the market in them. The owner must
+/// then sign, so nobody else can run up its usage of a shared deployment.
+pub fn with_tenant_stats(program_id: &Pubkey, mut initialize: Instruction) -> Instruction {
+    let owner = &mut initialize.accounts[InitializeAccounts::OWNER];
+    owner.is_signer = true;
+    let tenant = find_tenant_address(program_id, &owner.pubkey).0;
+    initialize.accounts.push(AccountMeta::new(tenant, false));
+    initialize
+}
+
+/// Append the instructions sysvar to a purchase instruction, unless it has it
+/// already. The program looks for a memo in the transaction through it, which
+/// markets requiring a memo need and others record when given.
+pub fn with_instructions_sysvar(mut buy_tokens: Instruction) -> Instruction {
+    if !buy_tokens
+        .accounts
+        .iter()
+        .any(|meta| meta.pubkey == sysvar::instructions::id())
+    {
+        buy_tokens
+            .accounts
+            .push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    }
+    buy_tokens
+}
+
+/// Append the coupon of `market` with the code hashing to `code_hash` to a
+/// `BuyTokens` instruction, after any receipt accounts
+pub fn with_coupon(
+    program_id: &Pubkey,
+    mut buy_tokens: Instruction,
+    market: &Pubkey,
+    code_hash: &[u8; 32],
+) -> Instruction {
+    buy_tokens.accounts.push(AccountMeta::new(
+        find_coupon_address(program_id, market, code_hash).0,
+        false,
+    ));
+    buy_tokens
+}
+
+/// Append the [SalePhases](../state/struct.SalePhases.html) of `market` to a
+/// purchase instruction, after any receipt accounts. Phased markets refuse
+/// purchases without it.
+pub fn with_sale_phases(
+    program_id: &Pubkey,
+    mut buy_tokens: Instruction,
+    market: &Pubkey,
+) -> Instruction {
+    buy_tokens.accounts.push(AccountMeta::new(
+        find_sale_phases_address(program_id, market).0,
+        false,
+    ));
+    buy_tokens
+}
+
+/// Append the [AllowlistEntry](../state/struct.AllowlistEntry.html) of
+/// `buyer`, the owner of the tokens recipient, to a purchase instruction.
+/// Markets with the allowlist enabled refuse purchases without it.
+pub fn with_allowlist_entry(
+    program_id: &Pubkey,
+    mut buy_tokens: Instruction,
+    market: &Pubkey,
+    buyer: &Pubkey,
+) -> Instruction {
+    buy_tokens.accounts.push(AccountMeta::new_readonly(
+        find_allowlist_address(program_id, market, buyer).0,
+        false,
+    ));
+    buy_tokens
+}
+
+/// Append the Merkle `proof` that the owner of the tokens recipient is on
+/// the [allowlist](../merkle/index.html) of the market to a `BuyTokens`
+/// instruction, as its [trailing field](struct.TrailingFields.html)
+pub fn with_allowlist_proof(
+    buy_tokens: Instruction,
+    proof: &[[u8; 32]],
+) -> Result<Instruction, ProgramError> {
+    with_trailing_field(buy_tokens, &proof.to_vec())
+}
+
+/// Make the market of a purchase instruction writable, as a market with
+/// [pricing tiers](../state/struct.PricingTier.html) or a
+/// [bonding curve](../curve/index.html) counts what it sells
+pub fn with_tiered_pricing(mut buy_tokens: Instruction) -> Instruction {
+    buy_tokens.accounts[0].is_writable = true;
+    buy_tokens
+}
+
+/// Append the [BuyerRecord](../state/struct.BuyerRecord.html) of `buyer`,
+/// the owner of the tokens recipient, to a purchase instruction on a market
+/// tracking its buyers, see [TokenMarket::tracks_buyers](../state/struct.TokenMarket.html#method.tracks_buyers).
+/// `fee_payer` funds the record on the first purchase.
+pub fn with_buyer_record(
+    program_id: &Pubkey,
+    mut buy_tokens: Instruction,
+    market: &Pubkey,
+    buyer: &Pubkey,
+    fee_payer: &Pubkey,
+) -> Instruction {
+    buy_tokens.accounts.extend(
+        BuyerRecordAccounts {
+            record: find_buyer_record_address(program_id, market, buyer).0,
+            fee_payer: *fee_payer,
+            system_program: system_program::id(),
+            rent: sysvar::rent::id(),
+        }
+        .to_metas(),
+    );
+    buy_tokens
+}
+
+/// Append the account holding the stake of the buyer to a `BuyTokens`
+/// instruction on a market with a [StakeWeight](../state/struct.StakeWeight.html):
+/// its token account of the pool mint, or its token owner record in the realm
+pub fn with_stake(mut buy_tokens: Instruction, stake: &Pubkey) -> Instruction {
+    buy_tokens
+        .accounts
+        .push(AccountMeta::new_readonly(*stake, false));
+    buy_tokens
+}
+
+/// Append the fee vault of a market taking a protocol fee to a purchase
+/// instruction, with the [TenantStats](../state/struct.TenantStats.html) of
+/// its `owner` that count the fee when they exist. Markets with a fee refuse
+/// purchases without the vault. Escrowed purchases and gifts pay the fee on
+/// release and claim, which take the vault the same way.
+pub fn with_protocol_fee(
+    program_id: &Pubkey,
+    mut buy_tokens: Instruction,
+    fee_vault: &Pubkey,
+    owner: &Pubkey,
+) -> Instruction {
+    buy_tokens.accounts.extend_from_slice(&[
+        AccountMeta::new(*fee_vault, false),
+        AccountMeta::new(find_tenant_address(program_id, owner).0, false),
+    ]);
+    buy_tokens
+}
+
+/// Same as [with_protocol_fee](fn.with_protocol_fee.html) for purchases paid
+/// off chain, `BuyTokensWithFiat` and `RedeemWormholePurchase`. Their `payer`
+/// pays the fee from its associated token account of the `acceptable` mint.
+pub fn with_external_protocol_fee(
+    program_id: &Pubkey,
+    buy_tokens: Instruction,
+    fee_vault: &Pubkey,
+    owner: &Pubkey,
+    payer: &Pubkey,
+    acceptable: &Pubkey,
+) -> Instruction {
+    let mut buy_tokens = with_protocol_fee(program_id, buy_tokens, fee_vault, owner);
+    buy_tokens.accounts.push(AccountMeta::new(
+        find_associated_token_address(payer, acceptable),
+        false,
+    ));
+    buy_tokens
+}
+
+/// Create `BuyTokensWithTip` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn buy_tokens_with_tip(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    write_off_acc: &Pubkey,
+    tip_account: &Pubkey,
+    amount: AcceptableAmount,
+    tip: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = BuyTokensWithTipAccounts {
+        market: *market,
+        bank: *bank,
+        recipient: *recipient,
+        write_off: *write_off_acc,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+        tip: *tip_account,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensWithTip {
+            amount: amount.0,
+            tip: tip.0,
+        },
+        accounts,
+    ))
+}
+
+/// Create the `approve`, `BuyTokens` and `revoke` instructions of a purchase,
+/// see [bundle_buy_tokens](fn.bundle_buy_tokens.html)
+#[allow(clippy::too_many_arguments)]
+pub fn approve_and_buy_tokens(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    write_off_acc: &Pubkey,
+    write_off_owner: &Pubkey,
+    amount: AcceptableAmount,
+) -> Result<Vec<Instruction>, ProgramError> {
+    let buy_tokens = buy_tokens(
+        program_id,
+        market,
+        bank,
+        emitter,
+        recipient,
+        write_off_acc,
+        &spl_token::id(),
+        amount,
+    )?;
+    bundle_buy_tokens(
+        program_id,
+        buy_tokens,
+        write_off_acc,
+        write_off_owner,
+        amount,
+    )
+}
+
+/// Create `CreateVestingVault` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn create_vesting_vault(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    emitter: &Pubkey,
+    beneficiary: &Pubkey,
+    total: EmittedAmount,
+    start: i64,
+    cliff: i64,
+    duration: i64,
+    revocable: bool,
+) -> Result<Instruction, ProgramError> {
+    let accounts = CreateVestingVaultAccounts {
+        owner: *owner,
+        market: *market,
+        vesting: *vesting,
+        vault: *vault,
+        emitter: *emitter,
+        beneficiary: *beneficiary,
+        authority: find_authority_address(program_id).0,
+        rent: sysvar::rent::id(),
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::CreateVestingVault {
+            total: total.0,
+            start,
+            cliff,
+            duration,
+            revocable,
+        },
+        accounts,
+    ))
+}
+
+/// Create `ClaimVested` instruction
+pub fn claim_vested(
+    program_id: &Pubkey,
+    beneficiary: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    destination: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = ClaimVestedAccounts {
+        beneficiary: *beneficiary,
+        vesting: *vesting,
+        vault: *vault,
+        destination: *destination,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::ClaimVested,
+        accounts,
+    ))
+}
+
+/// Create `RevokeVesting` instruction
+pub fn revoke_vesting(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    vesting: &Pubkey,
+    vault: &Pubkey,
+    destination: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = RevokeVestingAccounts {
+        owner: *owner,
+        market: *market,
+        vesting: *vesting,
+        vault: *vault,
+        destination: *destination,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::RevokeVesting,
+        accounts,
+    ))
+}
+
+/// Create `MigrateAcceptableMint` instruction
+pub fn migrate_acceptable_mint(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    new_bank: &Pubkey,
+    new_acceptable: &Pubkey,
+    emitter: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = MigrateAcceptableMintAccounts {
+        owner: *owner,
+        market: *market,
+        new_bank: *new_bank,
+        new_acceptable: *new_acceptable,
+        emitter: *emitter,
+        rent: sysvar::rent::id(),
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::MigrateAcceptableMint,
+        accounts,
+    ))
+}
+
+/// Create `CreateHoldingsMigration` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn create_holdings_migration(
+    program_id: &Pubkey,
+    from_owner: &Pubkey,
+    to_owner: &Pubkey,
+    from_market: &Pubkey,
+    to_market: &Pubkey,
+    migration: &Pubkey,
+    numerator: u64,
+    denominator: u64,
+) -> Result<Instruction, ProgramError> {
+    let accounts = CreateHoldingsMigrationAccounts {
+        from_owner: *from_owner,
+        to_owner: *to_owner,
+        from_market: *from_market,
+        to_market: *to_market,
+        migration: *migration,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::CreateHoldingsMigration {
+            numerator,
+            denominator,
+        },
+        accounts,
+    ))
+}
+
+/// Create `MigrateHoldings` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn migrate_holdings(
+    program_id: &Pubkey,
+    holder: &Pubkey,
+    migration: &Pubkey,
+    source: &Pubkey,
+    from_emitter: &Pubkey,
+    to_emitter: &Pubkey,
+    destination: &Pubkey,
+    amount: EmittedAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = MigrateHoldingsAccounts {
+        holder: *holder,
+        migration: *migration,
+        source: *source,
+        from_emitter: *from_emitter,
+        to_emitter: *to_emitter,
+        destination: *destination,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::MigrateHoldings { amount: amount.0 },
+        accounts,
+    ))
+}
+
+/// Create `SetVoucherSigner` instruction
+pub fn set_voucher_signer(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    eth_address: [u8; 20],
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetVoucherSignerAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetVoucherSigner { eth_address },
+        accounts,
+    ))
+}
+
+/// Create `BuyTokensWithVoucher` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn buy_tokens_with_voucher(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    write_off_acc: &Pubkey,
+    amount: AcceptableAmount,
+    voucher: &PurchaseVoucher,
+) -> Result<Instruction, ProgramError> {
+    let accounts = BuyTokensWithVoucherAccounts {
+        market: *market,
+        bank: *bank,
+        recipient: *recipient,
+        write_off: *write_off_acc,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+        instructions: sysvar::instructions::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensWithVoucher {
+            amount: amount.0,
+            max_amount: voucher.max_amount,
+            expiry: voucher.expiry,
+        },
+        accounts,
+    ))
+}
+
+/// Create `BuyTokensWithCampaign` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn buy_tokens_with_campaign(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    write_off_acc: &Pubkey,
+    fee_payer: &Pubkey,
+    amount: AcceptableAmount,
+    campaign: [u8; 8],
+) -> Result<Instruction, ProgramError> {
+    let accounts = BuyTokensWithCampaignAccounts {
+        market: *market,
+        bank: *bank,
+        recipient: *recipient,
+        write_off: *write_off_acc,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+        fee_payer: *fee_payer,
+        campaign: find_campaign_address(program_id, market, &campaign).0,
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensWithCampaign {
+            amount: amount.0,
+            campaign,
+        },
+        accounts,
+    ))
+}
+
+/// Create `BuyTokensWithQuote` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn buy_tokens_with_quote(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    write_off_acc: &Pubkey,
+    amount: AcceptableAmount,
+    quote: &PriceQuote,
+) -> Result<Instruction, ProgramError> {
+    let accounts = BuyTokensWithQuoteAccounts {
+        market: *market,
+        bank: *bank,
+        recipient: *recipient,
+        write_off: *write_off_acc,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+        instructions: sysvar::instructions::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensWithQuote {
+            amount: amount.0,
+            numerator: quote.numerator,
+            denominator: quote.denominator,
+            max_amount: quote.max_amount,
+            expiry: quote.expiry,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetTreasury` instruction
+pub fn set_treasury(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    treasury: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetTreasuryAccounts {
+        owner: *owner,
+        market: *market,
+        treasury: *treasury,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetTreasury,
+        accounts,
+    ))
+}
+
+/// Create `SetListingInfo` instruction
+pub fn set_listing_info(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    fee_payer: &Pubkey,
+    market: &Pubkey,
+    website: String,
+    logo_uri_hash: [u8; 32],
+    contact_hash: [u8; 32],
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetListingInfoAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        listing: find_listing_address(program_id, market).0,
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetListingInfo {
+            website,
+            logo_uri_hash,
+            contact_hash,
+        },
+        accounts,
+    ))
+}
+
+/// Create `Deprecate` instruction
+pub fn deprecate(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    fee_payer: &Pubkey,
+    market: &Pubkey,
+    successor: Option<Pubkey>,
+    message_uri: String,
+) -> Result<Instruction, ProgramError> {
+    let accounts = DeprecateAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        notice: find_deprecation_address(program_id, market).0,
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::Deprecate {
+            successor,
+            message_uri,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetOverpaymentPolicy` instruction
+pub fn set_overpayment_policy(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    policy: OverpaymentPolicy,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetOverpaymentPolicyAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetOverpaymentPolicy { policy },
+        accounts,
+    ))
+}
+
+/// Create `SetReceiptMinting` instruction
+pub fn set_receipt_minting(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    enabled: bool,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetReceiptMintingAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetReceiptMinting { enabled },
+        accounts,
+    ))
+}
+
+/// Create `SetDelegation` instruction
+pub fn set_delegation(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    fee_payer: &Pubkey,
+    market: &Pubkey,
+    delegate: &Pubkey,
+    daily_limits: [u64; DelegatedAction::COUNT],
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetDelegationAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        delegate: *delegate,
+        delegation: find_delegation_address(program_id, market, delegate).0,
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetDelegation { daily_limits },
+        accounts,
+    ))
+}
+
+/// Create `RecreateBank` instruction
+pub fn recreate_bank(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    fee_payer: &Pubkey,
+    market: &Pubkey,
+    missing_bank: &Pubkey,
+    acceptable: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = RecreateBankAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        missing_bank: *missing_bank,
+        bank: find_bank_address(program_id, market).0,
+        acceptable: *acceptable,
+        token_program: spl_token::id(),
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::RecreateBank,
+        accounts,
+    ))
+}
+
+/// Create `SetMemoPolicy` instruction
+pub fn set_memo_policy(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    required: bool,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetMemoPolicyAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetMemoPolicy { required },
+        accounts,
+    ))
+}
+
+/// Create `SetSandwichGuard` instruction
+pub fn set_sandwich_guard(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    enabled: bool,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetSandwichGuardAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetSandwichGuard { enabled },
+        accounts,
+    ))
+}
+
+/// Create `SetWormholeEmitter` instruction
+pub fn set_wormhole_emitter(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    chain: u16,
+    emitter: [u8; 32],
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetWormholeEmitterAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetWormholeEmitter { chain, emitter },
+        accounts,
+    ))
+}
+
+/// Create `RedeemWormholePurchase` instruction for the posted message `vaa`
+/// at `posted_vaa`
+pub fn redeem_wormhole_purchase(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    posted_vaa: &Pubkey,
+    vaa: &PostedVaa,
+    fee_payer: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = RedeemWormholePurchaseAccounts {
+        market: *market,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        recipient: *recipient,
+        posted_vaa: *posted_vaa,
+        consumed_vaa: find_consumed_vaa_address(
+            program_id,
+            vaa.emitter_chain,
+            &vaa.emitter_address,
+            vaa.sequence,
+        )
+        .0,
+        fee_payer: *fee_payer,
+        token_program: spl_token::id(),
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::RedeemWormholePurchase,
+        accounts,
+    ))
+}
+
+/// Create `SetOnrampSigner` instruction
+pub fn set_onramp_signer(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    signer: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetOnrampSignerAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetOnrampSigner { signer: *signer },
+        accounts,
+    ))
+}
+
+/// Create `BuyTokensWithFiat` instruction, to be signed by `onramp_signer`
+#[allow(clippy::too_many_arguments)]
+pub fn buy_tokens_with_fiat(
+    program_id: &Pubkey,
+    onramp_signer: &Pubkey,
+    market: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    fee_payer: &Pubkey,
+    amount: AcceptableAmount,
+    reference: [u8; 32],
+) -> Result<Instruction, ProgramError> {
+    let accounts = BuyTokensWithFiatAccounts {
+        onramp_signer: *onramp_signer,
+        market: *market,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        recipient: *recipient,
+        settlement: find_fiat_settlement_address(program_id, market, &reference).0,
+        fee_payer: *fee_payer,
+        token_program: spl_token::id(),
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensWithFiat {
+            amount: amount.0,
+            reference,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetDisputeEscrow` instruction
+pub fn set_dispute_escrow(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    threshold: EmittedAmount,
+    window: i64,
+    guardian: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetDisputeEscrowAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetDisputeEscrow {
+            threshold: threshold.0,
+            window,
+            guardian: *guardian,
+        },
+        accounts,
+    ))
+}
+
+/// Create `BuyTokensEscrowed` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn buy_tokens_escrowed(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    acceptable: &Pubkey,
+    recipient: &Pubkey,
+    write_off: &Pubkey,
+    fee_payer: &Pubkey,
+    amount: AcceptableAmount,
+    nonce: u64,
+) -> Result<Instruction, ProgramError> {
+    let escrow = find_escrow_address(program_id, market, write_off, nonce).0;
+    let accounts = BuyTokensEscrowedAccounts {
+        market: *market,
+        bank: *bank,
+        recipient: *recipient,
+        write_off: *write_off,
+        emitter: *emitter,
+        acceptable: *acceptable,
+        authority: find_authority_address(program_id).0,
+        escrow,
+        vault: find_escrow_vault_address(program_id, &escrow).0,
+        payment: find_escrow_payment_address(program_id, &escrow).0,
+        fee_payer: *fee_payer,
+        token_program: spl_token::id(),
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+        instructions: sysvar::instructions::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensEscrowed {
+            amount: amount.0,
+            nonce,
+        },
+        accounts,
+    ))
+}
+
+/// Create `ReleaseEscrow` instruction
+pub fn release_escrow(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    escrow: &Pubkey,
+    recipient: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = ReleaseEscrowAccounts {
+        market: *market,
+        escrow: *escrow,
+        vault: find_escrow_vault_address(program_id, escrow).0,
+        payment: find_escrow_payment_address(program_id, escrow).0,
+        recipient: *recipient,
+        bank: *bank,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::ReleaseEscrow,
+        accounts,
+    ))
+}
+
+/// Create `DisputeEscrow` instruction, to be signed by the market owner or
+/// dispute guardian `disputer`
+pub fn dispute_escrow(
+    program_id: &Pubkey,
+    disputer: &Pubkey,
+    market: &Pubkey,
+    escrow: &Pubkey,
+    emitter: &Pubkey,
+    write_off: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = DisputeEscrowAccounts {
+        disputer: *disputer,
+        market: *market,
+        escrow: *escrow,
+        vault: find_escrow_vault_address(program_id, escrow).0,
+        payment: find_escrow_payment_address(program_id, escrow).0,
+        emitter: *emitter,
+        write_off: *write_off,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::DisputeEscrow,
+        accounts,
+    ))
+}
+
+/// Create `CreateCoupon` instruction for the coupon with the code hashing to
+/// `code_hash`, see [coupon_code_hash](../state/fn.coupon_code_hash.html)
+#[allow(clippy::too_many_arguments)]
+pub fn create_coupon(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    fee_payer: &Pubkey,
+    code_hash: [u8; 32],
+    discount_bps: u16,
+    max_uses: u32,
+    expiry: i64,
+) -> Result<Instruction, ProgramError> {
+    let accounts = CreateCouponAccounts {
+        owner: *owner,
+        market: *market,
+        coupon: find_coupon_address(program_id, market, &code_hash).0,
+        fee_payer: *fee_payer,
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::CreateCoupon {
+            code_hash,
+            discount_bps,
+            max_uses,
+            expiry,
+        },
+        accounts,
+    ))
+}
+
+/// Create `BuyGift` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn buy_gift(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    acceptable: &Pubkey,
+    write_off: &Pubkey,
+    fee_payer: &Pubkey,
+    amount: AcceptableAmount,
+    secret_hash: [u8; 32],
+    expiry: i64,
+) -> Result<Instruction, ProgramError> {
+    let gift = find_gift_address(program_id, market, &secret_hash).0;
+    let accounts = BuyGiftAccounts {
+        market: *market,
+        bank: *bank,
+        write_off: *write_off,
+        emitter: *emitter,
+        acceptable: *acceptable,
+        authority: find_authority_address(program_id).0,
+        gift,
+        vault: find_gift_vault_address(program_id, &gift).0,
+        payment: find_gift_payment_address(program_id, &gift).0,
+        fee_payer: *fee_payer,
+        token_program: spl_token::id(),
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+        instructions: sysvar::instructions::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyGift {
+            amount: amount.0,
+            secret_hash,
+            expiry,
+        },
+        accounts,
+    ))
+}
+
+/// Create `ClaimGift` instruction
+pub fn claim_gift(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    recipient: &Pubkey,
+    secret: [u8; 32],
+) -> Result<Instruction, ProgramError> {
+    let gift = find_gift_address(program_id, market, &gift_secret_hash(&secret)).0;
+    let accounts = ClaimGiftAccounts {
+        market: *market,
+        gift,
+        vault: find_gift_vault_address(program_id, &gift).0,
+        payment: find_gift_payment_address(program_id, &gift).0,
+        recipient: *recipient,
+        bank: *bank,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::ClaimGift { secret },
+        accounts,
+    ))
+}
+
+/// Create `RefundGift` instruction
+pub fn refund_gift(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    gift: &Pubkey,
+    emitter: &Pubkey,
+    write_off: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = RefundGiftAccounts {
+        market: *market,
+        gift: *gift,
+        vault: find_gift_vault_address(program_id, gift).0,
+        payment: find_gift_payment_address(program_id, gift).0,
+        emitter: *emitter,
+        write_off: *write_off,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::RefundGift,
+        accounts,
+    ))
+}
+
+/// Create `SellTokens` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn sell_tokens(
+    program_id: &Pubkey,
+    seller: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    holding: &Pubkey,
+    emitter: &Pubkey,
+    destination: &Pubkey,
+    amount: EmittedAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SellTokensAccounts {
+        seller: *seller,
+        market: *market,
+        bank: *bank,
+        holding: *holding,
+        emitter: *emitter,
+        destination: *destination,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SellTokens { amount: amount.0 },
+        accounts,
+    ))
+}
+
+/// Create `WithdrawBank` instruction
+pub fn withdraw_bank(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    destination: &Pubkey,
+    amount: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = WithdrawBankAccounts {
+        owner: *owner,
+        market: *market,
+        bank: *bank,
+        destination: *destination,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::WithdrawBank { amount: amount.0 },
+        accounts,
+    ))
+}
+
+/// Create `Pause` instruction
+pub fn pause(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = PauseAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::Pause,
+        accounts,
+    ))
+}
+
+/// Create `Resume` instruction
+pub fn resume(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = ResumeAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::Resume,
+        accounts,
+    ))
+}
+
+/// Create `CrankVesting` instruction claiming every vesting of `claims`
+pub fn crank_vesting(
+    program_id: &Pubkey,
+    claims: &[VestingClaimAccounts],
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = CrankVestingAccounts {
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+    for claim in claims {
+        accounts.extend(claim.to_metas());
+    }
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::CrankVesting,
+        accounts,
+    ))
+}
+
+/// Create `SetSalePhases` instruction
+pub fn set_sale_phases(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    fee_payer: &Pubkey,
+    market: &Pubkey,
+    phases: Vec<SalePhase>,
+    end: i64,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetSalePhasesAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        phases: find_sale_phases_address(program_id, market).0,
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetSalePhases { phases, end },
+        accounts,
+    ))
+}
+
+/// Create `AdvanceSalePhase` instruction
+pub fn advance_sale_phase(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = AdvanceSalePhaseAccounts {
+        owner: *owner,
+        market: *market,
+        phases: find_sale_phases_address(program_id, market).0,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::AdvanceSalePhase,
+        accounts,
+    ))
+}
+
+/// Create `SetProtocolFee` instruction
+pub fn set_protocol_fee(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    fee_payer: &Pubkey,
+    market: &Pubkey,
+    acceptable: &Pubkey,
+    fee_bps: u16,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetProtocolFeeAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        fee_vault: find_fee_vault_address(program_id, market, acceptable).0,
+        acceptable: *acceptable,
+        token_program: spl_token::id(),
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetProtocolFee { fee_bps },
+        accounts,
+    ))
+}
+
+/// Create `WithdrawFees` instruction withdrawing from the fee vault of `mint`
+pub fn withdraw_fees(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    amount: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = WithdrawFeesAccounts {
+        owner: *owner,
+        market: *market,
+        fee_vault: find_fee_vault_address(program_id, market, mint).0,
+        destination: *destination,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::WithdrawFees { amount: amount.0 },
+        accounts,
+    ))
+}
+
+/// Create `SetPurchaseLimits` instruction
+pub fn set_purchase_limits(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    min_purchase: AcceptableAmount,
+    max_purchase: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetPurchaseLimitsAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetPurchaseLimits {
+            min_purchase: min_purchase.0,
+            max_purchase: max_purchase.0,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetWalletCap` instruction
+pub fn set_wallet_cap(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    wallet_cap: EmittedAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetWalletCapAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetWalletCap {
+            wallet_cap: wallet_cap.0,
+        },
+        accounts,
+    ))
+}
+
+/// Create `GetVersion` instruction
+pub fn get_version(program_id: &Pubkey) -> Instruction {
+    Instruction::new_with_borsh(*program_id, &TokenMarketInstructions::GetVersion, vec![])
+}
+
+/// Create `SetNotificationConfig` instruction
+pub fn set_notification_config(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    fee_payer: &Pubkey,
+    market: &Pubkey,
+    webhook_hash: [u8; 32],
+    thresholds: AlertThresholds,
+    sealed: Vec<u8>,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetNotificationConfigAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        config: find_notification_address(program_id, market, owner).0,
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetNotificationConfig {
+            webhook_hash,
+            thresholds,
+            sealed,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetSaleWindow` instruction
+pub fn set_sale_window(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    sale_start: i64,
+    sale_end: i64,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetSaleWindowAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetSaleWindow {
+            sale_start,
+            sale_end,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetAllowlist` instruction
+pub fn set_allowlist(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    enabled: bool,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetAllowlistAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetAllowlist { enabled },
+        accounts,
+    ))
+}
+
+/// Create `AddToAllowlist` instruction
+pub fn add_to_allowlist(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    fee_payer: &Pubkey,
+    market: &Pubkey,
+    buyer: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = AddToAllowlistAccounts {
+        owner: *owner,
+        fee_payer: *fee_payer,
+        market: *market,
+        entry: find_allowlist_address(program_id, market, buyer).0,
+        system_program: system_program::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::AddToAllowlist { buyer: *buyer },
+        accounts,
+    ))
+}
+
+/// Create `RemoveFromAllowlist` instruction
+pub fn remove_from_allowlist(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    buyer: &Pubkey,
+    receiver: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = RemoveFromAllowlistAccounts {
+        owner: *owner,
+        market: *market,
+        entry: find_allowlist_address(program_id, market, buyer).0,
+        receiver: *receiver,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::RemoveFromAllowlist { buyer: *buyer },
+        accounts,
+    ))
+}
+
+/// Create `SetAllowlistRoot` instruction
+pub fn set_allowlist_root(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    root: [u8; 32],
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetAllowlistRootAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetAllowlistRoot { root },
+        accounts,
+    ))
+}
+
+/// Create `BuyTokensWithSol` instruction paying `lamports` from `buyer`
+#[allow(clippy::too_many_arguments)]
+pub fn buy_tokens_with_sol(
+    program_id: &Pubkey,
+    buyer: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    lamports: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = BuyTokensWithSolAccounts {
+        buyer: *buyer,
+        market: *market,
+        bank: *bank,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        recipient: *recipient,
+        token_program: spl_token::id(),
+        system_program: system_program::id(),
+        wrap: find_wrap_address(program_id, buyer).0,
+        native_mint: spl_token::native_mint::id(),
+        rent: sysvar::rent::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensWithSol {
+            lamports: lamports.0,
+        },
+        accounts,
+    ))
+}
+
+/// Create `BuyTokensMulti` instruction buying for every recipient token
+/// account of `purchases` the amount next to it
+pub fn buy_tokens_multi(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    write_off_acc: &Pubkey,
+    purchases: &[(Pubkey, AcceptableAmount)],
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = BuyTokensMultiAccounts {
+        market: *market,
+        bank: *bank,
+        write_off: *write_off_acc,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+    accounts.extend(
+        purchases
+            .iter()
+            .map(|(recipient, _)| AccountMeta::new(*recipient, false)),
+    );
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensMulti {
+            amounts: purchases.iter().map(|(_, amount)| amount.0).collect(),
+        },
+        accounts,
+    ))
+}
+
+/// Create `BuyTokensExactOut` instruction buying exactly `out_amount` for
+/// `recipient`, failing if that costs more than `max_in`
+#[allow(clippy::too_many_arguments)]
+pub fn buy_tokens_exact_out(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    write_off_acc: &Pubkey,
+    out_amount: EmittedAmount,
+    max_in: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = BuyTokensExactOutAccounts {
+        market: *market,
+        bank: *bank,
+        recipient: *recipient,
+        write_off: *write_off_acc,
+        emitter: *emitter,
+        authority: find_authority_address(program_id).0,
+        token_program: spl_token::id(),
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::BuyTokensExactOut {
+            out_amount: out_amount.0,
+            max_in: max_in.0,
+        },
+        accounts,
+    ))
+}
+
+/// Create `CloseBuyerRecord` instruction closing the record of `buyer`
+pub fn close_buyer_record(program_id: &Pubkey, market: &Pubkey, buyer: &Pubkey) -> Instruction {
+    let accounts = CloseBuyerRecordAccounts {
+        market: *market,
+        record: find_buyer_record_address(program_id, market, buyer).0,
+        buyer: *buyer,
+    }
+    .to_metas();
+
+    Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::CloseBuyerRecord,
+        accounts,
+    )
+}
+
+/// Create `CloseReceipt` instruction closing the receipt of `receipt_mint`,
+/// bought by `buyer`
+pub fn close_receipt(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    receipt_mint: &Pubkey,
+    buyer: &Pubkey,
+) -> Instruction {
+    let accounts = CloseReceiptAccounts {
+        market: *market,
+        receipt_mint: *receipt_mint,
+        receipt: find_receipt_address(program_id, receipt_mint).0,
+        buyer: *buyer,
+    }
+    .to_metas();
+
+    Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::CloseReceipt,
+        accounts,
+    )
+}
+
+/// Create `SetStakeWeight` instruction
+pub fn set_stake_weight(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    stake_weight: StakeWeight,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetStakeWeightAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetStakeWeight { stake_weight },
+        accounts,
+    ))
+}
+
+/// Create `SetWithdrawRate` instruction
+pub fn set_withdraw_rate(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    withdraw_rate: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetWithdrawRateAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetWithdrawRate {
+            withdraw_rate: withdraw_rate.0,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetWalletSpendCap` instruction
+pub fn set_wallet_spend_cap(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    wallet_spend_cap: AcceptableAmount,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetWalletSpendCapAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetWalletSpendCap {
+            wallet_spend_cap: wallet_spend_cap.0,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetPurchaseCooldown` instruction
+pub fn set_purchase_cooldown(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    purchase_cooldown_secs: i64,
+) -> Result<Instruction, ProgramError> {
+    let accounts = SetPurchaseCooldownAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
+
+    Ok(Instruction::new_with_borsh(
+        *program_id,
+        &TokenMarketInstructions::SetPurchaseCooldown {
+            purchase_cooldown_secs,
+        },
+        accounts,
+    ))
+}
+
+/// Create `SetLoyaltyTiers` instruction
+pub fn set_loyalty_tiers(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    loyalty_tiers: &[LoyaltyTier],
 ) -> Result<Instruction, ProgramError> {
-    let accounts = vec![
-        AccountMeta::new(*market, false),
-        AccountMeta::new(*bank, false),
-        AccountMeta::new(*recipient, false),
-        AccountMeta::new(*write_off_acc, false),
-        AccountMeta::new(*token_program, false),
-    ];
+    let accounts = SetLoyaltyTiersAccounts {
+        owner: *owner,
+        market: *market,
+    }
+    .to_metas();
 
     Ok(Instruction::new_with_borsh(
         *program_id,
-        &TokenMarketInstructions::BuyTokens { amount },
+        &TokenMarketInstructions::SetLoyaltyTiers {
+            loyalty_tiers: loyalty_tiers.to_vec(),
+        },
         accounts,
     ))
 }
@@ -0,0 +1,73 @@
+//! Invariant checks compiled into audit builds only.
+//!
+//! [invariant!](../macro.invariant.html) states what must hold after the
+//! program did its work, e.g. that the bank grew by exactly what the purchase
+//! math says it keeps. Built with the `debug-assert-onchain` feature a
+//! violated invariant logs its condition and context and fails the
+//! instruction with `InvariantViolated`. Without the feature the checks are
+//! never evaluated and what they read is compiled out, so the production .so
+//! spends no compute units on them.
+//!
+//! ```text
+//! cargo build-audit   # build-bpf --features debug-assert-onchain
+//! cargo test-audit    # test-bpf --features debug-assert-onchain
+//! ```
+//!
+//! Values read only for invariants are bound under
+//! `#[cfg(feature = "debug-assert-onchain")]`, see [Balances](struct.Balances.html).
+
+#[cfg(feature = "debug-assert-onchain")]
+use solana_program::{account_info::AccountInfo, program_error::ProgramError, program_pack::Pack};
+#[cfg(feature = "debug-assert-onchain")]
+use spl_token::state::{Account, Mint};
+
+/// Fail the instruction with `InvariantViolated` unless `condition` holds,
+/// logging the condition and the `format!` style context
+#[cfg(feature = "debug-assert-onchain")]
+#[macro_export]
+macro_rules! invariant {
+    ($condition:expr, $($context:tt)+) => {
+        if !$condition {
+            $crate::solana_program::msg!("Invariant violated: {}", stringify!($condition));
+            $crate::solana_program::msg!($($context)+);
+            return Err($crate::error::TokenMarketError::InvariantViolated.into());
+        }
+    };
+}
+
+/// Fail the instruction with `InvariantViolated` unless `condition` holds,
+/// logging the condition and the `format!` style context. Compiled out
+/// without the `debug-assert-onchain` feature: the condition still type
+/// checks but is never evaluated.
+#[cfg(not(feature = "debug-assert-onchain"))]
+#[macro_export]
+macro_rules! invariant {
+    ($condition:expr, $($context:tt)+) => {
+        let _ = || $condition;
+    };
+}
+
+/// Bank balance and emitted supply of a market, read before and after the
+/// transfers of a purchase to check they moved what its math says
+#[cfg(feature = "debug-assert-onchain")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Balances {
+    pub bank: u64,
+    pub supply: u64,
+}
+
+#[cfg(feature = "debug-assert-onchain")]
+impl Balances {
+    pub fn read(bank_info: &AccountInfo, emitter_info: &AccountInfo) -> Result<Self, ProgramError> {
+        Ok(Self {
+            bank: Account::unpack(&bank_info.data.borrow())?.amount,
+            supply: Mint::unpack(&emitter_info.data.borrow())?.supply,
+        })
+    }
+
+    /// Whether the bank grew by `kept` and the supply by `emitted` since `before`
+    pub fn moved(&self, before: &Balances, kept: u64, emitted: u64) -> bool {
+        self.bank.checked_sub(before.bank) == Some(kept)
+            && self.supply.checked_sub(before.supply) == Some(emitted)
+    }
+}
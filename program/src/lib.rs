@@ -1,7 +1,27 @@
+pub mod accounts;
+pub mod amounts;
+pub mod curve;
+pub mod dex;
 pub mod error;
+pub mod events;
+#[cfg(feature = "client")]
+pub mod filters;
 pub mod instruction;
+pub mod invariants;
+pub mod memo;
+pub mod merkle;
+pub mod pricing;
 pub mod processor;
+#[cfg(feature = "client")]
+pub mod quote;
+#[cfg(feature = "client")]
+pub mod simulator;
+pub mod stake;
 pub mod state;
+#[cfg(feature = "client")]
+pub mod validation;
+pub mod version;
+pub mod wormhole;
 
 /// Current program version
 pub const PROGRAM_VERSION: u8 = 1;
@@ -0,0 +1,27 @@
+//! SPL Memo instructions in purchase transactions
+//!
+//! Markets requiring a memo accept either version of the memo program
+//! anywhere in the purchase transaction.
+
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+
+solana_program::declare_id!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+/// The first version of SPL Memo
+pub mod v1 {
+    solana_program::declare_id!("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo");
+}
+
+/// Whether `program_id` is a version of SPL Memo
+pub fn is_memo_program(program_id: &Pubkey) -> bool {
+    check_id(program_id) || v1::check_id(program_id)
+}
+
+/// Create a memo instruction stating `text`, signed by no one
+pub fn memo(text: &str) -> Instruction {
+    Instruction {
+        program_id: id(),
+        accounts: vec![],
+        data: text.as_bytes().to_vec(),
+    }
+}
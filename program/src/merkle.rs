@@ -0,0 +1,85 @@
+//! Merkle allowlists shared by the program and off-chain tools
+//!
+//! An allowlist too large for one [AllowlistEntry](../state/struct.AllowlistEntry.html)
+//! per buyer is committed to by the root of a Merkle tree over its buyers,
+//! stored as the market's `allowlist_root`. A buyer proves membership with
+//! the hashes of the siblings on the path from its leaf to the root.
+//!
+//! Leaves and inner nodes are hashed with distinct prefixes, so an inner
+//! node can't pass as a leaf, and the two children of a node are hashed in
+//! sorted order, so a proof needs no left or right flags.
+
+use solana_program::{hash::hashv, pubkey::Pubkey};
+
+const LEAF_PREFIX: &[u8] = &[0];
+const NODE_PREFIX: &[u8] = &[1];
+
+/// Leaf of `buyer` in an allowlist tree
+pub fn allowlist_leaf(buyer: &Pubkey) -> [u8; 32] {
+    hashv(&[LEAF_PREFIX, buyer.as_ref()]).to_bytes()
+}
+
+/// Parent of two nodes, whichever order they come in
+pub fn parent(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    hashv(&[NODE_PREFIX, low, high]).to_bytes()
+}
+
+/// Whether `proof` leads from `leaf` to `root`
+pub fn verify(root: &[u8; 32], leaf: [u8; 32], proof: &[[u8; 32]]) -> bool {
+    proof
+        .iter()
+        .fold(leaf, |node, sibling| parent(&node, sibling))
+        == *root
+}
+
+/// Allowlist tree built off-chain to publish its root and hand out proofs
+pub struct MerkleTree {
+    /// Levels from the leaves up to the root
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    /// Tree over `buyers`, in the order given. An odd node out is carried
+    /// up to the next level unchanged.
+    pub fn new(buyers: &[Pubkey]) -> Self {
+        let mut levels = vec![buyers.iter().map(allowlist_leaf).collect::<Vec<_>>()];
+        while levels.last().map_or(false, |level| level.len() > 1) {
+            let level = levels.last().unwrap();
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => parent(a, b),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    /// Root to store on the market, all zeros for an empty allowlist
+    pub fn root(&self) -> [u8; 32] {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Proof of the buyer at `index` among the buyers the tree was built over
+    pub fn proof(&self, mut index: usize) -> Option<Vec<[u8; 32]>> {
+        if index >= self.levels[0].len() {
+            return None;
+        }
+        let mut proof = vec![];
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(index ^ 1) {
+                proof.push(*sibling);
+            }
+            index /= 2;
+        }
+        Some(proof)
+    }
+}
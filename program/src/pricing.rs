@@ -0,0 +1,337 @@
+//! Purchase math shared by the program and off-chain tools
+//!
+//! Markets sell one emitted token per acceptable token, purchases under a
+//! signed [PriceQuote](../state/struct.PriceQuote.html) settle at its [Price](struct.Price.html).
+//! [settle_purchase](fn.settle_purchase.html) decides how a purchase moves
+//! tokens, so simulations run the exact code the program runs.
+//! [explain_purchase](fn.explain_purchase.html) lays out every intermediate
+//! value of that math, for disputes over charged amounts.
+
+use crate::{
+    error::TokenMarketError,
+    state::{LoyaltyTier, OverpaymentPolicy, PricingTier},
+};
+use solana_program::{msg, program_error::ProgramError};
+use std::{convert::TryFrom, fmt};
+
+/// Emitted tokens per acceptable token, `numerator / denominator`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Price {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Price {
+    /// One emitted token per acceptable token
+    pub const PAR: Price = Price {
+        numerator: 1,
+        denominator: 1,
+    };
+
+    /// Emitted tokens bought with `amount` acceptable tokens, rounded down
+    pub fn emitted(&self, amount: u64) -> Option<u64> {
+        if self.denominator == 0 {
+            return None;
+        }
+        u64::try_from(amount as u128 * self.numerator as u128 / self.denominator as u128).ok()
+    }
+
+    /// The price with `discount_bps` basis points of the cost waived, so the
+    /// same payment emits `10000 / (10000 - discount_bps)` times as much
+    pub fn discounted(&self, discount_bps: u16) -> Option<Price> {
+        if discount_bps == 0 || discount_bps >= 10_000 {
+            return None;
+        }
+        Some(Price {
+            numerator: self.numerator.checked_mul(10_000)?,
+            denominator: self.denominator.checked_mul(10_000 - discount_bps as u64)?,
+        })
+    }
+}
+
+/// Token movements of a purchase
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Settlement {
+    /// Emitted tokens minted to the recipient
+    pub emitted: u64,
+    /// Acceptable tokens moved from the write-off account into the bank
+    pub to_bank: u64,
+    /// Acceptable tokens moved from the write-off account to the relayer
+    pub tip: u64,
+    /// Part of the delegation over the price and the tip
+    pub excess: u64,
+    /// Acceptable tokens returned from the bank to the write-off account
+    pub refund: u64,
+}
+
+impl Settlement {
+    /// Acceptable tokens the bank keeps
+    pub fn proceeds(&self) -> u64 {
+        self.to_bank - self.refund
+    }
+
+    /// Acceptable tokens the buyer spends in the end
+    pub fn paid(&self) -> u64 {
+        self.proceeds() + self.tip
+    }
+}
+
+/// Settle buying `amount` with a relayer `tip` out of a `delegated` write-off
+/// delegation, the excess of which is handled by `policy`
+pub fn settle_purchase(
+    policy: OverpaymentPolicy,
+    amount: u64,
+    tip: u64,
+    delegated: u64,
+) -> Result<Settlement, ProgramError> {
+    let total = amount
+        .checked_add(tip)
+        .ok_or(ProgramError::InvalidArgument)?;
+    if delegated < total || delegated > total && policy == OverpaymentPolicy::Reject {
+        return Err(TokenMarketError::DelegationMismatch.into());
+    }
+    let excess = delegated - total;
+
+    Ok(Settlement {
+        emitted: amount,
+        to_bank: amount + excess,
+        tip,
+        excess,
+        refund: if policy == OverpaymentPolicy::Refund {
+            excess
+        } else {
+            0
+        },
+    })
+}
+
+/// [settle_purchase](fn.settle_purchase.html) at `price`, refusing paid
+/// purchases too small to emit anything
+pub fn settle_purchase_at(
+    policy: OverpaymentPolicy,
+    price: Price,
+    amount: u64,
+    tip: u64,
+    delegated: u64,
+) -> Result<Settlement, ProgramError> {
+    let settlement = settle_purchase(policy, amount, tip, delegated)?;
+    let emitted = price
+        .emitted(amount)
+        .filter(|emitted| *emitted > 0 || amount == 0)
+        .ok_or(ProgramError::InvalidArgument)?;
+
+    Ok(Settlement {
+        emitted,
+        ..settlement
+    })
+}
+
+/// Fail unless `tiers` can price purchases: every tier set has a nonzero
+/// price and ends after the one before, only the last set one is unbounded
+pub fn check_pricing_tiers(tiers: &[PricingTier]) -> Result<(), TokenMarketError> {
+    let mut previous_end = 0;
+    for (index, tier) in tiers.iter().enumerate() {
+        let last = index + 1 == tiers.len();
+        if tier.price_numerator == 0
+            || tier.price_denominator == 0
+            || tier.up_to == 0 && !last
+            || tier.up_to != 0 && tier.up_to <= previous_end
+        {
+            return Err(TokenMarketError::InvalidPricingTiers);
+        }
+        previous_end = tier.up_to;
+    }
+    Ok(())
+}
+
+/// Fail unless `tiers` can discount purchases: every tier starts after the
+/// one before and waives more of the cost, less than all of it
+pub fn check_loyalty_tiers(tiers: &[LoyaltyTier]) -> Result<(), TokenMarketError> {
+    let mut previous = LoyaltyTier::default();
+    for tier in tiers {
+        if tier.threshold <= previous.threshold
+            || tier.discount_bps <= previous.discount_bps
+            || tier.discount_bps >= 10_000
+        {
+            return Err(TokenMarketError::InvalidLoyaltyTiers);
+        }
+        previous = *tier;
+    }
+    Ok(())
+}
+
+/// Emitted tokens bought with `amount` acceptable tokens from the set ones
+/// of `tiers` once `sold` emitted tokens are sold. A purchase crossing the
+/// end of a tier pays the rest of that tier, rounded up, at its price and
+/// the remainder at the next ones, the emitted tokens are rounded down.
+/// `None` if the tiers end before `amount` is spent.
+pub fn emitted_across_tiers(tiers: &[PricingTier], sold: u64, amount: u64) -> Option<u64> {
+    let (mut sold, mut left, mut emitted) = (sold, amount as u128, 0u64);
+    for tier in tiers.iter().filter(|tier| tier.is_set()) {
+        if left == 0 {
+            break;
+        }
+        if tier.up_to != 0 && sold >= tier.up_to {
+            continue;
+        }
+        let (numerator, denominator) =
+            (tier.price_numerator as u128, tier.price_denominator as u128);
+        if numerator == 0 {
+            return None;
+        }
+        let bought = left.checked_mul(numerator)? / denominator;
+        let room = match tier.up_to {
+            0 => u128::MAX,
+            up_to => (up_to - sold) as u128,
+        };
+        if bought <= room {
+            emitted = emitted.checked_add(u64::try_from(bought).ok()?)?;
+            left = 0;
+            break;
+        }
+        let cost = (room.checked_mul(denominator)? + numerator - 1) / numerator;
+        left -= cost;
+        emitted = emitted.checked_add(room as u64)?;
+        sold = sold.checked_add(room as u64)?;
+    }
+    match left {
+        0 => Some(emitted),
+        _ => None,
+    }
+}
+
+/// Acceptable tokens buying exactly `emitted` tokens from the set ones of
+/// `tiers` costs once `sold` emitted tokens are sold, the part bought in
+/// every tier rounded up, the inverse of
+/// [emitted_across_tiers](fn.emitted_across_tiers.html). `None` if the
+/// tiers end before `emitted` are bought or the cost overflows.
+pub fn cost_across_tiers(tiers: &[PricingTier], sold: u64, emitted: u64) -> Option<u64> {
+    let (mut sold, mut left, mut cost) = (sold, emitted, 0u128);
+    for tier in tiers.iter().filter(|tier| tier.is_set()) {
+        if left == 0 {
+            break;
+        }
+        if tier.up_to != 0 && sold >= tier.up_to {
+            continue;
+        }
+        let (numerator, denominator) =
+            (tier.price_numerator as u128, tier.price_denominator as u128);
+        if numerator == 0 {
+            return None;
+        }
+        let bought = match tier.up_to {
+            0 => left,
+            up_to => left.min(up_to - sold),
+        };
+        cost = cost.checked_add(((bought as u128) * denominator + numerator - 1) / numerator)?;
+        left -= bought;
+        sold = sold.checked_add(bought)?;
+    }
+    match left {
+        0 => u64::try_from(cost).ok(),
+        _ => None,
+    }
+}
+
+/// Part of the `proceeds` of a purchase taken as a protocol fee of
+/// `fee_bps` basis points, rounded down
+pub fn protocol_fee(proceeds: u64, fee_bps: u16) -> u64 {
+    (proceeds as u128 * fee_bps as u128 / 10_000) as u64
+}
+
+/// Intermediate values of the math of a purchase
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MathBreakdown {
+    pub price: Price,
+    /// Acceptable tokens paid for emitted ones
+    pub amount: u64,
+    pub tip: u64,
+    /// Delegation of the write-off account
+    pub delegated: u64,
+    /// `amount * numerator`, before the division by the denominator
+    pub cost: u128,
+    /// Remainder of that division, in `1 / denominator` emitted tokens the
+    /// buyer loses to rounding down
+    pub remainder: u128,
+    pub settlement: Settlement,
+    pub fee_bps: u16,
+    /// `proceeds * fee_bps`, before the division by 10000
+    pub fee_product: u128,
+    /// Protocol fee, rounded down in favor of the market owner
+    pub fee: u64,
+}
+
+impl MathBreakdown {
+    /// Write the breakdown to the program log, one value per line
+    pub fn log(&self) {
+        for line in self.to_string().lines() {
+            msg!("Math: {}", line);
+        }
+    }
+}
+
+impl fmt::Display for MathBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "price {}/{}",
+            self.price.numerator, self.price.denominator
+        )?;
+        writeln!(
+            f,
+            "amount {} tip {} delegated {}",
+            self.amount, self.tip, self.delegated
+        )?;
+        writeln!(
+            f,
+            "cost {} = {} * {}",
+            self.cost, self.amount, self.price.numerator
+        )?;
+        writeln!(
+            f,
+            "emitted {} = {} / {} rounded down, remainder {}",
+            self.settlement.emitted, self.cost, self.price.denominator, self.remainder
+        )?;
+        writeln!(
+            f,
+            "excess {} refund {} to bank {} proceeds {}",
+            self.settlement.excess,
+            self.settlement.refund,
+            self.settlement.to_bank,
+            self.settlement.proceeds()
+        )?;
+        write!(
+            f,
+            "fee {} = {} / 10000 rounded down, {} bps",
+            self.fee, self.fee_product, self.fee_bps
+        )
+    }
+}
+
+/// [settle_purchase_at](fn.settle_purchase_at.html) with its intermediate
+/// values and the protocol fee of `fee_bps` basis points
+pub fn explain_purchase(
+    policy: OverpaymentPolicy,
+    price: Price,
+    amount: u64,
+    tip: u64,
+    delegated: u64,
+    fee_bps: u16,
+) -> Result<MathBreakdown, ProgramError> {
+    let settlement = settle_purchase_at(policy, price, amount, tip, delegated)?;
+    let cost = amount as u128 * price.numerator as u128;
+    let proceeds = settlement.proceeds();
+
+    Ok(MathBreakdown {
+        price,
+        amount,
+        tip,
+        delegated,
+        cost,
+        remainder: cost % price.denominator as u128,
+        settlement,
+        fee_bps,
+        fee_product: proceeds as u128 * fee_bps as u128,
+        fee: protocol_fee(proceeds, fee_bps),
+    })
+}
@@ -1,17 +1,63 @@
 //! Program state processor
 
+use crate::curve;
+use crate::dex;
+use crate::error::TokenMarketError;
+use crate::events::Event;
 use crate::instruction::TokenMarketInstructions;
-use crate::state::TokenMarket;
+use crate::memo;
+use crate::merkle;
+use crate::pricing::{
+    check_loyalty_tiers, check_pricing_tiers, emitted_across_tiers, settle_purchase_at, Price,
+    Settlement,
+};
+use crate::stake;
+use crate::state::{
+    find_allowlist_address, find_associated_token_address, find_authority_address,
+    find_buyer_record_address, find_coupon_address, find_delegation_address,
+    find_escrow_payment_address, find_fee_vault_address, find_gift_address,
+    find_gift_payment_address, find_receipt_address, find_sale_phases_address, find_tenant_address,
+    gift_secret_hash, AllowlistEntry, BondingCurve, BuyerRecord, CampaignStats, ConsumedVaa,
+    Coupon, DelegatedAction, Delegation, DeprecationNotice, EscrowStatus, FiatSettlement, Gift,
+    GiftStatus, HoldingsMigration, ListingInfo, LoyaltyTier, MintIndex, NotificationConfig,
+    OverpaymentPolicy, PriceQuote, PricingTier, PurchaseEscrow, PurchaseReceipt, PurchaseVoucher,
+    SalePhase, SalePhases, StakeSource, StakeWeight, TenantStats, TokenMarket, VestingVault,
+    ALLOWLIST_SEED, AUTHORITY_SEED, BANK_SEED, BUYER_RECORD_SEED, CAMPAIGN_SEED, CONSUMED_VAA_SEED,
+    COUPON_SEED, DELEGATION_SEED, DEPRECATION_SEED, ESCROW_PAYMENT_SEED, ESCROW_SEED,
+    ESCROW_VAULT_SEED, FEE_VAULT_SEED, FIAT_SETTLEMENT_SEED, GIFT_PAYMENT_SEED, GIFT_SEED,
+    GIFT_VAULT_SEED, LISTING_SEED, MINT_INDEX_SEED, NOTIFY_SEED, RECEIPT_SEED, SALE_PHASES_SEED,
+    TENANT_SEED, WRAP_SEED,
+};
+use crate::version::Capabilities;
+use crate::wormhole::{self, PostedVaa, PurchasePayload};
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
-    account_info::next_account_info, account_info::AccountInfo, entrypoint::ProgramResult, msg,
-    program::invoke, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey,
+    account_info::next_account_info,
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    hash::{hash, Hash},
+    instruction::Instruction,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    secp256k1_program,
+    system_instruction::{self, create_account},
+    sysvar::{instructions, Sysvar},
 };
 use spl_token::{
     self,
-    instruction::{initialize_account, initialize_mint, mint_to, set_authority, transfer},
+    instruction::{
+        burn, close_account, initialize_account2, initialize_mint, mint_to, set_authority,
+        transfer, AuthorityType,
+    },
+    native_mint,
     solana_program::program_pack::IsInitialized,
-    state::Account,
+    state::{Account, Mint},
 };
 
 /// Program state handler.
@@ -19,17 +65,20 @@ pub struct Processor;
 
 impl Processor {
     /// Processes an instruction
-    pub fn process_instruction<'accounts>(
+    pub fn process_instruction(
         program_id: &Pubkey,
-        accounts: &'accounts [AccountInfo<'accounts>],
+        accounts: &[AccountInfo],
         input: &[u8],
     ) -> ProgramResult {
-        let instruction = TokenMarketInstructions::try_from_slice(input)?;
+        let (instruction, mut trailing) = TokenMarketInstructions::unpack(input)?;
         let account_info_iter = &mut accounts.iter();
         match instruction {
-            TokenMarketInstructions::Initialize => {
+            TokenMarketInstructions::Initialize {
+                category,
+                project_id,
+            } => {
                 msg!("Instruction: InitMarket");
-                
+
                 let owner_info = next_account_info(account_info_iter)?;
                 let fee_payer_info = next_account_info(account_info_iter)?;
                 let market_info = next_account_info(account_info_iter)?;
@@ -37,6 +86,22 @@ impl Processor {
                 let emitter_info = next_account_info(account_info_iter)?;
                 let accepted_info = next_account_info(account_info_iter)?;
                 let token_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                // Clients built before the mint index don't pass it
+                let mint_index = match (account_info_iter.next(), account_info_iter.next()) {
+                    (Some(mint_index_info), Some(system_program_info)) => {
+                        Some((mint_index_info, system_program_info))
+                    }
+                    _ => None,
+                };
+                // nor the tenant stats after it
+                let tenant_info = account_info_iter.next();
+                let features = trailing.read()?.unwrap_or(TokenMarket::DEFAULT_FEATURES);
+                let min_purchase = trailing.read()?.unwrap_or(0);
+                let max_purchase = trailing.read()?.unwrap_or(0);
+                let pricing_tiers: Vec<PricingTier> = trailing.read()?.unwrap_or_default();
+                let bonding_curve = trailing.read()?.unwrap_or_default();
+                let purchase_cooldown_secs = trailing.read()?.unwrap_or(0);
                 Self::process_init_market(
                     program_id,
                     owner_info,
@@ -46,6 +111,17 @@ impl Processor {
                     emitter_info,
                     accepted_info,
                     token_program_info,
+                    rent_info,
+                    mint_index,
+                    tenant_info,
+                    category,
+                    project_id,
+                    features,
+                    min_purchase,
+                    max_purchase,
+                    &pricing_tiers,
+                    bonding_curve,
+                    purchase_cooldown_secs,
                 )
             }
             TokenMarketInstructions::BuyTokens { amount } => {
@@ -55,140 +131,5977 @@ impl Processor {
                 let bank_info = next_account_info(account_info_iter)?;
                 let recipient_info = next_account_info(account_info_iter)?;
                 let write_off_acc_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
                 let token_program = next_account_info(account_info_iter)?;
+                let allowlist_proof = trailing.read()?;
                 Self::process_buy_tokens(
                     program_id,
                     token_market_info,
                     bank_info,
                     recipient_info,
                     write_off_acc_info,
+                    emitter_info,
+                    authority_info,
                     token_program,
                     amount,
+                    None,
+                    false,
+                    None,
+                    [0; 8],
+                    Self::find_coupon(program_id, account_info_iter.as_slice()),
+                    Self::find_instructions_sysvar(account_info_iter.as_slice()),
+                    allowlist_proof,
+                    account_info_iter.as_slice(),
                 )
             }
-        }
-    }
+            TokenMarketInstructions::BuyTokensWithTip { amount, tip } => {
+                msg!("Instruction: BuyTokensWithTip");
 
-    /// Process [InitMarket](enum.TokenMarketInstructions.html) instruction
-    pub fn process_init_market(
-        program_id: &Pubkey,
-        owner_info: &AccountInfo,
-        fee_payer_info: &AccountInfo,
-        market_info: &AccountInfo,
-        bank_info: &AccountInfo,
-        emitter_info: &AccountInfo,
-        accepted_mint_info: &AccountInfo,
-        token_program_info: &AccountInfo,
-    ) -> ProgramResult {
-        let token_market = TokenMarket::try_from_slice(&market_info.data.borrow())?;
-        if token_market.is_initialized() {
-            return Err(ProgramError::AccountAlreadyInitialized);
-        }
+                let token_market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let write_off_acc_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program = next_account_info(account_info_iter)?;
+                let tip_info = next_account_info(account_info_iter)?;
+                Self::process_buy_tokens(
+                    program_id,
+                    token_market_info,
+                    bank_info,
+                    recipient_info,
+                    write_off_acc_info,
+                    emitter_info,
+                    authority_info,
+                    token_program,
+                    amount,
+                    None,
+                    false,
+                    Some((tip_info, tip)),
+                    [0; 8],
+                    None,
+                    Self::find_instructions_sysvar(account_info_iter.as_slice()),
+                    None,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::CreateVestingVault {
+                total,
+                start,
+                cliff,
+                duration,
+                revocable,
+            } => {
+                msg!("Instruction: CreateVestingVault");
 
-        let authority = Pubkey::find_program_address(&[b"token-market"], program_id).0;
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let vesting_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let beneficiary_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let delegation_info = account_info_iter.next();
+                Self::process_create_vesting_vault(
+                    program_id,
+                    owner_info,
+                    delegation_info,
+                    market_info,
+                    vesting_info,
+                    vault_info,
+                    emitter_info,
+                    beneficiary_info,
+                    authority_info,
+                    rent_info,
+                    token_program_info,
+                    VestingVault {
+                        is_initialized: true,
+                        market: *market_info.key,
+                        beneficiary: *beneficiary_info.key,
+                        vault: *vault_info.key,
+                        total,
+                        released: 0,
+                        start,
+                        cliff,
+                        duration,
+                        revocable,
+                        revoked: false,
+                    },
+                )
+            }
+            TokenMarketInstructions::ClaimVested => {
+                msg!("Instruction: ClaimVested");
 
-        invoke(
-            &initialize_account(
-                token_program_info.key,
-                &bank_info.key,
-                accepted_mint_info.key,
-                &authority,
-            )?,
-            &[]
-        )?;
+                let beneficiary_info = next_account_info(account_info_iter)?;
+                let vesting_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let destination_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_claim_vested(
+                    program_id,
+                    beneficiary_info,
+                    vesting_info,
+                    vault_info,
+                    destination_info,
+                    authority_info,
+                    token_program_info,
+                )
+            }
+            TokenMarketInstructions::RevokeVesting => {
+                msg!("Instruction: RevokeVesting");
 
-        invoke(
-            &initialize_mint(
-                token_program_info.key,
-                emitter_info.key,
-                &authority,
-                Some(&authority),
-                todo!(),
-            )?,
-            &[]
-        )?;
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let vesting_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let destination_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_revoke_vesting(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    vesting_info,
+                    vault_info,
+                    destination_info,
+                    authority_info,
+                    token_program_info,
+                )
+            }
+            TokenMarketInstructions::MigrateAcceptableMint => {
+                msg!("Instruction: MigrateAcceptableMint");
 
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let new_bank_info = next_account_info(account_info_iter)?;
+                let new_accepted_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_migrate_acceptable_mint(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    new_bank_info,
+                    new_accepted_info,
+                    emitter_info,
+                    rent_info,
+                    token_program_info,
+                )
+            }
+            TokenMarketInstructions::CreateHoldingsMigration {
+                numerator,
+                denominator,
+            } => {
+                msg!("Instruction: CreateHoldingsMigration");
 
-        TokenMarket {
-            is_initialized: true,
-            owner: *owner_info.key,
-            bank: *bank_info.key,
-            emitter_mint: *emitter_info.key,
-            authority: authority,
-            mint_of_acceptable: *accepted_mint_info.key,
-        }
-        .serialize(&mut *market_info.data.borrow_mut())?;
+                let from_owner_info = next_account_info(account_info_iter)?;
+                let to_owner_info = next_account_info(account_info_iter)?;
+                let from_market_info = next_account_info(account_info_iter)?;
+                let to_market_info = next_account_info(account_info_iter)?;
+                let migration_info = next_account_info(account_info_iter)?;
+                Self::process_create_holdings_migration(
+                    program_id,
+                    from_owner_info,
+                    to_owner_info,
+                    from_market_info,
+                    to_market_info,
+                    migration_info,
+                    numerator,
+                    denominator,
+                )
+            }
+            TokenMarketInstructions::MigrateHoldings { amount } => {
+                msg!("Instruction: MigrateHoldings");
 
-        Ok(())
-    }
+                let holder_info = next_account_info(account_info_iter)?;
+                let migration_info = next_account_info(account_info_iter)?;
+                let source_info = next_account_info(account_info_iter)?;
+                let from_emitter_info = next_account_info(account_info_iter)?;
+                let to_emitter_info = next_account_info(account_info_iter)?;
+                let destination_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_migrate_holdings(
+                    program_id,
+                    holder_info,
+                    migration_info,
+                    source_info,
+                    from_emitter_info,
+                    to_emitter_info,
+                    destination_info,
+                    authority_info,
+                    token_program_info,
+                    amount,
+                )
+            }
+            TokenMarketInstructions::SetVoucherSigner { eth_address } => {
+                msg!("Instruction: SetVoucherSigner");
 
-    pub fn process_buy_tokens<'accounts>(
-        program_id: &Pubkey,
-        market_info: &'accounts AccountInfo<'accounts>,
-        bank_info: &'accounts AccountInfo<'accounts>,
-        recipient: &AccountInfo<'accounts>,
-        write_off_acc_info: &AccountInfo<'accounts>,
-        token_program: &'accounts AccountInfo<'accounts>,
-        amount: u64,
-    ) -> ProgramResult {
-        let token_market = TokenMarket::try_from_slice(*market_info.data.borrow())?;
-        if !token_market.is_initialized() {
-            return Err(ProgramError::UninitializedAccount);
-        }
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_voucher_signer(program_id, owner_info, market_info, eth_address)
+            }
+            TokenMarketInstructions::SetTreasury => {
+                msg!("Instruction: SetTreasury");
 
-        let write_off_acc = Account::unpack_from_slice(*write_off_acc_info.data.borrow())?;
-        if !write_off_acc.is_initialized() {
-            return Err(ProgramError::UninitializedAccount);
-        }
-        if write_off_acc.mint != token_market.mint_of_acceptable {
-            return Err(ProgramError::InvalidAccountData);
-        }
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let treasury_info = next_account_info(account_info_iter)?;
+                Self::process_set_treasury(program_id, owner_info, market_info, treasury_info)
+            }
+            TokenMarketInstructions::SetListingInfo {
+                website,
+                logo_uri_hash,
+                contact_hash,
+            } => {
+                msg!("Instruction: SetListingInfo");
 
-        let recipient_acc = Account::unpack_from_slice(*recipient.data.borrow())?;
-        if !recipient_acc.is_initialized() {
-            return Err(ProgramError::UninitializedAccount);
-        }
-        if recipient_acc.mint != token_market.emitter_mint {
-            return Err(ProgramError::InvalidAccountData);
-        }
+                let owner_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let listing_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                let delegation_info = account_info_iter.next();
+                Self::process_set_listing_info(
+                    program_id,
+                    owner_info,
+                    delegation_info,
+                    fee_payer_info,
+                    market_info,
+                    listing_info,
+                    system_program_info,
+                    rent_info,
+                    ListingInfo {
+                        is_initialized: true,
+                        market: *market_info.key,
+                        website,
+                        logo_uri_hash,
+                        contact_hash,
+                    },
+                )
+            }
+            TokenMarketInstructions::Deprecate {
+                successor,
+                message_uri,
+            } => {
+                msg!("Instruction: Deprecate");
 
-        // check that there are enough tokens to exchange the requested number of tokens
-        if write_off_acc.amount < amount {
-            return Err(ProgramError::InsufficientFunds);
-        }
-        
-        invoke(
-            &transfer(
-                &token_program.key,
-                write_off_acc_info.key,
-                &token_market.bank,
-                &token_market.authority,
-                &[&token_market.authority],
+                let owner_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let notice_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_deprecate(
+                    program_id,
+                    owner_info,
+                    fee_payer_info,
+                    market_info,
+                    notice_info,
+                    system_program_info,
+                    rent_info,
+                    successor,
+                    message_uri,
+                )
+            }
+            TokenMarketInstructions::SetOverpaymentPolicy { policy } => {
+                msg!("Instruction: SetOverpaymentPolicy");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let delegation_info = account_info_iter.next();
+                Self::process_set_overpayment_policy(
+                    program_id,
+                    owner_info,
+                    delegation_info,
+                    market_info,
+                    policy,
+                )
+            }
+            TokenMarketInstructions::SetReceiptMinting { enabled } => {
+                msg!("Instruction: SetReceiptMinting");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let delegation_info = account_info_iter.next();
+                Self::process_set_receipt_minting(
+                    program_id,
+                    owner_info,
+                    delegation_info,
+                    market_info,
+                    enabled,
+                )
+            }
+            TokenMarketInstructions::BuyTokensWithCampaign { amount, campaign } => {
+                msg!("Instruction: BuyTokensWithCampaign");
+
+                let token_market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let write_off_acc_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let campaign_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                if campaign == [0; 8] {
+                    return Err(ProgramError::InvalidArgument);
+                }
+                Self::process_buy_tokens(
+                    program_id,
+                    token_market_info,
+                    bank_info,
+                    recipient_info,
+                    write_off_acc_info,
+                    emitter_info,
+                    authority_info,
+                    token_program,
+                    amount,
+                    None,
+                    false,
+                    None,
+                    campaign,
+                    None,
+                    Self::find_instructions_sysvar(account_info_iter.as_slice()),
+                    None,
+                    account_info_iter.as_slice(),
+                )?;
+                Self::record_campaign_sale(
+                    program_id,
+                    token_market_info,
+                    fee_payer_info,
+                    campaign_info,
+                    system_program_info,
+                    rent_info,
+                    campaign,
+                    amount,
+                )
+            }
+            TokenMarketInstructions::BuyTokensWithVoucher {
                 amount,
-            )?,
-            &[
-                token_program.clone(),
-                write_off_acc_info.clone(),
-                bank_info.clone(),
-            ],
-        )?;
+                max_amount,
+                expiry,
+            } => {
+                msg!("Instruction: BuyTokensWithVoucher");
 
-        invoke(
-            &mint_to(
-                &token_program.key,
-                &token_market.emitter_mint,
-                &recipient.key,
-                &token_market.authority,
-                &[&token_market.authority],
+                let token_market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let write_off_acc_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program = next_account_info(account_info_iter)?;
+                let instructions_info = next_account_info(account_info_iter)?;
+                Self::verify_voucher(
+                    token_market_info,
+                    write_off_acc_info,
+                    instructions_info,
+                    amount,
+                    max_amount,
+                    expiry,
+                )?;
+                Self::process_buy_tokens(
+                    program_id,
+                    token_market_info,
+                    bank_info,
+                    recipient_info,
+                    write_off_acc_info,
+                    emitter_info,
+                    authority_info,
+                    token_program,
+                    amount,
+                    None,
+                    true,
+                    None,
+                    [0; 8],
+                    None,
+                    Some(instructions_info),
+                    None,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::BuyTokensWithQuote {
                 amount,
-            )?,
-            &[
-                token_program.clone(),
-                market_info.clone(),
-                recipient.clone(),
-            ],
-        )?;
+                numerator,
+                denominator,
+                max_amount,
+                expiry,
+            } => {
+                msg!("Instruction: BuyTokensWithQuote");
 
-        Ok(())
+                let token_market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let write_off_acc_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program = next_account_info(account_info_iter)?;
+                let instructions_info = next_account_info(account_info_iter)?;
+                let quote = PriceQuote {
+                    market: *token_market_info.key,
+                    numerator,
+                    denominator,
+                    max_amount,
+                    expiry,
+                };
+                Self::verify_quote(token_market_info, instructions_info, amount, &quote)?;
+                Self::verify_isolated(program_id, token_market_info, instructions_info)?;
+                Self::process_buy_tokens(
+                    program_id,
+                    token_market_info,
+                    bank_info,
+                    recipient_info,
+                    write_off_acc_info,
+                    emitter_info,
+                    authority_info,
+                    token_program,
+                    amount,
+                    Some(quote.price()),
+                    true,
+                    None,
+                    [0; 8],
+                    None,
+                    Some(instructions_info),
+                    None,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::SetDelegation { daily_limits } => {
+                msg!("Instruction: SetDelegation");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let delegate_info = next_account_info(account_info_iter)?;
+                let delegation_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_set_delegation(
+                    program_id,
+                    owner_info,
+                    fee_payer_info,
+                    market_info,
+                    delegate_info,
+                    delegation_info,
+                    system_program_info,
+                    rent_info,
+                    daily_limits,
+                )
+            }
+            TokenMarketInstructions::SetMemoPolicy { required } => {
+                msg!("Instruction: SetMemoPolicy");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_memo_policy(program_id, owner_info, market_info, required)
+            }
+            TokenMarketInstructions::SetSandwichGuard { enabled } => {
+                msg!("Instruction: SetSandwichGuard");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_sandwich_guard(program_id, owner_info, market_info, enabled)
+            }
+            TokenMarketInstructions::SetWormholeEmitter { chain, emitter } => {
+                msg!("Instruction: SetWormholeEmitter");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_wormhole_emitter(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    chain,
+                    emitter,
+                )
+            }
+            TokenMarketInstructions::RedeemWormholePurchase => {
+                msg!("Instruction: RedeemWormholePurchase");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let posted_vaa_info = next_account_info(account_info_iter)?;
+                let consumed_vaa_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_redeem_wormhole_purchase(
+                    program_id,
+                    market_info,
+                    emitter_info,
+                    authority_info,
+                    recipient_info,
+                    posted_vaa_info,
+                    consumed_vaa_info,
+                    fee_payer_info,
+                    token_program_info,
+                    system_program_info,
+                    rent_info,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::SetOnrampSigner { signer } => {
+                msg!("Instruction: SetOnrampSigner");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_onramp_signer(program_id, owner_info, market_info, signer)
+            }
+            TokenMarketInstructions::BuyTokensWithFiat { amount, reference } => {
+                msg!("Instruction: BuyTokensWithFiat");
+
+                let onramp_signer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let settlement_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_buy_tokens_with_fiat(
+                    program_id,
+                    onramp_signer_info,
+                    market_info,
+                    emitter_info,
+                    authority_info,
+                    recipient_info,
+                    settlement_info,
+                    fee_payer_info,
+                    token_program_info,
+                    system_program_info,
+                    rent_info,
+                    amount,
+                    reference,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::SetDisputeEscrow {
+                threshold,
+                window,
+                guardian,
+            } => {
+                msg!("Instruction: SetDisputeEscrow");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_dispute_escrow(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    threshold,
+                    window,
+                    guardian,
+                )
+            }
+            TokenMarketInstructions::BuyTokensEscrowed { amount, nonce } => {
+                msg!("Instruction: BuyTokensEscrowed");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let write_off_acc_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let acceptable_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let escrow_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let payment_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                let instructions_info = next_account_info(account_info_iter)?;
+                Self::process_buy_tokens_escrowed(
+                    program_id,
+                    market_info,
+                    bank_info,
+                    recipient_info,
+                    write_off_acc_info,
+                    emitter_info,
+                    acceptable_info,
+                    authority_info,
+                    escrow_info,
+                    vault_info,
+                    payment_info,
+                    fee_payer_info,
+                    token_program_info,
+                    system_program_info,
+                    rent_info,
+                    instructions_info,
+                    amount,
+                    nonce,
+                )
+            }
+            TokenMarketInstructions::ReleaseEscrow => {
+                msg!("Instruction: ReleaseEscrow");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let escrow_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let payment_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_release_escrow(
+                    program_id,
+                    market_info,
+                    escrow_info,
+                    vault_info,
+                    payment_info,
+                    recipient_info,
+                    bank_info,
+                    authority_info,
+                    token_program_info,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::DisputeEscrow => {
+                msg!("Instruction: DisputeEscrow");
+
+                let disputer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let escrow_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let payment_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let write_off_acc_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_dispute_escrow(
+                    program_id,
+                    disputer_info,
+                    market_info,
+                    escrow_info,
+                    vault_info,
+                    payment_info,
+                    emitter_info,
+                    write_off_acc_info,
+                    authority_info,
+                    token_program_info,
+                )
+            }
+            TokenMarketInstructions::RecreateBank => {
+                msg!("Instruction: RecreateBank");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let missing_bank_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let acceptable_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_recreate_bank(
+                    program_id,
+                    owner_info,
+                    fee_payer_info,
+                    market_info,
+                    missing_bank_info,
+                    bank_info,
+                    acceptable_info,
+                    token_program_info,
+                    system_program_info,
+                    rent_info,
+                )
+            }
+            TokenMarketInstructions::CreateCoupon {
+                code_hash,
+                discount_bps,
+                max_uses,
+                expiry,
+            } => {
+                msg!("Instruction: CreateCoupon");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let coupon_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_create_coupon(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    coupon_info,
+                    fee_payer_info,
+                    system_program_info,
+                    rent_info,
+                    code_hash,
+                    discount_bps,
+                    max_uses,
+                    expiry,
+                )
+            }
+            TokenMarketInstructions::BuyGift {
+                amount,
+                secret_hash,
+                expiry,
+            } => {
+                msg!("Instruction: BuyGift");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let write_off_acc_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let acceptable_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let gift_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let payment_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                let instructions_info = next_account_info(account_info_iter)?;
+                Self::process_buy_gift(
+                    program_id,
+                    market_info,
+                    bank_info,
+                    write_off_acc_info,
+                    emitter_info,
+                    acceptable_info,
+                    authority_info,
+                    gift_info,
+                    vault_info,
+                    payment_info,
+                    fee_payer_info,
+                    token_program_info,
+                    system_program_info,
+                    rent_info,
+                    instructions_info,
+                    amount,
+                    secret_hash,
+                    expiry,
+                )
+            }
+            TokenMarketInstructions::ClaimGift { secret } => {
+                msg!("Instruction: ClaimGift");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let gift_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let payment_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_claim_gift(
+                    program_id,
+                    market_info,
+                    gift_info,
+                    vault_info,
+                    payment_info,
+                    recipient_info,
+                    bank_info,
+                    authority_info,
+                    token_program_info,
+                    secret,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::RefundGift => {
+                msg!("Instruction: RefundGift");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let gift_info = next_account_info(account_info_iter)?;
+                let vault_info = next_account_info(account_info_iter)?;
+                let payment_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let write_off_acc_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_refund_gift(
+                    program_id,
+                    market_info,
+                    gift_info,
+                    vault_info,
+                    payment_info,
+                    emitter_info,
+                    write_off_acc_info,
+                    authority_info,
+                    token_program_info,
+                )
+            }
+            TokenMarketInstructions::SellTokens { amount } => {
+                msg!("Instruction: SellTokens");
+
+                let seller_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let holding_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let destination_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_sell_tokens(
+                    program_id,
+                    seller_info,
+                    market_info,
+                    bank_info,
+                    holding_info,
+                    emitter_info,
+                    destination_info,
+                    authority_info,
+                    token_program_info,
+                    amount,
+                )
+            }
+            TokenMarketInstructions::WithdrawBank { amount } => {
+                msg!("Instruction: WithdrawBank");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let destination_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_withdraw_bank(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    bank_info,
+                    destination_info,
+                    authority_info,
+                    token_program_info,
+                    amount,
+                )
+            }
+            TokenMarketInstructions::Pause => {
+                msg!("Instruction: Pause");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_paused(program_id, owner_info, market_info, true)
+            }
+            TokenMarketInstructions::Resume => {
+                msg!("Instruction: Resume");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_paused(program_id, owner_info, market_info, false)
+            }
+            TokenMarketInstructions::CrankVesting => {
+                msg!("Instruction: CrankVesting");
+
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_crank_vesting(
+                    program_id,
+                    authority_info,
+                    token_program_info,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::SetSalePhases { phases, end } => {
+                msg!("Instruction: SetSalePhases");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let phases_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_set_sale_phases(
+                    program_id,
+                    owner_info,
+                    fee_payer_info,
+                    market_info,
+                    phases_info,
+                    system_program_info,
+                    rent_info,
+                    phases,
+                    end,
+                )
+            }
+            TokenMarketInstructions::AdvanceSalePhase => {
+                msg!("Instruction: AdvanceSalePhase");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let phases_info = next_account_info(account_info_iter)?;
+                Self::process_advance_sale_phase(program_id, owner_info, market_info, phases_info)
+            }
+            TokenMarketInstructions::SetProtocolFee { fee_bps } => {
+                msg!("Instruction: SetProtocolFee");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let fee_vault_info = next_account_info(account_info_iter)?;
+                let acceptable_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_set_protocol_fee(
+                    program_id,
+                    owner_info,
+                    fee_payer_info,
+                    market_info,
+                    fee_vault_info,
+                    acceptable_info,
+                    token_program_info,
+                    system_program_info,
+                    rent_info,
+                    fee_bps,
+                )
+            }
+            TokenMarketInstructions::WithdrawFees { amount } => {
+                msg!("Instruction: WithdrawFees");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let fee_vault_info = next_account_info(account_info_iter)?;
+                let destination_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_withdraw_fees(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    fee_vault_info,
+                    destination_info,
+                    authority_info,
+                    token_program_info,
+                    amount,
+                )
+            }
+            TokenMarketInstructions::SetPurchaseLimits {
+                min_purchase,
+                max_purchase,
+            } => {
+                msg!("Instruction: SetPurchaseLimits");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_purchase_limits(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    min_purchase,
+                    max_purchase,
+                )
+            }
+            TokenMarketInstructions::SetWalletCap { wallet_cap } => {
+                msg!("Instruction: SetWalletCap");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_wallet_cap(program_id, owner_info, market_info, wallet_cap)
+            }
+            TokenMarketInstructions::GetVersion => {
+                msg!("Instruction: GetVersion");
+
+                Capabilities::current().log();
+                Ok(())
+            }
+            TokenMarketInstructions::SetNotificationConfig {
+                webhook_hash,
+                thresholds,
+                sealed,
+            } => {
+                msg!("Instruction: SetNotificationConfig");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let config_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_set_notification_config(
+                    program_id,
+                    owner_info,
+                    fee_payer_info,
+                    market_info,
+                    config_info,
+                    system_program_info,
+                    rent_info,
+                    NotificationConfig {
+                        is_initialized: true,
+                        market: *market_info.key,
+                        owner: *owner_info.key,
+                        webhook_hash,
+                        thresholds,
+                        sealed,
+                    },
+                )
+            }
+            TokenMarketInstructions::SetSaleWindow {
+                sale_start,
+                sale_end,
+            } => {
+                msg!("Instruction: SetSaleWindow");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_sale_window(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    sale_start,
+                    sale_end,
+                )
+            }
+            TokenMarketInstructions::SetAllowlist { enabled } => {
+                msg!("Instruction: SetAllowlist");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_allowlist(program_id, owner_info, market_info, enabled)
+            }
+            TokenMarketInstructions::AddToAllowlist { buyer } => {
+                msg!("Instruction: AddToAllowlist");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let fee_payer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let entry_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_add_to_allowlist(
+                    program_id,
+                    owner_info,
+                    fee_payer_info,
+                    market_info,
+                    entry_info,
+                    system_program_info,
+                    rent_info,
+                    buyer,
+                )
+            }
+            TokenMarketInstructions::SetAllowlistRoot { root } => {
+                msg!("Instruction: SetAllowlistRoot");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_allowlist_root(program_id, owner_info, market_info, root)
+            }
+            TokenMarketInstructions::RemoveFromAllowlist { buyer } => {
+                msg!("Instruction: RemoveFromAllowlist");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let entry_info = next_account_info(account_info_iter)?;
+                let receiver_info = next_account_info(account_info_iter)?;
+                Self::process_remove_from_allowlist(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    entry_info,
+                    receiver_info,
+                    &buyer,
+                )
+            }
+            TokenMarketInstructions::BuyTokensWithSol { lamports } => {
+                msg!("Instruction: BuyTokensWithSol");
+
+                let buyer_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let system_program_info = next_account_info(account_info_iter)?;
+                let wrap_info = next_account_info(account_info_iter)?;
+                let native_mint_info = next_account_info(account_info_iter)?;
+                let rent_info = next_account_info(account_info_iter)?;
+                Self::process_buy_tokens_with_sol(
+                    program_id,
+                    buyer_info,
+                    market_info,
+                    bank_info,
+                    emitter_info,
+                    authority_info,
+                    recipient_info,
+                    token_program_info,
+                    system_program_info,
+                    wrap_info,
+                    native_mint_info,
+                    rent_info,
+                    lamports,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::BuyTokensMulti { amounts } => {
+                msg!("Instruction: BuyTokensMulti");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let write_off_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                let remaining_infos = account_info_iter.as_slice();
+                if remaining_infos.len() < amounts.len() {
+                    return Err(ProgramError::NotEnoughAccountKeys);
+                }
+                let (recipient_infos, trailing_infos) = remaining_infos.split_at(amounts.len());
+                Self::process_buy_tokens_multi(
+                    program_id,
+                    market_info,
+                    bank_info,
+                    write_off_info,
+                    emitter_info,
+                    authority_info,
+                    token_program_info,
+                    &amounts,
+                    recipient_infos,
+                    trailing_infos,
+                )
+            }
+            TokenMarketInstructions::BuyTokensExactOut { out_amount, max_in } => {
+                msg!("Instruction: BuyTokensExactOut");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let bank_info = next_account_info(account_info_iter)?;
+                let recipient_info = next_account_info(account_info_iter)?;
+                let write_off_info = next_account_info(account_info_iter)?;
+                let emitter_info = next_account_info(account_info_iter)?;
+                let authority_info = next_account_info(account_info_iter)?;
+                let token_program_info = next_account_info(account_info_iter)?;
+                Self::process_buy_tokens_exact_out(
+                    program_id,
+                    market_info,
+                    bank_info,
+                    recipient_info,
+                    write_off_info,
+                    emitter_info,
+                    authority_info,
+                    token_program_info,
+                    out_amount,
+                    max_in,
+                    account_info_iter.as_slice(),
+                )
+            }
+            TokenMarketInstructions::CloseBuyerRecord => {
+                msg!("Instruction: CloseBuyerRecord");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let record_info = next_account_info(account_info_iter)?;
+                let buyer_info = next_account_info(account_info_iter)?;
+                Self::process_close_buyer_record(program_id, market_info, record_info, buyer_info)
+            }
+            TokenMarketInstructions::CloseReceipt => {
+                msg!("Instruction: CloseReceipt");
+
+                let market_info = next_account_info(account_info_iter)?;
+                let receipt_mint_info = next_account_info(account_info_iter)?;
+                let receipt_info = next_account_info(account_info_iter)?;
+                let buyer_info = next_account_info(account_info_iter)?;
+                Self::process_close_receipt(
+                    program_id,
+                    market_info,
+                    receipt_mint_info,
+                    receipt_info,
+                    buyer_info,
+                )
+            }
+            TokenMarketInstructions::SetStakeWeight { stake_weight } => {
+                msg!("Instruction: SetStakeWeight");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_stake_weight(program_id, owner_info, market_info, stake_weight)
+            }
+            TokenMarketInstructions::SetWithdrawRate { withdraw_rate } => {
+                msg!("Instruction: SetWithdrawRate");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_withdraw_rate(program_id, owner_info, market_info, withdraw_rate)
+            }
+            TokenMarketInstructions::SetWalletSpendCap { wallet_spend_cap } => {
+                msg!("Instruction: SetWalletSpendCap");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_wallet_spend_cap(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    wallet_spend_cap,
+                )
+            }
+            TokenMarketInstructions::SetPurchaseCooldown {
+                purchase_cooldown_secs,
+            } => {
+                msg!("Instruction: SetPurchaseCooldown");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_purchase_cooldown(
+                    program_id,
+                    owner_info,
+                    market_info,
+                    purchase_cooldown_secs,
+                )
+            }
+            TokenMarketInstructions::SetLoyaltyTiers { loyalty_tiers } => {
+                msg!("Instruction: SetLoyaltyTiers");
+
+                let owner_info = next_account_info(account_info_iter)?;
+                let market_info = next_account_info(account_info_iter)?;
+                Self::process_set_loyalty_tiers(program_id, owner_info, market_info, &loyalty_tiers)
+            }
+        }
+    }
+
+    /// Invoke `instruction` signed by the market authority PDA. The authority
+    /// is shared by all markets and holds their mints and banks, so it only
+    /// ever signs for the token program.
+    fn invoke_as_authority(
+        program_id: &Pubkey,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+    ) -> ProgramResult {
+        if instruction.program_id != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let (_, bump) = find_authority_address(program_id);
+        invoke_signed(instruction, account_infos, &[&[AUTHORITY_SEED, &[bump]]])
+    }
+
+    /// Load an initialized market of the program
+    fn load_market(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+    ) -> Result<TokenMarket, ProgramError> {
+        // a forged market could name any mint or bank of the shared authority
+        if market_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_market = TokenMarket::try_from_slice(&market_info.data.borrow())?;
+        if !token_market.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Ok(token_market)
+    }
+
+    /// Load an initialized market of the program and check that `owner_info`
+    /// is its signing owner
+    fn load_owned_market(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        owner_info: &AccountInfo,
+    ) -> Result<TokenMarket, ProgramError> {
+        let token_market = Self::load_market(program_id, market_info)?;
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if token_market.owner != *owner_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(token_market)
+    }
+
+    /// Load an initialized market for an owner instruction signed by
+    /// `signer_info`, either the owner or a delegate whose `delegation_info`
+    /// record still allows `units` of `action` today
+    fn load_market_as(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        signer_info: &AccountInfo,
+        delegation_info: Option<&AccountInfo>,
+        action: DelegatedAction,
+        units: u64,
+    ) -> Result<TokenMarket, ProgramError> {
+        let token_market = Self::load_market(program_id, market_info)?;
+        if !signer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if token_market.owner == *signer_info.key {
+            return Ok(token_market);
+        }
+
+        let delegation_info = delegation_info.ok_or(ProgramError::InvalidAccountData)?;
+        if delegation_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *delegation_info.key
+            != find_delegation_address(program_id, market_info.key, signer_info.key).0
+        {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let mut delegation = Delegation::try_from_slice(&delegation_info.data.borrow())?;
+        if !delegation.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        delegation.spend(action, units, Clock::get()?.unix_timestamp)?;
+        delegation.serialize(&mut &mut delegation_info.data.borrow_mut()[..])?;
+        Ok(token_market)
+    }
+
+    /// Process [InitMarket](enum.TokenMarketInstructions.html) instruction,
+    /// writing the mint index if its account and the system program are given,
+    /// and counting the market in the owner's tenant stats if given after them.
+    /// `pricing_tiers` need [FEATURE_TIERED_PRICING](../state/struct.TokenMarket.html#associatedconstant.FEATURE_TIERED_PRICING)
+    /// unless empty, a `bonding_curve` that is set needs
+    /// [FEATURE_BONDING_CURVE](../state/struct.TokenMarket.html#associatedconstant.FEATURE_BONDING_CURVE)
+    /// and no tiers.
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_init_market<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        accepted_mint_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        mint_index: Option<(&AccountInfo<'a>, &AccountInfo<'a>)>,
+        tenant_info: Option<&AccountInfo<'a>>,
+        category: [u8; 4],
+        project_id: [u8; 32],
+        features: u64,
+        min_purchase: u64,
+        max_purchase: u64,
+        pricing_tiers: &[PricingTier],
+        bonding_curve: BondingCurve,
+        purchase_cooldown_secs: i64,
+    ) -> ProgramResult {
+        let token_market = TokenMarket::try_from_slice(&market_info.data.borrow())?;
+        if token_market.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        // Bits of later versions would enable features this version doesn't enforce
+        if features & !TokenMarket::ALL_FEATURES != 0 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        TokenMarket::check_selling(features)?;
+        Self::check_purchase_limits(min_purchase, max_purchase)?;
+        let mut tiers = [PricingTier::default(); TokenMarket::MAX_PRICING_TIERS];
+        if !pricing_tiers.is_empty() {
+            if features & TokenMarket::FEATURE_TIERED_PRICING == 0
+                || pricing_tiers.len() > TokenMarket::MAX_PRICING_TIERS
+            {
+                return Err(TokenMarketError::InvalidPricingTiers.into());
+            }
+            check_pricing_tiers(pricing_tiers)?;
+            tiers[..pricing_tiers.len()].copy_from_slice(pricing_tiers);
+        }
+        if bonding_curve.is_set()
+            && (features & TokenMarket::FEATURE_BONDING_CURVE == 0 || !pricing_tiers.is_empty())
+        {
+            return Err(TokenMarketError::InvalidBondingCurve.into());
+        }
+        curve::check_bonding_curve(&bonding_curve)?;
+        if purchase_cooldown_secs < 0
+            || (purchase_cooldown_secs != 0
+                && features & TokenMarket::FEATURE_PURCHASE_COOLDOWN == 0)
+        {
+            return Err(TokenMarketError::InvalidPurchaseCooldown.into());
+        }
+
+        let authority = find_authority_address(program_id).0;
+        let accepted_mint = Mint::unpack(&accepted_mint_info.data.borrow())?;
+
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                bank_info.key,
+                accepted_mint_info.key,
+                &authority,
+            )?,
+            &[
+                bank_info.clone(),
+                accepted_mint_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        invoke(
+            &initialize_mint(
+                token_program_info.key,
+                emitter_info.key,
+                &authority,
+                Some(&authority),
+                accepted_mint.decimals,
+            )?,
+            &[
+                emitter_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        TokenMarket {
+            is_initialized: true,
+            owner: *owner_info.key,
+            bank: *bank_info.key,
+            emitter_mint: *emitter_info.key,
+            authority,
+            mint_of_acceptable: *accepted_mint_info.key,
+            legacy_bank: Pubkey::default(),
+            legacy_mint_of_acceptable: Pubkey::default(),
+            voucher_signer: [0; 20],
+            category,
+            project_id,
+            treasury: Pubkey::default(),
+            deprecated: false,
+            successor: Pubkey::default(),
+            overpayment_policy: OverpaymentPolicy::Reject,
+            mint_receipts: false,
+            features,
+            require_memo: false,
+            sandwich_guard: false,
+            wormhole_chain: 0,
+            wormhole_emitter: [0; 32],
+            onramp_signer: Pubkey::default(),
+            escrow_threshold: 0,
+            dispute_window: 0,
+            dispute_guardian: Pubkey::default(),
+            paused: false,
+            phased: false,
+            fee_bps: 0,
+            fee_vault: Pubkey::default(),
+            min_purchase,
+            max_purchase,
+            wallet_cap: 0,
+            sale_start: 0,
+            sale_end: 0,
+            allowlist_enabled: false,
+            allowlist_root: [0; 32],
+            pricing_tiers: tiers,
+            total_sold: 0,
+            bonding_curve,
+            stake_weight: StakeWeight::default(),
+            withdraw_rate: 0,
+            last_withdraw: 0,
+            wallet_spend_cap: 0,
+            purchase_cooldown_secs,
+            loyalty_tiers: [LoyaltyTier::default(); TokenMarket::MAX_LOYALTY_TIERS],
+        }
+        .serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        if let Some((mint_index_info, system_program_info)) = mint_index {
+            Self::create_mint_index(
+                program_id,
+                fee_payer_info,
+                market_info,
+                emitter_info,
+                rent_info,
+                mint_index_info,
+                system_program_info,
+            )?;
+            if let Some(tenant_info) = tenant_info {
+                Self::count_tenant_market(
+                    program_id,
+                    fee_payer_info,
+                    owner_info,
+                    rent_info,
+                    tenant_info,
+                    system_program_info,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count a new market of `owner_info` in its
+    /// [TenantStats](../state/struct.TenantStats.html), created on its first market
+    fn count_tenant_market<'a>(
+        program_id: &Pubkey,
+        fee_payer_info: &AccountInfo<'a>,
+        owner_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        tenant_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            tenant_info,
+            system_program_info,
+            rent_info,
+            TenantStats::LEN,
+            &[TENANT_SEED, owner_info.key.as_ref()],
+        )?;
+        let mut stats = TenantStats::try_from_slice(&tenant_info.data.borrow())?;
+        stats.owner = *owner_info.key;
+        stats.markets_created = stats
+            .markets_created
+            .checked_add(1)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        stats.serialize(&mut &mut tenant_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Create the [MintIndex](../state/struct.MintIndex.html) PDA of the emitter
+    /// pointing at the market, funded by the fee payer
+    fn create_mint_index<'a>(
+        program_id: &Pubkey,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        mint_index_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            mint_index_info,
+            system_program_info,
+            rent_info,
+            MintIndex::LEN,
+            &[MINT_INDEX_SEED, emitter_info.key.as_ref()],
+        )?;
+        MintIndex {
+            market: *market_info.key,
+        }
+        .serialize(&mut &mut mint_index_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Create `account_info` with `len` bytes, owned by the program, at the PDA
+    /// of `seeds`, funded by the fee payer. An account the program already
+    /// created there is kept as is.
+    fn create_pda_account<'a>(
+        program_id: &Pubkey,
+        fee_payer_info: &AccountInfo<'a>,
+        account_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        len: usize,
+        seeds: &[&[u8]],
+    ) -> ProgramResult {
+        Self::create_pda_account_owned_by(
+            program_id,
+            program_id,
+            fee_payer_info,
+            account_info,
+            system_program_info,
+            rent_info,
+            len,
+            seeds,
+        )
+    }
+
+    /// Same as [create_pda_account](#method.create_pda_account) for an
+    /// account owned by `owner` instead of the program
+    #[allow(clippy::too_many_arguments)]
+    fn create_pda_account_owned_by<'a>(
+        program_id: &Pubkey,
+        owner: &Pubkey,
+        fee_payer_info: &AccountInfo<'a>,
+        account_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        len: usize,
+        seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let (address, bump) = Pubkey::find_program_address(seeds, program_id);
+        if *account_info.key != address {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if !account_info.data_is_empty() {
+            if account_info.owner != owner {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            return Ok(());
+        }
+
+        let rent = Rent::from_account_info(rent_info)?;
+        let bump = [bump];
+        let mut signer_seeds = seeds.to_vec();
+        signer_seeds.push(&bump);
+        // anyone can send lamports to the address beforehand, and
+        // create_account refuses funded accounts, so top it up instead
+        let lamports = account_info.lamports();
+        if lamports > 0 {
+            let missing = rent.minimum_balance(len).saturating_sub(lamports);
+            if missing > 0 {
+                invoke(
+                    &system_instruction::transfer(fee_payer_info.key, &address, missing),
+                    &[
+                        fee_payer_info.clone(),
+                        account_info.clone(),
+                        system_program_info.clone(),
+                    ],
+                )?;
+            }
+            let account_infos = &[account_info.clone(), system_program_info.clone()];
+            invoke_signed(
+                &system_instruction::allocate(&address, len as u64),
+                account_infos,
+                &[&signer_seeds],
+            )?;
+            return invoke_signed(
+                &system_instruction::assign(&address, owner),
+                account_infos,
+                &[&signer_seeds],
+            );
+        }
+        invoke_signed(
+            &create_account(
+                fee_payer_info.key,
+                &address,
+                rent.minimum_balance(len),
+                len as u64,
+                owner,
+            ),
+            &[
+                fee_payer_info.clone(),
+                account_info.clone(),
+                system_program_info.clone(),
+            ],
+            &[&signer_seeds],
+        )
+    }
+
+    /// Fail if the write-off account at `write_off_info` still delegates
+    /// anything once a purchase pulled its payment. Purchases pull the whole
+    /// delegation, which clears it, but the token program skips the
+    /// bookkeeping of transfers to the source itself, e.g. a tip paid back to
+    /// the write-off account, and what is left could be spent by anyone later.
+    fn require_allowance_spent(write_off_info: &AccountInfo) -> ProgramResult {
+        let write_off = Account::unpack(&write_off_info.data.borrow())?;
+        if write_off.delegate.is_some() {
+            return Err(TokenMarketError::AllowanceNotSpent.into());
+        }
+        Ok(())
+    }
+
+    /// Whether the token account at `account_info` was closed or never created
+    fn is_closed(account_info: &AccountInfo) -> bool {
+        account_info.data_is_empty() || *account_info.owner != spl_token::id()
+    }
+
+    /// Process [BuyTokens](enum.TokenMarketInstructions.html) instruction at the
+    /// `quoted` price, or else at the price of the open sale phase or at par,
+    /// with `tip` also paid from the write-off account for relayed purchases.
+    /// `allowlisted` purchases carry a voucher or a quote of the voucher signer.
+    /// `campaign` is recorded in the receipt, the memo of the transaction is
+    /// looked up through `instructions_info` when given, `receipt_infos` are
+    /// the accounts after the purchase accounts. Tiered and bonding curve
+    /// markets price the purchases that aren't quoted or phased by their tiers
+    /// or curve and count them in `total_sold`, which needs the market writable.
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_buy_tokens<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        recipient: &AccountInfo<'a>,
+        write_off_acc_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+        amount: u64,
+        quoted: Option<Price>,
+        allowlisted: bool,
+        tip: Option<(&AccountInfo<'a>, u64)>,
+        campaign: [u8; 8],
+        coupon_info: Option<&AccountInfo<'a>>,
+        instructions_info: Option<&AccountInfo<'a>>,
+        allowlist_proof: Option<Vec<[u8; 32]>>,
+        receipt_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let tip_amount = tip.map_or(0, |(_, tip_amount)| tip_amount);
+        let mut token_market = Self::load_market(program_id, market_info)?;
+        token_market.require_trading()?;
+        Self::require_sale_open(&token_market)?;
+        if token_market.bank != *bank_info.key
+            || token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(bank_info) {
+            return Err(TokenMarketError::BankMissing.into());
+        }
+        if tip.is_some() {
+            token_market.require_features(TokenMarket::FEATURE_RELAYER_TIPS)?;
+        }
+        if campaign != [0; 8] {
+            token_market.require_features(TokenMarket::FEATURE_CAMPAIGNS)?;
+        }
+        let coupon = match coupon_info {
+            Some(coupon_info) => {
+                token_market.require_features(TokenMarket::FEATURE_COUPONS)?;
+                Some(Self::load_redeemable_coupon(
+                    program_id,
+                    market_info,
+                    coupon_info,
+                )?)
+            }
+            None => None,
+        };
+        let memo_hash = match instructions_info {
+            Some(instructions_info) => Self::memo_hash(instructions_info)?,
+            None => None,
+        };
+        if token_market.require_memo && memo_hash.is_none() {
+            return Err(TokenMarketError::MemoRequired.into());
+        }
+        token_market.check_purchase(amount)?;
+        // phased markets sell on the terms of the phase open now
+        let mut phase = None;
+        if token_market.phased {
+            let phases_info = Self::find_sale_phases(program_id, receipt_infos)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let phases = Self::load_sale_phases(program_id, market_info, phases_info)?;
+            let index = phases
+                .active(Clock::get()?.unix_timestamp)
+                .ok_or(TokenMarketError::SaleNotOpen)?;
+            phase = Some((phases_info, phases, index));
+        }
+        let price = match (quoted, &phase) {
+            (Some(quoted), _) => quoted,
+            (None, Some((_, phases, index))) => phases.phases[*index].price(),
+            (None, None) => Price::PAR,
+        };
+        let counted = quoted.is_none() && phase.is_none() && token_market.counts_sales();
+        if counted {
+            // tier and curve prices already are what the owner sells at
+            if coupon.is_some() {
+                return Err(TokenMarketError::InvalidDiscount.into());
+            }
+            if !market_info.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+        }
+
+        let write_off_acc = Account::unpack(&write_off_acc_info.data.borrow())?;
+        if write_off_acc.mint != token_market.mint_of_acceptable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let recipient_acc = Account::unpack(&recipient.data.borrow())?;
+        if recipient_acc.mint != token_market.emitter_mint {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // caught here, the mint CPI would fail with a generic token error
+        // after the payment already went through
+        if recipient_acc.is_frozen() {
+            return Err(TokenMarketError::RecipientFrozen.into());
+        }
+        if token_market.allowlist_enabled {
+            match allowlist_proof {
+                Some(proof) => {
+                    let leaf = merkle::allowlist_leaf(&recipient_acc.owner);
+                    if token_market.allowlist_root == [0; 32]
+                        || !merkle::verify(&token_market.allowlist_root, leaf, &proof)
+                    {
+                        return Err(TokenMarketError::InvalidAllowlistProof.into());
+                    }
+                }
+                None => Self::require_allowlisted(
+                    program_id,
+                    market_info,
+                    &recipient_acc.owner,
+                    receipt_infos,
+                )?,
+            }
+        }
+
+        // the payment is pulled through a delegation to the market authority,
+        // a stale or oversized one could be spent again by anyone later,
+        // so any excess is either rejected or pulled as well
+        if write_off_acc.delegate != COption::Some(token_market.authority) {
+            return Err(TokenMarketError::DelegationMismatch.into());
+        }
+        // loyal buyers get the larger of their discount and the coupon's
+        let loyalty_bps = if token_market.has_loyalty_tiers() && !counted {
+            Self::loyalty_discount(
+                program_id,
+                market_info,
+                &token_market,
+                &recipient_acc.owner,
+                receipt_infos,
+            )?
+        } else {
+            0
+        };
+        let discount_bps = coupon
+            .as_ref()
+            .map_or(0, |coupon| coupon.discount_bps)
+            .max(loyalty_bps);
+        let discounted = match (&coupon, discount_bps) {
+            (None, 0) => price,
+            _ => price
+                .discounted(discount_bps)
+                .ok_or(TokenMarketError::InvalidDiscount)?,
+        };
+        let mut settlement = settle_purchase_at(
+            token_market.overpayment_policy,
+            discounted,
+            amount,
+            tip_amount,
+            write_off_acc.delegated_amount,
+        )?;
+        if counted && token_market.has_bonding_curve() {
+            settlement.emitted =
+                curve::emitted_for(&token_market.bonding_curve, token_market.total_sold, amount)
+                    .filter(|emitted| *emitted > 0 || amount == 0)
+                    .ok_or(ProgramError::InvalidArgument)?;
+        } else if counted {
+            // a purchase crossing the end of a tier is split across the tiers
+            settlement.emitted =
+                emitted_across_tiers(&token_market.pricing_tiers, token_market.total_sold, amount)
+                    .filter(|emitted| *emitted > 0 || amount == 0)
+                    .ok_or(TokenMarketError::TiersSoldOut)?;
+        }
+        // large purchases go through BuyTokensEscrowed, which can't be
+        // combined with tips, receipts or campaigns
+        if token_market.escrows(settlement.emitted) {
+            return Err(TokenMarketError::EscrowRequired.into());
+        }
+        // the protocol fee comes out of what the bank keeps, the buyer pays the same
+        let fee = token_market.protocol_fee(settlement.proceeds());
+        #[cfg(feature = "debug-math")]
+        crate::pricing::explain_purchase(
+            token_market.overpayment_policy,
+            discounted,
+            amount,
+            tip_amount,
+            write_off_acc.delegated_amount,
+            token_market.fee_bps,
+        )?
+        .log();
+        let fee_vault_info = match token_market.fee_bps {
+            0 => None,
+            _ => Some(
+                receipt_infos
+                    .iter()
+                    .find(|account_info| *account_info.key == token_market.fee_vault)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?,
+            ),
+        };
+        if token_market.tracks_buyers() {
+            let staked = stake::find_stake(
+                &token_market.stake_weight,
+                &recipient_acc.owner,
+                receipt_infos,
+            );
+            Self::record_wallet_purchase(
+                program_id,
+                market_info,
+                token_market.wallet_cap_with(staked),
+                token_market.wallet_spend_cap,
+                token_market.purchase_cooldown_secs,
+                &recipient_acc.owner,
+                &settlement,
+                receipt_infos,
+            )?;
+        }
+        if let Some((phases_info, phases, index)) = &mut phase {
+            phases.record_purchase(
+                *index,
+                settlement.emitted,
+                settlement.proceeds(),
+                allowlisted,
+            )?;
+            phases.serialize(&mut &mut phases_info.data.borrow_mut()[..])?;
+        }
+        if counted {
+            token_market.total_sold = token_market
+                .total_sold
+                .checked_add(settlement.emitted)
+                .ok_or(TokenMarketError::TiersSoldOut)?;
+            token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+        }
+
+        // check that there are enough tokens to exchange the requested number of tokens
+        if write_off_acc.amount < write_off_acc.delegated_amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        crate::invariant!(
+            settlement.refund <= settlement.to_bank && fee <= settlement.proceeds(),
+            "fee {} refund {} to bank {}",
+            fee,
+            settlement.refund,
+            settlement.to_bank
+        );
+        #[cfg(feature = "debug-assert-onchain")]
+        let before = crate::invariants::Balances::read(bank_info, emitter_info)?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program.key,
+                write_off_acc_info.key,
+                bank_info.key,
+                authority_info.key,
+                &[],
+                settlement.to_bank - fee,
+            )?,
+            &[
+                write_off_acc_info.clone(),
+                bank_info.clone(),
+                authority_info.clone(),
+                token_program.clone(),
+            ],
+        )?;
+        if let Some(fee_vault_info) = fee_vault_info.filter(|_| fee > 0) {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program.key,
+                    write_off_acc_info.key,
+                    fee_vault_info.key,
+                    authority_info.key,
+                    &[],
+                    fee,
+                )?,
+                &[
+                    write_off_acc_info.clone(),
+                    fee_vault_info.clone(),
+                    authority_info.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+            Self::count_protocol_fee(program_id, &token_market.owner, fee, receipt_infos)?;
+            Event::ProtocolFee {
+                market: *market_info.key,
+                fee_vault: *fee_vault_info.key,
+                amount: fee,
+            }
+            .log();
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program.key,
+                emitter_info.key,
+                recipient.key,
+                authority_info.key,
+                &[],
+                settlement.emitted,
+            )?,
+            &[
+                emitter_info.clone(),
+                recipient.clone(),
+                authority_info.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        if let Some((tip_info, _)) = tip {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program.key,
+                    write_off_acc_info.key,
+                    tip_info.key,
+                    authority_info.key,
+                    &[],
+                    settlement.tip,
+                )?,
+                &[
+                    write_off_acc_info.clone(),
+                    tip_info.clone(),
+                    authority_info.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+        }
+        Self::require_allowance_spent(write_off_acc_info)?;
+
+        if settlement.excess > 0 {
+            if settlement.refund > 0 {
+                Self::invoke_as_authority(
+                    program_id,
+                    &transfer(
+                        token_program.key,
+                        bank_info.key,
+                        write_off_acc_info.key,
+                        authority_info.key,
+                        &[],
+                        settlement.refund,
+                    )?,
+                    &[
+                        bank_info.clone(),
+                        write_off_acc_info.clone(),
+                        authority_info.clone(),
+                        token_program.clone(),
+                    ],
+                )?;
+            }
+            Event::Overpayment {
+                market: *market_info.key,
+                policy: token_market.overpayment_policy,
+                excess: settlement.excess,
+            }
+            .log();
+        }
+        // the bank keeps the proceeds less the fee and the buyer gets all that was emitted
+        #[cfg(feature = "debug-assert-onchain")]
+        {
+            let after = crate::invariants::Balances::read(bank_info, emitter_info)?;
+            crate::invariant!(
+                after.moved(&before, settlement.proceeds() - fee, settlement.emitted),
+                "before {:?} after {:?} proceeds {} fee {} emitted {}",
+                before,
+                after,
+                settlement.proceeds(),
+                fee,
+                settlement.emitted
+            );
+        }
+
+        Event::Purchase {
+            market: *market_info.key,
+            buyer: recipient_acc.owner,
+            amount: settlement.emitted,
+            memo_hash: memo_hash.unwrap_or_default(),
+        }
+        .log();
+        if let Some((_, _, index)) = phase {
+            Event::PhaseSale {
+                market: *market_info.key,
+                phase: index as u8,
+                amount: settlement.emitted,
+                paid: settlement.proceeds(),
+            }
+            .log();
+        }
+
+        if let (Some(mut coupon), Some(coupon_info)) = (coupon, coupon_info) {
+            coupon.remaining_uses -= 1;
+            coupon.serialize(&mut &mut coupon_info.data.borrow_mut()[..])?;
+            Event::CouponRedeemed {
+                market: *market_info.key,
+                coupon: *coupon_info.key,
+                bonus: settlement.emitted - price.emitted(amount).unwrap_or_default(),
+            }
+            .log();
+        }
+
+        if token_market.mint_receipts {
+            Self::mint_receipt(
+                program_id,
+                market_info,
+                authority_info,
+                token_program,
+                &recipient_acc.owner,
+                settlement.emitted,
+                campaign,
+                receipt_infos,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Mint a receipt NFT of a purchase of `amount` to `buyer` and record
+    /// the purchase in its [PurchaseReceipt](../state/struct.PurchaseReceipt.html)
+    #[allow(clippy::too_many_arguments)]
+    fn mint_receipt<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+        buyer: &Pubkey,
+        amount: u64,
+        campaign: [u8; 8],
+        receipt_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let (
+            fee_payer_info,
+            receipt_mint_info,
+            holding_info,
+            receipt_info,
+            system_program_info,
+            rent_info,
+        ) = match receipt_infos {
+            [fee_payer, receipt_mint, holding, receipt, system_program, rent, ..] => (
+                fee_payer,
+                receipt_mint,
+                holding,
+                receipt,
+                system_program,
+                rent,
+            ),
+            _ => return Err(ProgramError::NotEnoughAccountKeys),
+        };
+        let rent = Rent::from_account_info(rent_info)?;
+
+        for (account_info, len) in &[(receipt_mint_info, Mint::LEN), (holding_info, Account::LEN)] {
+            invoke(
+                &create_account(
+                    fee_payer_info.key,
+                    account_info.key,
+                    rent.minimum_balance(*len),
+                    *len as u64,
+                    token_program.key,
+                ),
+                &[
+                    fee_payer_info.clone(),
+                    (*account_info).clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+        }
+        invoke(
+            &initialize_mint(
+                token_program.key,
+                receipt_mint_info.key,
+                authority_info.key,
+                None,
+                0,
+            )?,
+            &[
+                receipt_mint_info.clone(),
+                rent_info.clone(),
+                token_program.clone(),
+            ],
+        )?;
+        invoke(
+            &initialize_account2(
+                token_program.key,
+                holding_info.key,
+                receipt_mint_info.key,
+                buyer,
+            )?,
+            &[
+                holding_info.clone(),
+                receipt_mint_info.clone(),
+                rent_info.clone(),
+                token_program.clone(),
+            ],
+        )?;
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program.key,
+                receipt_mint_info.key,
+                holding_info.key,
+                authority_info.key,
+                &[],
+                1,
+            )?,
+            &[
+                receipt_mint_info.clone(),
+                holding_info.clone(),
+                authority_info.clone(),
+                token_program.clone(),
+            ],
+        )?;
+        // a single token with no mint authority left makes it non-fungible
+        Self::invoke_as_authority(
+            program_id,
+            &set_authority(
+                token_program.key,
+                receipt_mint_info.key,
+                None,
+                AuthorityType::MintTokens,
+                authority_info.key,
+                &[],
+            )?,
+            &[
+                receipt_mint_info.clone(),
+                authority_info.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            receipt_info,
+            system_program_info,
+            rent_info,
+            PurchaseReceipt::LEN,
+            &[RECEIPT_SEED, receipt_mint_info.key.as_ref()],
+        )?;
+        PurchaseReceipt {
+            market: *market_info.key,
+            buyer: *buyer,
+            amount,
+            slot: Clock::get()?.slot,
+            campaign,
+        }
+        .serialize(&mut &mut receipt_info.data.borrow_mut()[..])?;
+
+        Event::ReceiptMinted {
+            market: *market_info.key,
+            receipt_mint: *receipt_mint_info.key,
+            buyer: *buyer,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [CreateVestingVault](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_create_vesting_vault<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        delegation_info: Option<&AccountInfo<'a>>,
+        market_info: &AccountInfo<'a>,
+        vesting_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        beneficiary_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        vesting: VestingVault,
+    ) -> ProgramResult {
+        let token_market = Self::load_market_as(
+            program_id,
+            market_info,
+            owner_info,
+            delegation_info,
+            DelegatedAction::CreateVestingVault,
+            vesting.total,
+        )?;
+        token_market.require_features(TokenMarket::FEATURE_VESTING)?;
+        if token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if vesting_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if VestingVault::try_from_slice(&vesting_info.data.borrow())?.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        if vesting.duration <= 0 || vesting.cliff < 0 || vesting.cliff > vesting.duration {
+            return Err(TokenMarketError::InvalidVestingSchedule.into());
+        }
+
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                vault_info.key,
+                emitter_info.key,
+                authority_info.key,
+            )?,
+            &[
+                vault_info.clone(),
+                emitter_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program_info.key,
+                emitter_info.key,
+                vault_info.key,
+                authority_info.key,
+                &[],
+                vesting.total,
+            )?,
+            &[
+                emitter_info.clone(),
+                vault_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        msg!(
+            "Vesting of {} tokens created for {}",
+            vesting.total,
+            beneficiary_info.key
+        );
+        vesting.serialize(&mut &mut vesting_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [ClaimVested](enum.TokenMarketInstructions.html) instruction
+    pub fn process_claim_vested<'a>(
+        program_id: &Pubkey,
+        beneficiary_info: &AccountInfo<'a>,
+        vesting_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        if vesting_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let vesting = VestingVault::try_from_slice(&vesting_info.data.borrow())?;
+        if !vesting.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if !beneficiary_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if vesting.beneficiary != *beneficiary_info.key || vesting.vault != *vault_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let released = Self::release_vested(
+            program_id,
+            vesting_info,
+            vault_info,
+            destination_info,
+            authority_info,
+            token_program_info,
+            vesting,
+        )?;
+        if released == 0 {
+            msg!("Nothing to claim");
+        }
+
+        Ok(())
+    }
+
+    /// Process [CrankVesting](enum.TokenMarketInstructions.html) instruction,
+    /// `claim_infos` holding the vesting, vault and destination of every vesting
+    pub fn process_crank_vesting<'a>(
+        program_id: &Pubkey,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        claim_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        if claim_infos.is_empty() || claim_infos.len() % 3 != 0 {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let mut cranked = 0;
+        for claim in claim_infos.chunks(3) {
+            let (vesting_info, vault_info, destination_info) = (&claim[0], &claim[1], &claim[2]);
+            if vesting_info.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            let vesting = VestingVault::try_from_slice(&vesting_info.data.borrow())?;
+            if !vesting.is_initialized() {
+                return Err(ProgramError::UninitializedAccount);
+            }
+            if vesting.vault != *vault_info.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            // Nobody signs for the beneficiary, so the tokens may only go to its own account
+            let mint = Account::unpack(&vault_info.data.borrow())?.mint;
+            if *destination_info.key != find_associated_token_address(&vesting.beneficiary, &mint) {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            let released = Self::release_vested(
+                program_id,
+                vesting_info,
+                vault_info,
+                destination_info,
+                authority_info,
+                token_program_info,
+                vesting,
+            )?;
+            if released > 0 {
+                cranked += 1;
+            }
+        }
+        msg!("Claimed {} of {} vestings", cranked, claim_infos.len() / 3);
+
+        Ok(())
+    }
+
+    /// Transfer what `vesting` vested and was not claimed yet from its vault
+    /// to `destination_info`, returning the amount transferred
+    fn release_vested<'a>(
+        program_id: &Pubkey,
+        vesting_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        mut vesting: VestingVault,
+    ) -> Result<u64, ProgramError> {
+        let now = Clock::get()?.unix_timestamp;
+        let amount = vesting.vested_amount(now).saturating_sub(vesting.released);
+        if amount == 0 {
+            return Ok(0);
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                vault_info.key,
+                destination_info.key,
+                authority_info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                vault_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        vesting.released += amount;
+        vesting.serialize(&mut &mut vesting_info.data.borrow_mut()[..])?;
+
+        Ok(amount)
+    }
+
+    /// Process [RevokeVesting](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_revoke_vesting<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        vesting_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        Self::load_owned_market(program_id, market_info, owner_info)?;
+        if vesting_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut vesting = VestingVault::try_from_slice(&vesting_info.data.borrow())?;
+        if !vesting.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if vesting.market != *market_info.key || vesting.vault != *vault_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if !vesting.revocable {
+            return Err(TokenMarketError::VestingNotRevocable.into());
+        }
+        if vesting.revoked {
+            return Err(TokenMarketError::VestingAlreadyRevoked.into());
+        }
+
+        let now = Clock::get()?.unix_timestamp;
+        let vested = vesting.vested_amount(now);
+        let unvested = vesting.total - vested;
+
+        if unvested > 0 {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    vault_info.key,
+                    destination_info.key,
+                    authority_info.key,
+                    &[],
+                    unvested,
+                )?,
+                &[
+                    vault_info.clone(),
+                    destination_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+        }
+
+        vesting.total = vested;
+        vesting.revoked = true;
+        vesting.serialize(&mut &mut vesting_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [MigrateAcceptableMint](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_migrate_acceptable_mint<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        new_bank_info: &AccountInfo<'a>,
+        new_accepted_mint_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if token_market.has_legacy_bank() {
+            return Err(TokenMarketError::LegacyBankExists.into());
+        }
+        if token_market.mint_of_acceptable == *new_accepted_mint_info.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        if token_market.emitter_mint != *emitter_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // tokens are exchanged 1:1, so the successor must keep the emitter decimals
+        let new_decimals = Mint::unpack(&new_accepted_mint_info.data.borrow())?.decimals;
+        if new_decimals != Mint::unpack(&emitter_info.data.borrow())?.decimals {
+            return Err(TokenMarketError::MintDecimalsMismatch.into());
+        }
+
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                new_bank_info.key,
+                new_accepted_mint_info.key,
+                &token_market.authority,
+            )?,
+            &[
+                new_bank_info.clone(),
+                new_accepted_mint_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        token_market.legacy_bank = token_market.bank;
+        token_market.legacy_mint_of_acceptable = token_market.mint_of_acceptable;
+        token_market.bank = *new_bank_info.key;
+        token_market.mint_of_acceptable = *new_accepted_mint_info.key;
+        // the treasury holds the old mint and can't receive new payments
+        if token_market.has_treasury() {
+            Event::TreasuryChanged {
+                market: *market_info.key,
+                old: token_market.treasury,
+                new: Pubkey::default(),
+            }
+            .log();
+            token_market.treasury = Pubkey::default();
+        }
+        // so is the fee vault, the owner sets the fee again to get one of the new mint
+        token_market.fee_bps = 0;
+        // and the purchase limits count tokens of the old mint
+        token_market.min_purchase = 0;
+        token_market.max_purchase = 0;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [CreateHoldingsMigration](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_create_holdings_migration(
+        program_id: &Pubkey,
+        from_owner_info: &AccountInfo,
+        to_owner_info: &AccountInfo,
+        from_market_info: &AccountInfo,
+        to_market_info: &AccountInfo,
+        migration_info: &AccountInfo,
+        numerator: u64,
+        denominator: u64,
+    ) -> ProgramResult {
+        let from_market = Self::load_owned_market(program_id, from_market_info, from_owner_info)?;
+        let to_market = Self::load_owned_market(program_id, to_market_info, to_owner_info)?;
+        // migrated tokens weren't paid into the bank of a market that buys them back
+        if to_market.supports(TokenMarket::FEATURE_SELLING) {
+            return Err(TokenMarketError::UnbackedSelling.into());
+        }
+        if from_market_info.key == to_market_info.key {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if numerator == 0 || denominator == 0 {
+            return Err(TokenMarketError::InvalidMigrationRatio.into());
+        }
+        if migration_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if HoldingsMigration::try_from_slice(&migration_info.data.borrow())?.is_initialized() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        HoldingsMigration {
+            is_initialized: true,
+            from_market: *from_market_info.key,
+            to_market: *to_market_info.key,
+            from_emitter: from_market.emitter_mint,
+            to_emitter: to_market.emitter_mint,
+            numerator,
+            denominator,
+        }
+        .serialize(&mut &mut migration_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [MigrateHoldings](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_migrate_holdings<'a>(
+        program_id: &Pubkey,
+        holder_info: &AccountInfo<'a>,
+        migration_info: &AccountInfo<'a>,
+        source_info: &AccountInfo<'a>,
+        from_emitter_info: &AccountInfo<'a>,
+        to_emitter_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        // only CreateHoldingsMigration writes migrations, taking both emitters
+        // from markets of the program their owners signed for
+        if migration_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let migration = HoldingsMigration::try_from_slice(&migration_info.data.borrow())?;
+        if !migration.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if migration.from_emitter != *from_emitter_info.key
+            || migration.to_emitter != *to_emitter_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let converted = match migration.converted_amount(amount) {
+            Some(converted) if converted > 0 => converted,
+            _ => return Err(TokenMarketError::InvalidMigrationRatio.into()),
+        };
+
+        invoke(
+            &burn(
+                token_program_info.key,
+                source_info.key,
+                from_emitter_info.key,
+                holder_info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                source_info.clone(),
+                from_emitter_info.clone(),
+                holder_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program_info.key,
+                to_emitter_info.key,
+                destination_info.key,
+                authority_info.key,
+                &[],
+                converted,
+            )?,
+            &[
+                to_emitter_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        msg!("Migrated {} tokens into {}", amount, converted);
+        Ok(())
+    }
+
+    /// Process [SetVoucherSigner](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_voucher_signer(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        eth_address: [u8; 20],
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        // The voucher signer signs both vouchers and price quotes
+        if eth_address != [0; 20]
+            && !token_market.supports(TokenMarket::FEATURE_VOUCHERS)
+            && !token_market.supports(TokenMarket::FEATURE_PRICE_QUOTES)
+        {
+            return Err(TokenMarketError::FeatureDisabled.into());
+        }
+        token_market.voucher_signer = eth_address;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Count a sale of `amount` in the [CampaignStats](../state/struct.CampaignStats.html)
+    /// of `campaign`, created by the fee payer on its first sale
+    #[allow(clippy::too_many_arguments)]
+    fn record_campaign_sale<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        campaign_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        campaign: [u8; 8],
+        amount: u64,
+    ) -> ProgramResult {
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            campaign_info,
+            system_program_info,
+            rent_info,
+            CampaignStats::LEN,
+            &[CAMPAIGN_SEED, market_info.key.as_ref(), &campaign],
+        )?;
+        let mut stats = CampaignStats::try_from_slice(&campaign_info.data.borrow())?;
+        stats.market = *market_info.key;
+        stats.campaign = campaign;
+        stats.purchases = stats.purchases.saturating_add(1);
+        stats.amount = stats.amount.saturating_add(amount);
+        stats.serialize(&mut &mut campaign_info.data.borrow_mut()[..])?;
+
+        Event::CampaignSale {
+            market: *market_info.key,
+            campaign,
+            amount,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [SetReceiptMinting](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_receipt_minting(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        delegation_info: Option<&AccountInfo>,
+        market_info: &AccountInfo,
+        enabled: bool,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_market_as(
+            program_id,
+            market_info,
+            owner_info,
+            delegation_info,
+            DelegatedAction::SetReceiptMinting,
+            1,
+        )?;
+        if enabled {
+            token_market.require_features(TokenMarket::FEATURE_RECEIPTS)?;
+        }
+        token_market.mint_receipts = enabled;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetOverpaymentPolicy](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_overpayment_policy(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        delegation_info: Option<&AccountInfo>,
+        market_info: &AccountInfo,
+        policy: OverpaymentPolicy,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_market_as(
+            program_id,
+            market_info,
+            owner_info,
+            delegation_info,
+            DelegatedAction::SetOverpaymentPolicy,
+            1,
+        )?;
+        token_market.overpayment_policy = policy;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetTreasury](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_treasury(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        treasury_info: &AccountInfo,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if *treasury_info.owner != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let treasury = Account::unpack(&treasury_info.data.borrow())?;
+        if treasury.mint != token_market.mint_of_acceptable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Event::TreasuryChanged {
+            market: *market_info.key,
+            old: token_market.treasury,
+            new: *treasury_info.key,
+        }
+        .log();
+        token_market.treasury = *treasury_info.key;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetListingInfo](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_set_listing_info<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        delegation_info: Option<&AccountInfo<'a>>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        listing_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        listing: ListingInfo,
+    ) -> ProgramResult {
+        Self::load_market_as(
+            program_id,
+            market_info,
+            owner_info,
+            delegation_info,
+            DelegatedAction::SetListingInfo,
+            1,
+        )?;
+        if listing.website.len() > ListingInfo::MAX_WEBSITE_LEN {
+            return Err(ProgramError::InvalidArgument);
+        }
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            listing_info,
+            system_program_info,
+            rent_info,
+            ListingInfo::LEN,
+            &[LISTING_SEED, market_info.key.as_ref()],
+        )?;
+        listing.serialize(&mut &mut listing_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetNotificationConfig](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_set_notification_config<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        config_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        config: NotificationConfig,
+    ) -> ProgramResult {
+        Self::load_owned_market(program_id, market_info, owner_info)?;
+        if config.sealed.len() > NotificationConfig::MAX_SEALED_LEN {
+            return Err(ProgramError::InvalidArgument);
+        }
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            config_info,
+            system_program_info,
+            rent_info,
+            NotificationConfig::LEN,
+            &[
+                NOTIFY_SEED,
+                market_info.key.as_ref(),
+                owner_info.key.as_ref(),
+            ],
+        )?;
+        config.serialize(&mut &mut config_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetDelegation](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_set_delegation<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        delegate_info: &AccountInfo<'a>,
+        delegation_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        daily_limits: [u64; DelegatedAction::COUNT],
+    ) -> ProgramResult {
+        Self::load_owned_market(program_id, market_info, owner_info)?
+            .require_features(TokenMarket::FEATURE_DELEGATION)?;
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            delegation_info,
+            system_program_info,
+            rent_info,
+            Delegation::LEN,
+            &[
+                DELEGATION_SEED,
+                market_info.key.as_ref(),
+                delegate_info.key.as_ref(),
+            ],
+        )?;
+
+        let mut delegation = Delegation::try_from_slice(&delegation_info.data.borrow())?;
+        delegation.is_initialized = true;
+        delegation.market = *market_info.key;
+        delegation.delegate = *delegate_info.key;
+        delegation.daily_limits = daily_limits;
+        delegation.serialize(&mut &mut delegation_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [Deprecate](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_deprecate<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        notice_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        successor: Option<Pubkey>,
+        message_uri: String,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if message_uri.len() > DeprecationNotice::MAX_MESSAGE_URI_LEN
+            || successor == Some(*market_info.key)
+        {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            notice_info,
+            system_program_info,
+            rent_info,
+            DeprecationNotice::LEN,
+            &[DEPRECATION_SEED, market_info.key.as_ref()],
+        )?;
+        DeprecationNotice {
+            market: *market_info.key,
+            message_uri,
+        }
+        .serialize(&mut &mut notice_info.data.borrow_mut()[..])?;
+
+        token_market.deprecated = true;
+        token_market.successor = successor.unwrap_or_default();
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetMemoPolicy](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_memo_policy(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        required: bool,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        token_market.require_memo = required;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [Pause](enum.TokenMarketInstructions.html) and
+    /// [Resume](enum.TokenMarketInstructions.html) instructions
+    pub fn process_set_paused(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        paused: bool,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        token_market.paused = paused;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetSalePhases](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_set_sale_phases<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        phases_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        phases: Vec<SalePhase>,
+        end: i64,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        token_market.require_features(TokenMarket::FEATURE_SALE_PHASES)?;
+        if !SalePhases::is_valid_schedule(&phases, end) {
+            return Err(TokenMarketError::InvalidSalePhases.into());
+        }
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            phases_info,
+            system_program_info,
+            rent_info,
+            SalePhases::LEN,
+            &[SALE_PHASES_SEED, market_info.key.as_ref()],
+        )?;
+
+        // a new account holds zeros, which load as phases of no market
+        let mut schedule = SalePhases::new(*market_info.key, phases, end);
+        if let Ok(previous) = SalePhases::load(&phases_info.data.borrow()) {
+            if previous.market == *market_info.key {
+                schedule.carry_over(&previous);
+            }
+        }
+        schedule.serialize(&mut &mut phases_info.data.borrow_mut()[..])?;
+
+        token_market.phased = true;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [AdvanceSalePhase](enum.TokenMarketInstructions.html) instruction
+    pub fn process_advance_sale_phase(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        phases_info: &AccountInfo,
+    ) -> ProgramResult {
+        Self::load_owned_market(program_id, market_info, owner_info)?;
+        let mut phases = Self::load_sale_phases(program_id, market_info, phases_info)?;
+        let now = Clock::get()?.unix_timestamp;
+        if phases.closed(now) {
+            return Err(TokenMarketError::SaleNotOpen.into());
+        }
+
+        let next = phases.active(now).map_or(0, |index| index + 1);
+        if next < phases.phases.len() {
+            msg!("Sale phase {} open", next);
+            phases.opened = next as u8 + 1;
+        } else {
+            msg!("Sale closed");
+            phases.end = now;
+        }
+        phases.serialize(&mut &mut phases_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetProtocolFee](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_set_protocol_fee<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        fee_vault_info: &AccountInfo<'a>,
+        acceptable_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        fee_bps: u16,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        token_market.require_features(TokenMarket::FEATURE_PROTOCOL_FEE)?;
+        if fee_bps > TokenMarket::MAX_FEE_BPS {
+            return Err(TokenMarketError::InvalidProtocolFee.into());
+        }
+        if token_market.mint_of_acceptable != *acceptable_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // the vault outlives a fee set back to zero, it is created once per mint
+        let created = fee_vault_info.data_is_empty();
+        Self::create_pda_account_owned_by(
+            program_id,
+            token_program_info.key,
+            fee_payer_info,
+            fee_vault_info,
+            system_program_info,
+            rent_info,
+            Account::LEN,
+            &[
+                FEE_VAULT_SEED,
+                market_info.key.as_ref(),
+                acceptable_info.key.as_ref(),
+            ],
+        )?;
+        if created {
+            invoke(
+                &initialize_account2(
+                    token_program_info.key,
+                    fee_vault_info.key,
+                    acceptable_info.key,
+                    &token_market.authority,
+                )?,
+                &[
+                    fee_vault_info.clone(),
+                    acceptable_info.clone(),
+                    rent_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+        }
+
+        token_market.fee_bps = fee_bps;
+        token_market.fee_vault = *fee_vault_info.key;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [WithdrawFees](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_withdraw_fees<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        fee_vault_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        let token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if token_market.authority != *authority_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(fee_vault_info) {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        let fee_vault = Account::unpack(&fee_vault_info.data.borrow())?;
+        if *fee_vault_info.key
+            != find_fee_vault_address(program_id, market_info.key, &fee_vault.mint).0
+        {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if amount == 0 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if token_market.has_treasury()
+            && fee_vault.mint == token_market.mint_of_acceptable
+            && token_market.treasury != *destination_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let destination = Account::unpack(&destination_info.data.borrow())?;
+        if destination.mint != fee_vault.mint {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                fee_vault_info.key,
+                destination_info.key,
+                authority_info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                fee_vault_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Event::FeesWithdrawn {
+            market: *market_info.key,
+            fee_vault: *fee_vault_info.key,
+            destination: *destination_info.key,
+            amount,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [SetPurchaseLimits](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_purchase_limits(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        min_purchase: u64,
+        max_purchase: u64,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        Self::check_purchase_limits(min_purchase, max_purchase)?;
+        token_market.min_purchase = min_purchase;
+        token_market.max_purchase = max_purchase;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetWalletCap](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_wallet_cap(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        wallet_cap: u64,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if wallet_cap != 0 {
+            token_market.require_features(TokenMarket::FEATURE_WALLET_CAPS)?;
+        }
+        token_market.wallet_cap = wallet_cap;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetWalletSpendCap](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_wallet_spend_cap(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        wallet_spend_cap: u64,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if wallet_spend_cap != 0 {
+            token_market.require_features(TokenMarket::FEATURE_WALLET_CAPS)?;
+        }
+        token_market.wallet_spend_cap = wallet_spend_cap;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetPurchaseCooldown](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_purchase_cooldown(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        purchase_cooldown_secs: i64,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if purchase_cooldown_secs < 0 {
+            return Err(TokenMarketError::InvalidPurchaseCooldown.into());
+        }
+        if purchase_cooldown_secs != 0 {
+            token_market.require_features(TokenMarket::FEATURE_PURCHASE_COOLDOWN)?;
+        }
+        token_market.purchase_cooldown_secs = purchase_cooldown_secs;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetLoyaltyTiers](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_loyalty_tiers(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        loyalty_tiers: &[LoyaltyTier],
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if !loyalty_tiers.is_empty() {
+            token_market.require_features(TokenMarket::FEATURE_LOYALTY_TIERS)?;
+        }
+        if loyalty_tiers.len() > TokenMarket::MAX_LOYALTY_TIERS {
+            return Err(TokenMarketError::InvalidLoyaltyTiers.into());
+        }
+        check_loyalty_tiers(loyalty_tiers)?;
+        let mut tiers = [LoyaltyTier::default(); TokenMarket::MAX_LOYALTY_TIERS];
+        tiers[..loyalty_tiers.len()].copy_from_slice(loyalty_tiers);
+        token_market.loyalty_tiers = tiers;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetStakeWeight](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_stake_weight(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        stake_weight: StakeWeight,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        let stake_weight = match stake_weight.source {
+            StakeSource::Off => StakeWeight::default(),
+            source => {
+                token_market.require_features(TokenMarket::FEATURE_STAKE_WEIGHT)?;
+                if stake_weight.mint == Pubkey::default()
+                    || stake_weight.bonus_bps == 0
+                    || (source == StakeSource::GovernanceDeposit
+                        && stake_weight.governance_program == Pubkey::default())
+                {
+                    return Err(TokenMarketError::InvalidStakeWeight.into());
+                }
+                stake_weight
+            }
+        };
+        token_market.stake_weight = stake_weight;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetWithdrawRate](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_withdraw_rate(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        withdraw_rate: u64,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if withdraw_rate != 0 {
+            token_market.require_features(TokenMarket::FEATURE_WITHDRAW_STREAM)?;
+        }
+        if token_market.withdraw_rate == 0 {
+            // a new stream releases nothing of what the bank already holds
+            token_market.last_withdraw = Clock::get()?.unix_timestamp;
+        } else if withdraw_rate == 0 || withdraw_rate > token_market.withdraw_rate {
+            return Err(TokenMarketError::WithdrawRateLocked.into());
+        }
+        token_market.withdraw_rate = withdraw_rate;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetSaleWindow](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_sale_window(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        sale_start: i64,
+        sale_end: i64,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if sale_start != 0 || sale_end != 0 {
+            token_market.require_features(TokenMarket::FEATURE_SALE_WINDOW)?;
+        }
+        if sale_start != 0 && sale_end != 0 && sale_start >= sale_end {
+            return Err(TokenMarketError::InvalidSaleWindow.into());
+        }
+        if token_market.sale_started(Clock::get()?.unix_timestamp) {
+            return Err(TokenMarketError::SaleWindowLocked.into());
+        }
+        token_market.sale_start = sale_start;
+        token_market.sale_end = sale_end;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetAllowlist](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_allowlist(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        enabled: bool,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if enabled {
+            token_market.require_features(TokenMarket::FEATURE_ALLOWLIST)?;
+        }
+        token_market.allowlist_enabled = enabled;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetAllowlistRoot](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_allowlist_root(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        root: [u8; 32],
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if root != [0; 32] {
+            token_market.require_features(TokenMarket::FEATURE_ALLOWLIST)?;
+        }
+        token_market.allowlist_root = root;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [AddToAllowlist](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_add_to_allowlist<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        entry_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        buyer: Pubkey,
+    ) -> ProgramResult {
+        Self::load_owned_market(program_id, market_info, owner_info)?
+            .require_features(TokenMarket::FEATURE_ALLOWLIST)?;
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            entry_info,
+            system_program_info,
+            rent_info,
+            AllowlistEntry::LEN,
+            &[ALLOWLIST_SEED, market_info.key.as_ref(), buyer.as_ref()],
+        )?;
+        AllowlistEntry {
+            market: *market_info.key,
+            buyer,
+        }
+        .serialize(&mut &mut entry_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [RemoveFromAllowlist](enum.TokenMarketInstructions.html) instruction
+    pub fn process_remove_from_allowlist(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        entry_info: &AccountInfo,
+        receiver_info: &AccountInfo,
+        buyer: &Pubkey,
+    ) -> ProgramResult {
+        Self::load_owned_market(program_id, market_info, owner_info)?;
+        if *entry_info.key != find_allowlist_address(program_id, market_info.key, buyer).0 {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if entry_info.owner != program_id || entry_info.data_is_empty() {
+            return Err(TokenMarketError::NotOnAllowlist.into());
+        }
+
+        Self::close_program_account(entry_info, receiver_info)
+    }
+
+    /// Move the lamports of the program account at `account_info` to
+    /// `receiver_info` and zero its data
+    fn close_program_account(
+        account_info: &AccountInfo,
+        receiver_info: &AccountInfo,
+    ) -> ProgramResult {
+        // an account left without lamports is dropped once the transaction
+        // ends, the zeroed data keeps it from passing as what it was until then
+        let lamports = account_info.lamports();
+        **account_info.lamports.borrow_mut() = 0;
+        **receiver_info.lamports.borrow_mut() = receiver_info
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(ProgramError::InvalidArgument)?;
+        for byte in account_info.data.borrow_mut().iter_mut() {
+            *byte = 0;
+        }
+        Ok(())
+    }
+
+    /// Fail unless `buyer` has an [AllowlistEntry](../state/struct.AllowlistEntry.html)
+    /// on the market among `account_infos`
+    fn require_allowlisted(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        buyer: &Pubkey,
+        account_infos: &[AccountInfo],
+    ) -> ProgramResult {
+        let address = find_allowlist_address(program_id, market_info.key, buyer).0;
+        let entry_info = account_infos
+            .iter()
+            .find(|account_info| *account_info.key == address)
+            .filter(|entry_info| entry_info.owner == program_id && !entry_info.data_is_empty())
+            .ok_or(TokenMarketError::NotOnAllowlist)?;
+        let entry = AllowlistEntry::try_from_slice(&entry_info.data.borrow())?;
+        if entry.market != *market_info.key || entry.buyer != *buyer {
+            return Err(TokenMarketError::NotOnAllowlist.into());
+        }
+        Ok(())
+    }
+
+    /// Fail for purchases outside the sale window of `token_market`
+    fn require_sale_open(token_market: &TokenMarket) -> ProgramResult {
+        if token_market.has_sale_window() {
+            token_market.check_sale_window(Clock::get()?.unix_timestamp)?;
+        }
+        Ok(())
+    }
+
+    /// Count the `settlement` of a purchase by `buyer` in its
+    /// [BuyerRecord](../state/struct.BuyerRecord.html) among `account_infos`,
+    /// created on the first purchase, failing past `wallet_cap` or `spend_cap`
+    #[allow(clippy::too_many_arguments)]
+    fn record_wallet_purchase<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        wallet_cap: u64,
+        spend_cap: u64,
+        cooldown: i64,
+        buyer: &Pubkey,
+        settlement: &Settlement,
+        account_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let address = find_buyer_record_address(program_id, market_info.key, buyer).0;
+        let position = account_infos
+            .iter()
+            .position(|account_info| *account_info.key == address)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let (record_info, fee_payer_info, system_program_info, rent_info) =
+            match &account_infos[position..] {
+                [record, fee_payer, system_program, rent, ..] => {
+                    (record, fee_payer, system_program, rent)
+                }
+                _ => return Err(ProgramError::NotEnoughAccountKeys),
+            };
+
+        let mut record = if record_info.data_is_empty() {
+            Self::create_pda_account(
+                program_id,
+                fee_payer_info,
+                record_info,
+                system_program_info,
+                rent_info,
+                BuyerRecord::LEN,
+                &[BUYER_RECORD_SEED, market_info.key.as_ref(), buyer.as_ref()],
+            )?;
+            BuyerRecord {
+                market: *market_info.key,
+                buyer: *buyer,
+                purchased: 0,
+                spent: 0,
+                last_purchase: 0,
+            }
+        } else {
+            if record_info.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            BuyerRecord::try_from_slice(&record_info.data.borrow())?
+        };
+        record.cool_down(Clock::get()?.unix_timestamp, cooldown)?;
+        record.count(
+            settlement.emitted,
+            settlement.proceeds(),
+            wallet_cap,
+            spend_cap,
+        )?;
+        record.serialize(&mut &mut record_info.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    /// Loyalty discount of `buyer` by the purchases its record counts, none
+    /// before the record exists
+    fn loyalty_discount(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        token_market: &TokenMarket,
+        buyer: &Pubkey,
+        account_infos: &[AccountInfo],
+    ) -> Result<u16, ProgramError> {
+        let address = find_buyer_record_address(program_id, market_info.key, buyer).0;
+        let record_info = account_infos
+            .iter()
+            .find(|account_info| *account_info.key == address)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if record_info.data_is_empty() {
+            return Ok(0);
+        }
+        if record_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let record = BuyerRecord::try_from_slice(&record_info.data.borrow())?;
+        Ok(token_market.loyalty_discount(record.purchased))
+    }
+
+    /// Fail for limits no purchase could satisfy
+    fn check_purchase_limits(min_purchase: u64, max_purchase: u64) -> ProgramResult {
+        if max_purchase != 0 && min_purchase > max_purchase {
+            return Err(TokenMarketError::InvalidPurchaseLimits.into());
+        }
+        Ok(())
+    }
+
+    /// Process [SetSandwichGuard](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_sandwich_guard(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        enabled: bool,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if enabled {
+            token_market.require_features(TokenMarket::FEATURE_PRICE_QUOTES)?;
+        }
+        token_market.sandwich_guard = enabled;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [SetWormholeEmitter](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_wormhole_emitter(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        chain: u16,
+        emitter: [u8; 32],
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if emitter != [0; 32] {
+            token_market.require_features(TokenMarket::FEATURE_WORMHOLE)?;
+        }
+        token_market.wormhole_chain = chain;
+        token_market.wormhole_emitter = emitter;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [RedeemWormholePurchase](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_redeem_wormhole_purchase<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        recipient_info: &AccountInfo<'a>,
+        posted_vaa_info: &AccountInfo<'a>,
+        consumed_vaa_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        trailing_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        // the VAA stays unconsumed, so it can be redeemed once resumed
+        token_market.require_trading()?;
+        Self::require_sale_open(&token_market)?;
+        token_market.require_unphased()?;
+        token_market.require_uncapped()?;
+        token_market.require_open_access()?;
+        token_market.require_untiered()?;
+        token_market.require_features(TokenMarket::FEATURE_WORMHOLE)?;
+        if token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // only the core bridge writes accounts it owns, after verifying the
+        // guardian signatures
+        if *posted_vaa_info.owner != wormhole::id() {
+            return Err(TokenMarketError::InvalidVaa.into());
+        }
+        let vaa = PostedVaa::unpack(&posted_vaa_info.data.borrow())?;
+        let payload = PurchasePayload::unpack(&vaa.payload)
+            .filter(|payload| payload.market == *market_info.key);
+        let payload = match payload {
+            Some(payload)
+                if token_market.accepts_wormhole()
+                    && vaa.emitter_chain == token_market.wormhole_chain
+                    && vaa.emitter_address == token_market.wormhole_emitter =>
+            {
+                payload
+            }
+            _ => return Err(TokenMarketError::InvalidVaa.into()),
+        };
+        token_market.check_purchase(payload.amount)?;
+
+        let recipient = Account::unpack(&recipient_info.data.borrow())?;
+        if recipient.mint != token_market.emitter_mint || recipient.owner != payload.recipient {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if recipient.is_frozen() {
+            return Err(TokenMarketError::RecipientFrozen.into());
+        }
+        let amount = Price::PAR
+            .emitted(payload.amount)
+            .ok_or(ProgramError::InvalidInstructionData)?;
+
+        if !consumed_vaa_info.data_is_empty() {
+            return Err(TokenMarketError::VaaConsumed.into());
+        }
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            consumed_vaa_info,
+            system_program_info,
+            rent_info,
+            ConsumedVaa::LEN,
+            &[
+                CONSUMED_VAA_SEED,
+                &vaa.emitter_chain.to_le_bytes(),
+                &vaa.emitter_address,
+                &vaa.sequence.to_le_bytes(),
+            ],
+        )?;
+        ConsumedVaa {
+            market: *market_info.key,
+            recipient: *recipient_info.key,
+            amount,
+        }
+        .serialize(&mut &mut consumed_vaa_info.data.borrow_mut()[..])?;
+        Self::take_external_protocol_fee(
+            program_id,
+            market_info,
+            &token_market,
+            fee_payer_info,
+            token_program_info,
+            payload.amount,
+            trailing_infos,
+        )?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program_info.key,
+                emitter_info.key,
+                recipient_info.key,
+                authority_info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                emitter_info.clone(),
+                recipient_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Event::WormholePurchase {
+            market: *market_info.key,
+            buyer: payload.recipient,
+            amount,
+            sequence: vaa.sequence,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [SetOnrampSigner](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_onramp_signer(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        signer: Pubkey,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if signer != Pubkey::default() {
+            token_market.require_features(TokenMarket::FEATURE_FIAT_ONRAMP)?;
+        }
+        token_market.onramp_signer = signer;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [BuyTokensWithFiat](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_buy_tokens_with_fiat<'a>(
+        program_id: &Pubkey,
+        onramp_signer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        recipient_info: &AccountInfo<'a>,
+        settlement_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        amount: u64,
+        reference: [u8; 32],
+        trailing_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        // a forged market would name the attacker's key as on-ramp signer
+        let token_market = Self::load_market(program_id, market_info)?;
+        token_market.require_trading()?;
+        Self::require_sale_open(&token_market)?;
+        token_market.require_unphased()?;
+        token_market.require_uncapped()?;
+        token_market.require_open_access()?;
+        token_market.require_untiered()?;
+        token_market.require_features(TokenMarket::FEATURE_FIAT_ONRAMP)?;
+        if token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // the provider's signature is the only evidence of the payment
+        if !token_market.accepts_onramp()
+            || token_market.onramp_signer != *onramp_signer_info.key
+            || !onramp_signer_info.is_signer
+        {
+            return Err(TokenMarketError::InvalidOnrampAttestation.into());
+        }
+        token_market.check_purchase(amount)?;
+
+        let recipient = Account::unpack(&recipient_info.data.borrow())?;
+        if recipient.mint != token_market.emitter_mint {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if recipient.is_frozen() {
+            return Err(TokenMarketError::RecipientFrozen.into());
+        }
+        let emitted = Price::PAR
+            .emitted(amount)
+            .ok_or(ProgramError::InvalidInstructionData)?;
+
+        if !settlement_info.data_is_empty() {
+            return Err(TokenMarketError::FiatPaymentSettled.into());
+        }
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            settlement_info,
+            system_program_info,
+            rent_info,
+            FiatSettlement::LEN,
+            &[FIAT_SETTLEMENT_SEED, market_info.key.as_ref(), &reference],
+        )?;
+        FiatSettlement {
+            market: *market_info.key,
+            recipient: *recipient_info.key,
+            amount: emitted,
+            reference,
+            slot: Clock::get()?.slot,
+        }
+        .serialize(&mut &mut settlement_info.data.borrow_mut()[..])?;
+        Self::take_external_protocol_fee(
+            program_id,
+            market_info,
+            &token_market,
+            onramp_signer_info,
+            token_program_info,
+            amount,
+            trailing_infos,
+        )?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program_info.key,
+                emitter_info.key,
+                recipient_info.key,
+                authority_info.key,
+                &[],
+                emitted,
+            )?,
+            &[
+                emitter_info.clone(),
+                recipient_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Event::FiatPurchase {
+            market: *market_info.key,
+            buyer: recipient.owner,
+            amount: emitted,
+            reference: Hash::new_from_array(reference),
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [BuyTokensWithSol](enum.TokenMarketInstructions.html) instruction,
+    /// `trailing_infos` holding the fee vault and the instructions sysvar when given
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_buy_tokens_with_sol<'a>(
+        program_id: &Pubkey,
+        buyer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        recipient_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        wrap_info: &AccountInfo<'a>,
+        native_mint_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        lamports: u64,
+        trailing_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        token_market.require_trading()?;
+        Self::require_sale_open(&token_market)?;
+        token_market.require_unphased()?;
+        token_market.require_uncapped()?;
+        token_market.require_open_access()?;
+        token_market.require_untiered()?;
+        if token_market.mint_of_acceptable != native_mint::id() {
+            return Err(TokenMarketError::NotNativeMint.into());
+        }
+        if token_market.bank != *bank_info.key
+            || token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(bank_info) {
+            return Err(TokenMarketError::BankMissing.into());
+        }
+        if !buyer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let memo_hash = match Self::find_instructions_sysvar(trailing_infos) {
+            Some(instructions_info) => Self::memo_hash(instructions_info)?,
+            None => None,
+        };
+        if token_market.require_memo && memo_hash.is_none() {
+            return Err(TokenMarketError::MemoRequired.into());
+        }
+        token_market.check_purchase(lamports)?;
+
+        let recipient = Account::unpack(&recipient_info.data.borrow())?;
+        if recipient.mint != token_market.emitter_mint {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if recipient.is_frozen() {
+            return Err(TokenMarketError::RecipientFrozen.into());
+        }
+        let emitted = Price::PAR
+            .emitted(lamports)
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        if token_market.escrows(emitted) {
+            return Err(TokenMarketError::EscrowRequired.into());
+        }
+        let fee = token_market.protocol_fee(lamports);
+        let fee_vault_info = match token_market.fee_bps {
+            0 => None,
+            _ => Some(
+                trailing_infos
+                    .iter()
+                    .find(|account_info| *account_info.key == token_market.fee_vault)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?,
+            ),
+        };
+
+        // the lamports are wrapped in an account of their own: initialized
+        // with them on top of its rent reserve, its balance is the lamports
+        if *native_mint_info.key != native_mint::id() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if !wrap_info.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        Self::create_pda_account_owned_by(
+            program_id,
+            token_program_info.key,
+            buyer_info,
+            wrap_info,
+            system_program_info,
+            rent_info,
+            Account::LEN,
+            &[WRAP_SEED, buyer_info.key.as_ref()],
+        )?;
+        invoke(
+            &system_instruction::transfer(buyer_info.key, wrap_info.key, lamports),
+            &[
+                buyer_info.clone(),
+                wrap_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                wrap_info.key,
+                native_mint_info.key,
+                authority_info.key,
+            )?,
+            &[
+                wrap_info.clone(),
+                native_mint_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        let pay = |destination_info: &AccountInfo<'a>, amount: u64| -> ProgramResult {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    wrap_info.key,
+                    destination_info.key,
+                    authority_info.key,
+                    &[],
+                    amount,
+                )?,
+                &[
+                    wrap_info.clone(),
+                    destination_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )
+        };
+        pay(bank_info, lamports - fee)?;
+        if let Some(fee_vault_info) = fee_vault_info.filter(|_| fee > 0) {
+            pay(fee_vault_info, fee)?;
+            Self::count_protocol_fee(program_id, &token_market.owner, fee, trailing_infos)?;
+            Event::ProtocolFee {
+                market: *market_info.key,
+                fee_vault: *fee_vault_info.key,
+                amount: fee,
+            }
+            .log();
+        }
+        // the rent reserve of the emptied account goes back to the buyer
+        Self::invoke_as_authority(
+            program_id,
+            &close_account(
+                token_program_info.key,
+                wrap_info.key,
+                buyer_info.key,
+                authority_info.key,
+                &[],
+            )?,
+            &[
+                wrap_info.clone(),
+                buyer_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program_info.key,
+                emitter_info.key,
+                recipient_info.key,
+                authority_info.key,
+                &[],
+                emitted,
+            )?,
+            &[
+                emitter_info.clone(),
+                recipient_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Event::Purchase {
+            market: *market_info.key,
+            buyer: recipient.owner,
+            amount: emitted,
+            memo_hash: memo_hash.unwrap_or_default(),
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [BuyTokensMulti](enum.TokenMarketInstructions.html) instruction,
+    /// `recipient_infos[i]` receiving the tokens `amounts[i]` pays for and
+    /// `trailing_infos` holding the fee vault and the instructions sysvar when given
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_buy_tokens_multi<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        write_off_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        amounts: &[u64],
+        recipient_infos: &[AccountInfo<'a>],
+        trailing_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        token_market.require_trading()?;
+        Self::require_sale_open(&token_market)?;
+        token_market.require_unphased()?;
+        token_market.require_uncapped()?;
+        token_market.require_open_access()?;
+        token_market.require_untiered()?;
+        if token_market.bank != *bank_info.key
+            || token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(bank_info) {
+            return Err(TokenMarketError::BankMissing.into());
+        }
+        if amounts.is_empty() {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let memo_hash = match Self::find_instructions_sysvar(trailing_infos) {
+            Some(instructions_info) => Self::memo_hash(instructions_info)?,
+            None => None,
+        };
+        if token_market.require_memo && memo_hash.is_none() {
+            return Err(TokenMarketError::MemoRequired.into());
+        }
+
+        let write_off = Account::unpack(&write_off_info.data.borrow())?;
+        if write_off.mint != token_market.mint_of_acceptable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if write_off.delegate != COption::Some(token_market.authority) {
+            return Err(TokenMarketError::DelegationMismatch.into());
+        }
+        let mut total = 0u64;
+        let mut purchases = Vec::with_capacity(amounts.len());
+        for (amount, recipient_info) in amounts.iter().zip(recipient_infos) {
+            token_market.check_purchase(*amount)?;
+            let recipient = Account::unpack(&recipient_info.data.borrow())?;
+            if recipient.mint != token_market.emitter_mint {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            if recipient.is_frozen() {
+                return Err(TokenMarketError::RecipientFrozen.into());
+            }
+            let emitted = Price::PAR
+                .emitted(*amount)
+                .ok_or(ProgramError::InvalidInstructionData)?;
+            if token_market.escrows(emitted) {
+                return Err(TokenMarketError::EscrowRequired.into());
+            }
+            total = total
+                .checked_add(*amount)
+                .ok_or(ProgramError::InvalidInstructionData)?;
+            purchases.push((recipient_info, recipient.owner, emitted));
+        }
+        if write_off.amount < total {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        let fee = token_market.protocol_fee(total);
+        let fee_vault_info = match token_market.fee_bps {
+            0 => None,
+            _ => Some(
+                trailing_infos
+                    .iter()
+                    .find(|account_info| *account_info.key == token_market.fee_vault)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?,
+            ),
+        };
+        crate::invariant!(fee <= total, "fee {} of total {}", fee, total);
+        #[cfg(feature = "debug-assert-onchain")]
+        let (before, emitted_total) = (
+            crate::invariants::Balances::read(bank_info, emitter_info)?,
+            purchases
+                .iter()
+                .map(|(_, _, emitted)| *emitted)
+                .sum::<u64>(),
+        );
+
+        // one payment for all the purchases
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                write_off_info.key,
+                bank_info.key,
+                authority_info.key,
+                &[],
+                total - fee,
+            )?,
+            &[
+                write_off_info.clone(),
+                bank_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        if let Some(fee_vault_info) = fee_vault_info.filter(|_| fee > 0) {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    write_off_info.key,
+                    fee_vault_info.key,
+                    authority_info.key,
+                    &[],
+                    fee,
+                )?,
+                &[
+                    write_off_info.clone(),
+                    fee_vault_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+            Self::count_protocol_fee(program_id, &token_market.owner, fee, trailing_infos)?;
+            Event::ProtocolFee {
+                market: *market_info.key,
+                fee_vault: *fee_vault_info.key,
+                amount: fee,
+            }
+            .log();
+        }
+        Self::require_allowance_spent(write_off_info)?;
+
+        for (recipient_info, buyer, emitted) in purchases {
+            Self::invoke_as_authority(
+                program_id,
+                &mint_to(
+                    token_program_info.key,
+                    emitter_info.key,
+                    recipient_info.key,
+                    authority_info.key,
+                    &[],
+                    emitted,
+                )?,
+                &[
+                    emitter_info.clone(),
+                    recipient_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+            Event::Purchase {
+                market: *market_info.key,
+                buyer,
+                amount: emitted,
+                memo_hash: memo_hash.unwrap_or_default(),
+            }
+            .log();
+        }
+        #[cfg(feature = "debug-assert-onchain")]
+        {
+            let after = crate::invariants::Balances::read(bank_info, emitter_info)?;
+            crate::invariant!(
+                after.moved(&before, total - fee, emitted_total),
+                "before {:?} after {:?} total {} fee {} emitted {}",
+                before,
+                after,
+                total,
+                fee,
+                emitted_total
+            );
+        }
+        Ok(())
+    }
+
+    /// Process [BuyTokensExactOut](enum.TokenMarketInstructions.html) instruction,
+    /// `trailing_infos` holding the fee vault and the instructions sysvar
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_buy_tokens_exact_out<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        recipient_info: &AccountInfo<'a>,
+        write_off_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        out_amount: u64,
+        max_in: u64,
+        trailing_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let mut token_market = Self::load_market(program_id, market_info)?;
+        token_market.require_trading()?;
+        Self::require_sale_open(&token_market)?;
+        token_market.require_unphased()?;
+        token_market.require_uncapped()?;
+        token_market.require_open_access()?;
+        if token_market.bank != *bank_info.key
+            || token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(bank_info) {
+            return Err(TokenMarketError::BankMissing.into());
+        }
+        if out_amount == 0 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let memo_hash = match Self::find_instructions_sysvar(trailing_infos) {
+            Some(instructions_info) => Self::memo_hash(instructions_info)?,
+            None => None,
+        };
+        if token_market.require_memo && memo_hash.is_none() {
+            return Err(TokenMarketError::MemoRequired.into());
+        }
+
+        let cost = token_market
+            .cost_of(out_amount)
+            .ok_or(TokenMarketError::TiersSoldOut)?;
+        if cost > max_in {
+            return Err(TokenMarketError::SlippageExceeded.into());
+        }
+        token_market.check_purchase(cost)?;
+        if token_market.escrows(out_amount) {
+            return Err(TokenMarketError::EscrowRequired.into());
+        }
+        let counted = token_market.counts_sales();
+        if counted && !market_info.is_writable {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let write_off = Account::unpack(&write_off_info.data.borrow())?;
+        if write_off.mint != token_market.mint_of_acceptable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let recipient = Account::unpack(&recipient_info.data.borrow())?;
+        if recipient.mint != token_market.emitter_mint {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if recipient.is_frozen() {
+            return Err(TokenMarketError::RecipientFrozen.into());
+        }
+        // the whole delegation is pulled so none of it is left to spend,
+        // the part over the cost goes back
+        if write_off.delegate != COption::Some(token_market.authority)
+            || write_off.delegated_amount < cost
+        {
+            return Err(TokenMarketError::DelegationMismatch.into());
+        }
+        if write_off.amount < write_off.delegated_amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        let refund = write_off.delegated_amount - cost;
+        let fee = token_market.protocol_fee(cost);
+        let fee_vault_info = match token_market.fee_bps {
+            0 => None,
+            _ => Some(
+                trailing_infos
+                    .iter()
+                    .find(|account_info| *account_info.key == token_market.fee_vault)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?,
+            ),
+        };
+        if counted {
+            token_market.total_sold = token_market
+                .total_sold
+                .checked_add(out_amount)
+                .ok_or(TokenMarketError::TiersSoldOut)?;
+            token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+        }
+        crate::invariant!(fee <= cost, "fee {} of cost {}", fee, cost);
+        #[cfg(feature = "debug-assert-onchain")]
+        let before = crate::invariants::Balances::read(bank_info, emitter_info)?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                write_off_info.key,
+                bank_info.key,
+                authority_info.key,
+                &[],
+                write_off.delegated_amount - fee,
+            )?,
+            &[
+                write_off_info.clone(),
+                bank_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        if let Some(fee_vault_info) = fee_vault_info.filter(|_| fee > 0) {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    write_off_info.key,
+                    fee_vault_info.key,
+                    authority_info.key,
+                    &[],
+                    fee,
+                )?,
+                &[
+                    write_off_info.clone(),
+                    fee_vault_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+            Self::count_protocol_fee(program_id, &token_market.owner, fee, trailing_infos)?;
+            Event::ProtocolFee {
+                market: *market_info.key,
+                fee_vault: *fee_vault_info.key,
+                amount: fee,
+            }
+            .log();
+        }
+        Self::require_allowance_spent(write_off_info)?;
+        if refund > 0 {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    bank_info.key,
+                    write_off_info.key,
+                    authority_info.key,
+                    &[],
+                    refund,
+                )?,
+                &[
+                    bank_info.clone(),
+                    write_off_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program_info.key,
+                emitter_info.key,
+                recipient_info.key,
+                authority_info.key,
+                &[],
+                out_amount,
+            )?,
+            &[
+                emitter_info.clone(),
+                recipient_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        #[cfg(feature = "debug-assert-onchain")]
+        {
+            let after = crate::invariants::Balances::read(bank_info, emitter_info)?;
+            crate::invariant!(
+                after.moved(&before, cost - fee, out_amount),
+                "before {:?} after {:?} cost {} fee {} out {}",
+                before,
+                after,
+                cost,
+                fee,
+                out_amount
+            );
+        }
+        Event::Purchase {
+            market: *market_info.key,
+            buyer: recipient.owner,
+            amount: out_amount,
+            memo_hash: memo_hash.unwrap_or_default(),
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [CloseBuyerRecord](enum.TokenMarketInstructions.html) instruction
+    pub fn process_close_buyer_record(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        record_info: &AccountInfo,
+        buyer_info: &AccountInfo,
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        if record_info.owner != program_id || record_info.data_is_empty() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let record = BuyerRecord::try_from_slice(&record_info.data.borrow())?;
+        if *record_info.key
+            != find_buyer_record_address(program_id, market_info.key, &record.buyer).0
+        {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if *buyer_info.key != record.buyer {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // the record enforces the wallet cap for as long as the sale may go on
+        if !token_market.sale_finalized(Clock::get()?.unix_timestamp) {
+            return Err(TokenMarketError::RecordStillNeeded.into());
+        }
+
+        Self::close_program_account(record_info, buyer_info)
+    }
+
+    /// Process [CloseReceipt](enum.TokenMarketInstructions.html) instruction
+    pub fn process_close_receipt(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        receipt_mint_info: &AccountInfo,
+        receipt_info: &AccountInfo,
+        buyer_info: &AccountInfo,
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        if *receipt_info.key != find_receipt_address(program_id, receipt_mint_info.key).0 {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if receipt_info.owner != program_id || receipt_info.data_is_empty() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let receipt = PurchaseReceipt::try_from_slice(&receipt_info.data.borrow())?;
+        if receipt.market != *market_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if *buyer_info.key != receipt.buyer {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // a purchase may be disputed for the dispute window after the sale
+        let now = Clock::get()?.unix_timestamp;
+        let disputes_over = token_market
+            .sale_end
+            .checked_add(token_market.dispute_window)
+            .map_or(false, |end| now >= end);
+        if !token_market.sale_finalized(now) || !disputes_over {
+            return Err(TokenMarketError::RecordStillNeeded.into());
+        }
+
+        Self::close_program_account(receipt_info, buyer_info)
+    }
+
+    /// Process [SetDisputeEscrow](enum.TokenMarketInstructions.html) instruction
+    pub fn process_set_dispute_escrow(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo,
+        market_info: &AccountInfo,
+        threshold: u64,
+        window: i64,
+        guardian: Pubkey,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if threshold != 0 {
+            token_market.require_features(TokenMarket::FEATURE_DISPUTE_ESCROW)?;
+            if window <= 0 {
+                return Err(TokenMarketError::InvalidDisputeWindow.into());
+            }
+        }
+        token_market.escrow_threshold = threshold;
+        token_market.dispute_window = window;
+        token_market.dispute_guardian = guardian;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [BuyTokensEscrowed](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_buy_tokens_escrowed<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        recipient_info: &AccountInfo<'a>,
+        write_off_acc_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        acceptable_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        escrow_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        payment_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        instructions_info: &AccountInfo<'a>,
+        amount: u64,
+        nonce: u64,
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        token_market.require_trading()?;
+        Self::require_sale_open(&token_market)?;
+        token_market.require_unphased()?;
+        token_market.require_uncapped()?;
+        token_market.require_open_access()?;
+        token_market.require_untiered()?;
+        token_market.require_features(TokenMarket::FEATURE_DISPUTE_ESCROW)?;
+        if token_market.bank != *bank_info.key
+            || token_market.emitter_mint != *emitter_info.key
+            || token_market.mint_of_acceptable != *acceptable_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(bank_info) {
+            return Err(TokenMarketError::BankMissing.into());
+        }
+        let memo_hash = Self::memo_hash(instructions_info)?;
+        if token_market.require_memo && memo_hash.is_none() {
+            return Err(TokenMarketError::MemoRequired.into());
+        }
+        token_market.check_purchase(amount)?;
+
+        let write_off_acc = Account::unpack(&write_off_acc_info.data.borrow())?;
+        if write_off_acc.mint != token_market.mint_of_acceptable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let recipient_acc = Account::unpack(&recipient_info.data.borrow())?;
+        if recipient_acc.mint != token_market.emitter_mint {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if write_off_acc.delegate != COption::Some(token_market.authority) {
+            return Err(TokenMarketError::DelegationMismatch.into());
+        }
+        let settlement = settle_purchase_at(
+            token_market.overpayment_policy,
+            Price::PAR,
+            amount,
+            0,
+            write_off_acc.delegated_amount,
+        )?;
+        // the fee waits with the payment, a dispute refunds both
+        let fee = token_market.protocol_fee(settlement.proceeds());
+        if write_off_acc.amount < write_off_acc.delegated_amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        if !escrow_info.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            escrow_info,
+            system_program_info,
+            rent_info,
+            PurchaseEscrow::LEN,
+            &[
+                ESCROW_SEED,
+                market_info.key.as_ref(),
+                write_off_acc_info.key.as_ref(),
+                &nonce.to_le_bytes(),
+            ],
+        )?;
+        Self::create_pda_account_owned_by(
+            program_id,
+            token_program_info.key,
+            fee_payer_info,
+            vault_info,
+            system_program_info,
+            rent_info,
+            Account::LEN,
+            &[ESCROW_VAULT_SEED, escrow_info.key.as_ref()],
+        )?;
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                vault_info.key,
+                emitter_info.key,
+                authority_info.key,
+            )?,
+            &[
+                vault_info.clone(),
+                emitter_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        // the payment stays out of the bank until release, so
+        // WithdrawBank can't take what a dispute may still refund
+        Self::create_pda_account_owned_by(
+            program_id,
+            token_program_info.key,
+            fee_payer_info,
+            payment_info,
+            system_program_info,
+            rent_info,
+            Account::LEN,
+            &[ESCROW_PAYMENT_SEED, escrow_info.key.as_ref()],
+        )?;
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                payment_info.key,
+                acceptable_info.key,
+                authority_info.key,
+            )?,
+            &[
+                payment_info.clone(),
+                acceptable_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                write_off_acc_info.key,
+                payment_info.key,
+                authority_info.key,
+                &[],
+                settlement.to_bank,
+            )?,
+            &[
+                write_off_acc_info.clone(),
+                payment_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::require_allowance_spent(write_off_acc_info)?;
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program_info.key,
+                emitter_info.key,
+                vault_info.key,
+                authority_info.key,
+                &[],
+                settlement.emitted,
+            )?,
+            &[
+                emitter_info.clone(),
+                vault_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        if settlement.refund > 0 {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    payment_info.key,
+                    write_off_acc_info.key,
+                    authority_info.key,
+                    &[],
+                    settlement.refund,
+                )?,
+                &[
+                    payment_info.clone(),
+                    write_off_acc_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+        }
+        if settlement.excess > 0 {
+            Event::Overpayment {
+                market: *market_info.key,
+                policy: token_market.overpayment_policy,
+                excess: settlement.excess,
+            }
+            .log();
+        }
+
+        PurchaseEscrow {
+            market: *market_info.key,
+            recipient: *recipient_info.key,
+            write_off: *write_off_acc_info.key,
+            vault: *vault_info.key,
+            paid: settlement.proceeds() - fee,
+            fee,
+            emitted: settlement.emitted,
+            release_after: Clock::get()?.unix_timestamp + token_market.dispute_window,
+            status: EscrowStatus::Held,
+        }
+        .serialize(&mut &mut escrow_info.data.borrow_mut()[..])?;
+
+        Event::PurchaseEscrowed {
+            market: *market_info.key,
+            buyer: recipient_acc.owner,
+            escrow: *escrow_info.key,
+            amount: settlement.emitted,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Load the held escrow at `escrow_info` of `market_info` with `vault_info` and
+    /// `payment_info`
+    fn load_held_escrow(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        escrow_info: &AccountInfo,
+        vault_info: &AccountInfo,
+        payment_info: &AccountInfo,
+    ) -> Result<PurchaseEscrow, ProgramError> {
+        if escrow_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let escrow = PurchaseEscrow::try_from_slice(&escrow_info.data.borrow())?;
+        if escrow.market != *market_info.key
+            || escrow.vault != *vault_info.key
+            || find_escrow_payment_address(program_id, escrow_info.key).0 != *payment_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if escrow.status != EscrowStatus::Held {
+            return Err(TokenMarketError::EscrowSettled.into());
+        }
+        Ok(escrow)
+    }
+
+    /// Process [ReleaseEscrow](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_release_escrow<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        escrow_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        payment_info: &AccountInfo<'a>,
+        recipient_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        trailing_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        if token_market.bank != *bank_info.key || token_market.authority != *authority_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut escrow = Self::load_held_escrow(
+            program_id,
+            market_info,
+            escrow_info,
+            vault_info,
+            payment_info,
+        )?;
+        if escrow.recipient != *recipient_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Clock::get()?.unix_timestamp < escrow.release_after {
+            return Err(TokenMarketError::DisputeWindowOpen.into());
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                vault_info.key,
+                recipient_info.key,
+                authority_info.key,
+                &[],
+                escrow.emitted,
+            )?,
+            &[
+                vault_info.clone(),
+                recipient_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                payment_info.key,
+                bank_info.key,
+                authority_info.key,
+                &[],
+                escrow.paid,
+            )?,
+            &[
+                payment_info.clone(),
+                bank_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        if escrow.fee > 0 {
+            let fee_vault_info = trailing_infos
+                .iter()
+                .find(|account_info| *account_info.key == token_market.fee_vault)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    payment_info.key,
+                    fee_vault_info.key,
+                    authority_info.key,
+                    &[],
+                    escrow.fee,
+                )?,
+                &[
+                    payment_info.clone(),
+                    fee_vault_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+            Self::count_protocol_fee(program_id, &token_market.owner, escrow.fee, trailing_infos)?;
+            Event::ProtocolFee {
+                market: *market_info.key,
+                fee_vault: *fee_vault_info.key,
+                amount: escrow.fee,
+            }
+            .log();
+        }
+
+        escrow.status = EscrowStatus::Released;
+        escrow.serialize(&mut &mut escrow_info.data.borrow_mut()[..])?;
+        Event::EscrowReleased {
+            market: *market_info.key,
+            escrow: *escrow_info.key,
+            amount: escrow.emitted,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [DisputeEscrow](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_dispute_escrow<'a>(
+        program_id: &Pubkey,
+        disputer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        escrow_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        payment_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        write_off_acc_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        if !disputer_info.is_signer || !token_market.may_dispute(disputer_info.key) {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut escrow = Self::load_held_escrow(
+            program_id,
+            market_info,
+            escrow_info,
+            vault_info,
+            payment_info,
+        )?;
+        if escrow.write_off != *write_off_acc_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Clock::get()?.unix_timestamp >= escrow.release_after {
+            return Err(TokenMarketError::DisputeWindowClosed.into());
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &burn(
+                token_program_info.key,
+                vault_info.key,
+                emitter_info.key,
+                authority_info.key,
+                &[],
+                escrow.emitted,
+            )?,
+            &[
+                vault_info.clone(),
+                emitter_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                payment_info.key,
+                write_off_acc_info.key,
+                authority_info.key,
+                &[],
+                escrow.paid + escrow.fee,
+            )?,
+            &[
+                payment_info.clone(),
+                write_off_acc_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        escrow.status = EscrowStatus::Disputed;
+        escrow.serialize(&mut &mut escrow_info.data.borrow_mut()[..])?;
+        Event::EscrowDisputed {
+            market: *market_info.key,
+            escrow: *escrow_info.key,
+            refund: escrow.paid + escrow.fee,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [RecreateBank](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_recreate_bank<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        missing_bank_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        acceptable_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if token_market.bank != *missing_bank_info.key
+            || token_market.mint_of_acceptable != *acceptable_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // a live bank may hold payments, it is never replaced
+        if !Self::is_closed(missing_bank_info) {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        Self::create_pda_account_owned_by(
+            program_id,
+            token_program_info.key,
+            fee_payer_info,
+            bank_info,
+            system_program_info,
+            rent_info,
+            Account::LEN,
+            &[BANK_SEED, market_info.key.as_ref()],
+        )?;
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                bank_info.key,
+                acceptable_info.key,
+                &token_market.authority,
+            )?,
+            &[
+                bank_info.clone(),
+                acceptable_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Event::BankRecreated {
+            market: *market_info.key,
+            old: token_market.bank,
+            new: *bank_info.key,
+        }
+        .log();
+        token_market.bank = *bank_info.key;
+        token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [CreateCoupon](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_create_coupon<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        coupon_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        code_hash: [u8; 32],
+        discount_bps: u16,
+        max_uses: u32,
+        expiry: i64,
+    ) -> ProgramResult {
+        let token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        token_market.require_features(TokenMarket::FEATURE_COUPONS)?;
+        if Price::PAR.discounted(discount_bps).is_none() {
+            return Err(TokenMarketError::InvalidDiscount.into());
+        }
+        // reissuing a code would hand out its uses again
+        if !coupon_info.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            coupon_info,
+            system_program_info,
+            rent_info,
+            Coupon::LEN,
+            &[COUPON_SEED, market_info.key.as_ref(), &code_hash],
+        )?;
+        Coupon {
+            market: *market_info.key,
+            code_hash,
+            discount_bps,
+            remaining_uses: max_uses,
+            expiry,
+        }
+        .serialize(&mut &mut coupon_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    /// Process [BuyGift](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_buy_gift<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        write_off_acc_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        acceptable_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        gift_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        payment_info: &AccountInfo<'a>,
+        fee_payer_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+        instructions_info: &AccountInfo<'a>,
+        amount: u64,
+        secret_hash: [u8; 32],
+        expiry: i64,
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        token_market.require_trading()?;
+        Self::require_sale_open(&token_market)?;
+        token_market.require_unphased()?;
+        token_market.require_uncapped()?;
+        token_market.require_open_access()?;
+        token_market.require_untiered()?;
+        token_market.require_features(TokenMarket::FEATURE_GIFTS)?;
+        if token_market.bank != *bank_info.key
+            || token_market.emitter_mint != *emitter_info.key
+            || token_market.mint_of_acceptable != *acceptable_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(bank_info) {
+            return Err(TokenMarketError::BankMissing.into());
+        }
+        if expiry <= Clock::get()?.unix_timestamp {
+            return Err(TokenMarketError::InvalidGiftExpiry.into());
+        }
+        let memo_hash = Self::memo_hash(instructions_info)?;
+        if token_market.require_memo && memo_hash.is_none() {
+            return Err(TokenMarketError::MemoRequired.into());
+        }
+        token_market.check_purchase(amount)?;
+
+        let write_off_acc = Account::unpack(&write_off_acc_info.data.borrow())?;
+        if write_off_acc.mint != token_market.mint_of_acceptable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if write_off_acc.delegate != COption::Some(token_market.authority) {
+            return Err(TokenMarketError::DelegationMismatch.into());
+        }
+        let settlement = settle_purchase_at(
+            token_market.overpayment_policy,
+            Price::PAR,
+            amount,
+            0,
+            write_off_acc.delegated_amount,
+        )?;
+        // a gift refunded at expiry would undo a purchase the escrow
+        // threshold meant to keep disputable
+        if token_market.escrows(settlement.emitted) {
+            return Err(TokenMarketError::EscrowRequired.into());
+        }
+        // the fee waits with the payment, a refund refunds both
+        let fee = token_market.protocol_fee(settlement.proceeds());
+        if write_off_acc.amount < write_off_acc.delegated_amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        if !gift_info.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        Self::create_pda_account(
+            program_id,
+            fee_payer_info,
+            gift_info,
+            system_program_info,
+            rent_info,
+            Gift::LEN,
+            &[GIFT_SEED, market_info.key.as_ref(), &secret_hash],
+        )?;
+        Self::create_pda_account_owned_by(
+            program_id,
+            token_program_info.key,
+            fee_payer_info,
+            vault_info,
+            system_program_info,
+            rent_info,
+            Account::LEN,
+            &[GIFT_VAULT_SEED, gift_info.key.as_ref()],
+        )?;
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                vault_info.key,
+                emitter_info.key,
+                authority_info.key,
+            )?,
+            &[
+                vault_info.clone(),
+                emitter_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        // the payment stays out of the bank until claimed, so
+        // WithdrawBank can't take what an expired gift refunds
+        Self::create_pda_account_owned_by(
+            program_id,
+            token_program_info.key,
+            fee_payer_info,
+            payment_info,
+            system_program_info,
+            rent_info,
+            Account::LEN,
+            &[GIFT_PAYMENT_SEED, gift_info.key.as_ref()],
+        )?;
+        invoke(
+            &initialize_account2(
+                token_program_info.key,
+                payment_info.key,
+                acceptable_info.key,
+                authority_info.key,
+            )?,
+            &[
+                payment_info.clone(),
+                acceptable_info.clone(),
+                rent_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                write_off_acc_info.key,
+                payment_info.key,
+                authority_info.key,
+                &[],
+                settlement.to_bank,
+            )?,
+            &[
+                write_off_acc_info.clone(),
+                payment_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::require_allowance_spent(write_off_acc_info)?;
+        Self::invoke_as_authority(
+            program_id,
+            &mint_to(
+                token_program_info.key,
+                emitter_info.key,
+                vault_info.key,
+                authority_info.key,
+                &[],
+                settlement.emitted,
+            )?,
+            &[
+                emitter_info.clone(),
+                vault_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        if settlement.refund > 0 {
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    payment_info.key,
+                    write_off_acc_info.key,
+                    authority_info.key,
+                    &[],
+                    settlement.refund,
+                )?,
+                &[
+                    payment_info.clone(),
+                    write_off_acc_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+        }
+        if settlement.excess > 0 {
+            Event::Overpayment {
+                market: *market_info.key,
+                policy: token_market.overpayment_policy,
+                excess: settlement.excess,
+            }
+            .log();
+        }
+
+        Gift {
+            market: *market_info.key,
+            write_off: *write_off_acc_info.key,
+            vault: *vault_info.key,
+            paid: settlement.proceeds() - fee,
+            fee,
+            emitted: settlement.emitted,
+            expiry,
+            status: GiftStatus::Pending,
+        }
+        .serialize(&mut &mut gift_info.data.borrow_mut()[..])?;
+
+        Event::GiftPurchased {
+            market: *market_info.key,
+            buyer: write_off_acc.owner,
+            gift: *gift_info.key,
+            amount: settlement.emitted,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Load the pending gift at `gift_info` of `market_info` with `vault_info` and
+    /// `payment_info`
+    fn load_pending_gift(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        gift_info: &AccountInfo,
+        vault_info: &AccountInfo,
+        payment_info: &AccountInfo,
+    ) -> Result<Gift, ProgramError> {
+        if gift_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let gift = Gift::try_from_slice(&gift_info.data.borrow())?;
+        if gift.market != *market_info.key
+            || gift.vault != *vault_info.key
+            || find_gift_payment_address(program_id, gift_info.key).0 != *payment_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if gift.status != GiftStatus::Pending {
+            return Err(TokenMarketError::GiftSettled.into());
+        }
+        Ok(gift)
+    }
+
+    /// Process [ClaimGift](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_claim_gift<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        gift_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        payment_info: &AccountInfo<'a>,
+        recipient_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        secret: [u8; 32],
+        trailing_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        if token_market.bank != *bank_info.key || token_market.authority != *authority_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut gift =
+            Self::load_pending_gift(program_id, market_info, gift_info, vault_info, payment_info)?;
+        // the address commits to the secret hash, so no other secret opens it
+        let secret_hash = gift_secret_hash(&secret);
+        if *gift_info.key != find_gift_address(program_id, market_info.key, &secret_hash).0 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if Clock::get()?.unix_timestamp >= gift.expiry {
+            return Err(TokenMarketError::GiftExpired.into());
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                vault_info.key,
+                recipient_info.key,
+                authority_info.key,
+                &[],
+                gift.emitted,
+            )?,
+            &[
+                vault_info.clone(),
+                recipient_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                payment_info.key,
+                bank_info.key,
+                authority_info.key,
+                &[],
+                gift.paid,
+            )?,
+            &[
+                payment_info.clone(),
+                bank_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        if gift.fee > 0 {
+            let fee_vault_info = trailing_infos
+                .iter()
+                .find(|account_info| *account_info.key == token_market.fee_vault)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            Self::invoke_as_authority(
+                program_id,
+                &transfer(
+                    token_program_info.key,
+                    payment_info.key,
+                    fee_vault_info.key,
+                    authority_info.key,
+                    &[],
+                    gift.fee,
+                )?,
+                &[
+                    payment_info.clone(),
+                    fee_vault_info.clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+            )?;
+            Self::count_protocol_fee(program_id, &token_market.owner, gift.fee, trailing_infos)?;
+            Event::ProtocolFee {
+                market: *market_info.key,
+                fee_vault: *fee_vault_info.key,
+                amount: gift.fee,
+            }
+            .log();
+        }
+
+        gift.status = GiftStatus::Claimed;
+        gift.serialize(&mut &mut gift_info.data.borrow_mut()[..])?;
+        Event::GiftClaimed {
+            market: *market_info.key,
+            gift: *gift_info.key,
+            recipient: *recipient_info.key,
+            amount: gift.emitted,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [RefundGift](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_refund_gift<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        gift_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        payment_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        write_off_acc_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let token_market = Self::load_market(program_id, market_info)?;
+        if token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut gift =
+            Self::load_pending_gift(program_id, market_info, gift_info, vault_info, payment_info)?;
+        if gift.write_off != *write_off_acc_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Clock::get()?.unix_timestamp < gift.expiry {
+            return Err(TokenMarketError::GiftNotExpired.into());
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &burn(
+                token_program_info.key,
+                vault_info.key,
+                emitter_info.key,
+                authority_info.key,
+                &[],
+                gift.emitted,
+            )?,
+            &[
+                vault_info.clone(),
+                emitter_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                payment_info.key,
+                write_off_acc_info.key,
+                authority_info.key,
+                &[],
+                gift.paid + gift.fee,
+            )?,
+            &[
+                payment_info.clone(),
+                write_off_acc_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        gift.status = GiftStatus::Refunded;
+        gift.serialize(&mut &mut gift_info.data.borrow_mut()[..])?;
+        Event::GiftRefunded {
+            market: *market_info.key,
+            gift: *gift_info.key,
+            refund: gift.paid + gift.fee,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [SellTokens](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_sell_tokens<'a>(
+        program_id: &Pubkey,
+        seller_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        holding_info: &AccountInfo<'a>,
+        emitter_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        // a forged market could pair the real bank with a mint its creator controls
+        let token_market = Self::load_market(program_id, market_info)?;
+        token_market.require_trading()?;
+        token_market.require_features(TokenMarket::FEATURE_SELLING)?;
+        // sellers are paid one acceptable token per emitted token
+        TokenMarket::check_selling(token_market.features)?;
+        if !seller_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if token_market.bank != *bank_info.key
+            || token_market.emitter_mint != *emitter_info.key
+            || token_market.authority != *authority_info.key
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(bank_info) {
+            return Err(TokenMarketError::BankMissing.into());
+        }
+        if amount == 0 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let destination = Account::unpack(&destination_info.data.borrow())?;
+        if destination.mint != token_market.mint_of_acceptable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // the token program checks that the seller owns the burned tokens
+        invoke(
+            &burn(
+                token_program_info.key,
+                holding_info.key,
+                emitter_info.key,
+                seller_info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                holding_info.clone(),
+                emitter_info.clone(),
+                seller_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                bank_info.key,
+                destination_info.key,
+                authority_info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                bank_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Event::TokensSold {
+            market: *market_info.key,
+            seller: *seller_info.key,
+            amount,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Process [WithdrawBank](enum.TokenMarketInstructions.html) instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_withdraw_bank<'a>(
+        program_id: &Pubkey,
+        owner_info: &AccountInfo<'a>,
+        market_info: &AccountInfo<'a>,
+        bank_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        // a forged market could name the real bank under another owner
+        let mut token_market = Self::load_owned_market(program_id, market_info, owner_info)?;
+        if token_market.bank != *bank_info.key || token_market.authority != *authority_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if Self::is_closed(bank_info) {
+            return Err(TokenMarketError::BankMissing.into());
+        }
+        if amount == 0 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if token_market.has_treasury() && token_market.treasury != *destination_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let destination = Account::unpack(&destination_info.data.borrow())?;
+        if destination.mint != token_market.mint_of_acceptable {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if token_market.withdraw_rate != 0 {
+            if !market_info.is_writable {
+                return Err(ProgramError::InvalidArgument);
+            }
+            token_market.stream_withdrawal(amount, Clock::get()?.unix_timestamp)?;
+            token_market.serialize(&mut &mut market_info.data.borrow_mut()[..])?;
+        }
+
+        Self::invoke_as_authority(
+            program_id,
+            &transfer(
+                token_program_info.key,
+                bank_info.key,
+                destination_info.key,
+                authority_info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                bank_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+
+        Event::BankWithdrawn {
+            market: *market_info.key,
+            destination: *destination_info.key,
+            amount,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Check that an earlier secp256k1 instruction of the transaction carries
+    /// the voucher signer's signature of the voucher covering this purchase
+    pub fn verify_voucher(
+        market_info: &AccountInfo,
+        write_off_acc_info: &AccountInfo,
+        instructions_info: &AccountInfo,
+        amount: u64,
+        max_amount: u64,
+        expiry: i64,
+    ) -> ProgramResult {
+        if *instructions_info.key != instructions::id() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let token_market = TokenMarket::try_from_slice(&market_info.data.borrow())?;
+        token_market.require_features(TokenMarket::FEATURE_VOUCHERS)?;
+        if !token_market.accepts_vouchers() || amount > max_amount {
+            return Err(TokenMarketError::InvalidVoucher.into());
+        }
+        if Clock::get()?.unix_timestamp > expiry {
+            return Err(TokenMarketError::VoucherExpired.into());
+        }
+
+        let voucher = PurchaseVoucher {
+            market: *market_info.key,
+            buyer: Account::unpack(&write_off_acc_info.data.borrow())?.owner,
+            max_amount,
+            expiry,
+        }
+        .try_to_vec()?;
+
+        if Self::signed_earlier(instructions_info, &token_market.voucher_signer, &voucher)? {
+            Ok(())
+        } else {
+            Err(TokenMarketError::InvalidVoucher.into())
+        }
+    }
+
+    /// Check that an earlier secp256k1 instruction of the transaction carries
+    /// the voucher signer's signature of `quote`, which covers this purchase
+    pub fn verify_quote(
+        market_info: &AccountInfo,
+        instructions_info: &AccountInfo,
+        amount: u64,
+        quote: &PriceQuote,
+    ) -> ProgramResult {
+        if *instructions_info.key != instructions::id() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let token_market = TokenMarket::try_from_slice(&market_info.data.borrow())?;
+        token_market.require_features(TokenMarket::FEATURE_PRICE_QUOTES)?;
+        if !token_market.accepts_vouchers() || amount > quote.max_amount || quote.denominator == 0 {
+            return Err(TokenMarketError::InvalidQuote.into());
+        }
+        if Clock::get()?.unix_timestamp > quote.expiry {
+            return Err(TokenMarketError::QuoteExpired.into());
+        }
+
+        if Self::signed_earlier(
+            instructions_info,
+            &token_market.voucher_signer,
+            &quote.try_to_vec()?,
+        )? {
+            Ok(())
+        } else {
+            Err(TokenMarketError::InvalidQuote.into())
+        }
+    }
+
+    /// Check that a guarded market's purchase is the only instruction of the
+    /// transaction on the market and that no exchange is traded on around it
+    pub fn verify_isolated(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        instructions_info: &AccountInfo,
+    ) -> ProgramResult {
+        let token_market = TokenMarket::try_from_slice(&market_info.data.borrow())?;
+        if !token_market.sandwich_guard {
+            return Ok(());
+        }
+        if *instructions_info.key != instructions::id() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let data = instructions_info.data.borrow();
+        let current = instructions::load_current_index(&data) as usize;
+        let mut index = 0;
+        while let Ok(instruction) = instructions::load_instruction_at(index, &data) {
+            let on_market = instruction.program_id == *program_id
+                && instruction
+                    .accounts
+                    .iter()
+                    .any(|meta| meta.pubkey == *market_info.key);
+            if index != current && (on_market || dex::is_dex_program(&instruction.program_id)) {
+                return Err(TokenMarketError::TransactionNotIsolated.into());
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+
+    /// The coupon among `account_infos`, if the client passed one. Only the
+    /// program owns accounts of its length, the rest is checked on redemption.
+    fn find_coupon<'b, 'a>(
+        program_id: &Pubkey,
+        account_infos: &'b [AccountInfo<'a>],
+    ) -> Option<&'b AccountInfo<'a>> {
+        account_infos.iter().find(|account_info| {
+            account_info.owner == program_id && account_info.data_len() == Coupon::LEN
+        })
+    }
+
+    /// The [SalePhases](../state/struct.SalePhases.html) account among the
+    /// optional accounts of a purchase, recognized by its size
+    fn find_sale_phases<'b, 'a>(
+        program_id: &Pubkey,
+        account_infos: &'b [AccountInfo<'a>],
+    ) -> Option<&'b AccountInfo<'a>> {
+        account_infos.iter().find(|account_info| {
+            account_info.owner == program_id && account_info.data_len() == SalePhases::LEN
+        })
+    }
+
+    /// Count `fee` in the [TenantStats](../state/struct.TenantStats.html) of
+    /// `owner` when they are among `account_infos`. Markets created without
+    /// tenant stats have none, so missing stats are no error.
+    fn count_protocol_fee(
+        program_id: &Pubkey,
+        owner: &Pubkey,
+        fee: u64,
+        account_infos: &[AccountInfo],
+    ) -> ProgramResult {
+        let tenant_info = match account_infos.iter().find(|account_info| {
+            account_info.owner == program_id && account_info.data_len() == TenantStats::LEN
+        }) {
+            Some(tenant_info) => tenant_info,
+            None => return Ok(()),
+        };
+        if *tenant_info.key != find_tenant_address(program_id, owner).0 {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let mut stats = TenantStats::try_from_slice(&tenant_info.data.borrow())?;
+        stats.protocol_fees = stats.protocol_fees.saturating_add(fee);
+        stats.serialize(&mut &mut tenant_info.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    /// Take the protocol fee of a purchase of `paid` acceptable tokens paid
+    /// off chain, from the associated token account of `payer_info`, which
+    /// signs for it. No payment reaches the bank to take the fee from.
+    fn take_external_protocol_fee<'a>(
+        program_id: &Pubkey,
+        market_info: &AccountInfo<'a>,
+        token_market: &TokenMarket,
+        payer_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        paid: u64,
+        account_infos: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let fee = token_market.protocol_fee(paid);
+        if token_market.fee_bps == 0 || fee == 0 {
+            return Ok(());
+        }
+        let source =
+            find_associated_token_address(payer_info.key, &token_market.mint_of_acceptable);
+        let find = |address: &Pubkey| {
+            account_infos
+                .iter()
+                .find(|account_info| account_info.key == address)
+                .ok_or(ProgramError::NotEnoughAccountKeys)
+        };
+        let fee_vault_info = find(&token_market.fee_vault)?;
+        let source_info = find(&source)?;
+
+        invoke(
+            &transfer(
+                token_program_info.key,
+                source_info.key,
+                fee_vault_info.key,
+                payer_info.key,
+                &[],
+                fee,
+            )?,
+            &[
+                source_info.clone(),
+                fee_vault_info.clone(),
+                payer_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        Self::count_protocol_fee(program_id, &token_market.owner, fee, account_infos)?;
+        Event::ProtocolFee {
+            market: *market_info.key,
+            fee_vault: *fee_vault_info.key,
+            amount: fee,
+        }
+        .log();
+        Ok(())
+    }
+
+    /// Load the sale phases at `phases_info` of `market_info`
+    fn load_sale_phases(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        phases_info: &AccountInfo,
+    ) -> Result<SalePhases, ProgramError> {
+        if phases_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if *phases_info.key != find_sale_phases_address(program_id, market_info.key).0 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let phases = SalePhases::load(&phases_info.data.borrow())?;
+        if phases.market != *market_info.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(phases)
+    }
+
+    /// Load the coupon at `coupon_info` of `market_info`, failing unless it
+    /// can be redeemed now
+    fn load_redeemable_coupon(
+        program_id: &Pubkey,
+        market_info: &AccountInfo,
+        coupon_info: &AccountInfo,
+    ) -> Result<Coupon, ProgramError> {
+        if coupon_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let coupon = Coupon::try_from_slice(&coupon_info.data.borrow())?;
+        if coupon.market != *market_info.key
+            || *coupon_info.key
+                != find_coupon_address(program_id, market_info.key, &coupon.code_hash).0
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if coupon.expired(Clock::get()?.unix_timestamp) {
+            return Err(TokenMarketError::CouponExpired.into());
+        }
+        if coupon.remaining_uses == 0 {
+            return Err(TokenMarketError::CouponExhausted.into());
+        }
+        Ok(coupon)
+    }
+
+    /// The instructions sysvar among `account_infos`, if the client passed it
+    fn find_instructions_sysvar<'b, 'a>(
+        account_infos: &'b [AccountInfo<'a>],
+    ) -> Option<&'b AccountInfo<'a>> {
+        account_infos
+            .iter()
+            .find(|account_info| *account_info.key == instructions::id())
+    }
+
+    /// Hash of the data of the first SPL memo instruction of the transaction,
+    /// `None` if it has no memo
+    fn memo_hash(instructions_info: &AccountInfo) -> Result<Option<Hash>, ProgramError> {
+        if *instructions_info.key != instructions::id() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let data = instructions_info.data.borrow();
+        // loading fails past the last instruction
+        let mut index = 0;
+        while let Ok(instruction) = instructions::load_instruction_at(index, &data) {
+            if memo::is_memo_program(&instruction.program_id) {
+                return Ok(Some(hash(&instruction.data)));
+            }
+            index += 1;
+        }
+        Ok(None)
+    }
+
+    /// Whether a secp256k1 instruction before the current one verifies a
+    /// signature of `eth_address` over `message`
+    fn signed_earlier(
+        instructions_info: &AccountInfo,
+        eth_address: &[u8; 20],
+        message: &[u8],
+    ) -> Result<bool, ProgramError> {
+        let data = instructions_info.data.borrow();
+        let current = instructions::load_current_index(&data) as usize;
+        for index in 0..current {
+            let instruction = instructions::load_instruction_at(index, &data)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            if instruction.program_id == secp256k1_program::id()
+                && Self::secp256k1_signs(&instruction.data, index as u8, eth_address, message)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether the secp256k1 program instruction at `index` with `data` verifies
+    /// a signature of `eth_address` over `message`. Only single-signature
+    /// instructions keeping the address and the message inline are recognized.
+    fn secp256k1_signs(data: &[u8], index: u8, eth_address: &[u8; 20], message: &[u8]) -> bool {
+        // count followed by the offsets of the only signature
+        if data.len() < 12 || data[0] != 1 {
+            return false;
+        }
+        let read_u16 =
+            |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]) as usize;
+        let (eth_address_offset, message_offset, message_size) =
+            (read_u16(4), read_u16(7), read_u16(9));
+        if data[3] != index || data[6] != index || data[11] != index {
+            return false;
+        }
+        data.get(eth_address_offset..eth_address_offset + 20) == Some(&eth_address[..])
+            && data.get(message_offset..message_offset + message_size) == Some(message)
     }
 }
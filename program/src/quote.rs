@@ -0,0 +1,106 @@
+//! Quoting the full cost of a purchase before sending it
+//!
+//! A purchase costs acceptable tokens, the price and any relayer tip, and
+//! lamports, the rent of the accounts it creates and the network fee. A
+//! [Quote](struct.Quote.html) breaks both down so integrators can show
+//! users everything they pay, not only the price.
+
+use crate::{
+    amounts::{AcceptableAmount, EmittedAmount},
+    state::{CampaignStats, PurchaseReceipt},
+};
+use solana_program::{program_pack::Pack, rent::Rent};
+use spl_token::state::{Account, Mint};
+
+/// What a purchase does besides paying and emitting tokens
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct PurchaseSetup {
+    /// The recipient token account doesn't exist yet and the fee payer creates it
+    pub create_recipient: bool,
+    /// The market mints a receipt on every purchase
+    pub mint_receipt: bool,
+    /// The purchase is the first sale of its campaign and creates the campaign stats
+    pub open_campaign: bool,
+    /// The buyer signs in addition to the fee payer, as in relayed purchases
+    pub buyer_signs: bool,
+}
+
+/// Cost breakdown of a purchase
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct Quote {
+    /// Emitted tokens the recipient receives
+    pub emitted: EmittedAmount,
+    /// Acceptable tokens paid into the bank
+    pub price: AcceptableAmount,
+    /// Acceptable tokens paid to the relayer
+    pub relayer_tip: AcceptableAmount,
+    /// Lamports of the recipient token account, if it is created
+    pub recipient_rent: u64,
+    /// Lamports of the receipt mint, holding and record, if a receipt is minted
+    pub receipt_rent: u64,
+    /// Lamports of the campaign stats, if the campaign has no sale yet
+    pub campaign_rent: u64,
+    /// Lamports of the transaction signatures
+    pub network_fee: u64,
+}
+
+impl Quote {
+    /// All acceptable tokens the buyer delegates, `None` on overflow
+    pub fn total_tokens(&self) -> Option<AcceptableAmount> {
+        self.price
+            .0
+            .checked_add(self.relayer_tip.0)
+            .map(AcceptableAmount)
+    }
+
+    /// All lamports the fee payer spends, `None` on overflow
+    pub fn total_lamports(&self) -> Option<u64> {
+        self.recipient_rent
+            .checked_add(self.receipt_rent)?
+            .checked_add(self.campaign_rent)?
+            .checked_add(self.network_fee)
+    }
+}
+
+/// Quote buying `amount` with a relayer `tip` under `rent` and the cluster
+/// `lamports_per_signature`
+pub fn quote_purchase(
+    amount: AcceptableAmount,
+    tip: AcceptableAmount,
+    setup: PurchaseSetup,
+    rent: &Rent,
+    lamports_per_signature: u64,
+) -> Quote {
+    let rent_of = |created: bool, lens: &[usize]| {
+        if created {
+            lens.iter().map(|len| rent.minimum_balance(*len)).sum()
+        } else {
+            0
+        }
+    };
+    // The fee payer, the buyer and the two new receipt accounts
+    let signatures = 1 + setup.buyer_signs as u64 + 2 * setup.mint_receipt as u64;
+
+    Quote {
+        emitted: EmittedAmount(amount.0),
+        price: amount,
+        relayer_tip: tip,
+        recipient_rent: rent_of(setup.create_recipient, &[Account::LEN]),
+        receipt_rent: rent_of(
+            setup.mint_receipt,
+            &[Mint::LEN, Account::LEN, PurchaseReceipt::LEN],
+        ),
+        campaign_rent: rent_of(setup.open_campaign, &[CampaignStats::LEN]),
+        network_fee: signatures * lamports_per_signature,
+    }
+}
@@ -0,0 +1,73 @@
+//! Off-chain replay of hypothetical purchases
+//!
+//! Issuers model a market before creating it by replaying purchases
+//! through [settle_purchase](../pricing/fn.settle_purchase.html). Markets
+//! sell at one to one, so the price a buyer effectively pays only moves
+//! when donated overpayments are kept.
+
+use crate::{
+    pricing::{settle_purchase, Settlement},
+    state::OverpaymentPolicy,
+};
+use solana_program::program_error::ProgramError;
+
+/// Hypothetical purchase, raw amounts of the acceptable mint
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Purchase {
+    pub amount: u64,
+    pub tip: u64,
+    /// Delegation the write-off account grants the market authority
+    pub delegated: u64,
+}
+
+/// Outcome of one replayed purchase, with the market state after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub purchase: Purchase,
+    /// Rejected purchases leave the market unchanged
+    pub settlement: Result<Settlement, ProgramError>,
+    /// Emitted supply after the purchase
+    pub supply: u64,
+    /// Acceptable tokens in the bank after the purchase
+    pub proceeds: u64,
+}
+
+impl Step {
+    /// Acceptable tokens paid per emitted token, `None` if the purchase
+    /// was rejected or emitted nothing
+    pub fn effective_price(&self) -> Option<f64> {
+        match &self.settlement {
+            Ok(settlement) if settlement.emitted > 0 => {
+                Some(settlement.paid() as f64 / settlement.emitted as f64)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Replay `purchases` in order on a market with `policy` that has already
+/// emitted `supply` tokens and holds `proceeds`
+pub fn simulate(
+    policy: OverpaymentPolicy,
+    mut supply: u64,
+    mut proceeds: u64,
+    purchases: &[Purchase],
+) -> Vec<Step> {
+    purchases
+        .iter()
+        .map(|purchase| {
+            let settlement =
+                settle_purchase(policy, purchase.amount, purchase.tip, purchase.delegated);
+            if let Ok(settlement) = &settlement {
+                supply = supply.saturating_add(settlement.emitted);
+                proceeds = proceeds.saturating_add(settlement.proceeds());
+            }
+            Step {
+                purchase: *purchase,
+                settlement,
+                supply,
+                proceeds,
+            }
+        })
+        .collect()
+}
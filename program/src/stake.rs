@@ -0,0 +1,93 @@
+//! Stakes raising the wallet cap of buyers
+//!
+//! A market with a [StakeWeight](../state/struct.StakeWeight.html) adds part
+//! of what a buyer stakes to its wallet cap: pool tokens of an SPL stake pool
+//! held in a token account of the buyer, or governing tokens the buyer
+//! deposited in an SPL governance realm, as its token owner record shows.
+//! BuyTokens reads the stake from the first trailing account holding one of
+//! the buyer, see [with_stake](../instruction/fn.with_stake.html). Stakes are
+//! read as they are at the purchase.
+
+use crate::state::{StakeSource, StakeWeight};
+use solana_program::{account_info::AccountInfo, program_pack::Pack, pubkey::Pubkey};
+use spl_token::state::Account;
+use std::convert::TryInto;
+
+/// Head of an SPL governance token owner record, the deposit of one owner in a realm
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenOwnerRecord {
+    pub account_type: u8,
+    pub realm: Pubkey,
+    pub governing_token_mint: Pubkey,
+    pub governing_token_owner: Pubkey,
+    pub governing_token_deposit_amount: u64,
+}
+
+impl TokenOwnerRecord {
+    pub const LEN: usize = 1 + 32 * 3 + 8;
+    /// Account types of version 1 and version 2 token owner records
+    pub const ACCOUNT_TYPES: [u8; 2] = [2, 17];
+
+    pub fn unpack(data: &[u8]) -> Option<Self> {
+        if data.len() < Self::LEN || !Self::ACCOUNT_TYPES.contains(&data[0]) {
+            return None;
+        }
+        Some(TokenOwnerRecord {
+            account_type: data[0],
+            realm: Pubkey::new(&data[1..33]),
+            governing_token_mint: Pubkey::new(&data[33..65]),
+            governing_token_owner: Pubkey::new(&data[65..97]),
+            governing_token_deposit_amount: u64::from_le_bytes(
+                data[97..Self::LEN].try_into().ok()?,
+            ),
+        })
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(Self::LEN);
+        data.push(self.account_type);
+        data.extend_from_slice(self.realm.as_ref());
+        data.extend_from_slice(self.governing_token_mint.as_ref());
+        data.extend_from_slice(self.governing_token_owner.as_ref());
+        data.extend_from_slice(&self.governing_token_deposit_amount.to_le_bytes());
+        data
+    }
+}
+
+/// Tokens `buyer` stakes in `account_info` as `weight` reads them, `None`
+/// if the account holds no stake of the buyer
+pub fn staked(weight: &StakeWeight, buyer: &Pubkey, account_info: &AccountInfo) -> Option<u64> {
+    match weight.source {
+        StakeSource::Off => None,
+        StakeSource::PoolTokens => {
+            if *account_info.owner != spl_token::id() {
+                return None;
+            }
+            let account = Account::unpack(&account_info.data.borrow()).ok()?;
+            if account.mint != weight.mint || account.owner != *buyer {
+                return None;
+            }
+            Some(account.amount)
+        }
+        StakeSource::GovernanceDeposit => {
+            if *account_info.owner != weight.governance_program {
+                return None;
+            }
+            let record = TokenOwnerRecord::unpack(&account_info.data.borrow())?;
+            if record.governing_token_mint != weight.mint || record.governing_token_owner != *buyer
+            {
+                return None;
+            }
+            Some(record.governing_token_deposit_amount)
+        }
+    }
+}
+
+/// Tokens `buyer` stakes as `weight` reads them, from the first account of
+/// `account_infos` holding a stake of the buyer, 0 without one
+pub fn find_stake(weight: &StakeWeight, buyer: &Pubkey, account_infos: &[AccountInfo]) -> u64 {
+    account_infos
+        .iter()
+        .find_map(|account_info| staked(weight, buyer, account_info))
+        .unwrap_or(0)
+}
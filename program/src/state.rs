@@ -1,9 +1,80 @@
 //! State transition types
 
+use crate::error::TokenMarketError;
+use crate::pricing::Price;
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{program_pack::IsInitialized, pubkey::Pubkey};
+use std::convert::TryFrom;
 
+/// Seed of the PDA that owns the bank and holds the emitter mint authority
+pub const AUTHORITY_SEED: &[u8] = b"token-market";
+
+/// Find the market authority address and its bump seed
+pub fn find_authority_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[AUTHORITY_SEED], program_id)
+}
+
+/// Seed prefix of the [MintIndex](struct.MintIndex.html) PDA of an emitter mint
+pub const MINT_INDEX_SEED: &[u8] = b"mint-index";
+
+/// Find the mint index address of `emitter_mint` and its bump seed
+pub fn find_mint_index_address(program_id: &Pubkey, emitter_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MINT_INDEX_SEED, emitter_mint.as_ref()], program_id)
+}
+
+/// Seed prefix of the bank PDA that [RecreateBank](../instruction/enum.TokenMarketInstructions.html#variant.RecreateBank)
+/// creates in place of a missing bank
+pub const BANK_SEED: &[u8] = b"bank";
+
+/// Find the recreated bank address of `market` and its bump seed
+pub fn find_bank_address(program_id: &Pubkey, market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BANK_SEED, market.as_ref()], program_id)
+}
+
+/// Seed prefix of the token accounts collecting the protocol fees of a market
+pub const FEE_VAULT_SEED: &[u8] = b"fee-vault";
+
+/// Find the address of the token account of `mint` collecting the protocol
+/// fees of `market` and its bump seed. A market that migrated its acceptable
+/// mint gets a new vault, the old one can still be withdrawn from.
+pub fn find_fee_vault_address(program_id: &Pubkey, market: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[FEE_VAULT_SEED, market.as_ref(), mint.as_ref()],
+        program_id,
+    )
+}
+
+/// Seed prefix of the wrapped SOL account a native SOL purchase pays through
+pub const WRAP_SEED: &[u8] = b"wrap";
+
+/// Find the wrapped SOL account the lamports of `buyer` pass through on a
+/// native SOL purchase and its bump seed. It only lives for the instruction.
+pub fn find_wrap_address(program_id: &Pubkey, buyer: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[WRAP_SEED, buyer.as_ref()], program_id)
+}
+
+/// SPL Associated Token Account program, whose accounts receive cranked vestings
+pub mod associated_token {
+    solana_program::declare_id!("ATokenGPvbdGVxr1b2hvZbsiqW5xUHi9pPcZbu9JpVfR");
+}
+
+/// Find the associated token account of `wallet` for `mint`
+pub fn find_associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), spl_token::id().as_ref(), mint.as_ref()],
+        &associated_token::id(),
+    )
+    .0
+}
+
+/// Market account. Fields are laid out at fixed offsets, so markets can be
+/// found by `getProgramAccounts` memcmp filters on them, see the `*_OFFSET` constants.
 #[derive(BorshSerialize, BorshDeserialize)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
 pub struct TokenMarket {
     pub is_initialized: bool,
     pub owner: Pubkey,
@@ -11,10 +82,1030 @@ pub struct TokenMarket {
     pub emitter_mint: Pubkey,
     pub authority: Pubkey,
     pub mint_of_acceptable: Pubkey,
+    /// Bank of the acceptable mint used before the last migration,
+    /// `Pubkey::default()` if the market was never migrated
+    pub legacy_bank: Pubkey,
+    /// Acceptable mint used before the last migration
+    pub legacy_mint_of_acceptable: Pubkey,
+    /// Ethereum address whose secp256k1 signatures authorize
+    /// [purchase vouchers](struct.PurchaseVoucher.html), zero if vouchers are disabled
+    pub voucher_signer: [u8; 20],
+    /// Free-form market type chosen by the creator, e.g. `*b"IDO\0"`
+    pub category: [u8; 4],
+    /// Identifier of the project the market sells for, chosen by the creator
+    pub project_id: [u8; 32],
+    /// Token account of the acceptable mint that receives withdrawn payments,
+    /// `Pubkey::default()` until the owner sets one
+    pub treasury: Pubkey,
+    /// Set by the owner when the market is abandoned, see
+    /// [DeprecationNotice](struct.DeprecationNotice.html)
+    pub deprecated: bool,
+    /// Market that replaces this one, `Pubkey::default()` if there is none
+    pub successor: Pubkey,
+    /// What purchases do with a delegation larger than their cost
+    pub overpayment_policy: OverpaymentPolicy,
+    /// Whether purchases mint a [receipt](struct.PurchaseReceipt.html) NFT to the buyer
+    pub mint_receipts: bool,
+    /// Optional functionality the market supports, `FEATURE_*` bits chosen at Initialize
+    pub features: u64,
+    /// Whether every purchase transaction must carry an SPL [memo](../memo/index.html)
+    pub require_memo: bool,
+    /// Whether quoted purchases must be the only instructions on the market
+    /// in their transaction and come without exchange instructions
+    pub sandwich_guard: bool,
+    /// Wormhole chain id of the [emitter](#structfield.wormhole_emitter)
+    pub wormhole_chain: u16,
+    /// Emitter address whose Wormhole messages attest purchases paid on
+    /// `wormhole_chain`, zero if cross-chain purchases are disabled
+    pub wormhole_emitter: [u8; 32],
+    /// Key of the fiat on-ramp provider that co-signs card purchases,
+    /// `Pubkey::default()` if fiat purchases are disabled
+    pub onramp_signer: Pubkey,
+    /// Emitted amount from which purchases are held in a
+    /// [PurchaseEscrow](struct.PurchaseEscrow.html), zero if nothing is escrowed
+    pub escrow_threshold: u64,
+    /// Seconds an escrowed purchase can be disputed before it may be released
+    pub dispute_window: i64,
+    /// Key that may dispute escrowed purchases besides the owner,
+    /// `Pubkey::default()` if only the owner may
+    pub dispute_guardian: Pubkey,
+    /// Set by the owner to halt purchases and sales during an incident
+    pub paused: bool,
+    /// Whether purchases follow the [SalePhases](struct.SalePhases.html) of the market
+    pub phased: bool,
+    /// Basis points of the payment of every purchase that go to `fee_vault`
+    pub fee_bps: u16,
+    /// Token account of the acceptable mint collecting protocol fees,
+    /// `Pubkey::default()` until the owner sets a fee
+    pub fee_vault: Pubkey,
+    /// Fewest acceptable tokens a single purchase may pay
+    pub min_purchase: u64,
+    /// Most acceptable tokens a single purchase may pay, no limit if 0
+    pub max_purchase: u64,
+    /// Emitted tokens a single buyer may purchase in total, counted in its
+    /// [BuyerRecord](struct.BuyerRecord.html), no cap if 0
+    pub wallet_cap: u64,
+    /// Unix timestamp purchases are accepted from, 0 if they always were
+    pub sale_start: i64,
+    /// Unix timestamp purchases are accepted until, 0 if the sale never ends
+    pub sale_end: i64,
+    /// Whether only buyers with an [AllowlistEntry](struct.AllowlistEntry.html)
+    /// may purchase
+    pub allowlist_enabled: bool,
+    /// Root of a [Merkle tree](../merkle/index.html) of further allowlisted
+    /// buyers, who prove membership instead of holding an entry. All zeros
+    /// for none.
+    pub allowlist_root: [u8; 32],
+    /// Prices by amount sold, set at Initialize, unused tiers are zeroed
+    pub pricing_tiers: [PricingTier; TokenMarket::MAX_PRICING_TIERS],
+    /// Emitted tokens sold by purchases on a tiered or bonding curve market
+    pub total_sold: u64,
+    /// Price as a function of `total_sold`, set at Initialize
+    pub bonding_curve: BondingCurve,
+    /// Stake that raises the wallet cap of its buyers, off if unset
+    pub stake_weight: StakeWeight,
+    /// Acceptable tokens a day WithdrawBank releases from the bank, by the
+    /// second since `last_withdraw` and up to a day's worth, no limit if 0
+    pub withdraw_rate: u64,
+    /// Unix timestamp the withdraw stream released everything until
+    pub last_withdraw: i64,
+    /// Acceptable tokens a single buyer may pay for purchases in total,
+    /// counted in its [BuyerRecord](struct.BuyerRecord.html), no cap if 0
+    pub wallet_spend_cap: u64,
+    /// Seconds a buyer waits after a purchase before its next one, counted
+    /// from its [BuyerRecord](struct.BuyerRecord.html), no wait if 0
+    pub purchase_cooldown_secs: i64,
+    /// Discounts buyers get once their [BuyerRecord](struct.BuyerRecord.html)
+    /// shows enough emitted tokens purchased, in order, unused ones zeroed
+    pub loyalty_tiers: [LoyaltyTier; TokenMarket::MAX_LOYALTY_TIERS],
+}
+
+/// Handling of the part of a write-off delegation that exceeds the cost of a purchase
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub enum OverpaymentPolicy {
+    /// Fail the purchase unless exactly the cost is delegated
+    Reject,
+    /// Keep the excess in the bank without emitting tokens for it
+    Donate,
+    /// Return the excess to the write-off account
+    Refund,
 }
 
 impl TokenMarket {
-    pub const LEN: usize = 32 * 5 + 1;
+    pub const LEN: usize = 32 * 7
+        + 1
+        + 20
+        + 4
+        + 32
+        + 32
+        + 1
+        + 32
+        + 1
+        + 1
+        + 8
+        + 1
+        + 1
+        + 2
+        + 32
+        + 32
+        + 8
+        + 8
+        + 32
+        + 1
+        + 1
+        + 2
+        + 32
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 32
+        + PricingTier::LEN * TokenMarket::MAX_PRICING_TIERS
+        + 8
+        + BondingCurve::LEN
+        + StakeWeight::LEN
+        + 8
+        + 8
+        + 8
+        + 8
+        + LoyaltyTier::LEN * TokenMarket::MAX_LOYALTY_TIERS;
+    /// Most tiers a market prices by
+    pub const MAX_PRICING_TIERS: usize = 4;
+    /// Most loyalty tiers a market discounts by
+    pub const MAX_LOYALTY_TIERS: usize = 4;
+    /// Offset of `owner` in the account data
+    pub const OWNER_OFFSET: usize = 1;
+    /// Offset of `category` in the account data
+    pub const CATEGORY_OFFSET: usize = 1 + 32 * 7 + 20;
+    /// Offset of `project_id` in the account data
+    pub const PROJECT_ID_OFFSET: usize = Self::CATEGORY_OFFSET + 4;
+    /// Offset of `emitter_mint` in the account data
+    pub const EMITTER_MINT_OFFSET: usize = Self::OWNER_OFFSET + 32 * 2;
+    /// Offset of `mint_of_acceptable` in the account data
+    pub const MINT_OF_ACCEPTABLE_OFFSET: usize = Self::OWNER_OFFSET + 32 * 4;
+    /// Offset of `features` in the account data
+    pub const FEATURES_OFFSET: usize = Self::PROJECT_ID_OFFSET + 32 * 2 + 1 + 32 + 1 + 1;
+    /// Offset of `paused` in the account data
+    pub const PAUSED_OFFSET: usize = Self::FEATURES_OFFSET + 8 + 1 + 1 + 2 + 32 * 2 + 8 * 2 + 32;
+    /// Offset of `fee_bps` in the account data
+    pub const FEE_BPS_OFFSET: usize = Self::PAUSED_OFFSET + 2;
+    /// Offset of `total_sold` in the account data
+    pub const TOTAL_SOLD_OFFSET: usize = Self::FEE_BPS_OFFSET
+        + 2
+        + 32
+        + 8 * 5
+        + 1
+        + 32
+        + PricingTier::LEN * TokenMarket::MAX_PRICING_TIERS;
+
+    /// Purchases may pay a relayer tip
+    pub const FEATURE_RELAYER_TIPS: u64 = 1 << 0;
+    /// The owner may lock emitted tokens in vestings
+    pub const FEATURE_VESTING: u64 = 1 << 1;
+    /// Purchases may be authorized by vouchers of the voucher signer
+    pub const FEATURE_VOUCHERS: u64 = 1 << 2;
+    /// Purchases may settle at prices quoted by the voucher signer
+    pub const FEATURE_PRICE_QUOTES: u64 = 1 << 3;
+    /// The owner may make purchases mint receipts
+    pub const FEATURE_RECEIPTS: u64 = 1 << 4;
+    /// Purchases may be attributed to campaigns
+    pub const FEATURE_CAMPAIGNS: u64 = 1 << 5;
+    /// The owner may delegate owner instructions to other keys
+    pub const FEATURE_DELEGATION: u64 = 1 << 6;
+    /// Purchases paid on other chains may be redeemed from Wormhole messages
+    pub const FEATURE_WORMHOLE: u64 = 1 << 7;
+    /// Purchases settled in fiat may be attested by an on-ramp provider
+    pub const FEATURE_FIAT_ONRAMP: u64 = 1 << 8;
+    /// Large purchases may be held in escrow while they can be disputed
+    pub const FEATURE_DISPUTE_ESCROW: u64 = 1 << 9;
+    /// The owner may issue discount coupons redeemable in purchases
+    pub const FEATURE_COUPONS: u64 = 1 << 10;
+    /// Purchases may be gifted, held until claimed with a secret
+    pub const FEATURE_GIFTS: u64 = 1 << 11;
+    /// Holders may sell emitted tokens back for acceptable tokens from the bank
+    pub const FEATURE_SELLING: u64 = 1 << 12;
+    /// The owner may sell in phases with their own prices, caps and allowlist
+    pub const FEATURE_SALE_PHASES: u64 = 1 << 13;
+    /// The owner may take a protocol fee out of every purchase
+    pub const FEATURE_PROTOCOL_FEE: u64 = 1 << 14;
+    /// The owner may cap what a single buyer purchases in total
+    pub const FEATURE_WALLET_CAPS: u64 = 1 << 15;
+    /// The owner may schedule when the market sells
+    pub const FEATURE_SALE_WINDOW: u64 = 1 << 16;
+    /// The owner may restrict purchases to allowlisted buyers
+    pub const FEATURE_ALLOWLIST: u64 = 1 << 17;
+    /// Purchases are priced by tiers of the amount sold so far
+    pub const FEATURE_TIERED_PRICING: u64 = 1 << 18;
+    /// Purchases are priced by a bonding curve of the amount sold so far
+    pub const FEATURE_BONDING_CURVE: u64 = 1 << 19;
+    /// The owner may raise wallet caps by what buyers stake
+    pub const FEATURE_STAKE_WEIGHT: u64 = 1 << 20;
+    /// The owner may stream withdrawals from the bank at a capped rate
+    pub const FEATURE_WITHDRAW_STREAM: u64 = 1 << 21;
+    /// Buyers wait a cooldown between their purchases
+    pub const FEATURE_PURCHASE_COOLDOWN: u64 = 1 << 22;
+    /// The owner may discount purchases of buyers who bought enough before
+    pub const FEATURE_LOYALTY_TIERS: u64 = 1 << 23;
+    /// Every feature this program version knows
+    pub const ALL_FEATURES: u64 = Self::FEATURE_RELAYER_TIPS
+        | Self::FEATURE_VESTING
+        | Self::FEATURE_VOUCHERS
+        | Self::FEATURE_PRICE_QUOTES
+        | Self::FEATURE_RECEIPTS
+        | Self::FEATURE_CAMPAIGNS
+        | Self::FEATURE_DELEGATION
+        | Self::FEATURE_WORMHOLE
+        | Self::FEATURE_FIAT_ONRAMP
+        | Self::FEATURE_DISPUTE_ESCROW
+        | Self::FEATURE_COUPONS
+        | Self::FEATURE_GIFTS
+        | Self::FEATURE_SELLING
+        | Self::FEATURE_SALE_PHASES
+        | Self::FEATURE_PROTOCOL_FEE
+        | Self::FEATURE_WALLET_CAPS
+        | Self::FEATURE_SALE_WINDOW
+        | Self::FEATURE_ALLOWLIST
+        | Self::FEATURE_TIERED_PRICING
+        | Self::FEATURE_BONDING_CURVE
+        | Self::FEATURE_STAKE_WEIGHT
+        | Self::FEATURE_WITHDRAW_STREAM
+        | Self::FEATURE_PURCHASE_COOLDOWN
+        | Self::FEATURE_LOYALTY_TIERS;
+    /// Features Initialize enables when the client doesn't choose, so older
+    /// clients keep getting full markets. Selling has to be chosen.
+    pub const DEFAULT_FEATURES: u64 = Self::ALL_FEATURES & !Self::FEATURE_SELLING;
+    /// Features under which every emitted token was paid at par into the
+    /// bank, the only ones selling may be combined with
+    pub const SELLABLE_FEATURES: u64 = Self::FEATURE_RELAYER_TIPS
+        | Self::FEATURE_VOUCHERS
+        | Self::FEATURE_RECEIPTS
+        | Self::FEATURE_CAMPAIGNS
+        | Self::FEATURE_DELEGATION
+        | Self::FEATURE_DISPUTE_ESCROW
+        | Self::FEATURE_GIFTS
+        | Self::FEATURE_SELLING;
+    /// Names of the feature bits for clients
+    pub const FEATURE_NAMES: &'static [(&'static str, u64)] = &[
+        ("relayer-tips", Self::FEATURE_RELAYER_TIPS),
+        ("vesting", Self::FEATURE_VESTING),
+        ("vouchers", Self::FEATURE_VOUCHERS),
+        ("price-quotes", Self::FEATURE_PRICE_QUOTES),
+        ("receipts", Self::FEATURE_RECEIPTS),
+        ("campaigns", Self::FEATURE_CAMPAIGNS),
+        ("delegation", Self::FEATURE_DELEGATION),
+        ("wormhole", Self::FEATURE_WORMHOLE),
+        ("fiat-onramp", Self::FEATURE_FIAT_ONRAMP),
+        ("dispute-escrow", Self::FEATURE_DISPUTE_ESCROW),
+        ("coupons", Self::FEATURE_COUPONS),
+        ("gifts", Self::FEATURE_GIFTS),
+        ("selling", Self::FEATURE_SELLING),
+        ("sale-phases", Self::FEATURE_SALE_PHASES),
+        ("protocol-fee", Self::FEATURE_PROTOCOL_FEE),
+        ("wallet-caps", Self::FEATURE_WALLET_CAPS),
+        ("sale-window", Self::FEATURE_SALE_WINDOW),
+        ("allowlist", Self::FEATURE_ALLOWLIST),
+        ("tiered-pricing", Self::FEATURE_TIERED_PRICING),
+        ("bonding-curve", Self::FEATURE_BONDING_CURVE),
+        ("stake-weight", Self::FEATURE_STAKE_WEIGHT),
+        ("withdraw-stream", Self::FEATURE_WITHDRAW_STREAM),
+        ("purchase-cooldown", Self::FEATURE_PURCHASE_COOLDOWN),
+        ("loyalty-tiers", Self::FEATURE_LOYALTY_TIERS),
+    ];
+    /// Fees are taken out of the payment, so they stay below all of it
+    pub const MAX_FEE_BPS: u16 = 9_999;
+    /// Seconds over which the withdraw stream releases `withdraw_rate`
+    pub const WITHDRAW_PERIOD: i64 = 24 * 60 * 60;
+
+    /// Whether the market supports every feature of `features`
+    pub fn supports(&self, features: u64) -> bool {
+        self.features & features == features
+    }
+
+    /// Fail unless the market supports every feature of `features`
+    pub fn require_features(&self, features: u64) -> Result<(), TokenMarketError> {
+        if self.supports(features) {
+            Ok(())
+        } else {
+            Err(TokenMarketError::FeatureDisabled)
+        }
+    }
+
+    /// Fail if `features` enable selling along with a way to emit tokens that
+    /// weren't paid at par into the bank, which sellers would be paid for
+    pub fn check_selling(features: u64) -> Result<(), TokenMarketError> {
+        if features & Self::FEATURE_SELLING != 0 && features & !Self::SELLABLE_FEATURES != 0 {
+            Err(TokenMarketError::UnbackedSelling)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fail while the owner has the market paused
+    pub fn require_trading(&self) -> Result<(), TokenMarketError> {
+        if self.paused {
+            Err(TokenMarketError::MarketPaused)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the owner scheduled the sale
+    pub fn has_sale_window(&self) -> bool {
+        self.sale_start != 0 || self.sale_end != 0
+    }
+
+    /// Fail for purchases at `now` outside the sale window
+    pub fn check_sale_window(&self, now: i64) -> Result<(), TokenMarketError> {
+        if self.sale_start != 0 && now < self.sale_start {
+            Err(TokenMarketError::SaleNotStarted)
+        } else if self.sale_end != 0 && now >= self.sale_end {
+            Err(TokenMarketError::SaleEnded)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether a scheduled sale started by `now`, after which the window is fixed
+    pub fn sale_started(&self, now: i64) -> bool {
+        self.sale_start != 0 && now >= self.sale_start
+    }
+
+    /// Whether the sale is over for good by `now`: a scheduled window ended,
+    /// which can't be moved again once it started. Records of the sale may
+    /// then be closed.
+    pub fn sale_finalized(&self, now: i64) -> bool {
+        self.sale_started(now) && self.sale_end != 0 && now >= self.sale_end
+    }
+
+    /// Fail for purchases that can't follow sale phases when the market has them
+    pub fn require_unphased(&self) -> Result<(), TokenMarketError> {
+        if self.phased {
+            Err(TokenMarketError::PhasedSale)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether purchases are priced by [pricing_tiers](#structfield.pricing_tiers)
+    pub fn has_pricing_tiers(&self) -> bool {
+        self.pricing_tiers[0].is_set()
+    }
+
+    /// Whether purchases are priced by the [bonding_curve](#structfield.bonding_curve)
+    pub fn has_bonding_curve(&self) -> bool {
+        self.bonding_curve.is_set()
+    }
+
+    /// Whether purchases are priced by `total_sold`, which counts them
+    pub fn counts_sales(&self) -> bool {
+        self.has_pricing_tiers() || self.has_bonding_curve()
+    }
+
+    /// Acceptable tokens buying exactly `emitted` tokens costs at par, or by
+    /// the bonding curve or the tiers from `total_sold`. `None` if the tiers
+    /// end first or the cost overflows.
+    pub fn cost_of(&self, emitted: u64) -> Option<u64> {
+        if self.has_bonding_curve() {
+            crate::curve::quote(&self.bonding_curve, self.total_sold, emitted)
+        } else if self.has_pricing_tiers() {
+            crate::pricing::cost_across_tiers(&self.pricing_tiers, self.total_sold, emitted)
+        } else {
+            Some(emitted)
+        }
+    }
+
+    /// Fail for purchases that can't count in `total_sold` when the market
+    /// is tiered or on a bonding curve
+    pub fn require_untiered(&self) -> Result<(), TokenMarketError> {
+        if self.counts_sales() {
+            Err(TokenMarketError::TieredMarket)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fail for purchases that can't show an allowlist entry when the market requires one
+    pub fn require_open_access(&self) -> Result<(), TokenMarketError> {
+        if self.allowlist_enabled {
+            Err(TokenMarketError::AllowlistedMarket)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether purchases are limited by the [BuyerRecord](struct.BuyerRecord.html) of their buyer
+    pub fn caps_buyers(&self) -> bool {
+        self.wallet_cap != 0 || self.wallet_spend_cap != 0 || self.purchase_cooldown_secs != 0
+    }
+
+    /// Whether purchases are counted in the [BuyerRecord](struct.BuyerRecord.html) of their buyer
+    pub fn tracks_buyers(&self) -> bool {
+        self.caps_buyers() || self.has_loyalty_tiers()
+    }
+
+    /// Whether buyers are discounted by the [loyalty_tiers](#structfield.loyalty_tiers)
+    pub fn has_loyalty_tiers(&self) -> bool {
+        self.loyalty_tiers[0].is_set()
+    }
+
+    /// Discount of a buyer who purchased `purchased` emitted tokens before,
+    /// that of the last loyalty tier reached, 0 below the first
+    pub fn loyalty_discount(&self, purchased: u64) -> u16 {
+        self.loyalty_tiers
+            .iter()
+            .take_while(|tier| tier.is_set() && purchased >= tier.threshold)
+            .last()
+            .map_or(0, |tier| tier.discount_bps)
+    }
+
+    /// Fail for purchases that can't count against the wallet caps when the market has one
+    pub fn require_uncapped(&self) -> Result<(), TokenMarketError> {
+        if self.caps_buyers() {
+            Err(TokenMarketError::WalletCapped)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wallet cap of a buyer staking `staked` tokens, raised by the stake weight
+    pub fn wallet_cap_with(&self, staked: u64) -> u64 {
+        self.wallet_cap
+            .saturating_add(self.stake_weight.bonus(staked))
+    }
+
+    /// Fail for purchases paying `amount` outside the limits set by the owner
+    pub fn check_purchase(&self, amount: u64) -> Result<(), TokenMarketError> {
+        if amount < self.min_purchase {
+            Err(TokenMarketError::PurchaseBelowMinimum)
+        } else if self.max_purchase != 0 && amount > self.max_purchase {
+            Err(TokenMarketError::PurchaseAboveMaximum)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the acceptable mint was migrated and the legacy bank is still tracked
+    pub fn has_legacy_bank(&self) -> bool {
+        self.legacy_bank != Pubkey::default()
+    }
+
+    /// Start of what the withdraw stream released by `now`, at most a period back
+    fn stream_start(&self, now: i64) -> i64 {
+        self.last_withdraw.max(now - Self::WITHDRAW_PERIOD)
+    }
+
+    /// Acceptable tokens the withdraw stream released by `now`, rounded
+    /// down, `u64::MAX` if withdrawals aren't streamed
+    pub fn withdrawable(&self, now: i64) -> u64 {
+        if self.withdraw_rate == 0 {
+            return u64::MAX;
+        }
+        let elapsed = (now - self.stream_start(now)).max(0) as u128;
+        (self.withdraw_rate as u128 * elapsed / Self::WITHDRAW_PERIOD as u128) as u64
+    }
+
+    /// Take a withdrawal of `amount` at `now` out of the withdraw stream,
+    /// moving `last_withdraw` by the seconds releasing it took, rounded up
+    pub fn stream_withdrawal(&mut self, amount: u64, now: i64) -> Result<(), TokenMarketError> {
+        if self.withdraw_rate == 0 {
+            return Ok(());
+        }
+        if amount > self.withdrawable(now) {
+            return Err(TokenMarketError::WithdrawRateExceeded);
+        }
+        let rate = self.withdraw_rate as u128;
+        let seconds = (amount as u128 * Self::WITHDRAW_PERIOD as u128 + rate - 1) / rate;
+        self.last_withdraw = self.stream_start(now) + seconds as i64;
+        Ok(())
+    }
+
+    /// Whether the owner has set a treasury
+    pub fn has_treasury(&self) -> bool {
+        self.treasury != Pubkey::default()
+    }
+
+    /// Part of the `proceeds` of a purchase taken as protocol fee, rounded down
+    pub fn protocol_fee(&self, proceeds: u64) -> u64 {
+        crate::pricing::protocol_fee(proceeds, self.fee_bps)
+    }
+
+    /// Market that replaces this deprecated one, if any
+    pub fn successor(&self) -> Option<Pubkey> {
+        Some(self.successor).filter(|successor| *successor != Pubkey::default())
+    }
+
+    /// Whether purchases with a voucher are accepted
+    pub fn accepts_vouchers(&self) -> bool {
+        self.voucher_signer != [0; 20]
+    }
+
+    /// Whether purchases attested by Wormhole messages are accepted
+    pub fn accepts_wormhole(&self) -> bool {
+        self.wormhole_emitter != [0; 32]
+    }
+
+    /// Whether purchases attested by an on-ramp provider are accepted
+    pub fn accepts_onramp(&self) -> bool {
+        self.onramp_signer != Pubkey::default()
+    }
+
+    /// Whether a purchase emitting `emitted` must be held in escrow
+    pub fn escrows(&self, emitted: u64) -> bool {
+        self.escrow_threshold != 0 && emitted >= self.escrow_threshold
+    }
+
+    /// Whether `key` may dispute escrowed purchases
+    pub fn may_dispute(&self, key: &Pubkey) -> bool {
+        *key == self.owner
+            || (self.dispute_guardian != Pubkey::default() && *key == self.dispute_guardian)
+    }
+}
+
+/// Reverse lookup from an emitter mint to the market issuing it,
+/// written at [find_mint_index_address](fn.find_mint_index_address.html) by Initialize
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct MintIndex {
+    pub market: Pubkey,
+}
+
+impl MintIndex {
+    pub const LEN: usize = 32;
+}
+
+/// Seed prefix of the [TenantStats](struct.TenantStats.html) PDA of a market owner
+pub const TENANT_SEED: &[u8] = b"tenant";
+
+/// Find the usage stats address of the market owner `owner` and its bump seed
+pub fn find_tenant_address(program_id: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TENANT_SEED, owner.as_ref()], program_id)
+}
+
+/// Usage of a shared deployment by one market owner, kept by the program at
+/// [find_tenant_address](fn.find_tenant_address.html) so the operator can
+/// bill owners without indexing transactions
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct TenantStats {
+    pub owner: Pubkey,
+    /// Markets initialized with this owner
+    pub markets_created: u64,
+    /// Protocol fees paid by purchases on the owner's markets
+    pub protocol_fees: u64,
+}
+
+impl TenantStats {
+    pub const LEN: usize = 32 + 8 * 2;
+}
+
+/// Seed prefix of the [ListingInfo](struct.ListingInfo.html) PDA of a market
+pub const LISTING_SEED: &[u8] = b"listing";
+
+/// Find the listing info address of `market` and its bump seed
+pub fn find_listing_address(program_id: &Pubkey, market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LISTING_SEED, market.as_ref()], program_id)
+}
+
+/// Metadata the market owner publishes for explorers, stored next to the
+/// market at [find_listing_address](fn.find_listing_address.html)
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct ListingInfo {
+    pub is_initialized: bool,
+    pub market: Pubkey,
+    /// At most [MAX_WEBSITE_LEN](#associatedconstant.MAX_WEBSITE_LEN) bytes
+    pub website: String,
+    /// SHA-256 of the logo URI
+    pub logo_uri_hash: [u8; 32],
+    /// SHA-256 of the issuer contact
+    pub contact_hash: [u8; 32],
+}
+
+impl ListingInfo {
+    pub const MAX_WEBSITE_LEN: usize = 128;
+    /// Size of the account, enough for the longest website
+    pub const LEN: usize = 1 + 32 + 4 + Self::MAX_WEBSITE_LEN + 32 * 2;
+
+    /// Load the listing from account `data`. A shorter website leaves
+    /// unused bytes at the end of the account, which are ignored.
+    pub fn load(mut data: &[u8]) -> std::io::Result<Self> {
+        Self::deserialize(&mut data)
+    }
+}
+
+/// Seed prefix of the [DeprecationNotice](struct.DeprecationNotice.html) PDA of a market
+pub const DEPRECATION_SEED: &[u8] = b"deprecation";
+
+/// Find the deprecation notice address of `market` and its bump seed
+pub fn find_deprecation_address(program_id: &Pubkey, market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[DEPRECATION_SEED, market.as_ref()], program_id)
+}
+
+/// Explanation of a market deprecation, stored next to the market at
+/// [find_deprecation_address](fn.find_deprecation_address.html)
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct DeprecationNotice {
+    pub market: Pubkey,
+    /// At most [MAX_MESSAGE_URI_LEN](#associatedconstant.MAX_MESSAGE_URI_LEN) bytes
+    pub message_uri: String,
+}
+
+impl DeprecationNotice {
+    pub const MAX_MESSAGE_URI_LEN: usize = 128;
+    /// Size of the account, enough for the longest message URI
+    pub const LEN: usize = 32 + 4 + Self::MAX_MESSAGE_URI_LEN;
+
+    /// Load the notice from account `data`, ignoring unused bytes at the end
+    pub fn load(mut data: &[u8]) -> std::io::Result<Self> {
+        Self::deserialize(&mut data)
+    }
+}
+
+/// Seed prefix of the [PurchaseReceipt](struct.PurchaseReceipt.html) PDA of a receipt mint
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+
+/// Find the purchase receipt address of `receipt_mint` and its bump seed
+pub fn find_receipt_address(program_id: &Pubkey, receipt_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RECEIPT_SEED, receipt_mint.as_ref()], program_id)
+}
+
+/// Details of the purchase behind a receipt NFT, stored at
+/// [find_receipt_address](fn.find_receipt_address.html) of its mint
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct PurchaseReceipt {
+    pub market: Pubkey,
+    /// Owner of the tokens recipient, who holds the receipt
+    pub buyer: Pubkey,
+    /// Emitted tokens bought
+    pub amount: u64,
+    pub slot: u64,
+    /// Campaign the purchase is attributed to, zero if none
+    pub campaign: [u8; 8],
+}
+
+impl PurchaseReceipt {
+    pub const LEN: usize = 32 * 2 + 8 * 3;
+}
+
+/// Seed prefix of the [CampaignStats](struct.CampaignStats.html) PDA of a market campaign
+pub const CAMPAIGN_SEED: &[u8] = b"campaign";
+
+/// Find the stats address of `campaign` on `market` and its bump seed
+pub fn find_campaign_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    campaign: &[u8; 8],
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CAMPAIGN_SEED, market.as_ref(), campaign], program_id)
+}
+
+/// Sales attributed to a campaign code, stored at
+/// [find_campaign_address](fn.find_campaign_address.html)
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct CampaignStats {
+    pub market: Pubkey,
+    pub campaign: [u8; 8],
+    pub purchases: u64,
+    /// Emitted tokens bought in total
+    pub amount: u64,
+}
+
+impl CampaignStats {
+    pub const LEN: usize = 32 + 8 * 3;
+}
+
+/// Seed prefix of the [ConsumedVaa](struct.ConsumedVaa.html) PDA of a redeemed Wormhole message
+pub const CONSUMED_VAA_SEED: &[u8] = b"consumed-vaa";
+
+/// Find the address recording that the Wormhole message `sequence` of
+/// `emitter` on `chain` was redeemed, and its bump seed
+pub fn find_consumed_vaa_address(
+    program_id: &Pubkey,
+    chain: u16,
+    emitter: &[u8; 32],
+    sequence: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            CONSUMED_VAA_SEED,
+            &chain.to_le_bytes(),
+            emitter,
+            &sequence.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// Purchase redeemed from a Wormhole message, stored at
+/// [find_consumed_vaa_address](fn.find_consumed_vaa_address.html) so the
+/// message is redeemed once
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct ConsumedVaa {
+    pub market: Pubkey,
+    /// Token account the emitted tokens were minted to
+    pub recipient: Pubkey,
+    /// Emitted tokens minted
+    pub amount: u64,
+}
+
+impl ConsumedVaa {
+    pub const LEN: usize = 32 * 2 + 8;
+}
+
+/// Seed prefix of the [FiatSettlement](struct.FiatSettlement.html) PDA of an on-ramp payment
+pub const FIAT_SETTLEMENT_SEED: &[u8] = b"fiat-settlement";
+
+/// Find the address recording the fiat payment `reference` on `market` and its bump seed
+pub fn find_fiat_settlement_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    reference: &[u8; 32],
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[FIAT_SETTLEMENT_SEED, market.as_ref(), reference],
+        program_id,
+    )
+}
+
+/// Purchase paid in fiat and attested by the market on-ramp signer, stored at
+/// [find_fiat_settlement_address](fn.find_fiat_settlement_address.html) so the
+/// payment is minted for once and can be reconciled with the provider's records
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct FiatSettlement {
+    pub market: Pubkey,
+    /// Token account the emitted tokens were minted to
+    pub recipient: Pubkey,
+    /// Emitted tokens minted
+    pub amount: u64,
+    /// Provider reference of the payment, usually the hash of its id
+    pub reference: [u8; 32],
+    pub slot: u64,
+}
+
+impl FiatSettlement {
+    pub const LEN: usize = 32 * 2 + 8 + 32 + 8;
+}
+
+/// Seed prefix of the [Coupon](struct.Coupon.html) PDA of a discount code
+pub const COUPON_SEED: &[u8] = b"coupon";
+
+/// Find the coupon of `market` whose code hashes to `code_hash` and its bump seed
+pub fn find_coupon_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    code_hash: &[u8; 32],
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[COUPON_SEED, market.as_ref(), code_hash], program_id)
+}
+
+/// Hash of the coupon `code` on `market`. Codes are case-insensitive and
+/// hashed with the market, so the same code on two markets gives unrelated
+/// coupons and the plain code never appears on chain.
+pub fn coupon_code_hash(market: &Pubkey, code: &str) -> [u8; 32] {
+    solana_program::hash::hashv(&[market.as_ref(), code.trim().to_ascii_uppercase().as_bytes()])
+        .to_bytes()
+}
+
+/// Discount the owner issued under a code, stored at
+/// [find_coupon_address](fn.find_coupon_address.html). Anyone who knows the
+/// code may redeem it until it runs out of uses or expires.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct Coupon {
+    pub market: Pubkey,
+    /// [coupon_code_hash](fn.coupon_code_hash.html) of the code
+    pub code_hash: [u8; 32],
+    /// Part of the price waived, in basis points
+    pub discount_bps: u16,
+    pub remaining_uses: u32,
+    /// Unix timestamp from which the coupon can't be redeemed, 0 if it never expires
+    pub expiry: i64,
+}
+
+impl Coupon {
+    pub const LEN: usize = 32 * 2 + 2 + 4 + 8;
+
+    /// Whether the coupon is past its expiry at `now`
+    pub fn expired(&self, now: i64) -> bool {
+        self.expiry != 0 && now >= self.expiry
+    }
+}
+
+/// Seed prefix of the [PurchaseEscrow](struct.PurchaseEscrow.html) PDA of an escrowed purchase
+pub const ESCROW_SEED: &[u8] = b"escrow";
+
+/// Find the escrow of the purchase of `market` paid from `write_off` under
+/// `nonce` and its bump seed
+pub fn find_escrow_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    write_off: &Pubkey,
+    nonce: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            ESCROW_SEED,
+            market.as_ref(),
+            write_off.as_ref(),
+            &nonce.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// Seed prefix of the token account holding the tokens of an escrowed purchase
+pub const ESCROW_VAULT_SEED: &[u8] = b"escrow-vault";
+
+/// Find the token account holding the emitted tokens of `escrow` and its bump seed
+pub fn find_escrow_vault_address(program_id: &Pubkey, escrow: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ESCROW_VAULT_SEED, escrow.as_ref()], program_id)
+}
+
+/// Seed prefix of the token account holding the payment of an escrowed purchase
+pub const ESCROW_PAYMENT_SEED: &[u8] = b"escrow-payment";
+
+/// Find the token account holding the payment of `escrow` until release and
+/// its bump seed
+pub fn find_escrow_payment_address(program_id: &Pubkey, escrow: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ESCROW_PAYMENT_SEED, escrow.as_ref()], program_id)
+}
+
+/// Purchase at or above the market escrow threshold, its emitted tokens wait
+/// in `vault` and its payment out of the bank until the dispute window
+/// passes. A dispute burns them and refunds the payment instead.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct PurchaseEscrow {
+    pub market: Pubkey,
+    /// Token account of the emitter mint the tokens are released to
+    pub recipient: Pubkey,
+    /// Token account of the acceptable mint a dispute refunds
+    pub write_off: Pubkey,
+    pub vault: Pubkey,
+    /// Payment held in the escrow payment account, see
+    /// [find_escrow_payment_address](fn.find_escrow_payment_address.html)
+    pub paid: u64,
+    /// Protocol fee held along with the payment, released to the fee vault
+    pub fee: u64,
+    /// Emitted tokens held in `vault`
+    pub emitted: u64,
+    /// Unix timestamp from which the purchase may be released
+    pub release_after: i64,
+    pub status: EscrowStatus,
+}
+
+impl PurchaseEscrow {
+    pub const LEN: usize = 32 * 4 + 8 * 4 + 1;
+}
+
+/// Where an escrowed purchase stands
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub enum EscrowStatus {
+    /// The tokens wait in the vault
+    Held,
+    /// The tokens went to the recipient
+    Released,
+    /// The tokens were burned and the payment refunded
+    Disputed,
+}
+
+/// Seed prefix of the [Delegation](struct.Delegation.html) PDA of a market delegate
+pub const DELEGATION_SEED: &[u8] = b"delegation";
+
+/// Find the delegation record of `delegate` on `market` and its bump seed
+pub fn find_delegation_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    delegate: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[DELEGATION_SEED, market.as_ref(), delegate.as_ref()],
+        program_id,
+    )
+}
+
+/// Owner instructions a delegate may sign instead of the market owner,
+/// indexing [Delegation](struct.Delegation.html) limits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DelegatedAction {
+    /// Limited in emitted tokens vested per day
+    CreateVestingVault,
+    SetListingInfo,
+    SetOverpaymentPolicy,
+    SetReceiptMinting,
+}
+
+impl DelegatedAction {
+    pub const COUNT: usize = 4;
+}
+
+/// Owner instructions a cold market owner lets a hot `delegate` key sign,
+/// stored at [find_delegation_address](fn.find_delegation_address.html).
+/// Limits and usage are indexed by [DelegatedAction](enum.DelegatedAction.html),
+/// counting calls per day unless the action says otherwise. A zero limit
+/// forbids the action. Usage starts over on every new UTC day of the Clock.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct Delegation {
+    pub is_initialized: bool,
+    pub market: Pubkey,
+    pub delegate: Pubkey,
+    pub daily_limits: [u64; DelegatedAction::COUNT],
+    /// Days since the Unix epoch `used_today` counts
+    pub day: i64,
+    pub used_today: [u64; DelegatedAction::COUNT],
+}
+
+impl Delegation {
+    pub const LEN: usize = 1 + 32 * 2 + 8 * DelegatedAction::COUNT * 2 + 8;
+    pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+    /// Count `units` of `action` at `unix_timestamp` against its daily limit
+    pub fn spend(
+        &mut self,
+        action: DelegatedAction,
+        units: u64,
+        unix_timestamp: i64,
+    ) -> Result<(), TokenMarketError> {
+        let day = unix_timestamp.div_euclid(Self::SECONDS_PER_DAY);
+        if day != self.day {
+            self.day = day;
+            self.used_today = [0; DelegatedAction::COUNT];
+        }
+        let index = action as usize;
+        self.used_today[index] = self.used_today[index]
+            .checked_add(units)
+            .filter(|used| *used <= self.daily_limits[index])
+            .ok_or(TokenMarketError::DelegationLimitExceeded)?;
+        Ok(())
+    }
+}
+
+impl IsInitialized for Delegation {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
 }
 
 impl IsInitialized for TokenMarket {
@@ -22,3 +1113,733 @@ impl IsInitialized for TokenMarket {
         self.is_initialized
     }
 }
+
+/// Team/investor lockup funded by minting emitter tokens into a vault
+/// token account owned by the market authority.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct VestingVault {
+    pub is_initialized: bool,
+    pub market: Pubkey,
+    pub beneficiary: Pubkey,
+    pub vault: Pubkey,
+    /// Amount locked in the vault, reduced to the vested part on revoke
+    pub total: u64,
+    /// Amount already claimed by the beneficiary
+    pub released: u64,
+    /// Unix timestamp the schedule starts at
+    pub start: i64,
+    /// Seconds after `start` before anything is vested
+    pub cliff: i64,
+    /// Seconds after `start` when everything is vested
+    pub duration: i64,
+    pub revocable: bool,
+    pub revoked: bool,
+}
+
+impl VestingVault {
+    pub const LEN: usize = 1 + 32 * 3 + 8 * 5 + 1 + 1;
+    /// Offset of `market` in the account data
+    pub const MARKET_OFFSET: usize = 1;
+
+    /// Amount vested at the unix timestamp `now`
+    pub fn vested_amount(&self, now: i64) -> u64 {
+        if self.revoked {
+            return self.total;
+        }
+        let elapsed = now.saturating_sub(self.start);
+        if elapsed < self.cliff {
+            0
+        } else if elapsed >= self.duration {
+            self.total
+        } else {
+            (self.total as u128 * elapsed as u128 / self.duration as u128) as u64
+        }
+    }
+}
+
+impl IsInitialized for VestingVault {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+/// Swap route from the emitter token of a deprecated market to the emitter
+/// token of its replacement, agreed by the owners of both markets.
+#[derive(BorshSerialize, BorshDeserialize)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct HoldingsMigration {
+    pub is_initialized: bool,
+    pub from_market: Pubkey,
+    pub to_market: Pubkey,
+    /// Emitter mint of `from_market`, burned on migration
+    pub from_emitter: Pubkey,
+    /// Emitter mint of `to_market`, minted on migration
+    pub to_emitter: Pubkey,
+    /// New tokens received per `denominator` burned tokens
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl HoldingsMigration {
+    pub const LEN: usize = 1 + 32 * 4 + 8 * 2;
+
+    /// Amount of new tokens received for burning `amount` deprecated tokens
+    pub fn converted_amount(&self, amount: u64) -> Option<u64> {
+        let converted = amount as u128 * self.numerator as u128 / self.denominator as u128;
+        if converted > u64::MAX as u128 {
+            None
+        } else {
+            Some(converted as u64)
+        }
+    }
+}
+
+impl IsInitialized for HoldingsMigration {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+/// Message signed off-chain by the market voucher signer that lets `buyer`
+/// spend up to `max_amount` per purchase until `expiry`
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct PurchaseVoucher {
+    pub market: Pubkey,
+    /// Owner of the write-off account
+    pub buyer: Pubkey,
+    pub max_amount: u64,
+    /// Unix timestamp after which the voucher is rejected
+    pub expiry: i64,
+}
+
+impl PurchaseVoucher {
+    pub const LEN: usize = 32 * 2 + 8 * 2;
+}
+
+/// Price offered off-chain and signed by the market
+/// [voucher signer](struct.TokenMarket.html#structfield.voucher_signer).
+/// Anyone holding it may buy at the price until it expires, each purchase
+/// paying at most `max_amount` acceptable tokens.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct PriceQuote {
+    pub market: Pubkey,
+    /// Emitted tokens per `denominator` acceptable tokens
+    pub numerator: u64,
+    pub denominator: u64,
+    pub max_amount: u64,
+    /// Unix timestamp after which the quote is rejected
+    pub expiry: i64,
+}
+
+impl PriceQuote {
+    pub const LEN: usize = 32 + 8 * 4;
+
+    pub fn price(&self) -> Price {
+        Price {
+            numerator: self.numerator,
+            denominator: self.denominator,
+        }
+    }
+}
+
+/// Seed prefix of the [Gift](struct.Gift.html) PDA of a gifted purchase
+pub const GIFT_SEED: &[u8] = b"gift";
+
+/// Find the gift of `market` claimed with the secret hashing to
+/// `secret_hash` and its bump seed
+pub fn find_gift_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    secret_hash: &[u8; 32],
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[GIFT_SEED, market.as_ref(), secret_hash], program_id)
+}
+
+/// Seed prefix of the token account holding the tokens of a gift
+pub const GIFT_VAULT_SEED: &[u8] = b"gift-vault";
+
+/// Find the token account holding the emitted tokens of `gift` and its bump seed
+pub fn find_gift_vault_address(program_id: &Pubkey, gift: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[GIFT_VAULT_SEED, gift.as_ref()], program_id)
+}
+
+/// Seed prefix of the token account holding the payment of a gift
+pub const GIFT_PAYMENT_SEED: &[u8] = b"gift-payment";
+
+/// Find the token account holding the payment of `gift` until claimed and
+/// its bump seed
+pub fn find_gift_payment_address(program_id: &Pubkey, gift: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[GIFT_PAYMENT_SEED, gift.as_ref()], program_id)
+}
+
+/// Hash of the claim `secret` of a gift, the only part of it stored on chain
+pub fn gift_secret_hash(secret: &[u8; 32]) -> [u8; 32] {
+    solana_program::hash::hash(secret).to_bytes()
+}
+
+/// Purchase paid for someone else, its emitted tokens wait in `vault` and its
+/// payment out of the bank until claimed with the secret the buyer shares. Past `expiry` the tokens are
+/// burned and the payment refunded instead.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct Gift {
+    pub market: Pubkey,
+    /// Token account of the acceptable mint a refund goes to
+    pub write_off: Pubkey,
+    pub vault: Pubkey,
+    /// Payment held in the gift payment account, see
+    /// [find_gift_payment_address](fn.find_gift_payment_address.html)
+    pub paid: u64,
+    /// Protocol fee held along with the payment, claimed into the fee vault
+    pub fee: u64,
+    /// Emitted tokens held in `vault`
+    pub emitted: u64,
+    /// Unix timestamp from which the gift can't be claimed and may be refunded
+    pub expiry: i64,
+    pub status: GiftStatus,
+}
+
+impl Gift {
+    pub const LEN: usize = 32 * 3 + 8 * 4 + 1;
+}
+
+/// Where a gift stands
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub enum GiftStatus {
+    /// The tokens wait in the vault
+    Pending,
+    /// The tokens went to the account the secret was presented for
+    Claimed,
+    /// The tokens were burned and the payment refunded
+    Refunded,
+}
+
+/// Seed prefix of the [BuyerRecord](struct.BuyerRecord.html) PDA of a buyer on a market
+pub const BUYER_RECORD_SEED: &[u8] = b"buyer";
+
+/// Find the purchase record address of `buyer` on `market` and its bump seed
+pub fn find_buyer_record_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    buyer: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[BUYER_RECORD_SEED, market.as_ref(), buyer.as_ref()],
+        program_id,
+    )
+}
+
+/// Purchases of one buyer on a market with wallet caps, created by its
+/// first purchase at [find_buyer_record_address](fn.find_buyer_record_address.html).
+/// The buyer is the owner of the account receiving the emitted tokens.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct BuyerRecord {
+    pub market: Pubkey,
+    pub buyer: Pubkey,
+    /// Emitted tokens purchased so far
+    pub purchased: u64,
+    /// Acceptable tokens paid for them
+    pub spent: u64,
+    /// Unix timestamp of the last purchase, 0 before the first
+    pub last_purchase: i64,
+}
+
+impl BuyerRecord {
+    pub const LEN: usize = 32 * 2 + 8 * 3;
+
+    /// Emitted tokens the buyer may still purchase under `wallet_cap`
+    pub fn remaining(&self, wallet_cap: u64) -> u64 {
+        wallet_cap.saturating_sub(self.purchased)
+    }
+
+    /// Acceptable tokens the buyer may still pay under `spend_cap`
+    pub fn remaining_spend(&self, spend_cap: u64) -> u64 {
+        spend_cap.saturating_sub(self.spent)
+    }
+
+    /// Count a purchase of `emitted` tokens paying `spent`, failing past
+    /// `wallet_cap` or `spend_cap`, either 0 for no cap
+    pub fn count(
+        &mut self,
+        emitted: u64,
+        spent: u64,
+        wallet_cap: u64,
+        spend_cap: u64,
+    ) -> Result<(), TokenMarketError> {
+        if wallet_cap != 0 && emitted > self.remaining(wallet_cap) {
+            return Err(TokenMarketError::WalletCapExceeded);
+        }
+        if spend_cap != 0 && spent > self.remaining_spend(spend_cap) {
+            return Err(TokenMarketError::SpendCapExceeded);
+        }
+        self.purchased = self.purchased.saturating_add(emitted);
+        self.spent = self.spent.saturating_add(spent);
+        Ok(())
+    }
+
+    /// Stamp a purchase at `now`, failing within `cooldown` seconds of the
+    /// last one, 0 for no cooldown
+    pub fn cool_down(&mut self, now: i64, cooldown: i64) -> Result<(), TokenMarketError> {
+        if cooldown != 0
+            && self.last_purchase != 0
+            && now < self.last_purchase.saturating_add(cooldown)
+        {
+            return Err(TokenMarketError::PurchaseCooldown);
+        }
+        self.last_purchase = now;
+        Ok(())
+    }
+}
+
+/// Seed prefix of the [AllowlistEntry](struct.AllowlistEntry.html) PDA of a buyer on a market
+pub const ALLOWLIST_SEED: &[u8] = b"allowlist";
+
+/// Find the allowlist entry address of `buyer` on `market` and its bump seed
+pub fn find_allowlist_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    buyer: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[ALLOWLIST_SEED, market.as_ref(), buyer.as_ref()],
+        program_id,
+    )
+}
+
+/// Permission of `buyer` to purchase on a market with the allowlist enabled,
+/// stored at [find_allowlist_address](fn.find_allowlist_address.html) while
+/// the owner keeps the buyer on the list. The buyer is the owner of the
+/// account receiving the emitted tokens.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct AllowlistEntry {
+    pub market: Pubkey,
+    pub buyer: Pubkey,
+}
+
+impl AllowlistEntry {
+    pub const LEN: usize = 32 * 2;
+}
+
+/// Seed prefix of the [NotificationConfig](struct.NotificationConfig.html) PDA
+/// of a market owner
+pub const NOTIFY_SEED: &[u8] = b"notify";
+
+/// Find the notification config address of `owner` for `market` and its bump seed
+pub fn find_notification_address(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    owner: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[NOTIFY_SEED, market.as_ref(), owner.as_ref()], program_id)
+}
+
+/// When monitoring of a market raises an alert, 0 for never
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct AlertThresholds {
+    /// Emitted tokens of a single purchase
+    pub large_purchase: u64,
+    /// Acceptable tokens paid to the market in one UTC day
+    pub daily_volume: u64,
+    /// Acceptable tokens in the bank, alerting once it holds less
+    pub low_bank_balance: u64,
+}
+
+/// Where and when the market owner wants to hear about its market, stored at
+/// [find_notification_address](fn.find_notification_address.html) for
+/// indexers and monitoring to read. The program never interprets it.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct NotificationConfig {
+    pub is_initialized: bool,
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    /// SHA-256 of the webhook URL, so a notifier knows the URL it was
+    /// given is the one the owner published
+    pub webhook_hash: [u8; 32],
+    pub thresholds: AlertThresholds,
+    /// Settings the owner encrypted for its notifier, e.g. the webhook URL
+    /// itself, at most [MAX_SEALED_LEN](#associatedconstant.MAX_SEALED_LEN) bytes
+    pub sealed: Vec<u8>,
+}
+
+impl NotificationConfig {
+    pub const MAX_SEALED_LEN: usize = 256;
+    /// Size of the account, enough for the longest sealed settings
+    pub const LEN: usize = 1 + 32 * 3 + 8 * 3 + 4 + Self::MAX_SEALED_LEN;
+
+    /// Load the config from account `data`, ignoring the unused bytes
+    /// shorter sealed settings leave at the end of the account
+    pub fn load(mut data: &[u8]) -> std::io::Result<Self> {
+        Self::deserialize(&mut data)
+    }
+}
+
+/// Seed prefix of the [SalePhases](struct.SalePhases.html) PDA of a market
+pub const SALE_PHASES_SEED: &[u8] = b"sale-phases";
+
+/// Find the sale phases address of `market` and its bump seed
+pub fn find_sale_phases_address(program_id: &Pubkey, market: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SALE_PHASES_SEED, market.as_ref()], program_id)
+}
+
+/// Terms of one stage of a phased sale, e.g. an allowlisted presale
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct SalePhase {
+    /// Unix timestamp the phase opens at, unless the owner opens it earlier
+    pub start: i64,
+    /// Emitted tokens per `price_denominator` acceptable tokens
+    pub price_numerator: u64,
+    pub price_denominator: u64,
+    /// Emitted tokens the phase sells at most, 0 if it has no cap
+    pub cap: u64,
+    /// Emitted tokens a single purchase buys at most, 0 if there is no limit
+    pub max_purchase: u64,
+    /// Whether only purchases under a voucher or a quote of the voucher
+    /// signer are accepted, which makes the signer the allowlist keeper
+    pub allowlist_only: bool,
+}
+
+/// Discount of the purchases of a buyer who purchased at least `threshold`
+/// emitted tokens before
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct LoyaltyTier {
+    pub threshold: u64,
+    /// Basis points of the cost waived
+    pub discount_bps: u16,
+}
+
+impl LoyaltyTier {
+    pub const LEN: usize = 8 + 2;
+
+    /// Whether the tier is in use, unused ones are zeroed
+    pub fn is_set(&self) -> bool {
+        self.threshold != 0
+    }
+}
+
+/// Price of the emitted tokens a market sells until its `total_sold` reaches `up_to`
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct PricingTier {
+    /// Emitted tokens sold in total when the tier ends, 0 if it never
+    /// does, which only the last tier may
+    pub up_to: u64,
+    /// Emitted tokens per `price_denominator` acceptable tokens
+    pub price_numerator: u64,
+    pub price_denominator: u64,
+}
+
+impl PricingTier {
+    pub const LEN: usize = 8 * 3;
+
+    /// Whether the tier is in use, unused ones are zeroed
+    pub fn is_set(&self) -> bool {
+        self.price_denominator != 0
+    }
+
+    /// Price purchases settle at within the tier
+    pub fn price(&self) -> Price {
+        Price {
+            numerator: self.price_numerator,
+            denominator: self.price_denominator,
+        }
+    }
+}
+
+/// Shape of a [BondingCurve](struct.BondingCurve.html)
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub enum CurveKind {
+    /// The market isn't on a curve
+    #[default]
+    Off,
+    /// The price grows by `growth` for every
+    /// [PRICE_SCALE](../curve/constant.PRICE_SCALE.html) emitted tokens sold
+    Linear,
+    /// The price grows by `growth` basis points for every `step` emitted tokens sold
+    Exponential,
+}
+
+/// Price of emitted tokens as a function of the emitted tokens a market sold,
+/// see the [curve](../curve/index.html) module for the math
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct BondingCurve {
+    pub kind: CurveKind,
+    /// Acceptable tokens per emitted token before any sale, times
+    /// [PRICE_SCALE](../curve/constant.PRICE_SCALE.html)
+    pub base_price: u64,
+    /// Growth of the price, see [CurveKind](enum.CurveKind.html)
+    pub growth: u64,
+    /// Emitted tokens sold between two growths of an exponential curve
+    pub step: u64,
+}
+
+impl BondingCurve {
+    pub const LEN: usize = 1 + 8 * 3;
+
+    /// Whether the market sells on the curve
+    pub fn is_set(&self) -> bool {
+        self.kind != CurveKind::Off
+    }
+}
+
+/// Where a [StakeWeight](struct.StakeWeight.html) reads the stake of a buyer,
+/// see the [stake](../stake/index.html) module
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub enum StakeSource {
+    /// Stakes don't raise wallet caps
+    #[default]
+    Off,
+    /// Pool tokens of an SPL stake pool held in a token account of the buyer
+    PoolTokens,
+    /// Governing tokens the buyer deposited in an SPL governance realm
+    GovernanceDeposit,
+}
+
+/// Stake that raises the wallet cap of a buyer by `bonus_bps` of it
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct StakeWeight {
+    pub source: StakeSource,
+    /// Pool mint of the stake pool or governing token mint of the realm
+    pub mint: Pubkey,
+    /// Governance program the deposits are made with, unused for pool tokens
+    pub governance_program: Pubkey,
+    /// Emitted tokens added to the wallet cap per 10 000 staked tokens
+    pub bonus_bps: u32,
+}
+
+impl StakeWeight {
+    pub const LEN: usize = 1 + 32 * 2 + 4;
+
+    /// Whether stakes raise wallet caps
+    pub fn is_set(&self) -> bool {
+        self.source != StakeSource::Off
+    }
+
+    /// Emitted tokens `staked` tokens add to the wallet cap, rounded down
+    pub fn bonus(&self, staked: u64) -> u64 {
+        if !self.is_set() {
+            return 0;
+        }
+        let bonus = staked as u128 * self.bonus_bps as u128 / 10_000;
+        u64::try_from(bonus).unwrap_or(u64::MAX)
+    }
+}
+
+impl SalePhase {
+    pub const LEN: usize = 8 * 5 + 1;
+
+    /// Price purchases settle at during the phase
+    pub fn price(&self) -> Price {
+        Price {
+            numerator: self.price_numerator,
+            denominator: self.price_denominator,
+        }
+    }
+}
+
+/// Ordered phases a market sells in, stored at
+/// [find_sale_phases_address](fn.find_sale_phases_address.html), e.g. a
+/// presale followed by the public sale. A phase opens at its start or when
+/// the owner advances to it, whichever comes first, and closes when a later
+/// one opens. The whole sale closes at `end`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize)
+)]
+#[cfg_attr(feature = "serde", serde(crate = "serde_crate"))]
+pub struct SalePhases {
+    pub market: Pubkey,
+    /// Phases the owner opened ahead of their start, counted from the first
+    pub opened: u8,
+    /// Unix timestamp the sale closes at, 0 if it stays open in the last phase
+    pub end: i64,
+    /// At most [MAX_PHASES](#associatedconstant.MAX_PHASES), in the order they open
+    pub phases: Vec<SalePhase>,
+    /// Emitted tokens sold in each phase
+    pub sold: Vec<u64>,
+    /// Purchases made in each phase
+    pub purchases: Vec<u64>,
+    /// Acceptable tokens the bank kept from the purchases of each phase
+    pub paid: Vec<u64>,
+}
+
+impl SalePhases {
+    pub const MAX_PHASES: usize = 8;
+    /// Size of the account, enough for the longest schedule
+    pub const LEN: usize =
+        32 + 1 + 8 + 4 + Self::MAX_PHASES * SalePhase::LEN + 3 * (4 + Self::MAX_PHASES * 8);
+
+    /// Schedule of `phases` of `market` closing at `end`, nothing sold yet
+    pub fn new(market: Pubkey, phases: Vec<SalePhase>, end: i64) -> Self {
+        let count = phases.len();
+        Self {
+            market,
+            opened: 0,
+            end,
+            phases,
+            sold: vec![0; count],
+            purchases: vec![0; count],
+            paid: vec![0; count],
+        }
+    }
+
+    /// Keep what the phases of `previous` sold and which of them the owner
+    /// opened, for the phases this schedule still has
+    pub fn carry_over(&mut self, previous: &SalePhases) {
+        let keep = |kept: &mut Vec<u64>, previous: &[u64]| {
+            for (kept, previous) in kept.iter_mut().zip(previous) {
+                *kept = *previous;
+            }
+        };
+        keep(&mut self.sold, &previous.sold);
+        keep(&mut self.purchases, &previous.purchases);
+        keep(&mut self.paid, &previous.paid);
+        self.opened = previous.opened.min(self.phases.len() as u8);
+    }
+
+    /// Load the phases from account `data`, ignoring unused bytes at the end
+    pub fn load(mut data: &[u8]) -> std::io::Result<Self> {
+        Self::deserialize(&mut data)
+    }
+
+    /// Whether `phases` closing at `end` make a schedule: one to
+    /// [MAX_PHASES](#associatedconstant.MAX_PHASES) phases opening in order,
+    /// each at a price that emits something, and an end after the last start
+    pub fn is_valid_schedule(phases: &[SalePhase], end: i64) -> bool {
+        let last = match phases.last() {
+            Some(last) if phases.len() <= Self::MAX_PHASES => last,
+            _ => return false,
+        };
+        phases
+            .iter()
+            .all(|phase| phase.price_numerator > 0 && phase.price_denominator > 0)
+            && phases.windows(2).all(|pair| pair[0].start <= pair[1].start)
+            && (end == 0 || end > last.start)
+    }
+
+    /// Whether the sale is over at the unix timestamp `now`
+    pub fn closed(&self, now: i64) -> bool {
+        self.end != 0 && now >= self.end
+    }
+
+    /// Index of the phase open at the unix timestamp `now`, `None` before
+    /// the first phase opens and once the sale closed
+    pub fn active(&self, now: i64) -> Option<usize> {
+        if self.closed(now) {
+            return None;
+        }
+        let by_clock = self.phases.iter().rposition(|phase| phase.start <= now);
+        let by_owner = (self.opened as usize).checked_sub(1);
+        by_clock.max(by_owner)
+    }
+
+    /// Check a purchase of `emitted` tokens for `paid` acceptable ones in the
+    /// phase at `index` against its terms and count it in the phase.
+    /// `allowlisted` purchases carry a voucher or a quote of the voucher signer.
+    pub fn record_purchase(
+        &mut self,
+        index: usize,
+        emitted: u64,
+        paid: u64,
+        allowlisted: bool,
+    ) -> Result<(), TokenMarketError> {
+        let phase = &self.phases[index];
+        if phase.allowlist_only && !allowlisted {
+            return Err(TokenMarketError::NotAllowlisted);
+        }
+        if phase.max_purchase != 0 && emitted > phase.max_purchase {
+            return Err(TokenMarketError::PhaseLimitExceeded);
+        }
+        let sold = self.sold[index]
+            .checked_add(emitted)
+            .filter(|sold| phase.cap == 0 || *sold <= phase.cap)
+            .ok_or(TokenMarketError::PhaseLimitExceeded)?;
+        self.sold[index] = sold;
+        // volume only feeds analytics, so it saturates instead of failing purchases
+        self.purchases[index] = self.purchases[index].saturating_add(1);
+        self.paid[index] = self.paid[index].saturating_add(paid);
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+//! Client-side validation of instruction inputs
+//!
+//! The plain builders in [instruction](../instruction/index.html) accept any
+//! pubkeys. The checked variants here load the referenced accounts first and
+//! report every problem at once instead of letting the transaction fail.
+//! [TransactionBuilder](struct.TransactionBuilder.html) does the same for a
+//! whole transaction, including whether the payer can cover fees and rent.
+
+use crate::{amounts::AcceptableAmount, instruction, state::TokenMarket};
+use borsh::BorshDeserialize;
+use solana_program::{
+    instruction::Instruction, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey,
+    system_instruction::SystemInstruction, system_program,
+};
+use solana_sdk::program_utils::limited_deserialize;
+use spl_token::state::Account;
+use std::fmt;
+use thiserror::Error;
+
+/// Account as loaded by an [AccountSource](trait.AccountSource.html)
+pub struct FetchedAccount {
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+}
+
+/// Where the checked builders load accounts from, usually an RPC client
+pub trait AccountSource {
+    /// Returns `None` if there is no account at `address`
+    fn fetch(&self, address: &Pubkey) -> Option<FetchedAccount>;
+}
+
+impl From<solana_sdk::account::Account> for FetchedAccount {
+    fn from(account: solana_sdk::account::Account) -> Self {
+        Self {
+            owner: account.owner,
+            lamports: account.lamports,
+            data: account.data,
+        }
+    }
+}
+
+impl<F: Fn(&Pubkey) -> Option<FetchedAccount>> AccountSource for F {
+    fn fetch(&self, address: &Pubkey) -> Option<FetchedAccount> {
+        self(address)
+    }
+}
+
+/// Single problem found while validating instruction inputs
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum Problem {
+    #[error("market {0} does not exist")]
+    MarketMissing(Pubkey),
+    #[error("market {0} is not owned by the token market program")]
+    MarketNotOwned(Pubkey),
+    #[error("market {0} is not initialized")]
+    MarketUninitialized(Pubkey),
+    #[error("market {market} is deprecated{}", successor_hint(.successor))]
+    MarketDeprecated {
+        market: Pubkey,
+        successor: Option<Pubkey>,
+    },
+    #[error("bank {actual} is not the market bank {expected}")]
+    WrongBank { expected: Pubkey, actual: Pubkey },
+    #[error("emitter {actual} is not the market emitter {expected}")]
+    WrongEmitter { expected: Pubkey, actual: Pubkey },
+    #[error("token account {0} does not exist")]
+    TokenAccountMissing(Pubkey),
+    #[error("{0} is not a token account")]
+    NotTokenAccount(Pubkey),
+    #[error("token account {account} holds {actual}, expected {expected}")]
+    WrongMint {
+        account: Pubkey,
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    #[error("token account {account} holds {available}, {requested} requested")]
+    InsufficientFunds {
+        account: Pubkey,
+        available: u64,
+        requested: u64,
+    },
+    #[error("amount must be positive")]
+    ZeroAmount,
+    #[error("account {0} does not exist")]
+    AccountMissing(Pubkey),
+    #[error("account {account} is owned by {actual}, expected {expected}")]
+    WrongOwner {
+        account: Pubkey,
+        expected: Pubkey,
+        actual: Pubkey,
+    },
+    #[error("payer {payer} has {available} lamports, {required} needed for fees and rent")]
+    InsufficientLamports {
+        payer: Pubkey,
+        available: u64,
+        required: u64,
+    },
+    #[error("can't build instruction: {0}")]
+    Instruction(ProgramError),
+}
+
+fn successor_hint(successor: &Option<Pubkey>) -> String {
+    match successor {
+        Some(successor) => format!(", its successor is {}", successor),
+        None => String::new(),
+    }
+}
+
+/// Every problem found while validating instruction inputs
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationError {
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} problem(s) found", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Load the token account at `address`, recording a problem if it is
+/// missing, malformed or holds a different mint than `mint`
+fn load_token_account(
+    source: &impl AccountSource,
+    address: &Pubkey,
+    mint: &Pubkey,
+    problems: &mut Vec<Problem>,
+) -> Option<Account> {
+    let fetched = match source.fetch(address) {
+        Some(fetched) => fetched,
+        None => {
+            problems.push(Problem::TokenAccountMissing(*address));
+            return None;
+        }
+    };
+    let account = match Account::unpack(&fetched.data) {
+        Ok(account) if fetched.owner == spl_token::id() => account,
+        _ => {
+            problems.push(Problem::NotTokenAccount(*address));
+            return None;
+        }
+    };
+    if account.mint != *mint {
+        problems.push(Problem::WrongMint {
+            account: *address,
+            expected: *mint,
+            actual: account.mint,
+        });
+    }
+    Some(account)
+}
+
+/// Create `BuyTokens` instruction after checking the market, the bank,
+/// the paying account and the recipient against the accounts in `source`.
+/// Deprecated markets are refused unless `allow_deprecated` is set.
+#[allow(clippy::too_many_arguments)]
+pub fn checked_buy_tokens(
+    source: &impl AccountSource,
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bank: &Pubkey,
+    emitter: &Pubkey,
+    recipient: &Pubkey,
+    write_off_acc: &Pubkey,
+    token_program: &Pubkey,
+    amount: AcceptableAmount,
+    allow_deprecated: bool,
+) -> Result<Instruction, ValidationError> {
+    let mut problems = vec![];
+    if amount.0 == 0 {
+        problems.push(Problem::ZeroAmount);
+    }
+
+    let token_market = match source.fetch(market) {
+        None => {
+            problems.push(Problem::MarketMissing(*market));
+            None
+        }
+        Some(fetched) if fetched.owner != *program_id => {
+            problems.push(Problem::MarketNotOwned(*market));
+            None
+        }
+        Some(fetched) => match TokenMarket::try_from_slice(&fetched.data) {
+            Ok(token_market) if token_market.is_initialized => Some(token_market),
+            _ => {
+                problems.push(Problem::MarketUninitialized(*market));
+                None
+            }
+        },
+    };
+
+    if let Some(token_market) = token_market {
+        if token_market.deprecated && !allow_deprecated {
+            problems.push(Problem::MarketDeprecated {
+                market: *market,
+                successor: token_market.successor(),
+            });
+        }
+        if token_market.bank != *bank {
+            problems.push(Problem::WrongBank {
+                expected: token_market.bank,
+                actual: *bank,
+            });
+        }
+        if token_market.emitter_mint != *emitter {
+            problems.push(Problem::WrongEmitter {
+                expected: token_market.emitter_mint,
+                actual: *emitter,
+            });
+        }
+        let write_off = load_token_account(
+            source,
+            write_off_acc,
+            &token_market.mint_of_acceptable,
+            &mut problems,
+        );
+        if let Some(write_off) = write_off {
+            if write_off.amount < amount.0 {
+                problems.push(Problem::InsufficientFunds {
+                    account: *write_off_acc,
+                    available: write_off.amount,
+                    requested: amount.0,
+                });
+            }
+        }
+        load_token_account(source, recipient, &token_market.emitter_mint, &mut problems);
+    }
+
+    if !problems.is_empty() {
+        return Err(ValidationError { problems });
+    }
+    instruction::buy_tokens(
+        program_id,
+        market,
+        bank,
+        emitter,
+        recipient,
+        write_off_acc,
+        token_program,
+        amount,
+    )
+    .map_err(|err| ValidationError {
+        problems: vec![Problem::Instruction(err)],
+    })
+}
+
+/// What a transaction expects of an account it references
+enum Expectation {
+    Owner { address: Pubkey, owner: Pubkey },
+    TokenAccount { address: Pubkey, mint: Pubkey },
+}
+
+/// Instructions of a transaction paid by `payer`, with the accounts they
+/// expect to find, checked all at once by [preflight](#method.preflight)
+pub struct TransactionBuilder {
+    payer: Pubkey,
+    instructions: Vec<Instruction>,
+    expectations: Vec<Expectation>,
+    rent: u64,
+}
+
+impl TransactionBuilder {
+    pub fn new(payer: &Pubkey) -> Self {
+        Self {
+            payer: *payer,
+            instructions: vec![],
+            expectations: vec![],
+            rent: 0,
+        }
+    }
+
+    pub fn instruction(&mut self, instruction: Instruction) -> &mut Self {
+        self.instructions.push(instruction);
+        self
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// `address` must exist and be owned by `owner`
+    pub fn expect_owner(&mut self, address: &Pubkey, owner: &Pubkey) -> &mut Self {
+        self.expectations.push(Expectation::Owner {
+            address: *address,
+            owner: *owner,
+        });
+        self
+    }
+
+    /// `address` must be a token account holding `mint`
+    pub fn expect_token_account(&mut self, address: &Pubkey, mint: &Pubkey) -> &mut Self {
+        self.expectations.push(Expectation::TokenAccount {
+            address: *address,
+            mint: *mint,
+        });
+        self
+    }
+
+    /// The payer also funds `lamports` of rent inside a program, e.g. for
+    /// PDAs the token market creates. Accounts created by the system
+    /// program in this transaction are counted without it
+    pub fn expect_rent(&mut self, lamports: u64) -> &mut Self {
+        self.rent = self.rent.saturating_add(lamports);
+        self
+    }
+
+    /// Lamports the payer spends: `fee`, the declared rent and every
+    /// account creation or transfer the system program makes from it
+    pub fn payer_spending(&self, fee: u64) -> u64 {
+        self.instructions
+            .iter()
+            .filter(|ix| ix.program_id == system_program::id())
+            .filter(|ix| ix.accounts.first().map(|meta| meta.pubkey) == Some(self.payer))
+            .filter_map(|ix| match limited_deserialize(&ix.data) {
+                Ok(SystemInstruction::CreateAccount { lamports, .. })
+                | Ok(SystemInstruction::Transfer { lamports }) => Some(lamports),
+                _ => None,
+            })
+            .fold(fee.saturating_add(self.rent), u64::saturating_add)
+    }
+
+    /// Check every expectation and the payer balance against the accounts
+    /// in `source`, reporting all problems together. `fee` is the network
+    /// fee of the transaction
+    pub fn preflight(&self, source: &impl AccountSource, fee: u64) -> Result<(), ValidationError> {
+        let mut problems = vec![];
+        for expectation in &self.expectations {
+            match expectation {
+                Expectation::Owner { address, owner } => match source.fetch(address) {
+                    None => problems.push(Problem::AccountMissing(*address)),
+                    Some(fetched) if fetched.owner != *owner => {
+                        problems.push(Problem::WrongOwner {
+                            account: *address,
+                            expected: *owner,
+                            actual: fetched.owner,
+                        })
+                    }
+                    Some(_) => {}
+                },
+                Expectation::TokenAccount { address, mint } => {
+                    load_token_account(source, address, mint, &mut problems);
+                }
+            }
+        }
+
+        let required = self.payer_spending(fee);
+        let available = source
+            .fetch(&self.payer)
+            .map_or(0, |fetched| fetched.lamports);
+        if available < required {
+            problems.push(Problem::InsufficientLamports {
+                payer: self.payer,
+                available,
+                required,
+            });
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { problems })
+        }
+    }
+}
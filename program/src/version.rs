@@ -0,0 +1,136 @@
+//! Version and features of a deployed program, as answered to `GetVersion`
+//!
+//! Clusters run different versions of the program, and a client encoding
+//! instructions for a newer one gets them rejected by an older one. The
+//! `GetVersion` instruction logs a line clients parse after simulating it:
+//!
+//! ```text
+//! Version: 0.1.0 65535
+//! ```
+//!
+//! The version of the crate the program is built from, then the bitmask of
+//! the [features](../state/struct.TokenMarket.html#associatedconstant.ALL_FEATURES)
+//! it knows. The runtime this program is built for has no return data, so
+//! the log carries the answer like it carries [events](../events/enum.Event.html).
+
+use crate::state::TokenMarket;
+use solana_program::msg;
+use std::{fmt, str::FromStr};
+
+/// Semantic version of the program crate
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProgramVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl ProgramVersion {
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Version of this build
+    pub fn current() -> Self {
+        let part = |part: &str| part.parse().unwrap_or_default();
+        Self::new(
+            part(env!("CARGO_PKG_VERSION_MAJOR")),
+            part(env!("CARGO_PKG_VERSION_MINOR")),
+            part(env!("CARGO_PKG_VERSION_PATCH")),
+        )
+    }
+}
+
+impl fmt::Display for ProgramVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for ProgramVersion {
+    type Err = ();
+
+    fn from_str(version: &str) -> Result<Self, ()> {
+        let mut parts = version.split('.').map(|part| part.parse().map_err(|_| ()));
+        let version = Self::new(
+            parts.next().ok_or(())??,
+            parts.next().ok_or(())??,
+            parts.next().ok_or(())??,
+        );
+        match parts.next() {
+            Some(_) => Err(()),
+            None => Ok(version),
+        }
+    }
+}
+
+/// What a deployed program supports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `None` for programs built before `GetVersion`
+    pub version: Option<ProgramVersion>,
+    /// Feature bits the program knows
+    pub features: u64,
+}
+
+impl Capabilities {
+    /// Programs built before `GetVersion` fail it as invalid instruction data.
+    /// They know none of the feature bits and read no
+    /// [trailing fields](../instruction/struct.TrailingFields.html).
+    pub const LEGACY: Capabilities = Capabilities {
+        version: None,
+        features: 0,
+    };
+
+    /// Capabilities of this build
+    pub fn current() -> Self {
+        Self {
+            version: Some(ProgramVersion::current()),
+            features: TokenMarket::ALL_FEATURES,
+        }
+    }
+
+    /// Write the capabilities to the program log
+    pub fn log(&self) {
+        msg!("{}", self);
+    }
+
+    /// Parse the capabilities from the line [log](#method.log) wrote, without
+    /// the runtime's `Program log: ` prefix. `None` if the line is another one.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.strip_prefix("Version: ")?.split(' ');
+        let version = ProgramVersion::from_str(fields.next()?).ok()?;
+        let features = fields.next()?.parse().ok()?;
+        match fields.next() {
+            Some(_) => None,
+            None => Some(Self {
+                version: Some(version),
+                features,
+            }),
+        }
+    }
+
+    /// Whether the program knows every bit of `features`
+    pub fn supports(&self, features: u64) -> bool {
+        features & !self.features == 0
+    }
+
+    /// Whether the program reads the trailing fields of `Initialize`: the
+    /// features, then the minimum and maximum purchase
+    pub fn reads_initialize_fields(&self) -> bool {
+        self.version.is_some()
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.version {
+            Some(version) => write!(f, "Version: {} {}", version, self.features),
+            None => f.write_str("Version: legacy"),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Wormhole messages attesting purchases paid on other chains
+//!
+//! The Wormhole core bridge verifies the guardian signatures of a VAA and
+//! posts its message to an account it owns. Markets with a
+//! [registered emitter](../state/struct.TokenMarket.html#structfield.wormhole_emitter)
+//! redeem such posted messages carrying a [PurchasePayload](struct.PurchasePayload.html),
+//! see [RedeemWormholePurchase](../instruction/enum.TokenMarketInstructions.html#variant.RedeemWormholePurchase).
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use std::convert::TryInto;
+
+solana_program::declare_id!("worm2ZoG2kUd4vFXhvjh93UUH596ayRfgQ2MgjNMTth");
+
+/// Message of a verified VAA as the core bridge posts it, after [PostedVaa::MAGIC](#associatedconstant.MAGIC)
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct PostedVaa {
+    pub vaa_version: u8,
+    pub consistency_level: u8,
+    pub vaa_time: u32,
+    pub vaa_signature_account: Pubkey,
+    pub submission_time: u32,
+    pub nonce: u32,
+    pub sequence: u64,
+    pub emitter_chain: u16,
+    pub emitter_address: [u8; 32],
+    pub payload: Vec<u8>,
+}
+
+impl PostedVaa {
+    /// Prefix of posted VAA accounts
+    pub const MAGIC: &'static [u8] = b"vaa";
+
+    /// Parse the data of a posted VAA account
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        match data.strip_prefix(Self::MAGIC) {
+            Some(mut message) => {
+                Self::deserialize(&mut message).map_err(|_| ProgramError::InvalidAccountData)
+            }
+            None => Err(ProgramError::InvalidAccountData),
+        }
+    }
+
+    /// Data of a posted VAA account with this message
+    pub fn pack(&self) -> Result<Vec<u8>, ProgramError> {
+        let mut data = Self::MAGIC.to_vec();
+        self.serialize(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Payment on another chain, encoded by the emitter contract as the market,
+/// the owner of the recipient token account and the paid amount of acceptable
+/// tokens as a big-endian `u64`, the way EVM contracts pack them
+#[derive(Clone, Debug, PartialEq)]
+pub struct PurchasePayload {
+    pub market: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+impl PurchasePayload {
+    pub const LEN: usize = 32 * 2 + 8;
+
+    pub fn unpack(payload: &[u8]) -> Option<Self> {
+        if payload.len() != Self::LEN {
+            return None;
+        }
+        Some(PurchasePayload {
+            market: Pubkey::new(&payload[..32]),
+            recipient: Pubkey::new(&payload[32..64]),
+            amount: u64::from_be_bytes(payload[64..].try_into().ok()?),
+        })
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(Self::LEN);
+        payload.extend_from_slice(self.market.as_ref());
+        payload.extend_from_slice(self.recipient.as_ref());
+        payload.extend_from_slice(&self.amount.to_be_bytes());
+        payload
+    }
+}
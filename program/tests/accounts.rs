@@ -0,0 +1,54 @@
+use solana_program::pubkey::Pubkey;
+use token_market::{accounts::*, amounts::AcceptableAmount, instruction, state::find_authority_address};
+
+#[test]
+fn test_buy_tokens_accounts_by_role() {
+    let accounts = BuyTokensAccounts {
+        market: Pubkey::new_unique(),
+        bank: Pubkey::new_unique(),
+        recipient: Pubkey::new_unique(),
+        write_off: Pubkey::new_unique(),
+        emitter: Pubkey::new_unique(),
+        authority: find_authority_address(&token_market::id()).0,
+        token_program: spl_token::id(),
+    };
+    let metas = accounts.to_metas();
+    assert_eq!(metas[BuyTokensAccounts::MARKET].pubkey, accounts.market);
+    assert_eq!(metas[BuyTokensAccounts::WRITE_OFF].pubkey, accounts.write_off);
+    assert_eq!(metas.len(), BuyTokensAccounts::TOKEN_PROGRAM + 1);
+
+    let ix = instruction::buy_tokens(
+        &token_market::id(),
+        &accounts.market,
+        &accounts.bank,
+        &accounts.emitter,
+        &accounts.recipient,
+        &accounts.write_off,
+        &accounts.token_program,
+        AcceptableAmount(1),
+    )
+    .unwrap();
+    assert_eq!(ix.accounts, metas);
+}
+
+#[test]
+fn test_claim_vested_accounts_by_role() {
+    let beneficiary = Pubkey::new_unique();
+    let ix = instruction::claim_vested(
+        &token_market::id(),
+        &beneficiary,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+    )
+    .unwrap();
+
+    let signer = &ix.accounts[ClaimVestedAccounts::BENEFICIARY];
+    assert_eq!(signer.pubkey, beneficiary);
+    assert!(signer.is_signer);
+    assert_eq!(
+        ix.accounts[ClaimVestedAccounts::AUTHORITY].pubkey,
+        find_authority_address(&token_market::id()).0
+    );
+    assert_eq!(ix.accounts[ClaimVestedAccounts::TOKEN_PROGRAM].pubkey, spl_token::id());
+}
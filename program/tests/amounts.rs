@@ -0,0 +1,26 @@
+use token_market::amounts::*;
+
+#[test]
+fn test_ui_amount_to_raw() {
+    let amount: UiAmount = "1.5".parse().unwrap();
+    assert_eq!(amount.to_raw(6), Some(1_500_000));
+    assert_eq!(amount.to_acceptable(1), Some(AcceptableAmount(15)));
+    assert_eq!(amount.to_emitted(0), None);
+
+    assert_eq!("7".parse::<UiAmount>().unwrap().to_raw(0), Some(7));
+    assert_eq!(".25".parse::<UiAmount>().unwrap().to_raw(2), Some(25));
+    assert_eq!("18446744073709551616".parse::<UiAmount>().unwrap().to_raw(0), None);
+
+    assert!("".parse::<UiAmount>().is_err());
+    assert!(".".parse::<UiAmount>().is_err());
+    assert!("1.2.3".parse::<UiAmount>().is_err());
+    assert!("-1".parse::<UiAmount>().is_err());
+}
+
+#[test]
+fn test_ui_amount_display() {
+    assert_eq!(UiAmount::from_raw(1_500_000, 6).to_string(), "1.5");
+    assert_eq!(UiAmount::from_raw(5, 3).to_string(), "0.005");
+    assert_eq!(UiAmount::from_raw(42, 0).to_string(), "42");
+    assert_eq!(UiAmount::from_raw(0, 2).to_string(), "0");
+}
@@ -0,0 +1,64 @@
+use token_market::{
+    curve::{check_bonding_curve, emitted_for, quote, PRICE_SCALE},
+    error::TokenMarketError,
+    state::{BondingCurve, CurveKind},
+};
+
+fn curve(kind: CurveKind, base_price: u64, growth: u64, step: u64) -> BondingCurve {
+    BondingCurve {
+        kind,
+        base_price,
+        growth,
+        step,
+    }
+}
+
+#[test]
+fn test_linear_curve() {
+    let flat = curve(CurveKind::Linear, PRICE_SCALE, 0, 0);
+    assert_eq!(quote(&flat, 1000, 500), Some(500));
+    assert_eq!(emitted_for(&flat, 1000, 500), Some(500));
+
+    // the price grows from 1 to 2 over the first PRICE_SCALE tokens
+    let linear = curve(CurveKind::Linear, PRICE_SCALE, PRICE_SCALE, 0);
+    assert_eq!(quote(&linear, 0, PRICE_SCALE), Some(PRICE_SCALE / 2 * 3));
+    assert_eq!(emitted_for(&linear, 0, PRICE_SCALE / 2 * 3), Some(PRICE_SCALE));
+    // later tokens cost more than the same number of earlier ones
+    assert!(quote(&linear, PRICE_SCALE, 1000) > quote(&linear, 0, 1000));
+
+    let steep = curve(CurveKind::Linear, PRICE_SCALE, u64::MAX, 0);
+    assert_eq!(quote(&steep, u64::MAX / 2, u64::MAX / 2), None);
+}
+
+#[test]
+fn test_exponential_curve() {
+    // the price doubles every 100 tokens sold
+    let doubling = curve(CurveKind::Exponential, PRICE_SCALE, 10_000, 100);
+    assert_eq!(quote(&doubling, 0, 100), Some(100));
+    assert_eq!(quote(&doubling, 0, 300), Some(100 + 200 + 400));
+    assert_eq!(quote(&doubling, 50, 100), Some(50 + 100));
+    assert_eq!(quote(&doubling, 250, 100), Some(50 * 4 + 50 * 8));
+
+    assert_eq!(emitted_for(&doubling, 0, 700), Some(300));
+    assert_eq!(emitted_for(&doubling, 0, 707), Some(300));
+    assert_eq!(emitted_for(&doubling, 0, 708), Some(301));
+
+    let flat = curve(CurveKind::Exponential, PRICE_SCALE / 2, 0, 100);
+    assert_eq!(quote(&flat, 30, 250), Some(125));
+}
+
+#[test]
+fn test_check_bonding_curve() {
+    assert_eq!(check_bonding_curve(&BondingCurve::default()), Ok(()));
+    assert_eq!(emitted_for(&BondingCurve::default(), 0, 100), None);
+    for invalid in &[
+        curve(CurveKind::Linear, 0, 1, 0),
+        curve(CurveKind::Exponential, PRICE_SCALE, 100, 0),
+    ] {
+        assert_eq!(
+            check_bonding_curve(invalid),
+            Err(TokenMarketError::InvalidBondingCurve)
+        );
+        assert_eq!(emitted_for(invalid, 0, 100), None);
+    }
+}
@@ -0,0 +1,150 @@
+use solana_program::{hash::hash, pubkey::Pubkey};
+use token_market::{events::Event, state::OverpaymentPolicy};
+
+#[test]
+fn test_event_log_lines() {
+    let market = Pubkey::new(&[1; 32]);
+    assert_eq!(
+        Event::Overpayment {
+            market,
+            policy: OverpaymentPolicy::Refund,
+            excess: 8,
+        }
+        .to_string(),
+        format!("Event: Overpayment {} Refund 8", market)
+    );
+    assert_eq!(
+        Event::CampaignSale {
+            market,
+            campaign: *b"SALE\0\0\0\0",
+            amount: 30,
+        }
+        .to_string(),
+        format!("Event: CampaignSale {} SALE 30", market)
+    );
+}
+
+#[test]
+fn test_event_parse_round_trip() {
+    let market = Pubkey::new(&[1; 32]);
+    let events = [
+        Event::TreasuryChanged {
+            market,
+            old: Pubkey::default(),
+            new: Pubkey::new(&[2; 32]),
+        },
+        Event::BankRecreated {
+            market,
+            old: Pubkey::new(&[5; 32]),
+            new: Pubkey::new(&[6; 32]),
+        },
+        Event::Purchase {
+            market,
+            buyer: Pubkey::new(&[7; 32]),
+            amount: 50,
+            memo_hash: hash(b"invoice 42"),
+        },
+        Event::WormholePurchase {
+            market,
+            buyer: Pubkey::new(&[7; 32]),
+            amount: 50,
+            sequence: 3,
+        },
+        Event::FiatPurchase {
+            market,
+            buyer: Pubkey::new(&[8; 32]),
+            amount: 75,
+            reference: hash(b"card-payment-7"),
+        },
+        Event::PurchaseEscrowed {
+            market,
+            buyer: Pubkey::new(&[8; 32]),
+            escrow: Pubkey::new(&[9; 32]),
+            amount: 5000,
+        },
+        Event::EscrowReleased {
+            market,
+            escrow: Pubkey::new(&[9; 32]),
+            amount: 5000,
+        },
+        Event::EscrowDisputed {
+            market,
+            escrow: Pubkey::new(&[9; 32]),
+            refund: 5000,
+        },
+        Event::Overpayment {
+            market,
+            policy: OverpaymentPolicy::Donate,
+            excess: 8,
+        },
+        Event::ReceiptMinted {
+            market,
+            receipt_mint: Pubkey::new(&[3; 32]),
+            buyer: Pubkey::new(&[4; 32]),
+        },
+        Event::CampaignSale {
+            market,
+            campaign: *b"SALE\0\0\0\0",
+            amount: 30,
+        },
+        Event::CouponRedeemed {
+            market,
+            coupon: Pubkey::new(&[10; 32]),
+            bonus: 12,
+        },
+        Event::GiftPurchased {
+            market,
+            buyer: Pubkey::new(&[8; 32]),
+            gift: Pubkey::new(&[11; 32]),
+            amount: 40,
+        },
+        Event::GiftClaimed {
+            market,
+            gift: Pubkey::new(&[11; 32]),
+            recipient: Pubkey::new(&[4; 32]),
+            amount: 40,
+        },
+        Event::GiftRefunded {
+            market,
+            gift: Pubkey::new(&[11; 32]),
+            refund: 40,
+        },
+        Event::TokensSold {
+            market,
+            seller: Pubkey::new(&[8; 32]),
+            amount: 25,
+        },
+        Event::BankWithdrawn {
+            market,
+            destination: Pubkey::new(&[12; 32]),
+            amount: 60,
+        },
+        Event::PhaseSale {
+            market,
+            phase: 1,
+            amount: 100,
+            paid: 50,
+        },
+        Event::ProtocolFee {
+            market,
+            fee_vault: Pubkey::new(&[13; 32]),
+            amount: 2,
+        },
+        Event::FeesWithdrawn {
+            market,
+            fee_vault: Pubkey::new(&[13; 32]),
+            destination: Pubkey::new(&[12; 32]),
+            amount: 2,
+        },
+    ];
+    for event in &events {
+        assert_eq!(Event::parse(&event.to_string()).as_ref(), Some(event));
+    }
+
+    assert_eq!(Event::parse("Instruction: BuyTokens"), None);
+    assert_eq!(Event::parse("Event: Unknown 1"), None);
+    assert_eq!(
+        Event::parse(&format!("Event: Overpayment {} Refund 8 9", market)),
+        None
+    );
+}
@@ -0,0 +1,156 @@
+use borsh::BorshSerialize;
+use solana_program::pubkey::Pubkey;
+use token_market::{
+    filters,
+    state::{find_mint_index_address, BondingCurve, BuyerRecord, LoyaltyTier, MintIndex, PurchaseReceipt, OverpaymentPolicy, PricingTier, StakeWeight, TokenMarket, VestingVault},
+    validation::FetchedAccount,
+};
+
+fn sample_market() -> TokenMarket {
+    TokenMarket {
+        is_initialized: true,
+        owner: Pubkey::new_unique(),
+        bank: Pubkey::new_unique(),
+        emitter_mint: Pubkey::new_unique(),
+        authority: Pubkey::new_unique(),
+        mint_of_acceptable: Pubkey::new_unique(),
+        legacy_bank: Pubkey::default(),
+        legacy_mint_of_acceptable: Pubkey::default(),
+        voucher_signer: [1; 20],
+        category: *b"IDO\0",
+        project_id: [7; 32],
+        treasury: Pubkey::new_unique(),
+        deprecated: false,
+        successor: Pubkey::default(),
+        overpayment_policy: OverpaymentPolicy::Reject,
+        mint_receipts: false,
+        features: TokenMarket::ALL_FEATURES,
+        require_memo: false,
+        sandwich_guard: false,
+        wormhole_chain: 0,
+        wormhole_emitter: [0; 32],
+        onramp_signer: Pubkey::default(),
+        escrow_threshold: 0,
+        dispute_window: 0,
+        dispute_guardian: Pubkey::default(),
+        paused: false,
+        phased: false,
+        fee_bps: 0,
+        fee_vault: Pubkey::default(),
+        min_purchase: 0,
+        max_purchase: 0,
+        wallet_cap: 0,
+        sale_start: 0,
+        sale_end: 0,
+        allowlist_enabled: false,
+        allowlist_root: [0; 32],
+        pricing_tiers: [PricingTier::default(); TokenMarket::MAX_PRICING_TIERS],
+        total_sold: 0,
+        bonding_curve: BondingCurve::default(),
+        stake_weight: StakeWeight::default(),
+        withdraw_rate: 0,
+        last_withdraw: 0,
+        wallet_spend_cap: 0,
+        purchase_cooldown_secs: 0,
+        loyalty_tiers: [LoyaltyTier::default(); TokenMarket::MAX_LOYALTY_TIERS],
+    }
+}
+
+#[test]
+fn test_filters_match_serialized_market() {
+    let token_market = sample_market();
+    let data = token_market.try_to_vec().unwrap();
+    assert_eq!(data.len() as u64, filters::MARKET_DATA_SIZE);
+
+    assert!(filters::by_owner(&token_market.owner).matches(&data));
+    assert!(filters::by_category(*b"IDO\0").matches(&data));
+    assert!(filters::by_project([7; 32]).matches(&data));
+    assert!(filters::initialized().matches(&data));
+    assert!(!filters::by_owner(&token_market.bank).matches(&data));
+    assert!(!filters::by_category(*b"DAO\0").matches(&data));
+    assert!(!filters::by_project([8; 32]).matches(&data));
+}
+
+#[test]
+fn test_offsets_match_serialized_market() {
+    let mut token_market = sample_market();
+    token_market.paused = true;
+    token_market.fee_bps = 250;
+    token_market.total_sold = 1_000_000;
+    let data = token_market.try_to_vec().unwrap();
+    let at = |offset: usize, len: usize| &data[offset..offset + len];
+
+    assert_eq!(at(TokenMarket::EMITTER_MINT_OFFSET, 32), token_market.emitter_mint.as_ref());
+    assert_eq!(at(TokenMarket::MINT_OF_ACCEPTABLE_OFFSET, 32), token_market.mint_of_acceptable.as_ref());
+    assert_eq!(at(TokenMarket::FEATURES_OFFSET, 8), &TokenMarket::ALL_FEATURES.to_le_bytes());
+    assert_eq!(at(TokenMarket::PAUSED_OFFSET, 1), &[1]);
+    assert_eq!(at(TokenMarket::FEE_BPS_OFFSET, 2), &250u16.to_le_bytes());
+    assert_eq!(at(TokenMarket::TOTAL_SOLD_OFFSET, 8), &1_000_000u64.to_le_bytes());
+}
+
+#[test]
+fn test_vestings_of_market() {
+    let market = Pubkey::new_unique();
+    let vesting = VestingVault {
+        is_initialized: true,
+        market,
+        beneficiary: Pubkey::new_unique(),
+        vault: Pubkey::new_unique(),
+        total: 100,
+        released: 0,
+        start: 0,
+        cliff: 0,
+        duration: 10,
+        revocable: false,
+        revoked: false,
+    };
+    let data = vesting.try_to_vec().unwrap();
+    assert_eq!(data.len() as u64, filters::VESTING_DATA_SIZE);
+
+    assert!(filters::vestings_of(&market).matches(&data));
+    assert!(!filters::vestings_of(&vesting.beneficiary).matches(&data));
+}
+
+#[test]
+fn test_records_of_market() {
+    let market = Pubkey::new_unique();
+    let record = BuyerRecord { market, buyer: Pubkey::new_unique(), purchased: 1, spent: 1, last_purchase: 0 };
+    let data = record.try_to_vec().unwrap();
+    assert_eq!(data.len() as u64, filters::BUYER_RECORD_DATA_SIZE);
+    assert!(filters::records_of(&market).matches(&data));
+    assert!(!filters::records_of(&record.buyer).matches(&data));
+
+    let receipt = PurchaseReceipt { market, buyer: record.buyer, amount: 1, slot: 1, campaign: [0; 8] };
+    let data = receipt.try_to_vec().unwrap();
+    assert_eq!(data.len() as u64, filters::RECEIPT_DATA_SIZE);
+    assert!(filters::records_of(&market).matches(&data));
+}
+
+#[test]
+fn test_filter_bytes_are_base58() {
+    let owner = Pubkey::new_unique();
+    assert_eq!(filters::by_owner(&owner).encoded_bytes(), owner.to_string());
+}
+
+#[test]
+fn test_find_market_by_emitter() {
+    let emitter = Pubkey::new_unique();
+    let market = Pubkey::new_unique();
+    let index = find_mint_index_address(&token_market::id(), &emitter).0;
+    let source = |address: &Pubkey| {
+        (*address == index).then(|| FetchedAccount {
+            owner: token_market::id(),
+            lamports: 0,
+            data: MintIndex { market }.try_to_vec().unwrap(),
+        })
+    };
+
+    assert_eq!(
+        filters::find_market_by_emitter(&source, &token_market::id(), &emitter),
+        Some(market)
+    );
+    assert_eq!(
+        filters::find_market_by_emitter(&source, &token_market::id(), &market),
+        None
+    );
+}
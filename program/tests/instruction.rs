@@ -0,0 +1,55 @@
+use borsh::BorshDeserialize;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use token_market::{
+    amounts::AcceptableAmount,
+    instruction::{self, TokenMarketInstructions},
+};
+
+fn buy_tokens() -> solana_program::instruction::Instruction {
+    instruction::buy_tokens(
+        &token_market::id(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &spl_token::id(),
+        AcceptableAmount(30),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_unpack_across_versions() {
+    // A client that predates a trailing field omits it
+    let old = buy_tokens();
+    let (decoded, mut trailing) = TokenMarketInstructions::unpack(&old.data).unwrap();
+    assert_eq!(decoded, TokenMarketInstructions::BuyTokens { amount: 30 });
+    assert_eq!(trailing.read::<[u8; 8]>().unwrap(), None);
+
+    // A program that predates it ignores it, a newer one reads it
+    let new = instruction::with_trailing_field(buy_tokens(), &[7u8; 8]).unwrap();
+    let new = instruction::with_trailing_field(new, &5u64).unwrap();
+    assert!(TokenMarketInstructions::try_from_slice(&new.data).is_err());
+    let (decoded, mut trailing) = TokenMarketInstructions::unpack(&new.data).unwrap();
+    assert_eq!(decoded, TokenMarketInstructions::BuyTokens { amount: 30 });
+    assert_eq!(trailing.read::<[u8; 8]>().unwrap(), Some([7; 8]));
+    assert_eq!(trailing.read::<u64>().unwrap(), Some(5));
+    assert_eq!(trailing.read::<u64>().unwrap(), None);
+}
+
+#[test]
+fn test_unpack_rejects_truncated_fields() {
+    let mut truncated = buy_tokens();
+    truncated.data.extend(&[1, 2, 3]);
+    let (_, mut trailing) = TokenMarketInstructions::unpack(&truncated.data).unwrap();
+    assert_eq!(
+        trailing.read::<u64>(),
+        Err(ProgramError::InvalidInstructionData)
+    );
+
+    assert_eq!(
+        TokenMarketInstructions::unpack(&[]).err(),
+        Some(ProgramError::InvalidInstructionData)
+    );
+}
@@ -0,0 +1,35 @@
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError};
+use token_market::{error::TokenMarketError, invariant};
+
+fn guarded(fee: u64, proceeds: u64) -> ProgramResult {
+    invariant!(fee <= proceeds, "fee {} of proceeds {}", fee, proceeds);
+    Ok(())
+}
+
+#[test]
+fn test_invariant() {
+    assert_eq!(guarded(3, 100), Ok(()));
+    assert_eq!(guarded(100, 100), Ok(()));
+
+    #[cfg(feature = "debug-assert-onchain")]
+    assert_eq!(
+        guarded(101, 100),
+        Err(ProgramError::from(TokenMarketError::InvariantViolated))
+    );
+    // production builds leave the check out
+    #[cfg(not(feature = "debug-assert-onchain"))]
+    assert_eq!(guarded(101, 100), Ok(()));
+}
+
+#[cfg(feature = "debug-assert-onchain")]
+#[test]
+fn test_balances_moved() {
+    use token_market::invariants::Balances;
+
+    let before = Balances { bank: 1_000, supply: 50 };
+    let after = Balances { bank: 1_097, supply: 150 };
+    assert!(after.moved(&before, 97, 100));
+    assert!(!after.moved(&before, 100, 100));
+    assert!(!after.moved(&before, 97, 99));
+    assert!(!before.moved(&after, 0, 0));
+}
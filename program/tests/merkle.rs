@@ -0,0 +1,29 @@
+use solana_program::pubkey::Pubkey;
+use token_market::merkle::{allowlist_leaf, parent, verify, MerkleTree};
+
+#[test]
+fn test_every_buyer_proves_membership() {
+    for count in 1..=9 {
+        let buyers: Vec<Pubkey> = (0..count).map(|_| Pubkey::new_unique()).collect();
+        let tree = MerkleTree::new(&buyers);
+        for (index, buyer) in buyers.iter().enumerate() {
+            let proof = tree.proof(index).unwrap();
+            assert!(verify(&tree.root(), allowlist_leaf(buyer), &proof));
+        }
+        assert_eq!(tree.proof(count), None);
+    }
+}
+
+#[test]
+fn test_outsiders_fail() {
+    let buyers: Vec<Pubkey> = (0..5).map(|_| Pubkey::new_unique()).collect();
+    let tree = MerkleTree::new(&buyers);
+    let proof = tree.proof(0).unwrap();
+    assert!(!verify(&tree.root(), allowlist_leaf(&Pubkey::new_unique()), &proof));
+    assert!(!verify(&tree.root(), allowlist_leaf(&buyers[0]), &proof[1..]));
+    // Siblings hash the same in either order
+    let (a, b) = (allowlist_leaf(&buyers[0]), allowlist_leaf(&buyers[1]));
+    assert_eq!(parent(&a, &b), parent(&b, &a));
+
+    assert_eq!(MerkleTree::new(&[]).root(), [0; 32]);
+}
@@ -0,0 +1,198 @@
+use borsh::BorshDeserialize;
+use solana_program::program_error::ProgramError;
+use token_market::{
+    error::TokenMarketError,
+    pricing::{check_loyalty_tiers, check_pricing_tiers, cost_across_tiers, emitted_across_tiers, explain_purchase, settle_purchase_at, Price},
+    state::{LoyaltyTier, OverpaymentPolicy, PricingTier, TokenMarket},
+};
+
+#[test]
+fn test_settle_purchase_at() {
+    let price = Price {
+        numerator: 3,
+        denominator: 2,
+    };
+    let settlement = settle_purchase_at(OverpaymentPolicy::Refund, price, 31, 1, 34).unwrap();
+    assert_eq!(settlement.emitted, 46);
+    assert_eq!(settlement.proceeds(), 31);
+    assert_eq!(settlement.refund, 2);
+
+    assert_eq!(
+        settle_purchase_at(OverpaymentPolicy::Reject, Price::PAR, 30, 0, 30)
+            .unwrap()
+            .emitted,
+        30
+    );
+
+    let dust = Price {
+        numerator: 1,
+        denominator: 1000,
+    };
+    assert_eq!(
+        settle_purchase_at(OverpaymentPolicy::Reject, dust, 999, 0, 999),
+        Err(ProgramError::InvalidArgument)
+    );
+    let invalid = Price {
+        numerator: 1,
+        denominator: 0,
+    };
+    assert_eq!(
+        settle_purchase_at(OverpaymentPolicy::Reject, invalid, 10, 0, 10),
+        Err(ProgramError::InvalidArgument)
+    );
+}
+
+#[test]
+fn test_explain_purchase() {
+    let price = Price {
+        numerator: 3,
+        denominator: 2,
+    };
+    let breakdown = explain_purchase(OverpaymentPolicy::Refund, price, 31, 1, 34, 250).unwrap();
+    assert_eq!(breakdown.cost, 93);
+    assert_eq!((breakdown.settlement.emitted, breakdown.remainder), (46, 1));
+    assert_eq!(breakdown.settlement.refund, 2);
+    // 250 bps of the 31 the bank keeps
+    assert_eq!((breakdown.fee_product, breakdown.fee), (7750, 0));
+    assert_eq!(
+        breakdown.to_string().lines().nth(3),
+        Some("emitted 46 = 93 / 2 rounded down, remainder 1")
+    );
+
+    let dust = Price {
+        numerator: 1,
+        denominator: 1000,
+    };
+    assert_eq!(
+        explain_purchase(OverpaymentPolicy::Reject, dust, 999, 0, 999, 0),
+        Err(ProgramError::InvalidArgument)
+    );
+}
+
+fn tier(up_to: u64, price_numerator: u64, price_denominator: u64) -> PricingTier {
+    PricingTier {
+        up_to,
+        price_numerator,
+        price_denominator,
+    }
+}
+
+#[test]
+fn test_emitted_across_tiers() {
+    let tiers = [tier(100, 2, 1), tier(0, 1, 1), PricingTier::default()];
+    assert_eq!(emitted_across_tiers(&tiers, 0, 30), Some(60));
+    // 50 buy the 100 left at 2/1, the other 10 sell at par
+    assert_eq!(emitted_across_tiers(&tiers, 0, 60), Some(110));
+    assert_eq!(emitted_across_tiers(&tiers, 90, 10), Some(15));
+    assert_eq!(emitted_across_tiers(&tiers, 100, 10), Some(10));
+
+    // the rest of a tier is paid rounded up
+    let tiers = [tier(10, 3, 2), tier(0, 1, 1)];
+    assert_eq!(emitted_across_tiers(&tiers, 0, 7), Some(10));
+    assert_eq!(emitted_across_tiers(&tiers, 0, 8), Some(11));
+
+    let bounded = [tier(100, 1, 1)];
+    assert_eq!(emitted_across_tiers(&bounded, 50, 50), Some(50));
+    assert_eq!(emitted_across_tiers(&bounded, 50, 60), None);
+    assert_eq!(emitted_across_tiers(&bounded, 100, 1), None);
+}
+
+#[test]
+fn test_cost_across_tiers() {
+    let tiers = [tier(100, 2, 1), tier(0, 1, 1), PricingTier::default()];
+    assert_eq!(cost_across_tiers(&tiers, 0, 60), Some(30));
+    assert_eq!(cost_across_tiers(&tiers, 0, 110), Some(60));
+    assert_eq!(cost_across_tiers(&tiers, 90, 15), Some(10));
+
+    // the part bought in a tier is paid rounded up, and buys at least as much
+    let tiers = [tier(10, 3, 2), tier(0, 1, 1)];
+    assert_eq!(cost_across_tiers(&tiers, 0, 10), Some(7));
+    assert_eq!(cost_across_tiers(&tiers, 0, 11), Some(8));
+    assert_eq!(emitted_across_tiers(&tiers, 0, 8), Some(11));
+
+    let bounded = [tier(100, 1, 1)];
+    assert_eq!(cost_across_tiers(&bounded, 50, 50), Some(50));
+    assert_eq!(cost_across_tiers(&bounded, 50, 60), None);
+}
+
+#[test]
+fn test_check_pricing_tiers() {
+    assert_eq!(check_pricing_tiers(&[tier(100, 2, 1), tier(0, 1, 1)]), Ok(()));
+    assert_eq!(check_pricing_tiers(&[tier(100, 2, 1), tier(200, 1, 1)]), Ok(()));
+    for invalid in &[
+        vec![tier(100, 2, 1), tier(100, 1, 1)],
+        vec![tier(0, 2, 1), tier(100, 1, 1)],
+        vec![tier(100, 0, 1)],
+        vec![tier(100, 1, 0)],
+    ] {
+        assert_eq!(
+            check_pricing_tiers(invalid),
+            Err(TokenMarketError::InvalidPricingTiers)
+        );
+    }
+}
+
+fn loyalty(threshold: u64, discount_bps: u16) -> LoyaltyTier {
+    LoyaltyTier {
+        threshold,
+        discount_bps,
+    }
+}
+
+#[test]
+fn test_check_loyalty_tiers() {
+    assert_eq!(check_loyalty_tiers(&[]), Ok(()));
+    assert_eq!(check_loyalty_tiers(&[loyalty(1000, 200), loyalty(5000, 500)]), Ok(()));
+    for invalid in &[
+        vec![loyalty(0, 200)],
+        vec![loyalty(1000, 0)],
+        vec![loyalty(1000, 10_000)],
+        vec![loyalty(1000, 200), loyalty(1000, 500)],
+        vec![loyalty(1000, 500), loyalty(5000, 500)],
+    ] {
+        assert_eq!(
+            check_loyalty_tiers(invalid),
+            Err(TokenMarketError::InvalidLoyaltyTiers)
+        );
+    }
+}
+
+#[test]
+fn test_loyalty_discount_thresholds() {
+    let mut token_market = TokenMarket::try_from_slice(&[0; TokenMarket::LEN]).unwrap();
+    assert_eq!(token_market.loyalty_discount(u64::MAX), 0);
+    token_market.loyalty_tiers[0] = loyalty(1000, 200);
+    token_market.loyalty_tiers[1] = loyalty(5000, 500);
+    assert_eq!(token_market.loyalty_discount(999), 0);
+    // the threshold itself reaches the tier
+    assert_eq!(token_market.loyalty_discount(1000), 200);
+    assert_eq!(token_market.loyalty_discount(4999), 200);
+    assert_eq!(token_market.loyalty_discount(5000), 500);
+    assert_eq!(token_market.loyalty_discount(u64::MAX), 500);
+}
+
+#[test]
+fn test_loyalty_discount_rounding() {
+    let emitted = |price: Price, discount_bps, amount| {
+        let discounted = price.discounted(discount_bps).unwrap();
+        settle_purchase_at(OverpaymentPolicy::Reject, discounted, amount, 0, amount)
+            .unwrap()
+            .emitted
+    };
+    // 2% off emits 10000 / 9800 per token, rounded down
+    assert_eq!(emitted(Price::PAR, 200, 49), 50);
+    assert_eq!(emitted(Price::PAR, 200, 48), 48);
+    assert_eq!(emitted(Price::PAR, 200, 1000), 1020);
+    // a discount worth less than a token emits nothing more
+    assert_eq!(emitted(Price::PAR, 1, 99), 99);
+    assert_eq!(emitted(Price::PAR, 1, 1), 1);
+    let price = Price {
+        numerator: 3,
+        denominator: 2,
+    };
+    assert_eq!(emitted(price, 333, 100), 155);
+    // the buyer never pays more than without the discount
+    for amount in 1..200 {
+        assert!(emitted(price, 333, amount) >= price.emitted(amount).unwrap());
+    }
+}
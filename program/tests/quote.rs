@@ -0,0 +1,58 @@
+use solana_program::{program_pack::Pack, rent::Rent};
+use spl_token::state::{Account, Mint};
+use token_market::{
+    amounts::{AcceptableAmount, EmittedAmount},
+    quote::{quote_purchase, PurchaseSetup},
+    state::{CampaignStats, PurchaseReceipt},
+};
+
+#[test]
+fn test_quote_purchase() {
+    let rent = Rent::default();
+    let plain = quote_purchase(
+        AcceptableAmount(30),
+        AcceptableAmount(0),
+        PurchaseSetup::default(),
+        &rent,
+        5000,
+    );
+    assert_eq!(plain.emitted, EmittedAmount(30));
+    assert_eq!(plain.total_tokens(), Some(AcceptableAmount(30)));
+    assert_eq!(plain.total_lamports(), Some(5000));
+
+    let full = quote_purchase(
+        AcceptableAmount(30),
+        AcceptableAmount(2),
+        PurchaseSetup {
+            create_recipient: true,
+            mint_receipt: true,
+            open_campaign: true,
+            buyer_signs: true,
+        },
+        &rent,
+        5000,
+    );
+    assert_eq!(full.total_tokens(), Some(AcceptableAmount(32)));
+    assert_eq!(full.recipient_rent, rent.minimum_balance(Account::LEN));
+    assert_eq!(
+        full.receipt_rent,
+        rent.minimum_balance(Mint::LEN)
+            + rent.minimum_balance(Account::LEN)
+            + rent.minimum_balance(PurchaseReceipt::LEN)
+    );
+    assert_eq!(full.campaign_rent, rent.minimum_balance(CampaignStats::LEN));
+    assert_eq!(full.network_fee, 4 * 5000);
+    assert_eq!(
+        full.total_lamports(),
+        Some(full.recipient_rent + full.receipt_rent + full.campaign_rent + full.network_fee)
+    );
+
+    let overflow = quote_purchase(
+        AcceptableAmount(u64::MAX),
+        AcceptableAmount(1),
+        PurchaseSetup::default(),
+        &rent,
+        5000,
+    );
+    assert_eq!(overflow.total_tokens(), None);
+}
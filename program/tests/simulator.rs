@@ -0,0 +1,59 @@
+use token_market::{
+    error::TokenMarketError,
+    pricing::Settlement,
+    simulator::{simulate, Purchase},
+    state::OverpaymentPolicy,
+};
+
+#[test]
+fn test_simulate() {
+    let purchases = [
+        Purchase {
+            amount: 30,
+            tip: 0,
+            delegated: 30,
+        },
+        Purchase {
+            amount: 10,
+            tip: 2,
+            delegated: 20,
+        },
+        Purchase {
+            amount: 10,
+            tip: 0,
+            delegated: 5,
+        },
+    ];
+
+    let steps = simulate(OverpaymentPolicy::Donate, 100, 0, &purchases);
+    assert_eq!(
+        steps[1].settlement,
+        Ok(Settlement {
+            emitted: 10,
+            to_bank: 18,
+            tip: 2,
+            excess: 8,
+            refund: 0,
+        })
+    );
+    assert_eq!(
+        steps[2].settlement,
+        Err(TokenMarketError::DelegationMismatch.into())
+    );
+    assert_eq!((steps[2].supply, steps[2].proceeds), (140, 48));
+    assert_eq!(
+        steps
+            .iter()
+            .map(|step| step.effective_price())
+            .collect::<Vec<_>>(),
+        vec![Some(1.0), Some(2.0), None]
+    );
+
+    let steps = simulate(OverpaymentPolicy::Refund, 100, 0, &purchases);
+    assert_eq!(steps[1].settlement.as_ref().unwrap().refund, 8);
+    assert_eq!((steps[2].supply, steps[2].proceeds), (140, 40));
+
+    let steps = simulate(OverpaymentPolicy::Reject, 100, 0, &purchases);
+    assert!(steps[1].settlement.is_err());
+    assert_eq!((steps[2].supply, steps[2].proceeds), (130, 30));
+}
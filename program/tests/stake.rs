@@ -0,0 +1,55 @@
+use solana_program::pubkey::Pubkey;
+use token_market::{
+    stake::TokenOwnerRecord,
+    state::{StakeSource, StakeWeight},
+};
+
+#[test]
+fn test_token_owner_record_layout() {
+    let record = TokenOwnerRecord {
+        account_type: 17,
+        realm: Pubkey::new(&[1; 32]),
+        governing_token_mint: Pubkey::new(&[2; 32]),
+        governing_token_owner: Pubkey::new(&[3; 32]),
+        governing_token_deposit_amount: 0x0102,
+    };
+    let mut data = record.pack();
+    assert_eq!(data.len(), TokenOwnerRecord::LEN);
+    assert_eq!(&data[97..], &[2, 1, 0, 0, 0, 0, 0, 0]);
+    // records carry more fields after the deposit
+    data.extend_from_slice(&[0; 64]);
+    assert_eq!(TokenOwnerRecord::unpack(&data), Some(record));
+
+    // realms and other governance accounts aren't records
+    data[0] = 16;
+    assert_eq!(TokenOwnerRecord::unpack(&data), None);
+    data[0] = 2;
+    assert_eq!(
+        TokenOwnerRecord::unpack(&data[..TokenOwnerRecord::LEN - 1]),
+        None
+    );
+}
+
+#[test]
+fn test_stake_bonus_rounds_down() {
+    let weight = StakeWeight {
+        source: StakeSource::PoolTokens,
+        mint: Pubkey::new_unique(),
+        governance_program: Pubkey::default(),
+        bonus_bps: 2_500,
+    };
+    assert_eq!(weight.bonus(3), 0);
+    assert_eq!(weight.bonus(7), 1);
+    assert_eq!(weight.bonus(400), 100);
+    // weights above 100% saturate instead of overflowing
+    let weight = StakeWeight {
+        bonus_bps: u32::MAX,
+        ..weight
+    };
+    assert_eq!(weight.bonus(u64::MAX), u64::MAX);
+    let weight = StakeWeight {
+        source: StakeSource::Off,
+        ..weight
+    };
+    assert_eq!(weight.bonus(400), 0);
+}
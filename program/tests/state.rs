@@ -0,0 +1,207 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+use token_market::{
+    error::TokenMarketError,
+    state::{
+        BuyerRecord, DelegatedAction, Delegation, ListingInfo, SalePhase, SalePhases, TokenMarket,
+    },
+};
+
+#[test]
+fn test_listing_info_ignores_unused_space() {
+    let listing = ListingInfo {
+        is_initialized: true,
+        market: Pubkey::new_unique(),
+        website: "https://example.org".to_string(),
+        logo_uri_hash: [1; 32],
+        contact_hash: [2; 32],
+    };
+    let mut data = vec![0; ListingInfo::LEN];
+    listing.serialize(&mut &mut data[..]).unwrap();
+    assert_eq!(ListingInfo::load(&data).unwrap(), listing);
+
+    let longest = ListingInfo {
+        website: "x".repeat(ListingInfo::MAX_WEBSITE_LEN),
+        ..listing
+    };
+    assert_eq!(longest.try_to_vec().unwrap().len(), ListingInfo::LEN);
+}
+
+#[test]
+fn test_delegation_limits_reset_daily() {
+    let mut delegation = Delegation {
+        is_initialized: true,
+        daily_limits: [100, 0, 2, 0],
+        ..Delegation::default()
+    };
+    let day = Delegation::SECONDS_PER_DAY;
+    let noon = 19_000 * day + day / 2;
+
+    delegation
+        .spend(DelegatedAction::CreateVestingVault, 60, noon)
+        .unwrap();
+    assert_eq!(
+        delegation.spend(DelegatedAction::CreateVestingVault, 41, noon + 1),
+        Err(TokenMarketError::DelegationLimitExceeded)
+    );
+    assert_eq!(
+        delegation.spend(DelegatedAction::SetListingInfo, 1, noon),
+        Err(TokenMarketError::DelegationLimitExceeded)
+    );
+    delegation
+        .spend(DelegatedAction::SetOverpaymentPolicy, 1, noon)
+        .unwrap();
+    assert_eq!(delegation.used_today, [60, 0, 1, 0]);
+
+    // The next day starts at midnight
+    delegation
+        .spend(DelegatedAction::CreateVestingVault, 100, noon + day / 2)
+        .unwrap();
+    assert_eq!(delegation.day, 19_001);
+    assert_eq!(delegation.used_today, [100, 0, 0, 0]);
+}
+
+#[test]
+fn test_withdraw_stream_releases_by_the_second() {
+    let mut token_market = TokenMarket::try_from_slice(&[0; TokenMarket::LEN]).unwrap();
+    let day = TokenMarket::WITHDRAW_PERIOD;
+    let start = 19_000 * day;
+    assert_eq!(token_market.withdrawable(start), u64::MAX);
+    token_market.withdraw_rate = 1_000;
+    token_market.last_withdraw = start;
+
+    // 1000 a day is one every 86.4 seconds
+    assert_eq!(token_market.withdrawable(start + 86), 0);
+    assert_eq!(token_market.withdrawable(start + 87), 1);
+    assert_eq!(
+        token_market.stream_withdrawal(2, start + 87),
+        Err(TokenMarketError::WithdrawRateExceeded)
+    );
+    // releasing one token took 87 seconds, rounded up
+    token_market.stream_withdrawal(1, start + 87).unwrap();
+    assert_eq!(token_market.last_withdraw, start + 87);
+    assert_eq!(token_market.withdrawable(start + 87), 0);
+
+    // No more than a day's worth piles up
+    let later = start + 10 * day;
+    assert_eq!(token_market.withdrawable(later), 1_000);
+    token_market.stream_withdrawal(400, later).unwrap();
+    assert_eq!(token_market.withdrawable(later), 600);
+}
+
+#[test]
+fn test_buyer_record_counts_both_caps() {
+    let mut record = BuyerRecord {
+        market: Pubkey::new_unique(),
+        buyer: Pubkey::new_unique(),
+        purchased: 0,
+        spent: 0,
+        last_purchase: 0,
+    };
+    record.count(60, 30, 100, 50).unwrap();
+    assert_eq!(
+        record.count(41, 1, 100, 50),
+        Err(TokenMarketError::WalletCapExceeded)
+    );
+    assert_eq!(
+        record.count(1, 21, 100, 50),
+        Err(TokenMarketError::SpendCapExceeded)
+    );
+    // a cap of 0 doesn't cap
+    record.count(1_000, 20, 0, 50).unwrap();
+    assert_eq!((record.purchased, record.spent), (1_060, 50));
+    assert_eq!(record.remaining_spend(50), 0);
+}
+
+#[test]
+fn test_buyer_record_cools_down() {
+    let mut record = BuyerRecord {
+        market: Pubkey::new_unique(),
+        buyer: Pubkey::new_unique(),
+        purchased: 0,
+        spent: 0,
+        last_purchase: 0,
+    };
+    // the first purchase never waits
+    record.cool_down(1_000, 60).unwrap();
+    assert_eq!(
+        record.cool_down(1_059, 60),
+        Err(TokenMarketError::PurchaseCooldown)
+    );
+    assert_eq!(record.last_purchase, 1_000);
+    record.cool_down(1_060, 60).unwrap();
+    record.cool_down(1_060, 0).unwrap();
+    assert_eq!(record.last_purchase, 1_060);
+}
+
+#[test]
+fn test_feature_names_cover_all_features() {
+    let mut named = 0;
+    for (_, bit) in TokenMarket::FEATURE_NAMES {
+        assert_eq!(bit.count_ones(), 1);
+        assert_eq!(named & bit, 0);
+        named |= bit;
+    }
+    assert_eq!(named, TokenMarket::ALL_FEATURES);
+}
+
+#[test]
+fn test_selling_needs_backed_features() {
+    assert_eq!(
+        TokenMarket::DEFAULT_FEATURES & TokenMarket::FEATURE_SELLING,
+        0
+    );
+    assert!(TokenMarket::check_selling(TokenMarket::DEFAULT_FEATURES).is_ok());
+    assert!(TokenMarket::check_selling(TokenMarket::SELLABLE_FEATURES).is_ok());
+    assert_eq!(
+        TokenMarket::check_selling(TokenMarket::FEATURE_SELLING | TokenMarket::FEATURE_VESTING),
+        Err(TokenMarketError::UnbackedSelling)
+    );
+    assert_eq!(
+        TokenMarket::check_selling(TokenMarket::ALL_FEATURES),
+        Err(TokenMarketError::UnbackedSelling)
+    );
+}
+
+#[test]
+fn test_sale_phases_open_by_clock_or_owner() {
+    let phase = |start| SalePhase {
+        start,
+        price_numerator: 1,
+        price_denominator: 1,
+        ..SalePhase::default()
+    };
+    let schedule = vec![phase(100), phase(200), phase(300)];
+    assert!(SalePhases::is_valid_schedule(&schedule, 400));
+    assert!(!SalePhases::is_valid_schedule(&schedule, 300));
+    assert!(!SalePhases::is_valid_schedule(&[phase(200), phase(100)], 0));
+    assert!(!SalePhases::is_valid_schedule(&[], 0));
+    assert!(!SalePhases::is_valid_schedule(
+        &vec![phase(0); SalePhases::MAX_PHASES + 1],
+        0
+    ));
+
+    let mut phases = SalePhases::new(Pubkey::new_unique(), schedule, 400);
+    assert_eq!(phases.active(99), None);
+    assert_eq!(phases.active(250), Some(1));
+    assert_eq!(phases.active(400), None);
+
+    // Opening a phase early doesn't hold back the ones after it
+    phases.opened = 2;
+    assert_eq!(phases.active(0), Some(1));
+    assert_eq!(phases.active(300), Some(2));
+
+    // A new schedule keeps the sales of the phases it still has
+    phases.record_purchase(0, 50, 25, false).unwrap();
+    phases.record_purchase(2, 10, 10, false).unwrap();
+    let mut shorter = SalePhases::new(phases.market, vec![phase(100), phase(200)], 0);
+    shorter.carry_over(&phases);
+    assert_eq!(shorter.opened, 2);
+    assert_eq!(
+        (shorter.sold, shorter.purchases, shorter.paid),
+        (vec![50, 0], vec![1, 0], vec![25, 0])
+    );
+
+    let longest = SalePhases::new(phases.market, vec![phase(0); SalePhases::MAX_PHASES], 0);
+    assert_eq!(longest.try_to_vec().unwrap().len(), SalePhases::LEN);
+}
@@ -1,17 +1,21 @@
 #![cfg(feature = "test-bpf")]
 
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     pubkey::Pubkey,
+    program_option::COption,
     program_pack::Pack,
 };
-use token_market::{*, state::*, processor::*};
-use spl_token::state::{Account, Mint};
+use token_market::{*, amounts::*, error::TokenMarketError, state::*, processor::*, wormhole::{PostedVaa, PurchasePayload}};
+use spl_token::{error::TokenError, state::{Account, AccountState, Mint}};
 use solana_program_test::*;
 use solana_sdk::{
-    transaction::Transaction,
+    instruction::{Instruction, InstructionError},
+    transaction::{Transaction, TransactionError},
     rent::Rent,
     signature::{Keypair, Signer},
     system_instruction::create_account,
+    transport::TransportError,
 };
 
 pub fn program_test() -> ProgramTest {
@@ -22,61 +26,4071 @@ pub fn program_test() -> ProgramTest {
     )
 }
 
-#[tokio::test]
-async fn test_create_market() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+/// Accounts of a market created by [create_market](fn.create_market.html)
+pub struct MarketAccounts {
+    pub owner: Keypair,
+    pub market: Keypair,
+    pub bank: Keypair,
+    pub emitter: Keypair,
+    pub mint_acceptable: Keypair,
+}
 
-    let owner = Keypair::new();
-    let mint_acceptable = Keypair::new();
-    let market = Keypair::new();
-    let bank = Keypair::new();
-    let emitter = Keypair::new();
+pub async fn process(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    instructions: &[Instruction],
+    signers: &[&dyn Signer],
+) -> Result<(), TransportError> {
+    let recent_blockhash = banks_client.get_recent_blockhash().await.unwrap();
+    let mut ts = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+    let mut all_signers: Vec<&dyn Signer> = vec![payer];
+    all_signers.extend_from_slice(signers);
+    ts.sign(&all_signers, recent_blockhash);
+    banks_client.process_transaction(ts).await
+}
 
+/// Create `mint` that `authority` can mint and freeze tokens of
+pub async fn create_mint(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Keypair,
+    authority: &Pubkey,
+) {
     let instructions = &[
         create_account(
             &payer.pubkey(),
-            &market.pubkey(),
+            &mint.pubkey(),
+            Rent::default().minimum_balance(Mint::LEN),
+            Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint.pubkey(),
+            authority,
+            Some(authority),
+            0,
+        ).unwrap(),
+    ];
+    process(banks_client, payer, instructions, &[mint]).await.unwrap();
+}
+
+pub async fn create_token_account(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    account: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) {
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &account.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &account.pubkey(),
+            mint,
+            owner,
+        ).unwrap(),
+    ];
+    process(banks_client, payer, instructions, &[account]).await.unwrap();
+}
+
+pub async fn token_account(banks_client: &mut BanksClient, account: &Pubkey) -> Account {
+    let account = banks_client.get_account(*account).await.unwrap().unwrap();
+    Account::unpack(&account.data).unwrap()
+}
+
+pub async fn token_balance(banks_client: &mut BanksClient, account: &Pubkey) -> u64 {
+    token_account(banks_client, account).await.amount
+}
+
+pub async fn mint_supply(banks_client: &mut BanksClient, mint: &Pubkey) -> u64 {
+    let mint = banks_client.get_account(*mint).await.unwrap().unwrap();
+    Mint::unpack(&mint.data).unwrap().supply
+}
+
+/// Balances of token accounts taken before a transaction, compared against
+/// by [assert_token_delta](fn.assert_token_delta.html) after it
+pub struct Balances(Vec<(Pubkey, u64)>);
+
+impl Balances {
+    pub async fn take(banks_client: &mut BanksClient, accounts: &[Pubkey]) -> Self {
+        let mut balances = vec![];
+        for account in accounts {
+            balances.push((*account, token_balance(banks_client, account).await));
+        }
+        Self(balances)
+    }
+}
+
+/// Assert that the balance of `account` changed by `delta` since `before` was taken
+pub async fn assert_token_delta(
+    banks_client: &mut BanksClient,
+    before: &Balances,
+    account: &Pubkey,
+    delta: i128,
+) {
+    let (_, balance) = before.0.iter()
+        .find(|(address, _)| address == account)
+        .unwrap_or_else(|| panic!("No balance of {} was taken", account));
+    let after = token_balance(banks_client, account).await;
+    assert_eq!(after as i128 - *balance as i128, delta, "balance of {} changed by", account);
+}
+
+pub async fn assert_mint_supply(banks_client: &mut BanksClient, mint: &Pubkey, supply: u64) {
+    assert_eq!(mint_supply(banks_client, mint).await, supply, "supply of {}", mint);
+}
+
+/// Add a market in a state no instruction sequence reaches, owned by `owner`
+/// and with all the features, before the test starts
+pub fn add_market(program_test: &mut ProgramTest, market: &Pubkey, owner: &Pubkey, bank: &Pubkey, mint_acceptable: &Pubkey, emitter: &Pubkey) {
+    add_market_state(program_test, market, &market_state(owner, bank, mint_acceptable, emitter));
+}
+
+/// State [add_market](fn.add_market.html) adds, for tests changing it first
+pub fn market_state(owner: &Pubkey, bank: &Pubkey, mint_acceptable: &Pubkey, emitter: &Pubkey) -> TokenMarket {
+    TokenMarket {
+        is_initialized: true,
+        owner: *owner,
+        bank: *bank,
+        emitter_mint: *emitter,
+        authority: find_authority_address(&id()).0,
+        mint_of_acceptable: *mint_acceptable,
+        legacy_bank: Pubkey::default(),
+        legacy_mint_of_acceptable: Pubkey::default(),
+        voucher_signer: [0; 20],
+        category: [0; 4],
+        project_id: [0; 32],
+        treasury: Pubkey::default(),
+        deprecated: false,
+        successor: Pubkey::default(),
+        overpayment_policy: OverpaymentPolicy::Reject,
+        mint_receipts: false,
+        features: TokenMarket::ALL_FEATURES,
+        require_memo: false,
+        sandwich_guard: false,
+        wormhole_chain: 0,
+        wormhole_emitter: [0; 32],
+        onramp_signer: Pubkey::default(),
+        escrow_threshold: 0,
+        dispute_window: 0,
+        dispute_guardian: Pubkey::default(),
+        paused: false,
+        phased: false,
+        fee_bps: 0,
+        fee_vault: Pubkey::default(),
+        min_purchase: 0,
+        max_purchase: 0,
+        wallet_cap: 0,
+        sale_start: 0,
+        sale_end: 0,
+        allowlist_enabled: false,
+        allowlist_root: [0; 32],
+        pricing_tiers: [PricingTier::default(); TokenMarket::MAX_PRICING_TIERS],
+        total_sold: 0,
+        bonding_curve: BondingCurve::default(),
+        stake_weight: StakeWeight::default(),
+        withdraw_rate: 0,
+        last_withdraw: 0,
+        wallet_spend_cap: 0,
+        purchase_cooldown_secs: 0,
+        loyalty_tiers: [LoyaltyTier::default(); TokenMarket::MAX_LOYALTY_TIERS],
+    }
+}
+
+/// Add a market in the `token_market` state before the test starts
+pub fn add_market_state(program_test: &mut ProgramTest, market: &Pubkey, token_market: &TokenMarket) {
+    let mut data = vec![0; TokenMarket::LEN];
+    token_market.serialize(&mut &mut data[..]).unwrap();
+    program_test.add_account(*market, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+}
+
+/// Add a token program account packing `state` before the test starts
+pub fn add_packed<T: Pack>(program_test: &mut ProgramTest, address: &Pubkey, state: T) {
+    let mut data = vec![0; T::LEN];
+    state.pack_into_slice(&mut data);
+    program_test.add_account(*address, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+}
+
+/// Add a message of `emitter_address` posted by `owner`, the Wormhole core
+/// bridge unless testing forgeries, before the test starts
+pub fn add_posted_vaa(program_test: &mut ProgramTest, address: &Pubkey, owner: &Pubkey, emitter_address: [u8; 32], sequence: u64, payload: &PurchasePayload) -> PostedVaa {
+    let vaa = PostedVaa {
+        vaa_version: 1,
+        consistency_level: 1,
+        vaa_time: 0,
+        vaa_signature_account: Pubkey::new_unique(),
+        submission_time: 0,
+        nonce: 0,
+        sequence,
+        emitter_chain: 2,
+        emitter_address,
+        payload: payload.pack(),
+    };
+    let data = vaa.pack().unwrap();
+    program_test.add_account(*address, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: *owner,
+        executable: false,
+        rent_epoch: 0,
+    });
+    vaa
+}
+
+/// Token account of `mint` owned by `owner` holding `amount`
+pub fn token_account_state(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    Account {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..Account::default()
+    }
+}
+
+/// Assert that the transaction failed on instruction `index` with `error`
+pub fn assert_instruction_error(result: Result<(), TransportError>, index: u8, error: InstructionError) {
+    match result {
+        Err(TransportError::TransactionError(TransactionError::InstructionError(failed, actual))) => {
+            assert_eq!((failed, actual), (index, error));
+        }
+        result => panic!("Expected instruction {} to fail with {:?}, got {:?}", index, error, result),
+    }
+}
+
+pub async fn create_market(banks_client: &mut BanksClient, payer: &Keypair) -> MarketAccounts {
+    // The native processor can't allocate accounts in CPI, so the mint index
+    // is only written when running the BPF build
+    create_market_with(banks_client, payer, false, None).await
+}
+
+/// Create a market with the mint index if `mint_index`, and with `features`
+/// instead of the default ones if given
+pub async fn create_market_with(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint_index: bool,
+    features: Option<u64>,
+) -> MarketAccounts {
+    let accounts = MarketAccounts {
+        owner: Keypair::new(),
+        market: Keypair::new(),
+        bank: Keypair::new(),
+        emitter: Keypair::new(),
+        mint_acceptable: Keypair::new(),
+    };
+    create_mint(banks_client, payer, &accounts.mint_acceptable, &payer.pubkey()).await;
+
+    let mut initialize = instruction::initialize(
+        &token_market::id(),
+        &accounts.owner.pubkey(),
+        &payer.pubkey(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &accounts.mint_acceptable.pubkey(),
+        &spl_token::id(),
+        *b"IDO\0",
+        [9; 32],
+    ).unwrap();
+    if !mint_index {
+        initialize.accounts.truncate(token_market::accounts::InitializeAccounts::MINT_INDEX);
+    }
+    if let Some(features) = features {
+        initialize = instruction::with_trailing_field(initialize, &features).unwrap();
+    }
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &accounts.market.pubkey(),
             Rent::default().minimum_balance(TokenMarket::LEN),
             TokenMarket::LEN as u64,
             &token_market::id(),
         ),
         create_account(
             &payer.pubkey(),
-            &bank.pubkey(),
+            &accounts.bank.pubkey(),
             Rent::default().minimum_balance(Account::LEN),
             Account::LEN as u64,
-            &token_market::id(),
+            &spl_token::id(),
         ),
         create_account(
             &payer.pubkey(),
-            &emitter.pubkey(),
+            &accounts.emitter.pubkey(),
             Rent::default().minimum_balance(Mint::LEN),
             Mint::LEN as u64,
-            &token_market::id(),
+            &spl_token::id(),
         ),
-        instruction::initialize(
+        initialize,
+    ];
+    process(
+        banks_client,
+        payer,
+        instructions,
+        &[&accounts.market, &accounts.bank, &accounts.emitter],
+    ).await.unwrap();
+
+    accounts
+}
+
+/// Issue `amount` emitter tokens of `accounts` to a new token account of `holder`
+/// through a fully vested vesting
+pub async fn issue_tokens(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    accounts: &MarketAccounts,
+    holder: &Keypair,
+    amount: EmittedAmount,
+) -> Keypair {
+    let destination = Keypair::new();
+    create_token_account(banks_client, payer, &destination, &accounts.emitter.pubkey(), &holder.pubkey()).await;
+    let vesting = Keypair::new();
+    let vault = Keypair::new();
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &vesting.pubkey(),
+            Rent::default().minimum_balance(VestingVault::LEN),
+            VestingVault::LEN as u64,
             &token_market::id(),
+        ),
+        create_account(
+            &payer.pubkey(),
+            &vault.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        instruction::create_vesting_vault(
+            &id(),
+            &accounts.owner.pubkey(),
+            &accounts.market.pubkey(),
+            &vesting.pubkey(),
+            &vault.pubkey(),
+            &accounts.emitter.pubkey(),
+            &holder.pubkey(),
+            amount,
+            0,
+            0,
+            1,
+            false,
+        ).unwrap(),
+        instruction::claim_vested(
+            &id(),
+            &holder.pubkey(),
+            &vesting.pubkey(),
+            &vault.pubkey(),
+            &destination.pubkey(),
+        ).unwrap(),
+    ];
+    process(banks_client, payer, instructions, &[&accounts.owner, &vesting, &vault, holder])
+        .await
+        .unwrap();
+    destination
+}
+
+/// Create a write-off account of `buyer` holding 100 acceptable tokens and an empty tokens recipient
+pub async fn fund_buyer(banks_client: &mut BanksClient, payer: &Keypair, accounts: &MarketAccounts, buyer: &Keypair) -> (Keypair, Keypair) {
+    let write_off = Keypair::new();
+    create_token_account(banks_client, payer, &write_off, &accounts.mint_acceptable.pubkey(), &buyer.pubkey()).await;
+    let mint_payment = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &accounts.mint_acceptable.pubkey(),
+        &write_off.pubkey(),
+        &payer.pubkey(),
+        &[],
+        100,
+    ).unwrap();
+    process(banks_client, payer, &[mint_payment], &[]).await.unwrap();
+    let recipient = Keypair::new();
+    create_token_account(banks_client, payer, &recipient, &accounts.emitter.pubkey(), &buyer.pubkey()).await;
+    (write_off, recipient)
+}
+
+#[tokio::test]
+async fn test_create_market() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    let market = TokenMarket::try_from_slice(&market.data).unwrap();
+    assert!(market.is_initialized);
+    assert_eq!(market.owner, accounts.owner.pubkey());
+    assert_eq!(market.bank, accounts.bank.pubkey());
+    assert_eq!(market.emitter_mint, accounts.emitter.pubkey());
+    assert_eq!(market.authority, find_authority_address(&id()).0);
+    assert_eq!(&market.category, b"IDO\0");
+    assert_eq!(market.project_id, [9; 32]);
+    assert_eq!(market.features, TokenMarket::ALL_FEATURES);
+}
+
+#[tokio::test]
+async fn test_market_features() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+
+    let accounts = create_market_with(&mut banks_client, &payer, false, Some(TokenMarket::FEATURE_VESTING)).await;
+    let market = accounts.market.pubkey();
+    let owner = accounts.owner.pubkey();
+    let market_data = banks_client.get_account(market).await.unwrap().unwrap().data;
+    assert!(TokenMarket::try_from_slice(&market_data).unwrap().supports(TokenMarket::FEATURE_VESTING));
+
+    let enable = instruction::set_receipt_minting(&id(), &owner, &market, true).unwrap();
+    assert!(process(&mut banks_client, &payer, &[enable], &[&accounts.owner]).await.is_err());
+    let disable = instruction::set_receipt_minting(&id(), &owner, &market, false).unwrap();
+    process(&mut banks_client, &payer, &[disable], &[&accounts.owner]).await.unwrap();
+    let set_signer = instruction::set_voucher_signer(&id(), &owner, &market, [7; 20]).unwrap();
+    assert!(process(&mut banks_client, &payer, &[set_signer], &[&accounts.owner]).await.is_err());
+
+    let holder = Keypair::new();
+    let destination = issue_tokens(&mut banks_client, &payer, &accounts, &holder, EmittedAmount(10)).await;
+    assert_eq!(token_balance(&mut banks_client, &destination.pubkey()).await, 10);
+}
+
+#[tokio::test]
+async fn test_create_market_with_mint_index() {
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+
+    let accounts = create_market_with(&mut banks_client, &payer, true, None).await;
+
+    let index = find_mint_index_address(&id(), &accounts.emitter.pubkey()).0;
+    let index = banks_client.get_account(index).await.unwrap().unwrap();
+    assert_eq!(index.owner, id());
+    assert_eq!(MintIndex::try_from_slice(&index.data).unwrap().market, accounts.market.pubkey());
+}
+
+#[tokio::test]
+async fn test_create_market_counts_tenant_markets() {
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let (owner, mint_acceptable) = (Keypair::new(), Keypair::new());
+    create_mint(&mut banks_client, &payer, &mint_acceptable, &payer.pubkey()).await;
+
+    for signed in &[false, true, true] {
+        let (market, bank, emitter) = (Keypair::new(), Keypair::new(), Keypair::new());
+        let mut initialize = instruction::with_tenant_stats(&id(), instruction::initialize(
+            &id(),
             &owner.pubkey(),
+            &payer.pubkey(),
             &market.pubkey(),
             &bank.pubkey(),
             &emitter.pubkey(),
-            &emitter.pubkey(),
             &mint_acceptable.pubkey(),
             &spl_token::id(),
+            *b"IDO\0",
+            [9; 32],
+        ).unwrap());
+        let mut signers: Vec<&dyn Signer> = vec![&market, &bank, &emitter];
+        if *signed {
+            signers.push(&owner);
+        } else {
+            initialize.accounts[token_market::accounts::InitializeAccounts::OWNER].is_signer = false;
+        }
+        let instructions = &[
+            create_account(&payer.pubkey(), &market.pubkey(), Rent::default().minimum_balance(TokenMarket::LEN), TokenMarket::LEN as u64, &id()),
+            create_account(&payer.pubkey(), &bank.pubkey(), Rent::default().minimum_balance(Account::LEN), Account::LEN as u64, &spl_token::id()),
+            create_account(&payer.pubkey(), &emitter.pubkey(), Rent::default().minimum_balance(Mint::LEN), Mint::LEN as u64, &spl_token::id()),
+            initialize,
+        ];
+        let result = process(&mut banks_client, &payer, instructions, &signers).await;
+        // Nobody but the owner may run up its usage
+        assert_eq!(result.is_ok(), *signed);
+    }
+
+    let tenant = find_tenant_address(&id(), &owner.pubkey()).0;
+    let tenant = banks_client.get_account(tenant).await.unwrap().unwrap();
+    assert_eq!(TenantStats::try_from_slice(&tenant.data).unwrap(), TenantStats {
+        owner: owner.pubkey(),
+        markets_created: 2,
+        protocol_fees: 0,
+    });
+}
+
+#[tokio::test]
+async fn test_forged_market_mints_nothing() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let emitter = accounts.emitter.pubkey();
+
+    // Same market data, but held by another program
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    let emitter_account = banks_client.get_account(emitter).await.unwrap().unwrap();
+    let mut program_test = program_test();
+    program_test.add_account(accounts.market.pubkey(), solana_sdk::account::Account {
+        owner: Pubkey::new_unique(),
+        ..market
+    });
+    program_test.add_account(emitter, emitter_account);
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let beneficiary = Keypair::new();
+    let vesting = Keypair::new();
+    let vault = Keypair::new();
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &vesting.pubkey(),
+            Rent::default().minimum_balance(VestingVault::LEN),
+            VestingVault::LEN as u64,
+            &token_market::id(),
+        ),
+        create_account(
+            &payer.pubkey(),
+            &vault.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        instruction::create_vesting_vault(
+            &id(),
+            &accounts.owner.pubkey(),
+            &accounts.market.pubkey(),
+            &vesting.pubkey(),
+            &vault.pubkey(),
+            &emitter,
+            &beneficiary.pubkey(),
+            EmittedAmount(100),
+            0,
+            10,
+            20,
+            false,
+        ).unwrap(),
+    ];
+    assert!(process(&mut banks_client, &payer, instructions, &[&accounts.owner, &vesting, &vault])
+        .await
+        .is_err());
+
+    let emitter = banks_client.get_account(emitter).await.unwrap().unwrap();
+    assert_eq!(Mint::unpack(&emitter.data).unwrap().supply, 0);
+}
+
+#[tokio::test]
+async fn test_vesting_claim_and_revoke() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let emitter = accounts.emitter.pubkey();
+
+    // Fully vested schedule: claim everything
+    let beneficiary = Keypair::new();
+    let destination = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &destination, &emitter, &beneficiary.pubkey()).await;
+    let vesting = Keypair::new();
+    let vault = Keypair::new();
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &vesting.pubkey(),
+            Rent::default().minimum_balance(VestingVault::LEN),
+            VestingVault::LEN as u64,
+            &token_market::id(),
+        ),
+        create_account(
+            &payer.pubkey(),
+            &vault.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        instruction::create_vesting_vault(
+            &id(),
+            &accounts.owner.pubkey(),
+            &accounts.market.pubkey(),
+            &vesting.pubkey(),
+            &vault.pubkey(),
+            &emitter,
+            &beneficiary.pubkey(),
+            EmittedAmount(100),
+            0,
+            10,
+            20,
+            false,
+        ).unwrap(),
+    ];
+    process(&mut banks_client, &payer, instructions, &[&accounts.owner, &vesting, &vault])
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut banks_client, &vault.pubkey()).await, 100);
+
+    let claim = instruction::claim_vested(
+        &id(),
+        &beneficiary.pubkey(),
+        &vesting.pubkey(),
+        &vault.pubkey(),
+        &destination.pubkey(),
+    ).unwrap();
+    process(&mut banks_client, &payer, &[claim], &[&beneficiary]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &destination.pubkey()).await, 100);
+    assert_eq!(token_balance(&mut banks_client, &vault.pubkey()).await, 0);
+
+    // Schedule starting in the far future: revoke returns everything
+    let owner_tokens = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &owner_tokens, &emitter, &accounts.owner.pubkey()).await;
+    let vesting = Keypair::new();
+    let vault = Keypair::new();
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &vesting.pubkey(),
+            Rent::default().minimum_balance(VestingVault::LEN),
+            VestingVault::LEN as u64,
+            &token_market::id(),
+        ),
+        create_account(
+            &payer.pubkey(),
+            &vault.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        instruction::create_vesting_vault(
+            &id(),
+            &accounts.owner.pubkey(),
+            &accounts.market.pubkey(),
+            &vesting.pubkey(),
+            &vault.pubkey(),
+            &emitter,
+            &beneficiary.pubkey(),
+            EmittedAmount(50),
+            i64::MAX / 2,
+            0,
+            20,
+            true,
+        ).unwrap(),
+        instruction::revoke_vesting(
+            &id(),
+            &accounts.owner.pubkey(),
+            &accounts.market.pubkey(),
+            &vesting.pubkey(),
+            &vault.pubkey(),
+            &owner_tokens.pubkey(),
+        ).unwrap(),
+    ];
+    process(&mut banks_client, &payer, instructions, &[&accounts.owner, &vesting, &vault])
+        .await
+        .unwrap();
+    assert_eq!(token_balance(&mut banks_client, &owner_tokens.pubkey()).await, 50);
+
+    let vesting = banks_client.get_account(vesting.pubkey()).await.unwrap().unwrap();
+    let vesting = VestingVault::try_from_slice(&vesting.data).unwrap();
+    assert!(vesting.revoked);
+    assert_eq!(vesting.total, 0);
+}
+
+#[tokio::test]
+async fn test_migrate_acceptable_mint() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let new_mint = Keypair::new();
+    create_mint(&mut banks_client, &payer, &new_mint, &payer.pubkey()).await;
+    let new_bank = Keypair::new();
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &new_bank.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        instruction::migrate_acceptable_mint(
+            &id(),
+            &accounts.owner.pubkey(),
+            &accounts.market.pubkey(),
+            &new_bank.pubkey(),
+            &new_mint.pubkey(),
+            &accounts.emitter.pubkey(),
+        ).unwrap(),
+    ];
+    process(&mut banks_client, &payer, instructions, &[&accounts.owner, &new_bank])
+        .await
+        .unwrap();
+
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    let market = TokenMarket::try_from_slice(&market.data).unwrap();
+    assert_eq!(market.bank, new_bank.pubkey());
+    assert_eq!(market.mint_of_acceptable, new_mint.pubkey());
+    assert_eq!(market.legacy_bank, accounts.bank.pubkey());
+    assert_eq!(market.legacy_mint_of_acceptable, accounts.mint_acceptable.pubkey());
+
+    // A second migration would orphan the legacy bank
+    let newer_mint = Keypair::new();
+    create_mint(&mut banks_client, &payer, &newer_mint, &payer.pubkey()).await;
+    let newer_bank = Keypair::new();
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &newer_bank.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        instruction::migrate_acceptable_mint(
+            &id(),
+            &accounts.owner.pubkey(),
+            &accounts.market.pubkey(),
+            &newer_bank.pubkey(),
+            &newer_mint.pubkey(),
+            &accounts.emitter.pubkey(),
         ).unwrap(),
     ];
+    assert!(process(&mut banks_client, &payer, instructions, &[&accounts.owner, &newer_bank])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_set_treasury() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let treasury = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &treasury, &accounts.mint_acceptable.pubkey(), &payer.pubkey()).await;
+    let emitted = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &emitted, &accounts.emitter.pubkey(), &payer.pubkey()).await;
+
+    let wrong_mint = instruction::set_treasury(&id(), &accounts.owner.pubkey(), &accounts.market.pubkey(), &emitted.pubkey()).unwrap();
+    assert!(process(&mut banks_client, &payer, &[wrong_mint], &[&accounts.owner]).await.is_err());
+    let not_owner = instruction::set_treasury(&id(), &payer.pubkey(), &accounts.market.pubkey(), &treasury.pubkey()).unwrap();
+    assert!(process(&mut banks_client, &payer, &[not_owner], &[]).await.is_err());
+
+    let set = instruction::set_treasury(&id(), &accounts.owner.pubkey(), &accounts.market.pubkey(), &treasury.pubkey()).unwrap();
+    process(&mut banks_client, &payer, &[set], &[&accounts.owner]).await.unwrap();
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    let market = TokenMarket::try_from_slice(&market.data).unwrap();
+    assert_eq!(market.treasury, treasury.pubkey());
+}
+
+#[tokio::test]
+async fn test_set_listing_info_rejects_long_website() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let set = instruction::set_listing_info(
+        &id(),
+        &accounts.owner.pubkey(),
+        &payer.pubkey(),
+        &accounts.market.pubkey(),
+        "x".repeat(ListingInfo::MAX_WEBSITE_LEN + 1),
+        [1; 32],
+        [2; 32],
+    ).unwrap();
+    assert!(process(&mut banks_client, &payer, &[set], &[&accounts.owner]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_set_listing_info() {
+    // The listing info is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let listing = find_listing_address(&id(), &accounts.market.pubkey()).0;
+
+    for website in &["https://example.org/token-sale", "https://example.org"] {
+        let set = instruction::set_listing_info(
+            &id(),
+            &accounts.owner.pubkey(),
+            &payer.pubkey(),
+            &accounts.market.pubkey(),
+            website.to_string(),
+            [1; 32],
+            [2; 32],
+        ).unwrap();
+        process(&mut banks_client, &payer, &[set], &[&accounts.owner]).await.unwrap();
+
+        let account = banks_client.get_account(listing).await.unwrap().unwrap();
+        let info = ListingInfo::load(&account.data).unwrap();
+        assert_eq!(info.market, accounts.market.pubkey());
+        assert_eq!(&info.website, website);
+}
+
+#[tokio::test]
+async fn test_migrate_holdings_rejects_forged_markets() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let own = create_market(&mut banks_client, &payer).await;
+    let victim = create_market(&mut banks_client, &payer).await;
+
+    // Migrating into a copy of the victim market held by another program
+    // would mint its emitter for junk
+    let own_market = banks_client.get_account(own.market.pubkey()).await.unwrap().unwrap();
+    let victim_market = banks_client.get_account(victim.market.pubkey()).await.unwrap().unwrap();
+    let mut program_test = program_test();
+    program_test.add_account(own.market.pubkey(), own_market);
+    program_test.add_account(victim.market.pubkey(), solana_sdk::account::Account {
+        owner: Pubkey::new_unique(),
+        ..victim_market
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let (own_market, forged) = (own.market.pubkey(), victim.market.pubkey());
+    for (from_market, to_market) in [(own_market, forged), (forged, own_market)].iter() {
+        let migration = Keypair::new();
+        let instructions = &[
+            create_account(
+                &payer.pubkey(),
+                &migration.pubkey(),
+                Rent::default().minimum_balance(HoldingsMigration::LEN),
+                HoldingsMigration::LEN as u64,
+                &token_market::id(),
+            ),
+            instruction::create_holdings_migration(
+                &id(),
+                &own.owner.pubkey(),
+                &victim.owner.pubkey(),
+                from_market,
+                to_market,
+                &migration.pubkey(),
+                1,
+                1,
+            ).unwrap(),
+        ];
+        assert!(process(&mut banks_client, &payer, instructions, &[&own.owner, &victim.owner, &migration])
+            .await
+            .is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_set_notification_config_rejects_long_sealed() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let set = instruction::set_notification_config(
+        &id(),
+        &accounts.owner.pubkey(),
+        &payer.pubkey(),
+        &accounts.market.pubkey(),
+        [1; 32],
+        AlertThresholds::default(),
+        vec![0; NotificationConfig::MAX_SEALED_LEN + 1],
+    ).unwrap();
+    let result = process(&mut banks_client, &payer, &[set], &[&accounts.owner]).await;
+    assert_instruction_error(result, 0, InstructionError::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_set_notification_config() {
+    // The config is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let address = find_notification_address(&id(), &accounts.market.pubkey(), &accounts.owner.pubkey()).0;
+
+    for (large_purchase, sealed) in &[(1_000, vec![7; 64]), (500, vec![])] {
+        let thresholds = AlertThresholds {
+            large_purchase: *large_purchase,
+            ..AlertThresholds::default()
+        };
+        let set = instruction::set_notification_config(
+            &id(),
+            &accounts.owner.pubkey(),
+            &payer.pubkey(),
+            &accounts.market.pubkey(),
+            [1; 32],
+            thresholds,
+            sealed.clone(),
+        ).unwrap();
+        process(&mut banks_client, &payer, &[set], &[&accounts.owner]).await.unwrap();
+
+        let account = banks_client.get_account(address).await.unwrap().unwrap();
+        let config = NotificationConfig::load(&account.data).unwrap();
+        assert_eq!((config.owner, config.thresholds, &config.sealed), (accounts.owner.pubkey(), thresholds, sealed));
+    }
+}
+
+/// Instructions creating a vesting vault of `total` tokens for `beneficiary`, signed by `signer`
+fn delegated_vesting(payer: &Keypair, accounts: &MarketAccounts, signer: &Pubkey, vesting: &Keypair, vault: &Keypair, beneficiary: &Pubkey, total: u64) -> Vec<Instruction> {
+    vec![
+        create_account(
+            &payer.pubkey(),
+            &vesting.pubkey(),
+            Rent::default().minimum_balance(VestingVault::LEN),
+            VestingVault::LEN as u64,
+            &token_market::id(),
+        ),
+        create_account(
+            &payer.pubkey(),
+            &vault.pubkey(),
+            Rent::default().minimum_balance(Account::LEN),
+            Account::LEN as u64,
+            &spl_token::id(),
+        ),
+        instruction::with_delegation(
+            &id(),
+            instruction::create_vesting_vault(
+                &id(),
+                signer,
+                &accounts.market.pubkey(),
+                &vesting.pubkey(),
+                &vault.pubkey(),
+                &accounts.emitter.pubkey(),
+                beneficiary,
+                EmittedAmount(total),
+                0,
+                0,
+                1,
+                false,
+            ).unwrap(),
+            &accounts.market.pubkey(),
+            signer,
+        ),
+    ]
+}
 
-    let mut ts = Transaction::new_with_payer(
-        instructions, 
-        Some(&payer.pubkey())
+#[tokio::test]
+async fn test_delegation() {
+    // The delegation record is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let market = accounts.market.pubkey();
+    let hot = Keypair::new();
+    let set_policy = |policy| instruction::with_delegation(
+        &id(),
+        instruction::set_overpayment_policy(&id(), &hot.pubkey(), &market, policy).unwrap(),
+        &market,
+        &hot.pubkey(),
     );
 
-    let signers = vec![
-        &payer, 
-        &owner, 
+    // Nothing is delegated yet
+    assert!(process(&mut banks_client, &payer, &[set_policy(OverpaymentPolicy::Donate)], &[&hot]).await.is_err());
+
+    let mut daily_limits = [0; DelegatedAction::COUNT];
+    daily_limits[DelegatedAction::CreateVestingVault as usize] = 50;
+    daily_limits[DelegatedAction::SetOverpaymentPolicy as usize] = 1;
+    let delegate = instruction::set_delegation(
+        &id(),
+        &accounts.owner.pubkey(),
+        &payer.pubkey(),
         &market,
-    ];
+        &hot.pubkey(),
+        daily_limits,
+    ).unwrap();
+    process(&mut banks_client, &payer, &[delegate], &[&accounts.owner]).await.unwrap();
+
+    process(&mut banks_client, &payer, &[set_policy(OverpaymentPolicy::Donate)], &[&hot]).await.unwrap();
+    assert!(process(&mut banks_client, &payer, &[set_policy(OverpaymentPolicy::Refund)], &[&hot]).await.is_err());
+    let set_receipts = instruction::with_delegation(
+        &id(),
+        instruction::set_receipt_minting(&id(), &hot.pubkey(), &market, true).unwrap(),
+        &market,
+        &hot.pubkey(),
+    );
+    assert!(process(&mut banks_client, &payer, &[set_receipts], &[&hot]).await.is_err());
+
+    let beneficiary = Pubkey::new_unique();
+    for (total, allowed) in &[(30, true), (21, false), (20, true)] {
+        let (vesting, vault) = (Keypair::new(), Keypair::new());
+        let instructions = delegated_vesting(&payer, &accounts, &hot.pubkey(), &vesting, &vault, &beneficiary, *total);
+        let result = process(&mut banks_client, &payer, &instructions, &[&hot, &vesting, &vault]).await;
+        assert_eq!(result.is_ok(), *allowed, "vesting {}", total);
+    }
+
+    // The owner itself is not limited
+    let set_receipts = instruction::set_receipt_minting(&id(), &accounts.owner.pubkey(), &market, true).unwrap();
+    process(&mut banks_client, &payer, &[set_receipts], &[&accounts.owner]).await.unwrap();
+
+    let address = find_delegation_address(&id(), &market, &hot.pubkey()).0;
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let delegation = Delegation::try_from_slice(&account.data).unwrap();
+    assert_eq!(delegation.daily_limits, daily_limits);
+    assert_eq!(delegation.used_today, [50, 0, 1, 0]);
+    let market_data = banks_client.get_account(market).await.unwrap().unwrap().data;
+    let token_market = TokenMarket::try_from_slice(&market_data).unwrap();
+    assert_eq!(token_market.overpayment_policy, OverpaymentPolicy::Donate);
+    assert!(token_market.mint_receipts);
+}
+
+#[tokio::test]
+async fn test_deprecate_rejects_self_successor() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let deprecate = instruction::deprecate(
+        &id(),
+        &accounts.owner.pubkey(),
+        &payer.pubkey(),
+        &accounts.market.pubkey(),
+        Some(accounts.market.pubkey()),
+        "https://example.org/sunset".to_string(),
+    ).unwrap();
+    assert!(process(&mut banks_client, &payer, &[deprecate], &[&accounts.owner]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_deprecate() {
+    // The notice is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let successor = create_market(&mut banks_client, &payer).await;
+
+    let deprecate = instruction::deprecate(
+        &id(),
+        &accounts.owner.pubkey(),
+        &payer.pubkey(),
+        &accounts.market.pubkey(),
+        Some(successor.market.pubkey()),
+        "https://example.org/sunset".to_string(),
+    ).unwrap();
+    process(&mut banks_client, &payer, &[deprecate], &[&accounts.owner]).await.unwrap();
+
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    let market = TokenMarket::try_from_slice(&market.data).unwrap();
+    assert!(market.deprecated);
+    assert_eq!(market.successor(), Some(successor.market.pubkey()));
+    let notice = find_deprecation_address(&id(), &accounts.market.pubkey()).0;
+    let notice = banks_client.get_account(notice).await.unwrap().unwrap();
+    assert_eq!(DeprecationNotice::load(&notice.data).unwrap().message_uri, "https://example.org/sunset");
+}
+
+#[tokio::test]
+async fn test_deprecate_with_prefunded_notice() {
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    // Lamports sent to the notice address beforehand must not block it
+    let notice = find_deprecation_address(&id(), &accounts.market.pubkey()).0;
+    let prefund = solana_sdk::system_instruction::transfer(&payer.pubkey(), &notice, 1);
+    process(&mut banks_client, &payer, &[prefund], &[]).await.unwrap();
+
+    let deprecate = instruction::deprecate(
+        &id(),
+        &accounts.owner.pubkey(),
+        &payer.pubkey(),
+        &accounts.market.pubkey(),
+        None,
+        "https://example.org/sunset".to_string(),
+    ).unwrap();
+    process(&mut banks_client, &payer, &[deprecate], &[&accounts.owner]).await.unwrap();
+
+    let notice = banks_client.get_account(notice).await.unwrap().unwrap();
+    assert_eq!(notice.owner, id());
+    assert_eq!(notice.lamports, Rent::default().minimum_balance(DeprecationNotice::LEN));
+}
+
+#[tokio::test]
+async fn test_migrate_holdings_rejects_selling_markets() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let deprecated = create_market(&mut banks_client, &payer).await;
+    let selling = create_market_with(&mut banks_client, &payer, false, Some(TokenMarket::SELLABLE_FEATURES)).await;
+
+    // Migrated tokens were paid into the bank of the deprecated market
+    let migration = Keypair::new();
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &migration.pubkey(),
+            Rent::default().minimum_balance(HoldingsMigration::LEN),
+            HoldingsMigration::LEN as u64,
+            &token_market::id(),
+        ),
+        instruction::create_holdings_migration(
+            &id(),
+            &deprecated.owner.pubkey(),
+            &selling.owner.pubkey(),
+            &deprecated.market.pubkey(),
+            &selling.market.pubkey(),
+            &migration.pubkey(),
+            1,
+            1,
+        ).unwrap(),
+    ];
+    let result = process(&mut banks_client, &payer, instructions, &[&deprecated.owner, &selling.owner, &migration]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::UnbackedSelling as u32));
+}
+
+#[tokio::test]
+async fn test_migrate_holdings() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let deprecated = create_market(&mut banks_client, &payer).await;
+    let replacement = create_market(&mut banks_client, &payer).await;
+
+    let holder = Keypair::new();
+    let source = issue_tokens(&mut banks_client, &payer, &deprecated, &holder, EmittedAmount(10)).await;
+    let destination = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &destination, &replacement.emitter.pubkey(), &holder.pubkey()).await;
+
+    let migration = Keypair::new();
+    let instructions = &[
+        create_account(
+            &payer.pubkey(),
+            &migration.pubkey(),
+            Rent::default().minimum_balance(HoldingsMigration::LEN),
+            HoldingsMigration::LEN as u64,
+            &token_market::id(),
+        ),
+        instruction::create_holdings_migration(
+            &id(),
+            &deprecated.owner.pubkey(),
+            &replacement.owner.pubkey(),
+            &deprecated.market.pubkey(),
+            &replacement.market.pubkey(),
+            &migration.pubkey(),
+            3,
+            2,
+        ).unwrap(),
+    ];
+    process(
+        &mut banks_client,
+        &payer,
+        instructions,
+        &[&deprecated.owner, &replacement.owner, &migration],
+    ).await.unwrap();
+
+    let swap = instruction::migrate_holdings(
+        &id(),
+        &holder.pubkey(),
+        &migration.pubkey(),
+        &source.pubkey(),
+        &deprecated.emitter.pubkey(),
+        &replacement.emitter.pubkey(),
+        &destination.pubkey(),
+        EmittedAmount(4),
+    ).unwrap();
+    process(&mut banks_client, &payer, &[swap], &[&holder]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &source.pubkey()).await, 6);
+    assert_eq!(token_balance(&mut banks_client, &destination.pubkey()).await, 6);
+    // The swapped tokens are burned, not kept by the program
+    assert_mint_supply(&mut banks_client, &deprecated.emitter.pubkey(), 6).await;
+    assert_mint_supply(&mut banks_client, &replacement.emitter.pubkey(), 6).await;
+
+    // Converting to nothing would burn the holder tokens for free
+    let dust = instruction::migrate_holdings(
+        &id(),
+        &holder.pubkey(),
+        &migration.pubkey(),
+        &source.pubkey(),
+        &deprecated.emitter.pubkey(),
+        &replacement.emitter.pubkey(),
+        &destination.pubkey(),
+        EmittedAmount(0),
+    ).unwrap();
+    assert!(process(&mut banks_client, &payer, &[dust], &[&holder]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_buy_tokens_rejects_forged_market() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let write_off = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &write_off, &accounts.mint_acceptable.pubkey(), &buyer.pubkey()).await;
+    let mint_payment = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &accounts.mint_acceptable.pubkey(),
+        &write_off.pubkey(),
+        &payer.pubkey(),
+        &[],
+        100,
+    ).unwrap();
+    process(&mut banks_client, &payer, &[mint_payment], &[]).await.unwrap();
+    let recipient = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &recipient, &accounts.emitter.pubkey(), &buyer.pubkey()).await;
+
+    // Same market data, but held by another program
+    let mut program_test = program_test();
+    for key in [
+        accounts.bank.pubkey(),
+        accounts.emitter.pubkey(),
+        accounts.mint_acceptable.pubkey(),
+        write_off.pubkey(),
+        recipient.pubkey(),
+    ].iter() {
+        let account = banks_client.get_account(*key).await.unwrap().unwrap();
+        program_test.add_account(*key, account);
+    }
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    program_test.add_account(accounts.market.pubkey(), solana_sdk::account::Account {
+        owner: Pubkey::new_unique(),
+        ..market
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let instructions = instruction::approve_and_buy_tokens(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &buyer.pubkey(),
+        AcceptableAmount(30),
+    ).unwrap();
+    assert!(process(&mut banks_client, &payer, &instructions, &[&buyer]).await.is_err());
+    assert_eq!(token_balance(&mut banks_client, &write_off.pubkey()).await, 100);
+    assert_eq!(token_balance(&mut banks_client, &recipient.pubkey()).await, 0);
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_exact_delegation() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let buyer = Keypair::new();
+    let write_off = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &write_off, &accounts.mint_acceptable.pubkey(), &buyer.pubkey()).await;
+    let mint_payment = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &accounts.mint_acceptable.pubkey(),
+        &write_off.pubkey(),
+        &payer.pubkey(),
+        &[],
+        100,
+    ).unwrap();
+    process(&mut banks_client, &payer, &[mint_payment], &[]).await.unwrap();
+    let recipient = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &recipient, &accounts.emitter.pubkey(), &buyer.pubkey()).await;
+
+    let instructions = instruction::approve_and_buy_tokens(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &buyer.pubkey(),
+        AcceptableAmount(30),
+    ).unwrap();
+    let before = Balances::take(&mut banks_client, &[write_off.pubkey(), accounts.bank.pubkey(), recipient.pubkey()]).await;
+    process(&mut banks_client, &payer, &instructions, &[&buyer]).await.unwrap();
+    assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), -30).await;
+    assert_token_delta(&mut banks_client, &before, &accounts.bank.pubkey(), 30).await;
+    assert_token_delta(&mut banks_client, &before, &recipient.pubkey(), 30).await;
+    assert_mint_supply(&mut banks_client, &accounts.emitter.pubkey(), 30).await;
+    assert!(token_account(&mut banks_client, &write_off.pubkey()).await.delegate.is_none());
+
+    // An oversized delegation would stay spendable after the purchase
+    let instructions = &[
+        spl_token::instruction::approve(
+            &spl_token::id(),
+            &write_off.pubkey(),
+            &find_authority_address(&id()).0,
+            &buyer.pubkey(),
+            &[],
+            31,
+        ).unwrap(),
+        instruction::buy_tokens(
+            &id(),
+            &accounts.market.pubkey(),
+            &accounts.bank.pubkey(),
+            &accounts.emitter.pubkey(),
+            &recipient.pubkey(),
+            &write_off.pubkey(),
+            &spl_token::id(),
+            AcceptableAmount(30),
+        ).unwrap(),
+    ];
+    assert!(process(&mut banks_client, &payer, instructions, &[&buyer]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_overpayment_policy() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let buyer = Keypair::new();
+    let write_off = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &write_off, &accounts.mint_acceptable.pubkey(), &buyer.pubkey()).await;
+    let mint_payment = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &accounts.mint_acceptable.pubkey(),
+        &write_off.pubkey(),
+        &payer.pubkey(),
+        &[],
+        100,
+    ).unwrap();
+    process(&mut banks_client, &payer, &[mint_payment], &[]).await.unwrap();
+    let recipient = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &recipient, &accounts.emitter.pubkey(), &buyer.pubkey()).await;
+
+    let not_owner = instruction::set_overpayment_policy(&id(), &payer.pubkey(), &accounts.market.pubkey(), OverpaymentPolicy::Donate).unwrap();
+    assert!(process(&mut banks_client, &payer, &[not_owner], &[]).await.is_err());
+
+    // 30 tokens bought with more delegated each time, different delegations keep the transactions distinct
+    for (policy, delegated, paid) in &[(OverpaymentPolicy::Donate, 40, 40), (OverpaymentPolicy::Refund, 45, 30)] {
+        let set = instruction::set_overpayment_policy(&id(), &accounts.owner.pubkey(), &accounts.market.pubkey(), *policy).unwrap();
+        process(&mut banks_client, &payer, &[set], &[&accounts.owner]).await.unwrap();
+
+        let instructions = &[
+            spl_token::instruction::approve(
+                &spl_token::id(),
+                &write_off.pubkey(),
+                &find_authority_address(&id()).0,
+                &buyer.pubkey(),
+                &[],
+                *delegated,
+            ).unwrap(),
+            instruction::buy_tokens(
+                &id(),
+                &accounts.market.pubkey(),
+                &accounts.bank.pubkey(),
+                &accounts.emitter.pubkey(),
+                &recipient.pubkey(),
+                &write_off.pubkey(),
+                &spl_token::id(),
+                AcceptableAmount(30),
+            ).unwrap(),
+        ];
+        let before = Balances::take(&mut banks_client, &[write_off.pubkey(), accounts.bank.pubkey()]).await;
+        process(&mut banks_client, &payer, instructions, &[&buyer]).await.unwrap();
+        assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), -paid).await;
+        assert_token_delta(&mut banks_client, &before, &accounts.bank.pubkey(), *paid).await;
+        assert_eq!(token_account(&mut banks_client, &write_off.pubkey()).await.delegated_amount, 0);
+    }
+    assert_eq!(token_balance(&mut banks_client, &recipient.pubkey()).await, 60);
+}
+
+#[tokio::test]
+async fn test_buy_tokens_requires_receipt_accounts() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+
+    let not_owner = instruction::set_receipt_minting(&id(), &payer.pubkey(), &accounts.market.pubkey(), true).unwrap();
+    assert!(process(&mut banks_client, &payer, &[not_owner], &[]).await.is_err());
+    let enable = instruction::set_receipt_minting(&id(), &accounts.owner.pubkey(), &accounts.market.pubkey(), true).unwrap();
+    process(&mut banks_client, &payer, &[enable], &[&accounts.owner]).await.unwrap();
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    assert!(TokenMarket::try_from_slice(&market.data).unwrap().mint_receipts);
+
+    let instructions = instruction::approve_and_buy_tokens(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &buyer.pubkey(),
+        AcceptableAmount(30),
+    ).unwrap();
+    assert!(process(&mut banks_client, &payer, &instructions, &[&buyer]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_receipt() {
+    // The receipt accounts are allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+    let enable = instruction::set_receipt_minting(&id(), &accounts.owner.pubkey(), &accounts.market.pubkey(), true).unwrap();
+    process(&mut banks_client, &payer, &[enable], &[&accounts.owner]).await.unwrap();
+
+    let receipt_mint = Keypair::new();
+    let receipt_holding = Keypair::new();
+    let buy = instruction::with_receipt(
+        &id(),
+        instruction::buy_tokens(
+            &id(),
+            &accounts.market.pubkey(),
+            &accounts.bank.pubkey(),
+            &accounts.emitter.pubkey(),
+            &recipient.pubkey(),
+            &write_off.pubkey(),
+            &spl_token::id(),
+            AcceptableAmount(30),
+        ).unwrap(),
+        &payer.pubkey(),
+        &receipt_mint.pubkey(),
+        &receipt_holding.pubkey(),
+    );
+    let instructions = instruction::bundle_buy_tokens(&id(), buy, &write_off.pubkey(), &buyer.pubkey(), AcceptableAmount(30)).unwrap();
+    process(&mut banks_client, &payer, &instructions, &[&buyer, &receipt_mint, &receipt_holding]).await.unwrap();
+
+    assert_eq!(token_balance(&mut banks_client, &recipient.pubkey()).await, 30);
+    let holding = token_account(&mut banks_client, &receipt_holding.pubkey()).await;
+    assert_eq!((holding.owner, holding.amount), (buyer.pubkey(), 1));
+    let mint = banks_client.get_account(receipt_mint.pubkey()).await.unwrap().unwrap();
+    let mint = Mint::unpack(&mint.data).unwrap();
+    assert_eq!((mint.supply, mint.decimals, mint.mint_authority), (1, 0, COption::None));
+    let receipt = find_receipt_address(&id(), &receipt_mint.pubkey()).0;
+    let receipt = banks_client.get_account(receipt).await.unwrap().unwrap();
+    let receipt = PurchaseReceipt::try_from_slice(&receipt.data).unwrap();
+    assert_eq!((receipt.market, receipt.buyer, receipt.amount), (accounts.market.pubkey(), buyer.pubkey(), 30));
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_campaign_rejects_zero_code() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+
+    let buy = instruction::buy_tokens_with_campaign(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &payer.pubkey(),
+        AcceptableAmount(30),
+        [0; 8],
+    ).unwrap();
+    let instructions = instruction::bundle_buy_tokens(&id(), buy, &write_off.pubkey(), &buyer.pubkey(), AcceptableAmount(30)).unwrap();
+    assert!(process(&mut banks_client, &payer, &instructions, &[&buyer]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_campaign() {
+    // The campaign stats are allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+
+    for amount in &[30, 12] {
+        let buy = instruction::buy_tokens_with_campaign(
+            &id(),
+            &accounts.market.pubkey(),
+            &accounts.bank.pubkey(),
+            &accounts.emitter.pubkey(),
+            &recipient.pubkey(),
+            &write_off.pubkey(),
+            &payer.pubkey(),
+            AcceptableAmount(*amount),
+            *b"SPRING21",
+        ).unwrap();
+        let instructions = instruction::bundle_buy_tokens(&id(), buy, &write_off.pubkey(), &buyer.pubkey(), AcceptableAmount(*amount)).unwrap();
+        process(&mut banks_client, &payer, &instructions, &[&buyer]).await.unwrap();
+    }
+
+    let stats = find_campaign_address(&id(), &accounts.market.pubkey(), b"SPRING21").0;
+    let stats = banks_client.get_account(stats).await.unwrap().unwrap();
+    let stats = CampaignStats::try_from_slice(&stats.data).unwrap();
+    assert_eq!(stats, CampaignStats {
+        market: accounts.market.pubkey(),
+        campaign: *b"SPRING21",
+        purchases: 2,
+        amount: 42,
+    });
+    assert_eq!(token_balance(&mut banks_client, &recipient.pubkey()).await, 42);
+}
+
+#[tokio::test]
+async fn test_relayed_buy_tokens_with_tip() {
+    let (mut banks_client, relayer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &relayer).await;
+
+    // The buyer holds no SOL, the relayer pays every fee
+    let buyer = Keypair::new();
+    let write_off = Keypair::new();
+    create_token_account(&mut banks_client, &relayer, &write_off, &accounts.mint_acceptable.pubkey(), &buyer.pubkey()).await;
+    let mint_payment = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &accounts.mint_acceptable.pubkey(),
+        &write_off.pubkey(),
+        &relayer.pubkey(),
+        &[],
+        100,
+    ).unwrap();
+    process(&mut banks_client, &relayer, &[mint_payment], &[]).await.unwrap();
+    let recipient = Keypair::new();
+    create_token_account(&mut banks_client, &relayer, &recipient, &accounts.emitter.pubkey(), &buyer.pubkey()).await;
+    let tip_account = Keypair::new();
+    create_token_account(&mut banks_client, &relayer, &tip_account, &accounts.mint_acceptable.pubkey(), &relayer.pubkey()).await;
+
+    let buy = instruction::buy_tokens_with_tip(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &tip_account.pubkey(),
+        AcceptableAmount(40),
+        AcceptableAmount(2),
+    ).unwrap();
+    let instructions = instruction::bundle_buy_tokens(
+        &id(),
+        buy,
+        &write_off.pubkey(),
+        &buyer.pubkey(),
+        AcceptableAmount(42),
+    ).unwrap();
+    let before = Balances::take(
+        &mut banks_client,
+        &[write_off.pubkey(), accounts.bank.pubkey(), tip_account.pubkey(), recipient.pubkey()],
+    ).await;
+    process(&mut banks_client, &relayer, &instructions, &[&buyer]).await.unwrap();
+    assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), -42).await;
+    assert_token_delta(&mut banks_client, &before, &accounts.bank.pubkey(), 40).await;
+    assert_token_delta(&mut banks_client, &before, &tip_account.pubkey(), 2).await;
+    assert_token_delta(&mut banks_client, &before, &recipient.pubkey(), 40).await;
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_voucher() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let voucher_key = secp256k1::SecretKey::parse(&[7; 32]).unwrap();
+    let eth_address = solana_sdk::secp256k1_instruction::construct_eth_pubkey(
+        &secp256k1::PublicKey::from_secret_key(&voucher_key),
+    );
+    let set_signer = instruction::set_voucher_signer(
+        &id(),
+        &accounts.owner.pubkey(),
+        &accounts.market.pubkey(),
+        eth_address,
+    ).unwrap();
+    process(&mut banks_client, &payer, &[set_signer], &[&accounts.owner]).await.unwrap();
+
+    let buyer = Keypair::new();
+    let write_off = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &write_off, &accounts.mint_acceptable.pubkey(), &buyer.pubkey()).await;
+    let mint_payment = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &accounts.mint_acceptable.pubkey(),
+        &write_off.pubkey(),
+        &payer.pubkey(),
+        &[],
+        100,
+    ).unwrap();
+    process(&mut banks_client, &payer, &[mint_payment], &[]).await.unwrap();
+    let recipient = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &recipient, &accounts.emitter.pubkey(), &buyer.pubkey()).await;
+
+    let voucher = PurchaseVoucher {
+        market: accounts.market.pubkey(),
+        buyer: buyer.pubkey(),
+        max_amount: 50,
+        expiry: i64::MAX,
+    };
+    let voucher_instructions = |amount: u64, voucher: &PurchaseVoucher| {
+        // The secp256k1 instruction builder expects to be the first instruction
+        let verify = solana_sdk::secp256k1_instruction::new_secp256k1_instruction(
+            &voucher_key,
+            &borsh::BorshSerialize::try_to_vec(voucher).unwrap(),
+        );
+        let buy = instruction::buy_tokens_with_voucher(
+            &id(),
+            &accounts.market.pubkey(),
+            &accounts.bank.pubkey(),
+            &accounts.emitter.pubkey(),
+            &recipient.pubkey(),
+            &write_off.pubkey(),
+            AcceptableAmount(amount),
+            voucher,
+        ).unwrap();
+        let mut instructions = vec![verify];
+        instructions.extend(instruction::bundle_buy_tokens(
+            &id(),
+            buy,
+            &write_off.pubkey(),
+            &buyer.pubkey(),
+            AcceptableAmount(amount),
+        ).unwrap());
+        instructions
+    };
+
+    let over_limit = voucher_instructions(60, &voucher);
+    assert!(process(&mut banks_client, &payer, &over_limit, &[&buyer]).await.is_err());
+    let expired = voucher_instructions(30, &PurchaseVoucher { expiry: 0, ..voucher.clone() });
+    assert!(process(&mut banks_client, &payer, &expired, &[&buyer]).await.is_err());
+
+    let within_limit = voucher_instructions(30, &voucher);
+    let before = Balances::take(&mut banks_client, &[write_off.pubkey(), recipient.pubkey()]).await;
+    process(&mut banks_client, &payer, &within_limit, &[&buyer]).await.unwrap();
+    assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), -30).await;
+    assert_token_delta(&mut banks_client, &before, &recipient.pubkey(), 30).await;
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_quote() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let quote_key = secp256k1::SecretKey::parse(&[9; 32]).unwrap();
+    let eth_address = solana_sdk::secp256k1_instruction::construct_eth_pubkey(
+        &secp256k1::PublicKey::from_secret_key(&quote_key),
+    );
+    let set_signer = instruction::set_voucher_signer(
+        &id(),
+        &accounts.owner.pubkey(),
+        &accounts.market.pubkey(),
+        eth_address,
+    ).unwrap();
+    process(&mut banks_client, &payer, &[set_signer], &[&accounts.owner]).await.unwrap();
+
+    let buyer = Keypair::new();
+    let write_off = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &write_off, &accounts.mint_acceptable.pubkey(), &buyer.pubkey()).await;
+    let mint_payment = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &accounts.mint_acceptable.pubkey(),
+        &write_off.pubkey(),
+        &payer.pubkey(),
+        &[],
+        100,
+    ).unwrap();
+    process(&mut banks_client, &payer, &[mint_payment], &[]).await.unwrap();
+    let recipient = Keypair::new();
+    create_token_account(&mut banks_client, &payer, &recipient, &accounts.emitter.pubkey(), &buyer.pubkey()).await;
+
+    // 3 emitted tokens for every 2 acceptable ones
+    let quote = PriceQuote {
+        market: accounts.market.pubkey(),
+        numerator: 3,
+        denominator: 2,
+        max_amount: 50,
+        expiry: i64::MAX,
+    };
+    let quote_instructions = |amount: u64, signed: &PriceQuote, quote: &PriceQuote| {
+        let verify = solana_sdk::secp256k1_instruction::new_secp256k1_instruction(
+            &quote_key,
+            &borsh::BorshSerialize::try_to_vec(signed).unwrap(),
+        );
+        let buy = instruction::buy_tokens_with_quote(
+            &id(),
+            &accounts.market.pubkey(),
+            &accounts.bank.pubkey(),
+            &accounts.emitter.pubkey(),
+            &recipient.pubkey(),
+            &write_off.pubkey(),
+            AcceptableAmount(amount),
+            quote,
+        ).unwrap();
+        let mut instructions = vec![verify];
+        instructions.extend(instruction::bundle_buy_tokens(
+            &id(),
+            buy,
+            &write_off.pubkey(),
+            &buyer.pubkey(),
+            AcceptableAmount(amount),
+        ).unwrap());
+        instructions
+    };
+
+    let over_limit = quote_instructions(60, &quote, &quote);
+    assert!(process(&mut banks_client, &payer, &over_limit, &[&buyer]).await.is_err());
+    let expired = PriceQuote { expiry: 0, ..quote.clone() };
+    let expired = quote_instructions(30, &expired, &expired);
+    assert!(process(&mut banks_client, &payer, &expired, &[&buyer]).await.is_err());
+    // A better price than the signed one is refused
+    let altered = quote_instructions(30, &quote, &PriceQuote { numerator: 4, ..quote.clone() });
+    assert!(process(&mut banks_client, &payer, &altered, &[&buyer]).await.is_err());
+
+    let within_limit = quote_instructions(30, &quote, &quote);
+    let before = Balances::take(&mut banks_client, &[write_off.pubkey(), accounts.bank.pubkey(), recipient.pubkey()]).await;
+    process(&mut banks_client, &payer, &within_limit, &[&buyer]).await.unwrap();
+    assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), -30).await;
+    assert_token_delta(&mut banks_client, &before, &accounts.bank.pubkey(), 30).await;
+    assert_token_delta(&mut banks_client, &before, &recipient.pubkey(), 45).await;
+    assert_mint_supply(&mut banks_client, &accounts.emitter.pubkey(), 45).await;
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_required_memo() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+
+    let not_owner = instruction::set_memo_policy(&id(), &payer.pubkey(), &accounts.market.pubkey(), true).unwrap();
+    assert!(process(&mut banks_client, &payer, &[not_owner], &[]).await.is_err());
+    let require = instruction::set_memo_policy(&id(), &accounts.owner.pubkey(), &accounts.market.pubkey(), true).unwrap();
+    process(&mut banks_client, &payer, &[require], &[&accounts.owner]).await.unwrap();
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    assert!(TokenMarket::try_from_slice(&market.data).unwrap().require_memo);
+
+    let buy = instruction::buy_tokens(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &spl_token::id(),
+        AcceptableAmount(30),
+    ).unwrap();
+    let bundle = |buy: Instruction| {
+        instruction::bundle_buy_tokens(&id(), buy, &write_off.pubkey(), &buyer.pubkey(), AcceptableAmount(30)).unwrap()
+    };
+
+    // Without the instructions sysvar the program can't see a memo
+    let mut instructions = bundle(buy.clone());
+    instructions.insert(0, memo::memo("invoice 42"));
+    let result = process(&mut banks_client, &payer, &instructions, &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::MemoRequired as u32));
+
+    let buy = instruction::with_instructions_sysvar(buy);
+    let result = process(&mut banks_client, &payer, &bundle(buy.clone()), &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::MemoRequired as u32));
+    assert_eq!(token_balance(&mut banks_client, &write_off.pubkey()).await, 100);
+
+    // The memo may come anywhere in the transaction
+    let mut instructions = bundle(buy);
+    instructions.push(memo::memo("invoice 42"));
+    let before = Balances::take(&mut banks_client, &[write_off.pubkey(), recipient.pubkey()]).await;
+    process(&mut banks_client, &payer, &instructions, &[&buyer]).await.unwrap();
+    assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), -30).await;
+    assert_token_delta(&mut banks_client, &before, &recipient.pubkey(), 30).await;
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_memo_not_required() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+
+    // The sysvar is optional and a transaction without a memo is fine
+    let buy = instruction::with_instructions_sysvar(instruction::buy_tokens(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &spl_token::id(),
+        AcceptableAmount(30),
+    ).unwrap());
+    assert_eq!(instruction::with_instructions_sysvar(buy.clone()), buy);
+    let instructions = instruction::bundle_buy_tokens(&id(), buy, &write_off.pubkey(), &buyer.pubkey(), AcceptableAmount(30)).unwrap();
+    process(&mut banks_client, &payer, &instructions, &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient.pubkey()).await, 30);
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_quote_under_sandwich_guard() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+
+    let quote_key = secp256k1::SecretKey::parse(&[9; 32]).unwrap();
+    let eth_address = solana_sdk::secp256k1_instruction::construct_eth_pubkey(
+        &secp256k1::PublicKey::from_secret_key(&quote_key),
+    );
+    let set_signer = instruction::set_voucher_signer(&id(), &accounts.owner.pubkey(), &accounts.market.pubkey(), eth_address).unwrap();
+    let not_owner = instruction::set_sandwich_guard(&id(), &payer.pubkey(), &accounts.market.pubkey(), true).unwrap();
+    assert!(process(&mut banks_client, &payer, &[not_owner], &[]).await.is_err());
+    let guard = instruction::set_sandwich_guard(&id(), &accounts.owner.pubkey(), &accounts.market.pubkey(), true).unwrap();
+    process(&mut banks_client, &payer, &[set_signer, guard], &[&accounts.owner]).await.unwrap();
+    let market = banks_client.get_account(accounts.market.pubkey()).await.unwrap().unwrap();
+    assert!(TokenMarket::try_from_slice(&market.data).unwrap().sandwich_guard);
+
+    let quote = PriceQuote {
+        market: accounts.market.pubkey(),
+        numerator: 3,
+        denominator: 2,
+        max_amount: 50,
+        expiry: i64::MAX,
+    };
+    let buy = instruction::buy_tokens_with_quote(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        AcceptableAmount(30),
+        &quote,
+    ).unwrap();
+    let mut instructions = vec![solana_sdk::secp256k1_instruction::new_secp256k1_instruction(
+        &quote_key,
+        &borsh::BorshSerialize::try_to_vec(&quote).unwrap(),
+    )];
+    instructions.extend(instruction::bundle_buy_tokens(&id(), buy, &write_off.pubkey(), &buyer.pubkey(), AcceptableAmount(30)).unwrap());
+
+    // Even a later instruction on the market is refused
+    let mut sandwiched = instructions.clone();
+    sandwiched.push(instruction::buy_tokens(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &spl_token::id(),
+        AcceptableAmount(10),
+    ).unwrap());
+    let result = process(&mut banks_client, &payer, &sandwiched, &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::TransactionNotIsolated as u32));
+
+    process(&mut banks_client, &payer, &instructions, &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient.pubkey()).await, 45);
+}
+
+#[tokio::test]
+async fn test_failed_purchase_with_frozen_bank_changes_nothing() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+    let freeze = spl_token::instruction::freeze_account(
+        &spl_token::id(),
+        &accounts.bank.pubkey(),
+        &accounts.mint_acceptable.pubkey(),
+        &payer.pubkey(),
+        &[],
+    ).unwrap();
+    process(&mut banks_client, &payer, &[freeze], &[]).await.unwrap();
+
+    let instructions = instruction::approve_and_buy_tokens(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &buyer.pubkey(),
+        AcceptableAmount(30),
+    ).unwrap();
+    let before = Balances::take(&mut banks_client, &[write_off.pubkey(), accounts.bank.pubkey(), recipient.pubkey()]).await;
+    let result = process(&mut banks_client, &payer, &instructions, &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenError::AccountFrozen as u32));
+
+    assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), 0).await;
+    assert_token_delta(&mut banks_client, &before, &accounts.bank.pubkey(), 0).await;
+    assert_token_delta(&mut banks_client, &before, &recipient.pubkey(), 0).await;
+    assert_mint_supply(&mut banks_client, &accounts.emitter.pubkey(), 0).await;
+    // The approval of the same transaction is rolled back as well
+    assert!(token_account(&mut banks_client, &write_off.pubkey()).await.delegate.is_none());
+}
+
+#[tokio::test]
+async fn test_failed_tip_rolls_back_the_payment() {
+    let (mut banks_client, relayer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &relayer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &relayer, &accounts, &buyer).await;
+    let tip_account = Keypair::new();
+    create_token_account(&mut banks_client, &relayer, &tip_account, &accounts.mint_acceptable.pubkey(), &relayer.pubkey()).await;
+    let freeze = spl_token::instruction::freeze_account(
+        &spl_token::id(),
+        &tip_account.pubkey(),
+        &accounts.mint_acceptable.pubkey(),
+        &relayer.pubkey(),
+        &[],
+    ).unwrap();
+    process(&mut banks_client, &relayer, &[freeze], &[]).await.unwrap();
+
+    // The bank is paid and the tokens minted before the tip transfer fails
+    let buy = instruction::buy_tokens_with_tip(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &tip_account.pubkey(),
+        AcceptableAmount(40),
+        AcceptableAmount(2),
+    ).unwrap();
+    let instructions = instruction::bundle_buy_tokens(&id(), buy, &write_off.pubkey(), &buyer.pubkey(), AcceptableAmount(42)).unwrap();
+    let before = Balances::take(&mut banks_client, &[write_off.pubkey(), accounts.bank.pubkey(), recipient.pubkey()]).await;
+    let result = process(&mut banks_client, &relayer, &instructions, &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenError::AccountFrozen as u32));
+
+    assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), 0).await;
+    assert_token_delta(&mut banks_client, &before, &accounts.bank.pubkey(), 0).await;
+    assert_token_delta(&mut banks_client, &before, &recipient.pubkey(), 0).await;
+    assert_mint_supply(&mut banks_client, &accounts.emitter.pubkey(), 0).await;
+}
+
+#[tokio::test]
+async fn test_failed_later_instruction_rolls_back_the_purchase() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Keypair::new();
+    let (write_off, recipient) = fund_buyer(&mut banks_client, &payer, &accounts, &buyer).await;
+
+    // The bundle revokes the delegation after the first purchase, so the repeated one fails
+    let buy = instruction::buy_tokens(
+        &id(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.emitter.pubkey(),
+        &recipient.pubkey(),
+        &write_off.pubkey(),
+        &spl_token::id(),
+        AcceptableAmount(30),
+    ).unwrap();
+    let mut instructions = instruction::bundle_buy_tokens(&id(), buy.clone(), &write_off.pubkey(), &buyer.pubkey(), AcceptableAmount(30)).unwrap();
+    instructions.push(buy);
+    let before = Balances::take(&mut banks_client, &[write_off.pubkey(), accounts.bank.pubkey(), recipient.pubkey()]).await;
+    let result = process(&mut banks_client, &payer, &instructions, &[&buyer]).await;
+    assert_instruction_error(result, 3, InstructionError::Custom(TokenMarketError::DelegationMismatch as u32));
+
+    assert_token_delta(&mut banks_client, &before, &write_off.pubkey(), 0).await;
+    assert_token_delta(&mut banks_client, &before, &accounts.bank.pubkey(), 0).await;
+    assert_token_delta(&mut banks_client, &before, &recipient.pubkey(), 0).await;
+    assert_mint_supply(&mut banks_client, &accounts.emitter.pubkey(), 0).await;
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_missing_bank() {
+    let mut program_test = program_test();
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &Pubkey::new_unique(), &bank, &mint_acceptable, &emitter);
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    // The bank was closed, it is caught before any account is read
+    let buy = instruction::buy_tokens(
+        &id(),
+        &market,
+        &bank,
+        &emitter,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &spl_token::id(),
+        AcceptableAmount(30),
+    ).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::BankMissing as u32));
+}
+
+#[tokio::test]
+async fn test_pause_and_resume() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let not_owner = instruction::pause(&id(), &payer.pubkey(), &market).unwrap();
+    let result = process(&mut banks_client, &payer, &[not_owner], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::InvalidAccountData);
+
+    let pause = instruction::pause(&id(), &owner.pubkey(), &market).unwrap();
+    process(&mut banks_client, &payer, &[pause], &[&owner]).await.unwrap();
+    let buy = instruction::buy_tokens(
+        &id(),
+        &market,
+        &bank,
+        &emitter,
+        &Pubkey::new_unique(),
+        &Pubkey::new_unique(),
+        &spl_token::id(),
+        AcceptableAmount(30),
+    ).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy.clone()], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::MarketPaused as u32));
+
+    // Once resumed the purchase gets past the pause, to the closed bank
+    let resume = instruction::resume(&id(), &owner.pubkey(), &market).unwrap();
+    process(&mut banks_client, &payer, &[resume], &[&owner]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &[memo::memo("again"), buy], &[]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::BankMissing as u32));
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_frozen_recipient() {
+    let mut program_test = program_test();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient, buyer) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &Pubkey::new_unique(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, Account {
+        delegate: COption::Some(authority),
+        delegated_amount: 30,
+        ..token_account_state(&mint_acceptable, &buyer, 100)
+    });
+    // Only the market authority can freeze emitted tokens, so no instruction gets here
+    add_packed(&mut program_test, &recipient, Account {
+        state: AccountState::Frozen,
+        ..token_account_state(&emitter, &buyer, 0)
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let buy = instruction::buy_tokens(
+        &id(),
+        &market,
+        &bank,
+        &emitter,
+        &recipient,
+        &write_off,
+        &spl_token::id(),
+        AcceptableAmount(30),
+    ).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::RecipientFrozen as u32));
+    assert_eq!(token_balance(&mut banks_client, &write_off).await, 100);
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_rejects_foreign_messages() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let payload = PurchasePayload { market, recipient: buyer, amount: 30 };
+    let (forged, foreign, misdirected) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let messages = [
+        (forged, add_posted_vaa(&mut program_test, &forged, &Pubkey::new_unique(), [4; 32], 1, &payload)),
+        (foreign, add_posted_vaa(&mut program_test, &foreign, &wormhole::id(), [5; 32], 1, &payload)),
+        (misdirected, add_posted_vaa(&mut program_test, &misdirected, &wormhole::id(), [4; 32], 2, &PurchasePayload {
+            market: Pubkey::new_unique(),
+            ..payload.clone()
+        })),
+    ];
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let not_owner = instruction::set_wormhole_emitter(&id(), &payer.pubkey(), &market, 2, [4; 32]).unwrap();
+    assert!(process(&mut banks_client, &payer, &[not_owner], &[]).await.is_err());
+    let register = instruction::set_wormhole_emitter(&id(), &owner.pubkey(), &market, 2, [4; 32]).unwrap();
+    process(&mut banks_client, &payer, &[register], &[&owner]).await.unwrap();
+
+    for (posted_vaa, vaa) in &messages {
+        let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, posted_vaa, vaa, &payer.pubkey()).unwrap();
+        let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+        assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidVaa as u32));
+    }
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_rejects_forged_market() {
+    let mut program_test = program_test();
+    let (market, emitter) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &Pubkey::new_unique(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    let (mut banks_client, _, _) = program_test.start().await;
+    let market_account = banks_client.get_account(market).await.unwrap().unwrap();
+
+    // Same market data, but held by another program
+    let mut program_test = program_test();
+    program_test.add_account(market, solana_sdk::account::Account { owner: Pubkey::new_unique(), ..market_account });
+    let (buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(find_authority_address(&id()).0),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 1, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::IncorrectProgramId);
+    assert_mint_supply(&mut banks_client, &emitter, 0).await;
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_on_paused_market() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 7, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let pause = instruction::pause(&id(), &owner.pubkey(), &market).unwrap();
+    process(&mut banks_client, &payer, &[pause], &[&owner]).await.unwrap();
+
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::MarketPaused as u32));
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_on_phased_market() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 7, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    // The phases account is allocated in CPI, which needs the BPF build
+    program_test.add_account(find_sale_phases_address(&id(), &market).0, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(SalePhases::LEN),
+        data: vec![0; SalePhases::LEN],
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let phase = SalePhase { start: 0, price_numerator: 2, price_denominator: 1, ..SalePhase::default() };
+    let set = instruction::set_sale_phases(&id(), &owner.pubkey(), &payer.pubkey(), &market, vec![phase], 0).unwrap();
+    process(&mut banks_client, &payer, &[set], &[&owner]).await.unwrap();
+
+    // A redemption pays at par outside of any phase
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::PhasedSale as u32));
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_on_capped_market() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 7, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let set_cap = instruction::set_wallet_cap(&id(), &owner.pubkey(), &market, EmittedAmount(100)).unwrap();
+    process(&mut banks_client, &payer, &[set_cap], &[&owner]).await.unwrap();
+
+    // A redemption has no buyer record to count against
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::WalletCapped as u32));
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_before_sale_start() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 7, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let schedule = instruction::set_sale_window(&id(), &owner.pubkey(), &market, i64::MAX - 1, 0).unwrap();
+    process(&mut banks_client, &payer, &[schedule], &[&owner]).await.unwrap();
+
+    // The VAA stays unconsumed, so it can be redeemed once the sale starts
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::SaleNotStarted as u32));
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_on_allowlisted_market() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 7, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let enable = instruction::set_allowlist(&id(), &owner.pubkey(), &market, true).unwrap();
+    process(&mut banks_client, &payer, &[enable], &[&owner]).await.unwrap();
+
+    // A redemption can't show an allowlist entry
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::AllowlistedMarket as u32));
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_on_tiered_market() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let mut token_market = market_state(&owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    token_market.pricing_tiers[0] = PricingTier { up_to: 0, price_numerator: 2, price_denominator: 1 };
+    add_market_state(&mut program_test, &market, &token_market);
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 7, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    // A redemption pays at par and can't count in what the tiers sold
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::TieredMarket as u32));
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase_on_bonding_curve() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let mut token_market = market_state(&owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    token_market.bonding_curve = BondingCurve { kind: CurveKind::Linear, base_price: curve::PRICE_SCALE, growth: 1, step: 0 };
+    add_market_state(&mut program_test, &market, &token_market);
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 7, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    // A redemption pays at par and can't move the curve
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::TieredMarket as u32));
+}
+
+#[tokio::test]
+async fn test_redeem_wormhole_purchase() {
+    // The consumed message record is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, emitter, buyer, recipient, posted_vaa) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(find_authority_address(&id()).0),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let vaa = add_posted_vaa(&mut program_test, &posted_vaa, &wormhole::id(), [4; 32], 7, &PurchasePayload { market, recipient: buyer, amount: 30 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let register = instruction::set_wormhole_emitter(&id(), &owner.pubkey(), &market, 2, [4; 32]).unwrap();
+    process(&mut banks_client, &payer, &[register], &[&owner]).await.unwrap();
+
+    let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+    process(&mut banks_client, &payer, &[redeem.clone()], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 30);
+    assert_mint_supply(&mut banks_client, &emitter, 30).await;
+    let consumed = find_consumed_vaa_address(&id(), 2, &[4; 32], 7).0;
+    let consumed = banks_client.get_account(consumed).await.unwrap().unwrap();
+    assert_eq!(ConsumedVaa::try_from_slice(&consumed.data).unwrap(), ConsumedVaa { market, recipient, amount: 30 });
+
+    // The memo only tells the transaction apart, the same one would be deduplicated
+    let result = process(&mut banks_client, &payer, &[memo::memo("again"), redeem], &[]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::VaaConsumed as u32));
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 30);
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_fiat_rejects_other_signers() {
+    let mut program_test = program_test();
+    let (owner, onramp, impostor) = (Keypair::new(), Keypair::new(), Keypair::new());
+    let (market, emitter, buyer, recipient) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    // No provider is trusted until the owner sets one
+    let buy = instruction::buy_tokens_with_fiat(&id(), &onramp.pubkey(), &market, &emitter, &recipient, &payer.pubkey(), AcceptableAmount(30), [1; 32]).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy], &[&onramp]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidOnrampAttestation as u32));
+
+    let not_owner = instruction::set_onramp_signer(&id(), &payer.pubkey(), &market, &onramp.pubkey()).unwrap();
+    assert!(process(&mut banks_client, &payer, &[not_owner], &[]).await.is_err());
+    let set = instruction::set_onramp_signer(&id(), &owner.pubkey(), &market, &onramp.pubkey()).unwrap();
+    process(&mut banks_client, &payer, &[set], &[&owner]).await.unwrap();
+
+    let buy = instruction::buy_tokens_with_fiat(&id(), &impostor.pubkey(), &market, &emitter, &recipient, &payer.pubkey(), AcceptableAmount(30), [1; 32]).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy], &[&impostor]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidOnrampAttestation as u32));
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 0);
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_fiat_rejects_forged_market() {
+    let mut program_test = program_test();
+    let attacker = Keypair::new();
+    let (market, emitter, recipient) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &attacker.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let set = instruction::set_onramp_signer(&id(), &attacker.pubkey(), &market, &attacker.pubkey()).unwrap();
+    process(&mut banks_client, &payer, &[set], &[&attacker]).await.unwrap();
+    let market_account = banks_client.get_account(market).await.unwrap().unwrap();
+
+    // A copy held by another program trusts the attacker to attest payments
+    let mut program_test = program_test();
+    program_test.add_account(market, solana_sdk::account::Account { owner: Pubkey::new_unique(), ..market_account });
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(find_authority_address(&id()).0),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &attacker.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let buy = instruction::buy_tokens_with_fiat(&id(), &attacker.pubkey(), &market, &emitter, &recipient, &payer.pubkey(), AcceptableAmount(30), [1; 32]).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy], &[&attacker]).await;
+    assert_instruction_error(result, 0, InstructionError::IncorrectProgramId);
+    assert_mint_supply(&mut banks_client, &emitter, 0).await;
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_fiat() {
+    // The settlement record is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let mut program_test = program_test();
+    let (owner, onramp) = (Keypair::new(), Keypair::new());
+    let (market, emitter, buyer, recipient) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &Pubkey::new_unique(), &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(find_authority_address(&id()).0),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let set = instruction::set_onramp_signer(&id(), &owner.pubkey(), &market, &onramp.pubkey()).unwrap();
+    process(&mut banks_client, &payer, &[set], &[&owner]).await.unwrap();
+
+    let buy = instruction::buy_tokens_with_fiat(&id(), &onramp.pubkey(), &market, &emitter, &recipient, &payer.pubkey(), AcceptableAmount(30), [7; 32]).unwrap();
+    process(&mut banks_client, &payer, &[buy.clone()], &[&onramp]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 30);
+    assert_mint_supply(&mut banks_client, &emitter, 30).await;
+    let settlement = find_fiat_settlement_address(&id(), &market, &[7; 32]).0;
+    let settlement = banks_client.get_account(settlement).await.unwrap().unwrap();
+    let settlement = FiatSettlement::try_from_slice(&settlement.data).unwrap();
+    assert_eq!((settlement.recipient, settlement.amount, settlement.reference), (recipient, 30, [7; 32]));
+
+    // The same payment reference never mints twice
+    let result = process(&mut banks_client, &payer, &[memo::memo("again"), buy], &[&onramp]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::FiatPaymentSettled as u32));
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 30);
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_fiat_protocol_fee() {
+    // The settlement record is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let mut program_test = program_test();
+    let (owner, onramp) = (Keypair::new(), Keypair::new());
+    let authority = find_authority_address(&id()).0;
+    let (market, mint_acceptable, emitter, buyer, recipient) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &Pubkey::new_unique(), &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let fee_vault = find_fee_vault_address(&id(), &market, &mint_acceptable).0;
+    add_packed(&mut program_test, &fee_vault, token_account_state(&mint_acceptable, &authority, 0));
+    let provider = find_associated_token_address(&onramp.pubkey(), &mint_acceptable);
+    add_packed(&mut program_test, &provider, token_account_state(&mint_acceptable, &onramp.pubkey(), 15));
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let set = instruction::set_onramp_signer(&id(), &owner.pubkey(), &market, &onramp.pubkey()).unwrap();
+    let set_fee = instruction::set_protocol_fee(&id(), &owner.pubkey(), &payer.pubkey(), &market, &mint_acceptable, 250).unwrap();
+    process(&mut banks_client, &payer, &[set, set_fee], &[&owner]).await.unwrap();
+
+    let buy = |reference| instruction::buy_tokens_with_fiat(&id(), &onramp.pubkey(), &market, &emitter, &recipient, &payer.pubkey(), AcceptableAmount(400), reference).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy([1; 32])], &[&onramp]).await;
+    assert_instruction_error(result, 0, InstructionError::NotEnoughAccountKeys);
+
+    // The provider pays the fee from its own acceptable tokens
+    let buy = instruction::with_external_protocol_fee(&id(), buy([2; 32]), &fee_vault, &owner.pubkey(), &onramp.pubkey(), &mint_acceptable);
+    process(&mut banks_client, &payer, &[buy], &[&onramp]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 400);
+    assert_eq!(token_balance(&mut banks_client, &provider).await, 5);
+    assert_eq!(token_balance(&mut banks_client, &fee_vault).await, 10);
+}
+
+#[tokio::test]
+async fn test_buy_tokens_above_escrow_threshold() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient, buyer) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, Account {
+        delegate: COption::Some(authority),
+        delegated_amount: 30,
+        ..token_account_state(&mint_acceptable, &buyer, 100)
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let no_window = instruction::set_dispute_escrow(&id(), &owner.pubkey(), &market, EmittedAmount(30), 0, &Pubkey::default()).unwrap();
+    let result = process(&mut banks_client, &payer, &[no_window], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidDisputeWindow as u32));
+    let set = instruction::set_dispute_escrow(&id(), &owner.pubkey(), &market, EmittedAmount(30), 3600, &Pubkey::default()).unwrap();
+    process(&mut banks_client, &payer, &[set], &[&owner]).await.unwrap();
+
+    let buy = instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(30)).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::EscrowRequired as u32));
+    assert_eq!(token_balance(&mut banks_client, &write_off).await, 100);
+}
+
+#[tokio::test]
+async fn test_dispute_escrowed_purchase() {
+    // The escrow and its vault are allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let mut program_test = program_test();
+    let (owner, guardian) = (Keypair::new(), Keypair::new());
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient, buyer, destination) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, Account {
+        delegate: COption::Some(authority),
+        delegated_amount: 50,
+        ..token_account_state(&mint_acceptable, &buyer, 100)
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    add_packed(&mut program_test, &mint_acceptable, Mint {
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &destination, token_account_state(&mint_acceptable, &owner.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let set = instruction::set_dispute_escrow(&id(), &owner.pubkey(), &market, EmittedAmount(30), 3600, &guardian.pubkey()).unwrap();
+    process(&mut banks_client, &payer, &[set], &[&owner]).await.unwrap();
+
+    let buy = instruction::buy_tokens_escrowed(&id(), &market, &bank, &emitter, &mint_acceptable, &recipient, &write_off, &payer.pubkey(), AcceptableAmount(50), 1).unwrap();
+    process(&mut banks_client, &payer, &[buy], &[]).await.unwrap();
+    let escrow = find_escrow_address(&id(), &market, &write_off, 1).0;
+    let vault = find_escrow_vault_address(&id(), &escrow).0;
+    let payment = find_escrow_payment_address(&id(), &escrow).0;
+    assert_eq!(token_balance(&mut banks_client, &vault).await, 50);
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 0);
+    assert_eq!(token_balance(&mut banks_client, &payment).await, 50);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 0);
+
+    // The payment a dispute may refund is out of the owner's reach
+    let withdraw = instruction::withdraw_bank(&id(), &owner.pubkey(), &market, &bank, &destination, AcceptableAmount(50)).unwrap();
+    let result = process(&mut banks_client, &payer, &[withdraw], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenError::InsufficientFunds as u32));
+
+    let release = instruction::release_escrow(&id(), &market, &bank, &escrow, &recipient).unwrap();
+    let result = process(&mut banks_client, &payer, &[release.clone()], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::DisputeWindowOpen as u32));
+
+    let stranger = instruction::dispute_escrow(&id(), &payer.pubkey(), &market, &escrow, &emitter, &write_off).unwrap();
+    assert!(process(&mut banks_client, &payer, &[stranger], &[]).await.is_err());
+    let dispute = instruction::dispute_escrow(&id(), &guardian.pubkey(), &market, &escrow, &emitter, &write_off).unwrap();
+    process(&mut banks_client, &payer, &[dispute], &[&guardian]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &write_off).await, 100);
+    assert_eq!(token_balance(&mut banks_client, &vault).await, 0);
+    assert_eq!(token_balance(&mut banks_client, &payment).await, 0);
+    assert_mint_supply(&mut banks_client, &emitter, 0).await;
+    let escrow_account = banks_client.get_account(escrow).await.unwrap().unwrap();
+    assert_eq!(PurchaseEscrow::try_from_slice(&escrow_account.data).unwrap().status, EscrowStatus::Disputed);
+
+    let result = process(&mut banks_client, &payer, &[release], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::EscrowSettled as u32));
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_coupon() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient, buyer) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, Account {
+        delegate: COption::Some(authority),
+        delegated_amount: 40,
+        ..token_account_state(&mint_acceptable, &buyer, 100)
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let code_hash = coupon_code_hash(&market, "spring");
+    let coupon = find_coupon_address(&id(), &market, &code_hash).0;
+    let mut data = vec![0; Coupon::LEN];
+    Coupon { market, code_hash, discount_bps: 2000, remaining_uses: 1, expiry: 0 }.serialize(&mut &mut data[..]).unwrap();
+    program_test.add_account(coupon, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    // The coupon itself is allocated in CPI, only the checks before it run here
+    let free = instruction::create_coupon(&id(), &owner.pubkey(), &market, &payer.pubkey(), [1; 32], 10_000, 5, 0).unwrap();
+    let result = process(&mut banks_client, &payer, &[free], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidDiscount as u32));
+    assert_eq!(code_hash, coupon_code_hash(&market, " SPRING "));
+
+    // 20% off: the 40 paid emit 50
+    let buy = instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(40)).unwrap();
+    let buy = instruction::with_coupon(&id(), buy, &market, &code_hash);
+    process(&mut banks_client, &payer, &[buy.clone()], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 40);
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 50);
+    let coupon_account = banks_client.get_account(coupon).await.unwrap().unwrap();
+    assert_eq!(Coupon::try_from_slice(&coupon_account.data).unwrap().remaining_uses, 0);
+
+    let result = process(&mut banks_client, &payer, &[memo::memo("again"), buy], &[]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::CouponExhausted as u32));
+}
+
+#[tokio::test]
+async fn test_claim_gift() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &Pubkey::new_unique(), 60));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &Pubkey::new_unique(), 0));
+    // A pending gift, buying one allocates accounts in CPI, which needs the BPF build
+    let secret = [7; 32];
+    let gift = find_gift_address(&id(), &market, &gift_secret_hash(&secret)).0;
+    let vault = find_gift_vault_address(&id(), &gift).0;
+    let payment = find_gift_payment_address(&id(), &gift).0;
+    add_packed(&mut program_test, &vault, token_account_state(&emitter, &authority, 40));
+    add_packed(&mut program_test, &payment, token_account_state(&mint_acceptable, &authority, 40));
+    let mut data = vec![0; Gift::LEN];
+    Gift {
+        market,
+        write_off,
+        vault,
+        paid: 40,
+        fee: 0,
+        emitted: 40,
+        expiry: i64::MAX,
+        status: GiftStatus::Pending,
+    }.serialize(&mut &mut data[..]).unwrap();
+    program_test.add_account(gift, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let refund = instruction::refund_gift(&id(), &market, &gift, &emitter, &write_off).unwrap();
+    let result = process(&mut banks_client, &payer, &[refund.clone()], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::GiftNotExpired as u32));
+
+    // Another secret derives another gift address
+    let mut wrong = instruction::claim_gift(&id(), &market, &bank, &recipient, [8; 32]).unwrap();
+    wrong.accounts[token_market::accounts::ClaimGiftAccounts::GIFT].pubkey = gift;
+    wrong.accounts[token_market::accounts::ClaimGiftAccounts::VAULT].pubkey = vault;
+    wrong.accounts[token_market::accounts::ClaimGiftAccounts::PAYMENT].pubkey = payment;
+    let result = process(&mut banks_client, &payer, &[wrong], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::InvalidArgument);
+
+    let claim = instruction::claim_gift(&id(), &market, &bank, &recipient, secret).unwrap();
+    process(&mut banks_client, &payer, &[claim], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 40);
+    assert_eq!(token_balance(&mut banks_client, &vault).await, 0);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 40);
+    assert_eq!(token_balance(&mut banks_client, &payment).await, 0);
+    let gift_account = banks_client.get_account(gift).await.unwrap().unwrap();
+    assert_eq!(Gift::try_from_slice(&gift_account.data).unwrap().status, GiftStatus::Claimed);
+
+    let result = process(&mut banks_client, &payer, &[refund], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::GiftSettled as u32));
+}
+
+#[tokio::test]
+async fn test_escrowed_purchase_holds_protocol_fee() {
+    // The escrow and its vault are allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let mut program_test = program_test();
+    let (owner, guardian) = (Keypair::new(), Keypair::new());
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient, buyer) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, Account {
+        delegate: COption::Some(authority),
+        delegated_amount: 400,
+        ..token_account_state(&mint_acceptable, &buyer, 400)
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    add_packed(&mut program_test, &mint_acceptable, Mint {
+        is_initialized: true,
+        ..Mint::default()
+    });
+    let fee_vault = find_fee_vault_address(&id(), &market, &mint_acceptable).0;
+    add_packed(&mut program_test, &fee_vault, token_account_state(&mint_acceptable, &authority, 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let setup = [
+        instruction::set_dispute_escrow(&id(), &owner.pubkey(), &market, EmittedAmount(30), 3600, &guardian.pubkey()).unwrap(),
+        instruction::set_protocol_fee(&id(), &owner.pubkey(), &payer.pubkey(), &market, &mint_acceptable, 250).unwrap(),
+    ];
+    process(&mut banks_client, &payer, &setup, &[&owner]).await.unwrap();
+
+    // The fee waits in the payment account until the escrow is settled
+    let buy = instruction::buy_tokens_escrowed(&id(), &market, &bank, &emitter, &mint_acceptable, &recipient, &write_off, &payer.pubkey(), AcceptableAmount(400), 1).unwrap();
+    process(&mut banks_client, &payer, &[buy], &[]).await.unwrap();
+    let escrow = find_escrow_address(&id(), &market, &write_off, 1).0;
+    let payment = find_escrow_payment_address(&id(), &escrow).0;
+    let escrow_account = banks_client.get_account(escrow).await.unwrap().unwrap();
+    let purchase = PurchaseEscrow::try_from_slice(&escrow_account.data).unwrap();
+    assert_eq!((purchase.paid, purchase.fee), (390, 10));
+    assert_eq!(token_balance(&mut banks_client, &payment).await, 400);
+    assert_eq!(token_balance(&mut banks_client, &fee_vault).await, 0);
+
+    // A dispute refunds the fee along with the payment
+    let dispute = instruction::dispute_escrow(&id(), &guardian.pubkey(), &market, &escrow, &emitter, &write_off).unwrap();
+    process(&mut banks_client, &payer, &[dispute], &[&guardian]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &write_off).await, 400);
+    assert_eq!(token_balance(&mut banks_client, &payment).await, 0);
+    assert_eq!(token_balance(&mut banks_client, &fee_vault).await, 0);
+}
+
+#[tokio::test]
+async fn test_claim_gift_with_protocol_fee() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &Pubkey::new_unique(), 0));
+    let fee_vault = find_fee_vault_address(&id(), &market, &mint_acceptable).0;
+    add_packed(&mut program_test, &fee_vault, token_account_state(&mint_acceptable, &authority, 0));
+    // A pending gift whose payment holds the fee until it is claimed
+    let secret = [7; 32];
+    let gift = find_gift_address(&id(), &market, &gift_secret_hash(&secret)).0;
+    let vault = find_gift_vault_address(&id(), &gift).0;
+    let payment = find_gift_payment_address(&id(), &gift).0;
+    add_packed(&mut program_test, &vault, token_account_state(&emitter, &authority, 40));
+    add_packed(&mut program_test, &payment, token_account_state(&mint_acceptable, &authority, 40));
+    let mut data = vec![0; Gift::LEN];
+    Gift {
+        market,
+        write_off,
+        vault,
+        paid: 39,
+        fee: 1,
+        emitted: 40,
+        expiry: i64::MAX,
+        status: GiftStatus::Pending,
+    }.serialize(&mut &mut data[..]).unwrap();
+    program_test.add_account(gift, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let set_fee = instruction::set_protocol_fee(&id(), &owner.pubkey(), &payer.pubkey(), &market, &mint_acceptable, 250).unwrap();
+    process(&mut banks_client, &payer, &[set_fee], &[&owner]).await.unwrap();
+
+    let claim = instruction::claim_gift(&id(), &market, &bank, &recipient, secret).unwrap();
+    let result = process(&mut banks_client, &payer, &[claim.clone()], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::NotEnoughAccountKeys);
+
+    let claim = instruction::with_protocol_fee(&id(), claim, &fee_vault, &owner.pubkey());
+    process(&mut banks_client, &payer, &[claim], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 40);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 39);
+    assert_eq!(token_balance(&mut banks_client, &fee_vault).await, 1);
+    assert_eq!(token_balance(&mut banks_client, &payment).await, 0);
+}
+
+#[tokio::test]
+async fn test_sell_tokens() {
+    let mut program_test = program_test();
+    let (owner, seller) = (Keypair::new(), Keypair::new());
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (holding, destination) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        supply: 30,
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 100));
+    add_packed(&mut program_test, &holding, token_account_state(&emitter, &seller.pubkey(), 30));
+    add_packed(&mut program_test, &destination, token_account_state(&mint_acceptable, &seller.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    // Vested tokens among others were never paid into the bank
+    let sell = instruction::sell_tokens(&id(), &seller.pubkey(), &market, &bank, &holding, &emitter, &destination, EmittedAmount(25)).unwrap();
+    let result = process(&mut banks_client, &payer, &[sell], &[&seller]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::UnbackedSelling as u32));
+
+    let mut program_test = program_test();
+    for key in [bank, emitter, holding, destination].iter() {
+        program_test.add_account(*key, banks_client.get_account(*key).await.unwrap().unwrap());
+    }
+    let mut market_account = banks_client.get_account(market).await.unwrap().unwrap();
+    let token_market = TokenMarket::try_from_slice(&market_account.data).unwrap();
+    TokenMarket { features: TokenMarket::SELLABLE_FEATURES, ..token_market }.serialize(&mut &mut market_account.data[..]).unwrap();
+    program_test.add_account(market, market_account);
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    // Only the holder may burn its tokens
+    let stranger = Keypair::new();
+    let stolen = instruction::sell_tokens(&id(), &stranger.pubkey(), &market, &bank, &holding, &emitter, &destination, EmittedAmount(10)).unwrap();
+    assert!(process(&mut banks_client, &payer, &[stolen], &[&stranger]).await.is_err());
+
+    let sell = instruction::sell_tokens(&id(), &seller.pubkey(), &market, &bank, &holding, &emitter, &destination, EmittedAmount(25)).unwrap();
+    process(&mut banks_client, &payer, &[sell.clone()], &[&seller]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &holding).await, 5);
+    assert_eq!(token_balance(&mut banks_client, &destination).await, 25);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 75);
+    assert_mint_supply(&mut banks_client, &emitter, 5).await;
+
+    let result = process(&mut banks_client, &payer, &[memo::memo("again"), sell], &[&seller]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenError::InsufficientFunds as u32));
+}
+
+#[tokio::test]
+async fn test_withdraw_bank() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (destination, foreign) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 100));
+    add_packed(&mut program_test, &destination, token_account_state(&mint_acceptable, &owner.pubkey(), 0));
+    add_packed(&mut program_test, &foreign, token_account_state(&emitter, &owner.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let stranger = Keypair::new();
+    let stolen = instruction::withdraw_bank(&id(), &stranger.pubkey(), &market, &bank, &destination, AcceptableAmount(10)).unwrap();
+    assert!(process(&mut banks_client, &payer, &[stolen], &[&stranger]).await.is_err());
+
+    let wrong_mint = instruction::withdraw_bank(&id(), &owner.pubkey(), &market, &bank, &foreign, AcceptableAmount(10)).unwrap();
+    let result = process(&mut banks_client, &payer, &[wrong_mint], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::InvalidAccountData);
+
+    let withdraw = instruction::withdraw_bank(&id(), &owner.pubkey(), &market, &bank, &destination, AcceptableAmount(60)).unwrap();
+    process(&mut banks_client, &payer, &[withdraw], &[&owner]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 40);
+    assert_eq!(token_balance(&mut banks_client, &destination).await, 60);
+}
+
+#[tokio::test]
+async fn test_withdraw_stream() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter, destination) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let mut token_market = market_state(&owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    // The stream last released long ago, a day's worth is waiting
+    token_market.withdraw_rate = 50;
+    token_market.last_withdraw = 1;
+    add_market_state(&mut program_test, &market, &token_market);
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 100));
+    add_packed(&mut program_test, &destination, token_account_state(&mint_acceptable, &owner.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let withdraw = |memo: &str, amount| vec![memo::memo(memo), instruction::withdraw_bank(&id(), &owner.pubkey(), &market, &bank, &destination, AcceptableAmount(amount)).unwrap()];
+    let result = process(&mut banks_client, &payer, &withdraw("over", 51), &[&owner]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::WithdrawRateExceeded as u32));
+    process(&mut banks_client, &payer, &withdraw("day", 50), &[&owner]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &destination).await, 50);
+    // and the next one releases by the second again
+    let result = process(&mut banks_client, &payer, &withdraw("again", 1), &[&owner]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::WithdrawRateExceeded as u32));
+
+    // The rate only goes down
+    let set_rate = |rate| instruction::set_withdraw_rate(&id(), &owner.pubkey(), &market, AcceptableAmount(rate)).unwrap();
+    for rate in [51, 0].iter() {
+        let result = process(&mut banks_client, &payer, &[set_rate(*rate)], &[&owner]).await;
+        assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::WithdrawRateLocked as u32));
+    }
+    process(&mut banks_client, &payer, &[set_rate(10)], &[&owner]).await.unwrap();
+    let account = banks_client.get_account(market).await.unwrap().unwrap();
+    assert_eq!(TokenMarket::try_from_slice(&account.data).unwrap().withdraw_rate, 10);
+}
+
+#[tokio::test]
+async fn test_recreate_bank_keeps_live_bank() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+
+    let recreate = instruction::recreate_bank(
+        &id(),
+        &accounts.owner.pubkey(),
+        &payer.pubkey(),
+        &accounts.market.pubkey(),
+        &accounts.bank.pubkey(),
+        &accounts.mint_acceptable.pubkey(),
+    ).unwrap();
+    let result = process(&mut banks_client, &payer, &[recreate], &[&accounts.owner]).await;
+    assert_instruction_error(result, 0, InstructionError::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_recreate_bank() {
+    // The new bank is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let (market, closed_bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &closed_bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &mint_acceptable, Mint {
+        is_initialized: true,
+        ..Mint::default()
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let not_owner = instruction::recreate_bank(&id(), &payer.pubkey(), &payer.pubkey(), &market, &closed_bank, &mint_acceptable).unwrap();
+    assert!(process(&mut banks_client, &payer, &[not_owner], &[]).await.is_err());
+    let recreate = instruction::recreate_bank(&id(), &owner.pubkey(), &payer.pubkey(), &market, &closed_bank, &mint_acceptable).unwrap();
+    process(&mut banks_client, &payer, &[recreate], &[&owner]).await.unwrap();
+
+    let bank = find_bank_address(&id(), &market).0;
+    let market = banks_client.get_account(market).await.unwrap().unwrap();
+    assert_eq!(TokenMarket::try_from_slice(&market.data).unwrap().bank, bank);
+    let bank = token_account(&mut banks_client, &bank).await;
+    assert_eq!((bank.mint, bank.owner, bank.amount), (mint_acceptable, find_authority_address(&id()).0, 0));
+}
+
+#[tokio::test]
+async fn test_crank_vesting() {
+    let mut program_test = program_test();
+    let authority = find_authority_address(&id()).0;
+    let (market, emitter) = (Pubkey::new_unique(), Pubkey::new_unique());
+    // Fully vested, not vested before the far future, and vested to a foreign account
+    let starts = [0, i64::MAX / 2, 0];
+    let mut claims = vec![];
+    for start in starts.iter() {
+        let beneficiary = Pubkey::new_unique();
+        let (vesting, vault) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let data = VestingVault {
+            is_initialized: true,
+            market,
+            beneficiary,
+            vault,
+            total: 100,
+            released: 0,
+            start: *start,
+            cliff: 0,
+            duration: 10,
+            revocable: false,
+            revoked: false,
+        }.try_to_vec().unwrap();
+        program_test.add_account(vesting, solana_sdk::account::Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: id(),
+            executable: false,
+            rent_epoch: 0,
+        });
+        add_packed(&mut program_test, &vault, token_account_state(&emitter, &authority, 100));
+        let destination = find_associated_token_address(&beneficiary, &emitter);
+        add_packed(&mut program_test, &destination, token_account_state(&emitter, &beneficiary, 0));
+        claims.push(token_market::accounts::VestingClaimAccounts { vesting, vault, destination });
+    }
+    let foreign = Pubkey::new_unique();
+    add_packed(&mut program_test, &foreign, token_account_state(&emitter, &Pubkey::new_unique(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let mut stolen = claims[2].clone();
+    stolen.destination = foreign;
+    let crank = instruction::crank_vesting(&id(), &[claims[0].clone(), stolen]).unwrap();
+    let result = process(&mut banks_client, &payer, &[crank], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::InvalidAccountData);
+
+    let crank = instruction::crank_vesting(&id(), &claims).unwrap();
+    process(&mut banks_client, &payer, &[crank], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &claims[0].destination).await, 100);
+    assert_eq!(token_balance(&mut banks_client, &claims[1].destination).await, 0);
+    assert_eq!(token_balance(&mut banks_client, &claims[2].destination).await, 100);
+    let vesting = banks_client.get_account(claims[0].vesting).await.unwrap().unwrap();
+    assert_eq!(VestingVault::try_from_slice(&vesting.data).unwrap().released, 100);
+
+    // Claimed vestings are skipped on the next round
+    let crank = instruction::crank_vesting(&id(), &claims).unwrap();
+    process(&mut banks_client, &payer, &[memo::memo("again"), crank], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &claims[0].destination).await, 100);
+}
+
+#[tokio::test]
+async fn test_sale_phases() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    // The phases account is allocated in CPI, which needs the BPF build
+    let phases = find_sale_phases_address(&id(), &market).0;
+    program_test.add_account(phases, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(SalePhases::LEN),
+        data: vec![0; SalePhases::LEN],
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let presale = SalePhase {
+        start: 0,
+        price_numerator: 2,
+        price_denominator: 1,
+        cap: 150,
+        max_purchase: 100,
+        allowlist_only: false,
+    };
+    let public = SalePhase {
+        start: i64::MAX / 2,
+        price_numerator: 1,
+        price_denominator: 1,
+        allowlist_only: true,
+        ..SalePhase::default()
+    };
+    let unordered = instruction::set_sale_phases(&id(), &owner.pubkey(), &payer.pubkey(), &market, vec![public, presale], 0).unwrap();
+    let result = process(&mut banks_client, &payer, &[unordered], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidSalePhases as u32));
+    let set = instruction::set_sale_phases(&id(), &owner.pubkey(), &payer.pubkey(), &market, vec![presale, public], 0).unwrap();
+    process(&mut banks_client, &payer, &[set], &[&owner]).await.unwrap();
+
+    let buy = |text: &str, amount: u64| vec![
+        memo::memo(text),
+        spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap(),
+        instruction::with_sale_phases(&id(), instruction::buy_tokens(
+            &id(),
+            &market,
+            &bank,
+            &emitter,
+            &recipient,
+            &write_off,
+            &spl_token::id(),
+            AcceptableAmount(amount),
+        ).unwrap(), &market),
+    ];
+    let phase_error = |error: TokenMarketError| InstructionError::Custom(error as u32);
+
+    // The presale sells at its price up to its limits
+    process(&mut banks_client, &payer, &buy("first", 40), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 80);
+    let result = process(&mut banks_client, &payer, &buy("too large", 60), &[&buyer]).await;
+    assert_instruction_error(result, 2, phase_error(TokenMarketError::PhaseLimitExceeded));
+    let result = process(&mut banks_client, &payer, &buy("over the cap", 40), &[&buyer]).await;
+    assert_instruction_error(result, 2, phase_error(TokenMarketError::PhaseLimitExceeded));
+    process(&mut banks_client, &payer, &buy("last", 35), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 150);
+
+    let mut without_phases = buy("without phases", 10);
+    without_phases[2].accounts.pop();
+    let result = process(&mut banks_client, &payer, &without_phases, &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::NotEnoughAccountKeys);
+
+    // The owner opens the allowlisted public sale ahead of its start, then closes the sale
+    let advance = instruction::advance_sale_phase(&id(), &owner.pubkey(), &market).unwrap();
+    process(&mut banks_client, &payer, &[advance.clone()], &[&owner]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &buy("public", 10), &[&buyer]).await;
+    assert_instruction_error(result, 2, phase_error(TokenMarketError::NotAllowlisted));
+
+    process(&mut banks_client, &payer, &[memo::memo("close"), advance.clone()], &[&owner]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &buy("closed", 10), &[&buyer]).await;
+    assert_instruction_error(result, 2, phase_error(TokenMarketError::SaleNotOpen));
+    let result = process(&mut banks_client, &payer, &[memo::memo("again"), advance], &[&owner]).await;
+    assert_instruction_error(result, 1, phase_error(TokenMarketError::SaleNotOpen));
+
+    let phases = banks_client.get_account(phases).await.unwrap().unwrap();
+    let phases = SalePhases::load(&phases.data).unwrap();
+    assert_eq!(phases.sold, vec![150, 0]);
+    assert_eq!(phases.purchases, vec![2, 0]);
+    assert_eq!(phases.paid, vec![75, 0]);
+    let market = banks_client.get_account(market).await.unwrap().unwrap();
+    assert!(TokenMarket::try_from_slice(&market.data).unwrap().phased);
+}
+
+#[tokio::test]
+async fn test_protocol_fee() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient, destination) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    add_packed(&mut program_test, &destination, token_account_state(&mint_acceptable, &owner.pubkey(), 0));
+    // The fee vault is allocated in CPI, which needs the BPF build
+    let fee_vault = find_fee_vault_address(&id(), &market, &mint_acceptable).0;
+    add_packed(&mut program_test, &fee_vault, token_account_state(&mint_acceptable, &authority, 0));
+    let tenant = find_tenant_address(&id(), &owner.pubkey()).0;
+    let stats = TenantStats {
+        owner: owner.pubkey(),
+        markets_created: 1,
+        protocol_fees: 0,
+    };
+    program_test.add_account(tenant, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(TenantStats::LEN),
+        data: stats.try_to_vec().unwrap(),
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let set_fee = |fee_bps| instruction::set_protocol_fee(&id(), &owner.pubkey(), &payer.pubkey(), &market, &mint_acceptable, fee_bps).unwrap();
+    let result = process(&mut banks_client, &payer, &[set_fee(10_000)], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidProtocolFee as u32));
+    process(&mut banks_client, &payer, &[set_fee(250)], &[&owner]).await.unwrap();
+
+    let buy = |text: &str, amount: u64| vec![
+        memo::memo(text),
+        spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap(),
+        instruction::with_protocol_fee(&id(), instruction::buy_tokens(
+            &id(),
+            &market,
+            &bank,
+            &emitter,
+            &recipient,
+            &write_off,
+            &spl_token::id(),
+            AcceptableAmount(amount),
+        ).unwrap(), &fee_vault, &owner.pubkey()),
+    ];
+
+    // The buyer pays the same, the bank keeps the payment less the fee
+    process(&mut banks_client, &payer, &buy("first", 400), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 400);
+    assert_eq!(token_balance(&mut banks_client, &write_off).await, 600);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 390);
+    assert_eq!(token_balance(&mut banks_client, &fee_vault).await, 10);
+    let stats = banks_client.get_account(tenant).await.unwrap().unwrap();
+    assert_eq!(TenantStats::try_from_slice(&stats.data).unwrap().protocol_fees, 10);
+
+    let mut without_vault = buy("without vault", 100);
+    without_vault[2].accounts.truncate(without_vault[2].accounts.len() - 2);
+    let result = process(&mut banks_client, &payer, &without_vault, &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::NotEnoughAccountKeys);
+
+    let stranger = Keypair::new();
+    let stolen = instruction::withdraw_fees(&id(), &stranger.pubkey(), &market, &mint_acceptable, &destination, AcceptableAmount(10)).unwrap();
+    assert!(process(&mut banks_client, &payer, &[stolen], &[&stranger]).await.is_err());
+    let withdraw = instruction::withdraw_fees(&id(), &owner.pubkey(), &market, &mint_acceptable, &destination, AcceptableAmount(10)).unwrap();
+    process(&mut banks_client, &payer, &[withdraw], &[&owner]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &fee_vault).await, 0);
+    assert_eq!(token_balance(&mut banks_client, &destination).await, 10);
+
+    let market = banks_client.get_account(market).await.unwrap().unwrap();
+    let market = TokenMarket::try_from_slice(&market.data).unwrap();
+    assert_eq!((market.fee_bps, market.fee_vault), (250, fee_vault));
+}
+
+#[tokio::test]
+async fn test_buy_tokens_with_sol() {
+    // The wrapped SOL account is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let native = spl_token::native_mint::id();
+    let (market, bank, emitter, recipient) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &native, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    // A wrapped SOL account holds its rent reserve on top of its balance
+    add_packed(&mut program_test, &bank, Account {
+        is_native: COption::Some(Rent::default().minimum_balance(Account::LEN)),
+        ..token_account_state(&native, &authority, 0)
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &owner.pubkey(), 0));
+    let (other_market, other_bank) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let other_acceptable = Pubkey::new_unique();
+    add_market(&mut program_test, &other_market, &owner.pubkey(), &other_bank, &other_acceptable, &emitter);
+    add_packed(&mut program_test, &other_bank, token_account_state(&other_acceptable, &authority, 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let before = banks_client.get_balance(payer.pubkey()).await.unwrap();
+    let buy = instruction::buy_tokens_with_sol(&id(), &payer.pubkey(), &market, &bank, &emitter, &recipient, AcceptableAmount(5000)).unwrap();
+    process(&mut banks_client, &payer, &[buy], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 5000);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 5000);
+    // The transaction fee aside, the buyer paid the lamports and got the rent of the wrapped SOL account back
+    let after = banks_client.get_balance(payer.pubkey()).await.unwrap();
+    assert!(after <= before - 5000 && after > before - 5000 - Rent::default().minimum_balance(Account::LEN));
+    let wrap = find_wrap_address(&id(), &payer.pubkey()).0;
+    assert!(banks_client.get_account(wrap).await.unwrap().is_none());
+
+    // Lamports sent to the wrapped SOL address beforehand must not block purchases
+    let prefund = solana_sdk::system_instruction::transfer(&payer.pubkey(), &wrap, 1);
+    process(&mut banks_client, &payer, &[prefund], &[]).await.unwrap();
+    let buy = instruction::buy_tokens_with_sol(&id(), &payer.pubkey(), &market, &bank, &emitter, &recipient, AcceptableAmount(100)).unwrap();
+    process(&mut banks_client, &payer, &[buy], &[]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 5100);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 5100);
+
+    let buy = instruction::buy_tokens_with_sol(&id(), &payer.pubkey(), &other_market, &other_bank, &emitter, &recipient, AcceptableAmount(5000)).unwrap();
+    let result = process(&mut banks_client, &payer, &[buy], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::NotNativeMint as u32));
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 5100);
+}
+
+#[tokio::test]
+async fn test_buy_tokens_multi() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, first, second) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &first, token_account_state(&emitter, &Pubkey::new_unique(), 0));
+    add_packed(&mut program_test, &second, token_account_state(&emitter, &Pubkey::new_unique(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let buy = |delegated: u64| {
+        let buy = instruction::buy_tokens_multi(&id(), &market, &bank, &emitter, &write_off, &[
+            (first, AcceptableAmount(100)),
+            (second, AcceptableAmount(250)),
+        ]).unwrap();
+        instruction::bundle_buy_tokens(&id(), buy, &write_off, &buyer.pubkey(), AcceptableAmount(delegated)).unwrap()
+    };
+
+    // A delegation beyond the sum of the amounts is left unspent
+    let result = process(&mut banks_client, &payer, &buy(400), &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::AllowanceNotSpent as u32));
+
+    process(&mut banks_client, &payer, &buy(350), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &first).await, 100);
+    assert_eq!(token_balance(&mut banks_client, &second).await, 250);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 350);
+    assert_eq!(token_balance(&mut banks_client, &write_off).await, 650);
+    assert_mint_supply(&mut banks_client, &emitter, 350).await;
+
+    let mut missing = buy(350);
+    missing[1].accounts.pop();
+    let result = process(&mut banks_client, &payer, &missing, &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::NotEnoughAccountKeys);
+}
+
+#[tokio::test]
+async fn test_purchase_limits() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let set_limits = |min, max| instruction::set_purchase_limits(&id(), &owner.pubkey(), &market, AcceptableAmount(min), AcceptableAmount(max)).unwrap();
+    let result = process(&mut banks_client, &payer, &[set_limits(200, 100)], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidPurchaseLimits as u32));
+    let not_owner = instruction::set_purchase_limits(&id(), &payer.pubkey(), &market, AcceptableAmount(0), AcceptableAmount(0)).unwrap();
+    let result = process(&mut banks_client, &payer, &[not_owner], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::InvalidAccountData);
+    process(&mut banks_client, &payer, &[set_limits(10, 100)], &[&owner]).await.unwrap();
+
+    let buy = |text: &str, amount: u64| vec![
+        memo::memo(text),
+        spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap(),
+        instruction::buy_tokens(
+            &id(),
+            &market,
+            &bank,
+            &emitter,
+            &recipient,
+            &write_off,
+            &spl_token::id(),
+            AcceptableAmount(amount),
+        ).unwrap(),
+    ];
+    let result = process(&mut banks_client, &payer, &buy("too small", 9), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::PurchaseBelowMinimum as u32));
+    let result = process(&mut banks_client, &payer, &buy("too large", 101), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::PurchaseAboveMaximum as u32));
+
+    // The limits are inclusive
+    process(&mut banks_client, &payer, &buy("smallest", 10), &[&buyer]).await.unwrap();
+    process(&mut banks_client, &payer, &buy("largest", 100), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 110);
+
+    // No upper limit once the maximum is 0
+    process(&mut banks_client, &payer, &[set_limits(10, 0)], &[&owner]).await.unwrap();
+    process(&mut banks_client, &payer, &buy("unlimited", 500), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 610);
+}
+
+#[tokio::test]
+async fn test_purchase_leaves_no_allowance() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+}
+
+#[tokio::test]
+async fn test_purchase_limits_of_escrowed_gift_and_external_purchases() {
+    let mut program_test = program_test();
+    let (owner, onramp) = (Keypair::new(), Keypair::new());
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient, buyer) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+
+    // Without the trailing revoke of the bundle the purchase still spends all of the delegation
+    process(&mut banks_client, &payer, &[approve(30), buy(30)], &[&buyer]).await.unwrap();
+    assert!(token_account(&mut banks_client, &write_off).await.delegate.is_none());
+
+    // and so does one refunding an oversized delegation
+    let refund = instruction::set_overpayment_policy(&id(), &owner.pubkey(), &market, OverpaymentPolicy::Refund).unwrap();
+    process(&mut banks_client, &payer, &[refund], &[&owner]).await.unwrap();
+    process(&mut banks_client, &payer, &[approve(500), buy(20)], &[&buyer]).await.unwrap();
+    let account = token_account(&mut banks_client, &write_off).await;
+    assert_eq!((account.amount, account.delegate, account.delegated_amount), (950, COption::None, 0));
+
+    // A tip back to the write-off account is not booked against the delegation
+    let self_tip = instruction::buy_tokens_with_tip(
+        &id(),
+        &market,
+        &bank,
+        &emitter,
+        &recipient,
+        &write_off,
+        &write_off,
+        AcceptableAmount(10),
+        AcceptableAmount(5),
+    ).unwrap();
+    let result = process(&mut banks_client, &payer, &[approve(15), self_tip], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::AllowanceNotSpent as u32));
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 50);
+}
+
+#[tokio::test]
+    add_packed(&mut program_test, &write_off, Account {
+        delegate: COption::Some(authority),
+        delegated_amount: 1000,
+        ..token_account_state(&mint_acceptable, &buyer, 1000)
+    });
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer, 0));
+    let (below, above) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let below_vaa = add_posted_vaa(&mut program_test, &below, &wormhole::id(), [4; 32], 1, &PurchasePayload { market, recipient: buyer, amount: 9 });
+    let above_vaa = add_posted_vaa(&mut program_test, &above, &wormhole::id(), [4; 32], 2, &PurchasePayload { market, recipient: buyer, amount: 101 });
+    let (mut banks_client, payer, _) = program_test.start().await;
+    let setup = [
+        instruction::set_purchase_limits(&id(), &owner.pubkey(), &market, AcceptableAmount(10), AcceptableAmount(100)).unwrap(),
+        instruction::set_onramp_signer(&id(), &owner.pubkey(), &market, &onramp.pubkey()).unwrap(),
+        instruction::set_wormhole_emitter(&id(), &owner.pubkey(), &market, 2, [4; 32]).unwrap(),
+    ];
+    process(&mut banks_client, &payer, &setup, &[&owner]).await.unwrap();
+
+    for (amount, error, posted_vaa, vaa) in [(9, TokenMarketError::PurchaseBelowMinimum, below, below_vaa), (101, TokenMarketError::PurchaseAboveMaximum, above, above_vaa)] {
+        let escrowed = instruction::buy_tokens_escrowed(&id(), &market, &bank, &emitter, &mint_acceptable, &recipient, &write_off, &payer.pubkey(), AcceptableAmount(amount), 0).unwrap();
+        let result = process(&mut banks_client, &payer, &[escrowed], &[]).await;
+        assert_instruction_error(result, 0, InstructionError::Custom(error.clone() as u32));
+
+        let gift = instruction::buy_gift(&id(), &market, &bank, &emitter, &mint_acceptable, &write_off, &payer.pubkey(), AcceptableAmount(amount), gift_secret_hash(&[7; 32]), i64::MAX).unwrap();
+        let result = process(&mut banks_client, &payer, &[gift], &[]).await;
+        assert_instruction_error(result, 0, InstructionError::Custom(error.clone() as u32));
+
+        let fiat = instruction::buy_tokens_with_fiat(&id(), &onramp.pubkey(), &market, &emitter, &recipient, &payer.pubkey(), AcceptableAmount(amount), [7; 32]).unwrap();
+        let result = process(&mut banks_client, &payer, &[fiat], &[&onramp]).await;
+        assert_instruction_error(result, 0, InstructionError::Custom(error.clone() as u32));
+
+        let redeem = instruction::redeem_wormhole_purchase(&id(), &market, &emitter, &recipient, &posted_vaa, &vaa, &payer.pubkey()).unwrap();
+        let result = process(&mut banks_client, &payer, &[redeem], &[]).await;
+        assert_instruction_error(result, 0, InstructionError::Custom(error as u32));
+    }
+    assert_mint_supply(&mut banks_client, &emitter, 0).await;
+}
+
+#[tokio::test]
+async fn test_wallet_cap() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    // The buyer record is allocated in CPI, which needs the BPF build
+    let address = find_buyer_record_address(&id(), &market, &buyer.pubkey()).0;
+    let record = BuyerRecord {
+        market,
+        buyer: buyer.pubkey(),
+        purchased: 0,
+        spent: 0,
+        last_purchase: 0,
+    };
+    program_test.add_account(address, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(BuyerRecord::LEN),
+        data: record.try_to_vec().unwrap(),
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let set_cap = instruction::set_wallet_cap(&id(), &owner.pubkey(), &market, EmittedAmount(100)).unwrap();
+    process(&mut banks_client, &payer, &[set_cap], &[&owner]).await.unwrap();
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy_tokens = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+    let buy = |text: &str, amount: u64| vec![
+        memo::memo(text),
+        approve(amount),
+        instruction::with_buyer_record(&id(), buy_tokens(amount), &market, &buyer.pubkey(), &payer.pubkey()),
+    ];
+
+    // Capped markets can't be bought from without the record
+    let result = process(&mut banks_client, &payer, &[approve(10), buy_tokens(10)], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::NotEnoughAccountKeys);
+
+    // The record counts every purchase
+    process(&mut banks_client, &payer, &buy("first", 60), &[&buyer]).await.unwrap();
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    assert_eq!(account.owner, id());
+    let record = BuyerRecord::try_from_slice(&account.data).unwrap();
+    assert_eq!((record.market, record.buyer, record.purchased), (market, buyer.pubkey(), 60));
+
+    let result = process(&mut banks_client, &payer, &buy("over", 41), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::WalletCapExceeded as u32));
+    process(&mut banks_client, &payer, &buy("rest", 40), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 100);
+
+    // Purchases the record can't count are refused on capped markets
+    let escrowed = instruction::buy_tokens_escrowed(&id(), &market, &bank, &emitter, &mint_acceptable, &recipient, &write_off, &payer.pubkey(), AcceptableAmount(1), 0).unwrap();
+    let result = process(&mut banks_client, &payer, &[approve(1), escrowed], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::WalletCapped as u32));
+}
+
+#[tokio::test]
+async fn test_purchase_cooldown() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    // The buyer record is allocated in CPI, which needs the BPF build
+    let address = find_buyer_record_address(&id(), &market, &buyer.pubkey()).0;
+    let record = BuyerRecord {
+        market,
+        buyer: buyer.pubkey(),
+        purchased: 0,
+        spent: 0,
+        last_purchase: 0,
+    };
+    program_test.add_account(address, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(BuyerRecord::LEN),
+        data: record.try_to_vec().unwrap(),
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let set_cooldown = |secs| instruction::set_purchase_cooldown(&id(), &owner.pubkey(), &market, secs).unwrap();
+    let result = process(&mut banks_client, &payer, &[set_cooldown(-1)], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidPurchaseCooldown as u32));
+    process(&mut banks_client, &payer, &[set_cooldown(3600)], &[&owner]).await.unwrap();
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy_tokens = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+    let buy = |text: &str, amount: u64| vec![
+        memo::memo(text),
+        approve(amount),
+        instruction::with_buyer_record(&id(), buy_tokens(amount), &market, &buyer.pubkey(), &payer.pubkey()),
+    ];
+
+    // The record stamps the purchase, the next one waits out the cooldown
+    process(&mut banks_client, &payer, &buy("first", 10), &[&buyer]).await.unwrap();
+    let record = BuyerRecord::try_from_slice(&banks_client.get_account(address).await.unwrap().unwrap().data).unwrap();
+    assert!(record.last_purchase > 0);
+    let result = process(&mut banks_client, &payer, &buy("again", 10), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::PurchaseCooldown as u32));
+
+    // Without the cooldown the buyer is no longer tracked
+    process(&mut banks_client, &payer, &[set_cooldown(0)], &[&owner]).await.unwrap();
+    process(&mut banks_client, &payer, &[memo::memo("free"), approve(10), buy_tokens(10)], &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 20);
+}
+
+#[tokio::test]
+async fn test_loyalty_tiers() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    // The buyer record is allocated in CPI, which needs the BPF build
+    let address = find_buyer_record_address(&id(), &market, &buyer.pubkey()).0;
+    let record = BuyerRecord {
+        market,
+        buyer: buyer.pubkey(),
+        purchased: 999,
+        spent: 999,
+        last_purchase: 0,
+    };
+    program_test.add_account(address, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(BuyerRecord::LEN),
+        data: record.try_to_vec().unwrap(),
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let set_tiers = |tiers: &[LoyaltyTier]| instruction::set_loyalty_tiers(&id(), &owner.pubkey(), &market, tiers).unwrap();
+    let tier = |threshold, discount_bps| LoyaltyTier { threshold, discount_bps };
+    let result = process(&mut banks_client, &payer, &[set_tiers(&[tier(1000, 200), tier(1000, 500)])], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidLoyaltyTiers as u32));
+    process(&mut banks_client, &payer, &[set_tiers(&[tier(1000, 200)])], &[&owner]).await.unwrap();
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy_tokens = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+    let buy = |text: &str, amount: u64| vec![
+        memo::memo(text),
+        approve(amount),
+        instruction::with_buyer_record(&id(), buy_tokens(amount), &market, &buyer.pubkey(), &payer.pubkey()),
+    ];
+
+    // The record counts the purchases the discount is earned by
+    let result = process(&mut banks_client, &payer, &[approve(49), buy_tokens(49)], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::NotEnoughAccountKeys);
+
+    // 999 bought before is just short of the tier
+    process(&mut banks_client, &payer, &buy("short", 49), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 49);
+
+    // 2% off, 49 emit 49 * 10000 / 9800
+    process(&mut banks_client, &payer, &buy("loyal", 49), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 99);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 98);
+    let record = BuyerRecord::try_from_slice(&banks_client.get_account(address).await.unwrap().unwrap().data).unwrap();
+    assert_eq!((record.purchased, record.spent), (1098, 1097));
+}
+
+#[tokio::test]
+async fn test_stake_weighted_wallet_cap() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (stake_mint, pool_tokens, governance, deposit) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    add_packed(&mut program_test, &pool_tokens, token_account_state(&stake_mint, &buyer.pubkey(), 50));
+    let record = stake::TokenOwnerRecord {
+        account_type: 17,
+        realm: Pubkey::new_unique(),
+        governing_token_mint: stake_mint,
+        governing_token_owner: buyer.pubkey(),
+        governing_token_deposit_amount: 200,
+    };
+    program_test.add_account(deposit, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(stake::TokenOwnerRecord::LEN),
+        data: record.pack(),
+        owner: governance,
+        executable: false,
+        rent_epoch: 0,
+    });
+    // The buyer record is allocated in CPI, which needs the BPF build
+    let address = find_buyer_record_address(&id(), &market, &buyer.pubkey()).0;
+    let record = BuyerRecord {
+        market,
+        buyer: buyer.pubkey(),
+        purchased: 0,
+        spent: 0,
+        last_purchase: 0,
+    };
+    program_test.add_account(address, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(BuyerRecord::LEN),
+        data: record.try_to_vec().unwrap(),
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let set_cap = instruction::set_wallet_cap(&id(), &owner.pubkey(), &market, EmittedAmount(100)).unwrap();
+    let set_weight = |source, governance_program| instruction::set_stake_weight(&id(), &owner.pubkey(), &market, StakeWeight { source, mint: stake_mint, governance_program, bonus_bps: 5_000 }).unwrap();
+    process(&mut banks_client, &payer, &[set_cap, set_weight(StakeSource::PoolTokens, Pubkey::default())], &[&owner]).await.unwrap();
+
+    let buy = |text: &str, amount: u64, stake: Option<&Pubkey>| {
+        let buy_tokens = instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+        let mut buy_tokens = instruction::with_buyer_record(&id(), buy_tokens, &market, &buyer.pubkey(), &payer.pubkey());
+        if let Some(stake) = stake {
+            buy_tokens = instruction::with_stake(buy_tokens, stake);
+        }
+        vec![
+            memo::memo(text),
+            spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap(),
+            buy_tokens,
+        ]
+    };
+
+    // Half of the 50 pool tokens raise the cap of 100 to 125
+    let result = process(&mut banks_client, &payer, &buy("unstaked", 110, None), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::WalletCapExceeded as u32));
+    let result = process(&mut banks_client, &payer, &buy("pool over", 126, Some(&pool_tokens)), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::WalletCapExceeded as u32));
+    process(&mut banks_client, &payer, &buy("pool", 110, Some(&pool_tokens)), &[&buyer]).await.unwrap();
+
+    // Pool tokens don't count for governance deposits, the 200 deposited raise it to 200
+    process(&mut banks_client, &payer, &[set_weight(StakeSource::GovernanceDeposit, governance)], &[&owner]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &buy("wrong stake", 10, Some(&pool_tokens)), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::WalletCapExceeded as u32));
+    process(&mut banks_client, &payer, &buy("deposit", 90, Some(&deposit)), &[&buyer]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &buy("deposit over", 1, Some(&deposit)), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::WalletCapExceeded as u32));
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 200);
+
+    // Deposits are only read from the governance program named
+    let result = process(&mut banks_client, &payer, &[set_weight(StakeSource::GovernanceDeposit, Pubkey::default())], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidStakeWeight as u32));
+}
+
+#[tokio::test]
+async fn test_close_records() {
+    let mut program_test = program_test();
+    let owner = Pubkey::new_unique();
+    let buyer = Pubkey::new_unique();
+    let (ended, unlocked) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut token_market = market_state(&owner, &Pubkey::new_unique(), &Pubkey::new_unique(), &Pubkey::new_unique());
+    token_market.wallet_cap = 100;
+    token_market.sale_start = 1;
+    token_market.sale_end = 2;
+    add_market_state(&mut program_test, &ended, &token_market);
+    // without a start the window could still be moved, so the sale may go on
+    token_market.sale_start = 0;
+    add_market_state(&mut program_test, &unlocked, &token_market);
+    let mut add_program_account = |address: Pubkey, data: Vec<u8>| {
+        program_test.add_account(address, solana_sdk::account::Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: id(),
+            executable: false,
+            rent_epoch: 0,
+        });
+    };
+    for market in &[ended, unlocked] {
+        let record = BuyerRecord { market: *market, buyer, purchased: 60, spent: 60, last_purchase: 0 };
+        add_program_account(find_buyer_record_address(&id(), market, &buyer).0, record.try_to_vec().unwrap());
+    }
+    let receipt_mint = Pubkey::new_unique();
+    let receipt = PurchaseReceipt { market: ended, buyer, amount: 60, slot: 1, campaign: [0; 8] };
+    let receipt_address = find_receipt_address(&id(), &receipt_mint).0;
+    add_program_account(receipt_address, receipt.try_to_vec().unwrap());
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let result = process(&mut banks_client, &payer, &[instruction::close_buyer_record(&id(), &unlocked, &buyer)], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::RecordStillNeeded as u32));
+
+    // the rent goes back to the buyer, whoever closes the record
+    process(&mut banks_client, &payer, &[instruction::close_buyer_record(&id(), &ended, &buyer)], &[]).await.unwrap();
+    let record = find_buyer_record_address(&id(), &ended, &buyer).0;
+    assert!(banks_client.get_account(record).await.unwrap().is_none());
+    let rent = Rent::default().minimum_balance(BuyerRecord::LEN);
+    assert_eq!(banks_client.get_balance(buyer).await.unwrap(), rent);
+
+    let mut misdirected = instruction::close_receipt(&id(), &ended, &receipt_mint, &buyer);
+    misdirected.accounts[3].pubkey = payer.pubkey();
+    let result = process(&mut banks_client, &payer, &[misdirected], &[]).await;
+    assert_instruction_error(result, 0, InstructionError::InvalidAccountData);
+    process(&mut banks_client, &payer, &[instruction::close_receipt(&id(), &ended, &receipt_mint, &buyer)], &[]).await.unwrap();
+    assert!(banks_client.get_account(receipt_address).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_get_version() {
+    let (mut banks_client, payer, _) = program_test().start().await;
+    // Takes no accounts and changes nothing, clients only simulate it
+    process(&mut banks_client, &payer, &[instruction::get_version(&id())], &[]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sale_window() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let set_window = |start, end| instruction::set_sale_window(&id(), &owner.pubkey(), &market, start, end).unwrap();
+    let buy = |text: &str, amount: u64| vec![
+        memo::memo(text),
+        spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap(),
+        instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap(),
+    ];
+
+    let result = process(&mut banks_client, &payer, &[set_window(200, 100)], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::InvalidSaleWindow as u32));
+
+    process(&mut banks_client, &payer, &[set_window(i64::MAX / 2, 0)], &[&owner]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &buy("early", 10), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::SaleNotStarted as u32));
+
+    // Until it starts the sale can be moved, here into the past
+    process(&mut banks_client, &payer, &[set_window(0, 1)], &[&owner]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &buy("late", 10), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::SaleEnded as u32));
+
+    process(&mut banks_client, &payer, &[set_window(1, 0)], &[&owner]).await.unwrap();
+    process(&mut banks_client, &payer, &buy("open", 10), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 10);
+
+    // and then it stays as scheduled
+    let result = process(&mut banks_client, &payer, &[set_window(0, 0)], &[&owner]).await;
+    assert_instruction_error(result, 0, InstructionError::Custom(TokenMarketError::SaleWindowLocked as u32));
+}
+
+#[tokio::test]
+async fn test_allowlist() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    // The entry is allocated in CPI, which needs the BPF build
+    let address = find_allowlist_address(&id(), &market, &buyer.pubkey()).0;
+    let entry = AllowlistEntry {
+        market,
+        buyer: buyer.pubkey(),
+    };
+    program_test.add_account(address, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(AllowlistEntry::LEN),
+        data: entry.try_to_vec().unwrap(),
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let enable = instruction::set_allowlist(&id(), &owner.pubkey(), &market, true).unwrap();
+    process(&mut banks_client, &payer, &[enable], &[&owner]).await.unwrap();
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy_tokens = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+    let buy = |text: &str, amount: u64| vec![
+        memo::memo(text),
+        approve(amount),
+        instruction::with_allowlist_entry(&id(), buy_tokens(amount), &market, &buyer.pubkey()),
+    ];
+
+    let result = process(&mut banks_client, &payer, &[approve(10), buy_tokens(10)], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::NotOnAllowlist as u32));
+    process(&mut banks_client, &payer, &buy("listed", 10), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 10);
+
+    // Purchases that can't show the entry are refused
+    let escrowed = instruction::buy_tokens_escrowed(&id(), &market, &bank, &emitter, &mint_acceptable, &recipient, &write_off, &payer.pubkey(), AcceptableAmount(1), 0).unwrap();
+    let result = process(&mut banks_client, &payer, &[approve(1), escrowed], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::AllowlistedMarket as u32));
+
+    // Removing the entry returns its rent and takes the buyer off the list
+    let receiver = Pubkey::new_unique();
+    let remove = instruction::remove_from_allowlist(&id(), &owner.pubkey(), &market, &buyer.pubkey(), &receiver).unwrap();
+    process(&mut banks_client, &payer, &[remove], &[&owner]).await.unwrap();
+    assert!(banks_client.get_account(address).await.unwrap().is_none());
+    assert_eq!(
+        banks_client.get_balance(receiver).await.unwrap(),
+        Rent::default().minimum_balance(AllowlistEntry::LEN)
+    );
+    let result = process(&mut banks_client, &payer, &buy("removed", 10), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::NotOnAllowlist as u32));
+
+    // Disabled, the market sells to anyone again
+    let disable = instruction::set_allowlist(&id(), &owner.pubkey(), &market, false).unwrap();
+    process(&mut banks_client, &payer, &[disable], &[&owner]).await.unwrap();
+    process(&mut banks_client, &payer, &[memo::memo("open"), approve(10), buy_tokens(10)], &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 20);
+}
+
+#[tokio::test]
+async fn test_add_to_allowlist() {
+    // The entry is allocated in CPI, which needs the BPF build
+    if std::env::var("BPF_OUT_DIR").is_err() {
+        return;
+    }
+    let (mut banks_client, payer, _) = program_test().start().await;
+    let accounts = create_market(&mut banks_client, &payer).await;
+    let buyer = Pubkey::new_unique();
+
+    let add = instruction::add_to_allowlist(&id(), &accounts.owner.pubkey(), &payer.pubkey(), &accounts.market.pubkey(), &buyer).unwrap();
+    process(&mut banks_client, &payer, &[add], &[&accounts.owner]).await.unwrap();
+
+    let address = find_allowlist_address(&id(), &accounts.market.pubkey(), &buyer).0;
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    assert_eq!(account.owner, id());
+    let entry = AllowlistEntry::try_from_slice(&account.data).unwrap();
+    assert_eq!((entry.market, entry.buyer), (accounts.market.pubkey(), buyer));
+}
+
+#[tokio::test]
+async fn test_allowlist_root() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    add_market(&mut program_test, &market, &owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let buyers = vec![Pubkey::new_unique(), buyer.pubkey(), Pubkey::new_unique()];
+    let tree = merkle::MerkleTree::new(&buyers);
+    let setup = vec![
+        instruction::set_allowlist(&id(), &owner.pubkey(), &market, true).unwrap(),
+        instruction::set_allowlist_root(&id(), &owner.pubkey(), &market, tree.root()).unwrap(),
+    ];
+    process(&mut banks_client, &payer, &setup, &[&owner]).await.unwrap();
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy_tokens = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+    let buy = |text: &str, proof: &[[u8; 32]]| vec![
+        memo::memo(text),
+        approve(10),
+        instruction::with_allowlist_proof(buy_tokens(10), proof).unwrap(),
+    ];
+
+    // The proof of another buyer doesn't lead to the root from this one
+    let result = process(&mut banks_client, &payer, &buy("wrong", &tree.proof(0).unwrap()), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::InvalidAllowlistProof as u32));
+    let result = process(&mut banks_client, &payer, &[approve(10), buy_tokens(10)], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::NotOnAllowlist as u32));
+
+    process(&mut banks_client, &payer, &buy("proved", &tree.proof(1).unwrap()), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 10);
+
+    // Without a root no proof admits anyone
+    let drop_root = instruction::set_allowlist_root(&id(), &owner.pubkey(), &market, [0; 32]).unwrap();
+    process(&mut banks_client, &payer, &[drop_root], &[&owner]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &buy("dropped", &tree.proof(1).unwrap()), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::InvalidAllowlistProof as u32));
+}
+
+#[tokio::test]
+async fn test_tiered_pricing() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut token_market = market_state(&owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    token_market.pricing_tiers[0] = PricingTier { up_to: 100, price_numerator: 2, price_denominator: 1 };
+    token_market.pricing_tiers[1] = PricingTier { up_to: 0, price_numerator: 1, price_denominator: 1 };
+    add_market_state(&mut program_test, &market, &token_market);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy_tokens = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+
+    // The market counts what it sells, so it must be writable
+    let result = process(&mut banks_client, &payer, &[approve(30), buy_tokens(30)], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::InvalidArgument);
+
+    let buy = |text: &str, amount| vec![memo::memo(text), approve(amount), instruction::with_tiered_pricing(buy_tokens(amount))];
+    process(&mut banks_client, &payer, &buy("first tier", 30), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 60);
+
+    // 20 buy the 40 left of the first tier, the other 10 sell at par
+    process(&mut banks_client, &payer, &buy("crossing", 30), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 110);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 60);
+
+    let account = banks_client.get_account(market).await.unwrap().unwrap();
+    assert_eq!(TokenMarket::try_from_slice(&account.data).unwrap().total_sold, 110);
+}
+
+#[tokio::test]
+async fn test_wallet_spend_cap_on_tiered_market() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut token_market = market_state(&owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    token_market.pricing_tiers[0] = PricingTier { up_to: 100, price_numerator: 2, price_denominator: 1 };
+    token_market.pricing_tiers[1] = PricingTier { up_to: 0, price_numerator: 1, price_denominator: 1 };
+    add_market_state(&mut program_test, &market, &token_market);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    // The buyer record is allocated in CPI, which needs the BPF build
+    let address = find_buyer_record_address(&id(), &market, &buyer.pubkey()).0;
+    let record = BuyerRecord {
+        market,
+        buyer: buyer.pubkey(),
+        purchased: 0,
+        spent: 0,
+        last_purchase: 0,
+    };
+    program_test.add_account(address, solana_sdk::account::Account {
+        lamports: Rent::default().minimum_balance(BuyerRecord::LEN),
+        data: record.try_to_vec().unwrap(),
+        owner: id(),
+        executable: false,
+        rent_epoch: 0,
+    });
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let set_cap = instruction::set_wallet_spend_cap(&id(), &owner.pubkey(), &market, AcceptableAmount(50)).unwrap();
+    process(&mut banks_client, &payer, &[set_cap], &[&owner]).await.unwrap();
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy_tokens = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+    let buy = |text: &str, amount| vec![
+        memo::memo(text),
+        approve(amount),
+        instruction::with_buyer_record(&id(), instruction::with_tiered_pricing(buy_tokens(amount)), &market, &buyer.pubkey(), &payer.pubkey()),
+    ];
+
+    // The cap counts what is paid whatever the tier sells at
+    process(&mut banks_client, &payer, &buy("first tier", 30), &[&buyer]).await.unwrap();
+    let result = process(&mut banks_client, &payer, &buy("over", 21), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::SpendCapExceeded as u32));
+    process(&mut banks_client, &payer, &buy("rest", 20), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 100);
+    let record = BuyerRecord::try_from_slice(&banks_client.get_account(address).await.unwrap().unwrap().data).unwrap();
+    assert_eq!((record.purchased, record.spent), (100, 50));
+
+    // Purchases the record can't count are refused
+    let escrowed = instruction::buy_tokens_escrowed(&id(), &market, &bank, &emitter, &mint_acceptable, &recipient, &write_off, &payer.pubkey(), AcceptableAmount(1), 0).unwrap();
+    let result = process(&mut banks_client, &payer, &[approve(1), escrowed], &[&buyer]).await;
+    assert_instruction_error(result, 1, InstructionError::Custom(TokenMarketError::WalletCapped as u32));
+}
+
+#[tokio::test]
+async fn test_buy_tokens_exact_out() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut token_market = market_state(&owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    token_market.pricing_tiers[0] = PricingTier { up_to: 100, price_numerator: 2, price_denominator: 1 };
+    token_market.pricing_tiers[1] = PricingTier { up_to: 0, price_numerator: 1, price_denominator: 1 };
+    add_market_state(&mut program_test, &market, &token_market);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let buy = |text: &str, out_amount, max_in| {
+        let buy = instruction::buy_tokens_exact_out(&id(), &market, &bank, &emitter, &recipient, &write_off, EmittedAmount(out_amount), AcceptableAmount(max_in)).unwrap();
+        let mut instructions = vec![memo::memo(text)];
+        instructions.extend(instruction::bundle_buy_tokens(&id(), buy, &write_off, &buyer.pubkey(), AcceptableAmount(max_in)).unwrap());
+        instructions
+    };
+
+    // 100 tokens at 2 per acceptable token cost 50, the next 50 cost 50
+    let result = process(&mut banks_client, &payer, &buy("short", 150, 99), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::Custom(TokenMarketError::SlippageExceeded as u32));
+
+    // what the delegation holds over the cost goes back
+    process(&mut banks_client, &payer, &buy("exact", 150, 120), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 150);
+    assert_eq!(token_balance(&mut banks_client, &bank).await, 100);
+    assert_eq!(token_balance(&mut banks_client, &write_off).await, 900);
+    let account = banks_client.get_account(market).await.unwrap().unwrap();
+    assert_eq!(TokenMarket::try_from_slice(&account.data).unwrap().total_sold, 150);
+
+    let result = process(&mut banks_client, &payer, &buy("nothing", 0, 10), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::InvalidInstructionData);
+}
+
+#[tokio::test]
+async fn test_bonding_curve() {
+    let mut program_test = program_test();
+    let owner = Keypair::new();
+    let buyer = Keypair::new();
+    let authority = find_authority_address(&id()).0;
+    let (market, bank, mint_acceptable, emitter) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let (write_off, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut token_market = market_state(&owner.pubkey(), &bank, &mint_acceptable, &emitter);
+    // the price doubles every 100 tokens sold
+    token_market.bonding_curve = BondingCurve { kind: CurveKind::Exponential, base_price: curve::PRICE_SCALE, growth: 10_000, step: 100 };
+    add_market_state(&mut program_test, &market, &token_market);
+    add_packed(&mut program_test, &emitter, Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    });
+    add_packed(&mut program_test, &bank, token_account_state(&mint_acceptable, &authority, 0));
+    add_packed(&mut program_test, &write_off, token_account_state(&mint_acceptable, &buyer.pubkey(), 1000));
+    add_packed(&mut program_test, &recipient, token_account_state(&emitter, &buyer.pubkey(), 0));
+    let (mut banks_client, payer, _) = program_test.start().await;
+
+    let approve = |amount| spl_token::instruction::approve(&spl_token::id(), &write_off, &authority, &buyer.pubkey(), &[], amount).unwrap();
+    let buy_tokens = |amount| instruction::buy_tokens(&id(), &market, &bank, &emitter, &recipient, &write_off, &spl_token::id(), AcceptableAmount(amount)).unwrap();
+    let buy = |text: &str, amount| vec![memo::memo(text), approve(amount), instruction::with_tiered_pricing(buy_tokens(amount))];
+
+    // 100 tokens at 1, then 25 at 2
+    process(&mut banks_client, &payer, &buy("first", 150), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 125);
+    assert_eq!(curve::quote(&token_market.bonding_curve, 125, 25), Some(50));
+    process(&mut banks_client, &payer, &buy("second", 50), &[&buyer]).await.unwrap();
+    assert_eq!(token_balance(&mut banks_client, &recipient).await, 150);
+
+    let account = banks_client.get_account(market).await.unwrap().unwrap();
+    assert_eq!(TokenMarket::try_from_slice(&account.data).unwrap().total_sold, 150);
 
-    ts.sign(&signers, recent_blockhash);
-    banks_client.process_transaction(ts).await.unwrap();
+    // too little for a single token at the current price of 2
+    let result = process(&mut banks_client, &payer, &buy("dust", 1), &[&buyer]).await;
+    assert_instruction_error(result, 2, InstructionError::InvalidArgument);
 }
@@ -0,0 +1,312 @@
+use borsh::BorshSerialize;
+use solana_program::{
+    program_option::COption, program_pack::Pack, pubkey::Pubkey, system_instruction,
+    system_program,
+};
+use spl_token::state::{Account, AccountState};
+use std::collections::HashMap;
+use token_market::{
+    amounts::AcceptableAmount,
+    state::{BondingCurve, LoyaltyTier, OverpaymentPolicy, PricingTier, StakeWeight, TokenMarket},
+    validation::{checked_buy_tokens, FetchedAccount, Problem, TransactionBuilder},
+};
+
+struct Accounts(HashMap<Pubkey, (Pubkey, Vec<u8>)>);
+
+impl Accounts {
+    fn fetch(&self, address: &Pubkey) -> Option<FetchedAccount> {
+        self.0.get(address).map(|(owner, data)| FetchedAccount {
+            owner: *owner,
+            lamports: 0,
+            data: data.clone(),
+        })
+    }
+
+    fn add_token_account(&mut self, address: Pubkey, mint: Pubkey, amount: u64) {
+        let mut data = vec![0; Account::LEN];
+        Account {
+            mint,
+            owner: Pubkey::new_unique(),
+            amount,
+            delegate: COption::None,
+            state: AccountState::Initialized,
+            is_native: COption::None,
+            delegated_amount: 0,
+            close_authority: COption::None,
+        }
+        .pack_into_slice(&mut data);
+        self.0.insert(address, (spl_token::id(), data));
+    }
+}
+
+#[test]
+fn test_checked_buy_tokens() {
+    let market = Pubkey::new_unique();
+    let token_market = TokenMarket {
+        is_initialized: true,
+        owner: Pubkey::new_unique(),
+        bank: Pubkey::new_unique(),
+        emitter_mint: Pubkey::new_unique(),
+        authority: Pubkey::new_unique(),
+        mint_of_acceptable: Pubkey::new_unique(),
+        legacy_bank: Pubkey::default(),
+        legacy_mint_of_acceptable: Pubkey::default(),
+        voucher_signer: [0; 20],
+        category: [0; 4],
+        project_id: [0; 32],
+        treasury: Pubkey::default(),
+        deprecated: false,
+        successor: Pubkey::default(),
+        overpayment_policy: OverpaymentPolicy::Reject,
+        mint_receipts: false,
+        features: TokenMarket::ALL_FEATURES,
+        require_memo: false,
+        sandwich_guard: false,
+        wormhole_chain: 0,
+        wormhole_emitter: [0; 32],
+        onramp_signer: Pubkey::default(),
+        escrow_threshold: 0,
+        dispute_window: 0,
+        dispute_guardian: Pubkey::default(),
+        paused: false,
+        phased: false,
+        fee_bps: 0,
+        fee_vault: Pubkey::default(),
+        min_purchase: 0,
+        max_purchase: 0,
+        wallet_cap: 0,
+        sale_start: 0,
+        sale_end: 0,
+        allowlist_enabled: false,
+        allowlist_root: [0; 32],
+        pricing_tiers: [PricingTier::default(); TokenMarket::MAX_PRICING_TIERS],
+        total_sold: 0,
+        bonding_curve: BondingCurve::default(),
+        stake_weight: StakeWeight::default(),
+        withdraw_rate: 0,
+        last_withdraw: 0,
+        wallet_spend_cap: 0,
+        purchase_cooldown_secs: 0,
+        loyalty_tiers: [LoyaltyTier::default(); TokenMarket::MAX_LOYALTY_TIERS],
+    };
+    let write_off = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+
+    let mut accounts = Accounts(HashMap::new());
+    accounts.0.insert(
+        market,
+        (token_market::id(), token_market.try_to_vec().unwrap()),
+    );
+    accounts.add_token_account(write_off, token_market.mint_of_acceptable, 10);
+    accounts.add_token_account(recipient, token_market.emitter_mint, 0);
+    let source = |address: &Pubkey| accounts.fetch(address);
+
+    assert!(checked_buy_tokens(
+        &source,
+        &token_market::id(),
+        &market,
+        &token_market.bank,
+        &token_market.emitter_mint,
+        &recipient,
+        &write_off,
+        &spl_token::id(),
+        AcceptableAmount(10),
+        false,
+    )
+    .is_ok());
+
+    // Every problem is reported, not only the first one
+    let wrong_bank = Pubkey::new_unique();
+    let missing = Pubkey::new_unique();
+    let err = checked_buy_tokens(
+        &source,
+        &token_market::id(),
+        &market,
+        &wrong_bank,
+        &token_market.emitter_mint,
+        &missing,
+        &write_off,
+        &spl_token::id(),
+        AcceptableAmount(11),
+        false,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.problems,
+        vec![
+            Problem::WrongBank {
+                expected: token_market.bank,
+                actual: wrong_bank,
+            },
+            Problem::InsufficientFunds {
+                account: write_off,
+                available: 10,
+                requested: 11,
+            },
+            Problem::TokenAccountMissing(missing),
+        ]
+    );
+
+    let err = checked_buy_tokens(
+        &source,
+        &token_market::id(),
+        &missing,
+        &token_market.bank,
+        &token_market.emitter_mint,
+        &recipient,
+        &write_off,
+        &spl_token::id(),
+        AcceptableAmount(0),
+        false,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.problems,
+        vec![Problem::ZeroAmount, Problem::MarketMissing(missing)]
+    );
+}
+
+#[test]
+fn test_checked_buy_tokens_refuses_deprecated_market() {
+    let market = Pubkey::new_unique();
+    let successor = Pubkey::new_unique();
+    let token_market = TokenMarket {
+        is_initialized: true,
+        owner: Pubkey::new_unique(),
+        bank: Pubkey::new_unique(),
+        emitter_mint: Pubkey::new_unique(),
+        authority: Pubkey::new_unique(),
+        mint_of_acceptable: Pubkey::new_unique(),
+        legacy_bank: Pubkey::default(),
+        legacy_mint_of_acceptable: Pubkey::default(),
+        voucher_signer: [0; 20],
+        category: [0; 4],
+        project_id: [0; 32],
+        treasury: Pubkey::default(),
+        deprecated: true,
+        successor,
+        overpayment_policy: OverpaymentPolicy::Reject,
+        mint_receipts: false,
+        features: TokenMarket::ALL_FEATURES,
+        require_memo: false,
+        sandwich_guard: false,
+        wormhole_chain: 0,
+        wormhole_emitter: [0; 32],
+        onramp_signer: Pubkey::default(),
+        escrow_threshold: 0,
+        dispute_window: 0,
+        dispute_guardian: Pubkey::default(),
+        paused: false,
+        phased: false,
+        fee_bps: 0,
+        fee_vault: Pubkey::default(),
+        min_purchase: 0,
+        max_purchase: 0,
+        wallet_cap: 0,
+        sale_start: 0,
+        sale_end: 0,
+        allowlist_enabled: false,
+        allowlist_root: [0; 32],
+        pricing_tiers: [PricingTier::default(); TokenMarket::MAX_PRICING_TIERS],
+        total_sold: 0,
+        bonding_curve: BondingCurve::default(),
+        stake_weight: StakeWeight::default(),
+        withdraw_rate: 0,
+        last_withdraw: 0,
+        wallet_spend_cap: 0,
+        purchase_cooldown_secs: 0,
+        loyalty_tiers: [LoyaltyTier::default(); TokenMarket::MAX_LOYALTY_TIERS],
+    };
+    let write_off = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+
+    let mut accounts = Accounts(HashMap::new());
+    accounts.0.insert(
+        market,
+        (token_market::id(), token_market.try_to_vec().unwrap()),
+    );
+    accounts.add_token_account(write_off, token_market.mint_of_acceptable, 10);
+    accounts.add_token_account(recipient, token_market.emitter_mint, 0);
+    let source = |address: &Pubkey| accounts.fetch(address);
+    let buy = |allow_deprecated| {
+        checked_buy_tokens(
+            &source,
+            &token_market::id(),
+            &market,
+            &token_market.bank,
+            &token_market.emitter_mint,
+            &recipient,
+            &write_off,
+            &spl_token::id(),
+            AcceptableAmount(10),
+            allow_deprecated,
+        )
+    };
+
+    assert_eq!(
+        buy(false).unwrap_err().problems,
+        vec![Problem::MarketDeprecated {
+            market,
+            successor: Some(successor),
+        }]
+    );
+    assert!(buy(true).is_ok());
+}
+
+#[test]
+fn test_preflight_reports_every_problem() {
+    let payer = Pubkey::new_unique();
+    let mint = Pubkey::new_unique();
+    let token = Pubkey::new_unique();
+    let market = Pubkey::new_unique();
+    let missing = Pubkey::new_unique();
+    let created = Pubkey::new_unique();
+
+    let mut accounts = Accounts(HashMap::new());
+    accounts.add_token_account(token, mint, 0);
+    accounts.0.insert(market, (system_program::id(), vec![]));
+    let source = |address: &Pubkey| {
+        if *address == payer {
+            Some(FetchedAccount {
+                owner: system_program::id(),
+                lamports: 5000,
+                data: vec![],
+            })
+        } else {
+            accounts.fetch(address)
+        }
+    };
+
+    let mut transaction = TransactionBuilder::new(&payer);
+    transaction.expect_token_account(&token, &mint);
+    assert_eq!(transaction.preflight(&source, 5000), Ok(()));
+
+    transaction
+        .instruction(system_instruction::create_account(
+            &payer,
+            &created,
+            1_000_000,
+            0,
+            &token_market::id(),
+        ))
+        .expect_owner(&market, &token_market::id())
+        .expect_owner(&missing, &token_market::id())
+        .expect_rent(100);
+    assert_eq!(transaction.payer_spending(5000), 1_005_100);
+    assert_eq!(
+        transaction.preflight(&source, 5000).unwrap_err().problems,
+        vec![
+            Problem::WrongOwner {
+                account: market,
+                expected: token_market::id(),
+                actual: system_program::id(),
+            },
+            Problem::AccountMissing(missing),
+            Problem::InsufficientLamports {
+                payer,
+                available: 5000,
+                required: 1_005_100,
+            },
+        ]
+    );
+}
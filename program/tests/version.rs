@@ -0,0 +1,33 @@
+use std::str::FromStr;
+use token_market::{
+    state::TokenMarket,
+    version::{Capabilities, ProgramVersion},
+};
+
+#[test]
+fn test_version_log_line() {
+    let capabilities = Capabilities {
+        version: Some(ProgramVersion::new(1, 12, 0)),
+        features: TokenMarket::FEATURE_RELAYER_TIPS | TokenMarket::FEATURE_COUPONS,
+    };
+    let line = capabilities.to_string();
+    assert_eq!(line, format!("Version: 1.12.0 {}", capabilities.features));
+    assert_eq!(Capabilities::parse(&line), Some(capabilities));
+
+    assert_eq!(Capabilities::parse("Event: Purchase"), None);
+    assert_eq!(Capabilities::parse("Version: 1.12 3"), None);
+    assert_eq!(Capabilities::parse("Version: 1.12.0 3 extra"), None);
+}
+
+#[test]
+fn test_version_order() {
+    assert_eq!(ProgramVersion::from_str("0.1.0"), Ok(ProgramVersion::new(0, 1, 0)));
+    assert!(ProgramVersion::new(0, 2, 0) > ProgramVersion::new(0, 1, 9));
+    assert!(ProgramVersion::from_str("0.1.0.1").is_err());
+
+    let current = Capabilities::current();
+    assert_eq!(current.version, Some(ProgramVersion::current()));
+    assert!(current.supports(TokenMarket::ALL_FEATURES));
+    assert!(!Capabilities::LEGACY.supports(TokenMarket::FEATURE_COUPONS));
+    assert!(!Capabilities::LEGACY.reads_initialize_fields());
+}
@@ -0,0 +1,40 @@
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use token_market::wormhole::{PostedVaa, PurchasePayload};
+
+#[test]
+fn test_purchase_payload_is_big_endian() {
+    let payload = PurchasePayload {
+        market: Pubkey::new(&[1; 32]),
+        recipient: Pubkey::new(&[2; 32]),
+        amount: 0x0102,
+    };
+    let packed = payload.pack();
+    assert_eq!(packed.len(), PurchasePayload::LEN);
+    assert_eq!(&packed[64..], &[0, 0, 0, 0, 0, 0, 1, 2]);
+    assert_eq!(PurchasePayload::unpack(&packed), Some(payload));
+    assert_eq!(PurchasePayload::unpack(&packed[1..]), None);
+}
+
+#[test]
+fn test_posted_vaa_requires_magic() {
+    let vaa = PostedVaa {
+        vaa_version: 1,
+        consistency_level: 1,
+        vaa_time: 2,
+        vaa_signature_account: Pubkey::new(&[3; 32]),
+        submission_time: 4,
+        nonce: 5,
+        sequence: 6,
+        emitter_chain: 2,
+        emitter_address: [7; 32],
+        payload: vec![8; 72],
+    };
+    let mut data = vaa.pack().unwrap();
+    assert_eq!(PostedVaa::unpack(&data).unwrap(), vaa);
+
+    data[0] = b'm';
+    assert_eq!(
+        PostedVaa::unpack(&data),
+        Err(ProgramError::InvalidAccountData)
+    );
+}